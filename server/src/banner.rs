@@ -48,10 +48,13 @@ fn print_ascii_art() {
 }
 
 fn status_info(config: &Config, scheme: &str, id: Uid) {
+    let http_address = match &config.parseable.uds_path {
+        Some(path) => format!("unix:{}", path.display()),
+        None => format!("{}://{}", scheme, config.parseable.address),
+    };
     let address = format!(
-        "\"{}://{}\" ({}), \":{}\" (livetail), \":{}\" (flight protocol)",
-        scheme,
-        config.parseable.address,
+        "\"{}\" ({}), \":{}\" (livetail), \":{}\" (flight protocol)",
+        http_address,
         scheme.to_ascii_uppercase(),
         config.parseable.grpc_port,
         config.parseable.flight_port
@@ -88,6 +88,15 @@ impl TypedStatistics {
         }
     }
 
+    /// Like [`TypedStatistics::update`], but None when the statistics are of
+    /// different types, as for a column widened between files
+    pub fn try_update(self, other: Self) -> Option<Self> {
+        if std::mem::discriminant(&self) != std::mem::discriminant(&other) {
+            return None;
+        }
+        Some(self.update(other))
+    }
+
     pub fn min_max_as_scalar(self, datatype: &DataType) -> Option<(ScalarValue, ScalarValue)> {
         let (min, max) = match (self, datatype) {
             (TypedStatistics::Bool(stats), DataType::Boolean) => (
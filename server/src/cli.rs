@@ -16,17 +16,25 @@
  *
  */
 
-use clap::{value_parser, Arg, ArgGroup, Command, FromArgMatches};
-use std::path::PathBuf;
+use clap::{value_parser, Arg, ArgAction, ArgGroup, Command, FromArgMatches};
+use serde::Serialize;
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    time::Duration,
+};
 
 use url::Url;
 
 use crate::{
+    handlers::http::modal::ssl_acceptor::TlsIdentity,
     oidc::{self, OpenidConfig},
-    option::{validation, Compression, Mode},
+    option::{
+        redacted, validation, AddrResolution, Compression, DiskUsageAction, Mode, TlsVersion,
+    },
 };
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize)]
 pub struct Cli {
     /// The location of TLS Cert file
     pub tls_cert_path: Option<PathBuf>,
@@ -34,9 +42,31 @@ pub struct Cli {
     /// The location of TLS Private Key file
     pub tls_key_path: Option<PathBuf>,
 
+    /// The location of a PKCS#12 bundle with the TLS certificate and private key
+    pub tls_pkcs12_path: Option<PathBuf>,
+
+    /// Password protecting the PKCS#12 bundle
+    #[serde(serialize_with = "redacted::option")]
+    pub tls_pkcs12_password: Option<String>,
+
+    /// Minimum TLS protocol version accepted by the server
+    pub tls_min_version: TlsVersion,
+
+    /// TLS cipher suites offered by the server, empty means rustls defaults
+    pub tls_cipher_suites: Vec<String>,
+
     /// The address on which the http server will listen.
     pub address: String,
 
+    /// Whether a hostname in `address` binds its first or all resolved addresses
+    pub address_resolution: AddrResolution,
+
+    /// Unix domain socket on which the http server will listen instead of `address`
+    pub uds_path: Option<PathBuf>,
+
+    /// Permission bits applied to the unix domain socket file
+    pub uds_permissions: u32,
+
     /// Base domain under which server is hosted.
     /// This information is used by OIDC to refer redirects
     pub domain_address: Option<Url>,
@@ -51,10 +81,17 @@ pub struct Cli {
     /// Size for local cache
     pub local_cache_size: u64,
 
+    /// Percentage of the staging disk in use above which `disk_usage_action` applies
+    pub max_disk_usage: Option<f64>,
+
+    /// What happens to ingestion once `max_disk_usage` is exceeded
+    pub disk_usage_action: DiskUsageAction,
+
     /// Username for the basic authentication on the server
     pub username: String,
 
     /// Password for the basic authentication on the server
+    #[serde(serialize_with = "redacted::serialize")]
     pub password: String,
 
     /// OpenId configuration
@@ -67,6 +104,7 @@ pub struct Cli {
     pub send_analytics: bool,
 
     /// Open AI access key
+    #[serde(serialize_with = "redacted::option")]
     pub open_ai_key: Option<String>,
 
     /// Livetail port
@@ -81,6 +119,33 @@ pub struct Cli {
     /// Query memory limit in bytes
     pub query_memory_pool_size: Option<usize>,
 
+    /// Number of threads a query is executed with, defaults to available parallelism
+    pub query_threads: usize,
+
+    /// How long query results are kept in memory, the result cache is disabled when unset
+    #[serde(with = "humantime_serde")]
+    pub query_result_cache_ttl: Option<Duration>,
+
+    /// Memory in bytes the query result cache may use
+    pub query_result_cache_size: u64,
+
+    /// Maximum number of queries executed at once, unlimited when unset
+    pub max_concurrent_queries: Option<usize>,
+
+    /// Number of queries that may wait for a free slot
+    pub query_queue_size: usize,
+
+    /// How long a query waits in the queue before it is rejected
+    #[serde(with = "humantime_serde")]
+    pub query_queue_timeout: Duration,
+
+    /// Span of time a correlation joins at once, bounds the events a join holds in memory
+    #[serde(with = "humantime_serde")]
+    pub correlation_join_window: Duration,
+
+    /// Maximum number of concurrent requests to the object store
+    pub store_concurrency: usize,
+
     /// Parquet compression algorithm
     pub parquet_compression: Compression,
 
@@ -98,19 +163,274 @@ pub struct Cli {
 
     /// Size for local cache
     pub query_cache_size: u64,
+
+    /// Enforce CORS on the HTTP server
+    pub cors: bool,
+
+    /// Origins allowed to make cross origin requests, empty means none
+    pub cors_origins: Vec<String>,
+
+    /// Path of the unauthenticated health check endpoint served outside the api base path
+    pub health_check_path: String,
+
+    /// Address the syslog server listens on for TCP, disabled when unset
+    pub syslog_tcp_addr: Option<String>,
+
+    /// Address the syslog server listens on for UDP, disabled when unset
+    pub syslog_udp_addr: Option<String>,
+
+    /// Serve syslog over TLS on the TCP listener using the server certificate
+    pub syslog_tls: bool,
+
+    /// Stream syslog messages go to, derived from their hostname when unset
+    pub syslog_stream: Option<String>,
+
+    /// Stream syslog messages that can't be parsed go to
+    pub syslog_quarantine_stream: String,
+
+    /// JSON file listing the local files to tail, disabled when unset
+    pub file_input_config: Option<PathBuf>,
+
+    /// Seconds between the scans for files matching the file input patterns
+    pub file_input_scan_interval: u64,
+
+    /// Seconds a tailed file that was deleted or rotated away is still read
+    pub file_input_release_after: u64,
+
+    /// Kafka brokers the connector consumes from, disabled when unset
+    #[cfg(feature = "kafka")]
+    pub kafka_brokers: Option<String>,
+
+    /// Consumer group the Kafka connector joins
+    #[cfg(feature = "kafka")]
+    pub kafka_group_id: String,
+
+    /// Kafka topics the connector consumes
+    #[cfg(feature = "kafka")]
+    pub kafka_topics: Vec<String>,
+
+    /// Regex of further Kafka topics the connector consumes
+    #[cfg(feature = "kafka")]
+    pub kafka_topic_pattern: Option<String>,
+
+    /// Streams of Kafka topics given as topic=stream, these topics skip the template
+    #[cfg(feature = "kafka")]
+    pub kafka_topic_streams: Vec<String>,
+
+    /// Stream a Kafka topic goes to, {topic} is replaced by the topic name
+    #[cfg(feature = "kafka")]
+    pub kafka_stream_template: String,
+
+    /// Create the streams Kafka topics go to when they don't exist
+    #[cfg(feature = "kafka")]
+    pub kafka_create_streams: bool,
+
+    /// When the Kafka connector commits offsets: after the staging flush, at an interval or every so many records
+    #[cfg(feature = "kafka")]
+    pub kafka_commit_strategy: String,
+
+    /// Seconds between offset commits of the interval commit strategy
+    #[cfg(feature = "kafka")]
+    pub kafka_commit_interval: u64,
+
+    /// Records between offset commits of the records commit strategy
+    #[cfg(feature = "kafka")]
+    pub kafka_commit_records: usize,
+
+    /// Protocol the Kafka connector talks to the brokers over
+    #[cfg(feature = "kafka")]
+    pub kafka_security_protocol: String,
+
+    /// SASL mechanism the Kafka connector authenticates with
+    #[cfg(feature = "kafka")]
+    pub kafka_sasl_mechanism: Option<String>,
+
+    /// Username of the PLAIN and SCRAM SASL mechanisms
+    #[cfg(feature = "kafka")]
+    pub kafka_sasl_username: Option<String>,
+
+    /// Password of the PLAIN and SCRAM SASL mechanisms
+    #[cfg(feature = "kafka")]
+    #[serde(serialize_with = "redacted::option")]
+    pub kafka_sasl_password: Option<String>,
+
+    /// OIDC token endpoint the OAUTHBEARER SASL mechanism gets tokens from
+    #[cfg(feature = "kafka")]
+    pub kafka_oauth_token_endpoint: Option<Url>,
+
+    /// Client the OAUTHBEARER SASL mechanism gets tokens as
+    #[cfg(feature = "kafka")]
+    pub kafka_oauth_client_id: Option<String>,
+
+    /// Secret of the client the OAUTHBEARER SASL mechanism gets tokens as
+    #[cfg(feature = "kafka")]
+    #[serde(serialize_with = "redacted::option")]
+    pub kafka_oauth_client_secret: Option<String>,
+
+    /// Scope of the tokens the OAUTHBEARER SASL mechanism gets
+    #[cfg(feature = "kafka")]
+    pub kafka_oauth_scope: Option<String>,
+
+    /// CA certificate the Kafka brokers are verified with, the system roots when unset
+    #[cfg(feature = "kafka")]
+    pub kafka_tls_ca_path: Option<PathBuf>,
+
+    /// Client certificate the Kafka connector presents to the brokers
+    #[cfg(feature = "kafka")]
+    pub kafka_tls_cert_path: Option<PathBuf>,
+
+    /// Private key of the Kafka client certificate
+    #[cfg(feature = "kafka")]
+    pub kafka_tls_key_path: Option<PathBuf>,
+
+    /// Password protecting the private key of the Kafka client certificate
+    #[cfg(feature = "kafka")]
+    #[serde(serialize_with = "redacted::option")]
+    pub kafka_tls_key_password: Option<String>,
+
+    /// NATS servers the connector consumes from, disabled when unset
+    #[cfg(feature = "nats")]
+    pub nats_servers: Option<String>,
+
+    /// JetStream stream the NATS connector consumes
+    #[cfg(feature = "nats")]
+    pub nats_stream: Option<String>,
+
+    /// Durable pull consumer the NATS connector consumes through
+    #[cfg(feature = "nats")]
+    pub nats_consumer: String,
+
+    /// Subjects of the JetStream stream the NATS connector consumes, all when empty
+    #[cfg(feature = "nats")]
+    pub nats_subjects: Vec<String>,
+
+    /// Streams of NATS subjects given as subject=stream, subjects may have wildcards
+    #[cfg(feature = "nats")]
+    pub nats_subject_streams: Vec<String>,
+
+    /// Stream NATS messages of subjects no mapping matches go to
+    #[cfg(feature = "nats")]
+    pub nats_default_stream: Option<String>,
+
+    /// Create the streams NATS subjects go to when they don't exist
+    #[cfg(feature = "nats")]
+    pub nats_create_streams: bool,
+
+    /// NATS messages delivered to the connector but not acked yet at most
+    #[cfg(feature = "nats")]
+    pub nats_max_in_flight: usize,
+
+    /// NATS messages fetched at once
+    #[cfg(feature = "nats")]
+    pub nats_batch_size: usize,
+
+    /// Seconds a NATS message may stay unacked before it is redelivered
+    #[cfg(feature = "nats")]
+    pub nats_ack_wait: u64,
+
+    /// Token the NATS connector authenticates with
+    #[cfg(feature = "nats")]
+    #[serde(serialize_with = "redacted::option")]
+    pub nats_token: Option<String>,
+
+    /// NKey seed the NATS connector authenticates with
+    #[cfg(feature = "nats")]
+    #[serde(serialize_with = "redacted::option")]
+    pub nats_nkey_seed: Option<String>,
+
+    /// User the NATS connector authenticates as
+    #[cfg(feature = "nats")]
+    pub nats_user: Option<String>,
+
+    /// Password of the NATS user
+    #[cfg(feature = "nats")]
+    #[serde(serialize_with = "redacted::option")]
+    pub nats_password: Option<String>,
+
+    /// URI of the AMQP broker the connector consumes from, disabled when unset
+    #[cfg(feature = "amqp")]
+    #[serde(serialize_with = "redacted::option")]
+    pub amqp_uri: Option<String>,
+
+    /// AMQP queues the connector consumes
+    #[cfg(feature = "amqp")]
+    pub amqp_queues: Vec<String>,
+
+    /// Streams of AMQP queues given as queue=stream, these queues skip the template
+    #[cfg(feature = "amqp")]
+    pub amqp_queue_streams: Vec<String>,
+
+    /// Stream an AMQP queue goes to, {queue} is replaced by the queue name
+    #[cfg(feature = "amqp")]
+    pub amqp_stream_template: String,
+
+    /// Create the streams AMQP queues go to when they don't exist
+    #[cfg(feature = "amqp")]
+    pub amqp_create_streams: bool,
+
+    /// AMQP messages delivered to the connector but not acked yet at most
+    #[cfg(feature = "amqp")]
+    pub amqp_prefetch: u16,
+
+    /// What becomes of AMQP messages that can't be decoded: dead-letter or requeue
+    #[cfg(feature = "amqp")]
+    pub amqp_reject: String,
+
+    /// CA certificate the AMQP broker is verified with, the system roots when unset
+    #[cfg(feature = "amqp")]
+    pub amqp_tls_ca_path: Option<PathBuf>,
+
+    /// PKCS#12 bundle of the client certificate the AMQP connector presents
+    #[cfg(feature = "amqp")]
+    pub amqp_tls_pkcs12_path: Option<PathBuf>,
+
+    /// Password of the PKCS#12 bundle of the AMQP client certificate
+    #[cfg(feature = "amqp")]
+    #[serde(serialize_with = "redacted::option")]
+    pub amqp_tls_pkcs12_password: Option<String>,
+
+    /// Bytes a line of a streamed NDJSON body may have
+    pub ingest_stream_max_line_size: u64,
+
+    /// Bytes a streamed NDJSON body may have over its whole connection
+    pub ingest_stream_max_size: u64,
+
+    /// How long events of a streamed NDJSON body wait to be flushed to staging
+    #[serde(with = "humantime_serde")]
+    pub ingest_stream_flush_interval: Duration,
+
+    /// Stream events rejected by a stream go to, unless the stream sets its own
+    pub dead_letter_stream: Option<String>,
+
+    /// Print the resolved configuration and exit instead of starting the server
+    #[serde(skip)]
+    pub print_config: bool,
+
+    /// Run the startup validations and exit instead of starting the server
+    #[serde(skip)]
+    pub validate_only: bool,
 }
 
 impl Cli {
     // identifiers for arguments
     pub const TLS_CERT: &'static str = "tls-cert-path";
     pub const TLS_KEY: &'static str = "tls-key-path";
+    pub const TLS_PKCS12: &'static str = "tls-pkcs12-path";
+    pub const TLS_PKCS12_PASSWORD: &'static str = "tls-pkcs12-password";
+    pub const TLS_MIN_VERSION: &'static str = "tls-min-version";
+    pub const TLS_CIPHER_SUITES: &'static str = "tls-cipher-suites";
     pub const ADDRESS: &'static str = "address";
+    pub const ADDRESS_RESOLUTION: &'static str = "address-resolution";
+    pub const UDS_PATH: &'static str = "uds-path";
+    pub const UDS_PERMISSIONS: &'static str = "uds-permissions";
     pub const DOMAIN_URI: &'static str = "origin";
     pub const STAGING: &'static str = "local-staging-path";
     pub const CACHE: &'static str = "cache-path";
     pub const QUERY_CACHE: &'static str = "query-cache-path";
     pub const QUERY_CACHE_SIZE: &'static str = "query-cache-size";
     pub const CACHE_SIZE: &'static str = "cache-size";
+    pub const MAX_DISK_USAGE: &'static str = "max-disk-usage";
+    pub const DISK_USAGE_ACTION: &'static str = "disk-usage-action";
     pub const USERNAME: &'static str = "username";
     pub const PASSWORD: &'static str = "password";
     pub const CHECK_UPDATE: &'static str = "check-update";
@@ -123,6 +443,14 @@ impl Cli {
     pub const LIVETAIL_CAPACITY: &'static str = "livetail-capacity";
     // todo : what should this flag be
     pub const QUERY_MEM_POOL_SIZE: &'static str = "query-mempool-size";
+    pub const QUERY_THREADS: &'static str = "query-threads";
+    pub const QUERY_RESULT_CACHE_TTL: &'static str = "query-result-cache-ttl";
+    pub const QUERY_RESULT_CACHE_SIZE: &'static str = "query-result-cache-size";
+    pub const MAX_CONCURRENT_QUERIES: &'static str = "max-concurrent-queries";
+    pub const QUERY_QUEUE_SIZE: &'static str = "query-queue-size";
+    pub const QUERY_QUEUE_TIMEOUT: &'static str = "query-queue-timeout";
+    pub const CORRELATION_JOIN_WINDOW: &'static str = "correlation-join-window";
+    pub const STORE_CONCURRENCY: &'static str = "store-concurrency";
     pub const ROW_GROUP_SIZE: &'static str = "row-group-size";
     pub const PARQUET_COMPRESSION_ALGO: &'static str = "compression-algo";
     pub const MODE: &'static str = "mode";
@@ -130,18 +458,181 @@ impl Cli {
     pub const DEFAULT_USERNAME: &'static str = "admin";
     pub const DEFAULT_PASSWORD: &'static str = "admin";
     pub const FLIGHT_PORT: &'static str = "flight-port";
+    pub const CORS: &'static str = "cors";
+    pub const CORS_ORIGINS: &'static str = "cors-origins";
+    pub const HEALTH_CHECK_PATH: &'static str = "health-check-path";
+    pub const SYSLOG_TCP_ADDR: &'static str = "syslog-tcp-addr";
+    pub const SYSLOG_UDP_ADDR: &'static str = "syslog-udp-addr";
+    pub const SYSLOG_TLS: &'static str = "syslog-tls";
+    pub const SYSLOG_STREAM: &'static str = "syslog-stream";
+    pub const SYSLOG_QUARANTINE_STREAM: &'static str = "syslog-quarantine-stream";
+    pub const FILE_INPUT_CONFIG: &'static str = "file-input-config";
+    pub const FILE_INPUT_SCAN_INTERVAL: &'static str = "file-input-scan-interval";
+    pub const FILE_INPUT_RELEASE_AFTER: &'static str = "file-input-release-after";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_BROKERS: &'static str = "kafka-brokers";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_GROUP_ID: &'static str = "kafka-group-id";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_TOPICS: &'static str = "kafka-topics";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_TOPIC_PATTERN: &'static str = "kafka-topic-pattern";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_TOPIC_STREAMS: &'static str = "kafka-topic-streams";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_STREAM_TEMPLATE: &'static str = "kafka-stream-template";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_CREATE_STREAMS: &'static str = "kafka-create-streams";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_COMMIT_STRATEGY: &'static str = "kafka-commit-strategy";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_COMMIT_INTERVAL: &'static str = "kafka-commit-interval";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_COMMIT_RECORDS: &'static str = "kafka-commit-records";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_SECURITY_PROTOCOL: &'static str = "kafka-security-protocol";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_SASL_MECHANISM: &'static str = "kafka-sasl-mechanism";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_SASL_USERNAME: &'static str = "kafka-sasl-username";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_SASL_PASSWORD: &'static str = "kafka-sasl-password";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_SASL_PASSWORD_FILE: &'static str = "kafka-sasl-password-file";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_OAUTH_TOKEN_ENDPOINT: &'static str = "kafka-oauth-token-endpoint";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_OAUTH_CLIENT_ID: &'static str = "kafka-oauth-client-id";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_OAUTH_CLIENT_SECRET: &'static str = "kafka-oauth-client-secret";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_OAUTH_CLIENT_SECRET_FILE: &'static str = "kafka-oauth-client-secret-file";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_OAUTH_SCOPE: &'static str = "kafka-oauth-scope";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_TLS_CA: &'static str = "kafka-tls-ca-path";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_TLS_CERT: &'static str = "kafka-tls-cert-path";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_TLS_KEY: &'static str = "kafka-tls-key-path";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_TLS_KEY_PASSWORD: &'static str = "kafka-tls-key-password";
+    #[cfg(feature = "kafka")]
+    pub const KAFKA_TLS_KEY_PASSWORD_FILE: &'static str = "kafka-tls-key-password-file";
+    #[cfg(feature = "nats")]
+    pub const NATS_SERVERS: &'static str = "nats-servers";
+    #[cfg(feature = "nats")]
+    pub const NATS_STREAM: &'static str = "nats-stream";
+    #[cfg(feature = "nats")]
+    pub const NATS_CONSUMER: &'static str = "nats-consumer";
+    #[cfg(feature = "nats")]
+    pub const NATS_SUBJECTS: &'static str = "nats-subjects";
+    #[cfg(feature = "nats")]
+    pub const NATS_SUBJECT_STREAMS: &'static str = "nats-subject-streams";
+    #[cfg(feature = "nats")]
+    pub const NATS_DEFAULT_STREAM: &'static str = "nats-default-stream";
+    #[cfg(feature = "nats")]
+    pub const NATS_CREATE_STREAMS: &'static str = "nats-create-streams";
+    #[cfg(feature = "nats")]
+    pub const NATS_MAX_IN_FLIGHT: &'static str = "nats-max-in-flight";
+    #[cfg(feature = "nats")]
+    pub const NATS_BATCH_SIZE: &'static str = "nats-batch-size";
+    #[cfg(feature = "nats")]
+    pub const NATS_ACK_WAIT: &'static str = "nats-ack-wait";
+    #[cfg(feature = "nats")]
+    pub const NATS_TOKEN: &'static str = "nats-token";
+    #[cfg(feature = "nats")]
+    pub const NATS_TOKEN_FILE: &'static str = "nats-token-file";
+    #[cfg(feature = "nats")]
+    pub const NATS_NKEY_SEED: &'static str = "nats-nkey-seed";
+    #[cfg(feature = "nats")]
+    pub const NATS_NKEY_SEED_FILE: &'static str = "nats-nkey-seed-file";
+    #[cfg(feature = "nats")]
+    pub const NATS_USER: &'static str = "nats-user";
+    #[cfg(feature = "nats")]
+    pub const NATS_PASSWORD: &'static str = "nats-password";
+    #[cfg(feature = "nats")]
+    pub const NATS_PASSWORD_FILE: &'static str = "nats-password-file";
+    #[cfg(feature = "amqp")]
+    pub const AMQP_URI: &'static str = "amqp-uri";
+    #[cfg(feature = "amqp")]
+    pub const AMQP_URI_FILE: &'static str = "amqp-uri-file";
+    #[cfg(feature = "amqp")]
+    pub const AMQP_QUEUES: &'static str = "amqp-queues";
+    #[cfg(feature = "amqp")]
+    pub const AMQP_QUEUE_STREAMS: &'static str = "amqp-queue-streams";
+    #[cfg(feature = "amqp")]
+    pub const AMQP_STREAM_TEMPLATE: &'static str = "amqp-stream-template";
+    #[cfg(feature = "amqp")]
+    pub const AMQP_CREATE_STREAMS: &'static str = "amqp-create-streams";
+    #[cfg(feature = "amqp")]
+    pub const AMQP_PREFETCH: &'static str = "amqp-prefetch";
+    #[cfg(feature = "amqp")]
+    pub const AMQP_REJECT: &'static str = "amqp-reject";
+    #[cfg(feature = "amqp")]
+    pub const AMQP_TLS_CA: &'static str = "amqp-tls-ca-path";
+    #[cfg(feature = "amqp")]
+    pub const AMQP_TLS_PKCS12: &'static str = "amqp-tls-pkcs12-path";
+    #[cfg(feature = "amqp")]
+    pub const AMQP_TLS_PKCS12_PASSWORD: &'static str = "amqp-tls-pkcs12-password";
+    #[cfg(feature = "amqp")]
+    pub const AMQP_TLS_PKCS12_PASSWORD_FILE: &'static str = "amqp-tls-pkcs12-password-file";
+    pub const INGEST_STREAM_MAX_LINE_SIZE: &'static str = "ingest-stream-max-line-size";
+    pub const INGEST_STREAM_MAX_SIZE: &'static str = "ingest-stream-max-size";
+    pub const INGEST_STREAM_FLUSH_INTERVAL: &'static str = "ingest-stream-flush-interval";
+    pub const DEAD_LETTER_STREAM: &'static str = "dead-letter-stream";
+    pub const PRINT_CONFIG: &'static str = "print-config";
+    pub const VALIDATE_ONLY: &'static str = "validate";
 
     pub fn local_stream_data_path(&self, stream_name: &str) -> PathBuf {
         self.local_staging_path.join(stream_name)
     }
 
     pub fn get_scheme(&self) -> String {
-        if self.tls_cert_path.is_some() && self.tls_key_path.is_some() {
+        if self.tls_identity().is_some() {
             return "https".to_string();
         }
         "http".to_string()
     }
 
+    /// Resolves `address` to the socket addresses the server binds, hostnames are
+    /// looked up here so they only need to resolve once the server starts
+    pub fn bind_addrs(&self) -> anyhow::Result<Vec<SocketAddr>> {
+        let resolved = self.address.to_socket_addrs().map_err(|err| {
+            anyhow::anyhow!("Failed to resolve server address {}: {err}", self.address)
+        })?;
+        let addrs: Vec<_> = match self.address_resolution {
+            AddrResolution::First => resolved.take(1).collect(),
+            AddrResolution::All => resolved.collect(),
+        };
+        if addrs.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Server address {} did not resolve to any address",
+                self.address
+            ));
+        }
+        Ok(addrs)
+    }
+
+    /// Certificate and key the server uses for TLS, if TLS is enabled
+    pub fn tls_identity(&self) -> Option<TlsIdentity> {
+        match (
+            &self.tls_cert_path,
+            &self.tls_key_path,
+            &self.tls_pkcs12_path,
+        ) {
+            (Some(cert), Some(key), _) => Some(TlsIdentity::Pem {
+                cert: cert.clone(),
+                key: key.clone(),
+            }),
+            (_, _, Some(path)) => Some(TlsIdentity::Pkcs12 {
+                path: path.clone(),
+                password: self.tls_pkcs12_password.clone().unwrap_or_default(),
+            }),
+            _ => None,
+        }
+    }
+
     pub fn create_cli_command_with_clap(name: &'static str) -> Command {
         Command::new(name).next_line_help(false)
             .arg(
@@ -150,6 +641,7 @@ impl Cli {
                     .env("P_TLS_CERT_PATH")
                     .value_name("PATH")
                     .value_parser(validation::file_path)
+                    .requires(Self::TLS_KEY)
                     .help("Local path on this device where certificate file is located. Required to enable TLS"),
             )
             .arg(
@@ -158,8 +650,46 @@ impl Cli {
                     .env("P_TLS_KEY_PATH")
                     .value_name("PATH")
                     .value_parser(validation::file_path)
+                    .requires(Self::TLS_CERT)
                     .help("Local path on this device where private key file is located. Required to enable TLS"),
             )
+            .arg(
+                Arg::new(Self::TLS_PKCS12)
+                    .long(Self::TLS_PKCS12)
+                    .env("P_TLS_PKCS12_PATH")
+                    .value_name("PATH")
+                    .value_parser(validation::file_path)
+                    .conflicts_with_all([Self::TLS_CERT, Self::TLS_KEY])
+                    .help("Local path on this device where a PKCS#12 (.p12/.pfx) bundle with the certificate and private key is located. Alternative to the cert and key paths"),
+            )
+            .arg(
+                Arg::new(Self::TLS_PKCS12_PASSWORD)
+                    .long(Self::TLS_PKCS12_PASSWORD)
+                    .env("P_TLS_PKCS12_PASSWORD")
+                    .value_name("STRING")
+                    .requires(Self::TLS_PKCS12)
+                    .help("Password of the PKCS#12 bundle"),
+            )
+            .arg(
+                Arg::new(Self::TLS_MIN_VERSION)
+                    .long(Self::TLS_MIN_VERSION)
+                    .env("P_TLS_MIN_VERSION")
+                    .value_name("VERSION")
+                    .required(false)
+                    .default_value("1.2")
+                    .value_parser(["1.2", "1.3"])
+                    .help("Minimum TLS protocol version accepted by the server"),
+            )
+            .arg(
+                Arg::new(Self::TLS_CIPHER_SUITES)
+                    .long(Self::TLS_CIPHER_SUITES)
+                    .env("P_TLS_CIPHER_SUITES")
+                    .value_name("SUITE,SUITE")
+                    .required(false)
+                    .value_delimiter(',')
+                    .value_parser(validation::tls_cipher_suite)
+                    .help("Comma separated list of TLS cipher suites to offer (e.g. TLS13_AES_256_GCM_SHA384)"),
+            )
             .arg(
                 Arg::new(Self::ADDRESS)
                     .long(Self::ADDRESS)
@@ -167,7 +697,42 @@ impl Cli {
                     .value_name("ADDR:PORT")
                     .default_value("0.0.0.0:8000")
                     .value_parser(validation::socket_addr)
-                    .help("Address and port for Parseable HTTP(s) server"),
+                    .help("Address and port for Parseable HTTP(s) server, the address may be a resolvable hostname"),
+            )
+            .arg(
+                Arg::new(Self::ADDRESS_RESOLUTION)
+                    .long(Self::ADDRESS_RESOLUTION)
+                    .env("P_ADDR_RESOLUTION")
+                    .value_name("STRING")
+                    .required(false)
+                    .default_value("all")
+                    .value_parser(["first", "all"])
+                    .help("Bind the first or all addresses a hostname in the server address resolves to"),
+            )
+            .arg(
+                Arg::new(Self::UDS_PATH)
+                    .long(Self::UDS_PATH)
+                    .env("P_UDS_PATH")
+                    .value_name("PATH")
+                    .required(false)
+                    .value_parser(value_parser!(PathBuf))
+                    .conflicts_with_all([
+                        Self::ADDRESS,
+                        Self::TLS_CERT,
+                        Self::TLS_KEY,
+                        Self::TLS_PKCS12,
+                    ])
+                    .help("Unix domain socket for Parseable HTTP server, replaces the TCP address"),
+            )
+            .arg(
+                Arg::new(Self::UDS_PERMISSIONS)
+                    .long(Self::UDS_PERMISSIONS)
+                    .env("P_UDS_PERMISSIONS")
+                    .value_name("OCTAL")
+                    .required(false)
+                    .default_value("660")
+                    .value_parser(validation::file_permissions)
+                    .help("Permissions of the unix domain socket file in octal (e.g. 660)"),
             )
             .arg(
                 Arg::new(Self::STAGING)
@@ -178,6 +743,25 @@ impl Cli {
                     .value_parser(validation::canonicalize_path)
                     .help("Local path on this device to be used as landing point for incoming events")
                     .next_line_help(true),
+            )
+            .arg(
+                Arg::new(Self::MAX_DISK_USAGE)
+                    .long(Self::MAX_DISK_USAGE)
+                    .env("P_MAX_DISK_USAGE")
+                    .value_name("PERCENT")
+                    .required(false)
+                    .value_parser(validation::disk_usage_percent)
+                    .help("Percentage of the staging disk in use above which the disk usage action applies, unlimited when unset"),
+            )
+            .arg(
+                Arg::new(Self::DISK_USAGE_ACTION)
+                    .long(Self::DISK_USAGE_ACTION)
+                    .env("P_DISK_USAGE_ACTION")
+                    .value_name("ACTION")
+                    .required(false)
+                    .default_value("reject")
+                    .value_parser(["reject", "evict", "readonly"])
+                    .help("Once the max disk usage is exceeded reject events with 507, evict the local data of streams receiving events until usage drops below the limit (their data past retention when stored on the staging disk, then their local cache least recently used first, staged events are never evicted) rejecting events when it stays above, or make streams receiving events read-only until usage drops below the limit"),
             )
              .arg(
                 Arg::new(Self::CACHE)
@@ -335,6 +919,79 @@ impl Cli {
                     .value_parser(value_parser!(u8))
                     .help("Set a fixed memory limit for query"),
             )
+            .arg(
+                Arg::new(Self::QUERY_THREADS)
+                    .long(Self::QUERY_THREADS)
+                    .env("P_QUERY_THREADS")
+                    .value_name("NUMBER")
+                    .required(false)
+                    .value_parser(value_parser!(u64).range(1..))
+                    .help("Number of threads used to execute a query, defaults to available parallelism"),
+            )
+            .arg(
+                Arg::new(Self::QUERY_RESULT_CACHE_TTL)
+                    .long(Self::QUERY_RESULT_CACHE_TTL)
+                    .env("P_QUERY_RESULT_CACHE_TTL")
+                    .value_name("DURATION")
+                    .required(false)
+                    .value_parser(validation::duration)
+                    .help("Keep query results in memory for this long (e.g. 5m), repeated queries over the same time range are served from memory. Disabled when unset"),
+            )
+            .arg(
+                Arg::new(Self::QUERY_RESULT_CACHE_SIZE)
+                    .long(Self::QUERY_RESULT_CACHE_SIZE)
+                    .env("P_QUERY_RESULT_CACHE_SIZE")
+                    .value_name("size")
+                    .default_value("256MiB")
+                    .value_parser(validation::memory_size)
+                    .help("Maximum memory used by the query result cache (In human readable format, e.g 256MiB, 1GiB)"),
+            )
+            .arg(
+                Arg::new(Self::MAX_CONCURRENT_QUERIES)
+                    .long(Self::MAX_CONCURRENT_QUERIES)
+                    .env("P_MAX_CONCURRENT_QUERIES")
+                    .value_name("NUMBER")
+                    .required(false)
+                    .value_parser(value_parser!(u64).range(1..))
+                    .help("Maximum number of queries executed at once, further queries wait in a queue. Unlimited when unset"),
+            )
+            .arg(
+                Arg::new(Self::QUERY_QUEUE_SIZE)
+                    .long(Self::QUERY_QUEUE_SIZE)
+                    .env("P_QUERY_QUEUE_SIZE")
+                    .value_name("NUMBER")
+                    .default_value("100")
+                    .value_parser(value_parser!(u64))
+                    .help("Number of queries that may wait for a free slot, queries beyond it are rejected"),
+            )
+            .arg(
+                Arg::new(Self::QUERY_QUEUE_TIMEOUT)
+                    .long(Self::QUERY_QUEUE_TIMEOUT)
+                    .env("P_QUERY_QUEUE_TIMEOUT")
+                    .value_name("DURATION")
+                    .default_value("30s")
+                    .value_parser(validation::duration)
+                    .help("How long a query waits for a free slot before it is rejected"),
+            )
+            .arg(
+                Arg::new(Self::CORRELATION_JOIN_WINDOW)
+                    .long(Self::CORRELATION_JOIN_WINDOW)
+                    .env("P_CORRELATION_JOIN_WINDOW")
+                    .value_name("DURATION")
+                    .default_value("1h")
+                    .value_parser(validation::duration)
+                    .help("Span of time a correlation joins at once (e.g. 15m), longer time ranges are joined window by window to bound memory"),
+            )
+            .arg(
+                Arg::new(Self::STORE_CONCURRENCY)
+                    .long(Self::STORE_CONCURRENCY)
+                    .env("P_STORE_CONCURRENCY")
+                    .value_name("NUMBER")
+                    .required(false)
+                    .default_value("1000")
+                    .value_parser(value_parser!(u64).range(1..))
+                    .help("Maximum number of concurrent requests to the object store"),
+            )
             .arg(
                 Arg::new(Self::ROW_GROUP_SIZE)
                     .long(Self::ROW_GROUP_SIZE)
@@ -342,8 +999,8 @@ impl Cli {
                     .value_name("NUMBER")
                     .required(false)
                     .default_value("16384")
-                    .value_parser(value_parser!(usize))
-                    .help("Number of rows in a row group"),
+                    .value_parser(validation::row_group_size)
+                    .help("Number of rows in a row group, between 1024 and 1048576"),
             ).arg(
                 Arg::new(Self::MODE)
                     .long(Self::MODE)
@@ -381,6 +1038,169 @@ impl Cli {
                         "lz4",
                         "zstd"])
                     .help("Parquet compression algorithm"),
+            )
+            .arg(
+                Arg::new(Self::CORS)
+                    .long(Self::CORS)
+                    .env("P_CORS")
+                    .value_name("BOOL")
+                    .required(false)
+                    .default_value("true")
+                    .value_parser(value_parser!(bool))
+                    .help("Enable/Disable CORS, when disabled cross origin requests from any origin are allowed"),
+            )
+            .arg(
+                Arg::new(Self::CORS_ORIGINS)
+                    .long(Self::CORS_ORIGINS)
+                    .env("P_CORS_ORIGINS")
+                    .value_name("ORIGIN,ORIGIN")
+                    .required(false)
+                    .value_delimiter(',')
+                    .value_parser(validation::cors_origin)
+                    .help("Comma separated list of origins allowed to make cross origin requests (e.g. https://app.example.com). Requests from other origins are still served without CORS headers, so browsers block them. Listed origins may send credentials to the live tail and Flight endpoints only, the HTTP API never allows them cross origin"),
+            )
+            .arg(
+                Arg::new(Self::HEALTH_CHECK_PATH)
+                    .long(Self::HEALTH_CHECK_PATH)
+                    .env("P_HEALTH_CHECK_PATH")
+                    .value_name("PATH")
+                    .required(false)
+                    .default_value("/liveness")
+                    .value_parser(validation::health_check_path)
+                    .help("Path of the unauthenticated liveness endpoint for load balancer probes"),
+            )
+            .arg(
+                Arg::new(Self::SYSLOG_TCP_ADDR)
+                    .long(Self::SYSLOG_TCP_ADDR)
+                    .env("P_SYSLOG_TCP_ADDR")
+                    .value_name("ADDR:PORT")
+                    .required(false)
+                    .value_parser(validation::socket_addr)
+                    .help("Address on which the syslog server accepts RFC 5424 and RFC 3164 messages over TCP"),
+            )
+            .arg(
+                Arg::new(Self::SYSLOG_UDP_ADDR)
+                    .long(Self::SYSLOG_UDP_ADDR)
+                    .env("P_SYSLOG_UDP_ADDR")
+                    .value_name("ADDR:PORT")
+                    .required(false)
+                    .value_parser(validation::socket_addr)
+                    .help("Address on which the syslog server accepts RFC 5424 and RFC 3164 messages over UDP"),
+            )
+            .arg(
+                Arg::new(Self::SYSLOG_TLS)
+                    .long(Self::SYSLOG_TLS)
+                    .env("P_SYSLOG_TLS")
+                    .value_name("BOOL")
+                    .required(false)
+                    .default_value("false")
+                    .value_parser(value_parser!(bool))
+                    .requires(Self::SYSLOG_TCP_ADDR)
+                    .help("Serve syslog over TLS on the TCP listener, uses the server TLS certificate"),
+            )
+            .arg(
+                Arg::new(Self::SYSLOG_STREAM)
+                    .long(Self::SYSLOG_STREAM)
+                    .env("P_SYSLOG_STREAM")
+                    .value_name("STREAM")
+                    .required(false)
+                    .value_parser(validation::stream_name)
+                    .help("Stream syslog messages go to, when unset the stream is derived from the hostname of each message"),
+            )
+            .arg(
+                Arg::new(Self::SYSLOG_QUARANTINE_STREAM)
+                    .long(Self::SYSLOG_QUARANTINE_STREAM)
+                    .env("P_SYSLOG_QUARANTINE_STREAM")
+                    .value_name("STREAM")
+                    .required(false)
+                    .default_value("syslogquarantine")
+                    .value_parser(validation::stream_name)
+                    .help("Stream syslog messages that can't be parsed go to"),
+            )
+            .arg(
+                Arg::new(Self::FILE_INPUT_CONFIG)
+                    .long(Self::FILE_INPUT_CONFIG)
+                    .env("P_FILE_INPUT_CONFIG")
+                    .value_name("PATH")
+                    .required(false)
+                    .value_parser(value_parser!(PathBuf))
+                    .help("JSON file listing the glob patterns of local files to tail and the streams they go to"),
+            )
+            .arg(
+                Arg::new(Self::FILE_INPUT_SCAN_INTERVAL)
+                    .long(Self::FILE_INPUT_SCAN_INTERVAL)
+                    .env("P_FILE_INPUT_SCAN_INTERVAL")
+                    .value_name("SECONDS")
+                    .required(false)
+                    .default_value("10")
+                    .value_parser(clap::builder::RangedU64ValueParser::<u64>::new().range(1..))
+                    .help("Seconds between the scans for new files matching the file input patterns"),
+            )
+            .arg(
+                Arg::new(Self::FILE_INPUT_RELEASE_AFTER)
+                    .long(Self::FILE_INPUT_RELEASE_AFTER)
+                    .env("P_FILE_INPUT_RELEASE_AFTER")
+                    .value_name("SECONDS")
+                    .required(false)
+                    .default_value("300")
+                    .value_parser(value_parser!(u64))
+                    .help("Seconds a tailed file that was deleted or rotated away is still read before it is let go"),
+            )
+            .args(Self::kafka_args())
+            .args(Self::nats_args())
+            .args(Self::amqp_args())
+            .arg(
+                Arg::new(Self::INGEST_STREAM_MAX_LINE_SIZE)
+                    .long(Self::INGEST_STREAM_MAX_LINE_SIZE)
+                    .env("P_INGEST_STREAM_MAX_LINE_SIZE")
+                    .value_name("size")
+                    .required(false)
+                    .default_value("1MiB")
+                    .value_parser(validation::memory_size)
+                    .help("Largest line of a streamed NDJSON body, longer lines are rejected (e.g. 1MiB)"),
+            )
+            .arg(
+                Arg::new(Self::INGEST_STREAM_MAX_SIZE)
+                    .long(Self::INGEST_STREAM_MAX_SIZE)
+                    .env("P_INGEST_STREAM_MAX_SIZE")
+                    .value_name("size")
+                    .required(false)
+                    .default_value("10GiB")
+                    .value_parser(validation::memory_size)
+                    .help("Bytes a streamed NDJSON connection may send before it is closed (e.g. 10GiB)"),
+            )
+            .arg(
+                Arg::new(Self::INGEST_STREAM_FLUSH_INTERVAL)
+                    .long(Self::INGEST_STREAM_FLUSH_INTERVAL)
+                    .env("P_INGEST_STREAM_FLUSH_INTERVAL")
+                    .value_name("DURATION")
+                    .default_value("5s")
+                    .value_parser(validation::duration)
+                    .help("How often events of open NDJSON streams are flushed to staging, so that they can be queried"),
+            )
+            .arg(
+                Arg::new(Self::DEAD_LETTER_STREAM)
+                    .long(Self::DEAD_LETTER_STREAM)
+                    .env("P_DEAD_LETTER_STREAM")
+                    .value_name("STREAM")
+                    .required(false)
+                    .value_parser(validation::stream_name)
+                    .help("Stream events rejected by any stream go to along with the reason, streams may set their own. Rejected events fail the request when unset"),
+            )
+            .arg(
+                Arg::new(Self::PRINT_CONFIG)
+                    .long(Self::PRINT_CONFIG)
+                    .required(false)
+                    .action(ArgAction::SetTrue)
+                    .help("Print the resolved configuration as JSON with secrets redacted and exit"),
+            )
+            .arg(
+                Arg::new(Self::VALIDATE_ONLY)
+                    .long(Self::VALIDATE_ONLY)
+                    .env("P_VALIDATE_ONLY")
+                    .required(false)
+                    .action(ArgAction::SetTrue)
+                    .help("Run the startup validations, print a summary and exit with a non zero code on failure"),
             ).group(
                 ArgGroup::new("oidc")
                     .args([Self::OPENID_CLIENT_ID, Self::OPENID_CLIENT_SECRET, Self::OPENID_ISSUER])
@@ -390,6 +1210,587 @@ impl Cli {
     }
 }
 
+impl Cli {
+    #[cfg(feature = "kafka")]
+    fn kafka_args() -> Vec<Arg> {
+        vec![
+            Arg::new(Self::KAFKA_BROKERS)
+                .long(Self::KAFKA_BROKERS)
+                .env("P_KAFKA_BROKERS")
+                .value_name("HOST:PORT,HOST:PORT")
+                .required(false)
+                .help("Kafka brokers the connector consumes from, the connector is disabled when unset"),
+            Arg::new(Self::KAFKA_GROUP_ID)
+                .long(Self::KAFKA_GROUP_ID)
+                .env("P_KAFKA_GROUP_ID")
+                .value_name("STRING")
+                .required(false)
+                .default_value("parseable")
+                .help("Consumer group the Kafka connector joins"),
+            Arg::new(Self::KAFKA_TOPICS)
+                .long(Self::KAFKA_TOPICS)
+                .env("P_KAFKA_TOPICS")
+                .value_name("TOPIC,TOPIC")
+                .required(false)
+                .value_delimiter(',')
+                .help("Comma separated list of Kafka topics the connector consumes"),
+            Arg::new(Self::KAFKA_TOPIC_PATTERN)
+                .long(Self::KAFKA_TOPIC_PATTERN)
+                .env("P_KAFKA_TOPIC_PATTERN")
+                .value_name("REGEX")
+                .required(false)
+                .help("Regex of further Kafka topics the connector consumes, matching topics created later are picked up too"),
+            Arg::new(Self::KAFKA_TOPIC_STREAMS)
+                .long(Self::KAFKA_TOPIC_STREAMS)
+                .env("P_KAFKA_TOPIC_STREAMS")
+                .value_name("TOPIC=STREAM,TOPIC=STREAM")
+                .required(false)
+                .value_delimiter(',')
+                .help("Comma separated list of streams Kafka topics go to, topics not listed go to the stream of the template"),
+            Arg::new(Self::KAFKA_STREAM_TEMPLATE)
+                .long(Self::KAFKA_STREAM_TEMPLATE)
+                .env("P_KAFKA_STREAM_TEMPLATE")
+                .value_name("TEMPLATE")
+                .required(false)
+                .default_value("{topic}")
+                .help("Stream a Kafka topic goes to, {topic} is replaced by the topic name and characters streams can't have are dropped (e.g. kafka-{topic})"),
+            Arg::new(Self::KAFKA_CREATE_STREAMS)
+                .long(Self::KAFKA_CREATE_STREAMS)
+                .env("P_KAFKA_CREATE_STREAMS")
+                .value_name("BOOL")
+                .required(false)
+                .default_value("false")
+                .value_parser(value_parser!(bool))
+                .help("Create the streams Kafka topics go to when they don't exist, records of missing streams are dropped otherwise"),
+            Arg::new(Self::KAFKA_COMMIT_STRATEGY)
+                .long(Self::KAFKA_COMMIT_STRATEGY)
+                .env("P_KAFKA_COMMIT_STRATEGY")
+                .value_name("STRATEGY")
+                .required(false)
+                .default_value("flush")
+                .value_parser(["flush", "interval", "records"])
+                .help("When the Kafka connector commits offsets. flush, the default, commits records once the staging flush has them on disk so a crash only has them consumed again. interval and records commit every --kafka-commit-interval seconds or --kafka-commit-records records ingested, records not flushed yet are lost on a crash"),
+            Arg::new(Self::KAFKA_COMMIT_INTERVAL)
+                .long(Self::KAFKA_COMMIT_INTERVAL)
+                .env("P_KAFKA_COMMIT_INTERVAL")
+                .value_name("SECONDS")
+                .required(false)
+                .default_value("5")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Seconds between offset commits of the interval commit strategy"),
+            Arg::new(Self::KAFKA_COMMIT_RECORDS)
+                .long(Self::KAFKA_COMMIT_RECORDS)
+                .env("P_KAFKA_COMMIT_RECORDS")
+                .value_name("RECORDS")
+                .required(false)
+                .default_value("10000")
+                .value_parser(value_parser!(usize))
+                .help("Records ingested between offset commits of the records commit strategy"),
+            Arg::new(Self::KAFKA_SECURITY_PROTOCOL)
+                .long(Self::KAFKA_SECURITY_PROTOCOL)
+                .env("P_KAFKA_SECURITY_PROTOCOL")
+                .value_name("PROTOCOL")
+                .required(false)
+                .default_value("plaintext")
+                .value_parser(["plaintext", "ssl", "sasl_plaintext", "sasl_ssl"])
+                .help("Protocol the Kafka connector talks to the brokers over"),
+            Arg::new(Self::KAFKA_SASL_MECHANISM)
+                .long(Self::KAFKA_SASL_MECHANISM)
+                .env("P_KAFKA_SASL_MECHANISM")
+                .value_name("MECHANISM")
+                .required(false)
+                .value_parser(["PLAIN", "SCRAM-SHA-256", "SCRAM-SHA-512", "OAUTHBEARER"])
+                .help("SASL mechanism the Kafka connector authenticates with, requires a SASL security protocol"),
+            Arg::new(Self::KAFKA_SASL_USERNAME)
+                .long(Self::KAFKA_SASL_USERNAME)
+                .env("P_KAFKA_SASL_USERNAME")
+                .value_name("STRING")
+                .required(false)
+                .help("Username of the PLAIN and SCRAM SASL mechanisms"),
+            Arg::new(Self::KAFKA_SASL_PASSWORD)
+                .long(Self::KAFKA_SASL_PASSWORD)
+                .env("P_KAFKA_SASL_PASSWORD")
+                .value_name("STRING")
+                .required(false)
+                .help("Password of the PLAIN and SCRAM SASL mechanisms"),
+            Arg::new(Self::KAFKA_SASL_PASSWORD_FILE)
+                .long(Self::KAFKA_SASL_PASSWORD_FILE)
+                .env("P_KAFKA_SASL_PASSWORD_FILE")
+                .value_name("PATH")
+                .required(false)
+                .value_parser(validation::file_path)
+                .conflicts_with(Self::KAFKA_SASL_PASSWORD)
+                .help("File holding the password of the PLAIN and SCRAM SASL mechanisms"),
+            Arg::new(Self::KAFKA_OAUTH_TOKEN_ENDPOINT)
+                .long(Self::KAFKA_OAUTH_TOKEN_ENDPOINT)
+                .env("P_KAFKA_OAUTH_TOKEN_ENDPOINT")
+                .value_name("URL")
+                .required(false)
+                .value_parser(validation::url)
+                .help("OIDC token endpoint the OAUTHBEARER SASL mechanism gets tokens from with the client credentials grant"),
+            Arg::new(Self::KAFKA_OAUTH_CLIENT_ID)
+                .long(Self::KAFKA_OAUTH_CLIENT_ID)
+                .env("P_KAFKA_OAUTH_CLIENT_ID")
+                .value_name("STRING")
+                .required(false)
+                .help("Client the OAUTHBEARER SASL mechanism gets tokens as"),
+            Arg::new(Self::KAFKA_OAUTH_CLIENT_SECRET)
+                .long(Self::KAFKA_OAUTH_CLIENT_SECRET)
+                .env("P_KAFKA_OAUTH_CLIENT_SECRET")
+                .value_name("STRING")
+                .required(false)
+                .help("Secret of the client the OAUTHBEARER SASL mechanism gets tokens as"),
+            Arg::new(Self::KAFKA_OAUTH_CLIENT_SECRET_FILE)
+                .long(Self::KAFKA_OAUTH_CLIENT_SECRET_FILE)
+                .env("P_KAFKA_OAUTH_CLIENT_SECRET_FILE")
+                .value_name("PATH")
+                .required(false)
+                .value_parser(validation::file_path)
+                .conflicts_with(Self::KAFKA_OAUTH_CLIENT_SECRET)
+                .help("File holding the secret of the client the OAUTHBEARER SASL mechanism gets tokens as"),
+            Arg::new(Self::KAFKA_OAUTH_SCOPE)
+                .long(Self::KAFKA_OAUTH_SCOPE)
+                .env("P_KAFKA_OAUTH_SCOPE")
+                .value_name("SCOPE")
+                .required(false)
+                .help("Scope of the tokens the OAUTHBEARER SASL mechanism gets"),
+            Arg::new(Self::KAFKA_TLS_CA)
+                .long(Self::KAFKA_TLS_CA)
+                .env("P_KAFKA_TLS_CA_PATH")
+                .value_name("PATH")
+                .required(false)
+                .value_parser(validation::file_path)
+                .help("CA certificate the Kafka brokers are verified with, the system roots are used when unset"),
+            Arg::new(Self::KAFKA_TLS_CERT)
+                .long(Self::KAFKA_TLS_CERT)
+                .env("P_KAFKA_TLS_CERT_PATH")
+                .value_name("PATH")
+                .required(false)
+                .value_parser(validation::file_path)
+                .help("Client certificate the Kafka connector presents to the brokers"),
+            Arg::new(Self::KAFKA_TLS_KEY)
+                .long(Self::KAFKA_TLS_KEY)
+                .env("P_KAFKA_TLS_KEY_PATH")
+                .value_name("PATH")
+                .required(false)
+                .value_parser(validation::file_path)
+                .help("Private key of the Kafka client certificate"),
+            Arg::new(Self::KAFKA_TLS_KEY_PASSWORD)
+                .long(Self::KAFKA_TLS_KEY_PASSWORD)
+                .env("P_KAFKA_TLS_KEY_PASSWORD")
+                .value_name("STRING")
+                .required(false)
+                .help("Password protecting the private key of the Kafka client certificate"),
+            Arg::new(Self::KAFKA_TLS_KEY_PASSWORD_FILE)
+                .long(Self::KAFKA_TLS_KEY_PASSWORD_FILE)
+                .env("P_KAFKA_TLS_KEY_PASSWORD_FILE")
+                .value_name("PATH")
+                .required(false)
+                .value_parser(validation::file_path)
+                .conflicts_with(Self::KAFKA_TLS_KEY_PASSWORD)
+                .help("File holding the password protecting the private key of the Kafka client certificate"),
+        ]
+    }
+
+    #[cfg(not(feature = "kafka"))]
+    fn kafka_args() -> Vec<Arg> {
+        Vec::new()
+    }
+
+    #[cfg(feature = "kafka")]
+    fn update_kafka_from_arg_matches(&mut self, m: &clap::ArgMatches) -> Result<(), clap::Error> {
+        self.kafka_brokers = m.get_one::<String>(Self::KAFKA_BROKERS).cloned();
+        self.kafka_group_id = m
+            .get_one::<String>(Self::KAFKA_GROUP_ID)
+            .cloned()
+            .expect("default for kafka group id");
+        self.kafka_topics = m
+            .get_many::<String>(Self::KAFKA_TOPICS)
+            .map(|topics| topics.cloned().collect())
+            .unwrap_or_default();
+        self.kafka_topic_pattern = m.get_one::<String>(Self::KAFKA_TOPIC_PATTERN).cloned();
+        self.kafka_topic_streams = m
+            .get_many::<String>(Self::KAFKA_TOPIC_STREAMS)
+            .map(|streams| streams.cloned().collect())
+            .unwrap_or_default();
+        self.kafka_stream_template = m
+            .get_one::<String>(Self::KAFKA_STREAM_TEMPLATE)
+            .cloned()
+            .expect("default for kafka stream template");
+        self.kafka_create_streams = m
+            .get_one::<bool>(Self::KAFKA_CREATE_STREAMS)
+            .cloned()
+            .expect("default for kafka create streams");
+        self.kafka_commit_strategy = m
+            .get_one::<String>(Self::KAFKA_COMMIT_STRATEGY)
+            .cloned()
+            .expect("default for kafka commit strategy");
+        self.kafka_commit_interval = m
+            .get_one::<u64>(Self::KAFKA_COMMIT_INTERVAL)
+            .cloned()
+            .expect("default for kafka commit interval");
+        self.kafka_commit_records = m
+            .get_one::<usize>(Self::KAFKA_COMMIT_RECORDS)
+            .cloned()
+            .expect("default for kafka commit records");
+        self.kafka_security_protocol = m
+            .get_one::<String>(Self::KAFKA_SECURITY_PROTOCOL)
+            .cloned()
+            .expect("default for kafka security protocol");
+        self.kafka_sasl_mechanism = m.get_one::<String>(Self::KAFKA_SASL_MECHANISM).cloned();
+        self.kafka_sasl_username = m.get_one::<String>(Self::KAFKA_SASL_USERNAME).cloned();
+        self.kafka_sasl_password =
+            secret_arg(m, Self::KAFKA_SASL_PASSWORD, Self::KAFKA_SASL_PASSWORD_FILE)?;
+        self.kafka_oauth_token_endpoint =
+            m.get_one::<Url>(Self::KAFKA_OAUTH_TOKEN_ENDPOINT).cloned();
+        self.kafka_oauth_client_id = m.get_one::<String>(Self::KAFKA_OAUTH_CLIENT_ID).cloned();
+        self.kafka_oauth_client_secret = secret_arg(
+            m,
+            Self::KAFKA_OAUTH_CLIENT_SECRET,
+            Self::KAFKA_OAUTH_CLIENT_SECRET_FILE,
+        )?;
+        self.kafka_oauth_scope = m.get_one::<String>(Self::KAFKA_OAUTH_SCOPE).cloned();
+        self.kafka_tls_ca_path = m.get_one::<PathBuf>(Self::KAFKA_TLS_CA).cloned();
+        self.kafka_tls_cert_path = m.get_one::<PathBuf>(Self::KAFKA_TLS_CERT).cloned();
+        self.kafka_tls_key_path = m.get_one::<PathBuf>(Self::KAFKA_TLS_KEY).cloned();
+        self.kafka_tls_key_password = secret_arg(
+            m,
+            Self::KAFKA_TLS_KEY_PASSWORD,
+            Self::KAFKA_TLS_KEY_PASSWORD_FILE,
+        )?;
+        Ok(())
+    }
+
+    #[cfg(feature = "nats")]
+    fn nats_args() -> Vec<Arg> {
+        vec![
+            Arg::new(Self::NATS_SERVERS)
+                .long(Self::NATS_SERVERS)
+                .env("P_NATS_SERVERS")
+                .value_name("URL,URL")
+                .required(false)
+                .help("NATS servers the connector consumes from, the connector is disabled when unset"),
+            Arg::new(Self::NATS_STREAM)
+                .long(Self::NATS_STREAM)
+                .env("P_NATS_STREAM")
+                .value_name("STREAM")
+                .required(false)
+                .help("JetStream stream the NATS connector consumes, required with --nats-servers"),
+            Arg::new(Self::NATS_CONSUMER)
+                .long(Self::NATS_CONSUMER)
+                .env("P_NATS_CONSUMER")
+                .value_name("STRING")
+                .required(false)
+                .default_value("parseable")
+                .help("Durable pull consumer the NATS connector consumes through, created when it doesn't exist"),
+            Arg::new(Self::NATS_SUBJECTS)
+                .long(Self::NATS_SUBJECTS)
+                .env("P_NATS_SUBJECTS")
+                .value_name("SUBJECT,SUBJECT")
+                .required(false)
+                .value_delimiter(',')
+                .help("Comma separated list of subjects of the JetStream stream the NATS connector consumes, all of them when unset"),
+            Arg::new(Self::NATS_SUBJECT_STREAMS)
+                .long(Self::NATS_SUBJECT_STREAMS)
+                .env("P_NATS_SUBJECT_STREAMS")
+                .value_name("SUBJECT=STREAM,SUBJECT=STREAM")
+                .required(false)
+                .value_delimiter(',')
+                .help("Comma separated list of streams NATS subjects go to, subjects may have * and > wildcards and the first match wins (e.g. logs.app.>=app)"),
+            Arg::new(Self::NATS_DEFAULT_STREAM)
+                .long(Self::NATS_DEFAULT_STREAM)
+                .env("P_NATS_DEFAULT_STREAM")
+                .value_name("STREAM")
+                .required(false)
+                .value_parser(validation::stream_name)
+                .help("Stream NATS messages of subjects no mapping matches go to, these messages are dropped when unset"),
+            Arg::new(Self::NATS_CREATE_STREAMS)
+                .long(Self::NATS_CREATE_STREAMS)
+                .env("P_NATS_CREATE_STREAMS")
+                .value_name("BOOL")
+                .required(false)
+                .default_value("false")
+                .value_parser(value_parser!(bool))
+                .help("Create the streams NATS subjects go to when they don't exist, messages of missing streams are redelivered otherwise"),
+            Arg::new(Self::NATS_MAX_IN_FLIGHT)
+                .long(Self::NATS_MAX_IN_FLIGHT)
+                .env("P_NATS_MAX_IN_FLIGHT")
+                .value_name("MESSAGES")
+                .required(false)
+                .default_value("1000")
+                .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+                .help("NATS messages delivered to the connector but not acked yet at most, the server holds back further messages until they are"),
+            Arg::new(Self::NATS_BATCH_SIZE)
+                .long(Self::NATS_BATCH_SIZE)
+                .env("P_NATS_BATCH_SIZE")
+                .value_name("MESSAGES")
+                .required(false)
+                .default_value("100")
+                .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+                .help("NATS messages fetched and ingested at once, capped by --nats-max-in-flight"),
+            Arg::new(Self::NATS_ACK_WAIT)
+                .long(Self::NATS_ACK_WAIT)
+                .env("P_NATS_ACK_WAIT")
+                .value_name("SECONDS")
+                .required(false)
+                .default_value("30")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Seconds a NATS message may stay unacked before the server redelivers it"),
+            Arg::new(Self::NATS_TOKEN)
+                .long(Self::NATS_TOKEN)
+                .env("P_NATS_TOKEN")
+                .value_name("STRING")
+                .required(false)
+                .help("Token the NATS connector authenticates with"),
+            Arg::new(Self::NATS_TOKEN_FILE)
+                .long(Self::NATS_TOKEN_FILE)
+                .env("P_NATS_TOKEN_FILE")
+                .value_name("PATH")
+                .required(false)
+                .value_parser(validation::file_path)
+                .conflicts_with(Self::NATS_TOKEN)
+                .help("File holding the token the NATS connector authenticates with"),
+            Arg::new(Self::NATS_NKEY_SEED)
+                .long(Self::NATS_NKEY_SEED)
+                .env("P_NATS_NKEY_SEED")
+                .value_name("SEED")
+                .required(false)
+                .help("NKey seed the NATS connector authenticates with"),
+            Arg::new(Self::NATS_NKEY_SEED_FILE)
+                .long(Self::NATS_NKEY_SEED_FILE)
+                .env("P_NATS_NKEY_SEED_FILE")
+                .value_name("PATH")
+                .required(false)
+                .value_parser(validation::file_path)
+                .conflicts_with(Self::NATS_NKEY_SEED)
+                .help("File holding the NKey seed the NATS connector authenticates with"),
+            Arg::new(Self::NATS_USER)
+                .long(Self::NATS_USER)
+                .env("P_NATS_USER")
+                .value_name("STRING")
+                .required(false)
+                .help("User the NATS connector authenticates as"),
+            Arg::new(Self::NATS_PASSWORD)
+                .long(Self::NATS_PASSWORD)
+                .env("P_NATS_PASSWORD")
+                .value_name("STRING")
+                .required(false)
+                .help("Password of the NATS user"),
+            Arg::new(Self::NATS_PASSWORD_FILE)
+                .long(Self::NATS_PASSWORD_FILE)
+                .env("P_NATS_PASSWORD_FILE")
+                .value_name("PATH")
+                .required(false)
+                .value_parser(validation::file_path)
+                .conflicts_with(Self::NATS_PASSWORD)
+                .help("File holding the password of the NATS user"),
+        ]
+    }
+
+    #[cfg(not(feature = "nats"))]
+    fn nats_args() -> Vec<Arg> {
+        Vec::new()
+    }
+
+    #[cfg(feature = "nats")]
+    fn update_nats_from_arg_matches(&mut self, m: &clap::ArgMatches) -> Result<(), clap::Error> {
+        self.nats_servers = m.get_one::<String>(Self::NATS_SERVERS).cloned();
+        self.nats_stream = m.get_one::<String>(Self::NATS_STREAM).cloned();
+        self.nats_consumer = m
+            .get_one::<String>(Self::NATS_CONSUMER)
+            .cloned()
+            .expect("default for nats consumer");
+        self.nats_subjects = m
+            .get_many::<String>(Self::NATS_SUBJECTS)
+            .map(|subjects| subjects.cloned().collect())
+            .unwrap_or_default();
+        self.nats_subject_streams = m
+            .get_many::<String>(Self::NATS_SUBJECT_STREAMS)
+            .map(|streams| streams.cloned().collect())
+            .unwrap_or_default();
+        self.nats_default_stream = m.get_one::<String>(Self::NATS_DEFAULT_STREAM).cloned();
+        self.nats_create_streams = m
+            .get_one::<bool>(Self::NATS_CREATE_STREAMS)
+            .cloned()
+            .expect("default for nats create streams");
+        self.nats_max_in_flight = m
+            .get_one::<usize>(Self::NATS_MAX_IN_FLIGHT)
+            .cloned()
+            .expect("default for nats max in flight");
+        self.nats_batch_size = m
+            .get_one::<usize>(Self::NATS_BATCH_SIZE)
+            .cloned()
+            .expect("default for nats batch size");
+        self.nats_ack_wait = m
+            .get_one::<u64>(Self::NATS_ACK_WAIT)
+            .cloned()
+            .expect("default for nats ack wait");
+        self.nats_token = secret_arg(m, Self::NATS_TOKEN, Self::NATS_TOKEN_FILE)?;
+        self.nats_nkey_seed = secret_arg(m, Self::NATS_NKEY_SEED, Self::NATS_NKEY_SEED_FILE)?;
+        self.nats_user = m.get_one::<String>(Self::NATS_USER).cloned();
+        self.nats_password = secret_arg(m, Self::NATS_PASSWORD, Self::NATS_PASSWORD_FILE)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "amqp")]
+    fn amqp_args() -> Vec<Arg> {
+        vec![
+            Arg::new(Self::AMQP_URI)
+                .long(Self::AMQP_URI)
+                .env("P_AMQP_URI")
+                .value_name("URI")
+                .required(false)
+                .help("URI of the AMQP broker the connector consumes from, amqps:// for TLS. The connector is disabled when unset"),
+            Arg::new(Self::AMQP_URI_FILE)
+                .long(Self::AMQP_URI_FILE)
+                .env("P_AMQP_URI_FILE")
+                .value_name("PATH")
+                .required(false)
+                .value_parser(validation::file_path)
+                .conflicts_with(Self::AMQP_URI)
+                .help("File holding the URI of the AMQP broker, for URIs with credentials"),
+            Arg::new(Self::AMQP_QUEUES)
+                .long(Self::AMQP_QUEUES)
+                .env("P_AMQP_QUEUES")
+                .value_name("QUEUE,QUEUE")
+                .required(false)
+                .value_delimiter(',')
+                .help("Comma separated list of AMQP queues the connector consumes"),
+            Arg::new(Self::AMQP_QUEUE_STREAMS)
+                .long(Self::AMQP_QUEUE_STREAMS)
+                .env("P_AMQP_QUEUE_STREAMS")
+                .value_name("QUEUE=STREAM,QUEUE=STREAM")
+                .required(false)
+                .value_delimiter(',')
+                .help("Comma separated list of streams AMQP queues go to, queues not listed go to the stream of the template"),
+            Arg::new(Self::AMQP_STREAM_TEMPLATE)
+                .long(Self::AMQP_STREAM_TEMPLATE)
+                .env("P_AMQP_STREAM_TEMPLATE")
+                .value_name("TEMPLATE")
+                .required(false)
+                .default_value("{queue}")
+                .help("Stream an AMQP queue goes to, {queue} is replaced by the queue name and characters streams can't have are dropped (e.g. amqp-{queue})"),
+            Arg::new(Self::AMQP_CREATE_STREAMS)
+                .long(Self::AMQP_CREATE_STREAMS)
+                .env("P_AMQP_CREATE_STREAMS")
+                .value_name("BOOL")
+                .required(false)
+                .default_value("false")
+                .value_parser(value_parser!(bool))
+                .help("Create the streams AMQP queues go to when they don't exist, messages of missing streams are requeued otherwise"),
+            Arg::new(Self::AMQP_PREFETCH)
+                .long(Self::AMQP_PREFETCH)
+                .env("P_AMQP_PREFETCH")
+                .value_name("MESSAGES")
+                .required(false)
+                .default_value("100")
+                .value_parser(value_parser!(u16).range(1..))
+                .help("AMQP messages delivered to the connector but not acked yet at most, they are ingested together"),
+            Arg::new(Self::AMQP_REJECT)
+                .long(Self::AMQP_REJECT)
+                .env("P_AMQP_REJECT")
+                .value_name("ACTION")
+                .required(false)
+                .default_value("dead-letter")
+                .value_parser(["dead-letter", "requeue"])
+                .help("What becomes of AMQP messages that can't be decoded. dead-letter, the default, nacks them to the dead letter exchange of their queue, dropping them when it has none. requeue has them delivered again"),
+            Arg::new(Self::AMQP_TLS_CA)
+                .long(Self::AMQP_TLS_CA)
+                .env("P_AMQP_TLS_CA_PATH")
+                .value_name("PATH")
+                .required(false)
+                .value_parser(validation::file_path)
+                .help("PEM CA certificate the AMQP broker is verified with, the system roots when unset"),
+            Arg::new(Self::AMQP_TLS_PKCS12)
+                .long(Self::AMQP_TLS_PKCS12)
+                .env("P_AMQP_TLS_PKCS12_PATH")
+                .value_name("PATH")
+                .required(false)
+                .value_parser(validation::file_path)
+                .help("PKCS#12 bundle of the client certificate and key the AMQP connector presents to the broker"),
+            Arg::new(Self::AMQP_TLS_PKCS12_PASSWORD)
+                .long(Self::AMQP_TLS_PKCS12_PASSWORD)
+                .env("P_AMQP_TLS_PKCS12_PASSWORD")
+                .value_name("STRING")
+                .required(false)
+                .help("Password of the PKCS#12 bundle of the AMQP client certificate"),
+            Arg::new(Self::AMQP_TLS_PKCS12_PASSWORD_FILE)
+                .long(Self::AMQP_TLS_PKCS12_PASSWORD_FILE)
+                .env("P_AMQP_TLS_PKCS12_PASSWORD_FILE")
+                .value_name("PATH")
+                .required(false)
+                .value_parser(validation::file_path)
+                .conflicts_with(Self::AMQP_TLS_PKCS12_PASSWORD)
+                .help("File holding the password of the PKCS#12 bundle of the AMQP client certificate"),
+        ]
+    }
+
+    #[cfg(not(feature = "amqp"))]
+    fn amqp_args() -> Vec<Arg> {
+        Vec::new()
+    }
+
+    #[cfg(feature = "amqp")]
+    fn update_amqp_from_arg_matches(&mut self, m: &clap::ArgMatches) -> Result<(), clap::Error> {
+        self.amqp_uri = secret_arg(m, Self::AMQP_URI, Self::AMQP_URI_FILE)?;
+        self.amqp_queues = m
+            .get_many::<String>(Self::AMQP_QUEUES)
+            .map(|queues| queues.cloned().collect())
+            .unwrap_or_default();
+        self.amqp_queue_streams = m
+            .get_many::<String>(Self::AMQP_QUEUE_STREAMS)
+            .map(|streams| streams.cloned().collect())
+            .unwrap_or_default();
+        self.amqp_stream_template = m
+            .get_one::<String>(Self::AMQP_STREAM_TEMPLATE)
+            .cloned()
+            .expect("default for amqp stream template");
+        self.amqp_create_streams = m
+            .get_one::<bool>(Self::AMQP_CREATE_STREAMS)
+            .cloned()
+            .expect("default for amqp create streams");
+        self.amqp_prefetch = m
+            .get_one::<u16>(Self::AMQP_PREFETCH)
+            .cloned()
+            .expect("default for amqp prefetch");
+        self.amqp_reject = m
+            .get_one::<String>(Self::AMQP_REJECT)
+            .cloned()
+            .expect("default for amqp reject");
+        self.amqp_tls_ca_path = m.get_one::<PathBuf>(Self::AMQP_TLS_CA).cloned();
+        self.amqp_tls_pkcs12_path = m.get_one::<PathBuf>(Self::AMQP_TLS_PKCS12).cloned();
+        self.amqp_tls_pkcs12_password = secret_arg(
+            m,
+            Self::AMQP_TLS_PKCS12_PASSWORD,
+            Self::AMQP_TLS_PKCS12_PASSWORD_FILE,
+        )?;
+        Ok(())
+    }
+}
+
+// a secret given either as the value of `arg` or as the contents of the file
+// at `file_arg`, without the trailing newline files usually end with
+#[cfg(any(feature = "kafka", feature = "nats", feature = "amqp"))]
+fn secret_arg(
+    m: &clap::ArgMatches,
+    arg: &str,
+    file_arg: &str,
+) -> Result<Option<String>, clap::Error> {
+    if let Some(secret) = m.get_one::<String>(arg) {
+        return Ok(Some(secret.clone()));
+    }
+    let Some(path) = m.get_one::<PathBuf>(file_arg) else {
+        return Ok(None);
+    };
+    let secret = std::fs::read_to_string(path).map_err(|err| {
+        clap::Error::raw(
+            clap::error::ErrorKind::Io,
+            format!("Failed to read --{file_arg} {}: {err}\n", path.display()),
+        )
+    })?;
+    Ok(Some(secret.trim_end_matches(['\r', '\n']).to_owned()))
+}
+
 impl FromArgMatches for Cli {
     fn from_arg_matches(m: &clap::ArgMatches) -> Result<Self, clap::Error> {
         let mut s: Self = Self::default();
@@ -402,12 +1803,41 @@ impl FromArgMatches for Cli {
         self.query_cache_path = m.get_one::<PathBuf>(Self::QUERY_CACHE).cloned();
         self.tls_cert_path = m.get_one::<PathBuf>(Self::TLS_CERT).cloned();
         self.tls_key_path = m.get_one::<PathBuf>(Self::TLS_KEY).cloned();
+        self.tls_pkcs12_path = m.get_one::<PathBuf>(Self::TLS_PKCS12).cloned();
+        self.tls_pkcs12_password = m.get_one::<String>(Self::TLS_PKCS12_PASSWORD).cloned();
+        self.tls_min_version = match m
+            .get_one::<String>(Self::TLS_MIN_VERSION)
+            .expect("default for tls min version")
+            .as_str()
+        {
+            "1.2" => TlsVersion::V1_2,
+            "1.3" => TlsVersion::V1_3,
+            _ => unreachable!(),
+        };
+        self.tls_cipher_suites = m
+            .get_many::<String>(Self::TLS_CIPHER_SUITES)
+            .map(|suites| suites.cloned().collect())
+            .unwrap_or_default();
         self.domain_address = m.get_one::<Url>(Self::DOMAIN_URI).cloned();
 
         self.address = m
             .get_one::<String>(Self::ADDRESS)
             .cloned()
             .expect("default value for address");
+        self.address_resolution = match m
+            .get_one::<String>(Self::ADDRESS_RESOLUTION)
+            .expect("default for address resolution")
+            .as_str()
+        {
+            "first" => AddrResolution::First,
+            "all" => AddrResolution::All,
+            _ => unreachable!(),
+        };
+        self.uds_path = m.get_one::<PathBuf>(Self::UDS_PATH).cloned();
+        self.uds_permissions = m
+            .get_one::<u32>(Self::UDS_PERMISSIONS)
+            .cloned()
+            .expect("default for uds permissions");
 
         self.ingestor_endpoint = m
             .get_one::<String>(Self::INGESTOR_ENDPOINT)
@@ -418,6 +1848,17 @@ impl FromArgMatches for Cli {
             .get_one::<PathBuf>(Self::STAGING)
             .cloned()
             .expect("default value for staging");
+        self.max_disk_usage = m.get_one::<f64>(Self::MAX_DISK_USAGE).cloned();
+        self.disk_usage_action = match m
+            .get_one::<String>(Self::DISK_USAGE_ACTION)
+            .expect("default for disk usage action")
+            .as_str()
+        {
+            "reject" => DiskUsageAction::Reject,
+            "evict" => DiskUsageAction::Evict,
+            "readonly" => DiskUsageAction::ReadOnly,
+            _ => unreachable!(),
+        };
         self.local_cache_size = m
             .get_one::<u64>(Self::CACHE_SIZE)
             .cloned()
@@ -460,6 +1901,34 @@ impl FromArgMatches for Cli {
             .get_one::<u8>(Self::QUERY_MEM_POOL_SIZE)
             .cloned()
             .map(|gib| gib as usize * 1024usize.pow(3));
+        self.query_result_cache_ttl = m.get_one::<Duration>(Self::QUERY_RESULT_CACHE_TTL).cloned();
+        self.query_result_cache_size = m
+            .get_one::<u64>(Self::QUERY_RESULT_CACHE_SIZE)
+            .cloned()
+            .expect("default for query result cache size");
+        self.max_concurrent_queries = m
+            .get_one::<u64>(Self::MAX_CONCURRENT_QUERIES)
+            .map(|limit| *limit as usize);
+        self.query_queue_size = m
+            .get_one::<u64>(Self::QUERY_QUEUE_SIZE)
+            .map(|size| *size as usize)
+            .expect("default for query queue size");
+        self.query_queue_timeout = m
+            .get_one::<Duration>(Self::QUERY_QUEUE_TIMEOUT)
+            .cloned()
+            .expect("default for query queue timeout");
+        self.correlation_join_window = m
+            .get_one::<Duration>(Self::CORRELATION_JOIN_WINDOW)
+            .cloned()
+            .expect("default for correlation join window");
+        self.query_threads = m
+            .get_one::<u64>(Self::QUERY_THREADS)
+            .map(|threads| *threads as usize)
+            .unwrap_or_else(num_cpus::get);
+        self.store_concurrency = m
+            .get_one::<u64>(Self::STORE_CONCURRENCY)
+            .map(|permits| *permits as usize)
+            .expect("default for store concurrency");
         self.row_group_size = m
             .get_one::<usize>(Self::ROW_GROUP_SIZE)
             .cloned()
@@ -478,6 +1947,12 @@ impl FromArgMatches for Cli {
             "zstd" => Compression::ZSTD,
             _ => unreachable!(),
         };
+        if let Err(err) = self.parquet_compression.validate() {
+            return Err(clap::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                err,
+            ));
+        }
 
         let openid_client_id = m.get_one::<String>(Self::OPENID_CLIENT_ID).cloned();
         let openid_client_secret = m.get_one::<String>(Self::OPENID_CLIENT_SECRET).cloned();
@@ -490,7 +1965,7 @@ impl FromArgMatches for Cli {
                 } else {
                     oidc::Origin::Local {
                         socket_addr: self.address.clone(),
-                        https: self.tls_cert_path.is_some() && self.tls_key_path.is_some(),
+                        https: self.tls_identity().is_some(),
                     }
                 };
                 Some(OpenidConfig {
@@ -503,6 +1978,66 @@ impl FromArgMatches for Cli {
             _ => None,
         };
 
+        self.cors = m
+            .get_one::<bool>(Self::CORS)
+            .cloned()
+            .expect("default for cors");
+        self.cors_origins = m
+            .get_many::<String>(Self::CORS_ORIGINS)
+            .map(|origins| origins.cloned().collect())
+            .unwrap_or_default();
+        self.health_check_path = m
+            .get_one::<String>(Self::HEALTH_CHECK_PATH)
+            .cloned()
+            .expect("default for health check path");
+        self.syslog_tcp_addr = m.get_one::<String>(Self::SYSLOG_TCP_ADDR).cloned();
+        self.syslog_udp_addr = m.get_one::<String>(Self::SYSLOG_UDP_ADDR).cloned();
+        self.syslog_tls = m
+            .get_one::<bool>(Self::SYSLOG_TLS)
+            .cloned()
+            .expect("default for syslog tls");
+        if self.syslog_tls && self.tls_identity().is_none() {
+            return Err(clap::Error::raw(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "Syslog over TLS requires the server TLS certificate, set the TLS cert and key or PKCS#12 paths\n",
+            ));
+        }
+        self.syslog_stream = m.get_one::<String>(Self::SYSLOG_STREAM).cloned();
+        self.syslog_quarantine_stream = m
+            .get_one::<String>(Self::SYSLOG_QUARANTINE_STREAM)
+            .cloned()
+            .expect("default for syslog quarantine stream");
+        self.file_input_config = m.get_one::<PathBuf>(Self::FILE_INPUT_CONFIG).cloned();
+        self.file_input_scan_interval = m
+            .get_one::<u64>(Self::FILE_INPUT_SCAN_INTERVAL)
+            .cloned()
+            .expect("default for file input scan interval");
+        self.file_input_release_after = m
+            .get_one::<u64>(Self::FILE_INPUT_RELEASE_AFTER)
+            .cloned()
+            .expect("default for file input release after");
+        #[cfg(feature = "kafka")]
+        self.update_kafka_from_arg_matches(m)?;
+        #[cfg(feature = "nats")]
+        self.update_nats_from_arg_matches(m)?;
+        #[cfg(feature = "amqp")]
+        self.update_amqp_from_arg_matches(m)?;
+        self.ingest_stream_max_line_size = m
+            .get_one::<u64>(Self::INGEST_STREAM_MAX_LINE_SIZE)
+            .cloned()
+            .expect("default for ingest stream max line size");
+        self.ingest_stream_max_size = m
+            .get_one::<u64>(Self::INGEST_STREAM_MAX_SIZE)
+            .cloned()
+            .expect("default for ingest stream max size");
+        self.ingest_stream_flush_interval = m
+            .get_one::<Duration>(Self::INGEST_STREAM_FLUSH_INTERVAL)
+            .cloned()
+            .expect("default for ingest stream flush interval");
+        self.dead_letter_stream = m.get_one::<String>(Self::DEAD_LETTER_STREAM).cloned();
+        self.print_config = m.get_flag(Self::PRINT_CONFIG);
+        self.validate_only = m.get_flag(Self::VALIDATE_ONLY);
+
         self.mode = match m
             .get_one::<String>(Self::MODE)
             .expect("Mode not set")
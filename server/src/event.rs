@@ -18,6 +18,9 @@
 */
 
 pub mod format;
+pub mod timestamp;
+pub mod transform;
+pub mod widening;
 mod writer;
 
 use arrow_array::RecordBatch;
@@ -26,7 +29,8 @@ use itertools::Itertools;
 use std::sync::Arc;
 
 use self::error::EventError;
-pub use self::writer::STREAM_WRITERS;
+use self::widening::SchemaChange;
+pub use self::writer::{StagedBatch, STAGING_FLUSHES, STREAM_WRITERS};
 use crate::{handlers::http::ingest::PostError, metadata};
 use chrono::NaiveDateTime;
 use std::collections::HashMap;
@@ -34,6 +38,7 @@ use std::collections::HashMap;
 pub const DEFAULT_TIMESTAMP_KEY: &str = "p_timestamp";
 pub const DEFAULT_TAGS_KEY: &str = "p_tags";
 pub const DEFAULT_METADATA_KEY: &str = "p_metadata";
+pub const DEFAULT_TIMESTAMP_SOURCE_KEY: &str = "p_timestamp_source";
 
 #[derive(Clone)]
 pub struct Event {
@@ -50,20 +55,7 @@ pub struct Event {
 // Events holds the schema related to a each event for a single log stream
 impl Event {
     pub async fn process(&self) -> Result<(), EventError> {
-        let mut key = get_schema_key(&self.rb.schema().fields);
-        if self.time_partition.is_some() {
-            let parsed_timestamp_to_min = self.parsed_timestamp.format("%Y%m%dT%H%M").to_string();
-            key = format!("{key}{parsed_timestamp_to_min}");
-        }
-
-        if !self.custom_partition_values.is_empty() {
-            let mut custom_partition_key = String::default();
-            for (k, v) in self.custom_partition_values.iter().sorted_by_key(|v| v.0) {
-                custom_partition_key = format!("{custom_partition_key}&{k}={v}");
-            }
-            key = format!("{key}{custom_partition_key}");
-        }
-
+        let key = self.staging_key();
         let num_rows = self.rb.num_rows() as u64;
         if self.is_first_event {
             commit_schema(&self.stream_name, self.rb.schema())?;
@@ -97,6 +89,84 @@ impl Event {
         Ok(())
     }
 
+    /// Processes the events of a stream as one, either all of them are staged
+    /// or none is. The columns they add are checked against the stream schema
+    /// before anything is staged and committed together once they are
+    pub async fn process_atomic(stream_name: &str, events: Vec<Event>) -> Result<u64, EventError> {
+        let added: Vec<Schema> = events
+            .iter()
+            .filter(|event| event.is_first_event)
+            .map(|event| event.rb.schema().as_ref().clone())
+            .collect();
+        let schema = if added.is_empty() {
+            None
+        } else {
+            let current = metadata::STREAM_INFO.schema(stream_name)?;
+            let merged =
+                widening::merge_schemas(std::iter::once(current.as_ref().clone()).chain(added))?;
+            Some(Arc::new(merged))
+        };
+
+        let batches = events
+            .iter()
+            .map(|event| StagedBatch {
+                schema_key: event.staging_key(),
+                rb: event.rb.clone(),
+                parsed_timestamp: event.parsed_timestamp,
+                custom_partition_values: event.custom_partition_values.clone(),
+            })
+            .collect();
+        STREAM_WRITERS.append_atomic(stream_name, batches)?;
+        if let Some(schema) = schema {
+            commit_schema(stream_name, schema)?;
+        }
+
+        let mut num_rows = 0;
+        for event in &events {
+            let rows = event.rb.num_rows() as u64;
+            num_rows += rows;
+            metadata::STREAM_INFO.update_stats(
+                stream_name,
+                event.origin_format,
+                event.origin_size,
+                rows,
+                event.parsed_timestamp,
+            )?;
+            crate::livetail::LIVETAIL.process(stream_name, &event.rb);
+            if let Err(e) = metadata::STREAM_INFO
+                .check_alerts(stream_name, &event.rb)
+                .await
+            {
+                log::error!("Error checking for alerts. {:?}", e);
+            }
+        }
+        Ok(num_rows)
+    }
+
+    // key of the staging writer the batch is appended to
+    fn staging_key(&self) -> String {
+        let mut key = get_schema_key(&self.rb.schema().fields);
+        // batches stamped with their event time are staged by that time
+        let event_time = self
+            .rb
+            .schema()
+            .column_with_name(DEFAULT_TIMESTAMP_SOURCE_KEY)
+            .is_some();
+        if self.time_partition.is_some() || event_time {
+            let parsed_timestamp_to_min = self.parsed_timestamp.format("%Y%m%dT%H%M").to_string();
+            key = format!("{key}{parsed_timestamp_to_min}");
+        }
+
+        if !self.custom_partition_values.is_empty() {
+            let mut custom_partition_key = String::default();
+            for (k, v) in self.custom_partition_values.iter().sorted_by_key(|v| v.0) {
+                custom_partition_key = format!("{custom_partition_key}&{k}={v}");
+            }
+            key = format!("{key}{custom_partition_key}");
+        }
+        key
+    }
+
     pub fn process_unchecked(&self) -> Result<(), PostError> {
         let key = get_schema_key(&self.rb.schema().fields);
 
@@ -135,10 +205,12 @@ impl Event {
 }
 
 pub fn get_schema_key(fields: &[Arc<Field>]) -> String {
-    // Fields must be sorted
+    // Fields must be sorted, types are part of the key as batches of a
+    // widened column can't share a writer with those from before
     let mut hasher = xxhash_rust::xxh3::Xxh3::new();
     for field in fields.iter().sorted_by_key(|v| v.name()) {
         hasher.update(field.name().as_bytes());
+        hasher.update(field.data_type().to_string().as_bytes());
     }
     let hash = hasher.digest();
     format!("{hash:x}")
@@ -152,12 +224,26 @@ pub fn commit_schema(stream_name: &str, schema: Arc<Schema>) -> Result<(), Event
         .expect("map has entry for this stream name")
         .schema;
     let current_schema = Schema::new(map.values().cloned().collect::<Fields>());
-    let schema = Schema::try_merge(vec![current_schema, schema.as_ref().clone()])?;
+    let schema = widening::merge_schemas(vec![current_schema, schema.as_ref().clone()])?;
     map.clear();
     map.extend(schema.fields.iter().map(|f| (f.name().clone(), f.clone())));
     Ok(())
 }
 
+pub fn commit_widened_schema(stream_name: &str, changes: &[SchemaChange]) {
+    let mut stream_metadata = metadata::STREAM_INFO.write().expect("lock poisoned");
+
+    let map = &mut stream_metadata
+        .get_mut(stream_name)
+        .expect("map has entry for this stream name")
+        .schema;
+    for change in changes {
+        if let Some(field) = map.get_mut(&change.field) {
+            *field = Arc::new(widening::widen_field(field, &change.to));
+        }
+    }
+}
+
 pub mod error {
     use arrow_schema::ArrowError;
 
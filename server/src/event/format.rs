@@ -27,7 +27,10 @@ use crate::utils::{self, arrow::get_field};
 
 use super::{DEFAULT_METADATA_KEY, DEFAULT_TAGS_KEY, DEFAULT_TIMESTAMP_KEY};
 
+pub mod csv;
+pub mod grok;
 pub mod json;
+pub mod protobuf;
 
 type Tags = String;
 type Metadata = String;
@@ -0,0 +1,716 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ *
+ */
+
+use std::{collections::HashMap, fmt::Display, sync::Arc};
+
+use anyhow::anyhow;
+use arrow_array::{
+    new_null_array, ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray,
+    TimestampMillisecondArray, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use csv::{ByteRecord, ReaderBuilder, StringRecord};
+use serde::Serialize;
+
+use super::{EventFormat, Metadata, Tags};
+
+/// How many rows are staged as a single event
+pub const ROWS_PER_EVENT: usize = 10_000;
+
+/// How many rejected rows are reported back
+pub const MAX_REPORTED_ROW_ERRORS: usize = 100;
+
+// formats tried for timestamps when the request names none
+const TIMESTAMP_FORMATS: [&str; 2] = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"];
+
+/// How a CSV or TSV body is read
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    /// Whether the first row names the columns
+    pub has_header: bool,
+    /// Names of the columns when the body has no header row
+    pub columns: Vec<String>,
+    /// Value read as null, the empty string by default
+    pub null_value: String,
+    /// Column parsed as a timestamp
+    pub timestamp_column: Option<String>,
+    /// strftime format of the timestamp column, RFC 3339 when unset
+    pub timestamp_format: Option<String>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            has_header: true,
+            columns: Vec::new(),
+            null_value: String::new(),
+            timestamp_column: None,
+            timestamp_format: None,
+        }
+    }
+}
+
+/// A row that could not be ingested
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RowError {
+    /// Line of the body the row starts on
+    pub line: u64,
+    pub error: String,
+}
+
+/// Rows of a body that could not be ingested
+#[derive(Debug)]
+pub struct RejectedRows(pub Vec<RowError>);
+
+impl Display for RejectedRows {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} rows could not be ingested", self.0.len())?;
+        for (index, row) in self.0.iter().take(MAX_REPORTED_ROW_ERRORS).enumerate() {
+            let separator = if index == 0 { ':' } else { ',' };
+            write!(f, "{separator} line {}: {}", row.line, row.error)?;
+        }
+        if self.0.len() > MAX_REPORTED_ROW_ERRORS {
+            write!(f, " and {} more", self.0.len() - MAX_REPORTED_ROW_ERRORS)?;
+        }
+        Ok(())
+    }
+}
+
+/// The rows of a body that can be ingested into the stream along with the
+/// schema they are ingested with, and the rows that can't be
+#[derive(Debug)]
+pub struct Parsed {
+    pub fields: Vec<Arc<Field>>,
+    pub rows: Rows,
+    pub rejected: Vec<RowError>,
+}
+
+impl Parsed {
+    /// The accepted rows staged as events of up to `ROWS_PER_EVENT` rows
+    pub fn into_events(self, tags: Tags, metadata: Metadata) -> Vec<Event> {
+        let Rows {
+            columns,
+            records,
+            options,
+        } = self.rows;
+        let mut events = Vec::with_capacity(records.len().div_ceil(ROWS_PER_EVENT));
+        let mut records = records.into_iter().peekable();
+        while records.peek().is_some() {
+            events.push(Event {
+                data: Rows {
+                    columns: columns.clone(),
+                    records: records.by_ref().take(ROWS_PER_EVENT).collect(),
+                    options: options.clone(),
+                },
+                fields: self.fields.clone(),
+                tags: tags.clone(),
+                metadata: metadata.clone(),
+            });
+        }
+        events
+    }
+}
+
+/// Records of a CSV body and how to read their values
+#[derive(Debug)]
+pub struct Rows {
+    columns: Arc<Vec<String>>,
+    records: Vec<StringRecord>,
+    options: Arc<CsvOptions>,
+}
+
+impl Rows {
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    // size of the values of the rows, without quotes and delimiters
+    pub fn size(&self) -> u64 {
+        self.records
+            .iter()
+            .map(|record| record.as_slice().len() as u64)
+            .sum()
+    }
+}
+
+/// Reads a CSV body against the schema of the stream.
+///
+/// Columns the stream has keep their type, new columns are inferred from their
+/// values. Rows with the wrong number of fields or with values that don't coerce
+/// to the type of their column are rejected, errors about the body as a whole
+/// such as a missing header fail the read.
+pub fn parse(
+    body: &[u8],
+    options: CsvOptions,
+    storage_schema: &HashMap<String, Arc<Field>>,
+) -> Result<Parsed, anyhow::Error> {
+    let mut reader = ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(body);
+    let mut byte_records = reader.byte_records();
+
+    let columns = if options.has_header {
+        let header = byte_records
+            .next()
+            .ok_or_else(|| anyhow!("CSV body has no header row"))??;
+        StringRecord::from_byte_record(header)
+            .map_err(|_| anyhow!("CSV header row is not valid UTF-8"))?
+            .iter()
+            .map(str::to_owned)
+            .collect()
+    } else {
+        options.columns.clone()
+    };
+    validate_columns(&columns, &options)?;
+
+    let mut records = Vec::new();
+    let mut rejected = Vec::new();
+    for record in byte_records {
+        let record: ByteRecord = record?;
+        let line = record.position().map_or(0, |position| position.line());
+        if record.len() != columns.len() {
+            rejected.push(RowError {
+                line,
+                error: format!("expected {} fields, found {}", columns.len(), record.len()),
+            });
+            continue;
+        }
+        match StringRecord::from_byte_record(record) {
+            Ok(record) => records.push(record),
+            Err(_) => rejected.push(RowError {
+                line,
+                error: "row is not valid UTF-8".to_string(),
+            }),
+        }
+    }
+
+    let fields = columns
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let field = match storage_schema.get(name) {
+                Some(field) => field.clone(),
+                None => Arc::new(Field::new(
+                    name,
+                    infer_type(&records, index, name, &options),
+                    true,
+                )),
+            };
+            if !is_supported(field.data_type()) {
+                return Err(anyhow!(
+                    "Column {name} is of type {} which can't be ingested from CSV",
+                    field.data_type()
+                ));
+            }
+            Ok(field)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // rows are checked against the schema before any is ingested, so that the
+    // request can be rejected as a whole
+    let mut accepted = Vec::with_capacity(records.len());
+    for record in records {
+        let invalid = fields
+            .iter()
+            .zip(record.iter())
+            .find(|(field, value)| !is_null(value, &options) && !coerces(field, value, &options));
+        match invalid {
+            Some((field, value)) => rejected.push(RowError {
+                line: record.position().map_or(0, |position| position.line()),
+                error: format!(
+                    "value {value:?} of column {} is not {}",
+                    field.name(),
+                    field.data_type()
+                ),
+            }),
+            None => accepted.push(record),
+        }
+    }
+    rejected.sort_by_key(|error| error.line);
+
+    Ok(Parsed {
+        fields,
+        rows: Rows {
+            columns: Arc::new(columns),
+            records: accepted,
+            options: Arc::new(options),
+        },
+        rejected,
+    })
+}
+
+fn validate_columns(columns: &[String], options: &CsvOptions) -> Result<(), anyhow::Error> {
+    if columns.is_empty() {
+        return Err(anyhow!(
+            "CSV body has no columns, send a header row or list the columns"
+        ));
+    }
+    for (index, column) in columns.iter().enumerate() {
+        if column.is_empty() {
+            return Err(anyhow!("CSV column {} has no name", index + 1));
+        }
+        if columns[..index].contains(column) {
+            return Err(anyhow!("CSV column {column} appears more than once"));
+        }
+    }
+    if let Some(column) = &options.timestamp_column {
+        if !columns.contains(column) {
+            return Err(anyhow!("Timestamp column {column} is not a CSV column"));
+        }
+    }
+    Ok(())
+}
+
+// the narrowest of boolean, integer, float and string that all values of the
+// column coerce to, the timestamp column is always a timestamp
+fn infer_type(
+    records: &[StringRecord],
+    index: usize,
+    name: &str,
+    options: &CsvOptions,
+) -> DataType {
+    if options.timestamp_column.as_deref() == Some(name) {
+        return DataType::Timestamp(TimeUnit::Millisecond, None);
+    }
+
+    let values: Vec<&str> = records
+        .iter()
+        .map(|record| &record[index])
+        .filter(|value| !is_null(value, options))
+        .collect();
+    if values.is_empty() {
+        DataType::Utf8
+    } else if values.iter().all(|value| parse_bool(value).is_some()) {
+        DataType::Boolean
+    } else if values.iter().all(|value| value.parse::<i64>().is_ok()) {
+        DataType::Int64
+    } else if values.iter().all(|value| value.parse::<f64>().is_ok()) {
+        DataType::Float64
+    } else {
+        DataType::Utf8
+    }
+}
+
+fn is_supported(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Utf8
+            | DataType::Boolean
+            | DataType::Int64
+            | DataType::UInt64
+            | DataType::Float64
+            | DataType::Timestamp(TimeUnit::Millisecond, None)
+    )
+}
+
+fn is_null(value: &str, options: &CsvOptions) -> bool {
+    value == options.null_value
+}
+
+fn coerces(field: &Field, value: &str, options: &CsvOptions) -> bool {
+    match field.data_type() {
+        DataType::Utf8 => true,
+        DataType::Boolean => parse_bool(value).is_some(),
+        DataType::Int64 => value.parse::<i64>().is_ok(),
+        DataType::UInt64 => value.parse::<u64>().is_ok(),
+        DataType::Float64 => value.parse::<f64>().is_ok(),
+        DataType::Timestamp(TimeUnit::Millisecond, None) => {
+            parse_timestamp(value, timestamp_format(field, options)).is_some()
+        }
+        _ => false,
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    if value.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if value.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+// the format given for the timestamp column, other timestamp columns use the defaults
+fn timestamp_format<'a>(field: &Field, options: &'a CsvOptions) -> Option<&'a str> {
+    options
+        .timestamp_column
+        .as_deref()
+        .filter(|column| column == field.name())
+        .and(options.timestamp_format.as_deref())
+}
+
+// milliseconds since the epoch, timestamps without an offset are taken to be UTC
+fn parse_timestamp(value: &str, format: Option<&str>) -> Option<i64> {
+    let timestamp = match format {
+        Some(format) => DateTime::parse_from_str(value, format)
+            .map(|timestamp| timestamp.with_timezone(&Utc))
+            .or_else(|_| NaiveDateTime::parse_from_str(value, format).map(|ts| ts.and_utc()))
+            .ok()?,
+        None => value.parse::<DateTime<Utc>>().ok().or_else(|| {
+            TIMESTAMP_FORMATS.iter().find_map(|format| {
+                NaiveDateTime::parse_from_str(value, format)
+                    .ok()
+                    .map(|timestamp| timestamp.and_utc())
+            })
+        })?,
+    };
+    Some(timestamp.timestamp_millis())
+}
+
+pub struct Event {
+    pub data: Rows,
+    /// Schema of the rows, one field per column
+    pub fields: Vec<Arc<Field>>,
+    pub tags: Tags,
+    pub metadata: Metadata,
+}
+
+impl EventFormat for Event {
+    type Data = Rows;
+
+    // the schema is decided when the body is parsed, the event is the first for
+    // the schema when it adds columns to the stream
+    fn to_data(
+        self,
+        schema: HashMap<String, Arc<Field>>,
+        _static_schema_flag: Option<String>,
+        _time_partition: Option<String>,
+    ) -> Result<(Self::Data, Vec<Arc<Field>>, bool, Tags, Metadata), anyhow::Error> {
+        let is_first = self
+            .fields
+            .iter()
+            .any(|field| !schema.contains_key(field.name()));
+        Ok((self.data, self.fields, is_first, self.tags, self.metadata))
+    }
+
+    // builds the columns of the record batch straight from the CSV values
+    fn decode(data: Self::Data, schema: Arc<Schema>) -> Result<RecordBatch, anyhow::Error> {
+        let columns = schema
+            .fields()
+            .iter()
+            .map(|field| {
+                match data
+                    .columns
+                    .iter()
+                    .position(|column| column == field.name())
+                {
+                    Some(index) => build_array(field, &data, index),
+                    None => Ok(new_null_array(field.data_type(), data.len())),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(RecordBatch::try_new(schema, columns)?)
+    }
+}
+
+fn build_array(field: &Field, rows: &Rows, index: usize) -> Result<ArrayRef, anyhow::Error> {
+    let options = &rows.options;
+    let values = rows.records.iter().map(|record| &record[index]);
+    let array: ArrayRef = match field.data_type() {
+        DataType::Utf8 => Arc::new(StringArray::from_iter(
+            values.map(|value| (!is_null(value, options)).then_some(value)),
+        )),
+        DataType::Boolean => Arc::new(BooleanArray::from(collect(
+            values, field, options, parse_bool,
+        )?)),
+        DataType::Int64 => Arc::new(Int64Array::from(collect(
+            values,
+            field,
+            options,
+            |value| value.parse().ok(),
+        )?)),
+        DataType::UInt64 => Arc::new(UInt64Array::from(collect(
+            values,
+            field,
+            options,
+            |value| value.parse().ok(),
+        )?)),
+        DataType::Float64 => Arc::new(Float64Array::from(collect(
+            values,
+            field,
+            options,
+            |value| value.parse().ok(),
+        )?)),
+        DataType::Timestamp(TimeUnit::Millisecond, None) => {
+            let format = timestamp_format(field, options);
+            Arc::new(TimestampMillisecondArray::from(collect(
+                values,
+                field,
+                options,
+                |value| parse_timestamp(value, format),
+            )?))
+        }
+        data_type => {
+            return Err(anyhow!(
+                "Column {} is of type {data_type} which can't be ingested from CSV",
+                field.name()
+            ))
+        }
+    };
+    Ok(array)
+}
+
+// parses the non null values, rows are checked when the body is parsed so a
+// value that doesn't parse here fails the event
+fn collect<'a, T>(
+    values: impl Iterator<Item = &'a str>,
+    field: &Field,
+    options: &CsvOptions,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<Vec<Option<T>>, anyhow::Error> {
+    values
+        .map(|value| {
+            if is_null(value, options) {
+                return Ok(None);
+            }
+            parse(value).map(Some).ok_or_else(|| {
+                anyhow!(
+                    "value {value:?} of column {} is not {}",
+                    field.name(),
+                    field.data_type()
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use arrow_array::{
+        cast::AsArray,
+        types::{Float64Type, Int64Type, TimestampMillisecondType},
+        Array,
+    };
+    use arrow_schema::{DataType, Field, TimeUnit};
+
+    use super::{parse, CsvOptions, RowError, ROWS_PER_EVENT};
+    use crate::event::{format::EventFormat, DEFAULT_METADATA_KEY, DEFAULT_TAGS_KEY};
+
+    fn schema(fields: &[(&str, DataType)]) -> HashMap<String, Arc<Field>> {
+        fields
+            .iter()
+            .map(|(name, data_type)| {
+                (
+                    name.to_string(),
+                    Arc::new(Field::new(*name, data_type.clone(), true)),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn headerless_tsv_with_provided_columns() {
+        let body = "GET\t200\t0.25\t2024-05-01 10:00:00\nPOST\t-\t1.5\t2024-05-01 10:00:01\n";
+        let options = CsvOptions {
+            delimiter: b'\t',
+            has_header: false,
+            columns: vec![
+                "method".to_string(),
+                "status".to_string(),
+                "latency".to_string(),
+                "time".to_string(),
+            ],
+            null_value: "-".to_string(),
+            timestamp_column: Some("time".to_string()),
+            timestamp_format: Some("%Y-%m-%d %H:%M:%S".to_string()),
+        };
+
+        let parsed = parse(body.as_bytes(), options, &HashMap::new()).unwrap();
+        assert!(parsed.rejected.is_empty());
+
+        let mut events = parsed.into_events("a=b".to_string(), String::new());
+        assert_eq!(events.len(), 1);
+        let (rb, is_first) = events
+            .remove(0)
+            .into_recordbatch(HashMap::new(), None, None)
+            .unwrap();
+        assert!(is_first);
+        assert_eq!(rb.num_rows(), 2);
+
+        let method = rb.column_by_name("method").unwrap().as_string::<i32>();
+        assert_eq!(method.value(0), "GET");
+        assert_eq!(method.value(1), "POST");
+        let status = rb
+            .column_by_name("status")
+            .unwrap()
+            .as_primitive::<Int64Type>();
+        assert_eq!(status.value(0), 200);
+        assert!(status.is_null(1));
+        let latency = rb
+            .column_by_name("latency")
+            .unwrap()
+            .as_primitive::<Float64Type>();
+        assert_eq!(latency.values().as_ref(), &[0.25, 1.5]);
+        let time = rb
+            .column_by_name("time")
+            .unwrap()
+            .as_primitive::<TimestampMillisecondType>();
+        assert_eq!(
+            time.data_type(),
+            &DataType::Timestamp(TimeUnit::Millisecond, None)
+        );
+        assert_eq!(time.value(0), 1714557600000);
+        assert_eq!(time.value(1), 1714557601000);
+        assert_eq!(
+            rb.column_by_name(DEFAULT_TAGS_KEY)
+                .unwrap()
+                .as_string::<i32>()
+                .value(0),
+            "a=b"
+        );
+        assert!(rb.column_by_name(DEFAULT_METADATA_KEY).is_some());
+    }
+
+    #[test]
+    fn quoted_fields_with_delimiters_and_newlines() {
+        let body = concat!(
+            "id,message,level\n",
+            "1,\"disk full, retrying\",warn\n",
+            "2,\"first line\nsecond line, with \"\"quotes\"\"\",error\n",
+            "3,plain,info\n",
+        );
+
+        let parsed = parse(body.as_bytes(), CsvOptions::default(), &HashMap::new()).unwrap();
+        assert!(parsed.rejected.is_empty());
+
+        let event = parsed
+            .into_events(String::new(), String::new())
+            .pop()
+            .unwrap();
+        let (rb, _) = event.into_recordbatch(HashMap::new(), None, None).unwrap();
+        let message = rb.column_by_name("message").unwrap().as_string::<i32>();
+        assert_eq!(message.value(0), "disk full, retrying");
+        assert_eq!(message.value(1), "first line\nsecond line, with \"quotes\"");
+        assert_eq!(message.value(2), "plain");
+        let id = rb.column_by_name("id").unwrap().as_primitive::<Int64Type>();
+        assert_eq!(id.values().as_ref(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn schema_mismatch_rows_are_rejected_with_line_numbers() {
+        let storage_schema = schema(&[("status", DataType::Int64), ("ok", DataType::Boolean)]);
+        let body = concat!(
+            "status,ok,path\n",
+            "200,true,/\n",
+            "oops,true,/a\n",
+            "404,\"multi\nline\",/b\n",
+            "500,false\n",
+            "503,FALSE,/c\n",
+        );
+
+        let parsed = parse(body.as_bytes(), CsvOptions::default(), &storage_schema).unwrap();
+        assert_eq!(
+            parsed.rejected,
+            vec![
+                RowError {
+                    line: 3,
+                    error: "value \"oops\" of column status is not Int64".to_string(),
+                },
+                RowError {
+                    line: 4,
+                    error: "value \"multi\\nline\" of column ok is not Boolean".to_string(),
+                },
+                RowError {
+                    line: 6,
+                    error: "expected 3 fields, found 2".to_string(),
+                },
+            ]
+        );
+
+        let event = parsed
+            .into_events(String::new(), String::new())
+            .pop()
+            .unwrap();
+        let (rb, is_first) = event.into_recordbatch(storage_schema, None, None).unwrap();
+        // path is new to the stream
+        assert!(is_first);
+        assert_eq!(rb.num_rows(), 2);
+        let status = rb
+            .column_by_name("status")
+            .unwrap()
+            .as_primitive::<Int64Type>();
+        assert_eq!(status.values().as_ref(), &[200, 503]);
+        let ok = rb.column_by_name("ok").unwrap().as_boolean();
+        assert!(ok.value(0));
+        assert!(!ok.value(1));
+    }
+
+    #[test]
+    fn rows_are_staged_in_chunks() {
+        let mut body = "n\n".to_string();
+        for n in 0..ROWS_PER_EVENT + 1 {
+            body.push_str(&format!("{n}\n"));
+        }
+
+        let parsed = parse(body.as_bytes(), CsvOptions::default(), &HashMap::new()).unwrap();
+        let events = parsed.into_events(String::new(), String::new());
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| event.data.len())
+                .collect::<Vec<_>>(),
+            vec![ROWS_PER_EVENT, 1]
+        );
+    }
+
+    #[test]
+    fn rejected_rows_message_is_capped() {
+        let rows = (1..=MAX_REPORTED_ROW_ERRORS as u64 + 2)
+            .map(|line| RowError {
+                line,
+                error: "bad".to_string(),
+            })
+            .collect();
+        let message = RejectedRows(rows).to_string();
+        assert!(message.starts_with("102 rows could not be ingested: line 1: bad, line 2: bad"));
+        assert!(message.ends_with("line 100: bad and 2 more"));
+    }
+
+    #[test]
+    fn body_level_errors() {
+        assert!(parse(b"", CsvOptions::default(), &HashMap::new()).is_err());
+        assert!(parse(b"a,a\n1,2\n", CsvOptions::default(), &HashMap::new()).is_err());
+
+        let headerless = CsvOptions {
+            has_header: false,
+            ..CsvOptions::default()
+        };
+        assert!(parse(b"1,2\n", headerless, &HashMap::new()).is_err());
+
+        let missing_timestamp = CsvOptions {
+            timestamp_column: Some("time".to_string()),
+            ..CsvOptions::default()
+        };
+        assert!(parse(b"a\n1\n", missing_timestamp, &HashMap::new()).is_err());
+
+        let unsupported = schema(&[(
+            "tags",
+            DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+        )]);
+        assert!(parse(b"tags\nx\n", CsvOptions::default(), &unsupported).is_err());
+    }
+}
@@ -0,0 +1,495 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Grok patterns, extracting the named captures of unstructured text into
+//! columns. Text bodies are ingested a line per event, JSON events have the
+//! text in one of their fields.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::SecondsFormat;
+use once_cell::sync::Lazy;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+
+use crate::event::timestamp::TimestampFormat;
+
+/// Field the text is read from when the settings name none
+pub const DEFAULT_RAW_FIELD: &str = "message";
+/// Column marking the events whose text did not match the pattern
+pub const UNPARSED_KEY: &str = "p_unparsed";
+
+// patterns referring to each other deeper than this are taken to be recursive
+const MAX_DEPTH: usize = 32;
+// compiled patterns can get large once every reference is expanded
+const SIZE_LIMIT: usize = 64 * (1 << 20);
+
+/// The standard pattern library, one `NAME definition` per line. Adapted from
+/// the logstash patterns without the lookarounds the regex crate lacks.
+pub const STANDARD_PATTERNS: &str = r#"
+USERNAME [a-zA-Z0-9._-]+
+USER %{USERNAME}
+EMAILLOCALPART [a-zA-Z0-9!#$%&'*+/=?^_`{|}~-]+(?:\.[a-zA-Z0-9!#$%&'*+/=?^_`{|}~-]+)*
+EMAILADDRESS %{EMAILLOCALPART}@%{HOSTNAME}
+INT (?:[+-]?(?:[0-9]+))
+BASE10NUM (?:[+-]?(?:[0-9]+(?:\.[0-9]+)?|\.[0-9]+))
+NUMBER (?:%{BASE10NUM})
+BASE16NUM (?:[+-]?(?:0x)?(?:[0-9A-Fa-f]+))
+POSINT \b(?:[1-9][0-9]*)\b
+NONNEGINT \b(?:[0-9]+)\b
+WORD \b\w+\b
+NOTSPACE \S+
+SPACE \s*
+DATA .*?
+GREEDYDATA .*
+QUOTEDSTRING (?:"(?:\\.|[^\\"])*"|'(?:\\.|[^\\'])*'|`(?:\\.|[^\\`])*`)
+QS %{QUOTEDSTRING}
+UUID [A-Fa-f0-9]{8}-(?:[A-Fa-f0-9]{4}-){3}[A-Fa-f0-9]{12}
+CISCOMAC (?:(?:[A-Fa-f0-9]{4}\.){2}[A-Fa-f0-9]{4})
+WINDOWSMAC (?:(?:[A-Fa-f0-9]{2}-){5}[A-Fa-f0-9]{2})
+COMMONMAC (?:(?:[A-Fa-f0-9]{2}:){5}[A-Fa-f0-9]{2})
+MAC (?:%{CISCOMAC}|%{WINDOWSMAC}|%{COMMONMAC})
+IPV6 (?:(?:[0-9A-Fa-f]{1,4}:|:){2,7}(?:[0-9A-Fa-f]{1,4}|:)(?:%[0-9A-Za-z]+)?)
+IPV4 \b(?:(?:25[0-5]|2[0-4][0-9]|[01]?[0-9]?[0-9])\.){3}(?:25[0-5]|2[0-4][0-9]|[01]?[0-9]?[0-9])\b
+IP (?:%{IPV6}|%{IPV4})
+HOSTNAME \b(?:[0-9A-Za-z][0-9A-Za-z-]{0,62})(?:\.(?:[0-9A-Za-z][0-9A-Za-z-]{0,62}))*\.?
+IPORHOST (?:%{IP}|%{HOSTNAME})
+HOSTPORT %{IPORHOST}:%{POSINT}
+UNIXPATH (?:/[\w%!$@:.,+~-]*)+
+WINPATH (?:[A-Za-z]+:|\\)(?:\\[^\\?*]*)+
+PATH (?:%{UNIXPATH}|%{WINPATH})
+URIPROTO [A-Za-z][A-Za-z0-9+.-]+
+URIHOST %{IPORHOST}(?::%{POSINT})?
+URIPATH (?:/[A-Za-z0-9$.+!*'(){},~:;=@#%&_-]*)+
+URIPARAM \?[A-Za-z0-9$.+!*'|(){},~@#%&/=:;_?\[\]<>-]*
+URIPATHPARAM %{URIPATH}(?:%{URIPARAM})?
+URI %{URIPROTO}://(?:%{USER}(?::[^@]*)?@)?(?:%{URIHOST})?(?:%{URIPATHPARAM})?
+MONTH \b(?:[Jj]an(?:uary)?|[Ff]eb(?:ruary)?|[Mm]ar(?:ch)?|[Aa]pr(?:il)?|[Mm]ay|[Jj]un(?:e)?|[Jj]ul(?:y)?|[Aa]ug(?:ust)?|[Ss]ep(?:tember)?|[Oo]ct(?:ober)?|[Nn]ov(?:ember)?|[Dd]ec(?:ember)?)\b
+MONTHNUM (?:0?[1-9]|1[0-2])
+MONTHNUM2 (?:0[1-9]|1[0-2])
+MONTHDAY (?:(?:0[1-9])|(?:[12][0-9])|(?:3[01])|[1-9])
+DAY (?:Mon(?:day)?|Tue(?:sday)?|Wed(?:nesday)?|Thu(?:rsday)?|Fri(?:day)?|Sat(?:urday)?|Sun(?:day)?)
+YEAR (?:\d\d){1,2}
+HOUR (?:2[0123]|[01]?[0-9])
+MINUTE (?:[0-5][0-9])
+SECOND (?:(?:[0-5]?[0-9]|60)(?:[:.,][0-9]+)?)
+TIME %{HOUR}:%{MINUTE}(?::%{SECOND})?
+DATE_US %{MONTHNUM}[/-]%{MONTHDAY}[/-]%{YEAR}
+DATE_EU %{MONTHDAY}[./-]%{MONTHNUM}[./-]%{YEAR}
+DATE %{DATE_US}|%{DATE_EU}
+DATESTAMP %{DATE}[- ]%{TIME}
+TZ (?:[APMCE][SD]T|UTC)
+ISO8601_TIMEZONE (?:Z|[+-]%{HOUR}(?::?%{MINUTE}))
+TIMESTAMP_ISO8601 %{YEAR}-%{MONTHNUM}-%{MONTHDAY}[T ]%{HOUR}:?%{MINUTE}(?::?%{SECOND})?%{ISO8601_TIMEZONE}?
+HTTPDATE %{MONTHDAY}/%{MONTH}/%{YEAR}:%{TIME} %{INT}
+SYSLOGTIMESTAMP %{MONTH} +%{MONTHDAY} %{TIME}
+PROG [\x21-\x5a\x5c\x5e-\x7e]+
+SYSLOGPROG %{PROG:program}(?:\[%{POSINT:pid}\])?
+SYSLOGHOST %{IPORHOST}
+SYSLOGFACILITY <%{NONNEGINT:facility}.%{NONNEGINT:priority}>
+SYSLOGBASE %{SYSLOGTIMESTAMP:timestamp} (?:%{SYSLOGFACILITY} )?%{SYSLOGHOST:logsource} %{SYSLOGPROG}:
+LOGLEVEL (?:[Aa]lert|ALERT|[Tt]race|TRACE|[Dd]ebug|DEBUG|[Nn]otice|NOTICE|[Ii]nfo|INFO|[Ww]arn?(?:ing)?|WARN?(?:ING)?|[Ee]rr?(?:or)?|ERR?(?:OR)?|[Cc]rit?(?:ical)?|CRIT?(?:ICAL)?|[Ff]atal|FATAL|[Ss]evere|SEVERE|EMERG(?:ENCY)?|[Ee]merg(?:ency)?)
+HTTPDUSER %{EMAILADDRESS}|%{USER}
+COMMONAPACHELOG %{IPORHOST:clientip} %{HTTPDUSER:ident} %{USER:auth} \[%{HTTPDATE:timestamp}\] "(?:%{WORD:verb} %{NOTSPACE:request}(?: HTTP/%{NUMBER:httpversion})?|%{DATA:rawrequest})" %{NUMBER:response} (?:%{NUMBER:bytes}|-)
+COMBINEDAPACHELOG %{COMMONAPACHELOG} %{QS:referrer} %{QS:agent}
+NGINXCOMBINED %{COMBINEDAPACHELOG}
+JAVACLASS (?:[a-zA-Z$_][a-zA-Z$_0-9]*\.)*[a-zA-Z$_][a-zA-Z$_0-9]*
+JAVAFILE (?:[A-Za-z0-9_. -]+)
+JAVAMETHOD (?:<init>|<clinit>|[a-zA-Z$_][a-zA-Z$_0-9]*)
+JAVASTACKTRACEPART %{SPACE}at %{JAVACLASS:class}\.%{JAVAMETHOD:method}\(%{JAVAFILE:file}(?::%{NUMBER:line})?\)
+JAVATHREAD (?:[A-Z]{2}-Processor[\d]+)
+JAVALOGMESSAGE .*
+"#;
+
+static LIBRARY: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
+    STANDARD_PATTERNS
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .collect()
+});
+
+// %{NAME}, %{NAME:capture} or %{NAME:capture:type}
+static REFERENCE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"%\{(\w+)(?::([\w.@\[\]-]+))?(?::(\w+))?\}").expect("reference pattern is valid")
+});
+
+fn default_field() -> String {
+    DEFAULT_RAW_FIELD.to_string()
+}
+
+/// Type a capture is stored as
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CaptureType {
+    #[default]
+    String,
+    Int,
+    Float,
+    /// Stored as RFC 3339 text in UTC, parsed in the given format
+    Timestamp {
+        format: TimestampFormat,
+    },
+}
+
+/// Grok pattern set for a stream, as persisted in stream.json
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GrokSettings {
+    /// Pattern matched against the text, e.g. `%{COMBINEDAPACHELOG}`. The
+    /// captures can be typed inline as `%{NUMBER:bytes:int}` or `:float`
+    pub pattern: String,
+    /// Field of JSON events holding the text, lines of text bodies are kept
+    /// in it when they don't match
+    #[serde(default = "default_field")]
+    pub field: String,
+    /// Patterns added to the standard library, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub definitions: BTreeMap<String, String>,
+    /// Types of the captures by name, over the ones given inline
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub types: BTreeMap<String, CaptureType>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum GrokError {
+    #[error("The field holding the text can't be empty")]
+    EmptyField,
+    #[error("Unknown grok pattern {0}")]
+    UnknownPattern(String),
+    #[error("Grok pattern {0} refers to itself")]
+    Recursive(String),
+    #[error("Unknown type {1} for capture {0}, expected int or float")]
+    UnknownType(String, String),
+    #[error("Grok pattern has no named captures")]
+    NoCaptures,
+    #[error("Invalid grok pattern: {0}")]
+    Regex(#[from] regex::Error),
+}
+
+#[derive(Debug, Clone)]
+struct Capture {
+    name: String,
+    kind: CaptureType,
+    group: usize,
+}
+
+/// Parses the text of a stream's events with its grok pattern
+#[derive(Debug, Clone)]
+pub struct GrokParser {
+    settings: GrokSettings,
+    regex: Regex,
+    captures: Vec<Capture>,
+}
+
+impl GrokParser {
+    pub fn new(settings: GrokSettings) -> Result<Self, GrokError> {
+        if settings.field.trim().is_empty() {
+            return Err(GrokError::EmptyField);
+        }
+        let mut named = Vec::new();
+        let expanded = expand(&settings.pattern, &settings.definitions, 0, &mut named)?;
+        if named.is_empty() {
+            return Err(GrokError::NoCaptures);
+        }
+        let regex = RegexBuilder::new(&expanded)
+            .size_limit(SIZE_LIMIT)
+            .build()?;
+
+        let groups: HashMap<&str, usize> = regex
+            .capture_names()
+            .enumerate()
+            .filter_map(|(group, alias)| Some((alias?, group)))
+            .collect();
+        let captures = named
+            .into_iter()
+            .enumerate()
+            .map(|(index, (name, hint))| {
+                let kind = match (settings.types.get(&name), hint.as_deref()) {
+                    (Some(kind), _) => kind.clone(),
+                    (None, None) => CaptureType::String,
+                    (None, Some("int")) => CaptureType::Int,
+                    (None, Some("float")) => CaptureType::Float,
+                    (None, Some(other)) => {
+                        return Err(GrokError::UnknownType(name, other.to_owned()))
+                    }
+                };
+                let group = groups[alias(index).as_str()];
+                Ok(Capture { name, kind, group })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            settings,
+            regex,
+            captures,
+        })
+    }
+
+    pub fn settings(&self) -> &GrokSettings {
+        &self.settings
+    }
+
+    /// Typed captures of `text`, None when it doesn't match. Captures that
+    /// didn't take part in the match or can't be read as their type are left out
+    pub fn parse(&self, text: &str) -> Option<Map<String, Value>> {
+        let found = self.regex.captures(text)?;
+        let mut fields = Map::new();
+        for capture in &self.captures {
+            // a name captured in several places takes the first match
+            if fields.contains_key(&capture.name) {
+                continue;
+            }
+            let Some(value) = found
+                .get(capture.group)
+                .and_then(|matched| typed(&capture.kind, matched.as_str()))
+            else {
+                continue;
+            };
+            fields.insert(capture.name.clone(), value);
+        }
+        Some(fields)
+    }
+
+    /// Replaces the text field of an event or array of events with its
+    /// captures. Events whose text doesn't match keep it and are marked with
+    /// `p_unparsed`, events without the field are left as they are.
+    pub fn apply(&self, body: &mut Value) {
+        match body {
+            Value::Array(events) => events.iter_mut().for_each(|event| self.apply(event)),
+            Value::Object(event) => {
+                let Some(Value::String(text)) = event.get(&self.settings.field) else {
+                    return;
+                };
+                match self.parse(text) {
+                    Some(fields) => {
+                        event.remove(&self.settings.field);
+                        event.extend(fields);
+                    }
+                    None => {
+                        event.insert(UNPARSED_KEY.to_owned(), Value::Bool(true));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Events of a text body, one per non empty line holding the line in `field`
+pub fn text_events(body: &str, field: &str) -> Vec<Value> {
+    body.lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut event = Map::new();
+            event.insert(field.to_owned(), Value::String(line.to_owned()));
+            Value::Object(event)
+        })
+        .collect()
+}
+
+// name of the regex group holding the `index`th capture, capture names can
+// have characters group names can't
+fn alias(index: usize) -> String {
+    format!("c{index}")
+}
+
+// the regex of `pattern` with its references expanded, pushing the named
+// captures with their inline type in order of their groups
+fn expand(
+    pattern: &str,
+    definitions: &BTreeMap<String, String>,
+    depth: usize,
+    named: &mut Vec<(String, Option<String>)>,
+) -> Result<String, GrokError> {
+    let mut expanded = String::with_capacity(pattern.len());
+    let mut last = 0;
+    for reference in REFERENCE.captures_iter(pattern) {
+        let whole = reference.get(0).expect("group 0 is the whole match");
+        expanded.push_str(&pattern[last..whole.start()]);
+        last = whole.end();
+
+        let name = &reference[1];
+        if depth >= MAX_DEPTH {
+            return Err(GrokError::Recursive(name.to_owned()));
+        }
+        let definition = definitions
+            .get(name)
+            .map(String::as_str)
+            .or_else(|| LIBRARY.get(name).copied())
+            .ok_or_else(|| GrokError::UnknownPattern(name.to_owned()))?;
+        match reference.get(2) {
+            Some(capture) => {
+                let index = named.len();
+                named.push((
+                    capture.as_str().to_owned(),
+                    reference.get(3).map(|hint| hint.as_str().to_owned()),
+                ));
+                let inner = expand(definition, definitions, depth + 1, named)?;
+                expanded.push_str(&format!("(?P<{}>{inner})", alias(index)));
+            }
+            None => {
+                let inner = expand(definition, definitions, depth + 1, named)?;
+                expanded.push_str(&format!("(?:{inner})"));
+            }
+        }
+    }
+    expanded.push_str(&pattern[last..]);
+    Ok(expanded)
+}
+
+fn typed(kind: &CaptureType, text: &str) -> Option<Value> {
+    match kind {
+        CaptureType::String => Some(Value::String(text.to_owned())),
+        CaptureType::Int => text.trim().parse::<i64>().ok().map(Value::from),
+        CaptureType::Float => text
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .and_then(Number::from_f64)
+            .map(Value::Number),
+        CaptureType::Timestamp { format } => format
+            .parse(&Value::String(text.to_owned()))
+            .map(|time| Value::String(time.and_utc().to_rfc3339_opts(SecondsFormat::Millis, true))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::Int64Type;
+    use arrow_schema::DataType;
+    use serde_json::{json, Value};
+
+    use super::{text_events, CaptureType, GrokError, GrokParser, GrokSettings, UNPARSED_KEY};
+    use crate::event::format::{json, EventFormat};
+    use crate::event::timestamp::TimestampFormat;
+
+    const NGINX: &str = r#"93.180.71.3 - - [17/May/2015:08:05:32 +0000] "GET /downloads/product_1 HTTP/1.1" 304 0 "-" "Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.21)""#;
+
+    fn settings(pattern: &str) -> GrokSettings {
+        serde_json::from_value(json!({ "pattern": pattern })).unwrap()
+    }
+
+    #[test]
+    fn nginx_lines_are_parsed_and_the_rest_kept_raw() {
+        let mut settings = settings("%{COMBINEDAPACHELOG}");
+        settings.types = BTreeMap::from([
+            ("response".to_string(), CaptureType::Int),
+            ("bytes".to_string(), CaptureType::Int),
+            (
+                "timestamp".to_string(),
+                CaptureType::Timestamp {
+                    format: TimestampFormat::Pattern("%d/%b/%Y:%H:%M:%S %z".to_string()),
+                },
+            ),
+        ]);
+        let grok = GrokParser::new(settings).unwrap();
+
+        let body = format!("{NGINX}\r\nnot an access log line\n\n");
+        let mut events = Value::Array(text_events(&body, "message"));
+        grok.apply(&mut events);
+
+        let event = json::Event {
+            data: events,
+            tags: String::default(),
+            metadata: String::default(),
+        };
+        let (rb, _) = event.into_recordbatch(HashMap::new(), None, None).unwrap();
+        assert_eq!(rb.num_rows(), 2);
+        let schema = rb.schema();
+        for column in ["response", "bytes"] {
+            assert_eq!(
+                schema.field_with_name(column).unwrap().data_type(),
+                &DataType::Int64
+            );
+        }
+
+        let text = |column: &str| rb.column_by_name(column).unwrap().as_string::<i32>();
+        assert_eq!(text("clientip").value(0), "93.180.71.3");
+        assert_eq!(text("verb").value(0), "GET");
+        assert_eq!(text("request").value(0), "/downloads/product_1");
+        assert_eq!(text("httpversion").value(0), "1.1");
+        assert_eq!(text("timestamp").value(0), "2015-05-17T08:05:32.000Z");
+        assert_eq!(
+            text("agent").value(0),
+            "\"Debian APT-HTTP/1.3 (0.8.16~exp12ubuntu10.21)\""
+        );
+        let response = rb
+            .column_by_name("response")
+            .unwrap()
+            .as_primitive::<Int64Type>();
+        assert_eq!(response.value(0), 304);
+        assert!(response.is_null(1));
+
+        // the line that doesn't match is kept as it was, flagged
+        let message = text("message");
+        assert!(message.is_null(0));
+        assert_eq!(message.value(1), "not an access log line");
+        let unparsed = rb.column_by_name(UNPARSED_KEY).unwrap().as_boolean();
+        assert!(unparsed.is_null(0));
+        assert!(unparsed.value(1));
+    }
+
+    #[test]
+    fn raw_field_of_json_events_is_parsed() {
+        let mut settings = settings("took %{DURATION:took:int}ms user=%{WORD:user}");
+        settings.field = "log".to_string();
+        settings.definitions = BTreeMap::from([("DURATION".to_string(), r"\d+".to_string())]);
+        let grok = GrokParser::new(settings).unwrap();
+
+        let mut event = json!({ "log": "took 35ms user=alice", "host": "a" });
+        grok.apply(&mut event);
+        assert_eq!(event, json!({ "took": 35, "user": "alice", "host": "a" }));
+
+        // events without the field are not text to parse
+        let mut event = json!({ "host": "a" });
+        grok.apply(&mut event);
+        assert_eq!(event, json!({ "host": "a" }));
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let invalid = |settings| GrokParser::new(settings).unwrap_err();
+        assert!(matches!(
+            invalid(settings("%{NOPE:x}")),
+            GrokError::UnknownPattern(_)
+        ));
+        assert!(matches!(
+            invalid(settings("%{NUMBER:x:decimal}")),
+            GrokError::UnknownType(..)
+        ));
+        assert!(matches!(
+            invalid(settings("%{NUMBER}")),
+            GrokError::NoCaptures
+        ));
+        assert!(matches!(
+            invalid(settings("%{WORD:x} (")),
+            GrokError::Regex(_)
+        ));
+
+        let mut looping = settings("%{A:a}");
+        looping.definitions = BTreeMap::from([
+            ("A".to_string(), "%{B}".to_string()),
+            ("B".to_string(), "x%{A}".to_string()),
+        ]);
+        assert!(matches!(invalid(looping), GrokError::Recursive(_)));
+    }
+}
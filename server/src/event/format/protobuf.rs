@@ -0,0 +1,777 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Protobuf payloads, decoded with the descriptor registered for their stream
+//! into the JSON values the ingest path flattens.
+
+use std::collections::HashSet;
+
+use arrow_schema::{DataType, Field, Schema};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{DateTime, SecondsFormat};
+use prost::{encoding::decode_length_delimiter, Message};
+use prost_reflect::{
+    DescriptorPool, DynamicMessage, FieldDescriptor, Kind, MapKey, MessageDescriptor,
+    Value as ProtoValue,
+};
+use prost_types::FileDescriptorSet;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+
+/// Column unknown fields are kept under when the registration names none
+pub const DEFAULT_UNKNOWN_FIELDS_COLUMN: &str = "unknown_fields";
+
+// nested messages are mapped to columns up to this depth
+const MAX_DEPTH: usize = 16;
+
+fn default_unknown_fields_column() -> String {
+    DEFAULT_UNKNOWN_FIELDS_COLUMN.to_string()
+}
+
+/// Protobuf descriptor registered for a stream, as persisted in stream.json
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtobufSchema {
+    /// Base64 of a `FileDescriptorSet` holding the message and its imports,
+    /// the well known types can be left out
+    pub descriptor_set: String,
+    /// Full name of the message payloads hold
+    pub message: String,
+    /// Column the fields a message holds but the descriptor doesn't know are
+    /// kept under, base64 encoded as they were on the wire
+    #[serde(default = "default_unknown_fields_column")]
+    pub unknown_fields_column: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProtobufError {
+    #[error("Invalid descriptor set: {0}")]
+    InvalidDescriptorSet(String),
+    #[error("Message {0} is not in the descriptor set")]
+    MessageNotFound(String),
+    #[error("The column for unknown fields needs a name")]
+    UnnamedColumn,
+    #[error("Malformed message {index} at byte {offset}: {error}")]
+    Malformed {
+        index: usize,
+        offset: usize,
+        error: String,
+    },
+    #[error("Body holds no messages")]
+    Empty,
+    #[error("Descriptor is not compatible with the stream: {}", .0.join("; "))]
+    Incompatible(Vec<String>),
+}
+
+/// Decodes the payloads of a stream with its registered descriptor
+#[derive(Debug, Clone)]
+pub struct ProtobufDecoder {
+    schema: ProtobufSchema,
+    message: MessageDescriptor,
+}
+
+impl ProtobufDecoder {
+    pub fn new(schema: ProtobufSchema) -> Result<Self, ProtobufError> {
+        let invalid =
+            |err: &dyn std::fmt::Display| ProtobufError::InvalidDescriptorSet(err.to_string());
+        if schema.unknown_fields_column.is_empty() {
+            return Err(ProtobufError::UnnamedColumn);
+        }
+        let bytes = STANDARD
+            .decode(&schema.descriptor_set)
+            .map_err(|err| invalid(&err))?;
+        let set = FileDescriptorSet::decode(bytes.as_slice()).map_err(|err| invalid(&err))?;
+
+        // the pool comes with the well known types, sets built with their
+        // imports carry them again
+        let mut pool = DescriptorPool::global();
+        let files: Vec<_> = set
+            .file
+            .into_iter()
+            .filter(|file| pool.get_file_by_name(file.name()).is_none())
+            .collect();
+        pool.add_file_descriptor_protos(files)
+            .map_err(|err| invalid(&err))?;
+        let message = pool
+            .get_message_by_name(&schema.message)
+            .ok_or_else(|| ProtobufError::MessageNotFound(schema.message.clone()))?;
+
+        Ok(Self { schema, message })
+    }
+
+    pub fn schema(&self) -> &ProtobufSchema {
+        &self.schema
+    }
+
+    /// Decodes a body holding a single message or, when `delimited`, a stream
+    /// of length delimited messages into one JSON object per message.
+    ///
+    /// Fields are named as in the descriptor and nested messages become
+    /// nested objects. Well known types are mapped to what they stand for,
+    /// a `Timestamp` to an RFC 3339 string, a `Duration` to seconds, a
+    /// `Struct` to an object and wrappers to their value. Enums are named by
+    /// their value and bytes are base64 encoded. Fields without presence
+    /// carry their default when unset.
+    pub fn decode(&self, body: &[u8], delimited: bool) -> Result<Value, ProtobufError> {
+        if !delimited {
+            let message = DynamicMessage::decode(self.message.clone(), body).map_err(|err| {
+                ProtobufError::Malformed {
+                    index: 0,
+                    offset: 0,
+                    error: err.to_string(),
+                }
+            })?;
+            return Ok(self.message_json(&message));
+        }
+
+        let mut records = Vec::new();
+        let mut buf = body;
+        while !buf.is_empty() {
+            let malformed = |error: String| ProtobufError::Malformed {
+                index: records.len(),
+                offset: body.len() - buf.len(),
+                error,
+            };
+            let mut rest = buf;
+            let len =
+                decode_length_delimiter(&mut rest).map_err(|err| malformed(err.to_string()))?;
+            if len > rest.len() {
+                return Err(malformed(format!(
+                    "length {len} runs past the end of the body"
+                )));
+            }
+            let (encoded, rest) = rest.split_at(len);
+            let message = DynamicMessage::decode(self.message.clone(), encoded)
+                .map_err(|err| malformed(err.to_string()))?;
+            records.push(self.message_json(&message));
+            buf = rest;
+        }
+        if records.is_empty() {
+            return Err(ProtobufError::Empty);
+        }
+        Ok(Value::Array(records))
+    }
+
+    /// Checks that payloads decoded with this descriptor can be ingested
+    /// next to what the stream already holds.
+    ///
+    /// Columns the descriptor maps to must keep the type the stream has
+    /// for them, and fields of the previous descriptor must keep their name
+    /// and type under their number. Fields can be added and removed.
+    pub fn check_compatible(
+        &self,
+        previous: Option<&ProtobufDecoder>,
+        schema: &Schema,
+    ) -> Result<(), ProtobufError> {
+        let mut problems = Vec::new();
+        let mut columns = Vec::new();
+        message_columns(&self.message, None, &mut vec![], &mut columns);
+        columns.push((self.schema.unknown_fields_column.clone(), DataType::Utf8));
+        for (name, data_type) in columns {
+            let Ok(field) = schema.field_with_name(&name) else {
+                continue;
+            };
+            if !same_type(field.data_type(), &data_type) {
+                problems.push(format!(
+                    "column {name} is {} in the stream but would be {data_type}",
+                    field.data_type()
+                ));
+            }
+        }
+        if let Some(previous) = previous {
+            compare_messages(
+                &previous.message,
+                &self.message,
+                &mut HashSet::new(),
+                &mut problems,
+            );
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ProtobufError::Incompatible(problems))
+        }
+    }
+
+    fn message_json(&self, message: &DynamicMessage) -> Value {
+        let mut object = Map::new();
+        for field in message.descriptor().fields() {
+            let unset = !message.has_field(&field);
+            if unset && (field.supports_presence() || field.is_list() || field.is_map()) {
+                continue;
+            }
+            let value = self.value_json(&message.get_field(&field), &field.kind());
+            if !value.is_null() {
+                object.insert(field.name().to_owned(), value);
+            }
+        }
+
+        let mut unknown = Vec::new();
+        for field in message.unknown_fields() {
+            field.encode(&mut unknown);
+        }
+        if !unknown.is_empty() {
+            object.insert(
+                self.schema.unknown_fields_column.clone(),
+                Value::String(STANDARD.encode(unknown)),
+            );
+        }
+        Value::Object(object)
+    }
+
+    fn value_json(&self, value: &ProtoValue, kind: &Kind) -> Value {
+        match value {
+            ProtoValue::Bool(value) => Value::Bool(*value),
+            ProtoValue::I32(value) => Value::from(*value),
+            ProtoValue::I64(value) => Value::from(*value),
+            ProtoValue::U32(value) => Value::from(*value),
+            ProtoValue::U64(value) => Value::from(*value),
+            ProtoValue::F32(value) => float(*value as f64),
+            ProtoValue::F64(value) => float(*value),
+            ProtoValue::String(value) => Value::String(value.clone()),
+            ProtoValue::Bytes(value) => Value::String(STANDARD.encode(value)),
+            ProtoValue::EnumNumber(number) => {
+                let name = match kind {
+                    Kind::Enum(enumeration) => enumeration
+                        .get_value(*number)
+                        .map(|value| value.name().to_owned()),
+                    _ => None,
+                };
+                Value::String(name.unwrap_or_else(|| number.to_string()))
+            }
+            ProtoValue::Message(message) => self.well_known_json(message),
+            ProtoValue::List(values) => Value::Array(
+                values
+                    .iter()
+                    .map(|value| self.value_json(value, kind))
+                    .collect(),
+            ),
+            ProtoValue::Map(entries) => {
+                let value_kind = match kind {
+                    Kind::Message(entry) => entry.map_entry_value_field().kind(),
+                    _ => kind.clone(),
+                };
+                let object = entries
+                    .iter()
+                    .map(|(key, value)| (map_key(key), self.value_json(value, &value_kind)))
+                    .collect();
+                Value::Object(object)
+            }
+        }
+    }
+
+    fn well_known_json(&self, message: &DynamicMessage) -> Value {
+        let number = |name: &str| {
+            message
+                .get_field_by_name(name)
+                .and_then(|value| value.as_i64().or(value.as_i32().map(i64::from)))
+                .unwrap_or_default()
+        };
+        match message.descriptor().full_name() {
+            "google.protobuf.Timestamp" => {
+                DateTime::from_timestamp(number("seconds"), number("nanos") as u32)
+                    .map_or(Value::Null, |time| {
+                        Value::String(time.to_rfc3339_opts(SecondsFormat::Millis, true))
+                    })
+            }
+            "google.protobuf.Duration" => {
+                float(number("seconds") as f64 + number("nanos") as f64 / 1e9)
+            }
+            "google.protobuf.Struct" => self.field_json(message, "fields"),
+            "google.protobuf.ListValue" => self.field_json(message, "values"),
+            "google.protobuf.Value" => match message.fields().next() {
+                Some((field, value)) if field.name() != "null_value" => {
+                    self.value_json(value, &field.kind())
+                }
+                _ => Value::Null,
+            },
+            "google.protobuf.DoubleValue"
+            | "google.protobuf.FloatValue"
+            | "google.protobuf.Int64Value"
+            | "google.protobuf.UInt64Value"
+            | "google.protobuf.Int32Value"
+            | "google.protobuf.UInt32Value"
+            | "google.protobuf.BoolValue"
+            | "google.protobuf.StringValue"
+            | "google.protobuf.BytesValue" => self.field_json(message, "value"),
+            _ => self.message_json(message),
+        }
+    }
+
+    fn field_json(&self, message: &DynamicMessage, name: &str) -> Value {
+        match message.descriptor().get_field_by_name(name) {
+            Some(field) => self.value_json(&message.get_field(&field), &field.kind()),
+            None => Value::Null,
+        }
+    }
+}
+
+// JSON has no NaN or infinities
+fn float(value: f64) -> Value {
+    Number::from_f64(value).map_or(Value::Null, Value::Number)
+}
+
+fn map_key(key: &MapKey) -> String {
+    match key {
+        MapKey::Bool(key) => key.to_string(),
+        MapKey::I32(key) => key.to_string(),
+        MapKey::I64(key) => key.to_string(),
+        MapKey::U32(key) => key.to_string(),
+        MapKey::U64(key) => key.to_string(),
+        MapKey::String(key) => key.clone(),
+    }
+}
+
+// type the JSON pipeline infers for the values of a field of this kind, None
+// when it depends on the payload
+fn leaf_type(kind: &Kind) -> Option<DataType> {
+    match kind {
+        Kind::Bool => Some(DataType::Boolean),
+        Kind::Int32
+        | Kind::Int64
+        | Kind::Uint32
+        | Kind::Uint64
+        | Kind::Sint32
+        | Kind::Sint64
+        | Kind::Fixed32
+        | Kind::Fixed64
+        | Kind::Sfixed32
+        | Kind::Sfixed64 => Some(DataType::Int64),
+        Kind::Float | Kind::Double => Some(DataType::Float64),
+        Kind::String | Kind::Bytes | Kind::Enum(_) => Some(DataType::Utf8),
+        Kind::Message(message) => match message.full_name() {
+            "google.protobuf.Timestamp" => Some(DataType::Utf8),
+            "google.protobuf.Duration" => Some(DataType::Float64),
+            name if name.starts_with("google.protobuf.") && name.ends_with("Value") => message
+                .get_field_by_name("value")
+                .and_then(|value| leaf_type(&value.kind())),
+            _ => None,
+        },
+    }
+}
+
+// columns the fields of `message` are flattened into along with their types
+fn message_columns(
+    message: &MessageDescriptor,
+    prefix: Option<&str>,
+    path: &mut Vec<String>,
+    columns: &mut Vec<(String, DataType)>,
+) {
+    path.push(message.full_name().to_owned());
+    for field in message.fields() {
+        let name = prefix.map_or_else(
+            || field.name().to_owned(),
+            |prefix| format!("{prefix}_{}", field.name()),
+        );
+        let kind = field.kind();
+        // keys of maps and structs name the columns, arrays of objects are
+        // flattened into arrays by key
+        if field.is_map() {
+            continue;
+        }
+        match (leaf_type(&kind), &kind) {
+            (Some(data_type), _) if field.is_list() => columns.push((
+                name,
+                DataType::List(Field::new("item", data_type, true).into()),
+            )),
+            (Some(data_type), _) => columns.push((name, data_type)),
+            (None, Kind::Message(nested))
+                if !field.is_list()
+                    && !nested.full_name().starts_with("google.protobuf.")
+                    && path.len() < MAX_DEPTH
+                    && !path.iter().any(|name| name == nested.full_name()) =>
+            {
+                message_columns(nested, Some(&name), path, columns)
+            }
+            _ => {}
+        }
+    }
+    path.pop();
+}
+
+// whether values of `descriptor` can go into a column of `stream` type
+fn same_type(stream: &DataType, descriptor: &DataType) -> bool {
+    match (stream, descriptor) {
+        (DataType::List(stream), DataType::List(descriptor)) => {
+            same_type(stream.data_type(), descriptor.data_type())
+        }
+        // a time partition holds timestamps sent as strings
+        (DataType::Timestamp(_, _), DataType::Utf8) => true,
+        (stream, descriptor) => stream == descriptor,
+    }
+}
+
+fn type_name(field: &FieldDescriptor) -> String {
+    let name = match field.kind() {
+        Kind::Message(message) => message.full_name().to_owned(),
+        Kind::Enum(enumeration) => enumeration.full_name().to_owned(),
+        kind => format!("{kind:?}").to_lowercase(),
+    };
+    if field.is_list() || field.is_map() {
+        format!("repeated {name}")
+    } else {
+        name
+    }
+}
+
+// fields of `previous` must keep their name and type under their number
+fn compare_messages(
+    previous: &MessageDescriptor,
+    current: &MessageDescriptor,
+    compared: &mut HashSet<String>,
+    problems: &mut Vec<String>,
+) {
+    if !compared.insert(current.full_name().to_owned()) {
+        return;
+    }
+    for old in previous.fields() {
+        let Some(new) = current.get_field(old.number()) else {
+            continue;
+        };
+        if old.name() != new.name() {
+            problems.push(format!(
+                "field {} of {} was renamed from {} to {}",
+                old.number(),
+                current.full_name(),
+                old.name(),
+                new.name()
+            ));
+        }
+        let (old_type, new_type) = (type_name(&old), type_name(&new));
+        if old_type != new_type {
+            problems.push(format!(
+                "field {} of {} changed from {old_type} to {new_type}",
+                new.name(),
+                current.full_name()
+            ));
+        } else if let (Kind::Message(old), Kind::Message(new)) = (old.kind(), new.kind()) {
+            compare_messages(&old, &new, compared, problems);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use arrow_array::{cast::AsArray, types::Int64Type, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+    use datafusion::{datasource::MemTable, prelude::SessionContext};
+    use prost::Message;
+    use prost_reflect::DynamicMessage;
+    use prost_types::{
+        field_descriptor_proto::{Label, Type},
+        DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+        FileDescriptorProto, FileDescriptorSet,
+    };
+    use serde_json::json;
+
+    use super::{ProtobufDecoder, ProtobufError, ProtobufSchema, DEFAULT_UNKNOWN_FIELDS_COLUMN};
+    use crate::event::format::{json, EventFormat};
+
+    fn field(
+        name: &str,
+        number: i32,
+        r#type: Type,
+        type_name: Option<&str>,
+    ) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_owned()),
+            number: Some(number),
+            label: Some(Label::Optional as i32),
+            r#type: Some(r#type as i32),
+            type_name: type_name.map(str::to_owned),
+            json_name: Some(name.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    // package test, message Request with the fields returned by `extra` added
+    fn descriptor_set(extra: Vec<FieldDescriptorProto>) -> String {
+        let mut tags = field("tags", 8, Type::String, None);
+        tags.label = Some(Label::Repeated as i32);
+        let mut fields = vec![
+            field("path", 1, Type::String, None),
+            field("status", 2, Type::Int64, None),
+            field("latency", 3, Type::Double, None),
+            field("time", 4, Type::Message, Some(".google.protobuf.Timestamp")),
+            field(
+                "elapsed",
+                5,
+                Type::Message,
+                Some(".google.protobuf.Duration"),
+            ),
+            field(
+                "attributes",
+                6,
+                Type::Message,
+                Some(".google.protobuf.Struct"),
+            ),
+            field("peer", 7, Type::Message, Some(".test.Request.Peer")),
+            tags,
+            field("method", 9, Type::Enum, Some(".test.Request.Method")),
+        ];
+        fields.extend(extra);
+        let enum_value = |name: &str, number| EnumValueDescriptorProto {
+            name: Some(name.to_owned()),
+            number: Some(number),
+            ..Default::default()
+        };
+        let file = FileDescriptorProto {
+            name: Some("test/request.proto".to_owned()),
+            package: Some("test".to_owned()),
+            dependency: vec![
+                "google/protobuf/timestamp.proto".to_owned(),
+                "google/protobuf/duration.proto".to_owned(),
+                "google/protobuf/struct.proto".to_owned(),
+            ],
+            message_type: vec![DescriptorProto {
+                name: Some("Request".to_owned()),
+                field: fields,
+                nested_type: vec![DescriptorProto {
+                    name: Some("Peer".to_owned()),
+                    field: vec![
+                        field("ip", 1, Type::String, None),
+                        field("port", 2, Type::Uint32, None),
+                    ],
+                    ..Default::default()
+                }],
+                enum_type: vec![EnumDescriptorProto {
+                    name: Some("Method".to_owned()),
+                    value: vec![enum_value("GET", 0), enum_value("POST", 1)],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            syntax: Some("proto3".to_owned()),
+            ..Default::default()
+        };
+        STANDARD.encode(FileDescriptorSet { file: vec![file] }.encode_to_vec())
+    }
+
+    fn decoder(extra: Vec<FieldDescriptorProto>) -> ProtobufDecoder {
+        ProtobufDecoder::new(ProtobufSchema {
+            descriptor_set: descriptor_set(extra),
+            message: "test.Request".to_owned(),
+            unknown_fields_column: DEFAULT_UNKNOWN_FIELDS_COLUMN.to_owned(),
+        })
+        .unwrap()
+    }
+
+    // length delimited messages given in their canonical JSON form
+    fn encode(decoder: &ProtobufDecoder, messages: &[serde_json::Value]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for message in messages {
+            let message =
+                DynamicMessage::deserialize(decoder.message.clone(), message.clone()).unwrap();
+            message.encode_length_delimited(&mut body).unwrap();
+        }
+        body
+    }
+
+    #[test]
+    fn messages_map_to_records() {
+        let decoder = decoder(vec![]);
+        let body = encode(
+            &decoder,
+            &[
+                json!({
+                    "path": "/api",
+                    "status": 200,
+                    "latency": 0.25,
+                    "time": "2024-05-01T10:00:00.250Z",
+                    "elapsed": "1.500s",
+                    "attributes": {"user": "alice", "retries": 2},
+                    "peer": {"ip": "10.0.0.1", "port": 443},
+                    "tags": ["a", "b"],
+                    "method": "POST",
+                }),
+                json!({"path": "/health"}),
+            ],
+        );
+        let records = decoder.decode(&body, true).unwrap();
+        assert_eq!(
+            records,
+            json!([
+                {
+                    "path": "/api",
+                    "status": 200,
+                    "latency": 0.25,
+                    "time": "2024-05-01T10:00:00.250Z",
+                    "elapsed": 1.5,
+                    "attributes": {"user": "alice", "retries": 2.0},
+                    "peer": {"ip": "10.0.0.1", "port": 443},
+                    "tags": ["a", "b"],
+                    "method": "POST",
+                },
+                {"path": "/health", "status": 0, "latency": 0.0, "method": "GET"},
+            ])
+        );
+        assert!(records[0]["status"].is_i64());
+        assert!(records[1]["latency"].is_f64());
+
+        // without the length prefix the body is a single message
+        let single = DynamicMessage::deserialize(decoder.message.clone(), json!({"path": "/"}))
+            .unwrap()
+            .encode_to_vec();
+        assert_eq!(
+            decoder.decode(&single, false).unwrap(),
+            json!({"path": "/", "status": 0, "latency": 0.0, "method": "GET"})
+        );
+    }
+
+    #[actix_web::test]
+    async fn decoded_columns_can_be_queried() {
+        let decoder = decoder(vec![]);
+        let body = encode(
+            &decoder,
+            &[
+                json!({"path": "/api", "status": 503, "elapsed": "0.5s", "peer": {"port": 443}}),
+                json!({"path": "/health", "status": 200, "attributes": {"user": "bob"}}),
+            ],
+        );
+        let event = json::Event {
+            data: decoder.decode(&body, true).unwrap(),
+            tags: String::default(),
+            metadata: String::default(),
+        };
+        let (rb, _) = event.into_recordbatch(HashMap::new(), None, None).unwrap();
+
+        let ctx = SessionContext::new();
+        ctx.register_table(
+            "requests",
+            Arc::new(MemTable::try_new(rb.schema(), vec![vec![rb]]).unwrap()),
+        )
+        .unwrap();
+        let batches = ctx
+            .sql(
+                "SELECT path, status, elapsed, peer_port, attributes_user, method
+                 FROM requests ORDER BY status",
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let batch: &RecordBatch = &batches[0];
+        let strings = |index: usize| -> Vec<Option<&str>> {
+            batch.column(index).as_string::<i32>().iter().collect()
+        };
+        assert_eq!(strings(0), [Some("/health"), Some("/api")]);
+        let status: Vec<_> = batch.column(1).as_primitive::<Int64Type>().iter().collect();
+        assert_eq!(status, [Some(200), Some(503)]);
+        assert!(batch.column(2).is_null(0));
+        assert!(batch.column(3).is_null(0));
+        assert_eq!(strings(4), [Some("bob"), None]);
+        assert_eq!(strings(5), [Some("GET"), Some("GET")]);
+    }
+
+    #[test]
+    fn unknown_fields_are_kept() {
+        let newer = decoder(vec![field("region", 10, Type::String, None)]);
+        let body = encode(&newer, &[json!({"path": "/", "region": "eu"})]);
+
+        let records = decoder(vec![]).decode(&body, true).unwrap();
+        let unknown = records[0][DEFAULT_UNKNOWN_FIELDS_COLUMN].as_str().unwrap();
+        // field 10, length delimited, holding "eu"
+        assert_eq!(STANDARD.decode(unknown).unwrap(), [0x52, 0x02, b'e', b'u']);
+        assert!(records[0].get("region").is_none());
+    }
+
+    #[test]
+    fn malformed_bodies_name_the_message() {
+        let decoder = decoder(vec![]);
+        let mut body = encode(&decoder, &[json!({"path": "/a"}), json!({"path": "/b"})]);
+        let second = body.len() / 2;
+        body.truncate(body.len() - 1);
+        let err = decoder.decode(&body, true).unwrap_err();
+        assert!(
+            matches!(err, ProtobufError::Malformed { index: 1, offset, .. } if offset == second),
+            "{err}"
+        );
+        assert!(matches!(
+            decoder.decode(&[], true),
+            Err(ProtobufError::Empty)
+        ));
+
+        let schema = ProtobufSchema {
+            descriptor_set: descriptor_set(vec![]),
+            message: "test.Missing".to_owned(),
+            unknown_fields_column: DEFAULT_UNKNOWN_FIELDS_COLUMN.to_owned(),
+        };
+        assert!(matches!(
+            ProtobufDecoder::new(schema),
+            Err(ProtobufError::MessageNotFound(_))
+        ));
+        let schema = ProtobufSchema {
+            descriptor_set: "not base64!".to_owned(),
+            message: "test.Request".to_owned(),
+            unknown_fields_column: DEFAULT_UNKNOWN_FIELDS_COLUMN.to_owned(),
+        };
+        assert!(matches!(
+            ProtobufDecoder::new(schema),
+            Err(ProtobufError::InvalidDescriptorSet(_))
+        ));
+    }
+
+    #[test]
+    fn updates_must_fit_the_stream() {
+        let current = decoder(vec![]);
+        let stream = Schema::new(vec![
+            Field::new("status", DataType::Int64, true),
+            Field::new("peer_port", DataType::Int64, true),
+            Field::new(
+                "tags",
+                DataType::List(Field::new("item", DataType::Utf8, true).into()),
+                true,
+            ),
+            Field::new("elapsed", DataType::Float64, true),
+        ]);
+        current.check_compatible(None, &stream).unwrap();
+
+        // adding a field is fine
+        let added = decoder(vec![field("region", 10, Type::String, None)]);
+        added.check_compatible(Some(&current), &stream).unwrap();
+
+        // a column the stream holds as text
+        let stream = Schema::new(vec![Field::new("elapsed", DataType::Utf8, true)]);
+        let err = current.check_compatible(None, &stream).unwrap_err();
+        assert!(err.to_string().contains("column elapsed is Utf8"), "{err}");
+
+        // the number of status taken by a string field
+        let set = STANDARD.decode(descriptor_set(vec![])).unwrap();
+        let mut set = FileDescriptorSet::decode(set.as_slice()).unwrap();
+        let request = &mut set.file[0].message_type[0];
+        request.field.retain(|field| field.number() != 2);
+        request.field.push(field("code", 2, Type::String, None));
+        let changed = ProtobufDecoder::new(ProtobufSchema {
+            descriptor_set: STANDARD.encode(set.encode_to_vec()),
+            ..current.schema.clone()
+        })
+        .unwrap();
+        let err = changed
+            .check_compatible(Some(&current), &Schema::empty())
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("renamed from status to code")
+                && err.to_string().contains("changed from int64 to string"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,425 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use arrow_array::{RecordBatch, StringArray, TimestampMillisecondArray};
+use arrow_schema::{DataType, Field, Schema};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{DEFAULT_TIMESTAMP_KEY, DEFAULT_TIMESTAMP_SOURCE_KEY};
+
+/// Where the `p_timestamp` of an event came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampSource {
+    /// Parsed from the configured field of the event
+    Event,
+    /// The field was missing or unparseable, the time the event arrived is used
+    Arrival,
+    /// The field was further from the arrival time than the allowed skew, the
+    /// event is kept at its arrival time
+    Quarantine,
+}
+
+impl TimestampSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimestampSource::Event => "event",
+            TimestampSource::Arrival => "arrival",
+            TimestampSource::Quarantine => "quarantine",
+        }
+    }
+}
+
+/// A format the timestamp field may be in
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum TimestampFormat {
+    Rfc3339,
+    EpochMillis,
+    EpochSeconds,
+    /// strftime pattern, times without an offset are taken to be UTC
+    Pattern(String),
+}
+
+impl TimestampFormat {
+    pub fn parse(&self, value: &Value) -> Option<NaiveDateTime> {
+        match (self, value) {
+            (TimestampFormat::Rfc3339, Value::String(value)) => DateTime::parse_from_rfc3339(value)
+                .ok()
+                .map(|timestamp| timestamp.naive_utc()),
+            (TimestampFormat::EpochMillis, value) => {
+                DateTime::from_timestamp_millis(epoch(value)? as i64).map(|ts| ts.naive_utc())
+            }
+            (TimestampFormat::EpochSeconds, value) => {
+                let millis = epoch(value)? * 1000.0;
+                DateTime::from_timestamp_millis(millis.round() as i64).map(|ts| ts.naive_utc())
+            }
+            (TimestampFormat::Pattern(pattern), Value::String(value)) => {
+                DateTime::parse_from_str(value, pattern)
+                    .map(|timestamp| timestamp.naive_utc())
+                    .or_else(|_| NaiveDateTime::parse_from_str(value, pattern))
+                    .or_else(|_| {
+                        NaiveDate::parse_from_str(value, pattern)
+                            .map(|date| date.and_hms_opt(0, 0, 0).expect("midnight is valid"))
+                    })
+                    .ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+// numbers and numeric strings both count as epochs
+fn epoch(value: &Value) -> Option<f64> {
+    let epoch = match value {
+        Value::Number(number) => number.as_f64()?,
+        Value::String(value) => value.trim().parse().ok()?,
+        _ => return None,
+    };
+    epoch.is_finite().then_some(epoch)
+}
+
+impl TryFrom<String> for TimestampFormat {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            "epoch_millis" => Ok(TimestampFormat::EpochMillis),
+            "epoch_seconds" => Ok(TimestampFormat::EpochSeconds),
+            pattern if pattern.contains('%') => Ok(TimestampFormat::Pattern(value)),
+            _ => Err(format!(
+                "unknown timestamp format {value}, expected rfc3339, epoch_millis, epoch_seconds or a strftime pattern"
+            )),
+        }
+    }
+}
+
+impl From<TimestampFormat> for String {
+    fn from(format: TimestampFormat) -> Self {
+        format.to_string()
+    }
+}
+
+impl fmt::Display for TimestampFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimestampFormat::Rfc3339 => f.write_str("rfc3339"),
+            TimestampFormat::EpochMillis => f.write_str("epoch_millis"),
+            TimestampFormat::EpochSeconds => f.write_str("epoch_seconds"),
+            TimestampFormat::Pattern(pattern) => f.write_str(pattern),
+        }
+    }
+}
+
+/// What happens to events further from their arrival than the allowed skew
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SkewAction {
+    /// The request fails
+    #[default]
+    Reject,
+    /// The event is kept at its arrival time, marked as quarantined
+    Quarantine,
+}
+
+fn default_formats() -> Vec<TimestampFormat> {
+    vec![TimestampFormat::Rfc3339]
+}
+
+/// Field of its events a stream takes the event time from, as persisted in stream.json
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimestampExtraction {
+    pub field: String,
+    /// Formats tried in order, RFC 3339 when unset
+    #[serde(default = "default_formats")]
+    pub formats: Vec<TimestampFormat>,
+    /// How far from the arrival time an event time may be, unbounded when unset
+    #[serde(default, with = "humantime_serde")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_skew: Option<Duration>,
+    #[serde(default)]
+    pub on_skew: SkewAction,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TimestampError {
+    #[error("Timestamp field can't be empty")]
+    EmptyField,
+    #[error("At least one timestamp format is needed")]
+    NoFormats,
+    #[error("Event time {timestamp} is more than {} from its arrival at {arrival}", humantime::format_duration(*.max_skew))]
+    Skewed {
+        timestamp: NaiveDateTime,
+        arrival: NaiveDateTime,
+        max_skew: Duration,
+    },
+}
+
+impl TimestampExtraction {
+    /// Extraction from `field` in the default formats
+    pub fn new(field: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            formats: default_formats(),
+            max_skew: None,
+            on_skew: SkewAction::default(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), TimestampError> {
+        if self.field.trim().is_empty() {
+            return Err(TimestampError::EmptyField);
+        }
+        if self.formats.is_empty() {
+            return Err(TimestampError::NoFormats);
+        }
+        Ok(())
+    }
+
+    /// The same settings reading the event time from another field
+    pub fn with_field(&self, field: &str) -> Self {
+        Self {
+            field: field.to_owned(),
+            ..self.clone()
+        }
+    }
+
+    /// Time of an event that arrived at `arrival`, events whose field can't
+    /// be read fall back to the arrival time
+    pub fn extract(
+        &self,
+        event: &Value,
+        arrival: NaiveDateTime,
+    ) -> Result<(NaiveDateTime, TimestampSource), TimestampError> {
+        let Some(timestamp) = event
+            .get(&self.field)
+            .and_then(|value| self.formats.iter().find_map(|format| format.parse(value)))
+        else {
+            return Ok((arrival, TimestampSource::Arrival));
+        };
+
+        let Some(max_skew) = self.max_skew else {
+            return Ok((timestamp, TimestampSource::Event));
+        };
+        let skew = (timestamp - arrival)
+            .abs()
+            .to_std()
+            .unwrap_or(Duration::MAX);
+        if skew <= max_skew {
+            return Ok((timestamp, TimestampSource::Event));
+        }
+        match self.on_skew {
+            SkewAction::Reject => Err(TimestampError::Skewed {
+                timestamp,
+                arrival,
+                max_skew,
+            }),
+            SkewAction::Quarantine => Ok((arrival, TimestampSource::Quarantine)),
+        }
+    }
+}
+
+/// Sets the `p_timestamp` of every row and appends the column noting its source
+pub fn stamp(rb: &RecordBatch, timestamp: NaiveDateTime, source: TimestampSource) -> RecordBatch {
+    let mut fields = rb.schema().fields().to_vec();
+    let mut columns = rb.columns().to_vec();
+    let index = fields
+        .iter()
+        .position(|field| field.name() == DEFAULT_TIMESTAMP_KEY)
+        .expect("event batches have p_timestamp");
+    columns[index] = Arc::new(TimestampMillisecondArray::from_value(
+        timestamp.and_utc().timestamp_millis(),
+        rb.num_rows(),
+    ));
+    fields.push(Arc::new(Field::new(
+        DEFAULT_TIMESTAMP_SOURCE_KEY,
+        DataType::Utf8,
+        true,
+    )));
+    columns.push(Arc::new(StringArray::from_iter_values(
+        std::iter::repeat(source.as_str()).take(rb.num_rows()),
+    )));
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+        .expect("columns match the schema they were built with")
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow_array::Array;
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    use super::*;
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> NaiveDateTime {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, s)
+            .unwrap()
+            .naive_utc()
+    }
+
+    #[test]
+    fn parses_every_format() {
+        let expected = at(2024, 3, 1, 10, 30, 0);
+        let cases = [
+            (TimestampFormat::Rfc3339, json!("2024-03-01T12:30:00+02:00")),
+            (TimestampFormat::EpochMillis, json!(1709289000000i64)),
+            (TimestampFormat::EpochMillis, json!("1709289000000")),
+            (TimestampFormat::EpochSeconds, json!(1709289000)),
+            (TimestampFormat::EpochSeconds, json!(1709289000.0)),
+            (
+                TimestampFormat::Pattern("%d/%m/%Y %H:%M".to_string()),
+                json!("01/03/2024 10:30"),
+            ),
+        ];
+        for (format, value) in cases {
+            assert_eq!(format.parse(&value), Some(expected), "{format} of {value}");
+        }
+        assert_eq!(
+            TimestampFormat::Pattern("%Y-%m-%d".to_string()).parse(&json!("2024-03-01")),
+            Some(at(2024, 3, 1, 0, 0, 0))
+        );
+        assert_eq!(TimestampFormat::Rfc3339.parse(&json!(1709289000)), None);
+        assert_eq!(TimestampFormat::EpochMillis.parse(&json!("soon")), None);
+    }
+
+    #[test]
+    fn formats_round_trip_through_json() {
+        let extraction: TimestampExtraction = serde_json::from_value(json!({
+            "field": "ts",
+            "formats": ["epoch_seconds", "%Y-%m-%d %H:%M:%S"],
+            "max_skew": "1h",
+            "on_skew": "quarantine"
+        }))
+        .unwrap();
+        assert_eq!(
+            extraction.formats,
+            vec![
+                TimestampFormat::EpochSeconds,
+                TimestampFormat::Pattern("%Y-%m-%d %H:%M:%S".to_string())
+            ]
+        );
+        assert_eq!(extraction.max_skew, Some(Duration::from_secs(3600)));
+        assert_eq!(extraction.on_skew, SkewAction::Quarantine);
+
+        let value = serde_json::to_value(&extraction).unwrap();
+        assert_eq!(
+            serde_json::from_value::<TimestampExtraction>(value).unwrap(),
+            extraction
+        );
+
+        let defaults: TimestampExtraction = serde_json::from_value(json!({"field": "ts"})).unwrap();
+        assert_eq!(defaults, TimestampExtraction::new("ts"));
+        assert!(serde_json::from_value::<TimestampExtraction>(
+            json!({"field": "ts", "formats": ["iso"]})
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn falls_back_to_arrival() {
+        let arrival = at(2024, 3, 1, 10, 0, 0);
+        let extraction = TimestampExtraction::new("ts");
+        for event in [
+            json!({"msg": "a"}),
+            json!({"ts": "yesterday"}),
+            json!({"ts": null}),
+        ] {
+            assert_eq!(
+                extraction.extract(&event, arrival).unwrap(),
+                (arrival, TimestampSource::Arrival)
+            );
+        }
+        assert_eq!(
+            extraction
+                .extract(&json!({"ts": "2020-01-01T00:00:00Z"}), arrival)
+                .unwrap(),
+            (at(2020, 1, 1, 0, 0, 0), TimestampSource::Event)
+        );
+    }
+
+    #[test]
+    fn bounds_skew() {
+        let arrival = at(2024, 3, 1, 10, 0, 0);
+        let mut extraction = TimestampExtraction {
+            max_skew: Some(Duration::from_secs(3600)),
+            ..TimestampExtraction::new("ts")
+        };
+        let within = json!({"ts": "2024-03-01T09:00:00Z"});
+        let old = json!({"ts": "2024-03-01T08:59:59Z"});
+        let future = json!({"ts": "2024-03-01T11:00:01Z"});
+
+        assert_eq!(
+            extraction.extract(&within, arrival).unwrap(),
+            (at(2024, 3, 1, 9, 0, 0), TimestampSource::Event)
+        );
+        assert!(matches!(
+            extraction.extract(&old, arrival),
+            Err(TimestampError::Skewed { .. })
+        ));
+        assert!(matches!(
+            extraction.extract(&future, arrival),
+            Err(TimestampError::Skewed { .. })
+        ));
+
+        extraction.on_skew = SkewAction::Quarantine;
+        assert_eq!(
+            extraction.extract(&old, arrival).unwrap(),
+            (arrival, TimestampSource::Quarantine)
+        );
+    }
+
+    #[test]
+    fn stamps_batches() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                DEFAULT_TIMESTAMP_KEY,
+                DataType::Timestamp(arrow_schema::TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("msg", DataType::Utf8, true),
+        ]));
+        let rb = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(TimestampMillisecondArray::new_null(2)),
+                Arc::new(StringArray::from(vec!["a", "b"])),
+            ],
+        )
+        .unwrap();
+
+        let stamped = stamp(&rb, at(2020, 1, 1, 0, 0, 0), TimestampSource::Event);
+        let timestamps = stamped
+            .column(0)
+            .as_any()
+            .downcast_ref::<TimestampMillisecondArray>()
+            .unwrap();
+        assert_eq!(timestamps.null_count(), 0);
+        assert_eq!(timestamps.value(1), 1577836800000);
+        let sources = stamped
+            .column_by_name(DEFAULT_TIMESTAMP_SOURCE_KEY)
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(sources.value(0), "event");
+    }
+}
@@ -0,0 +1,594 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Rules a stream rewrites its events with once they are flattened, before
+//! their schema is inferred. Fields are referred to by their flattened names.
+
+use std::time::Instant;
+
+use chrono::DateTime;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+
+use super::{
+    DEFAULT_METADATA_KEY, DEFAULT_TAGS_KEY, DEFAULT_TIMESTAMP_KEY, DEFAULT_TIMESTAMP_SOURCE_KEY,
+};
+use crate::metrics::{TRANSFORM_DROPPED_FIELDS, TRANSFORM_RULE_TIME};
+
+/// Rules a stream may have at most
+pub const MAX_RULES: usize = 64;
+
+fn default_mask() -> String {
+    "****".to_string()
+}
+
+/// A single rewrite of the events of a stream
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransformRule {
+    /// Removes the field
+    DropField { field: String },
+    /// Moves the value of `from` to `to`, replacing what `to` held
+    RenameField { from: String, to: String },
+    /// Replaces the matches of `pattern` in a string field, `$1` style
+    /// references to capture groups are expanded
+    MaskRegex {
+        field: String,
+        pattern: String,
+        #[serde(default = "default_mask")]
+        replacement: String,
+    },
+    /// Sets the field to the same value in every event
+    StaticAdd { field: String, value: Value },
+    /// Sets the field to the result of `expression`, numbers combined with
+    /// `+ - * /` or values joined as text with `||`
+    Derive { field: String, expression: String },
+}
+
+impl TransformRule {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TransformRule::DropField { .. } => "drop_field",
+            TransformRule::RenameField { .. } => "rename_field",
+            TransformRule::MaskRegex { .. } => "mask_regex",
+            TransformRule::StaticAdd { .. } => "static_add",
+            TransformRule::Derive { .. } => "derive",
+        }
+    }
+
+    // fields the rule writes to
+    fn targets(&self) -> Vec<&str> {
+        match self {
+            TransformRule::DropField { field } => vec![field],
+            TransformRule::RenameField { from, to } => vec![from, to],
+            TransformRule::MaskRegex { field, .. }
+            | TransformRule::StaticAdd { field, .. }
+            | TransformRule::Derive { field, .. } => vec![field],
+        }
+    }
+}
+
+/// Rules of a stream as persisted in stream.json, applied in order. The
+/// version goes up every time the rules are replaced
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransformRules {
+    #[serde(default)]
+    pub version: u64,
+    pub rules: Vec<TransformRule>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransformError {
+    #[error("At most {MAX_RULES} transform rules are allowed")]
+    TooManyRules,
+    #[error("Rule {0}: field name can't be empty")]
+    EmptyField(usize),
+    #[error("Rule {0}: field {1} is reserved")]
+    ReservedField(usize, String),
+    #[error("Rule {0}: invalid pattern: {1}")]
+    Pattern(usize, regex::Error),
+    #[error("Rule {0}: invalid expression: {1}")]
+    Expression(usize, String),
+}
+
+#[derive(Debug)]
+enum Compiled {
+    Drop(String),
+    Rename(String, String),
+    Mask(String, Regex, String),
+    Add(String, Value),
+    Derive(String, Expr),
+}
+
+/// Rules of a stream ready to be applied
+#[derive(Debug)]
+pub struct Transformer {
+    source: TransformRules,
+    // rules with the label their metrics are kept under
+    rules: Vec<(String, Compiled)>,
+}
+
+impl Transformer {
+    pub fn new(source: TransformRules) -> Result<Self, TransformError> {
+        if source.rules.len() > MAX_RULES {
+            return Err(TransformError::TooManyRules);
+        }
+        let mut rules = Vec::with_capacity(source.rules.len());
+        for (index, rule) in source.rules.iter().enumerate() {
+            for field in rule.targets() {
+                if field.trim().is_empty() {
+                    return Err(TransformError::EmptyField(index));
+                }
+                if [
+                    DEFAULT_TIMESTAMP_KEY,
+                    DEFAULT_TAGS_KEY,
+                    DEFAULT_METADATA_KEY,
+                    DEFAULT_TIMESTAMP_SOURCE_KEY,
+                ]
+                .contains(&field)
+                {
+                    return Err(TransformError::ReservedField(index, field.to_owned()));
+                }
+            }
+            let compiled = match rule {
+                TransformRule::DropField { field } => Compiled::Drop(field.clone()),
+                TransformRule::RenameField { from, to } => {
+                    Compiled::Rename(from.clone(), to.clone())
+                }
+                TransformRule::MaskRegex {
+                    field,
+                    pattern,
+                    replacement,
+                } => Compiled::Mask(
+                    field.clone(),
+                    Regex::new(pattern).map_err(|err| TransformError::Pattern(index, err))?,
+                    replacement.clone(),
+                ),
+                TransformRule::StaticAdd { field, value } => {
+                    Compiled::Add(field.clone(), value.clone())
+                }
+                TransformRule::Derive { field, expression } => Compiled::Derive(
+                    field.clone(),
+                    Expr::parse(expression)
+                        .map_err(|err| TransformError::Expression(index, err))?,
+                ),
+            };
+            rules.push((format!("{index}_{}", rule.kind()), compiled));
+        }
+        Ok(Self { source, rules })
+    }
+
+    /// The rules as they were set
+    pub fn rules(&self) -> &TransformRules {
+        &self.source
+    }
+
+    /// Rewrites the event or array of events of `stream_name`, anything that
+    /// is not an object is left as it is
+    pub fn apply(&self, stream_name: &str, body: &mut Value) {
+        let mut events: Vec<&mut Map<String, Value>> = match body {
+            Value::Object(event) => vec![event],
+            Value::Array(events) => events.iter_mut().filter_map(Value::as_object_mut).collect(),
+            _ => return,
+        };
+        for (label, rule) in &self.rules {
+            let started = Instant::now();
+            let mut dropped = 0;
+            for event in events.iter_mut() {
+                match rule {
+                    Compiled::Drop(field) => {
+                        if event.remove(field).is_some() {
+                            dropped += 1;
+                        }
+                    }
+                    Compiled::Rename(from, to) => {
+                        if let Some(value) = event.remove(from) {
+                            event.insert(to.clone(), value);
+                        }
+                    }
+                    Compiled::Mask(field, pattern, replacement) => {
+                        if let Some(Value::String(value)) = event.get_mut(field) {
+                            *value = pattern
+                                .replace_all(value, replacement.as_str())
+                                .into_owned();
+                        }
+                    }
+                    Compiled::Add(field, value) => {
+                        event.insert(field.clone(), value.clone());
+                    }
+                    Compiled::Derive(field, expr) => {
+                        if let Some(value) = expr.eval(event) {
+                            event.insert(field.clone(), value);
+                        }
+                    }
+                }
+            }
+            TRANSFORM_RULE_TIME
+                .with_label_values(&[stream_name, label])
+                .observe(started.elapsed().as_secs_f64());
+            if dropped > 0 {
+                TRANSFORM_DROPPED_FIELDS
+                    .with_label_values(&[stream_name, label])
+                    .inc_by(dropped);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Concat,
+}
+
+/// Expression of a derived field. Numbers are combined with `+ - * /` and
+/// parentheses, `||` joins values as text. Fields are referred to by name,
+/// or in double quotes when the name has other characters than letters,
+/// digits, `_`, `.` and `@`. Text literals are in single quotes.
+///
+/// Arithmetic is done in floating point, fields holding RFC 3339 timestamps
+/// count as milliseconds since the epoch so `end - start` is a duration in
+/// milliseconds. The derived field is left unset when a field is missing or
+/// can't be used, or on division by zero.
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Text(String),
+    Field(String),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Field(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '.' | '@')
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '+' | '-' | '*' | '/' | '(' | ')' => {
+                chars.next();
+                tokens.push(match c {
+                    '+' => Token::Op(Op::Add),
+                    '-' => Token::Op(Op::Sub),
+                    '*' => Token::Op(Op::Mul),
+                    '/' => Token::Op(Op::Div),
+                    '(' => Token::Open,
+                    _ => Token::Close,
+                });
+            }
+            '|' => {
+                chars.next();
+                if chars.next() != Some('|') {
+                    return Err("expected ||".to_string());
+                }
+                tokens.push(Token::Op(Op::Concat));
+            }
+            '\'' | '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        // a doubled quote stands for the quote itself
+                        Some(q) if q == c && chars.peek() == Some(&c) => {
+                            chars.next();
+                            text.push(c);
+                        }
+                        Some(q) if q == c => break,
+                        Some(other) => text.push(other),
+                        None => return Err(format!("unterminated {c}")),
+                    }
+                }
+                tokens.push(if c == '\'' {
+                    Token::Text(text)
+                } else {
+                    Token::Field(text)
+                });
+            }
+            c if c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(&d) = chars.peek() {
+                    if !(d.is_ascii_digit() || d == '.') {
+                        break;
+                    }
+                    number.push(d);
+                    chars.next();
+                }
+                let number = number
+                    .parse()
+                    .map_err(|_| format!("invalid number {number}"))?;
+                tokens.push(Token::Number(number));
+            }
+            c if is_name_char(c) => {
+                let mut name = String::new();
+                while let Some(&n) = chars.peek() {
+                    if !is_name_char(n) {
+                        break;
+                    }
+                    name.push(n);
+                    chars.next();
+                }
+                tokens.push(Token::Field(name));
+            }
+            other => return Err(format!("unexpected character {other}")),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    // concat := additive ('||' additive)*
+    fn concat(&mut self) -> Result<Expr, String> {
+        let mut expr = self.additive()?;
+        while self.peek() == Some(&Token::Op(Op::Concat)) {
+            self.next();
+            expr = Expr::Binary(Op::Concat, Box::new(expr), Box::new(self.additive()?));
+        }
+        Ok(expr)
+    }
+
+    // additive := term (('+' | '-') term)*
+    fn additive(&mut self) -> Result<Expr, String> {
+        let mut expr = self.term()?;
+        while let Some(Token::Op(op @ (Op::Add | Op::Sub))) = self.peek().cloned() {
+            self.next();
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.term()?));
+        }
+        Ok(expr)
+    }
+
+    // term := unary (('*' | '/') unary)*
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while let Some(Token::Op(op @ (Op::Mul | Op::Div))) = self.peek().cloned() {
+            self.next();
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    // unary := '-' unary | primary
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some(&Token::Op(Op::Sub)) {
+            self.next();
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    // primary := number | text | field | '(' concat ')'
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Expr::Number(number)),
+            Some(Token::Text(text)) => Ok(Expr::Text(text)),
+            Some(Token::Field(field)) => Ok(Expr::Field(field)),
+            Some(Token::Open) => {
+                let expr = self.concat()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("expected )".to_string()),
+                }
+            }
+            Some(token) => Err(format!("unexpected {token:?}")),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+impl Expr {
+    fn parse(expression: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            position: 0,
+        };
+        let expr = parser.concat()?;
+        match parser.next() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {token:?}")),
+        }
+    }
+
+    fn eval(&self, event: &Map<String, Value>) -> Option<Value> {
+        match self {
+            Expr::Number(number) => Number::from_f64(*number).map(Value::Number),
+            Expr::Text(text) => Some(Value::String(text.clone())),
+            Expr::Field(field) => event.get(field).filter(|value| !value.is_null()).cloned(),
+            Expr::Neg(expr) => Number::from_f64(-as_number(&expr.eval(event)?)?).map(Value::Number),
+            Expr::Binary(Op::Concat, left, right) => Some(Value::String(
+                as_text(&left.eval(event)?)? + &as_text(&right.eval(event)?)?,
+            )),
+            Expr::Binary(op, left, right) => {
+                let left = as_number(&left.eval(event)?)?;
+                let right = as_number(&right.eval(event)?)?;
+                let result = match op {
+                    Op::Add => left + right,
+                    Op::Sub => left - right,
+                    Op::Mul => left * right,
+                    Op::Div if right == 0.0 => return None,
+                    Op::Div => left / right,
+                    Op::Concat => unreachable!("handled above"),
+                };
+                Number::from_f64(result).map(Value::Number)
+            }
+        }
+    }
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => DateTime::parse_from_rfc3339(text)
+            .ok()
+            .map(|time| time.timestamp_millis() as f64),
+        _ => None,
+    }
+}
+
+fn as_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(flag) => Some(flag.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::Float64Type;
+    use arrow_schema::DataType;
+    use serde_json::json;
+
+    use super::{TransformError, TransformRules, Transformer};
+    use crate::event::format::{json, EventFormat};
+
+    fn transformer(rules: serde_json::Value) -> Result<Transformer, TransformError> {
+        let rules = serde_json::from_value(json!({ "version": 1, "rules": rules })).unwrap();
+        Transformer::new(rules)
+    }
+
+    #[test]
+    fn chain_of_rules_shapes_stored_rows() {
+        let transformer = transformer(json!([
+            { "type": "drop_field", "field": "debug" },
+            { "type": "rename_field", "from": "msg", "to": "message" },
+            { "type": "mask_regex", "field": "message", "pattern": r"\d{4}-\d{4}" },
+            { "type": "static_add", "field": "env", "value": "prod" },
+            { "type": "derive", "field": "duration_ms", "expression": "end - start" },
+            { "type": "derive", "field": "route", "expression": "method || ' ' || \"req.path\"" },
+        ]))
+        .unwrap();
+
+        let mut body = json!([
+            {
+                "msg": "card 1234-5678 declined",
+                "debug": "x",
+                "start": "2024-03-01T10:00:00Z",
+                "end": "2024-03-01T10:00:01.500Z",
+                "method": "GET",
+                "req.path": "/pay",
+            },
+            { "msg": "ok", "method": "POST" },
+        ]);
+        transformer.apply("app", &mut body);
+
+        let event = json::Event {
+            data: body,
+            tags: String::default(),
+            metadata: String::default(),
+        };
+        let (rb, _) = event.into_recordbatch(HashMap::new(), None, None).unwrap();
+        let schema = rb.schema();
+        assert!(schema.column_with_name("debug").is_none());
+        assert!(schema.column_with_name("msg").is_none());
+        assert_eq!(
+            schema.field_with_name("duration_ms").unwrap().data_type(),
+            &DataType::Float64
+        );
+
+        let message = rb.column_by_name("message").unwrap().as_string::<i32>();
+        assert_eq!(message.value(0), "card **** declined");
+        assert_eq!(message.value(1), "ok");
+        let env = rb.column_by_name("env").unwrap().as_string::<i32>();
+        assert_eq!(env.value(1), "prod");
+        let duration = rb
+            .column_by_name("duration_ms")
+            .unwrap()
+            .as_primitive::<Float64Type>();
+        assert_eq!(duration.value(0), 1500.0);
+        // the second event has no start and end to derive from
+        assert!(duration.is_null(1));
+        let route = rb.column_by_name("route").unwrap().as_string::<i32>();
+        assert_eq!(route.value(0), "GET /pay");
+        assert!(route.is_null(1));
+    }
+
+    #[test]
+    fn arithmetic_follows_precedence() {
+        let transformer = transformer(json!([
+            { "type": "derive", "field": "total", "expression": "-(a + b) * 2 / 4" },
+            { "type": "derive", "field": "ratio", "expression": "a / zero" },
+        ]))
+        .unwrap();
+        let mut event = json!({ "a": 3, "b": 1, "zero": 0 });
+        transformer.apply("app", &mut event);
+        assert_eq!(event["total"], json!(-2.0));
+        assert!(event.get("ratio").is_none());
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        assert!(matches!(
+            transformer(json!([{ "type": "mask_regex", "field": "a", "pattern": "(" }])),
+            Err(TransformError::Pattern(0, _))
+        ));
+        assert!(matches!(
+            transformer(json!([
+                { "type": "drop_field", "field": "a" },
+                { "type": "rename_field", "from": "a", "to": "p_timestamp" },
+            ])),
+            Err(TransformError::ReservedField(1, _))
+        ));
+        assert!(matches!(
+            transformer(json!([{ "type": "derive", "field": "a", "expression": "b +" }])),
+            Err(TransformError::Expression(0, _))
+        ));
+        assert!(matches!(
+            transformer(json!([{ "type": "static_add", "field": " ", "value": 1 }])),
+            Err(TransformError::EmptyField(0))
+        ));
+        assert!(Transformer::new(TransformRules::default()).is_ok());
+    }
+}
@@ -0,0 +1,424 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{collections::HashMap, sync::Arc};
+
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// A column whose type was widened while ingesting, kept in the stream
+/// metadata as a record of how its schema evolved
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaChange {
+    pub field: String,
+    pub from: DataType,
+    pub to: DataType,
+    pub changed_at: DateTime<Utc>,
+}
+
+// order of the types a column is widened through, narrowest first
+fn rank(data_type: &DataType) -> Option<u8> {
+    match data_type {
+        DataType::Int32 => Some(0),
+        DataType::Int64 => Some(1),
+        DataType::Float64 => Some(2),
+        DataType::Utf8 => Some(3),
+        _ => None,
+    }
+}
+
+fn widenable(data_type: &DataType) -> bool {
+    rank(data_type).is_some() || *data_type == DataType::Boolean
+}
+
+/// Narrowest type both `a` and `b` can be represented as. Int32 widens to
+/// Int64, integers widen to Float64 and any of these or Boolean to Utf8 as a
+/// last resort. None when either is some other type.
+pub fn wider(a: &DataType, b: &DataType) -> Option<DataType> {
+    if a == b {
+        return Some(a.clone());
+    }
+    match (rank(a), rank(b)) {
+        (Some(a_rank), Some(b_rank)) if a_rank > b_rank => Some(a.clone()),
+        (Some(_), Some(_)) => Some(b.clone()),
+        _ if widenable(a) && widenable(b) => Some(DataType::Utf8),
+        _ => None,
+    }
+}
+
+// type a column of type `column` has to be widened to for `value` to fit
+fn widened_type(column: &DataType, value: &Value) -> Option<DataType> {
+    let value_type = match value {
+        Value::Null => return None,
+        Value::Number(number)
+            if *column == DataType::Int32
+                && number.as_i64().is_some_and(|n| i32::try_from(n).is_ok()) =>
+        {
+            return None
+        }
+        Value::Number(number) if number.is_i64() => DataType::Int64,
+        Value::Number(_) => DataType::Float64,
+        Value::Bool(_) => DataType::Boolean,
+        // arrays and objects only fit a column once it holds their json text
+        Value::String(_) | Value::Array(_) | Value::Object(_) => DataType::Utf8,
+    };
+    wider(column, &value_type).filter(|to| to != column)
+}
+
+// converts `value` to the type of a widened column
+fn coerce(column: &DataType, value: &mut Value) {
+    match column {
+        DataType::Float64 if value.is_i64() || value.is_u64() => {
+            *value = value.as_f64().map(Value::from).unwrap_or(Value::Null)
+        }
+        DataType::Utf8 if !value.is_string() && !value.is_null() => {
+            *value = Value::String(value.to_string())
+        }
+        _ => {}
+    }
+}
+
+/// Widens the columns of `schema` that values of the flattened event, or
+/// array of events, do not fit in and returns the changes. Values of widened
+/// columns, widened now or by earlier events, are coerced to the column type
+/// so that the events decode with the widened schema.
+pub fn widen(schema: &HashMap<String, Arc<Field>>, events: &mut Value) -> Vec<SchemaChange> {
+    let mut rows: Vec<&mut Map<String, Value>> = match events {
+        Value::Object(event) => vec![event],
+        Value::Array(events) => events.iter_mut().filter_map(Value::as_object_mut).collect(),
+        _ => return Vec::new(),
+    };
+
+    let mut widened: HashMap<String, DataType> = HashMap::new();
+    for (name, value) in rows.iter().flat_map(|row| row.iter()) {
+        let Some(field) = schema.get(name) else {
+            continue;
+        };
+        let column = widened.get(name).unwrap_or(field.data_type());
+        if let Some(to) = widened_type(column, value) {
+            widened.insert(name.clone(), to);
+        }
+    }
+
+    for (name, value) in rows.iter_mut().flat_map(|row| row.iter_mut()) {
+        let column = widened
+            .get(name)
+            .or_else(|| schema.get(name).map(|field| field.data_type()));
+        if let Some(column) = column {
+            coerce(column, value);
+        }
+    }
+
+    let changed_at = Utc::now();
+    widened
+        .into_iter()
+        .map(|(field, to)| SchemaChange {
+            from: schema[&field].data_type().clone(),
+            field,
+            to,
+            changed_at,
+        })
+        .sorted_by(|a, b| a.field.cmp(&b.field))
+        .collect()
+}
+
+/// `field` widened to `to`, unless it already is at least as wide
+pub fn widen_field(field: &Field, to: &DataType) -> Field {
+    match wider(field.data_type(), to) {
+        Some(data_type) if data_type != *field.data_type() => {
+            field.clone().with_data_type(data_type)
+        }
+        _ => field.clone(),
+    }
+}
+
+/// `schema` with the changed fields widened, fields it does not have yet are added
+pub fn apply(schema: &Schema, changes: &[SchemaChange]) -> Schema {
+    let mut fields = schema
+        .fields()
+        .iter()
+        .map(
+            |field| match changes.iter().find(|c| c.field == *field.name()) {
+                Some(change) => widen_field(field, &change.to),
+                None => field.as_ref().clone(),
+            },
+        )
+        .collect_vec();
+    for change in changes {
+        if schema.field_with_name(&change.field).is_err() {
+            fields.push(Field::new(&change.field, change.to.clone(), true));
+        }
+    }
+    Schema::new_with_metadata(fields, schema.metadata().clone())
+}
+
+/// Merges schemas like [`Schema::try_merge`], except that a field with
+/// different types in different schemas takes the wider type. Batches of the
+/// narrower schemas have to be cast, see [`crate::utils::arrow::adapt_batch`].
+pub fn merge_schemas(schemas: impl IntoIterator<Item = Schema>) -> Result<Schema, ArrowError> {
+    let schemas = schemas.into_iter().collect_vec();
+    let mut widest: HashMap<&String, DataType> = HashMap::new();
+    for field in schemas.iter().flat_map(|schema| schema.fields().iter()) {
+        let data_type = match widest.get(field.name()) {
+            Some(current) => wider(current, field.data_type()).unwrap_or(current.clone()),
+            None => field.data_type().clone(),
+        };
+        widest.insert(field.name(), data_type);
+    }
+
+    let schemas = schemas
+        .iter()
+        .map(|schema| {
+            let fields = schema
+                .fields()
+                .iter()
+                .map(|field| widen_field(field, &widest[field.name()]))
+                .collect_vec();
+            Schema::new_with_metadata(fields, schema.metadata().clone())
+        })
+        .collect_vec();
+    Schema::try_merge(schemas)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs::File, sync::Arc};
+
+    use arrow_array::{cast::AsArray, types::Float64Type, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{
+        datasource::{
+            file_format::parquet::ParquetFormat,
+            listing::{ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl},
+        },
+        prelude::SessionContext,
+    };
+    use parquet::arrow::ArrowWriter;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::event::format::{json, EventFormat};
+    use crate::utils::arrow::adapt_batch;
+
+    #[test]
+    fn widens_to_the_narrowest_type_that_fits() {
+        let cases = [
+            (DataType::Int32, json!(12), None),
+            (DataType::Int32, json!(1i64 << 40), Some(DataType::Int64)),
+            (DataType::Int32, json!(12.5), Some(DataType::Float64)),
+            (DataType::Int64, json!(12.5), Some(DataType::Float64)),
+            (DataType::Int64, json!(u64::MAX), Some(DataType::Float64)),
+            (DataType::Int64, json!("slow"), Some(DataType::Utf8)),
+            (DataType::Float64, json!(12), None),
+            (DataType::Float64, json!(true), Some(DataType::Utf8)),
+            (DataType::Boolean, json!(1), Some(DataType::Utf8)),
+            (DataType::Int64, json!([1, 2]), Some(DataType::Utf8)),
+            (DataType::Utf8, json!(12), None),
+            (DataType::Int64, json!(null), None),
+        ];
+        for (column, value, expected) in cases {
+            assert_eq!(widened_type(&column, &value), expected, "{column} {value}");
+        }
+
+        let list = DataType::List(Arc::new(Field::new("item", DataType::Int64, true)));
+        assert_eq!(widened_type(&list, &json!("a")), None);
+    }
+
+    #[test]
+    fn widened_values_are_coerced() {
+        let schema = HashMap::from([
+            (
+                "latency".to_string(),
+                Arc::new(Field::new("latency", DataType::Int64, true)),
+            ),
+            (
+                "status".to_string(),
+                Arc::new(Field::new("status", DataType::Utf8, true)),
+            ),
+        ]);
+        let mut events = json!([
+            {"latency": 12, "status": 200},
+            {"latency": 12.5, "status": "ok"},
+            {"latency": null, "host": "a"},
+        ]);
+
+        let changes = widen(&schema, &mut events);
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].field, "latency");
+        assert_eq!(changes[0].from, DataType::Int64);
+        assert_eq!(changes[0].to, DataType::Float64);
+        assert_eq!(
+            events,
+            json!([
+                {"latency": 12.0, "status": "200"},
+                {"latency": 12.5, "status": "ok"},
+                {"latency": null, "host": "a"},
+            ])
+        );
+        assert!(events[0]["latency"].is_f64());
+    }
+
+    #[test]
+    fn merged_schemas_take_the_wider_type() {
+        let schema = |data_type| Schema::new(vec![Field::new("latency", data_type, true)]);
+        let merged = merge_schemas([
+            schema(DataType::Int64),
+            schema(DataType::Float64),
+            schema(DataType::Int32),
+        ])
+        .unwrap();
+        assert_eq!(merged, schema(DataType::Float64));
+
+        let merged = merge_schemas([schema(DataType::Boolean), schema(DataType::Int64)]).unwrap();
+        assert_eq!(merged, schema(DataType::Utf8));
+
+        let list = DataType::List(Arc::new(Field::new("item", DataType::Int64, true)));
+        assert!(merge_schemas([schema(DataType::Int64), schema(list)]).is_err());
+    }
+
+    // ingests `body` the way the ingest handler does for a stream with type
+    // widening allowed, committing new and widened columns to `schema`
+    fn ingest(schema: &mut HashMap<String, Arc<Field>>, mut body: Value) -> RecordBatch {
+        for change in widen(schema, &mut body) {
+            let field = widen_field(&schema[&change.field], &change.to);
+            schema.insert(change.field, Arc::new(field));
+        }
+        let event = json::Event {
+            data: body,
+            tags: String::default(),
+            metadata: String::default(),
+        };
+        let (rb, _) = event.into_recordbatch(schema.clone(), None, None).unwrap();
+        for field in rb.schema().fields() {
+            schema
+                .entry(field.name().clone())
+                .or_insert_with(|| field.clone());
+        }
+        rb
+    }
+
+    #[actix_web::test]
+    async fn int_float_and_string_events_are_queryable_together() {
+        let mut schema = HashMap::new();
+        let batches = [
+            ingest(&mut schema, json!({"host": "a", "latency": 12})),
+            ingest(&mut schema, json!({"host": "b", "latency": 12.5})),
+            ingest(&mut schema, json!({"host": "c", "latency": "timeout"})),
+        ];
+        let types = batches
+            .iter()
+            .map(|rb| {
+                rb.schema()
+                    .field_with_name("latency")
+                    .unwrap()
+                    .data_type()
+                    .clone()
+            })
+            .collect_vec();
+        assert_eq!(types, [DataType::Int64, DataType::Float64, DataType::Utf8]);
+        assert_eq!(schema["latency"].data_type(), &DataType::Utf8);
+
+        // the arrows staged before and after widening end up in one parquet file
+        let staged = merge_schemas(batches.iter().map(|rb| rb.schema().as_ref().clone())).unwrap();
+        let adapted = batches
+            .iter()
+            .map(|rb| adapt_batch(&staged, rb))
+            .collect_vec();
+        assert_eq!(
+            adapted
+                .iter()
+                .map(|rb| rb
+                    .column_by_name("latency")
+                    .unwrap()
+                    .as_string::<i32>()
+                    .value(0))
+                .collect_vec(),
+            ["12", "12.5", "timeout"]
+        );
+
+        // parquet files written before the widening keep their original types
+        let dir = tempfile::tempdir().unwrap();
+        for (index, rb) in batches.iter().enumerate() {
+            let file = File::create(dir.path().join(format!("{index}.data.parquet"))).unwrap();
+            let mut writer = ArrowWriter::try_new(file, rb.schema(), None).unwrap();
+            writer.write(rb).unwrap();
+            writer.close().unwrap();
+        }
+
+        let table = |schema: Schema, files: &[usize]| {
+            let urls = files
+                .iter()
+                .map(|index| {
+                    ListingTableUrl::parse(format!("{}/{index}.data.parquet", dir.path().display()))
+                        .unwrap()
+                })
+                .collect();
+            let options = ListingOptions::new(Arc::new(ParquetFormat::default()))
+                .with_file_extension(".parquet");
+            let config = ListingTableConfig::new_with_multi_paths(urls)
+                .with_listing_options(options)
+                .with_schema(Arc::new(schema));
+            Arc::new(ListingTable::try_new(config).unwrap())
+        };
+        let stream_schema = |schema: &HashMap<String, Arc<Field>>| {
+            Schema::new(schema.values().cloned().collect_vec())
+        };
+
+        let ctx = SessionContext::new();
+        // the stream as it was after the float arrived
+        let mut float_schema = schema.clone();
+        float_schema.insert(
+            "latency".to_string(),
+            Arc::new(Field::new("latency", DataType::Float64, true)),
+        );
+        ctx.register_table("numeric", table(stream_schema(&float_schema), &[0, 1]))
+            .unwrap();
+        ctx.register_table("logs", table(stream_schema(&schema), &[0, 1, 2]))
+            .unwrap();
+
+        let sum = ctx
+            .sql("SELECT sum(latency) FROM numeric")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            sum[0].column(0).as_primitive::<Float64Type>().value(0),
+            24.5
+        );
+
+        let rows = ctx
+            .sql("SELECT host, latency FROM logs ORDER BY host")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let rows = arrow_select::concat::concat_batches(&rows[0].schema(), &rows).unwrap();
+        let latencies = rows.column(1).as_string::<i32>();
+        assert_eq!(
+            latencies.iter().flatten().collect_vec(),
+            ["12", "12.5", "timeout"]
+        );
+    }
+}
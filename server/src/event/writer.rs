@@ -22,25 +22,67 @@ mod mem_writer;
 
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex, RwLock, RwLockWriteGuard},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock, RwLockWriteGuard,
+    },
 };
 
 use crate::{
     handlers::http::cluster::INTERNAL_STREAM_NAME,
     option::{Mode, CONFIG},
+    storage::staging::StorageDir,
     utils,
 };
 
-use self::{errors::StreamWriterError, file_writer::FileWriter, mem_writer::MemWriter};
-use arrow_array::{RecordBatch, TimestampMillisecondArray};
+use self::{
+    errors::StreamWriterError,
+    file_writer::{AtomicFiles, FileWriter},
+    mem_writer::MemWriter,
+};
+use arrow_array::{Array, RecordBatch, TimestampMillisecondArray};
 use arrow_schema::Schema;
 use chrono::NaiveDateTime;
 use chrono::Utc;
 use derive_more::{Deref, DerefMut};
 use once_cell::sync::Lazy;
+use rand::distributions::{Alphanumeric, DistString};
+use tokio::sync::watch;
 
 pub static STREAM_WRITERS: Lazy<WriterTable> = Lazy::new(WriterTable::default);
 
+pub static STAGING_FLUSHES: Lazy<StagingFlushes> = Lazy::new(StagingFlushes::default);
+
+/// Counts the flushes of the staged events to disk, so that those ingesting can
+/// tell when what they pushed is on disk
+#[derive(Debug)]
+pub struct StagingFlushes {
+    started: AtomicU64,
+    completed: watch::Sender<u64>,
+}
+
+impl Default for StagingFlushes {
+    fn default() -> Self {
+        Self {
+            started: AtomicU64::new(0),
+            completed: watch::channel(0).0,
+        }
+    }
+}
+
+impl StagingFlushes {
+    /// The flush that has everything pushed so far on disk once it completed
+    pub fn next(&self) -> u64 {
+        self.started.load(Ordering::SeqCst) + 1
+    }
+
+    /// Receives the number of flushes completed
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.completed.subscribe()
+    }
+}
+
 #[derive(Default)]
 pub struct Writer {
     pub mem: MemWriter<16384>,
@@ -56,13 +98,7 @@ impl Writer {
         parsed_timestamp: NaiveDateTime,
         custom_partition_values: &HashMap<String, String>,
     ) -> Result<(), StreamWriterError> {
-        let rb = utils::arrow::replace_columns(
-            rb.schema(),
-            &rb,
-            &[0],
-            &[Arc::new(get_timestamp_array(rb.num_rows()))],
-        );
-
+        let rb = with_ingestion_time(rb);
         self.disk.push(
             stream_name,
             schema_key,
@@ -80,6 +116,15 @@ impl Writer {
     }
 }
 
+/// A batch of an atomic append, see [`WriterTable::append_atomic`]
+#[derive(Debug, Clone)]
+pub struct StagedBatch {
+    pub schema_key: String,
+    pub rb: RecordBatch,
+    pub parsed_timestamp: NaiveDateTime,
+    pub custom_partition_values: HashMap<String, String>,
+}
+
 #[derive(Deref, DerefMut, Default)]
 pub struct WriterTable(RwLock<HashMap<String, Mutex<Writer>>>);
 
@@ -195,6 +240,77 @@ impl WriterTable {
         Ok(())
     }
 
+    /// Appends batches of a stream so that either all of them are staged or
+    /// none is. They go to staging files of their own which are moved in place
+    /// together, and queries see them once they are all in memory
+    pub fn append_atomic(
+        &self,
+        stream_name: &str,
+        batches: Vec<StagedBatch>,
+    ) -> Result<(), StreamWriterError> {
+        let to_disk = CONFIG.parseable.mode != Mode::Query || stream_name == INTERNAL_STREAM_NAME;
+        self.append_atomic_with(stream_name, batches, |batches| {
+            if !to_disk {
+                return Ok(());
+            }
+            let dir = StorageDir::new(stream_name);
+            // the open writers append to the files named by the schema key
+            // alone, these files get a name of their own
+            let suffix = Alphanumeric.sample_string(&mut rand::thread_rng(), 8);
+            let mut files: HashMap<PathBuf, Vec<&RecordBatch>> = HashMap::new();
+            for batch in batches {
+                let path = dir.path_by_current_time(
+                    &format!("{}{suffix}", batch.schema_key),
+                    batch.parsed_timestamp,
+                    &batch.custom_partition_values,
+                );
+                files.entry(path).or_default().push(&batch.rb);
+            }
+            let mut staged = AtomicFiles::default();
+            for (path, records) in files {
+                staged.write(path, &records)?;
+            }
+            staged.commit()
+        })
+    }
+
+    // stages the batches with `stage` while flushes are held off, they reach
+    // the buffers queries read in one go once it succeeds
+    fn append_atomic_with(
+        &self,
+        stream_name: &str,
+        batches: Vec<StagedBatch>,
+        stage: impl FnOnce(&[StagedBatch]) -> Result<(), StreamWriterError>,
+    ) -> Result<(), StreamWriterError> {
+        let batches: Vec<StagedBatch> = batches
+            .into_iter()
+            .map(|batch| StagedBatch {
+                rb: with_ingestion_time(batch.rb),
+                ..batch
+            })
+            .collect();
+        let mut stage = Some(stage);
+        loop {
+            {
+                let table = self.read().unwrap();
+                if let Some(writer) = table.get(stream_name) {
+                    let mut writer = writer.lock().unwrap();
+                    let stage = stage.take().expect("batches are staged once");
+                    stage(&batches)?;
+                    for batch in batches {
+                        writer.mem.push(&batch.schema_key, batch.rb);
+                    }
+                    return Ok(());
+                }
+            }
+            // a flush can take the writer again before the read lock is back
+            self.write()
+                .unwrap()
+                .entry(stream_name.to_owned())
+                .or_default();
+        }
+    }
+
     pub fn clear(&self, stream_name: &str) {
         let map = self.write().unwrap();
         if let Some(writer) = map.get(stream_name) {
@@ -210,11 +326,16 @@ impl WriterTable {
     pub fn unset_all(&self) {
         let mut table = self.write().unwrap();
         let map = std::mem::take(&mut *table);
+        // counted while pushes are locked out, what was pushed before is in this flush
+        let flush = STAGING_FLUSHES.started.fetch_add(1, Ordering::SeqCst) + 1;
         drop(table);
         for writer in map.into_values() {
             let writer = writer.into_inner().unwrap();
             writer.disk.close_all();
         }
+        STAGING_FLUSHES
+            .completed
+            .send_modify(|completed| *completed = (*completed).max(flush));
     }
 
     pub fn recordbatches_cloned(
@@ -236,6 +357,20 @@ impl WriterTable {
     }
 }
 
+// p_timestamp is the time of ingestion unless the event time was extracted
+fn with_ingestion_time(rb: RecordBatch) -> RecordBatch {
+    if rb.column(0).null_count() == rb.num_rows() {
+        utils::arrow::replace_columns(
+            rb.schema(),
+            &rb,
+            &[0],
+            &[Arc::new(get_timestamp_array(rb.num_rows()))],
+        )
+    } else {
+        rb
+    }
+}
+
 fn get_timestamp_array(size: usize) -> TimestampMillisecondArray {
     TimestampMillisecondArray::from_value(Utc::now().timestamp_millis(), size)
 }
@@ -250,3 +385,72 @@ pub mod errors {
         Io(#[from] std::io::Error),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use arrow_array::Int64Array;
+    use arrow_schema::{DataType, Field, TimeUnit};
+
+    use super::*;
+
+    fn schema() -> Arc<Schema> {
+        Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("amount", DataType::Int64, true),
+        ]))
+    }
+
+    fn batches() -> Vec<StagedBatch> {
+        [vec![10, 20], vec![30]]
+            .into_iter()
+            .map(|amounts| {
+                let rows = amounts.len();
+                let rb = RecordBatch::try_new(
+                    schema(),
+                    vec![
+                        Arc::new(TimestampMillisecondArray::from_value(0, rows)),
+                        Arc::new(Int64Array::from(amounts)),
+                    ],
+                )
+                .unwrap();
+                StagedBatch {
+                    schema_key: "key".to_owned(),
+                    rb,
+                    parsed_timestamp: NaiveDateTime::default(),
+                    custom_partition_values: HashMap::new(),
+                }
+            })
+            .collect()
+    }
+
+    fn queryable_rows(table: &WriterTable) -> usize {
+        table
+            .recordbatches_cloned("payments", &schema())
+            .unwrap_or_default()
+            .iter()
+            .map(RecordBatch::num_rows)
+            .sum()
+    }
+
+    #[test]
+    fn failed_atomic_appends_leave_no_rows() {
+        let table = WriterTable::default();
+        let result = table.append_atomic_with("payments", batches(), |batches| {
+            assert_eq!(batches.len(), 2);
+            Err(io::Error::other("disk full").into())
+        });
+        assert!(result.is_err());
+        assert_eq!(queryable_rows(&table), 0);
+
+        table
+            .append_atomic_with("payments", batches(), |_| Ok(()))
+            .unwrap();
+        assert_eq!(queryable_rows(&table), 3);
+    }
+}
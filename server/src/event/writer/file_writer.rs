@@ -21,7 +21,7 @@ use arrow_array::RecordBatch;
 use arrow_ipc::writer::StreamWriter;
 use derive_more::{Deref, DerefMut};
 use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
+use std::fs::{self, File, OpenOptions};
 use std::path::PathBuf;
 
 use super::errors::StreamWriterError;
@@ -100,3 +100,146 @@ fn init_new_stream_writer_file(
     stream_writer.write(record)?;
     Ok((path, stream_writer))
 }
+
+/// Staging files written for an atomic append. They are written under a
+/// temporary name that isn't picked up as staged data and only moved in place
+/// by [`AtomicFiles::commit`], files that aren't committed are removed on drop
+#[derive(Debug, Default)]
+pub struct AtomicFiles {
+    // (temporary, final) paths of the files written so far
+    written: Vec<(PathBuf, PathBuf)>,
+}
+
+impl AtomicFiles {
+    /// Writes `records` to a new file that becomes `path` once committed
+    pub fn write(
+        &mut self,
+        path: PathBuf,
+        records: &[&RecordBatch],
+    ) -> Result<(), StreamWriterError> {
+        let Some(first) = records.first() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp = path.with_extension("arrows.part");
+        self.written.push((temp.clone(), path));
+        let mut writer = StreamWriter::try_new(File::create(&temp)?, &first.schema())?;
+        for record in records {
+            writer.write(record)?;
+        }
+        writer.finish()?;
+        writer.into_inner()?.sync_all()?;
+        Ok(())
+    }
+
+    /// Moves the written files in place, when one of them can't be moved those
+    /// moved before it are removed again so that none of them is staged
+    pub fn commit(mut self) -> Result<(), StreamWriterError> {
+        let written = std::mem::take(&mut self.written);
+        for (index, (temp, path)) in written.iter().enumerate() {
+            if let Err(err) = fs::rename(temp, path) {
+                for (_, moved) in &written[..index] {
+                    _ = fs::remove_file(moved);
+                }
+                for (temp, _) in &written[index..] {
+                    _ = fs::remove_file(temp);
+                }
+                return Err(err.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AtomicFiles {
+    fn drop(&mut self) {
+        for (temp, _) in &self.written {
+            _ = fs::remove_file(temp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int64Array, RecordBatch};
+    use arrow_ipc::reader::StreamReader;
+    use arrow_schema::{DataType, Field, Schema};
+
+    use super::*;
+
+    fn batch(values: &[i64]) -> RecordBatch {
+        let schema = Schema::new(vec![Field::new("n", DataType::Int64, true)]);
+        RecordBatch::try_new(
+            Arc::new(schema),
+            vec![Arc::new(Int64Array::from(values.to_vec()))],
+        )
+        .unwrap()
+    }
+
+    fn staged(dir: &std::path::Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn rows(path: &std::path::Path) -> usize {
+        StreamReader::try_new(File::open(path).unwrap(), None)
+            .unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .sum()
+    }
+
+    #[test]
+    fn committed_files_are_moved_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let (one, two) = (batch(&[1, 2]), batch(&[3]));
+        let mut files = AtomicFiles::default();
+        files
+            .write(dir.path().join("a.data.arrows"), &[&one, &two])
+            .unwrap();
+        files
+            .write(dir.path().join("b.data.arrows"), &[&two])
+            .unwrap();
+        files.commit().unwrap();
+
+        assert_eq!(staged(dir.path()), ["a.data.arrows", "b.data.arrows"]);
+        assert_eq!(rows(&dir.path().join("a.data.arrows")), 3);
+        assert_eq!(rows(&dir.path().join("b.data.arrows")), 1);
+    }
+
+    #[test]
+    fn uncommitted_files_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = AtomicFiles::default();
+        files
+            .write(dir.path().join("a.data.arrows"), &[&batch(&[1])])
+            .unwrap();
+        assert_eq!(staged(dir.path()), ["a.data.arrows.part"]);
+        drop(files);
+        assert!(staged(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn failed_commits_leave_nothing_staged() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = AtomicFiles::default();
+        files
+            .write(dir.path().join("a.data.arrows"), &[&batch(&[1])])
+            .unwrap();
+        files
+            .write(dir.path().join("b.data.arrows"), &[&batch(&[2])])
+            .unwrap();
+        // the second file can't be moved once its temporary file is gone
+        fs::remove_file(dir.path().join("b.data.arrows.part")).unwrap();
+
+        assert!(files.commit().is_err());
+        assert!(staged(dir.path()).is_empty());
+    }
+}
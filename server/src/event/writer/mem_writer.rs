@@ -23,6 +23,7 @@ use arrow_schema::Schema;
 use arrow_select::concat::concat_batches;
 use itertools::Itertools;
 
+use crate::event::widening::merge_schemas;
 use crate::utils::arrow::adapt_batch;
 
 /// Structure to keep recordbatches in memory.
@@ -53,7 +54,7 @@ impl<const N: usize> MemWriter<N> {
     pub fn push(&mut self, schema_key: &str, rb: RecordBatch) {
         if !self.schema_map.contains(schema_key) {
             self.schema_map.insert(schema_key.to_owned());
-            self.schema = Schema::try_merge([self.schema.clone(), (*rb.schema()).clone()]).unwrap();
+            self.schema = merge_schemas([self.schema.clone(), (*rb.schema()).clone()]).unwrap();
         }
 
         if let Some(record) = self.mutable_buffer.push(rb) {
@@ -17,8 +17,16 @@
  */
 
 pub mod airplane;
+#[cfg(feature = "amqp")]
+pub mod amqp;
+pub mod file_input;
 pub mod http;
+#[cfg(feature = "kafka")]
+pub mod kafka;
 pub mod livetail;
+#[cfg(feature = "nats")]
+pub mod nats;
+pub mod syslog;
 
 const PREFIX_TAGS: &str = "x-p-tag-";
 const PREFIX_META: &str = "x-p-meta-";
@@ -26,11 +34,27 @@ const STREAM_NAME_HEADER_KEY: &str = "x-p-stream";
 const CACHE_RESULTS_HEADER_KEY: &str = "x-p-cache-results";
 const CACHE_VIEW_HEADER_KEY: &str = "x-p-show-cached";
 const USER_ID_HEADER_KEY: &str = "x-p-user-id";
+const RESULT_CACHE_HEADER_KEY: &str = "x-p-result-cache";
+const QUERY_ID_HEADER_KEY: &str = "x-p-query-id";
+const TIME_ZONE_HEADER_KEY: &str = "x-p-time-zone";
 const LOG_SOURCE_KEY: &str = "x-p-log-source";
 const TIME_PARTITION_KEY: &str = "x-p-time-partition";
 const TIME_PARTITION_LIMIT_KEY: &str = "x-p-time-partition-limit";
 const CUSTOM_PARTITION_KEY: &str = "x-p-custom-partition";
 const STATIC_SCHEMA_FLAG: &str = "x-p-static-schema-flag";
+const CSV_DELIMITER_KEY: &str = "x-p-csv-delimiter";
+const CSV_HEADER_KEY: &str = "x-p-csv-header";
+const CSV_COLUMNS_KEY: &str = "x-p-csv-columns";
+const CSV_NULL_KEY: &str = "x-p-csv-null";
+const CSV_TIMESTAMP_COLUMN_KEY: &str = "x-p-csv-timestamp-column";
+const CSV_TIMESTAMP_FORMAT_KEY: &str = "x-p-csv-timestamp-format";
+const PARTIAL_ACCEPT_KEY: &str = "x-p-partial-accept";
+const BINARY_ENCODING_KEY: &str = "x-p-binary-encoding";
+const PROTOBUF_DELIMITED_KEY: &str = "x-p-protobuf-delimited";
+const STRICT_KEY: &str = "x-p-strict";
+const IDEMPOTENCY_KEY: &str = "x-p-idempotency-key";
+const TIMESTAMP_FIELD_KEY: &str = "x-p-timestamp-field";
+const ATOMIC_KEY: &str = "x-p-atomic";
 const AUTHORIZATION_KEY: &str = "authorization";
 const SEPARATOR: char = '^';
 const UPDATE_STREAM_KEY: &str = "x-p-update-stream";
@@ -27,22 +27,23 @@ use std::net::SocketAddr;
 use std::time::Instant;
 use tonic::codec::CompressionEncoding;
 
-use futures_util::{Future, TryFutureExt};
-
-use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tokio::net::TcpListener;
+use tonic::transport::Server;
 use tonic_web::GrpcWebLayer;
 
 use crate::handlers::http::cluster::get_ingestor_info;
 
 use crate::handlers::{CACHE_RESULTS_HEADER_KEY, CACHE_VIEW_HEADER_KEY, USER_ID_HEADER_KEY};
 use crate::metrics::QUERY_EXECUTE_TIME;
-use crate::option::CONFIG;
+use crate::option::{Mode, CONFIG};
 
 use crate::handlers::livetail::cross_origin_config;
 
 use crate::handlers::http::query::{
     authorize_and_set_filter_tags, into_query, put_results_in_cache, update_schema_when_distributed,
 };
+use crate::query::concurrency::{QueryClass, QUERY_LIMITER};
+use crate::query::registry::CancelFlag;
 use crate::query::{TableScanVisitor, QUERY_SESSION};
 use crate::querycache::QueryCacheManager;
 use crate::utils::arrow::flight::{
@@ -62,6 +63,7 @@ use crate::handlers::livetail::extract_session_key;
 use crate::metadata::STREAM_INFO;
 use crate::rbac::Users;
 
+use super::http::modal::ssl_acceptor::{get_ssl_acceptor, grpc_incoming, spawn_reload};
 use super::http::query::get_results_from_cache;
 
 #[derive(Clone, Debug)]
@@ -236,9 +238,18 @@ impl FlightService for AirServiceImpl {
         authorize_and_set_filter_tags(&mut query, permissions, &stream_name).map_err(|_| {
             Status::permission_denied("User Does not have permission to access this")
         })?;
+        // on ingestors these are queriers fetching events not yet in object storage
+        let class = match CONFIG.parseable.mode {
+            Mode::Ingest => QueryClass::Internal,
+            _ => QueryClass::User,
+        };
+        let _permit = QUERY_LIMITER
+            .acquire(class)
+            .await
+            .map_err(|err| Status::resource_exhausted(err.to_string()))?;
         let time = Instant::now();
         let (records, _) = query
-            .execute(stream_name.clone())
+            .execute(stream_name.clone(), &CancelFlag::unregistered())
             .await
             .map_err(|err| Status::internal(err.to_string()))?;
 
@@ -319,13 +330,13 @@ impl FlightService for AirServiceImpl {
     }
 }
 
-pub fn server() -> impl Future<Output = Result<(), Box<dyn std::error::Error + Send>>> + Send {
+pub async fn server() -> Result<(), Box<dyn std::error::Error + Send>> {
     let mut addr: SocketAddr = CONFIG
         .parseable
-        .address
-        .parse()
-        .unwrap_or_else(|err| panic!("{}, failed to parse `{}` as a socket address. Please set the environment variable `P_ADDR` to `<ip address>:<port>` without the scheme (e.g., 192.168.1.1:8000). Please refer to the documentation: https://logg.ing/env for more details.",
-CONFIG.parseable.address, err));
+        .bind_addrs()
+        .map(|addrs| addrs[0])
+        .unwrap_or_else(|err| panic!("{}. Please set the environment variable `P_ADDR` to `<host>:<port>` without the scheme (e.g., 192.168.1.1:8000). Please refer to the documentation: https://logg.ing/env for more details.",
+err));
     addr.set_port(CONFIG.parseable.flight_port);
 
     let service = AirServiceImpl {};
@@ -338,51 +349,36 @@ CONFIG.parseable.address, err));
 
     let cors = cross_origin_config();
 
-    let identity = match (
-        &CONFIG.parseable.tls_cert_path,
-        &CONFIG.parseable.tls_key_path,
-    ) {
-        (Some(cert), Some(key)) => {
-            match (std::fs::read_to_string(cert), std::fs::read_to_string(key)) {
-                (Ok(cert_file), Ok(key_file)) => {
-                    let identity = Identity::from_pem(cert_file, key_file);
-                    Some(identity)
-                }
-                _ => None,
-            }
-        }
-        (_, _) => None,
-    };
-
-    let config = identity.map(|id| ServerTlsConfig::new().identity(id));
+    // the certificate is served through the resolver the HTTP server uses,
+    // so that it is reloaded along with it
+    let tls = get_ssl_acceptor(
+        CONFIG.parseable.tls_identity(),
+        CONFIG.parseable.tls_min_version,
+        &CONFIG.parseable.tls_cipher_suites,
+    )?;
 
     // rust is treating closures as different types
     let err_map_fn = |err| Box::new(err) as Box<dyn std::error::Error + Send>;
 
-    // match on config to decide if we want to use tls or not
-    match config {
-        Some(config) => {
-            let server = match Server::builder().tls_config(config) {
-                Ok(server) => server,
-                Err(_) => Server::builder(),
-            };
-
-            server
-                .max_frame_size(16 * 1024 * 1024 - 2)
-                .accept_http1(true)
-                .layer(cors)
-                .layer(GrpcWebLayer::new())
-                .add_service(svc)
-                .serve(addr)
+    let router = Server::builder()
+        .max_frame_size(16 * 1024 * 1024 - 2)
+        .accept_http1(true)
+        .layer(cors)
+        .layer(GrpcWebLayer::new())
+        .add_service(svc);
+
+    // match on tls to decide if we want to use tls or not
+    match tls {
+        Some((config, resolver)) => {
+            spawn_reload(resolver);
+            let listener = TcpListener::bind(addr)
+                .await
+                .map_err(|err| Box::new(err) as Box<dyn std::error::Error + Send>)?;
+            router
+                .serve_with_incoming(grpc_incoming(listener, config))
+                .await
                 .map_err(err_map_fn)
         }
-        None => Server::builder()
-            .max_frame_size(16 * 1024 * 1024 - 2)
-            .accept_http1(true)
-            .layer(cors)
-            .layer(GrpcWebLayer::new())
-            .add_service(svc)
-            .serve(addr)
-            .map_err(err_map_fn),
+        None => router.serve(addr).await.map_err(err_map_fn),
     }
 }
@@ -0,0 +1,416 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Tails local log files into streams. The inputs are read from a JSON file,
+//! each one tails the files matching its glob patterns into a stream
+//! [
+//!     {
+//!         "paths": ["/var/log/*.log"],
+//!         "stream": "varlog",
+//!         "multiline": {
+//!             "continuation": "^\\s",
+//!             "timeoutMs": 1000,
+//!             "maxLines": 500
+//!         },
+//!         "grok": { "pattern": "%{SYSLOGBASE} %{GREEDYDATA:text}" }
+//!     }
+//! ]
+
+mod positions;
+mod tailer;
+
+use std::{
+    collections::VecDeque,
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::time::sleep;
+
+use self::{
+    positions::Positions,
+    tailer::{Record, Tailer},
+};
+use super::http::ingest::{create_stream_if_not_exists, push_labelled_logs, PostError};
+use crate::{
+    cli::Cli,
+    event::format::grok::{GrokError, GrokParser, GrokSettings, DEFAULT_RAW_FIELD},
+    metadata::STREAM_INFO,
+    metrics::{FILE_INPUT_FILES, FILE_INPUT_RECORDS_INGESTED},
+    validator::{self, error::StreamNameValidationError},
+};
+
+/// File in the staging directory the positions of the tailed files are kept in
+const POSITIONS_FILE_NAME: &str = ".file_input_positions.json";
+
+/// How long the tailer waits for more lines once the files are read to the end
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Delay of the first retry of a failed ingest
+const BACKOFF_BASE: Duration = Duration::from_millis(100);
+
+/// Longest delay between retries
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+#[derive(Debug, thiserror::Error)]
+pub enum FileInputError {
+    #[error("Failed to read file input config {0}: {1}")]
+    Read(PathBuf, #[source] io::Error),
+    #[error("Invalid file input config: {0}")]
+    Config(#[from] serde_json::Error),
+    #[error("File input to {0} has no paths to tail")]
+    NoPaths(String),
+    #[error("File input path {0:?} is not a valid glob pattern: {1}")]
+    Pattern(String, #[source] glob::PatternError),
+    #[error("File input stream {0:?} is not a valid stream name: {1}")]
+    InvalidStream(String, #[source] StreamNameValidationError),
+    #[error("Multiline continuation of file input to {0} is not a valid regex: {1}")]
+    Continuation(String, #[source] regex::Error),
+    #[error("Multiline blocks of file input to {0} need at least one line")]
+    MaxLines(String),
+    #[error("Grok pattern of file input to {0} is not valid: {1}")]
+    Grok(String, #[source] GrokError),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct InputSettings {
+    paths: Vec<String>,
+    stream: String,
+    #[serde(default)]
+    multiline: Option<MultilineSettings>,
+    #[serde(default)]
+    grok: Option<GrokSettings>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct MultilineSettings {
+    continuation: String,
+    #[serde(default = "default_multiline_timeout")]
+    timeout_ms: u64,
+    #[serde(default = "default_max_lines")]
+    max_lines: usize,
+}
+
+fn default_multiline_timeout() -> u64 {
+    1000
+}
+
+fn default_max_lines() -> usize {
+    500
+}
+
+/// How the lines of a file are joined into records. A line matching the
+/// continuation is appended to the record before it, the record is complete
+/// once a line that doesn't match is read or none came for the timeout
+#[derive(Debug)]
+pub struct Multiline {
+    pub continuation: Regex,
+    pub timeout: Duration,
+    pub max_lines: usize,
+}
+
+/// Files tailed into a stream
+#[derive(Debug)]
+pub struct Input {
+    /// Glob patterns of the files
+    pub paths: Vec<String>,
+    pub stream: String,
+    pub multiline: Option<Multiline>,
+    /// Parses the records before they go through the stream's own pattern
+    pub grok: Option<GrokParser>,
+}
+
+impl Input {
+    fn new(settings: InputSettings) -> Result<Self, FileInputError> {
+        let stream = settings.stream;
+        validator::stream_name(&stream)
+            .map_err(|err| FileInputError::InvalidStream(stream.clone(), err))?;
+        if settings.paths.is_empty() {
+            return Err(FileInputError::NoPaths(stream));
+        }
+        for path in &settings.paths {
+            glob::Pattern::new(path).map_err(|err| FileInputError::Pattern(path.clone(), err))?;
+        }
+        let multiline = match settings.multiline {
+            Some(multiline) => {
+                if multiline.max_lines == 0 {
+                    return Err(FileInputError::MaxLines(stream));
+                }
+                let continuation = Regex::new(&multiline.continuation)
+                    .map_err(|err| FileInputError::Continuation(stream.clone(), err))?;
+                Some(Multiline {
+                    continuation,
+                    timeout: Duration::from_millis(multiline.timeout_ms),
+                    max_lines: multiline.max_lines,
+                })
+            }
+            None => None,
+        };
+        let grok = match settings.grok {
+            Some(grok) => Some(
+                GrokParser::new(grok).map_err(|err| FileInputError::Grok(stream.clone(), err))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            paths: settings.paths,
+            stream,
+            multiline,
+            grok,
+        })
+    }
+}
+
+/// The inputs of a config file's contents
+pub fn parse_inputs(config: &[u8]) -> Result<Vec<Input>, FileInputError> {
+    serde_json::from_slice::<Vec<InputSettings>>(config)?
+        .into_iter()
+        .map(Input::new)
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct FileInputConfig {
+    inputs: Vec<Input>,
+    positions_path: PathBuf,
+    scan_interval: Duration,
+    release_after: Duration,
+}
+
+impl FileInputConfig {
+    /// The file input settings of `cli`, none when it sets no config file.
+    /// Inputs that can't be used are rejected here so that the server fails
+    /// to start over them
+    pub fn from_cli(cli: &Cli) -> Result<Option<Self>, FileInputError> {
+        let Some(path) = &cli.file_input_config else {
+            return Ok(None);
+        };
+        let config = std::fs::read(path).map_err(|err| FileInputError::Read(path.clone(), err))?;
+        Ok(Some(Self {
+            inputs: parse_inputs(&config)?,
+            positions_path: cli.local_staging_path.join(POSITIONS_FILE_NAME),
+            scan_interval: Duration::from_secs(cli.file_input_scan_interval),
+            release_after: Duration::from_secs(cli.file_input_release_after),
+        }))
+    }
+}
+
+/// Delay before retry `attempts`, doubling up to [`BACKOFF_MAX`]
+fn backoff(attempts: usize) -> Duration {
+    BACKOFF_BASE
+        .saturating_mul(1u32 << attempts.min(16))
+        .min(BACKOFF_MAX)
+}
+
+/// Tails the files of the inputs until the server stops. The positions of the
+/// files are only saved once what was read is staged, so records are
+/// ingested at least once across restarts
+pub async fn server(config: FileInputConfig) {
+    let positions = match Positions::load(config.positions_path.clone()) {
+        Ok(positions) => positions,
+        Err(err) => {
+            log::error!("File input can't load its positions: {err}");
+            return;
+        }
+    };
+    let mut tailer = Tailer::new(config.inputs, positions, config.release_after);
+    let mut last_scan: Option<Instant> = None;
+    loop {
+        let now = Instant::now();
+        let scan = last_scan.map_or(true, |at| now.duration_since(at) >= config.scan_interval);
+        if scan {
+            last_scan = Some(now);
+        }
+        // the files are read off the runtime
+        let (returned, batch) = tokio::task::spawn_blocking(move || {
+            if scan {
+                tailer.scan(now);
+            }
+            let batch = tailer.poll(now);
+            (tailer, batch)
+        })
+        .await
+        .expect("file input tailer doesn't panic");
+        tailer = returned;
+        FILE_INPUT_FILES.set(tailer.tracked() as i64);
+
+        let mut pending = group(&batch.records);
+        let mut attempts = 0;
+        while let Some((input, path, texts)) = pending.front() {
+            let input = &tailer.inputs()[*input];
+            match ingest(input, path, texts).await {
+                Ok(()) => {
+                    FILE_INPUT_RECORDS_INGESTED
+                        .with_label_values(&[&input.stream])
+                        .inc_by(texts.len() as u64);
+                    pending.pop_front();
+                    attempts = 0;
+                }
+                Err(err) => {
+                    log::warn!(
+                        "File input failed to ingest {} into {}, retrying: {err}",
+                        path.display(),
+                        input.stream
+                    );
+                    sleep(backoff(attempts)).await;
+                    attempts += 1;
+                }
+            }
+        }
+        if let Err(err) = tailer.commit(&batch) {
+            log::error!("File input can't save its positions: {err}");
+        }
+        if batch.records.is_empty() {
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+// consecutive records of a file are ingested together
+fn group(records: &[Record]) -> VecDeque<(usize, &Path, Vec<&str>)> {
+    let mut groups: VecDeque<(usize, &Path, Vec<&str>)> = VecDeque::new();
+    for record in records {
+        match groups.back_mut() {
+            Some((input, path, texts)) if *input == record.input && *path == record.path => {
+                texts.push(record.text.as_str())
+            }
+            _ => groups.push_back((
+                record.input,
+                record.path.as_path(),
+                vec![record.text.as_str()],
+            )),
+        }
+    }
+    groups
+}
+
+async fn ingest(input: &Input, path: &Path, texts: &[&str]) -> Result<(), PostError> {
+    create_stream_if_not_exists(&input.stream, false).await?;
+    // the text goes where the stream's pattern looks for it when the input
+    // doesn't parse it itself
+    let field = match &input.grok {
+        Some(grok) => grok.settings().field.clone(),
+        None => STREAM_INFO
+            .get_grok(&input.stream)?
+            .map(|grok| grok.settings().field.clone())
+            .unwrap_or_else(|| DEFAULT_RAW_FIELD.to_owned()),
+    };
+    let mut events = Value::Array(
+        texts
+            .iter()
+            .map(|text| {
+                let mut event = Map::new();
+                event.insert(field.clone(), Value::String((*text).to_owned()));
+                Value::Object(event)
+            })
+            .collect(),
+    );
+    if let Some(grok) = &input.grok {
+        grok.apply(&mut events);
+    }
+    let body: Bytes = serde_json::to_vec(&events)?.into();
+    push_labelled_logs(
+        input.stream.clone(),
+        "",
+        &format!("file_path={}", path.display()),
+        body,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inputs_are_parsed() {
+        let inputs = parse_inputs(
+            br#"[
+                {"paths": ["/var/log/*.log"], "stream": "varlog"},
+                {
+                    "paths": ["/srv/app/**/*.log"],
+                    "stream": "app",
+                    "multiline": {"continuation": "^\\s"},
+                    "grok": {"pattern": "%{LOGLEVEL:level} %{GREEDYDATA:text}"}
+                }
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(inputs.len(), 2);
+        assert_eq!(inputs[0].stream, "varlog");
+        assert!(inputs[0].multiline.is_none() && inputs[0].grok.is_none());
+        let multiline = inputs[1].multiline.as_ref().unwrap();
+        assert!(multiline.continuation.is_match("\tat Main.main"));
+        assert_eq!(multiline.timeout, Duration::from_secs(1));
+        assert_eq!(multiline.max_lines, 500);
+        assert!(inputs[1].grok.is_some());
+    }
+
+    #[test]
+    fn unusable_inputs_are_rejected() {
+        let invalid = [
+            r#"[{"paths": [], "stream": "app"}]"#,
+            r#"[{"paths": ["/var/log/[.log"], "stream": "app"}]"#,
+            r#"[{"paths": ["/var/log/*.log"], "stream": "not a stream"}]"#,
+            r#"[{"paths": ["/var/log/*.log"], "stream": "app", "multiline": {"continuation": "("}}]"#,
+            r#"[{"paths": ["/var/log/*.log"], "stream": "app", "multiline": {"continuation": "^\\s", "maxLines": 0}}]"#,
+            r#"[{"paths": ["/var/log/*.log"], "stream": "app", "grok": {"pattern": "%{NOPE:x}"}}]"#,
+            r#"[{"path": "/var/log/*.log", "stream": "app"}]"#,
+        ];
+        for config in invalid {
+            assert!(parse_inputs(config.as_bytes()).is_err(), "{config}");
+        }
+    }
+
+    #[test]
+    fn records_are_grouped_by_file() {
+        let record = |input, path: &str, text: &str| Record {
+            input,
+            path: PathBuf::from(path),
+            text: text.to_owned(),
+        };
+        let records = [
+            record(0, "/a.log", "1"),
+            record(0, "/a.log", "2"),
+            record(0, "/b.log", "3"),
+            record(1, "/b.log", "4"),
+            record(0, "/a.log", "5"),
+        ];
+        let groups: Vec<_> = group(&records)
+            .into_iter()
+            .map(|(input, path, texts)| (input, path.to_str().unwrap(), texts))
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                (0, "/a.log", vec!["1", "2"]),
+                (0, "/b.log", vec!["3"]),
+                (1, "/b.log", vec!["4"]),
+                (0, "/a.log", vec!["5"]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,200 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Offsets the file input has ingested its files up to, kept in the staging
+//! directory so a restart resumes where the previous run left off
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, Metadata},
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Identity of a file that survives it being renamed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct FileId {
+    pub dev: u64,
+    pub ino: u64,
+}
+
+impl FileId {
+    #[cfg(unix)]
+    pub fn of(metadata: &Metadata, _path: &Path) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        }
+    }
+
+    // without inodes files are told apart by their path, a rotated file is
+    // then taken for a new one
+    #[cfg(not(unix))]
+    pub fn of(_metadata: &Metadata, path: &Path) -> Self {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        path.hash(&mut hasher);
+        Self {
+            dev: 0,
+            ino: hasher.finish(),
+        }
+    }
+}
+
+/// Where a file was last seen and the offset its lines were ingested up to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position {
+    pub path: PathBuf,
+    pub offset: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    id: FileId,
+    path: PathBuf,
+    offset: u64,
+}
+
+/// The positions of the tracked files, changes are kept in memory until saved
+#[derive(Debug)]
+pub struct Positions {
+    path: PathBuf,
+    entries: BTreeMap<FileId, Position>,
+    dirty: bool,
+}
+
+impl Positions {
+    /// Positions saved at `path`, none when the file doesn't exist yet. A file
+    /// that can't be parsed is ignored so the files are read from the start
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let entries = match fs::read(&path) {
+            Ok(bytes) => match serde_json::from_slice::<Vec<Entry>>(&bytes) {
+                Ok(entries) => entries
+                    .into_iter()
+                    .map(|entry| {
+                        let position = Position {
+                            path: entry.path,
+                            offset: entry.offset,
+                        };
+                        (entry.id, position)
+                    })
+                    .collect(),
+                Err(err) => {
+                    log::warn!(
+                        "File input positions in {} are ignored: {err}",
+                        path.display()
+                    );
+                    BTreeMap::new()
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            path,
+            entries,
+            dirty: false,
+        })
+    }
+
+    pub fn get(&self, id: &FileId) -> Option<&Position> {
+        self.entries.get(id)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &FileId> {
+        self.entries.keys()
+    }
+
+    pub fn set(&mut self, id: FileId, path: &Path, offset: u64) {
+        let position = Position {
+            path: path.to_owned(),
+            offset,
+        };
+        if self.entries.get(&id) != Some(&position) {
+            self.entries.insert(id, position);
+            self.dirty = true;
+        }
+    }
+
+    pub fn remove(&mut self, id: &FileId) {
+        self.dirty |= self.entries.remove(id).is_some();
+    }
+
+    /// Writes the positions when they changed since they were last saved, the
+    /// file is replaced whole so a crash leaves either the old or new positions
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let entries: Vec<Entry> = self
+            .entries
+            .iter()
+            .map(|(id, position)| Entry {
+                id: *id,
+                path: position.path.clone(),
+                offset: position.offset,
+            })
+            .collect();
+        let temp = self.path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_vec(&entries)?)?;
+        fs::rename(&temp, &self.path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_positions_load_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("positions.json");
+        let id = FileId { dev: 1, ino: 42 };
+
+        let mut positions = Positions::load(path.clone()).unwrap();
+        assert_eq!(positions.get(&id), None);
+        positions.set(id, Path::new("/var/log/app.log"), 128);
+        positions.save().unwrap();
+
+        let mut positions = Positions::load(path.clone()).unwrap();
+        assert_eq!(
+            positions.get(&id),
+            Some(&Position {
+                path: PathBuf::from("/var/log/app.log"),
+                offset: 128
+            })
+        );
+        positions.remove(&id);
+        positions.save().unwrap();
+        assert_eq!(Positions::load(path).unwrap().get(&id), None);
+    }
+
+    #[test]
+    fn corrupt_positions_start_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("positions.json");
+        fs::write(&path, b"{not json").unwrap();
+        assert_eq!(Positions::load(path).unwrap().ids().count(), 0);
+    }
+}
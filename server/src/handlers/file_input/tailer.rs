@@ -0,0 +1,638 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Reads the lines appended to the files matching the inputs' patterns,
+//! following them across rotations and joining multiline records
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use super::{
+    positions::{FileId, Positions},
+    Input,
+};
+
+/// Lines longer than this are split into several records
+const MAX_LINE_SIZE: usize = 1024 * 1024;
+
+/// Bytes read from a single file per poll, so one busy file doesn't hold
+/// up the others
+const MAX_READ_PER_POLL: u64 = 4 * 1024 * 1024;
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A line or joined multiline block read from a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Index of the input the file matched
+    pub input: usize,
+    pub path: PathBuf,
+    pub text: String,
+}
+
+/// Records read by a poll and the positions to save once they are ingested
+#[derive(Debug, Default)]
+pub struct Batch {
+    pub records: Vec<Record>,
+    offsets: BTreeMap<FileId, (PathBuf, u64)>,
+    released: Vec<FileId>,
+}
+
+// lines waiting for their continuations
+#[derive(Debug)]
+struct Block {
+    text: String,
+    lines: usize,
+    // offset after the block's last line
+    end: u64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+struct Tracked {
+    input: usize,
+    path: PathBuf,
+    file: File,
+    // offset the file was read up to
+    read: u64,
+    // offset up to which the file's records were handed out, the bytes after
+    // it are in `partial` or `block`
+    emitted: u64,
+    // the start of a line whose newline wasn't written yet
+    partial: Vec<u8>,
+    block: Option<Block>,
+    // when the file stopped matching the patterns, it was deleted or rotated
+    missing_since: Option<Instant>,
+}
+
+/// Tails the files of a set of inputs, see [`Tailer::scan`] and [`Tailer::poll`]
+#[derive(Debug)]
+pub struct Tailer {
+    inputs: Vec<Input>,
+    files: BTreeMap<FileId, Tracked>,
+    positions: Positions,
+    release_after: Duration,
+    scanned: bool,
+}
+
+impl Tailer {
+    /// Tails the files of `inputs` from the saved `positions`, files that no
+    /// longer match are read for `release_after` before they are let go
+    pub fn new(inputs: Vec<Input>, positions: Positions, release_after: Duration) -> Self {
+        Self {
+            inputs,
+            files: BTreeMap::new(),
+            positions,
+            release_after,
+            scanned: false,
+        }
+    }
+
+    pub fn inputs(&self) -> &[Input] {
+        &self.inputs
+    }
+
+    /// Number of files being tailed
+    pub fn tracked(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Matches the inputs' patterns against the file system. New files are
+    /// read from their saved position or else from the start, a file matched
+    /// by several inputs belongs to the first
+    pub fn scan(&mut self, now: Instant) {
+        let mut seen = HashSet::new();
+        for (index, input) in self.inputs.iter().enumerate() {
+            for pattern in &input.paths {
+                let paths = match glob::glob(pattern) {
+                    Ok(paths) => paths,
+                    Err(err) => {
+                        log::warn!("File input pattern {pattern} is skipped: {err}");
+                        continue;
+                    }
+                };
+                for path in paths.flatten() {
+                    let Ok(metadata) = fs::metadata(&path) else {
+                        continue;
+                    };
+                    if !metadata.is_file() {
+                        continue;
+                    }
+                    let id = FileId::of(&metadata, &path);
+                    if !seen.insert(id) {
+                        continue;
+                    }
+                    if let Some(tracked) = self.files.get_mut(&id) {
+                        tracked.path = path;
+                        tracked.missing_since = None;
+                        continue;
+                    }
+                    let offset = self
+                        .positions
+                        .get(&id)
+                        .map(|position| position.offset)
+                        .unwrap_or_default();
+                    match open(index, path.clone(), offset) {
+                        Ok(tracked) => {
+                            log::info!("File input tails {} from {offset}", path.display());
+                            self.files.insert(id, tracked);
+                        }
+                        Err(err) => log::warn!("File input can't open {}: {err}", path.display()),
+                    }
+                }
+            }
+        }
+
+        for (id, tracked) in self.files.iter_mut() {
+            if !seen.contains(id) && tracked.missing_since.is_none() {
+                tracked.missing_since = Some(now);
+            }
+        }
+        // files gone while the server was down won't be seen again
+        if !self.scanned {
+            let gone: Vec<FileId> = self
+                .positions
+                .ids()
+                .filter(|id| !seen.contains(*id))
+                .copied()
+                .collect();
+            for id in gone {
+                self.positions.remove(&id);
+            }
+            self.scanned = true;
+        }
+    }
+
+    /// Reads what was appended to the tracked files since the last poll.
+    /// Multiline blocks are handed out once a line that doesn't continue them
+    /// is read or they saw no new line for their timeout, files missing for
+    /// longer than the grace period are let go with whatever they had pending
+    pub fn poll(&mut self, now: Instant) -> Batch {
+        let mut batch = Batch::default();
+        for (id, tracked) in self.files.iter_mut() {
+            let input = &self.inputs[tracked.input];
+            if let Err(err) = read(input, tracked, now, &mut batch.records) {
+                log::warn!("File input can't read {}: {err}", tracked.path.display());
+            }
+            let timed_out = match (&input.multiline, &tracked.block) {
+                (Some(multiline), Some(block)) => {
+                    now.saturating_duration_since(block.updated) >= multiline.timeout
+                }
+                _ => false,
+            };
+            if timed_out {
+                flush_block(tracked, &mut batch.records);
+            }
+            batch
+                .offsets
+                .insert(*id, (tracked.path.clone(), tracked.emitted));
+        }
+
+        let expired: Vec<FileId> = self
+            .files
+            .iter()
+            .filter(|(_, tracked)| {
+                tracked
+                    .missing_since
+                    .is_some_and(|since| now.saturating_duration_since(since) >= self.release_after)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            let mut tracked = self.files.remove(&id).expect("expired file is tracked");
+            let input = &self.inputs[tracked.input];
+            finish(input, &mut tracked, now, &mut batch.records);
+            log::info!("File input released {}", tracked.path.display());
+            batch.offsets.remove(&id);
+            batch.released.push(id);
+        }
+        batch
+    }
+
+    /// Saves the positions reached by `batch` once its records are ingested
+    pub fn commit(&mut self, batch: &Batch) -> io::Result<()> {
+        for (id, (path, offset)) in &batch.offsets {
+            self.positions.set(*id, path, *offset);
+        }
+        for id in &batch.released {
+            self.positions.remove(id);
+        }
+        self.positions.save()
+    }
+}
+
+// opens a file at `offset`, from the start when the file is now shorter as it
+// was truncated in the meantime
+fn open(input: usize, path: PathBuf, offset: u64) -> io::Result<Tracked> {
+    let mut file = File::open(&path)?;
+    let offset = if file.metadata()?.len() < offset {
+        0
+    } else {
+        offset
+    };
+    file.seek(SeekFrom::Start(offset))?;
+    Ok(Tracked {
+        input,
+        path,
+        file,
+        read: offset,
+        emitted: offset,
+        partial: Vec::new(),
+        block: None,
+        missing_since: None,
+    })
+}
+
+fn read(
+    input: &Input,
+    tracked: &mut Tracked,
+    now: Instant,
+    records: &mut Vec<Record>,
+) -> io::Result<()> {
+    let len = tracked.file.metadata()?.len();
+    // truncated in place, as copytruncate does. What was pending was copied
+    // to the rotated file so it is handed out as it is
+    if len < tracked.read {
+        log::info!(
+            "File input reads truncated {} from the start",
+            tracked.path.display()
+        );
+        finish(input, tracked, now, records);
+        tracked.file.seek(SeekFrom::Start(0))?;
+        tracked.read = 0;
+        tracked.emitted = 0;
+    }
+
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    let mut budget = MAX_READ_PER_POLL;
+    while budget > 0 {
+        let size = tracked.file.read(&mut buffer)?;
+        if size == 0 {
+            break;
+        }
+        budget = budget.saturating_sub(size as u64);
+        let mut start = 0;
+        for (position, byte) in buffer[..size].iter().enumerate() {
+            if *byte != b'\n' {
+                continue;
+            }
+            tracked.partial.extend_from_slice(&buffer[start..position]);
+            start = position + 1;
+            let end = tracked.read + start as u64;
+            let line = std::mem::take(&mut tracked.partial);
+            push_line(input, tracked, &line, end, now, records);
+        }
+        tracked.partial.extend_from_slice(&buffer[start..size]);
+        tracked.read += size as u64;
+        if tracked.partial.len() >= MAX_LINE_SIZE {
+            let line = std::mem::take(&mut tracked.partial);
+            let end = tracked.read;
+            push_line(input, tracked, &line, end, now, records);
+        }
+    }
+    Ok(())
+}
+
+// hands out the partial line and pending block of a file that won't be read
+// from where it is anymore
+fn finish(input: &Input, tracked: &mut Tracked, now: Instant, records: &mut Vec<Record>) {
+    if !tracked.partial.is_empty() {
+        let line = std::mem::take(&mut tracked.partial);
+        let end = tracked.read;
+        push_line(input, tracked, &line, end, now, records);
+    }
+    flush_block(tracked, records);
+}
+
+// a line ending at `end`, either a record of its own or part of a block
+fn push_line(
+    input: &Input,
+    tracked: &mut Tracked,
+    line: &[u8],
+    end: u64,
+    now: Instant,
+    records: &mut Vec<Record>,
+) {
+    let text = String::from_utf8_lossy(line);
+    let text = text.trim_end_matches('\r');
+    let Some(multiline) = &input.multiline else {
+        if !text.trim().is_empty() {
+            records.push(record(tracked, text.to_owned()));
+        }
+        tracked.emitted = end;
+        return;
+    };
+
+    if let Some(block) = tracked.block.as_mut() {
+        if block.lines < multiline.max_lines && multiline.continuation.is_match(text) {
+            block.text.push('\n');
+            block.text.push_str(text);
+            block.lines += 1;
+            block.end = end;
+            block.updated = now;
+            return;
+        }
+    }
+    flush_block(tracked, records);
+    if text.trim().is_empty() {
+        tracked.emitted = end;
+    } else {
+        tracked.block = Some(Block {
+            text: text.to_owned(),
+            lines: 1,
+            end,
+            updated: now,
+        });
+    }
+}
+
+fn flush_block(tracked: &mut Tracked, records: &mut Vec<Record>) {
+    if let Some(block) = tracked.block.take() {
+        records.push(record(tracked, block.text));
+        tracked.emitted = block.end;
+    }
+}
+
+fn record(tracked: &Tracked, text: String) -> Record {
+    Record {
+        input: tracked.input,
+        path: tracked.path.clone(),
+        text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, io::Write, path::Path};
+
+    use regex::Regex;
+
+    use super::*;
+    use crate::handlers::file_input::Multiline;
+
+    const GRACE: Duration = Duration::from_secs(60);
+
+    fn input(dir: &Path, multiline: Option<Multiline>) -> Input {
+        Input {
+            paths: vec![dir.join("*.log").to_string_lossy().into_owned()],
+            stream: "app".to_owned(),
+            multiline,
+            grok: None,
+        }
+    }
+
+    fn stack_traces() -> Option<Multiline> {
+        Some(Multiline {
+            continuation: Regex::new(r"^\s+(at |\.\.\.)|^Caused by:").unwrap(),
+            timeout: Duration::from_secs(1),
+            max_lines: 100,
+        })
+    }
+
+    fn tailer(dir: &Path, multiline: Option<Multiline>) -> Tailer {
+        let positions = Positions::load(dir.join("positions.json")).unwrap();
+        Tailer::new(vec![input(dir, multiline)], positions, GRACE)
+    }
+
+    fn append(path: &Path, text: &str) {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(text.as_bytes())
+            .unwrap();
+    }
+
+    // scans, polls and commits, returning the texts read
+    fn step(tailer: &mut Tailer, now: Instant) -> Vec<String> {
+        tailer.scan(now);
+        let batch = tailer.poll(now);
+        tailer.commit(&batch).unwrap();
+        batch
+            .records
+            .into_iter()
+            .map(|record| record.text)
+            .collect()
+    }
+
+    #[test]
+    fn lines_are_read_as_they_are_appended() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "first\n\nsecond\r\nthi");
+        let mut tailer = tailer(dir.path(), None);
+        let now = Instant::now();
+
+        assert_eq!(step(&mut tailer, now), ["first", "second"]);
+        append(&log, "rd\n");
+        assert_eq!(step(&mut tailer, now), ["third"]);
+        assert!(step(&mut tailer, now).is_empty());
+    }
+
+    #[test]
+    fn restart_resumes_from_the_saved_position() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "one\ntwo\npart");
+        let now = Instant::now();
+
+        let mut first = tailer(dir.path(), None);
+        assert_eq!(step(&mut first, now), ["one", "two"]);
+        drop(first);
+
+        append(&log, "ial\nthree\n");
+        let mut second = tailer(dir.path(), None);
+        assert_eq!(step(&mut second, now), ["partial", "three"]);
+    }
+
+    #[test]
+    fn uncommitted_records_are_read_again_after_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "one\n");
+        let now = Instant::now();
+
+        let mut first = tailer(dir.path(), None);
+        assert_eq!(step(&mut first, now), ["one"]);
+        append(&log, "two\n");
+        first.scan(now);
+        assert_eq!(first.poll(now).records.len(), 1);
+        drop(first);
+
+        let mut second = tailer(dir.path(), None);
+        assert_eq!(step(&mut second, now), ["two"]);
+    }
+
+    #[test]
+    fn renamed_files_are_drained_and_the_new_file_read_from_the_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "before\n");
+        let mut tailer = tailer(dir.path(), None);
+        let now = Instant::now();
+        assert_eq!(step(&mut tailer, now), ["before"]);
+
+        // the writer still has the rotated file open for a while
+        let rotated = dir.path().join("app.log.1");
+        fs::rename(&log, &rotated).unwrap();
+        append(&rotated, "late\n");
+        append(&log, "after\n");
+        let mut texts = step(&mut tailer, now);
+        texts.sort();
+        assert_eq!(texts, ["after", "late"]);
+        assert_eq!(tailer.tracked(), 2);
+
+        append(&rotated, "last\n");
+        assert_eq!(step(&mut tailer, now + GRACE), ["last"]);
+        assert_eq!(tailer.tracked(), 1);
+    }
+
+    #[test]
+    fn truncated_files_are_read_from_the_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "a long line before the rotation\n");
+        let mut tailer = tailer(dir.path(), None);
+        let now = Instant::now();
+        assert_eq!(step(&mut tailer, now), ["a long line before the rotation"]);
+
+        // copytruncate copies the file away and truncates it in place
+        File::create(&log).unwrap();
+        append(&log, "after\n");
+        assert_eq!(step(&mut tailer, now), ["after"]);
+        append(&log, "more\n");
+        assert_eq!(step(&mut tailer, now), ["more"]);
+    }
+
+    #[test]
+    fn files_truncated_while_stopped_are_read_from_the_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "a long line before the rotation\n");
+        let now = Instant::now();
+        let mut first = tailer(dir.path(), None);
+        step(&mut first, now);
+        drop(first);
+
+        File::create(&log).unwrap();
+        append(&log, "after\n");
+        let mut second = tailer(dir.path(), None);
+        assert_eq!(step(&mut second, now), ["after"]);
+    }
+
+    #[test]
+    fn stack_traces_are_joined() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        append(
+            &log,
+            "INFO started\n\
+             ERROR request failed\n\
+             java.lang.IllegalStateException: boom\n",
+        );
+        append(
+            &log,
+            "\tat com.example.Handler.handle(Handler.java:42)\n\
+             \tat com.example.Server.run(Server.java:7)\n\
+             Caused by: java.io.IOException: closed\n\
+             \t... 2 more\n\
+             INFO recovered\n",
+        );
+        let mut tailer = tailer(dir.path(), stack_traces());
+        let now = Instant::now();
+
+        assert_eq!(
+            step(&mut tailer, now),
+            [
+                "INFO started",
+                "ERROR request failed",
+                "java.lang.IllegalStateException: boom\n\
+                 \tat com.example.Handler.handle(Handler.java:42)\n\
+                 \tat com.example.Server.run(Server.java:7)\n\
+                 Caused by: java.io.IOException: closed\n\
+                 \t... 2 more",
+            ]
+        );
+        // the last block waits for continuations until its timeout
+        assert!(step(&mut tailer, now).is_empty());
+        assert_eq!(
+            step(&mut tailer, now + Duration::from_secs(1)),
+            ["INFO recovered"]
+        );
+    }
+
+    #[test]
+    fn pending_blocks_are_read_again_after_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "INFO one\nERROR two\n");
+        let now = Instant::now();
+
+        let mut first = tailer(dir.path(), stack_traces());
+        assert_eq!(step(&mut first, now), ["INFO one"]);
+        drop(first);
+
+        append(&log, "\tat com.example.Main.main(Main.java:1)\n");
+        let mut second = tailer(dir.path(), stack_traces());
+        assert!(step(&mut second, now).is_empty());
+        assert_eq!(
+            step(&mut second, now + Duration::from_secs(1)),
+            ["ERROR two\n\tat com.example.Main.main(Main.java:1)"]
+        );
+    }
+
+    #[test]
+    fn blocks_are_capped() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "ERROR\n\tat a\n\tat b\n\tat c\n");
+        let mut multiline = stack_traces().unwrap();
+        multiline.max_lines = 2;
+        let mut tailer = tailer(dir.path(), Some(multiline));
+        let now = Instant::now();
+
+        assert_eq!(step(&mut tailer, now), ["ERROR\n\tat a"]);
+        assert_eq!(
+            step(&mut tailer, now + Duration::from_secs(1)),
+            ["\tat b\n\tat c"]
+        );
+    }
+
+    #[test]
+    fn deleted_files_are_released_after_the_grace_period() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("app.log");
+        append(&log, "one\nunterminated");
+        let mut tailer = tailer(dir.path(), None);
+        let now = Instant::now();
+        assert_eq!(step(&mut tailer, now), ["one"]);
+
+        fs::remove_file(&log).unwrap();
+        assert!(step(&mut tailer, now).is_empty());
+        assert_eq!(tailer.tracked(), 1);
+        assert_eq!(step(&mut tailer, now + GRACE), ["unterminated"]);
+        assert_eq!(tailer.tracked(), 0);
+        assert_eq!(tailer.positions.ids().count(), 0);
+    }
+}
@@ -28,6 +28,8 @@ use self::{cluster::get_ingestor_info, query::Query};
 pub(crate) mod about;
 mod cache;
 pub mod cluster;
+pub(crate) mod connectors;
+pub(crate) mod dead_letter;
 pub(crate) mod health_check;
 pub(crate) mod ingest;
 mod kinesis;
@@ -35,11 +37,14 @@ pub(crate) mod llm;
 pub(crate) mod logstream;
 pub(crate) mod middleware;
 pub mod modal;
+pub(crate) mod msgpack;
+mod ndjson;
 pub(crate) mod oidc;
 mod otel;
 pub(crate) mod query;
 pub(crate) mod rbac;
 pub(crate) mod role;
+mod scheduled;
 pub mod users;
 pub const MAX_EVENT_PAYLOAD_SIZE: usize = 10485760;
 pub const API_BASE_PATH: &str = "api";
@@ -55,9 +60,31 @@ pub fn metrics_path() -> String {
 
 pub(crate) fn cross_origin_config() -> Cors {
     if cfg!(feature = "debug") {
-        Cors::permissive().block_on_origin_mismatch(false)
-    } else {
+        return Cors::permissive().block_on_origin_mismatch(false);
+    }
+
+    cors_config(CONFIG.parseable.cors, &CONFIG.parseable.cors_origins)
+}
+
+/// CORS middleware for the given settings.
+/// With an explicit list of origins only those origins are echoed back, requests
+/// from any other origin are still served but get no CORS headers, so browsers
+/// keep their responses from the calling page. Otherwise `enabled` toggles
+/// between allowing no origin and allowing all of them.
+fn cors_config(enabled: bool, origins: &[String]) -> Cors {
+    if !origins.is_empty() {
+        return origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+            .allow_any_method()
+            .allow_any_header()
+            .block_on_origin_mismatch(false);
+    }
+
+    if enabled {
         Cors::default().block_on_origin_mismatch(false)
+    } else {
+        Cors::permissive().block_on_origin_mismatch(false)
     }
 }
 
@@ -130,3 +157,96 @@ pub async fn send_query_request_to_ingestor(query: &Query) -> anyhow::Result<Vec
 
     Ok(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use actix_web::{
+        http::{header, StatusCode},
+        test, web, App, HttpResponse,
+    };
+    use tower::{service_fn, Layer, ServiceExt};
+
+    use super::cors_config;
+    use crate::handlers::livetail::cors_layer;
+
+    #[actix_web::test]
+    async fn cors_allows_listed_origin_only() {
+        let origins = vec!["https://allowed.example.com".to_string()];
+        let app = test::init_service(
+            App::new()
+                .wrap(cors_config(true, &origins))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .insert_header((header::ORIGIN, "https://allowed.example.com"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .unwrap(),
+            "https://allowed.example.com"
+        );
+        assert!(res
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+            .is_none());
+
+        // requests without an origin, like the server's own console, are unaffected
+        let req = test::TestRequest::get().uri("/").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .insert_header((header::ORIGIN, "https://denied.example.com"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        // served, but without the headers a browser needs to share it
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+
+        // the gRPC-web endpoints allow the same origins
+        let grpc = cors_layer(true, &origins).layer(service_fn(|_: ::http::Request<()>| async {
+            Ok::<_, Infallible>(::http::Response::new(()))
+        }));
+        let request = |origin: &str| {
+            ::http::Request::get("/")
+                .header(::http::header::ORIGIN, origin)
+                .body(())
+                .unwrap()
+        };
+        let res = grpc
+            .clone()
+            .oneshot(request("https://allowed.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(
+            res.headers()
+                .get(::http::header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .unwrap(),
+            "https://allowed.example.com"
+        );
+        let res = grpc
+            .oneshot(request("https://denied.example.com"))
+            .await
+            .unwrap();
+        assert!(res
+            .headers()
+            .get(::http::header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+        assert!(res
+            .headers()
+            .get(::http::header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+            .is_none());
+    }
+}
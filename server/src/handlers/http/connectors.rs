@@ -0,0 +1,60 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use actix_web::web::Json;
+use serde_json::{Map, Value};
+
+/// Health of the connectors the server consumes from, keyed by connector.
+/// Connectors that aren't running are left out
+/// {
+///     "nats": {
+///         "servers": servers,
+///         "stream": jetstream_stream,
+///         "consumer": durable_consumer,
+///         "connected": is_connected,
+///         "since": last_connected_or_disconnected,
+///         "reconnects": times_the_connection_was_lost,
+///         "lastError": last_error
+///     },
+///     "amqp": {
+///         "queues": queues,
+///         "connected": is_connected,
+///         "since": last_connected_or_disconnected,
+///         "reconnects": times_the_connection_was_lost,
+///         "lastError": last_error
+///     }
+/// }
+pub async fn status() -> Json<Value> {
+    #[allow(unused_mut)]
+    let mut connectors = Map::new();
+    #[cfg(feature = "nats")]
+    if let Some(status) = crate::handlers::nats::status() {
+        connectors.insert(
+            "nats".to_owned(),
+            serde_json::to_value(status).expect("status serializes"),
+        );
+    }
+    #[cfg(feature = "amqp")]
+    if let Some(status) = crate::handlers::amqp::status() {
+        connectors.insert(
+            "amqp".to_owned(),
+            serde_json::to_value(status).expect("status serializes"),
+        );
+    }
+    Json(Value::Object(connectors))
+}
@@ -0,0 +1,306 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Events a stream rejects are kept in its dead letter stream, one row per
+//! event with the stream it was sent to, why it was rejected and the event as
+//! it was received, so that they can be looked into and replayed later on
+
+use crate::handlers::http::ingest::{push_labelled_logs, PostError};
+use crate::handlers::http::query::{parse_human_time, update_schema_when_distributed, QueryError};
+use crate::metadata::{error::stream_info::MetadataError, STREAM_INFO};
+use crate::option::CONFIG;
+use crate::query::{
+    concurrency::{QueryClass, QUERY_LIMITER},
+    registry::CancelFlag,
+    Query, QUERY_SESSION,
+};
+use actix_web::{http::header::ContentType, web, HttpRequest, Responder};
+use arrow_array::cast::AsArray;
+use arrow_array::{Array, RecordBatch};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use http::StatusCode;
+use serde_json::{json, Value};
+
+pub const SOURCE_STREAM_KEY: &str = "source_stream";
+pub const REASON_KEY: &str = "reason";
+pub const ERROR_KEY: &str = "error";
+pub const REJECTED_AT_KEY: &str = "rejected_at";
+pub const PAYLOAD_KEY: &str = "payload";
+pub const PAYLOAD_TRUNCATED_KEY: &str = "payload_truncated";
+
+/// Bytes of a rejected event kept in the dead letter stream, longer events
+/// are cut short and can't be replayed
+pub const MAX_PAYLOAD_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RejectionReason {
+    /// The event could not be flattened or lacks its partition fields
+    Flattening,
+    /// The event does not fit the schema of the stream
+    Schema,
+    /// The event time could not be read or is out of bounds
+    Timestamp,
+}
+
+impl RejectionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectionReason::Flattening => "flattening",
+            RejectionReason::Schema => "schema",
+            RejectionReason::Timestamp => "timestamp",
+        }
+    }
+}
+
+/// An event rejected by a stream
+#[derive(Debug, Clone)]
+pub struct Rejection {
+    pub event: Value,
+    pub reason: RejectionReason,
+    pub error: String,
+}
+
+impl Rejection {
+    pub fn new(event: Value, reason: RejectionReason, error: &impl ToString) -> Self {
+        Self {
+            event,
+            reason,
+            error: error.to_string(),
+        }
+    }
+
+    /// The row the rejected event is kept as in the dead letter stream
+    pub fn into_record(self, stream_name: &str, rejected_at: DateTime<Utc>) -> Value {
+        let mut payload = self.event.to_string();
+        let truncated = payload.len() > MAX_PAYLOAD_SIZE;
+        if truncated {
+            let mut end = MAX_PAYLOAD_SIZE;
+            while !payload.is_char_boundary(end) {
+                end -= 1;
+            }
+            payload.truncate(end);
+        }
+        json!({
+            SOURCE_STREAM_KEY: stream_name,
+            REASON_KEY: self.reason.as_str(),
+            ERROR_KEY: self.error,
+            REJECTED_AT_KEY: rejected_at.to_rfc3339(),
+            PAYLOAD_KEY: payload,
+            PAYLOAD_TRUNCATED_KEY: truncated,
+        })
+    }
+}
+
+/// The dead letter stream of `stream_name`, the one set for the stream or else
+/// the one set for the server. A stream is never its own dead letter stream
+pub fn target(stream_name: &str) -> Result<Option<String>, MetadataError> {
+    let target = STREAM_INFO
+        .get_dead_letter_stream(stream_name)?
+        .or_else(|| CONFIG.parseable.dead_letter_stream.clone());
+    Ok(target.filter(|target| target != stream_name))
+}
+
+/// Time range of the dead lettered events to replay, in the same format as queries
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayRequest {
+    start_time: String,
+    end_time: String,
+}
+
+// Handler for POST /api/v1/logstream/{logstream}/dead-letter/replay
+// ingests the events the stream rejected in the time range again, they are
+// not removed from the dead letter stream so a range should be replayed once
+pub async fn replay(
+    req: HttpRequest,
+    body: web::Json<ReplayRequest>,
+) -> Result<impl Responder, DeadLetterError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(DeadLetterError::StreamNotFound(stream_name));
+    }
+    let target =
+        target(&stream_name)?.ok_or_else(|| DeadLetterError::NotSet(stream_name.clone()))?;
+    if !STREAM_INFO.stream_exists(&target) {
+        // nothing was dead lettered yet
+        return Ok(web::Json(json!({ "replayed": 0, "skipped": 0 })));
+    }
+
+    let (start, end) = parse_human_time(&body.start_time, &body.end_time)?;
+    update_schema_when_distributed(vec![target.clone()]).await?;
+    let sql = format!(
+        "SELECT {PAYLOAD_KEY}, {PAYLOAD_TRUNCATED_KEY} FROM \"{target}\" WHERE {SOURCE_STREAM_KEY} = '{}'",
+        stream_name.replace('\'', "''")
+    );
+    let query = Query {
+        raw_logical_plan: QUERY_SESSION
+            .state()
+            .create_logical_plan(&sql)
+            .await
+            .map_err(QueryError::from)?,
+        start,
+        end,
+        filter_tag: None,
+    };
+    let batches = {
+        let _permit = QUERY_LIMITER
+            .acquire(QueryClass::Internal)
+            .await
+            .map_err(QueryError::from)?;
+        let (batches, _) = query
+            .execute(target, &CancelFlag::unregistered())
+            .await
+            .map_err(QueryError::from)?;
+        batches
+    };
+
+    let (events, skipped) = replayable_events(&batches);
+    let replayed = events.len();
+    if !events.is_empty() {
+        let body = Bytes::from(Value::Array(events).to_string());
+        push_labelled_logs(stream_name, "", "", body).await?;
+    }
+    Ok(web::Json(
+        json!({ "replayed": replayed, "skipped": skipped }),
+    ))
+}
+
+// the events of dead lettered rows and the number of rows that can't be
+// replayed, as their payload was cut short or is missing
+fn replayable_events(batches: &[RecordBatch]) -> (Vec<Value>, usize) {
+    let mut events = Vec::new();
+    let mut skipped = 0;
+    for batch in batches {
+        let Some(payloads) = batch.column_by_name(PAYLOAD_KEY) else {
+            skipped += batch.num_rows();
+            continue;
+        };
+        let payloads = payloads.as_string::<i32>();
+        let truncated = batch
+            .column_by_name(PAYLOAD_TRUNCATED_KEY)
+            .map(|column| column.as_boolean());
+        for row in 0..batch.num_rows() {
+            let is_truncated = truncated
+                .map(|truncated| truncated.is_valid(row) && truncated.value(row))
+                .unwrap_or(false);
+            let event = (payloads.is_valid(row) && !is_truncated)
+                .then(|| serde_json::from_str::<Value>(payloads.value(row)).ok())
+                .flatten();
+            match event {
+                Some(event) => events.push(event),
+                None => skipped += 1,
+            }
+        }
+    }
+    (events, skipped)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeadLetterError {
+    #[error("Stream {0} not found")]
+    StreamNotFound(String),
+    #[error("Stream {0} has no dead letter stream")]
+    NotSet(String),
+    #[error("Error: {0}")]
+    Metadata(#[from] MetadataError),
+    #[error("{0}")]
+    Query(#[from] QueryError),
+    #[error("Could not replay events: {0}")]
+    Ingest(#[from] PostError),
+}
+
+impl actix_web::ResponseError for DeadLetterError {
+    fn status_code(&self) -> http::StatusCode {
+        match self {
+            Self::StreamNotFound(_) => StatusCode::NOT_FOUND,
+            Self::NotSet(_) => StatusCode::BAD_REQUEST,
+            Self::Metadata(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Query(err) => err.status_code(),
+            Self::Ingest(err) => err.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
+        actix_web::HttpResponse::build(self.status_code())
+            .insert_header(ContentType::plaintext())
+            .body(self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use arrow_schema::Field;
+    use chrono::Utc;
+    use datafusion::datasource::MemTable;
+    use datafusion::prelude::SessionContext;
+    use serde_json::{json, Value};
+
+    use super::{replayable_events, Rejection, RejectionReason, MAX_PAYLOAD_SIZE};
+    use crate::event::format::{json, EventFormat};
+
+    fn into_batch(
+        data: Value,
+        schema: HashMap<String, Arc<Field>>,
+    ) -> anyhow::Result<arrow_array::RecordBatch> {
+        let event = json::Event {
+            data,
+            tags: String::default(),
+            metadata: String::default(),
+        };
+        event.into_recordbatch(schema, None, None).map(|(rb, _)| rb)
+    }
+
+    #[test]
+    fn long_payloads_are_truncated() {
+        let event = json!({ "message": "é".repeat(MAX_PAYLOAD_SIZE) });
+        let record = Rejection::new(event, RejectionReason::Flattening, &"too deep")
+            .into_record("app", Utc::now());
+
+        let payload = record["payload"].as_str().unwrap();
+        assert!(payload.len() <= MAX_PAYLOAD_SIZE);
+        assert_eq!(record["payload_truncated"], json!(true));
+        assert_eq!(record["reason"], json!("flattening"));
+    }
+
+    #[tokio::test]
+    async fn dead_lettered_events_are_replayed_as_received() {
+        let event = json!({ "status": "ok" });
+        let record = Rejection::new(event.clone(), RejectionReason::Schema, &"mismatch")
+            .into_record("app", Utc::now());
+        let rb = into_batch(record, HashMap::new()).unwrap();
+
+        let ctx = SessionContext::new();
+        let table = MemTable::try_new(rb.schema(), vec![vec![rb]]).unwrap();
+        ctx.register_table("deadletter", Arc::new(table)).unwrap();
+        let batches = ctx
+            .sql("SELECT payload, payload_truncated FROM deadletter WHERE source_stream = 'app'")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+
+        let (events, skipped) = replayable_events(&batches);
+        assert_eq!(events, vec![event]);
+        assert_eq!(skipped, 0);
+    }
+}
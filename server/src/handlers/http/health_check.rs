@@ -20,15 +20,66 @@ use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 
 use crate::option::CONFIG;
+use crate::storage::ObjectStorage;
 
 pub async fn liveness() -> HttpResponse {
     HttpResponse::new(StatusCode::OK)
 }
 
 pub async fn readiness() -> HttpResponse {
-    if CONFIG.storage().get_object_store().check().await.is_ok() {
+    storage_readiness(&*CONFIG.storage().get_object_store()).await
+}
+
+async fn storage_readiness(storage: &(dyn ObjectStorage + Send)) -> HttpResponse {
+    if storage.check().await.is_ok() {
         return HttpResponse::new(StatusCode::OK);
     }
 
     HttpResponse::new(StatusCode::SERVICE_UNAVAILABLE)
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, web, App};
+
+    use crate::storage::{localfs::LocalFS, StorePermits};
+
+    use super::{liveness, storage_readiness};
+
+    #[actix_web::test]
+    async fn liveness_is_always_ok() {
+        let app = test::init_service(
+            App::new().service(
+                web::resource("/healthz")
+                    .route(web::get().to(liveness))
+                    .route(web::head().to(liveness)),
+            ),
+        )
+        .await;
+
+        for req in [
+            test::TestRequest::get().uri("/healthz"),
+            test::TestRequest::default()
+                .method(actix_web::http::Method::HEAD)
+                .uri("/healthz"),
+        ] {
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+    }
+
+    #[actix_web::test]
+    async fn readiness_follows_storage_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let healthy = LocalFS::with_permits(dir.path().join("data"), StorePermits::new(1));
+        let resp = storage_readiness(&healthy).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // storage root below a regular file can never be created
+        let blocker = dir.path().join("blocker");
+        std::fs::write(&blocker, b"").unwrap();
+        let unhealthy = LocalFS::with_permits(blocker.join("data"), StorePermits::new(1));
+        let resp = storage_readiness(&unhealthy).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
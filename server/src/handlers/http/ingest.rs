@@ -17,39 +17,59 @@
  */
 
 use super::cluster::INTERNAL_STREAM_NAME;
+use super::dead_letter::{self, Rejection, RejectionReason};
 use super::logstream::error::CreateStreamError;
+use super::msgpack::{self, BinaryEncoding, MsgpackError};
+use super::ndjson::{self, NdjsonError, StreamLimits};
 use super::users::dashboards::DashboardError;
 use super::users::filters::FiltersError;
 use super::{kinesis, otel};
 use crate::event::{
     self,
     error::EventError,
-    format::{self, EventFormat},
+    format::{
+        self,
+        csv::{CsvOptions, RejectedRows, RowError, MAX_REPORTED_ROW_ERRORS},
+        grok::{self, DEFAULT_RAW_FIELD},
+        protobuf::ProtobufError,
+        EventFormat,
+    },
+    timestamp::{self, TimestampError, TimestampExtraction, TimestampSource},
+    widening, DEFAULT_TIMESTAMP_SOURCE_KEY,
 };
 use crate::handlers::{
-    LOG_SOURCE_KEY, LOG_SOURCE_KINESIS, LOG_SOURCE_OTEL, PREFIX_META, PREFIX_TAGS, SEPARATOR,
-    STREAM_NAME_HEADER_KEY,
+    ATOMIC_KEY, BINARY_ENCODING_KEY, CSV_COLUMNS_KEY, CSV_DELIMITER_KEY, CSV_HEADER_KEY,
+    CSV_NULL_KEY, CSV_TIMESTAMP_COLUMN_KEY, CSV_TIMESTAMP_FORMAT_KEY, IDEMPOTENCY_KEY,
+    LOG_SOURCE_KEY, LOG_SOURCE_KINESIS, LOG_SOURCE_OTEL, PARTIAL_ACCEPT_KEY, PREFIX_META,
+    PREFIX_TAGS, PROTOBUF_DELIMITED_KEY, SEPARATOR, STREAM_NAME_HEADER_KEY, STRICT_KEY,
+    TIMESTAMP_FIELD_KEY,
 };
 use crate::localcache::CacheError;
 use crate::metadata::error::stream_info::MetadataError;
 use crate::metadata::{self, STREAM_INFO};
+use crate::metrics::DEAD_LETTER_EVENTS;
 use crate::option::{Mode, CONFIG};
+use crate::storage::disk_usage::{DiskUsageError, DISK_GUARD};
+use crate::storage::object_storage::commit_widened_schema_to_storage;
 use crate::storage::{LogStream, ObjectStorageError};
 use crate::utils::header_parsing::{collect_labelled_headers, ParseHeaderError};
-use crate::utils::json::convert_array_to_object;
-use actix_web::{http::header::ContentType, HttpRequest, HttpResponse};
+use crate::utils::json::{convert_array_to_object, flatten::FlattenOptions};
+use actix_web::http::header::{ContentType, ToStrError};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
 use arrow_array::RecordBatch;
 use arrow_schema::{Field, Schema};
 use bytes::Bytes;
 use chrono::{DateTime, NaiveDateTime, Utc};
 use http::StatusCode;
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
 use std::sync::Arc;
 
 // Handler for POST /api/v1/ingest
 // ingests events by extracting stream name from header
 // creates if stream does not exist
+// CSV, TSV, MessagePack, protobuf and plain text bodies are selected by their content type
 pub async fn ingest(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostError> {
     if let Some((_, stream_name)) = req
         .headers()
@@ -65,6 +85,24 @@ pub async fn ingest(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostE
         }
         create_stream_if_not_exists(&stream_name, false).await?;
 
+        if bool_header(&req, ATOMIC_KEY)?.unwrap_or(false) {
+            return push_atomic(stream_name, &req, &body).await;
+        }
+        if let Some(delimiter) = csv_delimiter(req.content_type()) {
+            return push_csv(stream_name, &req, &body, delimiter).await;
+        }
+        if msgpack::is_msgpack(req.content_type()) {
+            push_msgpack(stream_name, &req, &body).await?;
+            return Ok(HttpResponse::Ok().finish());
+        }
+        if is_protobuf(req.content_type()) {
+            push_protobuf(stream_name, &req, &body).await?;
+            return Ok(HttpResponse::Ok().finish());
+        }
+        if is_text(req.content_type()) {
+            push_text(stream_name, &req, &body).await?;
+            return Ok(HttpResponse::Ok().finish());
+        }
         flatten_and_push_logs(req, body, stream_name).await?;
         Ok(HttpResponse::Ok().finish())
     } else {
@@ -72,6 +110,80 @@ pub async fn ingest(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostE
     }
 }
 
+// streams a newline delimited JSON body into the stream named by the header,
+// flushing its events to staging as they arrive. The response is a line of
+// progress after every flush, its status is sent before the body is read
+pub async fn ingest_ndjson(
+    req: HttpRequest,
+    payload: web::Payload,
+) -> Result<HttpResponse, PostError> {
+    // lines are flushed as they arrive, they can't be ingested as a whole
+    if bool_header(&req, ATOMIC_KEY)?.unwrap_or(false) {
+        return Err(atomic_unsupported());
+    }
+    let stream_name = str_header(&req, STREAM_NAME_HEADER_KEY)?
+        .ok_or(PostError::Header(ParseHeaderError::MissingStreamName))?
+        .to_owned();
+    if stream_name.eq(INTERNAL_STREAM_NAME) {
+        return Err(PostError::Invalid(anyhow::anyhow!(
+            "Stream {} is an internal stream and cannot be ingested into",
+            stream_name
+        )));
+    }
+    create_stream_if_not_exists(&stream_name, false).await?;
+
+    let strict = bool_header(&req, STRICT_KEY)?.unwrap_or(false);
+    let claim = str_header(&req, IDEMPOTENCY_KEY)?
+        .map(|key| ndjson::CHECKPOINTS.claim(&stream_name, key))
+        .transpose()?;
+    let tags = collect_labelled_headers(&req, PREFIX_TAGS, SEPARATOR)?;
+    let metadata = collect_labelled_headers(&req, PREFIX_META, SEPARATOR)?;
+    let timestamp_field = str_header(&req, TIMESTAMP_FIELD_KEY)?.map(str::to_owned);
+
+    let (progress, lines) = futures::channel::mpsc::unbounded();
+    actix_web::rt::spawn(async move {
+        let flush = |events: Vec<Value>, size: usize| {
+            let (stream_name, tags, metadata, timestamp_field) = (
+                stream_name.clone(),
+                tags.clone(),
+                metadata.clone(),
+                timestamp_field.clone(),
+            );
+            async move {
+                push_labelled_value(
+                    stream_name,
+                    &tags,
+                    &metadata,
+                    timestamp_field.as_deref(),
+                    Value::Array(events),
+                    size,
+                )
+                .await
+                .map_err(|err| err.to_string())
+            }
+        };
+        // a client that went away no longer reads the progress
+        let report = |update: &ndjson::Progress| {
+            let mut line = serde_json::to_vec(update).expect("progress serializes");
+            line.push(b'\n');
+            let _ = progress.unbounded_send(Ok::<_, std::convert::Infallible>(Bytes::from(line)));
+        };
+        ndjson::ingest(
+            payload,
+            StreamLimits::from_config(),
+            strict,
+            claim,
+            flush,
+            report,
+        )
+        .await;
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(lines))
+}
+
 pub async fn ingest_internal_stream(stream_name: String, body: Bytes) -> Result<(), PostError> {
     create_stream_if_not_exists(&stream_name, true).await?;
     let size: usize = body.len();
@@ -106,40 +218,147 @@ pub async fn ingest_internal_stream(stream_name: String, body: Bytes) -> Result<
     Ok(())
 }
 
-// Handler for POST /v1/logs to ingest OTEL logs
-// ingests events by extracting stream name from header
-// creates if stream does not exist
+// Handler for POST /v1/logs to ingest OTLP/HTTP logs
+// accepts protobuf and JSON requests, logs go to the stream in the header or
+// else the stream named by their resource, which is created if it does not exist
+// log records that can't be ingested are reported back as a partial success
 pub async fn ingest_otel_logs(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostError> {
-    if let Some((_, stream_name)) = req
-        .headers()
-        .iter()
-        .find(|&(key, _)| key == STREAM_NAME_HEADER_KEY)
-    {
-        let stream_name = stream_name.to_str().unwrap().to_owned();
-        create_stream_if_not_exists(&stream_name, false).await?;
+    let encoding = otel_encoding(&req).map_err(PostError::UnsupportedContentType)?;
+    let logs = encoding.decode_logs(&body)?;
+    let header_stream = otel_stream_header(&req).map_err(|err| PostError::Invalid(err.into()))?;
+
+    let mut partial_success = otel::PartialSuccess::default();
+    let mut streams: HashMap<String, Result<(), String>> = HashMap::new();
+    for otel::OtelRecord { stream, record } in otel::flatten_logs(&logs) {
+        let Some(stream_name) = header_stream.clone().or(stream) else {
+            partial_success.reject(format!(
+                "no {STREAM_NAME_HEADER_KEY} header or {} resource attribute",
+                otel::STREAM_ATTRIBUTE
+            ));
+            continue;
+        };
+        if !streams.contains_key(&stream_name) {
+            let created = create_otel_stream(&stream_name).await;
+            streams.insert(stream_name.clone(), created);
+        }
+        if let Err(err) = &streams[&stream_name] {
+            partial_success.reject(err);
+            continue;
+        }
 
-        //flatten logs
-        if let Some((_, log_source)) = req.headers().iter().find(|&(key, _)| key == LOG_SOURCE_KEY)
-        {
-            let log_source: String = log_source.to_str().unwrap().to_owned();
-            if log_source == LOG_SOURCE_OTEL {
-                let mut json = otel::flatten_otel_logs(&body);
-                for record in json.iter_mut() {
-                    let body: Bytes = serde_json::to_vec(record).unwrap().into();
-                    push_logs(stream_name.to_string(), req.clone(), body).await?;
-                }
-            } else {
-                return Err(PostError::CustomError("Unknown log source".to_string()));
+        let body: Bytes = serde_json::to_vec(&record)?.into();
+        if let Err(err) = push_logs(stream_name, req.clone(), body).await {
+            partial_success.reject(err);
+        }
+    }
+    if partial_success.rejected() > 0 {
+        log::warn!("Rejected {} OTEL log records", partial_success.rejected());
+    }
+    Ok(encoding.response(otel::Signal::Logs, &partial_success))
+}
+
+// Handler for POST /v1/traces to ingest OTLP/HTTP traces
+// accepts protobuf and JSON requests, one row per span goes to the stream in
+// the header, else the stream named by their resource, else `oteltraces`
+// spans are staged in chunks, those that can't be ingested are reported back
+// as a partial success
+pub async fn ingest_otel_traces(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostError> {
+    let encoding = otel_encoding(&req).map_err(PostError::UnsupportedContentType)?;
+    let traces = encoding.decode_traces(&body)?;
+    let header_stream = otel_stream_header(&req).map_err(|err| PostError::Invalid(err.into()))?;
+
+    let mut streams: BTreeMap<String, Vec<BTreeMap<String, Value>>> = BTreeMap::new();
+    for otel::OtelRecord { stream, record } in otel::flatten_traces(&traces) {
+        let stream_name = header_stream
+            .clone()
+            .or(stream)
+            .unwrap_or_else(|| otel::TRACES_STREAM.to_owned());
+        streams.entry(stream_name).or_default().push(record);
+    }
+
+    let mut partial_success = otel::PartialSuccess::default();
+    for (stream_name, spans) in streams {
+        if let Err(err) = create_otel_stream(&stream_name).await {
+            partial_success.reject_many(spans.len(), err);
+            continue;
+        }
+        for chunk in spans.chunks(otel::SPANS_PER_EVENT) {
+            let body: Bytes = serde_json::to_vec(chunk)?.into();
+            if let Err(err) = push_logs(stream_name.clone(), req.clone(), body).await {
+                partial_success.reject_many(chunk.len(), err);
             }
-        } else {
-            return Err(PostError::CustomError(
-                "log source key header is missing".to_string(),
-            ));
         }
-    } else {
-        return Err(PostError::Header(ParseHeaderError::MissingStreamName));
     }
-    Ok(HttpResponse::Ok().finish())
+    if partial_success.rejected() > 0 {
+        log::warn!("Rejected {} OTEL spans", partial_success.rejected());
+    }
+    Ok(encoding.response(otel::Signal::Traces, &partial_success))
+}
+
+// the unsupported content type on error
+// Handler for POST /v1/metrics to ingest OTLP/HTTP metrics
+// accepts protobuf and JSON requests, data points go to the stream in the
+// header, else the stream named by their resource, else `otelmetrics`
+// histogram data points are a row per bucket and summaries a row per quantile,
+// data points that can't be ingested are reported back as a partial success
+pub async fn ingest_otel_metrics(req: HttpRequest, body: Bytes) -> Result<HttpResponse, PostError> {
+    let encoding = otel_encoding(&req).map_err(PostError::UnsupportedContentType)?;
+    let metrics = encoding.decode_metrics(&body)?;
+    let header_stream = otel_stream_header(&req).map_err(|err| PostError::Invalid(err.into()))?;
+
+    let mut streams: BTreeMap<String, Vec<Vec<BTreeMap<String, Value>>>> = BTreeMap::new();
+    for otel::OtelDataPoint { stream, rows } in otel::flatten_metrics(&metrics) {
+        let stream_name = header_stream
+            .clone()
+            .or(stream)
+            .unwrap_or_else(|| otel::METRICS_STREAM.to_owned());
+        streams.entry(stream_name).or_default().push(rows);
+    }
+
+    let mut partial_success = otel::PartialSuccess::default();
+    for (stream_name, data_points) in streams {
+        if let Err(err) = create_otel_stream(&stream_name).await {
+            partial_success.reject_many(data_points.len(), err);
+            continue;
+        }
+        for chunk in data_points.chunks(otel::DATA_POINTS_PER_EVENT) {
+            let rows: Vec<_> = chunk.iter().flatten().collect();
+            let body: Bytes = serde_json::to_vec(&rows)?.into();
+            if let Err(err) = push_logs(stream_name.clone(), req.clone(), body).await {
+                partial_success.reject_many(chunk.len(), err);
+            }
+        }
+    }
+    if partial_success.rejected() > 0 {
+        log::warn!(
+            "Rejected {} OTEL metric data points",
+            partial_success.rejected()
+        );
+    }
+    Ok(encoding.response(otel::Signal::Metrics, &partial_success))
+}
+
+fn otel_encoding(req: &HttpRequest) -> Result<otel::Encoding, String> {
+    let content_type = req.content_type();
+    otel::Encoding::from_content_type(content_type).ok_or_else(|| content_type.to_owned())
+}
+
+fn otel_stream_header(req: &HttpRequest) -> Result<Option<String>, ToStrError> {
+    req.headers()
+        .get(STREAM_NAME_HEADER_KEY)
+        .map(|stream| stream.to_str().map(str::to_owned))
+        .transpose()
+}
+
+async fn create_otel_stream(stream_name: &str) -> Result<(), String> {
+    if stream_name == INTERNAL_STREAM_NAME {
+        return Err(format!(
+            "Stream {stream_name} is an internal stream and cannot be ingested into"
+        ));
+    }
+    create_stream_if_not_exists(stream_name, false)
+        .await
+        .map_err(|err| err.to_string())
 }
 
 async fn flatten_and_push_logs(
@@ -154,7 +373,7 @@ async fn flatten_and_push_logs(
         match log_source.as_str() {
             LOG_SOURCE_KINESIS => json = kinesis::flatten_kinesis_logs(&body),
             LOG_SOURCE_OTEL => {
-                json = otel::flatten_otel_logs(&body);
+                json = otel::flatten_otel_logs(&body)?;
             }
             _ => {
                 log::warn!("Unknown log source: {}", log_source);
@@ -182,10 +401,399 @@ pub async fn post_event(req: HttpRequest, body: Bytes) -> Result<HttpResponse, P
             stream_name
         )));
     }
+    if bool_header(&req, ATOMIC_KEY)?.unwrap_or(false) {
+        return push_atomic(stream_name, &req, &body).await;
+    }
+    if let Some(delimiter) = csv_delimiter(req.content_type()) {
+        return push_csv(stream_name, &req, &body, delimiter).await;
+    }
+    if msgpack::is_msgpack(req.content_type()) {
+        push_msgpack(stream_name, &req, &body).await?;
+        return Ok(HttpResponse::Ok().finish());
+    }
+    if is_protobuf(req.content_type()) {
+        push_protobuf(stream_name, &req, &body).await?;
+        return Ok(HttpResponse::Ok().finish());
+    }
+    if is_text(req.content_type()) {
+        push_text(stream_name, &req, &body).await?;
+        return Ok(HttpResponse::Ok().finish());
+    }
     flatten_and_push_logs(req, body, stream_name).await?;
     Ok(HttpResponse::Ok().finish())
 }
 
+// ingests a MessagePack body as the events of its JSON equivalent
+async fn push_msgpack(
+    stream_name: String,
+    req: &HttpRequest,
+    body: &[u8],
+) -> Result<(), PostError> {
+    let binary = match str_header(req, BINARY_ENCODING_KEY)? {
+        Some(encoding) => encoding.parse::<BinaryEncoding>()?,
+        None => BinaryEncoding::default(),
+    };
+    let body_val = msgpack::decode(body, binary)?;
+    let tags = collect_labelled_headers(req, PREFIX_TAGS, SEPARATOR)?;
+    let metadata = collect_labelled_headers(req, PREFIX_META, SEPARATOR)?;
+    let timestamp_field = str_header(req, TIMESTAMP_FIELD_KEY)?;
+    push_labelled_value(
+        stream_name,
+        &tags,
+        &metadata,
+        timestamp_field,
+        body_val,
+        body.len(),
+    )
+    .await
+}
+
+// whether a content type names a protobuf body
+fn is_protobuf(content_type: &str) -> bool {
+    matches!(
+        content_type.trim().to_ascii_lowercase().as_str(),
+        "application/protobuf" | "application/x-protobuf"
+    )
+}
+
+// ingests a protobuf body decoded with the descriptor registered for the stream
+async fn push_protobuf(
+    stream_name: String,
+    req: &HttpRequest,
+    body: &[u8],
+) -> Result<(), PostError> {
+    let decoder = STREAM_INFO
+        .get_protobuf(&stream_name)
+        .map_err(|_| PostError::StreamNotFound(stream_name.clone()))?
+        .ok_or_else(|| {
+            PostError::Invalid(anyhow::anyhow!(
+                "No protobuf descriptor registered for stream {stream_name}"
+            ))
+        })?;
+    let delimited = bool_header(req, PROTOBUF_DELIMITED_KEY)?.unwrap_or(false);
+    let body_val = decoder.decode(body, delimited)?;
+    let tags = collect_labelled_headers(req, PREFIX_TAGS, SEPARATOR)?;
+    let metadata = collect_labelled_headers(req, PREFIX_META, SEPARATOR)?;
+    let timestamp_field = str_header(req, TIMESTAMP_FIELD_KEY)?;
+    push_labelled_value(
+        stream_name,
+        &tags,
+        &metadata,
+        timestamp_field,
+        body_val,
+        body.len(),
+    )
+    .await
+}
+
+// whether a content type names a plain text body
+fn is_text(content_type: &str) -> bool {
+    content_type.trim().eq_ignore_ascii_case("text/plain")
+}
+
+// ingests a line of a text body per event, the stream's grok pattern parses
+// them like the text field of JSON events
+async fn push_text(stream_name: String, req: &HttpRequest, body: &[u8]) -> Result<(), PostError> {
+    let text = std::str::from_utf8(body)
+        .map_err(|err| PostError::Invalid(anyhow::anyhow!("Text body is not UTF-8: {err}")))?;
+    let field = STREAM_INFO
+        .get_grok(&stream_name)?
+        .map(|grok| grok.settings().field.clone())
+        .unwrap_or_else(|| DEFAULT_RAW_FIELD.to_owned());
+    let events = grok::text_events(text, &field);
+    if events.is_empty() {
+        return Ok(());
+    }
+    let tags = collect_labelled_headers(req, PREFIX_TAGS, SEPARATOR)?;
+    let metadata = collect_labelled_headers(req, PREFIX_META, SEPARATOR)?;
+    let timestamp_field = str_header(req, TIMESTAMP_FIELD_KEY)?;
+    push_labelled_value(
+        stream_name,
+        &tags,
+        &metadata,
+        timestamp_field,
+        Value::Array(events),
+        body.len(),
+    )
+    .await
+}
+
+// delimiter of the CSV dialect a content type names
+fn csv_delimiter(content_type: &str) -> Option<u8> {
+    match content_type.trim().to_ascii_lowercase().as_str() {
+        "text/csv" => Some(b','),
+        "text/tab-separated-values" => Some(b'\t'),
+        _ => None,
+    }
+}
+
+// ingests a CSV or TSV body into an existing stream in chunks of rows, rows that
+// can't be ingested fail the request unless partial accept is set, then the
+// other rows are ingested and the rejected ones reported back
+async fn push_csv(
+    stream_name: String,
+    req: &HttpRequest,
+    body: &[u8],
+    delimiter: u8,
+) -> Result<HttpResponse, PostError> {
+    DISK_GUARD.check(&stream_name).await?;
+    if STREAM_INFO.get_time_partition(&stream_name)?.is_some()
+        || STREAM_INFO.get_custom_partition(&stream_name)?.is_some()
+    {
+        return Err(PostError::Invalid(anyhow::anyhow!(
+            "Stream {stream_name} is partitioned, CSV can only be ingested into streams without a time or custom partition"
+        )));
+    }
+    let options = csv_options(req, delimiter)?;
+    let partial_accept = bool_header(req, PARTIAL_ACCEPT_KEY)?.unwrap_or(false);
+    let static_schema_flag = STREAM_INFO.get_static_schema_flag(&stream_name)?;
+    let tags = collect_labelled_headers(req, PREFIX_TAGS, SEPARATOR)?;
+    let metadata = collect_labelled_headers(req, PREFIX_META, SEPARATOR)?;
+
+    let parsed = format::csv::parse(body, options, &stream_schema(&stream_name)?)?;
+    if !parsed.rejected.is_empty() && !partial_accept {
+        return Err(PostError::RejectedRows(RejectedRows(parsed.rejected)));
+    }
+
+    let ingested = parsed.rows.len();
+    let rejected = parsed.rejected.len();
+    let errors: Vec<RowError> = parsed
+        .rejected
+        .iter()
+        .take(MAX_REPORTED_ROW_ERRORS)
+        .cloned()
+        .collect();
+    for event in parsed.into_events(tags, metadata) {
+        let origin_size = event.data.size();
+        // the first chunk commits the columns it adds to the stream schema
+        let (rb, is_first_event) = event.into_recordbatch(
+            stream_schema(&stream_name)?,
+            static_schema_flag.clone(),
+            None,
+        )?;
+        event::Event {
+            rb,
+            stream_name: stream_name.clone(),
+            origin_format: "csv",
+            origin_size,
+            is_first_event,
+            parsed_timestamp: Utc::now().naive_utc(),
+            time_partition: None,
+            custom_partition_values: HashMap::new(),
+        }
+        .process()
+        .await?;
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "ingested": ingested,
+        "rejected": rejected,
+        "errors": errors,
+    })))
+}
+
+fn csv_options(req: &HttpRequest, delimiter: u8) -> Result<CsvOptions, PostError> {
+    let delimiter = match str_header(req, CSV_DELIMITER_KEY)? {
+        Some("tab" | "\\t") => b'\t',
+        Some(value) if value.len() == 1 => value.as_bytes()[0],
+        Some(value) => {
+            return Err(PostError::Invalid(anyhow::anyhow!(
+                "{CSV_DELIMITER_KEY} must be a single character or tab, got {value}"
+            )))
+        }
+        None => delimiter,
+    };
+    let has_header = bool_header(req, CSV_HEADER_KEY)?.unwrap_or(true);
+    let columns: Vec<String> = str_header(req, CSV_COLUMNS_KEY)?
+        .map(|columns| columns.split(',').map(|c| c.trim().to_owned()).collect())
+        .unwrap_or_default();
+    if has_header && !columns.is_empty() {
+        return Err(PostError::Invalid(anyhow::anyhow!(
+            "{CSV_COLUMNS_KEY} names the columns of a body without a header row, set {CSV_HEADER_KEY} to false"
+        )));
+    }
+    if !has_header && columns.is_empty() {
+        return Err(PostError::Invalid(anyhow::anyhow!(
+            "{CSV_COLUMNS_KEY} is required when {CSV_HEADER_KEY} is false"
+        )));
+    }
+
+    Ok(CsvOptions {
+        delimiter,
+        has_header,
+        columns,
+        null_value: str_header(req, CSV_NULL_KEY)?
+            .unwrap_or_default()
+            .to_owned(),
+        timestamp_column: str_header(req, CSV_TIMESTAMP_COLUMN_KEY)?.map(str::to_owned),
+        timestamp_format: str_header(req, CSV_TIMESTAMP_FORMAT_KEY)?.map(str::to_owned),
+    })
+}
+
+// ingests a JSON body as a whole, all of its events are converted before any
+// is staged and a failure leaves none of them ingested. Events are neither
+// widened nor dead lettered, an event that doesn't fit rejects the body
+async fn push_atomic(
+    stream_name: String,
+    req: &HttpRequest,
+    body: &[u8],
+) -> Result<HttpResponse, PostError> {
+    let content_type = req.content_type();
+    if csv_delimiter(content_type).is_some()
+        || msgpack::is_msgpack(content_type)
+        || is_protobuf(content_type)
+        || is_text(content_type)
+        || ndjson::is_ndjson(content_type)
+        || req.headers().contains_key(LOG_SOURCE_KEY)
+    {
+        return Err(atomic_unsupported());
+    }
+    DISK_GUARD.check(&stream_name).await?;
+    let tags = collect_labelled_headers(req, PREFIX_TAGS, SEPARATOR)?;
+    let metadata = collect_labelled_headers(req, PREFIX_META, SEPARATOR)?;
+    let timestamp_field = str_header(req, TIMESTAMP_FIELD_KEY)?;
+    let mut body_val: Value = serde_json::from_slice(body)?;
+
+    let time_partition = STREAM_INFO.get_time_partition(&stream_name)?;
+    let time_partition_limit = STREAM_INFO.get_time_partition_limit(&stream_name)?;
+    let static_schema_flag = STREAM_INFO.get_static_schema_flag(&stream_name)?;
+    let custom_partition = STREAM_INFO.get_custom_partition(&stream_name)?;
+    let transformer = STREAM_INFO.get_transformer(&stream_name)?;
+    if let Some(grok) = STREAM_INFO.get_grok(&stream_name)? {
+        grok.apply(&mut body_val);
+    }
+    let mut body_val = match STREAM_INFO.get_flattening(&stream_name)? {
+        Some(flattening) => flattening.flatten(body_val)?,
+        None if transformer.is_some() => FlattenOptions::default().flatten(body_val)?,
+        None => body_val,
+    };
+    if let Some(transformer) = transformer {
+        transformer.apply(&stream_name, &mut body_val);
+    }
+    let timestamp_extraction = match (
+        STREAM_INFO.get_timestamp_extraction(&stream_name)?,
+        timestamp_field,
+    ) {
+        (Some(extraction), Some(field)) => Some(extraction.with_field(field)),
+        (None, Some(field)) => Some(TimestampExtraction::new(field)),
+        (extraction, None) => extraction,
+    };
+
+    // events staged by their time or partition are converted one by one,
+    // others as a single batch
+    let whole =
+        time_partition.is_none() && custom_partition.is_none() && timestamp_extraction.is_none();
+    let values = if whole {
+        vec![body_val]
+    } else {
+        convert_array_to_object(
+            body_val,
+            time_partition.clone(),
+            time_partition_limit,
+            custom_partition.clone(),
+        )?
+    };
+    let custom_partition_list = custom_partition
+        .as_deref()
+        .map(|custom_partition| custom_partition.split(',').collect::<Vec<&str>>())
+        .unwrap_or_default();
+    let schema = stream_schema(&stream_name)?;
+    let now = Utc::now().naive_utc();
+
+    let mut events = Vec::with_capacity(values.len());
+    for value in values {
+        let custom_partition_values = get_custom_partition_values(&value, &custom_partition_list);
+        // the time partition takes precedence over timestamp extraction
+        let (parsed_timestamp, timestamp_source) = match (&time_partition, &timestamp_extraction) {
+            (Some(_), _) => (get_parsed_timestamp(&value, &time_partition), None),
+            (None, Some(extraction)) => {
+                let (timestamp, source) = extraction.extract(&value, now)?;
+                (timestamp, Some(source))
+            }
+            (None, None) => (now, None),
+        };
+        let origin_size = if whole {
+            body.len() as u64
+        } else {
+            value.to_string().len() as u64
+        };
+        let (rb, is_first_event) = into_event_batch(
+            &tags,
+            &metadata,
+            value,
+            schema.clone(),
+            static_schema_flag.clone(),
+            time_partition.clone(),
+        )?;
+        let (rb, is_first_event) = match timestamp_source {
+            Some(source) => {
+                stamp_event_time(&stream_name, &rb, is_first_event, parsed_timestamp, source)?
+            }
+            None => (rb, is_first_event),
+        };
+        events.push(event::Event {
+            rb,
+            stream_name: stream_name.clone(),
+            origin_format: "json",
+            origin_size,
+            is_first_event,
+            parsed_timestamp,
+            time_partition: time_partition.clone(),
+            custom_partition_values,
+        });
+    }
+
+    let committed = event::Event::process_atomic(&stream_name, events).await?;
+    Ok(HttpResponse::Ok().json(json!({ "committed": committed })))
+}
+
+fn atomic_unsupported() -> PostError {
+    PostError::Invalid(anyhow::anyhow!(
+        "{ATOMIC_KEY} is only supported for JSON bodies"
+    ))
+}
+
+fn str_header<'a>(req: &'a HttpRequest, key: &str) -> Result<Option<&'a str>, PostError> {
+    req.headers()
+        .get(key)
+        .map(|value| value.to_str())
+        .transpose()
+        .map_err(|err| PostError::Invalid(anyhow::anyhow!("Invalid {key} header: {err}")))
+}
+
+fn bool_header(req: &HttpRequest, key: &str) -> Result<Option<bool>, PostError> {
+    str_header(req, key)?
+        .map(|value| match value.trim().to_ascii_lowercase().as_str() {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(PostError::Invalid(anyhow::anyhow!(
+                "{key} must be true or false, got {value}"
+            ))),
+        })
+        .transpose()
+}
+
+// widens the columns of the stream the events don't fit in, in storage first
+// so that a failed write leaves the stream as it was
+async fn widen_stream_schema(stream_name: &str, body_val: &mut Value) -> Result<(), PostError> {
+    let changes = widening::widen(&stream_schema(stream_name)?, body_val);
+    if changes.is_empty() {
+        return Ok(());
+    }
+    commit_widened_schema_to_storage(stream_name, &changes).await?;
+    event::commit_widened_schema(stream_name, &changes);
+    Ok(())
+}
+
+fn stream_schema(stream_name: &str) -> Result<HashMap<String, Arc<Field>>, PostError> {
+    let hash_map = STREAM_INFO.read().unwrap();
+    Ok(hash_map
+        .get(stream_name)
+        .ok_or(PostError::StreamNotFound(stream_name.to_owned()))?
+        .schema
+        .clone())
+}
+
 pub async fn push_logs_unchecked(
     batches: RecordBatch,
     stream_name: &str,
@@ -206,80 +814,243 @@ pub async fn push_logs_unchecked(
 }
 
 async fn push_logs(stream_name: String, req: HttpRequest, body: Bytes) -> Result<(), PostError> {
+    let tags = collect_labelled_headers(&req, PREFIX_TAGS, SEPARATOR)?;
+    let metadata = collect_labelled_headers(&req, PREFIX_META, SEPARATOR)?;
+    let timestamp_field = str_header(&req, TIMESTAMP_FIELD_KEY)?;
+    let body_val: Value = serde_json::from_slice(&body)?;
+    push_labelled_value(
+        stream_name,
+        &tags,
+        &metadata,
+        timestamp_field,
+        body_val,
+        body.len(),
+    )
+    .await
+}
+
+// ingests the events in `body` into an existing stream, every row carries the
+// given tags and metadata, for events that don't arrive with HTTP headers
+pub async fn push_labelled_logs(
+    stream_name: String,
+    tags: &str,
+    metadata: &str,
+    body: Bytes,
+) -> Result<(), PostError> {
+    let body_val: Value = serde_json::from_slice(&body)?;
+    push_labelled_value(stream_name, tags, metadata, None, body_val, body.len()).await
+}
+
+// ingests events already decoded from a body of `size` bytes, the event time
+// is read from `timestamp_field` when given over the one set for the stream.
+// Events the stream rejects go to its dead letter stream when it has one
+async fn push_labelled_value(
+    stream_name: String,
+    tags: &str,
+    metadata: &str,
+    timestamp_field: Option<&str>,
+    mut body_val: Value,
+    size: usize,
+) -> Result<(), PostError> {
+    DISK_GUARD.check(&stream_name).await?;
     let time_partition = STREAM_INFO.get_time_partition(&stream_name)?;
     let time_partition_limit = STREAM_INFO.get_time_partition_limit(&stream_name)?;
     let static_schema_flag = STREAM_INFO.get_static_schema_flag(&stream_name)?;
     let custom_partition = STREAM_INFO.get_custom_partition(&stream_name)?;
-    let body_val: Value = serde_json::from_slice(&body)?;
-    let size: usize = body.len();
+    let type_widening =
+        static_schema_flag.is_none() && STREAM_INFO.type_widening_allowed(&stream_name)?;
+    let transformer = STREAM_INFO.get_transformer(&stream_name)?;
+    let dead_letter = dead_letter::target(&stream_name)?;
+    // the body as received is only kept to dead letter it
+    let received = dead_letter.as_ref().map(|_| body_val.clone());
+    // text is parsed into its captures before anything else looks at the events
+    if let Some(grok) = STREAM_INFO.get_grok(&stream_name)? {
+        grok.apply(&mut body_val);
+    }
+    // events are flattened with the stream's settings first, the default
+    // flattening later on leaves them as they are
+    let flattened = match STREAM_INFO.get_flattening(&stream_name)? {
+        Some(flattening) => flattening.flatten(body_val),
+        // columns are widened and transformed by their flattened names
+        None if type_widening || transformer.is_some() => {
+            FlattenOptions::default().flatten(body_val)
+        }
+        None => Ok(body_val),
+    };
+    let mut body_val = match flattened {
+        Ok(body_val) => body_val,
+        Err(err) => {
+            return reject_body(
+                dead_letter.as_deref(),
+                &stream_name,
+                received,
+                RejectionReason::Flattening,
+                err.into(),
+            )
+            .await
+        }
+    };
+    if let Some(transformer) = transformer {
+        transformer.apply(&stream_name, &mut body_val);
+    }
+    if type_widening {
+        widen_stream_schema(&stream_name, &mut body_val).await?;
+    }
+    let timestamp_extraction = match (
+        STREAM_INFO.get_timestamp_extraction(&stream_name)?,
+        timestamp_field,
+    ) {
+        (Some(extraction), Some(field)) => Some(extraction.with_field(field)),
+        (None, Some(field)) => Some(TimestampExtraction::new(field)),
+        (extraction, None) => extraction,
+    };
     let mut parsed_timestamp = Utc::now().naive_utc();
     if time_partition.is_none() {
-        if custom_partition.is_none() {
+        if custom_partition.is_none() && timestamp_extraction.is_none() {
             let size = size as u64;
             create_process_record_batch(
                 stream_name.clone(),
-                req.clone(),
+                tags,
+                metadata,
                 body_val.clone(),
                 static_schema_flag.clone(),
                 None,
                 parsed_timestamp,
+                None,
                 HashMap::new(),
                 size,
+                dead_letter.as_deref(),
             )
             .await?;
         } else {
-            let data =
-                convert_array_to_object(body_val.clone(), None, None, custom_partition.clone())?;
-            let custom_partition = custom_partition.unwrap();
-            let custom_partition_list = custom_partition.split(',').collect::<Vec<&str>>();
-
+            let data = match convert_array_to_object(
+                body_val.clone(),
+                None,
+                None,
+                custom_partition.clone(),
+            ) {
+                Ok(data) => data,
+                Err(err) => {
+                    return reject_body(
+                        dead_letter.as_deref(),
+                        &stream_name,
+                        received,
+                        RejectionReason::Flattening,
+                        err.into(),
+                    )
+                    .await
+                }
+            };
+            let custom_partition_list = custom_partition
+                .as_deref()
+                .map(|custom_partition| custom_partition.split(',').collect::<Vec<&str>>())
+                .unwrap_or_default();
+            // every event time is checked before any event is ingested, so a
+            // skewed event rejects the whole body unless it can be dead lettered
+            let mut events = Vec::with_capacity(data.len());
+            let mut rejections = Vec::new();
+            let mut rejected = None;
             for value in data {
+                let timestamp = timestamp_extraction
+                    .as_ref()
+                    .map(|extraction| extraction.extract(&value, parsed_timestamp))
+                    .transpose();
+                match timestamp {
+                    Ok(timestamp) => events.push((value, timestamp)),
+                    Err(err) if dead_letter.is_some() => {
+                        rejections.push(Rejection::new(value, RejectionReason::Timestamp, &err));
+                        rejected.get_or_insert(err);
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            if let (Some(target), Some(err)) = (dead_letter.as_deref(), rejected) {
+                dead_letter_events(target, &stream_name, rejections)
+                    .await
+                    .map_err(|_| PostError::from(err))?;
+            }
+
+            for (value, timestamp) in events {
                 let custom_partition_values =
                     get_custom_partition_values(&value, &custom_partition_list);
+                let (parsed_timestamp, timestamp_source) = match timestamp {
+                    Some((timestamp, source)) => (timestamp, Some(source)),
+                    None => (parsed_timestamp, None),
+                };
 
                 let size = value.to_string().into_bytes().len() as u64;
                 create_process_record_batch(
                     stream_name.clone(),
-                    req.clone(),
+                    tags,
+                    metadata,
                     value.clone(),
                     static_schema_flag.clone(),
                     None,
                     parsed_timestamp,
+                    timestamp_source,
                     custom_partition_values.clone(),
                     size,
+                    dead_letter.as_deref(),
                 )
                 .await?;
             }
         }
     } else if custom_partition.is_none() {
-        let data = convert_array_to_object(
+        let data = match convert_array_to_object(
             body_val.clone(),
             time_partition.clone(),
             time_partition_limit,
             None,
-        )?;
+        ) {
+            Ok(data) => data,
+            Err(err) => {
+                return reject_body(
+                    dead_letter.as_deref(),
+                    &stream_name,
+                    received,
+                    RejectionReason::Flattening,
+                    err.into(),
+                )
+                .await
+            }
+        };
         for value in data {
             parsed_timestamp = get_parsed_timestamp(&value, &time_partition);
             let size = value.to_string().into_bytes().len() as u64;
             create_process_record_batch(
                 stream_name.clone(),
-                req.clone(),
+                tags,
+                metadata,
                 value.clone(),
                 static_schema_flag.clone(),
                 time_partition.clone(),
                 parsed_timestamp,
+                None,
                 HashMap::new(),
                 size,
+                dead_letter.as_deref(),
             )
             .await?;
         }
     } else {
-        let data = convert_array_to_object(
+        let data = match convert_array_to_object(
             body_val.clone(),
             time_partition.clone(),
             time_partition_limit,
             custom_partition.clone(),
-        )?;
+        ) {
+            Ok(data) => data,
+            Err(err) => {
+                return reject_body(
+                    dead_letter.as_deref(),
+                    &stream_name,
+                    received,
+                    RejectionReason::Flattening,
+                    err.into(),
+                )
+                .await
+            }
+        };
         let custom_partition = custom_partition.unwrap();
         let custom_partition_list = custom_partition.split(',').collect::<Vec<&str>>();
 
@@ -291,13 +1062,16 @@ async fn push_logs(stream_name: String, req: HttpRequest, body: Bytes) -> Result
             let size = value.to_string().into_bytes().len() as u64;
             create_process_record_batch(
                 stream_name.clone(),
-                req.clone(),
+                tags,
+                metadata,
                 value.clone(),
                 static_schema_flag.clone(),
                 time_partition.clone(),
                 parsed_timestamp,
+                None,
                 custom_partition_values.clone(),
                 size,
+                dead_letter.as_deref(),
             )
             .await?;
         }
@@ -306,6 +1080,109 @@ async fn push_logs(stream_name: String, req: HttpRequest, body: Bytes) -> Result
     Ok(())
 }
 
+// a body rejected before any of its events is ingested, its events are dead
+// lettered when the stream has a dead letter stream
+async fn reject_body(
+    dead_letter: Option<&str>,
+    stream_name: &str,
+    received: Option<Value>,
+    reason: RejectionReason,
+    err: PostError,
+) -> Result<(), PostError> {
+    let (Some(target), Some(body)) = (dead_letter, received) else {
+        return Err(err);
+    };
+    let events = match body {
+        Value::Array(events) => events,
+        event => vec![event],
+    };
+    let rejections = events
+        .into_iter()
+        .map(|event| Rejection::new(event, reason, &err))
+        .collect();
+    dead_letter_events(target, stream_name, rejections)
+        .await
+        .map_err(|_| err)
+}
+
+// ingests the rejected events of a stream into its dead letter stream, which
+// never dead letters them further. Failures are logged and returned, so that
+// the caller can fail with the rejection itself
+async fn dead_letter_events(
+    target: &str,
+    stream_name: &str,
+    rejections: Vec<Rejection>,
+) -> Result<(), PostError> {
+    dead_letter_events_with(target, stream_name, rejections, &process_event).await
+}
+
+async fn dead_letter_events_with<F: Future<Output = Result<(), EventError>>>(
+    target: &str,
+    stream_name: &str,
+    rejections: Vec<Rejection>,
+    process: &impl Fn(event::Event) -> F,
+) -> Result<(), PostError> {
+    if rejections.is_empty() {
+        return Ok(());
+    }
+    let rejected_at = Utc::now();
+    let mut counts: HashMap<RejectionReason, u64> = HashMap::new();
+    let records: Vec<Value> = rejections
+        .into_iter()
+        .map(|rejection| {
+            *counts.entry(rejection.reason).or_default() += 1;
+            rejection.into_record(stream_name, rejected_at)
+        })
+        .collect();
+    let size = records
+        .iter()
+        .map(|record| record.to_string().len() as u64)
+        .sum();
+
+    let ingested = async {
+        create_stream_if_not_exists(target, false).await?;
+        let static_schema_flag = STREAM_INFO.get_static_schema_flag(target)?;
+        let (rb, is_first_event) = get_stream_schema(
+            target.to_owned(),
+            "",
+            "",
+            Value::Array(records),
+            static_schema_flag,
+            None,
+        )?;
+        process(event::Event {
+            rb,
+            stream_name: target.to_owned(),
+            origin_format: "json",
+            origin_size: size,
+            is_first_event,
+            parsed_timestamp: rejected_at.naive_utc(),
+            time_partition: None,
+            custom_partition_values: HashMap::new(),
+        })
+        .await?;
+        Ok::<_, PostError>(())
+    }
+    .await;
+
+    match ingested {
+        Ok(()) => {
+            for (reason, count) in counts {
+                DEAD_LETTER_EVENTS
+                    .with_label_values(&[stream_name, reason.as_str()])
+                    .inc_by(count);
+            }
+            Ok(())
+        }
+        Err(err) => {
+            log::error!(
+                "Could not dead letter events of stream {stream_name} into {target}: {err}"
+            );
+            Err(err)
+        }
+    }
+}
+
 fn get_parsed_timestamp(body: &Value, time_partition: &Option<String>) -> NaiveDateTime {
     let body_timestamp = body.get(&time_partition.clone().unwrap().to_string());
     let parsed_timestamp = body_timestamp
@@ -342,40 +1219,127 @@ fn get_custom_partition_values(
 #[allow(clippy::too_many_arguments)]
 async fn create_process_record_batch(
     stream_name: String,
-    req: HttpRequest,
+    tags: &str,
+    metadata: &str,
+    value: Value,
+    static_schema_flag: Option<String>,
+    time_partition: Option<String>,
+    parsed_timestamp: NaiveDateTime,
+    timestamp_source: Option<TimestampSource>,
+    custom_partition_values: HashMap<String, String>,
+    origin_size: u64,
+    dead_letter: Option<&str>,
+) -> Result<(), PostError> {
+    create_process_record_batch_with(
+        stream_name,
+        tags,
+        metadata,
+        value,
+        static_schema_flag,
+        time_partition,
+        parsed_timestamp,
+        timestamp_source,
+        custom_partition_values,
+        origin_size,
+        dead_letter,
+        &process_event,
+    )
+    .await
+}
+
+// stages an event with the stream writers
+async fn process_event(event: event::Event) -> Result<(), EventError> {
+    event.process().await
+}
+
+// validates the events in `value` against the stream schema and hands them to
+// `process`, the ones that don't fit go to the dead letter stream when set
+#[allow(clippy::too_many_arguments)]
+async fn create_process_record_batch_with<F: Future<Output = Result<(), EventError>>>(
+    stream_name: String,
+    tags: &str,
+    metadata: &str,
     value: Value,
     static_schema_flag: Option<String>,
     time_partition: Option<String>,
     parsed_timestamp: NaiveDateTime,
+    timestamp_source: Option<TimestampSource>,
     custom_partition_values: HashMap<String, String>,
     origin_size: u64,
+    dead_letter: Option<&str>,
+    process: &impl Fn(event::Event) -> F,
 ) -> Result<(), PostError> {
-    let (rb, is_first_event) = get_stream_schema(
+    let batch = get_stream_schema(
         stream_name.clone(),
-        req.clone(),
+        tags,
+        metadata,
         value.clone(),
         static_schema_flag.clone(),
         time_partition.clone(),
-    )?;
-    event::Event {
-        rb,
-        stream_name: stream_name.clone(),
-        origin_format: "json",
-        origin_size,
-        is_first_event,
-        parsed_timestamp,
-        time_partition: time_partition.clone(),
-        custom_partition_values: custom_partition_values.clone(),
+    );
+    let batches = match (batch, dead_letter) {
+        (Ok(batch), _) => vec![(batch, origin_size)],
+        // the events are validated one by one so that only the ones that
+        // don't fit the schema are dead lettered
+        (Err(err @ PostError::Invalid(_)), Some(target)) => {
+            let events = match value {
+                Value::Array(events) => events,
+                event => vec![event],
+            };
+            let mut batches = Vec::with_capacity(events.len());
+            let mut rejections = Vec::new();
+            for event in events {
+                let size = event.to_string().len() as u64;
+                match get_stream_schema(
+                    stream_name.clone(),
+                    tags,
+                    metadata,
+                    event.clone(),
+                    static_schema_flag.clone(),
+                    time_partition.clone(),
+                ) {
+                    Ok(batch) => batches.push((batch, size)),
+                    Err(rejected @ PostError::Invalid(_)) => {
+                        rejections.push(Rejection::new(event, RejectionReason::Schema, &rejected))
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            dead_letter_events_with(target, &stream_name, rejections, process)
+                .await
+                .map_err(|_| err)?;
+            batches
+        }
+        (Err(err), _) => return Err(err),
+    };
+
+    for ((rb, is_first_event), origin_size) in batches {
+        let (rb, is_first_event) = match timestamp_source {
+            Some(source) => {
+                stamp_event_time(&stream_name, &rb, is_first_event, parsed_timestamp, source)?
+            }
+            None => (rb, is_first_event),
+        };
+        process(event::Event {
+            rb,
+            stream_name: stream_name.clone(),
+            origin_format: "json",
+            origin_size,
+            is_first_event,
+            parsed_timestamp,
+            time_partition: time_partition.clone(),
+            custom_partition_values: custom_partition_values.clone(),
+        })
+        .await?;
     }
-    .process()
-    .await?;
 
     Ok(())
 }
 
 fn get_stream_schema(
     stream_name: String,
-    req: HttpRequest,
+    tags: &str,
+    metadata: &str,
     body: Value,
     static_schema_flag: Option<String>,
     time_partition: Option<String>,
@@ -386,22 +1350,42 @@ fn get_stream_schema(
         .ok_or(PostError::StreamNotFound(stream_name))?
         .schema
         .clone();
-    into_event_batch(req, body, schema, static_schema_flag, time_partition)
+    into_event_batch(
+        tags,
+        metadata,
+        body,
+        schema,
+        static_schema_flag,
+        time_partition,
+    )
+}
+
+// sets the extracted event time of a batch, the source column is committed
+// to the schema of streams that don't have it yet
+fn stamp_event_time(
+    stream_name: &str,
+    rb: &RecordBatch,
+    is_first_event: bool,
+    parsed_timestamp: NaiveDateTime,
+    source: TimestampSource,
+) -> Result<(RecordBatch, bool), PostError> {
+    let rb = timestamp::stamp(rb, parsed_timestamp, source);
+    let has_source = stream_schema(stream_name)?.contains_key(DEFAULT_TIMESTAMP_SOURCE_KEY);
+    Ok((rb, is_first_event || !has_source))
 }
 
 fn into_event_batch(
-    req: HttpRequest,
+    tags: &str,
+    metadata: &str,
     body: Value,
     schema: HashMap<String, Arc<Field>>,
     static_schema_flag: Option<String>,
     time_partition: Option<String>,
 ) -> Result<(arrow_array::RecordBatch, bool), PostError> {
-    let tags = collect_labelled_headers(&req, PREFIX_TAGS, SEPARATOR)?;
-    let metadata = collect_labelled_headers(&req, PREFIX_META, SEPARATOR)?;
     let event = format::json::Event {
         data: body,
-        tags,
-        metadata,
+        tags: tags.to_owned(),
+        metadata: metadata.to_owned(),
     };
     let (rb, is_first) = event.into_recordbatch(schema, static_schema_flag, time_partition)?;
     Ok((rb, is_first))
@@ -475,6 +1459,8 @@ pub enum PostError {
     #[allow(unused)]
     #[error("Error: {0}")]
     CustomError(String),
+    #[error("Unsupported content type {0}, expected application/json or application/x-protobuf")]
+    UnsupportedContentType(String),
     #[error("Error: {0}")]
     NetworkError(#[from] reqwest::Error),
     #[error("ObjectStorageError: {0}")]
@@ -485,6 +1471,18 @@ pub enum PostError {
     DashboardError(#[from] DashboardError),
     #[error("Error: {0}")]
     CacheError(#[from] CacheError),
+    #[error("{0}")]
+    DiskUsage(#[from] DiskUsageError),
+    #[error("{0}, set the {PARTIAL_ACCEPT_KEY} header to ingest the other rows")]
+    RejectedRows(RejectedRows),
+    #[error("{0}")]
+    Msgpack(#[from] MsgpackError),
+    #[error("{0}")]
+    Protobuf(#[from] ProtobufError),
+    #[error("{0}")]
+    Timestamp(#[from] TimestampError),
+    #[error("{0}")]
+    Ndjson(#[from] NdjsonError),
 }
 
 impl actix_web::ResponseError for PostError {
@@ -501,11 +1499,19 @@ impl actix_web::ResponseError for PostError {
             PostError::MetadataStreamError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PostError::StreamNotFound(_) => StatusCode::NOT_FOUND,
             PostError::CustomError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            PostError::UnsupportedContentType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
             PostError::NetworkError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PostError::ObjectStorageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PostError::DashboardError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PostError::FiltersError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             PostError::CacheError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            PostError::DiskUsage(_) => StatusCode::INSUFFICIENT_STORAGE,
+            PostError::RejectedRows(_) => StatusCode::BAD_REQUEST,
+            PostError::Msgpack(_) => StatusCode::BAD_REQUEST,
+            PostError::Protobuf(_) => StatusCode::BAD_REQUEST,
+            PostError::Timestamp(_) => StatusCode::BAD_REQUEST,
+            PostError::Ndjson(NdjsonError::KeyInUse(_)) => StatusCode::CONFLICT,
+            PostError::Ndjson(_) => StatusCode::BAD_REQUEST,
         }
     }
 
@@ -521,7 +1527,7 @@ mod tests {
 
     use std::{collections::HashMap, sync::Arc};
 
-    use actix_web::test::TestRequest;
+    use actix_web::{http::header, test::TestRequest, web, FromRequest};
     use arrow_array::{
         types::Int64Type, ArrayRef, Float64Array, Int64Array, ListArray, StringArray,
     };
@@ -530,10 +1536,11 @@ mod tests {
 
     use crate::{
         event,
-        handlers::{PREFIX_META, PREFIX_TAGS},
+        handlers::{ATOMIC_KEY, PREFIX_META, PREFIX_TAGS, SEPARATOR, STREAM_NAME_HEADER_KEY},
+        utils::header_parsing::collect_labelled_headers,
     };
 
-    use super::into_event_batch;
+    use super::{ingest_ndjson, into_event_batch, msgpack, PostError};
 
     trait TestExt {
         fn as_int64_arr(&self) -> &Int64Array;
@@ -571,8 +1578,11 @@ mod tests {
             .append_header((PREFIX_TAGS.to_string() + "A", "tag1"))
             .append_header((PREFIX_META.to_string() + "C", "meta1"))
             .to_http_request();
+        let tags = collect_labelled_headers(&req, PREFIX_TAGS, SEPARATOR).unwrap();
+        let metadata = collect_labelled_headers(&req, PREFIX_META, SEPARATOR).unwrap();
 
-        let (rb, _) = into_event_batch(req, json, HashMap::default(), None, None).unwrap();
+        let (rb, _) =
+            into_event_batch(&tags, &metadata, json, HashMap::default(), None, None).unwrap();
 
         assert_eq!(rb.num_rows(), 1);
         assert_eq!(rb.num_columns(), 6);
@@ -602,6 +1612,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn msgpack_batch_matches_its_json_equivalent() {
+        let json = json!([
+            {"host": "web-1", "status": 200, "latency": 0.25, "retried": false, "tags": ["a", "b"]},
+            {"host": "web-2", "status": 503, "latency": 1.0, "body": null},
+            {"host": "web-3", "latency": 2},
+        ]);
+        let body = rmp_serde::to_vec(&json).unwrap();
+        let decoded = msgpack::decode(&body, msgpack::BinaryEncoding::default()).unwrap();
+
+        let (from_json, _) =
+            into_event_batch("", "", json, HashMap::default(), None, None).unwrap();
+        let (from_msgpack, _) =
+            into_event_batch("", "", decoded, HashMap::default(), None, None).unwrap();
+
+        // p_timestamp is the time of ingestion, every other column must match
+        let columns: Vec<usize> = (0..from_json.num_columns())
+            .filter(|&index| from_json.schema().field(index).name() != "p_timestamp")
+            .collect();
+        assert_eq!(from_json.schema(), from_msgpack.schema());
+        assert_eq!(
+            from_json.project(&columns).unwrap(),
+            from_msgpack.project(&columns).unwrap()
+        );
+        assert_eq!(from_msgpack.num_rows(), 3);
+    }
+
     #[test]
     fn basic_object_with_null_into_rb() {
         let json = json!({
@@ -610,9 +1647,7 @@ mod tests {
             "c": null
         });
 
-        let req = TestRequest::default().to_http_request();
-
-        let (rb, _) = into_event_batch(req, json, HashMap::default(), None, None).unwrap();
+        let (rb, _) = into_event_batch("", "", json, HashMap::default(), None, None).unwrap();
 
         assert_eq!(rb.num_rows(), 1);
         assert_eq!(rb.num_columns(), 6);
@@ -642,9 +1677,7 @@ mod tests {
             .into_iter(),
         );
 
-        let req = TestRequest::default().to_http_request();
-
-        let (rb, _) = into_event_batch(req, json, schema, None, None).unwrap();
+        let (rb, _) = into_event_batch("", "", json, schema, None, None).unwrap();
 
         assert_eq!(rb.num_rows(), 1);
         assert_eq!(rb.num_columns(), 5);
@@ -674,9 +1707,7 @@ mod tests {
             .into_iter(),
         );
 
-        let req = TestRequest::default().to_http_request();
-
-        assert!(into_event_batch(req, json, schema, None, None).is_err());
+        assert!(into_event_batch("", "", json, schema, None, None).is_err());
     }
 
     #[test]
@@ -692,9 +1723,7 @@ mod tests {
             .into_iter(),
         );
 
-        let req = TestRequest::default().to_http_request();
-
-        let (rb, _) = into_event_batch(req, json, schema, None, None).unwrap();
+        let (rb, _) = into_event_batch("", "", json, schema, None, None).unwrap();
 
         assert_eq!(rb.num_rows(), 1);
         assert_eq!(rb.num_columns(), 3);
@@ -704,9 +1733,7 @@ mod tests {
     fn non_object_arr_is_err() {
         let json = json!([1]);
 
-        let req = TestRequest::default().to_http_request();
-
-        assert!(into_event_batch(req, json, HashMap::default(), None, None).is_err())
+        assert!(into_event_batch("", "", json, HashMap::default(), None, None).is_err())
     }
 
     #[test]
@@ -727,9 +1754,7 @@ mod tests {
             },
         ]);
 
-        let req = TestRequest::default().to_http_request();
-
-        let (rb, _) = into_event_batch(req, json, HashMap::default(), None, None).unwrap();
+        let (rb, _) = into_event_batch("", "", json, HashMap::default(), None, None).unwrap();
 
         assert_eq!(rb.num_rows(), 3);
         assert_eq!(rb.num_columns(), 6);
@@ -775,9 +1800,7 @@ mod tests {
             },
         ]);
 
-        let req = TestRequest::default().to_http_request();
-
-        let (rb, _) = into_event_batch(req, json, HashMap::default(), None, None).unwrap();
+        let (rb, _) = into_event_batch("", "", json, HashMap::default(), None, None).unwrap();
 
         assert_eq!(rb.num_rows(), 3);
         assert_eq!(rb.num_columns(), 6);
@@ -823,9 +1846,7 @@ mod tests {
             ]
             .into_iter(),
         );
-        let req = TestRequest::default().to_http_request();
-
-        let (rb, _) = into_event_batch(req, json, schema, None, None).unwrap();
+        let (rb, _) = into_event_batch("", "", json, schema, None, None).unwrap();
 
         assert_eq!(rb.num_rows(), 3);
         assert_eq!(rb.num_columns(), 6);
@@ -863,8 +1884,6 @@ mod tests {
             },
         ]);
 
-        let req = TestRequest::default().to_http_request();
-
         let schema = fields_to_map(
             [
                 Field::new("a", DataType::Int64, true),
@@ -874,7 +1893,7 @@ mod tests {
             .into_iter(),
         );
 
-        assert!(into_event_batch(req, json, schema, None, None).is_err());
+        assert!(into_event_batch("", "", json, schema, None, None).is_err());
     }
 
     #[test]
@@ -900,9 +1919,7 @@ mod tests {
             },
         ]);
 
-        let req = TestRequest::default().to_http_request();
-
-        let (rb, _) = into_event_batch(req, json, HashMap::default(), None, None).unwrap();
+        let (rb, _) = into_event_batch("", "", json, HashMap::default(), None, None).unwrap();
 
         assert_eq!(rb.num_rows(), 4);
         assert_eq!(rb.num_columns(), 7);
@@ -941,4 +1958,220 @@ mod tests {
             &ListArray::from_iter_primitive::<Int64Type, _, _>(c_b)
         );
     }
+
+    #[actix_web::test]
+    async fn historical_events_are_queryable_at_their_event_time() {
+        use arrow_array::cast::AsArray;
+        use chrono::{NaiveDate, NaiveDateTime, Timelike};
+        use datafusion::{datasource::MemTable, prelude::SessionContext};
+
+        use crate::event::timestamp::{self, SkewAction, TimestampExtraction};
+        use crate::utils::{date_to_prefix, hour_to_prefix};
+
+        let arrival = NaiveDate::from_ymd_opt(2024, 1, 10)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let extraction = TimestampExtraction {
+            max_skew: Some(std::time::Duration::from_secs(365 * 24 * 3600)),
+            on_skew: SkewAction::Quarantine,
+            ..TimestampExtraction::new("ts")
+        };
+        // out of order, one unparseable and one older than the allowed skew
+        let events = [
+            json!({"ts": "2023-06-02T10:15:00Z", "msg": "b"}),
+            json!({"ts": "2023-06-01T08:00:00+00:00", "msg": "a"}),
+            json!({"ts": "not a time", "msg": "late"}),
+            json!({"ts": "2023-06-03T23:59:59Z", "msg": "c"}),
+            json!({"ts": "2001-01-01T00:00:00Z", "msg": "ancient"}),
+        ];
+
+        let mut batches = vec![];
+        let mut times: Vec<NaiveDateTime> = vec![];
+        for event in events {
+            let (time, source) = extraction.extract(&event, arrival).unwrap();
+            let (rb, _) = into_event_batch("", "", event, HashMap::new(), None, None).unwrap();
+            batches.push(timestamp::stamp(&rb, time, source));
+            times.push(time);
+        }
+        // staging is partitioned by the event time
+        assert_eq!(
+            date_to_prefix(times[1].date()) + &hour_to_prefix(times[1].hour()),
+            "date=2023-06-01/hour=08/"
+        );
+
+        let ctx = SessionContext::new();
+        ctx.register_table(
+            "app",
+            Arc::new(MemTable::try_new(batches[0].schema(), vec![batches]).unwrap()),
+        )
+        .unwrap();
+        let query = |sql: &'static str| {
+            let ctx = ctx.clone();
+            async move {
+                let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+                batches
+                    .iter()
+                    .flat_map(|batch| {
+                        (0..batch.num_rows()).map(|row| {
+                            (0..batch.num_columns())
+                                .map(|column| batch.column(column).as_string::<i32>().value(row))
+                                .collect::<Vec<_>>()
+                                .join(" ")
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            query(
+                "SELECT msg FROM app WHERE p_timestamp >= '2023-06-01T00:00:00' \
+                 AND p_timestamp < '2023-06-03T00:00:00' ORDER BY p_timestamp"
+            )
+            .await,
+            ["a", "b"]
+        );
+        assert_eq!(
+            query("SELECT msg, p_timestamp_source FROM app ORDER BY p_timestamp, msg").await,
+            [
+                "a event",
+                "b event",
+                "c event",
+                "ancient quarantine",
+                "late arrival"
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn atomic_ndjson_is_rejected() {
+        let (req, mut payload) = TestRequest::post()
+            .insert_header((header::CONTENT_TYPE, "application/x-ndjson"))
+            .insert_header((STREAM_NAME_HEADER_KEY, "app"))
+            .insert_header((ATOMIC_KEY, "true"))
+            .set_payload("{\"a\": 1}\n{\"a\": 2}\n")
+            .to_http_parts();
+        let payload = web::Payload::from_request(&req, &mut payload)
+            .await
+            .unwrap();
+
+        let err = ingest_ndjson(req, payload).await.unwrap_err();
+        assert!(matches!(err, PostError::Invalid(_)), "{err}");
+        assert!(err.to_string().contains(ATOMIC_KEY), "{err}");
+    }
+
+    #[actix_web::test]
+    async fn type_mismatch_is_dead_lettered_with_its_reason() {
+        use std::sync::Mutex;
+
+        use arrow_array::cast::AsArray;
+        use chrono::Utc;
+        use serde_json::Value;
+
+        use super::create_process_record_batch_with;
+        use crate::{
+            event::error::EventError, handlers::http::dead_letter, metadata::STREAM_INFO,
+            metrics::DEAD_LETTER_EVENTS,
+        };
+
+        // streams of their own as the stream metadata is shared between tests
+        let (stream, target) = ("dead_letter_app", "dead_letter_app_rejected");
+        let schema = HashMap::from([(
+            "status".to_string(),
+            Arc::new(Field::new("status", DataType::Int64, true)),
+        )]);
+        for (name, schema) in [(stream, schema), (target, HashMap::new())] {
+            STREAM_INFO.add_stream(
+                name.to_string(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                schema,
+            );
+        }
+        STREAM_INFO
+            .set_dead_letter_stream(stream, Some(target.to_string()))
+            .unwrap();
+        let dead_letter = dead_letter::target(stream).unwrap();
+        assert_eq!(dead_letter.as_deref(), Some(target));
+        let counted = DEAD_LETTER_EVENTS
+            .with_label_values(&[stream, "schema"])
+            .get();
+
+        // the events handed to the stream writers
+        let staged = Mutex::new(Vec::new());
+        let process = |event: event::Event| {
+            staged.lock().unwrap().push(event);
+            async { Ok::<_, EventError>(()) }
+        };
+        let body = json!([{"status": 200}, {"status": "ok"}, {"status": 404}]);
+        create_process_record_batch_with(
+            stream.to_string(),
+            "",
+            "",
+            body.clone(),
+            None,
+            None,
+            Utc::now().naive_utc(),
+            None,
+            HashMap::new(),
+            body.to_string().len() as u64,
+            dead_letter.as_deref(),
+            &process,
+        )
+        .await
+        .unwrap();
+
+        let (rejected, ingested): (Vec<_>, Vec<_>) = staged
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .partition(|event| event.stream_name == target);
+
+        // the events around the rejected one are still ingested
+        assert!(ingested.iter().all(|event| event.stream_name == stream));
+        let statuses: Vec<i64> = ingested
+            .iter()
+            .flat_map(|event| {
+                event
+                    .rb
+                    .column_by_name("status")
+                    .unwrap()
+                    .as_primitive::<Int64Type>()
+                    .values()
+                    .to_vec()
+            })
+            .collect();
+        assert_eq!(statuses, [200, 404]);
+
+        assert_eq!(rejected.len(), 1);
+        let row = &rejected[0].rb;
+        assert_eq!(row.num_rows(), 1);
+        let column = |name: &str| {
+            row.column_by_name(name)
+                .unwrap()
+                .as_string::<i32>()
+                .value(0)
+                .to_string()
+        };
+        assert_eq!(column(dead_letter::SOURCE_STREAM_KEY), stream);
+        assert_eq!(column(dead_letter::REASON_KEY), "schema");
+        assert!(column(dead_letter::ERROR_KEY).contains("mismatch in datatype"));
+        assert_eq!(
+            serde_json::from_str::<Value>(&column(dead_letter::PAYLOAD_KEY)).unwrap(),
+            json!({"status": "ok"})
+        );
+        assert_eq!(
+            DEAD_LETTER_EVENTS
+                .with_label_values(&[stream, "schema"])
+                .get(),
+            counted + 1
+        );
+
+        STREAM_INFO.delete_stream(stream);
+        STREAM_INFO.delete_stream(target);
+    }
 }
@@ -21,6 +21,10 @@ use super::base_path_without_preceding_slash;
 use super::cluster::utils::{merge_quried_stats, IngestionStats, QueriedStats, StorageStats};
 use super::cluster::{fetch_daily_stats_from_ingestors, fetch_stats_from_ingestors};
 use crate::alerts::Alerts;
+use crate::event::format::grok::{GrokParser, GrokSettings};
+use crate::event::format::protobuf::{ProtobufDecoder, ProtobufSchema};
+use crate::event::timestamp::TimestampExtraction;
+use crate::event::transform::{TransformRule, TransformRules, Transformer};
 use crate::handlers::{
     CUSTOM_PARTITION_KEY, STATIC_SCHEMA_FLAG, TIME_PARTITION_KEY, TIME_PARTITION_LIMIT_KEY,
     UPDATE_STREAM_KEY,
@@ -28,9 +32,11 @@ use crate::handlers::{
 use crate::metadata::STREAM_INFO;
 use crate::metrics::{EVENTS_INGESTED_DATE, EVENTS_INGESTED_SIZE_DATE, EVENTS_STORAGE_SIZE_DATE};
 use crate::option::{Mode, CONFIG};
+use crate::query::result_cache::RESULT_CACHE;
 use crate::static_schema::{convert_static_schema_to_arrow_schema, StaticSchema};
 use crate::stats::{event_labels_date, storage_size_labels_date, Stats};
 use crate::storage::{retention::Retention, LogStream, StorageDir, StreamInfo};
+use crate::utils::json::flatten::FlattenOptions;
 use crate::{
     catalog::{self, remove_manifest_from_snapshot},
     event, stats,
@@ -89,6 +95,9 @@ pub async fn delete(req: HttpRequest) -> Result<impl Responder, StreamError> {
 
     metadata::STREAM_INFO.delete_stream(&stream_name);
     event::STREAM_WRITERS.delete_stream(&stream_name);
+    if let Some(result_cache) = RESULT_CACHE.as_ref() {
+        result_cache.invalidate_stream(&stream_name);
+    }
     stats::delete_stats(&stream_name, "json").unwrap_or_else(|e| {
         log::warn!("failed to delete stats for stream {}: {:?}", stream_name, e)
     });
@@ -551,6 +560,372 @@ pub async fn put_enable_cache(
         StatusCode::OK,
     ))
 }
+pub async fn get_protobuf(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    match STREAM_INFO.get_protobuf(&stream_name)? {
+        Some(decoder) => Ok((web::Json(decoder.schema().clone()), StatusCode::OK)),
+        None => Err(StreamError::NoProtobufSet),
+    }
+}
+
+pub async fn put_protobuf(
+    req: HttpRequest,
+    body: web::Json<ProtobufSchema>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let storage = CONFIG.storage().get_object_store();
+
+    if CONFIG.parseable.mode == Mode::Ingest && !STREAM_INFO.stream_exists(&stream_name) {
+        // here the ingest server has not found the stream
+        // so it should check if the stream exists in storage
+        metadata::STREAM_INFO
+            .upsert_stream_info(
+                &*storage,
+                LogStream {
+                    name: stream_name.clone(),
+                },
+            )
+            .await
+            .map_err(|_| StreamError::StreamNotFound(stream_name.clone()))?;
+    }
+
+    let decoder = ProtobufDecoder::new(body.into_inner())?;
+    let previous = STREAM_INFO.get_protobuf(&stream_name)?;
+    let schema = STREAM_INFO.schema(&stream_name)?;
+    decoder.check_compatible(previous.as_ref(), &schema)?;
+
+    let mut stream_metadata = storage.get_object_store_format(&stream_name).await?;
+    stream_metadata.protobuf = Some(decoder.schema().clone());
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    let message = decoder.schema().message.clone();
+    STREAM_INFO.set_protobuf(&stream_name, decoder)?;
+    Ok((
+        format!("Protobuf message {message} registered for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_grok(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    match STREAM_INFO.get_grok(&stream_name)? {
+        Some(grok) => Ok((web::Json(grok.settings().clone()), StatusCode::OK)),
+        None => Err(StreamError::NoGrokSet),
+    }
+}
+
+pub async fn put_grok(
+    req: HttpRequest,
+    body: web::Json<GrokSettings>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let storage = CONFIG.storage().get_object_store();
+
+    if CONFIG.parseable.mode == Mode::Ingest && !STREAM_INFO.stream_exists(&stream_name) {
+        // here the ingest server has not found the stream
+        // so it should check if the stream exists in storage
+        metadata::STREAM_INFO
+            .upsert_stream_info(
+                &*storage,
+                LogStream {
+                    name: stream_name.clone(),
+                },
+            )
+            .await
+            .map_err(|_| StreamError::StreamNotFound(stream_name.clone()))?;
+    }
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let grok = GrokParser::new(body.into_inner())?;
+
+    let mut stream_metadata = storage.get_object_store_format(&stream_name).await?;
+    stream_metadata.grok = Some(grok.settings().clone());
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_grok(&stream_name, grok)?;
+    Ok((
+        format!("Grok pattern updated for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_timestamp_extraction(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    match STREAM_INFO.get_timestamp_extraction(&stream_name)? {
+        Some(extraction) => Ok((web::Json(extraction), StatusCode::OK)),
+        None => Err(StreamError::NoTimestampExtractionSet),
+    }
+}
+
+pub async fn put_timestamp_extraction(
+    req: HttpRequest,
+    body: web::Json<TimestampExtraction>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let storage = CONFIG.storage().get_object_store();
+
+    if CONFIG.parseable.mode == Mode::Ingest && !STREAM_INFO.stream_exists(&stream_name) {
+        // here the ingest server has not found the stream
+        // so it should check if the stream exists in storage
+        metadata::STREAM_INFO
+            .upsert_stream_info(
+                &*storage,
+                LogStream {
+                    name: stream_name.clone(),
+                },
+            )
+            .await
+            .map_err(|_| StreamError::StreamNotFound(stream_name.clone()))?;
+    }
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let extraction = body.into_inner();
+    extraction.validate()?;
+
+    let mut stream_metadata = storage.get_object_store_format(&stream_name).await?;
+    stream_metadata.timestamp_extraction = Some(extraction.clone());
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    let field = extraction.field.clone();
+    STREAM_INFO.set_timestamp_extraction(&stream_name, extraction)?;
+    Ok((
+        format!("Event time of log stream {stream_name} is read from field {field}"),
+        StatusCode::OK,
+    ))
+}
+
+// streams without settings are flattened with the defaults
+pub async fn get_flattening(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let flattening = STREAM_INFO.get_flattening(&stream_name)?;
+    Ok((web::Json(flattening.unwrap_or_default()), StatusCode::OK))
+}
+
+// only events ingested after the change are flattened with the new settings
+pub async fn put_flattening(
+    req: HttpRequest,
+    body: web::Json<FlattenOptions>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let storage = CONFIG.storage().get_object_store();
+
+    if CONFIG.parseable.mode == Mode::Ingest && !STREAM_INFO.stream_exists(&stream_name) {
+        // here the ingest server has not found the stream
+        // so it should check if the stream exists in storage
+        metadata::STREAM_INFO
+            .upsert_stream_info(
+                &*storage,
+                LogStream {
+                    name: stream_name.clone(),
+                },
+            )
+            .await
+            .map_err(|_| StreamError::StreamNotFound(stream_name.clone()))?;
+    }
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let flattening = body.into_inner();
+    flattening.validate().map_err(|err| StreamError::Custom {
+        msg: err.to_string(),
+        status: StatusCode::BAD_REQUEST,
+    })?;
+
+    let mut stream_metadata = storage.get_object_store_format(&stream_name).await?;
+    stream_metadata.flattening = Some(flattening.clone());
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_flattening(&stream_name, flattening)?;
+    Ok((
+        format!("Flattening updated for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_type_widening(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let allowed = STREAM_INFO.type_widening_allowed(&stream_name)?;
+    Ok((web::Json(allowed), StatusCode::OK))
+}
+
+// widening only applies to streams whose schema is not static
+pub async fn put_type_widening(
+    req: HttpRequest,
+    body: web::Json<bool>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let storage = CONFIG.storage().get_object_store();
+
+    if CONFIG.parseable.mode == Mode::Ingest && !STREAM_INFO.stream_exists(&stream_name) {
+        // here the ingest server has not found the stream
+        // so it should check if the stream exists in storage
+        metadata::STREAM_INFO
+            .upsert_stream_info(
+                &*storage,
+                LogStream {
+                    name: stream_name.clone(),
+                },
+            )
+            .await
+            .map_err(|_| StreamError::StreamNotFound(stream_name.clone()))?;
+    }
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let allow = body.into_inner();
+    if allow && STREAM_INFO.get_static_schema_flag(&stream_name)?.is_some() {
+        return Err(StreamError::Custom {
+            msg: "Column types of a stream with a static schema cannot be widened".to_string(),
+            status: StatusCode::BAD_REQUEST,
+        });
+    }
+
+    let mut stream_metadata = storage.get_object_store_format(&stream_name).await?;
+    stream_metadata.allow_type_widening = allow;
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_type_widening(&stream_name, allow)?;
+    Ok((
+        format!("Type widening set to {allow} for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+pub async fn get_dead_letter_stream(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let dead_letter_stream = STREAM_INFO.get_dead_letter_stream(&stream_name)?;
+    Ok((web::Json(dead_letter_stream), StatusCode::OK))
+}
+
+// the stream's own dead letter stream, null goes back to the one of the server
+pub async fn put_dead_letter_stream(
+    req: HttpRequest,
+    body: web::Json<Option<String>>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let storage = CONFIG.storage().get_object_store();
+
+    if CONFIG.parseable.mode == Mode::Ingest && !STREAM_INFO.stream_exists(&stream_name) {
+        // here the ingest server has not found the stream
+        // so it should check if the stream exists in storage
+        metadata::STREAM_INFO
+            .upsert_stream_info(
+                &*storage,
+                LogStream {
+                    name: stream_name.clone(),
+                },
+            )
+            .await
+            .map_err(|_| StreamError::StreamNotFound(stream_name.clone()))?;
+    }
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let dead_letter_stream = body.into_inner();
+    if let Some(dead_letter_stream) = &dead_letter_stream {
+        validator::stream_name(dead_letter_stream).map_err(|err| StreamError::Custom {
+            msg: err.to_string(),
+            status: StatusCode::BAD_REQUEST,
+        })?;
+        if *dead_letter_stream == stream_name {
+            return Err(StreamError::Custom {
+                msg: "A stream cannot be its own dead letter stream".to_string(),
+                status: StatusCode::BAD_REQUEST,
+            });
+        }
+    }
+
+    let mut stream_metadata = storage.get_object_store_format(&stream_name).await?;
+    stream_metadata.dead_letter_stream = dead_letter_stream.clone();
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_dead_letter_stream(&stream_name, dead_letter_stream)?;
+    Ok((
+        format!("Dead letter stream updated for log stream {stream_name}"),
+        StatusCode::OK,
+    ))
+}
+
+// streams without rules get an empty set at version 0
+pub async fn get_transforms(req: HttpRequest) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let rules = STREAM_INFO
+        .get_transformer(&stream_name)?
+        .map(|transformer| transformer.rules().clone())
+        .unwrap_or_default();
+    Ok((web::Json(rules), StatusCode::OK))
+}
+
+// replaces the transform rules of the stream under the next version, the
+// rules replaced are kept in the stream metadata
+pub async fn put_transforms(
+    req: HttpRequest,
+    body: web::Json<Vec<TransformRule>>,
+) -> Result<impl Responder, StreamError> {
+    let stream_name: String = req.match_info().get("logstream").unwrap().parse().unwrap();
+    let storage = CONFIG.storage().get_object_store();
+
+    if CONFIG.parseable.mode == Mode::Ingest && !STREAM_INFO.stream_exists(&stream_name) {
+        // here the ingest server has not found the stream
+        // so it should check if the stream exists in storage
+        metadata::STREAM_INFO
+            .upsert_stream_info(
+                &*storage,
+                LogStream {
+                    name: stream_name.clone(),
+                },
+            )
+            .await
+            .map_err(|_| StreamError::StreamNotFound(stream_name.clone()))?;
+    }
+    if !STREAM_INFO.stream_exists(&stream_name) {
+        return Err(StreamError::StreamNotFound(stream_name));
+    }
+
+    let mut stream_metadata = storage.get_object_store_format(&stream_name).await?;
+    let previous = stream_metadata.transforms.take();
+    let rules = TransformRules {
+        version: previous.as_ref().map_or(0, |previous| previous.version) + 1,
+        rules: body.into_inner(),
+    };
+    let transformer = Transformer::new(rules.clone()).map_err(|err| StreamError::Custom {
+        msg: err.to_string(),
+        status: StatusCode::BAD_REQUEST,
+    })?;
+
+    let version = rules.version;
+    stream_metadata.transform_history.extend(previous);
+    stream_metadata.transforms = Some(rules);
+    storage
+        .put_stream_manifest(&stream_name, &stream_metadata)
+        .await?;
+
+    STREAM_INFO.set_transformer(&stream_name, transformer)?;
+    Ok((
+        format!("Transform rules of log stream {stream_name} updated to version {version}"),
+        StatusCode::OK,
+    ))
+}
+
 pub async fn get_stats_date(stream_name: &str, date: &str) -> Result<Stats, StreamError> {
     let event_labels = event_labels_date(stream_name, "json", date);
     let storage_size_labels = storage_size_labels_date(stream_name, date);
@@ -867,6 +1242,10 @@ pub mod error {
     use http::StatusCode;
 
     use crate::{
+        event::{
+            format::{grok::GrokError, protobuf::ProtobufError},
+            timestamp::TimestampError,
+        },
         metadata::error::stream_info::MetadataError,
         storage::ObjectStorageError,
         validator::error::{AlertValidationError, StreamNameValidationError},
@@ -906,6 +1285,18 @@ pub mod error {
         Storage(#[from] ObjectStorageError),
         #[error("No alerts configured for this stream")]
         NoAlertsSet,
+        #[error("No protobuf descriptor registered for this stream")]
+        NoProtobufSet,
+        #[error("{0}")]
+        Protobuf(#[from] ProtobufError),
+        #[error("No grok pattern set for this stream")]
+        NoGrokSet,
+        #[error("{0}")]
+        Grok(#[from] GrokError),
+        #[error("No timestamp extraction configured for this stream")]
+        NoTimestampExtractionSet,
+        #[error("{0}")]
+        Timestamp(#[from] TimestampError),
         #[error("failed to set alert configuration for log stream {stream} due to err: {err}")]
         BadAlertJson {
             stream: String,
@@ -952,6 +1343,12 @@ pub mod error {
                 StreamError::UninitializedLogstream => StatusCode::METHOD_NOT_ALLOWED,
                 StreamError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
                 StreamError::NoAlertsSet => StatusCode::NOT_FOUND,
+                StreamError::NoProtobufSet => StatusCode::NOT_FOUND,
+                StreamError::Protobuf(_) => StatusCode::BAD_REQUEST,
+                StreamError::NoGrokSet => StatusCode::NOT_FOUND,
+                StreamError::Grok(_) => StatusCode::BAD_REQUEST,
+                StreamError::NoTimestampExtractionSet => StatusCode::NOT_FOUND,
+                StreamError::Timestamp(_) => StatusCode::BAD_REQUEST,
                 StreamError::BadAlertJson { .. } => StatusCode::BAD_REQUEST,
                 StreamError::AlertValidation(_) => StatusCode::BAD_REQUEST,
                 StreamError::InvalidAlert(_) => StatusCode::BAD_REQUEST,
@@ -330,7 +330,8 @@ where
 
             Mode::Ingest => {
                 let accessable_endpoints = ["ingest", "logstream", "liveness", "readiness"];
-                let cond = path.split('/').any(|x| accessable_endpoints.contains(&x));
+                let cond = path == CONFIG.parseable.health_check_path
+                    || path.split('/').any(|x| accessable_endpoints.contains(&x));
                 if !cond {
                     Box::pin(async {
                         Err(actix_web::error::ErrorUnauthorized(
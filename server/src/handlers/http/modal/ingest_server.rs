@@ -20,6 +20,7 @@ use crate::banner;
 use crate::handlers::airplane;
 use crate::handlers::http::logstream;
 use crate::handlers::http::middleware::RouteExt;
+use crate::handlers::syslog;
 use crate::localcache::LocalCacheManager;
 use crate::metrics;
 use crate::migration;
@@ -34,7 +35,9 @@ use crate::storage::ObjectStorageError;
 use crate::sync;
 
 use super::server::Server;
-use super::ssl_acceptor::get_ssl_acceptor;
+use super::ssl_acceptor::{get_ssl_acceptor, spawn_reload};
+#[cfg(unix)]
+use super::uds::uds_listener;
 use super::IngestorMetadata;
 use super::OpenIdClient;
 use super::ParseableServer;
@@ -75,8 +78,9 @@ impl ParseableServer for IngestServer {
 
         // get the ssl stuff
         let ssl = get_ssl_acceptor(
-            &CONFIG.parseable.tls_cert_path,
-            &CONFIG.parseable.tls_key_path,
+            CONFIG.parseable.tls_identity(),
+            CONFIG.parseable.tls_min_version,
+            &CONFIG.parseable.tls_cipher_suites,
         )?;
 
         // fn that creates the app
@@ -92,13 +96,28 @@ impl ParseableServer for IngestServer {
         // concurrent workers equal to number of logical cores
         let http_server = HttpServer::new(create_app_fn).workers(num_cpus::get());
 
-        if let Some(config) = ssl {
+        if let Some(path) = &CONFIG.parseable.uds_path {
+            #[cfg(unix)]
             http_server
-                .bind_rustls_0_22(&CONFIG.parseable.address, config)?
+                .listen_uds(uds_listener(path, CONFIG.parseable.uds_permissions)?)?
+                .run()
+                .await?;
+            #[cfg(not(unix))]
+            return Err(anyhow::anyhow!(
+                "Unix domain socket {} is not supported on this platform",
+                path.display()
+            ));
+        } else if let Some((config, resolver)) = ssl {
+            spawn_reload(resolver);
+            http_server
+                .bind_rustls_0_22(&CONFIG.parseable.bind_addrs()?[..], config)?
                 .run()
                 .await?;
         } else {
-            http_server.bind(&CONFIG.parseable.address)?.run().await?;
+            http_server
+                .bind(&CONFIG.parseable.bind_addrs()?[..])?
+                .run()
+                .await?;
         }
 
         Ok(())
@@ -143,11 +162,15 @@ impl IngestServer {
                     .service(Server::get_ingest_factory())
                     .service(Self::logstream_api())
                     .service(Server::get_about_factory())
+                    .service(Server::get_connectors_factory())
                     .service(Self::analytics_factory())
                     .service(Server::get_liveness_factory())
                     .service(Server::get_readiness_factory()),
             )
-            .service(Server::get_ingest_otel_factory());
+            .service(Server::get_ingest_otel_factory())
+            .service(Server::get_ingest_otel_traces_factory())
+            .service(Server::get_ingest_otel_metrics_factory())
+            .service(Server::get_health_check_factory());
     }
 
     fn analytics_factory() -> Scope {
@@ -202,6 +225,111 @@ impl IngestServer {
                                 .authorize_for_stream(Action::GetCacheEnabled),
                         ),
                 )
+                .service(
+                    web::resource("/protobuf")
+                        // PUT "/logstream/{logstream}/protobuf" ==> Register the protobuf descriptor for given logstream
+                        .route(
+                            web::put()
+                                .to(logstream::put_protobuf)
+                                .authorize_for_stream(Action::PutProtobuf),
+                        )
+                        // GET "/logstream/{logstream}/protobuf" ==> Get the protobuf descriptor for given logstream
+                        .route(
+                            web::get()
+                                .to(logstream::get_protobuf)
+                                .authorize_for_stream(Action::GetProtobuf),
+                        ),
+                )
+                .service(
+                    web::resource("/timestamp")
+                        // PUT "/logstream/{logstream}/timestamp" ==> Set the event timestamp extraction for given logstream
+                        .route(
+                            web::put()
+                                .to(logstream::put_timestamp_extraction)
+                                .authorize_for_stream(Action::PutTimestampExtraction),
+                        )
+                        // GET "/logstream/{logstream}/timestamp" ==> Get the event timestamp extraction for given logstream
+                        .route(
+                            web::get()
+                                .to(logstream::get_timestamp_extraction)
+                                .authorize_for_stream(Action::GetTimestampExtraction),
+                        ),
+                )
+                .service(
+                    web::resource("/flattening")
+                        // PUT "/logstream/{logstream}/flattening" ==> Set how events are flattened for given logstream
+                        .route(
+                            web::put()
+                                .to(logstream::put_flattening)
+                                .authorize_for_stream(Action::PutFlattening),
+                        )
+                        // GET "/logstream/{logstream}/flattening" ==> Get how events are flattened for given logstream
+                        .route(
+                            web::get()
+                                .to(logstream::get_flattening)
+                                .authorize_for_stream(Action::GetFlattening),
+                        ),
+                )
+                .service(
+                    web::resource("/type-widening")
+                        // PUT "/logstream/{logstream}/type-widening" ==> Allow or disallow widening column types for given logstream
+                        .route(
+                            web::put()
+                                .to(logstream::put_type_widening)
+                                .authorize_for_stream(Action::PutTypeWidening),
+                        )
+                        // GET "/logstream/{logstream}/type-widening" ==> Get whether column types are widened for given logstream
+                        .route(
+                            web::get()
+                                .to(logstream::get_type_widening)
+                                .authorize_for_stream(Action::GetTypeWidening),
+                        ),
+                )
+                .service(
+                    web::resource("/dead-letter")
+                        // PUT "/logstream/{logstream}/dead-letter" ==> Set the dead letter stream of given logstream
+                        .route(
+                            web::put()
+                                .to(logstream::put_dead_letter_stream)
+                                .authorize_for_stream(Action::PutDeadLetterStream),
+                        )
+                        // GET "/logstream/{logstream}/dead-letter" ==> Get the dead letter stream of given logstream
+                        .route(
+                            web::get()
+                                .to(logstream::get_dead_letter_stream)
+                                .authorize_for_stream(Action::GetDeadLetterStream),
+                        ),
+                )
+                .service(
+                    web::resource("/transforms")
+                        // PUT "/logstream/{logstream}/transforms" ==> Set the transform rules of given logstream
+                        .route(
+                            web::put()
+                                .to(logstream::put_transforms)
+                                .authorize_for_stream(Action::PutTransforms),
+                        )
+                        // GET "/logstream/{logstream}/transforms" ==> Get the transform rules of given logstream
+                        .route(
+                            web::get()
+                                .to(logstream::get_transforms)
+                                .authorize_for_stream(Action::GetTransforms),
+                        ),
+                )
+                .service(
+                    web::resource("/grok")
+                        // PUT "/logstream/{logstream}/grok" ==> Set the grok pattern of given logstream
+                        .route(
+                            web::put()
+                                .to(logstream::put_grok)
+                                .authorize_for_stream(Action::PutGrok),
+                        )
+                        // GET "/logstream/{logstream}/grok" ==> Get the grok pattern of given logstream
+                        .route(
+                            web::get()
+                                .to(logstream::get_grok)
+                                .authorize_for_stream(Action::GetGrok),
+                        ),
+                )
                 .service(
                     web::scope("/retention").service(
                         web::resource("/cleanup").route(
@@ -315,6 +443,14 @@ impl IngestServer {
                 .await?;
         };
 
+        #[cfg(feature = "kafka")]
+        let kafka = crate::handlers::kafka::KafkaConfig::from_cli(&CONFIG.parseable)?;
+        #[cfg(feature = "nats")]
+        let nats = crate::handlers::nats::NatsConfig::from_cli(&CONFIG.parseable)?;
+        #[cfg(feature = "amqp")]
+        let amqp = crate::handlers::amqp::AmqpConfig::from_cli(&CONFIG.parseable)?;
+        let file_input = crate::handlers::file_input::FileInputConfig::from_cli(&CONFIG.parseable)?;
+
         let prometheus = metrics::build_metrics_handler();
         CONFIG.storage().register_store_metrics(&prometheus);
 
@@ -325,6 +461,20 @@ impl IngestServer {
             sync::object_store_sync();
 
         tokio::spawn(airplane::server());
+        tokio::spawn(syslog::server());
+        #[cfg(feature = "kafka")]
+        let mut kafka = crate::handlers::kafka::Connector::spawn(kafka);
+        #[cfg(feature = "nats")]
+        if let Some(nats) = nats {
+            tokio::spawn(crate::handlers::nats::server(nats));
+        }
+        #[cfg(feature = "amqp")]
+        if let Some(amqp) = amqp {
+            tokio::spawn(crate::handlers::amqp::server(amqp));
+        }
+        if let Some(file_input) = file_input {
+            tokio::spawn(crate::handlers::file_input::server(file_input));
+        }
 
         let app = self.start(prometheus, CONFIG.parseable.openid.clone());
 
@@ -333,6 +483,8 @@ impl IngestServer {
             tokio::select! {
                 e = &mut app => {
                     // actix server finished .. stop other threads and stop the server
+                    #[cfg(feature = "kafka")]
+                    kafka.stop().await;
                     remote_sync_inbox.send(()).unwrap_or(());
                     localsync_inbox.send(()).unwrap_or(());
                     localsync_handler.join().unwrap_or(());
@@ -20,6 +20,8 @@ pub mod ingest_server;
 pub mod query_server;
 pub mod server;
 pub mod ssl_acceptor;
+#[cfg(unix)]
+pub mod uds;
 
 use std::sync::Arc;
 
@@ -22,9 +22,12 @@ use crate::handlers::http::middleware::RouteExt;
 use crate::handlers::http::{base_path, cross_origin_config, API_BASE_PATH, API_VERSION};
 
 use crate::rbac::role::Action;
+use crate::scheduled::{self, SCHEDULED_QUERIES};
 use crate::sync;
+use crate::users::correlations::CORRELATIONS;
 use crate::users::dashboards::DASHBOARDS;
 use crate::users::filters::FILTERS;
+use crate::users::saved_queries::SAVED_QUERIES;
 use crate::{analytics, banner, metrics, migration, rbac, storage};
 use actix_web::web;
 use actix_web::web::ServiceConfig;
@@ -35,7 +38,9 @@ use std::sync::Arc;
 use crate::option::CONFIG;
 
 use super::server::Server;
-use super::ssl_acceptor::get_ssl_acceptor;
+use super::ssl_acceptor::{get_ssl_acceptor, spawn_reload};
+#[cfg(unix)]
+use super::uds::uds_listener;
 use super::{OpenIdClient, ParseableServer};
 
 #[derive(Default, Debug)]
@@ -60,8 +65,9 @@ impl ParseableServer for QueryServer {
         };
 
         let ssl = get_ssl_acceptor(
-            &CONFIG.parseable.tls_cert_path,
-            &CONFIG.parseable.tls_key_path,
+            CONFIG.parseable.tls_identity(),
+            CONFIG.parseable.tls_min_version,
+            &CONFIG.parseable.tls_cipher_suites,
         )?;
 
         let create_app_fn = move || {
@@ -75,13 +81,28 @@ impl ParseableServer for QueryServer {
 
         // concurrent workers equal to number of cores on the cpu
         let http_server = HttpServer::new(create_app_fn).workers(num_cpus::get());
-        if let Some(config) = ssl {
+        if let Some(path) = &CONFIG.parseable.uds_path {
+            #[cfg(unix)]
             http_server
-                .bind_rustls_0_22(&CONFIG.parseable.address, config)?
+                .listen_uds(uds_listener(path, CONFIG.parseable.uds_permissions)?)?
+                .run()
+                .await?;
+            #[cfg(not(unix))]
+            return Err(anyhow::anyhow!(
+                "Unix domain socket {} is not supported on this platform",
+                path.display()
+            ));
+        } else if let Some((config, resolver)) = ssl {
+            spawn_reload(resolver);
+            http_server
+                .bind_rustls_0_22(&CONFIG.parseable.bind_addrs()?[..], config)?
                 .run()
                 .await?;
         } else {
-            http_server.bind(&CONFIG.parseable.address)?.run().await?;
+            http_server
+                .bind(&CONFIG.parseable.bind_addrs()?[..])?
+                .run()
+                .await?;
         }
 
         Ok(())
@@ -121,6 +142,10 @@ impl QueryServer {
                 web::scope(&base_path())
                     // POST "/query" ==> Get results of the SQL query passed in request body
                     .service(Server::get_query_factory())
+                    .service(Server::get_query_concurrency_factory())
+                    .service(Server::get_running_queries_factory())
+                    .service(Server::get_query_functions_factory())
+                    .service(Server::get_cancel_query_factory())
                     .service(Server::get_cache_webscope())
                     .service(Server::get_liveness_factory())
                     .service(Server::get_readiness_factory())
@@ -129,11 +154,15 @@ impl QueryServer {
                     .service(Server::get_user_webscope())
                     .service(Server::get_dashboards_webscope())
                     .service(Server::get_filters_webscope())
+                    .service(Server::get_correlations_webscope())
+                    .service(Server::get_saved_queries_webscope())
+                    .service(Server::get_scheduled_queries_webscope())
                     .service(Server::get_llm_webscope())
                     .service(Server::get_oauth_webscope(oidc_client))
                     .service(Server::get_user_role_webscope())
                     .service(Self::get_cluster_web_scope()),
             )
+            .service(Server::get_health_check_factory())
             .service(Server::get_generated());
     }
 
@@ -176,6 +205,10 @@ impl QueryServer {
 
         FILTERS.load().await?;
         DASHBOARDS.load().await?;
+        CORRELATIONS.load().await?;
+        SAVED_QUERIES.load().await?;
+        SCHEDULED_QUERIES.load().await?;
+        scheduled::init_scheduler();
         // track all parquet files already in the data directory
         storage::retention::load_retention_from_global();
 
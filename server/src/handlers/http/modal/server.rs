@@ -22,26 +22,33 @@ use crate::handlers;
 use crate::handlers::http::about;
 use crate::handlers::http::base_path;
 use crate::handlers::http::cache;
+use crate::handlers::http::connectors;
+use crate::handlers::http::dead_letter;
 use crate::handlers::http::health_check;
 use crate::handlers::http::query;
+use crate::handlers::http::users::correlations;
 use crate::handlers::http::users::dashboards;
 use crate::handlers::http::users::filters;
+use crate::handlers::http::users::saved_queries;
 use crate::handlers::http::API_BASE_PATH;
 use crate::handlers::http::API_VERSION;
 use crate::localcache::LocalCacheManager;
 use crate::metrics;
 use crate::migration;
 use crate::rbac;
+use crate::scheduled::{self, SCHEDULED_QUERIES};
 use crate::storage;
 use crate::sync;
+use crate::users::correlations::CORRELATIONS;
 use crate::users::dashboards::DASHBOARDS;
 use crate::users::filters::FILTERS;
+use crate::users::saved_queries::SAVED_QUERIES;
 use std::sync::Arc;
 
 use actix_web::web::resource;
 use actix_web::Resource;
 use actix_web::Scope;
-use actix_web::{web, App, HttpServer};
+use actix_web::{guard, web, App, HttpServer};
 use actix_web_prometheus::PrometheusMetrics;
 use actix_web_static_files::ResourceFiles;
 use async_trait::async_trait;
@@ -50,7 +57,7 @@ use crate::{
     handlers::http::{
         self, cross_origin_config, ingest, llm, logstream,
         middleware::{DisAllowRootUser, RouteExt},
-        oidc, role, MAX_EVENT_PAYLOAD_SIZE,
+        ndjson, oidc, role, MAX_EVENT_PAYLOAD_SIZE,
     },
     option::CONFIG,
     rbac::role::Action,
@@ -58,7 +65,9 @@ use crate::{
 
 // use super::generate;
 use super::generate;
-use super::ssl_acceptor::get_ssl_acceptor;
+use super::ssl_acceptor::{get_ssl_acceptor, spawn_reload};
+#[cfg(unix)]
+use super::uds::uds_listener;
 use super::OpenIdClient;
 use super::ParseableServer;
 
@@ -92,19 +101,35 @@ impl ParseableServer for Server {
         };
 
         let ssl = get_ssl_acceptor(
-            &CONFIG.parseable.tls_cert_path,
-            &CONFIG.parseable.tls_key_path,
+            CONFIG.parseable.tls_identity(),
+            CONFIG.parseable.tls_min_version,
+            &CONFIG.parseable.tls_cipher_suites,
         )?;
 
         // concurrent workers equal to number of cores on the cpu
         let http_server = HttpServer::new(create_app_fn).workers(num_cpus::get());
-        if let Some(config) = ssl {
+        if let Some(path) = &CONFIG.parseable.uds_path {
+            #[cfg(unix)]
             http_server
-                .bind_rustls_0_22(&CONFIG.parseable.address, config)?
+                .listen_uds(uds_listener(path, CONFIG.parseable.uds_permissions)?)?
+                .run()
+                .await?;
+            #[cfg(not(unix))]
+            return Err(anyhow::anyhow!(
+                "Unix domain socket {} is not supported on this platform",
+                path.display()
+            ));
+        } else if let Some((config, resolver)) = ssl {
+            spawn_reload(resolver);
+            http_server
+                .bind_rustls_0_22(&CONFIG.parseable.bind_addrs()?[..], config)?
                 .run()
                 .await?;
         } else {
-            http_server.bind(&CONFIG.parseable.address)?.run().await?;
+            http_server
+                .bind(&CONFIG.parseable.bind_addrs()?[..])?
+                .run()
+                .await?;
         }
 
         Ok(())
@@ -137,20 +162,31 @@ impl Server {
                 web::scope(&base_path())
                     // POST "/query" ==> Get results of the SQL query passed in request body
                     .service(Self::get_query_factory())
+                    .service(Self::get_query_concurrency_factory())
+                    .service(Self::get_running_queries_factory())
+                    .service(Self::get_query_functions_factory())
+                    .service(Self::get_cancel_query_factory())
                     .service(Self::get_cache_webscope())
                     .service(Self::get_ingest_factory())
                     .service(Self::get_liveness_factory())
                     .service(Self::get_readiness_factory())
                     .service(Self::get_about_factory())
+                    .service(Self::get_connectors_factory())
                     .service(Self::get_logstream_webscope())
                     .service(Self::get_user_webscope())
                     .service(Self::get_dashboards_webscope())
                     .service(Self::get_filters_webscope())
+                    .service(Self::get_correlations_webscope())
+                    .service(Self::get_saved_queries_webscope())
+                    .service(Self::get_scheduled_queries_webscope())
                     .service(Self::get_llm_webscope())
                     .service(Self::get_oauth_webscope(oidc_client))
                     .service(Self::get_user_role_webscope()),
             )
             .service(Self::get_ingest_otel_factory())
+            .service(Self::get_ingest_otel_traces_factory())
+            .service(Self::get_ingest_otel_metrics_factory())
+            .service(Self::get_health_check_factory())
             .service(Self::get_generated());
     }
 
@@ -215,11 +251,180 @@ impl Server {
         )
     }
 
+    // get the correlations web scope
+    pub fn get_correlations_webscope() -> Scope {
+        web::scope("/correlation").service(
+            web::scope("/{user_id}")
+                .service(
+                    web::resource("").route(
+                        web::get()
+                            .to(correlations::list)
+                            .authorize(Action::ListCorrelation),
+                    ),
+                )
+                .service(
+                    web::scope("/{correlation_id}")
+                        .service(
+                            web::resource("")
+                                .route(
+                                    web::get()
+                                        .to(correlations::get)
+                                        .authorize(Action::GetCorrelation),
+                                )
+                                .route(
+                                    web::post()
+                                        .to(correlations::post)
+                                        .authorize(Action::CreateCorrelation),
+                                )
+                                .route(
+                                    web::delete()
+                                        .to(correlations::delete)
+                                        .authorize(Action::DeleteCorrelation),
+                                ),
+                        )
+                        // POST "/correlation/{user_id}/{correlation_id}/execute" ==> Join the correlated streams over a time range
+                        .service(
+                            web::resource("/execute").route(
+                                web::post()
+                                    .to(correlations::execute)
+                                    .authorize(Action::Query),
+                            ),
+                        ),
+                ),
+        )
+    }
+
+    // get the saved queries web scope
+    pub fn get_saved_queries_webscope() -> Scope {
+        web::scope("/saved_queries").service(
+            web::scope("/{user_id}")
+                .service(
+                    web::resource("").route(
+                        web::get()
+                            .to(saved_queries::list)
+                            .authorize(Action::ListSavedQuery),
+                    ),
+                )
+                .service(
+                    web::scope("/{query_id}")
+                        .service(
+                            web::resource("")
+                                .route(
+                                    web::get()
+                                        .to(saved_queries::get)
+                                        .authorize(Action::GetSavedQuery),
+                                )
+                                .route(
+                                    web::post()
+                                        .to(saved_queries::post)
+                                        .authorize(Action::CreateSavedQuery),
+                                )
+                                .route(
+                                    web::delete()
+                                        .to(saved_queries::delete)
+                                        .authorize(Action::DeleteSavedQuery),
+                                ),
+                        )
+                        // POST "/saved_queries/{user_id}/{query_id}/execute" ==> Run the saved query with the given parameters
+                        .service(
+                            web::resource("/execute").route(
+                                web::post()
+                                    .to(saved_queries::execute)
+                                    .authorize(Action::Query),
+                            ),
+                        ),
+                ),
+        )
+    }
+
+    // get the scheduled queries web scope
+    pub fn get_scheduled_queries_webscope() -> Scope {
+        web::scope("/scheduled_queries")
+            .service(
+                web::resource("").route(
+                    web::get()
+                        .to(http::scheduled::list)
+                        .authorize(Action::ListScheduledQuery),
+                ),
+            )
+            .service(
+                web::scope("/{id}")
+                    .service(
+                        web::resource("")
+                            .route(
+                                web::get()
+                                    .to(http::scheduled::get)
+                                    .authorize(Action::GetScheduledQuery),
+                            )
+                            .route(
+                                web::post()
+                                    .to(http::scheduled::post)
+                                    .authorize(Action::CreateScheduledQuery),
+                            )
+                            .route(
+                                web::delete()
+                                    .to(http::scheduled::delete)
+                                    .authorize(Action::DeleteScheduledQuery),
+                            ),
+                    )
+                    // GET "/scheduled_queries/{id}/status" ==> Watermark and failures of the query
+                    .service(
+                        web::resource("/status").route(
+                            web::get()
+                                .to(http::scheduled::status)
+                                .authorize(Action::GetScheduledQuery),
+                        ),
+                    ),
+            )
+    }
+
     // get the query factory
     pub fn get_query_factory() -> Resource {
         web::resource("/query").route(web::post().to(query::query).authorize(Action::Query))
     }
 
+    // get the query concurrency factory
+    pub fn get_query_concurrency_factory() -> Resource {
+        web::resource("/query/concurrency")
+            // GET "/query/concurrency" ==> Get the limit of concurrently executed queries
+            .route(
+                web::get()
+                    .to(query::get_concurrency)
+                    .authorize(Action::GetQueryConcurrency),
+            )
+            // PUT "/query/concurrency" ==> Change the limit without a restart
+            .route(
+                web::put()
+                    .to(query::put_concurrency)
+                    .authorize(Action::PutQueryConcurrency),
+            )
+    }
+
+    // get the running queries factory
+    pub fn get_running_queries_factory() -> Resource {
+        // GET "/query/running" ==> List queries running on this node
+        web::resource("/query/running")
+            .route(web::get().to(query::running).authorize(Action::Query))
+    }
+
+    // get the query functions factory
+    pub fn get_query_functions_factory() -> Resource {
+        // GET "/query/functions" ==> List the functions queries can call
+        web::resource("/query/functions").route(
+            web::get()
+                .to(query::list_functions)
+                .authorize(Action::Query),
+        )
+    }
+
+    // get the query cancellation factory, it has to be registered after the
+    // other "/query/..." resources as its path matches theirs too
+    pub fn get_cancel_query_factory() -> Resource {
+        // DELETE "/query/{query_id}" ==> Cancel a running query
+        web::resource("/query/{query_id}")
+            .route(web::delete().to(query::cancel).authorize(Action::Query))
+    }
+
     pub fn get_cache_webscope() -> Scope {
         web::scope("/cache").service(
             web::scope("/{user_id}").service(
@@ -332,6 +537,120 @@ impl Server {
                                     .to(logstream::get_cache_enabled)
                                     .authorize_for_stream(Action::GetCacheEnabled),
                             ),
+                    )
+                    .service(
+                        web::resource("/protobuf")
+                            // PUT "/logstream/{logstream}/protobuf" ==> Register the protobuf descriptor for given logstream
+                            .route(
+                                web::put()
+                                    .to(logstream::put_protobuf)
+                                    .authorize_for_stream(Action::PutProtobuf),
+                            )
+                            // GET "/logstream/{logstream}/protobuf" ==> Get the protobuf descriptor for given logstream
+                            .route(
+                                web::get()
+                                    .to(logstream::get_protobuf)
+                                    .authorize_for_stream(Action::GetProtobuf),
+                            ),
+                    )
+                    .service(
+                        web::resource("/timestamp")
+                            // PUT "/logstream/{logstream}/timestamp" ==> Set the event timestamp extraction for given logstream
+                            .route(
+                                web::put()
+                                    .to(logstream::put_timestamp_extraction)
+                                    .authorize_for_stream(Action::PutTimestampExtraction),
+                            )
+                            // GET "/logstream/{logstream}/timestamp" ==> Get the event timestamp extraction for given logstream
+                            .route(
+                                web::get()
+                                    .to(logstream::get_timestamp_extraction)
+                                    .authorize_for_stream(Action::GetTimestampExtraction),
+                            ),
+                    )
+                    .service(
+                        web::resource("/flattening")
+                            // PUT "/logstream/{logstream}/flattening" ==> Set how events are flattened for given logstream
+                            .route(
+                                web::put()
+                                    .to(logstream::put_flattening)
+                                    .authorize_for_stream(Action::PutFlattening),
+                            )
+                            // GET "/logstream/{logstream}/flattening" ==> Get how events are flattened for given logstream
+                            .route(
+                                web::get()
+                                    .to(logstream::get_flattening)
+                                    .authorize_for_stream(Action::GetFlattening),
+                            ),
+                    )
+                    .service(
+                        web::resource("/type-widening")
+                            // PUT "/logstream/{logstream}/type-widening" ==> Allow or disallow widening column types for given logstream
+                            .route(
+                                web::put()
+                                    .to(logstream::put_type_widening)
+                                    .authorize_for_stream(Action::PutTypeWidening),
+                            )
+                            // GET "/logstream/{logstream}/type-widening" ==> Get whether column types are widened for given logstream
+                            .route(
+                                web::get()
+                                    .to(logstream::get_type_widening)
+                                    .authorize_for_stream(Action::GetTypeWidening),
+                            ),
+                    )
+                    .service(
+                        web::resource("/dead-letter")
+                            // PUT "/logstream/{logstream}/dead-letter" ==> Set the dead letter stream of given logstream
+                            .route(
+                                web::put()
+                                    .to(logstream::put_dead_letter_stream)
+                                    .authorize_for_stream(Action::PutDeadLetterStream),
+                            )
+                            // GET "/logstream/{logstream}/dead-letter" ==> Get the dead letter stream of given logstream
+                            .route(
+                                web::get()
+                                    .to(logstream::get_dead_letter_stream)
+                                    .authorize_for_stream(Action::GetDeadLetterStream),
+                            ),
+                    )
+                    .service(
+                        web::resource("/transforms")
+                            // PUT "/logstream/{logstream}/transforms" ==> Set the transform rules of given logstream
+                            .route(
+                                web::put()
+                                    .to(logstream::put_transforms)
+                                    .authorize_for_stream(Action::PutTransforms),
+                            )
+                            // GET "/logstream/{logstream}/transforms" ==> Get the transform rules of given logstream
+                            .route(
+                                web::get()
+                                    .to(logstream::get_transforms)
+                                    .authorize_for_stream(Action::GetTransforms),
+                            ),
+                    )
+                    .service(
+                        web::resource("/grok")
+                            // PUT "/logstream/{logstream}/grok" ==> Set the grok pattern of given logstream
+                            .route(
+                                web::put()
+                                    .to(logstream::put_grok)
+                                    .authorize_for_stream(Action::PutGrok),
+                            )
+                            // GET "/logstream/{logstream}/grok" ==> Get the grok pattern of given logstream
+                            .route(
+                                web::get()
+                                    .to(logstream::get_grok)
+                                    .authorize_for_stream(Action::GetGrok),
+                            ),
+                    )
+                    .service(
+                        web::resource("/dead-letter/replay")
+                            // POST "/logstream/{logstream}/dead-letter/replay" ==> Ingest the events given logstream rejected again
+                            .route(
+                                web::post()
+                                    .to(dead_letter::replay)
+                                    .authorize_for_stream(Action::ReplayDeadLetters),
+                            ),
                     ),
             )
     }
@@ -339,6 +658,13 @@ impl Server {
     // get the factory for the ingest route
     pub fn get_ingest_factory() -> Resource {
         web::resource("/ingest")
+            // newline delimited JSON is streamed in as it arrives
+            .route(
+                web::post()
+                    .guard(guard::fn_guard(ndjson::is_ndjson_request))
+                    .to(ingest::ingest_ndjson)
+                    .authorize_for_stream(Action::Ingest),
+            )
             .route(
                 web::post()
                     .to(ingest::ingest)
@@ -358,6 +684,28 @@ impl Server {
             .app_data(web::PayloadConfig::default().limit(MAX_EVENT_PAYLOAD_SIZE))
     }
 
+    // /v1/traces endpoint to be used for OTEL trace ingestion only
+    pub fn get_ingest_otel_traces_factory() -> Resource {
+        web::resource("/v1/traces")
+            .route(
+                web::post()
+                    .to(ingest::ingest_otel_traces)
+                    .authorize_for_stream(Action::Ingest),
+            )
+            .app_data(web::PayloadConfig::default().limit(MAX_EVENT_PAYLOAD_SIZE))
+    }
+
+    // /v1/metrics endpoint to be used for OTEL metrics ingestion only
+    pub fn get_ingest_otel_metrics_factory() -> Resource {
+        web::resource("/v1/metrics")
+            .route(
+                web::post()
+                    .to(ingest::ingest_otel_metrics)
+                    .authorize_for_stream(Action::Ingest),
+            )
+            .app_data(web::PayloadConfig::default().limit(MAX_EVENT_PAYLOAD_SIZE))
+    }
+
     // get the oauth webscope
     pub fn get_oauth_webscope(oidc_client: Option<OpenIdClient>) -> Scope {
         let oauth = web::scope("/o")
@@ -467,6 +815,15 @@ impl Server {
             .route(web::head().to(health_check::liveness))
     }
 
+    // get the health check served outside the api base path for load balancer probes
+    // GET "/liveness" (configurable with P_HEALTH_CHECK_PATH) ==> Liveness check
+    // HEAD "/liveness"
+    pub fn get_health_check_factory() -> Resource {
+        web::resource(CONFIG.parseable.health_check_path.as_str())
+            .route(web::get().to(health_check::liveness))
+            .route(web::head().to(health_check::liveness))
+    }
+
     // get the readiness check
     // GET "/readiness" ==> Readiness check as per https://kubernetes.io/docs/tasks/configure-pod-container/configure-liveness-readiness-startup-probes/#define-readiness-probes
     // HEAD "/readiness"
@@ -481,6 +838,15 @@ impl Server {
         web::resource("/about").route(web::get().to(about::about).authorize(Action::GetAbout))
     }
 
+    // GET "/connectors" ==> Get the health of the connectors the server consumes from
+    pub fn get_connectors_factory() -> Resource {
+        web::resource("/connectors").route(
+            web::get()
+                .to(connectors::status)
+                .authorize(Action::GetConnectors),
+        )
+    }
+
     // GET "/" ==> Serve the static frontend directory
     pub fn get_generated() -> ResourceFiles {
         ResourceFiles::new("/", generate()).resolve_not_found_to_root()
@@ -493,6 +859,14 @@ impl Server {
                 .await?;
         };
 
+        #[cfg(feature = "kafka")]
+        let kafka = handlers::kafka::KafkaConfig::from_cli(&CONFIG.parseable)?;
+        #[cfg(feature = "nats")]
+        let nats = handlers::nats::NatsConfig::from_cli(&CONFIG.parseable)?;
+        #[cfg(feature = "amqp")]
+        let amqp = handlers::amqp::AmqpConfig::from_cli(&CONFIG.parseable)?;
+        let file_input = handlers::file_input::FileInputConfig::from_cli(&CONFIG.parseable)?;
+
         let prometheus = metrics::build_metrics_handler();
         CONFIG.storage().register_store_metrics(&prometheus);
 
@@ -500,6 +874,10 @@ impl Server {
 
         FILTERS.load().await?;
         DASHBOARDS.load().await?;
+        CORRELATIONS.load().await?;
+        SAVED_QUERIES.load().await?;
+        SCHEDULED_QUERIES.load().await?;
+        scheduled::init_scheduler();
 
         storage::retention::load_retention_from_global();
 
@@ -513,6 +891,20 @@ impl Server {
 
         tokio::spawn(handlers::livetail::server());
         tokio::spawn(handlers::airplane::server());
+        tokio::spawn(handlers::syslog::server());
+        #[cfg(feature = "kafka")]
+        let mut kafka = handlers::kafka::Connector::spawn(kafka);
+        #[cfg(feature = "nats")]
+        if let Some(nats) = nats {
+            tokio::spawn(handlers::nats::server(nats));
+        }
+        #[cfg(feature = "amqp")]
+        if let Some(amqp) = amqp {
+            tokio::spawn(handlers::amqp::server(amqp));
+        }
+        if let Some(file_input) = file_input {
+            tokio::spawn(handlers::file_input::server(file_input));
+        }
 
         let app = self.start(prometheus, CONFIG.parseable.openid.clone());
 
@@ -521,6 +913,8 @@ impl Server {
             tokio::select! {
                 e = &mut app => {
                     // actix server finished .. stop other threads and stop the server
+                    #[cfg(feature = "kafka")]
+                    kafka.stop().await;
                     remote_sync_inbox.send(()).unwrap_or(());
                     localsync_inbox.send(()).unwrap_or(());
                     localsync_handler.join().unwrap_or(());
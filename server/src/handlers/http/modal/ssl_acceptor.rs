@@ -16,26 +16,611 @@
  *
  */
 
-use std::{fs::File, io::BufReader, path::PathBuf};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
 
-use rustls::ServerConfig;
+use p12_keystore::KeyStore;
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{
+        ring::{default_provider, sign::any_supported_type, ALL_CIPHER_SUITES},
+        verify_tls12_signature, verify_tls13_signature, CryptoProvider,
+    },
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    version::{TLS12, TLS13},
+    ClientConfig, ClientConnection, ConfigBuilder, DigitallySignedStruct, ServerConfig,
+    ServerConnection, SignatureScheme, SupportedProtocolVersion, WantsVerifier,
+};
 
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::option::TlsVersion;
+
+// connections done with their handshake waiting for a gRPC server to take them
+const GRPC_ACCEPT_BACKLOG: usize = 64;
+
+/// Where the server certificate chain and its private key are loaded from
+#[derive(Debug, Clone)]
+pub enum TlsIdentity {
+    /// Separate PEM encoded certificate and private key files
+    Pem { cert: PathBuf, key: PathBuf },
+    /// Single PKCS#12 (.p12/.pfx) bundle holding both
+    Pkcs12 { path: PathBuf, password: String },
+}
+
+impl TlsIdentity {
+    /// File the certificate is read from
+    pub fn path(&self) -> &Path {
+        match self {
+            TlsIdentity::Pem { cert, .. } => cert,
+            TlsIdentity::Pkcs12 { path, .. } => path,
+        }
+    }
+}
+
+/// Server TLS config for the given identity along with the resolver serving
+/// its certificate. Listeners keep using the resolver, so certificates are
+/// rotated without a restart by reloading it, see [`spawn_reload`]
 pub fn get_ssl_acceptor(
-    tls_cert: &Option<PathBuf>,
-    tls_key: &Option<PathBuf>,
-) -> anyhow::Result<Option<ServerConfig>> {
-    match (tls_cert, tls_key) {
-        (Some(cert), Some(key)) => {
-            let server_config = ServerConfig::builder().with_no_client_auth();
-
-            let cert_file = &mut BufReader::new(File::open(cert)?);
-            let key_file = &mut BufReader::new(File::open(key)?);
-            let certs = rustls_pemfile::certs(cert_file).collect::<Result<Vec<_>, _>>()?;
-            let private_key = rustls_pemfile::private_key(key_file)?
-                .ok_or(anyhow::anyhow!("Could not parse private key."))?;
-
-            Ok(Some(server_config.with_single_cert(certs, private_key)?))
+    identity: Option<TlsIdentity>,
+    min_version: TlsVersion,
+    cipher_suites: &[String],
+) -> anyhow::Result<Option<(ServerConfig, Arc<ReloadableCertResolver>)>> {
+    match identity {
+        Some(identity) => {
+            let resolver = Arc::new(ReloadableCertResolver::new(identity)?);
+            let server_config = server_config_builder(min_version, cipher_suites)?
+                .with_no_client_auth()
+                .with_cert_resolver(resolver.clone());
+
+            Ok(Some((server_config, resolver)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Reloads the certificate of the resolver every time the process gets a
+/// SIGHUP, started once by each listener serving TLS. Other platforms keep
+/// the certificate loaded at startup until a restart
+pub fn spawn_reload(resolver: Arc<ReloadableCertResolver>) {
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(resolver));
+    #[cfg(not(unix))]
+    let _ = resolver;
+}
+
+/// TLS connections accepted on `listener` for a gRPC server to serve with
+/// `serve_with_incoming`, as tonic can't take a rustls config of its own.
+/// Handshakes run concurrently so that a slow client holds up no other
+pub fn grpc_incoming(
+    listener: TcpListener,
+    mut server_config: ServerConfig,
+) -> ReceiverStream<io::Result<TlsStream<TcpStream>>> {
+    // gRPC runs over HTTP/2, gRPC-web over HTTP/1.1 as well
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    let (tx, rx) = mpsc::channel(GRPC_ACCEPT_BACKLOG);
+
+    tokio::spawn(async move {
+        while !tx.is_closed() {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    log::warn!("Failed to accept gRPC connection: {err}");
+                    continue;
+                }
+            };
+            let (acceptor, tx) = (acceptor.clone(), tx.clone());
+            tokio::spawn(async move {
+                match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        let _ = tx.send(Ok(stream)).await;
+                    }
+                    Err(err) => log::warn!("TLS handshake with gRPC client {peer} failed: {err}"),
+                }
+            });
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+/// Loads the cert/key pair and builds the server TLS config without serving it
+pub fn check_tls(
+    identity: &TlsIdentity,
+    min_version: TlsVersion,
+    cipher_suites: &[String],
+) -> anyhow::Result<()> {
+    let resolver = ReloadableCertResolver::new(identity.clone())?;
+    server_config_builder(min_version, cipher_suites)?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(resolver));
+    Ok(())
+}
+
+// restricts the protocol versions and cipher suites negotiated by the server
+fn server_config_builder(
+    min_version: TlsVersion,
+    cipher_suites: &[String],
+) -> anyhow::Result<ConfigBuilder<ServerConfig, WantsVerifier>> {
+    let mut provider = default_provider();
+    if !cipher_suites.is_empty() {
+        provider.cipher_suites = ALL_CIPHER_SUITES
+            .iter()
+            .filter(|suite| {
+                suite
+                    .suite()
+                    .as_str()
+                    .is_some_and(|name| cipher_suites.iter().any(|s| s == name))
+            })
+            .copied()
+            .collect();
+    }
+
+    let versions: &[&'static SupportedProtocolVersion] = match min_version {
+        TlsVersion::V1_2 => &[&TLS13, &TLS12],
+        TlsVersion::V1_3 => &[&TLS13],
+    };
+
+    ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(versions)
+        .map_err(|err| anyhow::anyhow!("Invalid TLS configuration: {err}"))
+}
+
+/// Serves the certificate currently loaded from disk and reloads it on demand
+#[derive(Debug)]
+pub struct ReloadableCertResolver {
+    identity: TlsIdentity,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadableCertResolver {
+    pub fn new(identity: TlsIdentity) -> anyhow::Result<Self> {
+        let current = load_certified_key(&identity)?;
+        Ok(Self {
+            identity,
+            current: RwLock::new(current),
+        })
+    }
+
+    /// Load the cert/key pair from disk again and swap it in if the pair is valid.
+    /// On error the previously loaded certificate stays in use.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let certified_key = load_certified_key(&self.identity)?;
+        *self.current.write().unwrap() = certified_key;
+        Ok(())
+    }
+
+    pub fn current(&self) -> Arc<CertifiedKey> {
+        self.current.read().unwrap().clone()
+    }
+}
+
+impl ResolvesServerCert for ReloadableCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+#[cfg(unix)]
+async fn reload_on_sighup(resolver: Arc<ReloadableCertResolver>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            log::error!(
+                "Failed to listen for SIGHUP, TLS certificates will not be reloaded: {err}"
+            );
+            return;
         }
-        (_, _) => Ok(None),
+    };
+
+    while hangup.recv().await.is_some() {
+        match resolver.reload() {
+            Ok(()) => log::info!(
+                "Reloaded TLS certificate from {}",
+                resolver.identity.path().display()
+            ),
+            Err(err) => {
+                log::error!("Failed to reload TLS certificate, keeping the current one: {err}")
+            }
+        }
+    }
+}
+
+fn load_certified_key(identity: &TlsIdentity) -> anyhow::Result<Arc<CertifiedKey>> {
+    let (certs, private_key) = match identity {
+        TlsIdentity::Pem { cert, key } => load_pem(cert, key)?,
+        TlsIdentity::Pkcs12 { path, password } => load_pkcs12(path, password)?,
+    };
+
+    if certs.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No certificate found in {}",
+            identity.path().display()
+        ));
+    }
+    validate_key_pair(&certs, &private_key)?;
+
+    let signing_key = any_supported_type(&private_key)?;
+    Ok(Arc::new(CertifiedKey::new(certs, signing_key)))
+}
+
+fn load_pem(
+    cert: &Path,
+    key: &Path,
+) -> anyhow::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let cert_file = &mut BufReader::new(File::open(cert)?);
+    let key_file = &mut BufReader::new(File::open(key)?);
+    let certs = rustls_pemfile::certs(cert_file).collect::<Result<Vec<_>, _>>()?;
+    let private_key = rustls_pemfile::private_key(key_file)?
+        .ok_or(anyhow::anyhow!("Could not parse private key."))?;
+    Ok((certs, private_key))
+}
+
+// the first key in the bundle along with its certificate chain, leaf first
+fn load_pkcs12(
+    path: &Path,
+    password: &str,
+) -> anyhow::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let keystore = KeyStore::from_pkcs12(&fs::read(path)?, password).map_err(|err| {
+        anyhow::anyhow!("Could not read PKCS#12 bundle {}: {err}", path.display())
+    })?;
+    let (_, chain) = keystore.private_key_chain().ok_or(anyhow::anyhow!(
+        "No private key found in {}",
+        path.display()
+    ))?;
+
+    let certs = chain
+        .chain()
+        .iter()
+        .map(|cert| CertificateDer::from(cert.as_der().to_vec()))
+        .collect();
+    let private_key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(chain.key().to_vec()));
+    Ok((certs, private_key))
+}
+
+// Proves the private key belongs to the certificate by completing an in memory
+// handshake where the client checks the server's signature against the certificate
+fn validate_key_pair(
+    certs: &[CertificateDer<'static>],
+    private_key: &PrivateKeyDer<'static>,
+) -> anyhow::Result<()> {
+    let provider = Arc::new(default_provider());
+    let server = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs.to_vec(), private_key.clone_key())?;
+    let client = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(SignatureVerifier(provider)))
+        .with_no_client_auth();
+
+    handshake(server, client)
+        .map_err(|err| anyhow::anyhow!("Certificate does not match the private key: {err}"))
+}
+
+// accepts any certificate but checks that the handshake was signed by its key
+#[derive(Debug)]
+struct SignatureVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SignatureVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+// drives an in memory handshake until it completes or either side errors
+fn handshake(server: ServerConfig, client: ClientConfig) -> Result<(), rustls::Error> {
+    let mut server = ServerConnection::new(Arc::new(server))?;
+    let mut client = ClientConnection::new(
+        Arc::new(client),
+        ServerName::try_from("localhost").expect("valid server name"),
+    )?;
+
+    while client.is_handshaking() || server.is_handshaking() {
+        let mut buf = Vec::new();
+        client.write_tls(&mut buf).map_err(io_error)?;
+        server.read_tls(&mut buf.as_slice()).map_err(io_error)?;
+        server.process_new_packets()?;
+
+        let mut buf = Vec::new();
+        server.write_tls(&mut buf).map_err(io_error)?;
+        client.read_tls(&mut buf.as_slice()).map_err(io_error)?;
+        client.process_new_packets()?;
+    }
+
+    Ok(())
+}
+
+fn io_error(err: std::io::Error) -> rustls::Error {
+    rustls::Error::General(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use base64::{prelude::BASE64_STANDARD, Engine};
+
+    use p12_keystore::{Certificate, KeyStore, KeyStoreEntry, PrivateKeyChain};
+    use rustls::{pki_types::CertificateDer, version::TLS12, ClientConfig, RootCertStore};
+    use tempfile::TempDir;
+
+    use super::{get_ssl_acceptor, grpc_incoming, handshake, ReloadableCertResolver, TlsIdentity};
+    use crate::option::TlsVersion;
+
+    // rcgen signs on every serialization, so the certificate is serialized once
+    struct TestCert {
+        pem: String,
+        key_pem: String,
+        key_der: Vec<u8>,
+        der: CertificateDer<'static>,
+    }
+
+    fn self_signed() -> TestCert {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let der = cert.serialize_der().unwrap();
+        TestCert {
+            pem: format!(
+                "-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n",
+                BASE64_STANDARD.encode(&der)
+            ),
+            key_pem: cert.serialize_private_key_pem(),
+            key_der: cert.serialize_private_key_der(),
+            der: CertificateDer::from(der),
+        }
+    }
+
+    fn write_pair(dir: &TempDir, cert: &TestCert, key: &TestCert) -> TlsIdentity {
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        fs::write(&cert_path, &cert.pem).unwrap();
+        fs::write(&key_path, &key.key_pem).unwrap();
+        TlsIdentity::Pem {
+            cert: cert_path,
+            key: key_path,
+        }
+    }
+
+    fn write_pkcs12(dir: &TempDir, cert: &TestCert, password: &str) -> PathBuf {
+        let chain = PrivateKeyChain::new(
+            &cert.key_der,
+            [1],
+            [Certificate::from_der(&cert.der).unwrap()],
+        );
+        let mut keystore = KeyStore::new();
+        keystore.add_entry("parseable", KeyStoreEntry::PrivateKeyChain(chain));
+        let path = dir.path().join("identity.p12");
+        fs::write(&path, keystore.writer(password).write().unwrap()).unwrap();
+        path
+    }
+
+    fn tls12_client(cert: CertificateDer<'static>) -> ClientConfig {
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+        ClientConfig::builder_with_protocol_versions(&[&TLS12])
+            .with_root_certificates(roots)
+            .with_no_client_auth()
+    }
+
+    #[test]
+    fn min_version_1_3_rejects_tls_1_2() {
+        let dir = TempDir::new().unwrap();
+        let cert = self_signed();
+        let identity = write_pair(&dir, &cert, &cert);
+
+        let (server, _) = get_ssl_acceptor(Some(identity), TlsVersion::V1_3, &[])
+            .unwrap()
+            .unwrap();
+        assert!(handshake(server, tls12_client(cert.der.clone())).is_err());
+    }
+
+    #[test]
+    fn min_version_1_2_accepts_tls_1_2() {
+        let dir = TempDir::new().unwrap();
+        let cert = self_signed();
+        let identity = write_pair(&dir, &cert, &cert);
+
+        let (server, _) = get_ssl_acceptor(Some(identity), TlsVersion::V1_2, &[])
+            .unwrap()
+            .unwrap();
+        assert!(handshake(server, tls12_client(cert.der.clone())).is_ok());
+    }
+
+    #[test]
+    fn acceptor_serves_the_certificate_its_resolver_reloads() {
+        let dir = TempDir::new().unwrap();
+        let first = self_signed();
+        let identity = write_pair(&dir, &first, &first);
+
+        let (server, resolver) = get_ssl_acceptor(Some(identity), TlsVersion::V1_2, &[])
+            .unwrap()
+            .unwrap();
+        let second = self_signed();
+        write_pair(&dir, &second, &second);
+        resolver.reload().unwrap();
+
+        assert!(handshake(server.clone(), tls12_client(first.der.clone())).is_err());
+        assert!(handshake(server, tls12_client(second.der.clone())).is_ok());
+    }
+
+    // a gRPC call over TLS trusting only `cert`, the live tail service has no
+    // actions so reaching it at all answers unimplemented
+    async fn list_actions(
+        port: u16,
+        cert: &TestCert,
+    ) -> Result<tonic::Code, tonic::transport::Error> {
+        use arrow_flight::{flight_service_client::FlightServiceClient, Empty};
+        use tonic::transport::{Certificate, Channel, ClientTlsConfig};
+
+        let channel = Channel::from_shared(format!("https://127.0.0.1:{port}"))
+            .unwrap()
+            .tls_config(
+                ClientTlsConfig::new()
+                    .ca_certificate(Certificate::from_pem(&cert.pem))
+                    .domain_name("localhost"),
+            )?
+            .connect()
+            .await?;
+        let status = FlightServiceClient::new(channel)
+            .list_actions(Empty {})
+            .await
+            .unwrap_err();
+        Ok(status.code())
+    }
+
+    #[tokio::test]
+    async fn grpc_listener_serves_the_certificate_its_resolver_reloads() {
+        use arrow_flight::flight_service_server::FlightServiceServer;
+        use tokio::net::TcpListener;
+        use tonic::transport::Server;
+
+        use crate::handlers::livetail::FlightServiceImpl;
+
+        let dir = TempDir::new().unwrap();
+        let first = self_signed();
+        let identity = write_pair(&dir, &first, &first);
+        let (server, resolver) = get_ssl_acceptor(Some(identity), TlsVersion::V1_2, &[])
+            .unwrap()
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(
+            Server::builder()
+                .add_service(FlightServiceServer::new(FlightServiceImpl {}))
+                .serve_with_incoming(grpc_incoming(listener, server)),
+        );
+
+        assert_eq!(
+            list_actions(port, &first).await.unwrap(),
+            tonic::Code::Unimplemented
+        );
+
+        let second = self_signed();
+        write_pair(&dir, &second, &second);
+        resolver.reload().unwrap();
+
+        assert!(list_actions(port, &first).await.is_err());
+        assert_eq!(
+            list_actions(port, &second).await.unwrap(),
+            tonic::Code::Unimplemented
+        );
+    }
+
+    #[test]
+    fn cipher_suites_incompatible_with_min_version_error() {
+        let dir = TempDir::new().unwrap();
+        let cert = self_signed();
+        let identity = write_pair(&dir, &cert, &cert);
+
+        let suites = vec!["TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256".to_string()];
+        assert!(get_ssl_acceptor(Some(identity), TlsVersion::V1_3, &suites).is_err());
+    }
+
+    #[test]
+    fn reload_swaps_valid_pair_and_keeps_current_on_invalid_pair() {
+        let dir = TempDir::new().unwrap();
+        let first = self_signed();
+        let identity = write_pair(&dir, &first, &first);
+        let resolver = ReloadableCertResolver::new(identity).unwrap();
+        assert_eq!(resolver.current().cert[0], first.der.clone());
+
+        let second = self_signed();
+        write_pair(&dir, &second, &second);
+        resolver.reload().unwrap();
+        assert_eq!(resolver.current().cert[0], second.der.clone());
+
+        // certificate of one pair with the key of another
+        let third = self_signed();
+        write_pair(&dir, &third, &first);
+        assert!(resolver.reload().is_err());
+        assert_eq!(resolver.current().cert[0], second.der.clone());
+    }
+
+    #[test]
+    fn mismatched_pair_is_rejected_at_startup() {
+        let dir = TempDir::new().unwrap();
+        let identity = write_pair(&dir, &self_signed(), &self_signed());
+
+        assert!(get_ssl_acceptor(Some(identity), TlsVersion::V1_2, &[]).is_err());
+    }
+
+    #[test]
+    fn pkcs12_bundle_produces_server_config() {
+        let dir = TempDir::new().unwrap();
+        let cert = self_signed();
+        let identity = TlsIdentity::Pkcs12 {
+            path: write_pkcs12(&dir, &cert, "changeit"),
+            password: "changeit".to_string(),
+        };
+
+        let (server, _) = get_ssl_acceptor(Some(identity), TlsVersion::V1_2, &[])
+            .unwrap()
+            .unwrap();
+        assert!(handshake(server, tls12_client(cert.der.clone())).is_ok());
+    }
+
+    #[test]
+    fn pkcs12_bundle_with_wrong_password_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let identity = TlsIdentity::Pkcs12 {
+            path: write_pkcs12(&dir, &self_signed(), "changeit"),
+            password: "hunter2".to_string(),
+        };
+
+        assert!(get_ssl_acceptor(Some(identity), TlsVersion::V1_2, &[]).is_err());
     }
 }
@@ -0,0 +1,110 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{
+    fs, io,
+    os::unix::{fs::PermissionsExt, net::UnixListener},
+    path::Path,
+};
+
+/// Binds a unix domain socket at `path` with the given permission bits.
+/// A stale socket file left behind by a previous run is removed first,
+/// any other kind of file at `path` is left alone and fails the bind.
+pub fn uds_listener(path: &Path, permissions: u32) -> io::Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        use std::os::unix::fs::FileTypeExt;
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(permissions))?;
+    Ok(listener)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        os::unix::{fs::PermissionsExt, net::UnixStream},
+    };
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+
+    use super::uds_listener;
+
+    #[actix_web::test]
+    async fn serves_requests_over_uds() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("parseable.sock");
+        // a stale socket from a previous run is replaced
+        drop(uds_listener(&path, 0o600).unwrap());
+
+        let listener = uds_listener(&path, 0o660).unwrap();
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o660
+        );
+
+        let server = HttpServer::new(|| {
+            App::new().route(
+                "/liveness",
+                web::get().to(|| async { HttpResponse::Ok().body("ok") }),
+            )
+        })
+        .workers(1)
+        .listen_uds(listener)
+        .unwrap()
+        .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let response = actix_web::rt::task::spawn_blocking(move || {
+            let mut stream = UnixStream::connect(&path).unwrap();
+            stream
+                .write_all(
+                    b"GET /liveness HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                )
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        })
+        .await
+        .unwrap();
+        handle.stop(false).await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("ok"), "{response}");
+    }
+
+    #[test]
+    fn refuses_to_replace_regular_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data");
+        std::fs::write(&path, b"important").unwrap();
+
+        assert!(uds_listener(&path, 0o660).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"important");
+    }
+}
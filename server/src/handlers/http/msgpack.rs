@@ -0,0 +1,351 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Decoding of MessagePack bodies into the JSON values the ingest path
+//! works on.
+
+use std::{fmt, io::Cursor, str::FromStr};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::de::{DeserializeSeed, Deserializer, Error as _, MapAccess, SeqAccess, Visitor};
+use serde_json::{Map, Number, Value};
+
+/// Whether a content type names a MessagePack body
+pub fn is_msgpack(content_type: &str) -> bool {
+    matches!(
+        content_type.trim().to_ascii_lowercase().as_str(),
+        "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack"
+    )
+}
+
+/// How binary values are turned into strings, JSON having no binary type
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BinaryEncoding {
+    #[default]
+    Base64,
+    Hex,
+}
+
+impl BinaryEncoding {
+    fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            BinaryEncoding::Base64 => STANDARD.encode(bytes),
+            BinaryEncoding::Hex => hex::encode(bytes),
+        }
+    }
+}
+
+impl FromStr for BinaryEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "base64" => Ok(BinaryEncoding::Base64),
+            "hex" => Ok(BinaryEncoding::Hex),
+            other => Err(anyhow::anyhow!(
+                "Binary encoding must be base64 or hex, got {other}"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MsgpackError {
+    #[error("Malformed MessagePack at byte {offset}: {error}")]
+    Malformed { offset: u64, error: String },
+    #[error("Unexpected bytes after the MessagePack value at byte {0}")]
+    TrailingBytes(u64),
+    #[error("MessagePack body must be a map or an array of maps")]
+    NotRecords,
+}
+
+/// Decodes a body holding a single map or an array of maps.
+///
+/// Integers stay integers and floats stay floats, binary values become
+/// strings in the given encoding and integer map keys become their decimal
+/// string. Floats that JSON can't hold, NaN and the infinities, become null.
+pub fn decode(body: &[u8], binary: BinaryEncoding) -> Result<Value, MsgpackError> {
+    let mut reader = Cursor::new(body);
+    let decoded = {
+        let mut deserializer = rmp_serde::Deserializer::new(&mut reader);
+        ValueSeed(binary).deserialize(&mut deserializer)
+    };
+    let value = decoded.map_err(|err| MsgpackError::Malformed {
+        // the reader stops right after the bytes that failed to decode
+        offset: reader.position(),
+        error: err.to_string(),
+    })?;
+    if reader.position() < body.len() as u64 {
+        return Err(MsgpackError::TrailingBytes(reader.position()));
+    }
+
+    let is_record = |value: &Value| value.is_object();
+    match &value {
+        Value::Object(_) => Ok(value),
+        Value::Array(records) if records.iter().all(is_record) => Ok(value),
+        _ => Err(MsgpackError::NotRecords),
+    }
+}
+
+// deserializes any MessagePack value into a JSON value
+#[derive(Clone, Copy)]
+struct ValueSeed(BinaryEncoding);
+
+impl<'de> DeserializeSeed<'de> for ValueSeed {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ValueSeed {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a MessagePack value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<Value, E> {
+        Ok(Value::Bool(value))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Value, E> {
+        Ok(Value::Number(value.into()))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Value, E> {
+        Ok(Value::Number(value.into()))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Value, E> {
+        Ok(Number::from_f64(value).map_or(Value::Null, Value::Number))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Value, E> {
+        Ok(Value::String(value.to_owned()))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Value, E> {
+        Ok(Value::String(value))
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Value, E> {
+        Ok(Value::String(self.0.encode(value)))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or_default().min(4096));
+        while let Some(value) = seq.next_element_seed(self)? {
+            values.push(value);
+        }
+        Ok(Value::Array(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut object = Map::new();
+        while let Some(key) = map.next_key_seed(self)? {
+            let key = match key {
+                Value::String(key) => key,
+                Value::Number(key) if !key.is_f64() => key.to_string(),
+                other => {
+                    return Err(A::Error::custom(format!(
+                        "map keys must be strings or integers, got {other}"
+                    )))
+                }
+            };
+            object.insert(key, map.next_value_seed(self)?);
+        }
+        Ok(Value::Object(object))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use actix_web::{
+        http::{header, StatusCode},
+        test::{call_and_read_body, call_service, init_service, TestRequest},
+        web, App,
+    };
+    use bytes::Bytes;
+    use flate2::{write::GzEncoder, Compression};
+    use serde_json::{json, Value};
+
+    use super::{decode, is_msgpack, BinaryEncoding, MsgpackError};
+
+    const LIMIT: usize = 1024;
+
+    async fn count_records(body: Bytes) -> String {
+        match decode(&body, BinaryEncoding::default()).unwrap() {
+            Value::Array(records) => records.len().to_string(),
+            _ => "1".to_string(),
+        }
+    }
+
+    fn records(count: usize) -> Vec<u8> {
+        let records: Vec<Value> = (0..count)
+            .map(|seq| json!({"seq": seq, "message": "connection reset"}))
+            .collect();
+        rmp_serde::to_vec(&records).unwrap()
+    }
+
+    #[test]
+    fn content_types() {
+        for content_type in [
+            "application/msgpack",
+            "application/x-msgpack",
+            "Application/MsgPack",
+        ] {
+            assert!(is_msgpack(content_type), "{content_type}");
+        }
+        assert!(!is_msgpack("application/json"));
+    }
+
+    #[test]
+    fn numbers_keep_their_kind() {
+        let record = json!({"count": 3, "ratio": 1.0, "offset": -7, "big": u64::MAX});
+        let body = rmp_serde::to_vec(&record).unwrap();
+        let decoded = decode(&body, BinaryEncoding::default()).unwrap();
+        assert_eq!(decoded, record);
+        assert!(decoded["count"].is_u64());
+        assert!(decoded["offset"].is_i64());
+        assert!(decoded["ratio"].is_f64());
+    }
+
+    #[test]
+    fn binary_values_are_encoded() {
+        // {"payload": bin8 [0xde, 0xad, 0xbe], 7: nil}
+        let body = [
+            0x82, 0xa7, b'p', b'a', b'y', b'l', b'o', b'a', b'd', 0xc4, 0x03, 0xde, 0xad, 0xbe,
+            0x07, 0xc0,
+        ];
+        assert_eq!(
+            decode(&body, BinaryEncoding::Base64).unwrap(),
+            json!({"payload": "3q2+", "7": null})
+        );
+        assert_eq!(
+            decode(&body, BinaryEncoding::Hex).unwrap(),
+            json!({"payload": "deadbe", "7": null})
+        );
+        assert_eq!(
+            "HEX".parse::<BinaryEncoding>().unwrap(),
+            BinaryEncoding::Hex
+        );
+        assert!("base32".parse::<BinaryEncoding>().is_err());
+    }
+
+    #[test]
+    fn errors_carry_the_byte_offset() {
+        let mut body = rmp_serde::to_vec(&json!([{"a": 1}, {"b": "text"}])).unwrap();
+        // cut the body inside the string of the second record
+        body.truncate(body.len() - 2);
+        let err = decode(&body, BinaryEncoding::default()).unwrap_err();
+        assert!(
+            matches!(err, MsgpackError::Malformed { offset, .. } if offset == body.len() as u64),
+            "{err}"
+        );
+        assert!(err.to_string().contains(&format!("at byte {}", body.len())));
+
+        let mut body = rmp_serde::to_vec(&json!({"a": 1})).unwrap();
+        let end = body.len() as u64;
+        body.push(0xc0);
+        assert!(matches!(
+            decode(&body, BinaryEncoding::default()),
+            Err(MsgpackError::TrailingBytes(offset)) if offset == end
+        ));
+
+        for value in [json!(1), json!([{"a": 1}, 2])] {
+            let body = rmp_serde::to_vec(&value).unwrap();
+            assert!(matches!(
+                decode(&body, BinaryEncoding::default()),
+                Err(MsgpackError::NotRecords)
+            ));
+        }
+    }
+
+    #[actix_web::test]
+    async fn compressed_bodies_are_decoded() {
+        let app = init_service(
+            App::new()
+                .route("/ingest", web::post().to(count_records))
+                .app_data(web::PayloadConfig::default().limit(LIMIT)),
+        )
+        .await;
+        let body = records(10);
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&body).unwrap();
+        for (encoding, payload) in [
+            ("gzip", gzip.finish().unwrap()),
+            ("zstd", zstd::encode_all(&body[..], 0).unwrap()),
+        ] {
+            let req = TestRequest::post()
+                .uri("/ingest")
+                .insert_header((header::CONTENT_TYPE, "application/msgpack"))
+                .insert_header((header::CONTENT_ENCODING, encoding))
+                .set_payload(payload)
+                .to_request();
+            assert_eq!(call_and_read_body(&app, req).await, "10", "{encoding}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn size_limit_applies_to_the_decoded_body() {
+        let app = init_service(
+            App::new()
+                .route("/ingest", web::post().to(count_records))
+                .app_data(web::PayloadConfig::default().limit(LIMIT)),
+        )
+        .await;
+        let body = records(100);
+        assert!(body.len() > LIMIT);
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::best());
+        gzip.write_all(&body).unwrap();
+        let compressed = gzip.finish().unwrap();
+        // repetitive records compress well below the limit
+        assert!(compressed.len() < LIMIT);
+
+        for (encoding, payload) in [("identity", body), ("gzip", compressed)] {
+            let req = TestRequest::post()
+                .uri("/ingest")
+                .insert_header((header::CONTENT_TYPE, "application/msgpack"))
+                .insert_header((header::CONTENT_ENCODING, encoding))
+                .set_payload(payload)
+                .to_request();
+            let response = call_service(&app, req).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "{encoding}"
+            );
+        }
+    }
+}
@@ -0,0 +1,657 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Streaming ingestion of newline delimited JSON. Lines are parsed as the
+//! body arrives and their events flushed to staging in batches, so a client
+//! can hold one connection open for as long as it has events to send.
+
+use std::{
+    fmt::Display,
+    future::Future,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use actix_web::{guard::GuardContext, http::header};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::event::format::csv::{RowError, MAX_REPORTED_ROW_ERRORS};
+use crate::option::CONFIG;
+
+/// Lines a batch holds at most, a full batch is flushed without waiting
+/// for the flush interval
+pub const MAX_BATCH_LINES: usize = 10_000;
+
+// idempotency keys remembered at once
+const MAX_CHECKPOINTS: usize = 10_000;
+
+/// Lines committed under the idempotency keys of recent connections
+pub static CHECKPOINTS: Lazy<Checkpoints> = Lazy::new(|| Checkpoints::new(MAX_CHECKPOINTS));
+
+/// Whether a content type names a newline delimited JSON body
+pub fn is_ndjson(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default();
+    matches!(
+        media_type.trim().to_ascii_lowercase().as_str(),
+        "application/x-ndjson" | "application/ndjson" | "application/jsonl"
+    )
+}
+
+/// Route guard for requests with a newline delimited JSON body
+pub fn is_ndjson_request(ctx: &GuardContext) -> bool {
+    ctx.head()
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(is_ndjson)
+}
+
+/// Limits a streaming connection is held to
+#[derive(Debug, Clone, Copy)]
+pub struct StreamLimits {
+    /// Bytes a line may have, longer lines are rejected
+    pub max_line_size: usize,
+    /// Bytes the whole body may have
+    pub max_size: u64,
+    /// How long events wait for their batch to be flushed
+    pub flush_interval: Duration,
+    /// Lines a batch holds at most
+    pub batch_lines: usize,
+}
+
+impl StreamLimits {
+    pub fn from_config() -> Self {
+        Self {
+            max_line_size: CONFIG.parseable.ingest_stream_max_line_size as usize,
+            max_size: CONFIG.parseable.ingest_stream_max_size,
+            flush_interval: CONFIG.parseable.ingest_stream_flush_interval,
+            batch_lines: MAX_BATCH_LINES,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum NdjsonError {
+    #[error("Malformed line {line}: {error}")]
+    Malformed { line: u64, error: String },
+    #[error("Body is larger than {0} bytes")]
+    TooLarge(u64),
+    #[error("Connection closed: {0}")]
+    Payload(String),
+    #[error("Failed to ingest the lines up to line {line}: {error}")]
+    Flush { line: u64, error: String },
+    #[error("Idempotency key {0} is in use by another connection")]
+    KeyInUse(String),
+}
+
+/// Progress of a connection, sent as a line of the response after every
+/// flush and once more when the connection ends
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Progress {
+    /// Lines read so far, skipped ones included
+    pub lines: u64,
+    /// Lines whose events are in staging, a retry with the same idempotency
+    /// key skips them
+    pub committed: u64,
+    /// Events flushed to staging
+    pub ingested: u64,
+    /// Lines that could not be ingested
+    pub rejected: u64,
+    /// Lines an earlier connection with the same idempotency key committed
+    pub skipped: u64,
+    /// Lines rejected since the previous progress, the first
+    /// [`MAX_REPORTED_ROW_ERRORS`] of them
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<RowError>,
+    /// Whether the whole body was read
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub done: bool,
+    /// Why the connection ended before the whole body was read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Checkpoint {
+    committed: u64,
+    active: bool,
+}
+
+type CheckpointKey = (String, String);
+
+/// Lines committed under each idempotency key of a stream, so that a client
+/// retrying a dropped connection doesn't ingest them twice
+#[derive(Debug, Clone)]
+pub struct Checkpoints(Arc<Mutex<LruCache<CheckpointKey, Checkpoint>>>);
+
+impl Checkpoints {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        Self(Arc::new(Mutex::new(LruCache::new(capacity))))
+    }
+
+    /// Claims `key` for a connection to `stream_name`, one connection holds
+    /// a key at a time
+    pub fn claim(&self, stream_name: &str, key: &str) -> Result<Claim, NdjsonError> {
+        let key = (stream_name.to_owned(), key.to_owned());
+        let mut checkpoints = self.0.lock().unwrap();
+        let checkpoint = checkpoints.get_or_insert_mut(key.clone(), || Checkpoint {
+            committed: 0,
+            active: false,
+        });
+        if checkpoint.active {
+            return Err(NdjsonError::KeyInUse(key.1));
+        }
+        checkpoint.active = true;
+        Ok(Claim {
+            checkpoints: self.clone(),
+            committed: checkpoint.committed,
+            key,
+        })
+    }
+}
+
+/// An idempotency key held by a connection, released when dropped
+#[derive(Debug)]
+pub struct Claim {
+    checkpoints: Checkpoints,
+    key: CheckpointKey,
+    committed: u64,
+}
+
+impl Claim {
+    fn commit(&mut self, lines: u64) {
+        self.committed = lines;
+        let mut checkpoints = self.checkpoints.0.lock().unwrap();
+        checkpoints.push(
+            self.key.clone(),
+            Checkpoint {
+                committed: lines,
+                active: true,
+            },
+        );
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        let mut checkpoints = self.checkpoints.0.lock().unwrap();
+        if let Some(checkpoint) = checkpoints.peek_mut(&self.key) {
+            checkpoint.active = false;
+        }
+    }
+}
+
+// splits the body into lines and collects the events of the current batch
+struct Reader {
+    limits: StreamLimits,
+    strict: bool,
+    skip: u64,
+    received: u64,
+    line: Vec<u8>,
+    too_long: bool,
+    batch: Vec<Value>,
+    batch_size: usize,
+    progress: Progress,
+    reported: u64,
+}
+
+impl Reader {
+    fn feed(&mut self, mut chunk: &[u8]) -> Result<(), NdjsonError> {
+        self.received += chunk.len() as u64;
+        if self.received > self.limits.max_size {
+            return Err(NdjsonError::TooLarge(self.limits.max_size));
+        }
+        while let Some(end) = chunk.iter().position(|byte| *byte == b'\n') {
+            self.extend(&chunk[..end]);
+            self.end_line()?;
+            chunk = &chunk[end + 1..];
+        }
+        self.extend(chunk);
+        Ok(())
+    }
+
+    // a last line without a newline
+    fn finish(&mut self) -> Result<(), NdjsonError> {
+        if self.line.is_empty() && !self.too_long {
+            return Ok(());
+        }
+        self.end_line()
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        if self.too_long {
+            return;
+        }
+        if self.line.len() + bytes.len() > self.limits.max_line_size {
+            // the rest of the line is dropped as it arrives
+            self.too_long = true;
+            self.line = Vec::new();
+            return;
+        }
+        self.line.extend_from_slice(bytes);
+    }
+
+    fn end_line(&mut self) -> Result<(), NdjsonError> {
+        self.progress.lines += 1;
+        let line = self.progress.lines;
+        let bytes = std::mem::take(&mut self.line);
+        let too_long = std::mem::take(&mut self.too_long);
+        if line <= self.skip {
+            self.progress.skipped += 1;
+            return Ok(());
+        }
+        if too_long {
+            let error = format!("line is longer than {} bytes", self.limits.max_line_size);
+            return self.reject(line, error);
+        }
+        if bytes.iter().all(u8::is_ascii_whitespace) {
+            return Ok(());
+        }
+        match serde_json::from_slice::<Value>(&bytes) {
+            Ok(event @ Value::Object(_)) => {
+                self.batch.push(event);
+                self.batch_size += bytes.len();
+                Ok(())
+            }
+            Ok(_) => self.reject(line, "line is not a JSON object".to_string()),
+            Err(err) => self.reject(line, err.to_string()),
+        }
+    }
+
+    fn reject(&mut self, line: u64, error: String) -> Result<(), NdjsonError> {
+        if self.strict {
+            return Err(NdjsonError::Malformed { line, error });
+        }
+        self.progress.rejected += 1;
+        if self.progress.errors.len() < MAX_REPORTED_ROW_ERRORS {
+            self.progress.errors.push(RowError { line, error });
+        }
+        Ok(())
+    }
+
+    // hands the batch to `flush` and commits the lines read so far
+    async fn flush<F, Fut>(
+        &mut self,
+        flush: &mut F,
+        claim: &mut Option<Claim>,
+        report: &mut impl FnMut(&Progress),
+    ) -> Result<(), NdjsonError>
+    where
+        F: FnMut(Vec<Value>, usize) -> Fut,
+        Fut: Future<Output = Result<(), String>>,
+    {
+        let events = std::mem::take(&mut self.batch);
+        let size = std::mem::take(&mut self.batch_size);
+        let count = events.len() as u64;
+        if count > 0 {
+            flush(events, size)
+                .await
+                .map_err(|error| NdjsonError::Flush {
+                    line: self.progress.lines,
+                    error,
+                })?;
+        }
+        self.progress.ingested += count;
+        // a retry that drops before reaching the lines committed earlier
+        // keeps them committed
+        self.progress.committed = self.progress.lines.max(self.skip);
+        if let Some(claim) = claim {
+            claim.commit(self.progress.committed);
+        }
+        if self.progress.lines > self.reported {
+            self.reported = self.progress.lines;
+            report(&self.progress);
+            self.progress.errors.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Reads newline delimited JSON from `body`, handing the events of each
+/// batch to `flush` along with the bytes they took.
+///
+/// Batches are flushed when they are full and every flush interval, after
+/// each flush the progress is passed to `report`. Lines that aren't a JSON
+/// object are rejected, which ends the connection when `strict`. Lines
+/// before the first line not committed under the claimed idempotency key
+/// are skipped.
+///
+/// Events read before the body ends or fails are flushed all the same. The
+/// final progress is reported and returned.
+pub async fn ingest<B, E, F, Fut>(
+    mut body: B,
+    limits: StreamLimits,
+    strict: bool,
+    mut claim: Option<Claim>,
+    mut flush: F,
+    mut report: impl FnMut(&Progress),
+) -> Progress
+where
+    B: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Display,
+    F: FnMut(Vec<Value>, usize) -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    let mut reader = Reader {
+        limits,
+        strict,
+        skip: claim.as_ref().map_or(0, |claim| claim.committed),
+        received: 0,
+        line: Vec::new(),
+        too_long: false,
+        batch: Vec::new(),
+        batch_size: 0,
+        progress: Progress::default(),
+        reported: 0,
+    };
+    let mut ticks = interval(limits.flush_interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // the first tick completes right away
+    ticks.tick().await;
+
+    let read = loop {
+        tokio::select! {
+            chunk = body.next() => match chunk {
+                Some(Ok(chunk)) => {
+                    if let Err(err) = reader.feed(&chunk) {
+                        break Err(err);
+                    }
+                    if reader.batch.len() >= limits.batch_lines {
+                        if let Err(err) = reader.flush(&mut flush, &mut claim, &mut report).await {
+                            break Err(err);
+                        }
+                    }
+                }
+                Some(Err(err)) => break Err(NdjsonError::Payload(err.to_string())),
+                None => break reader.finish(),
+            },
+            _ = ticks.tick() => {
+                if let Err(err) = reader.flush(&mut flush, &mut claim, &mut report).await {
+                    break Err(err);
+                }
+            }
+        }
+    };
+    let flushed = reader.flush(&mut flush, &mut claim, &mut report).await;
+
+    let mut progress = reader.progress;
+    match read.and(flushed) {
+        Ok(()) => progress.done = true,
+        Err(err) => progress.error = Some(err.to_string()),
+    }
+    report(&progress);
+    progress
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc, time::Duration};
+
+    use bytes::Bytes;
+    use futures::{channel::mpsc, stream};
+    use serde_json::{json, Value};
+
+    use super::{ingest, is_ndjson, Checkpoints, NdjsonError, Progress, StreamLimits};
+
+    const LIMITS: StreamLimits = StreamLimits {
+        max_line_size: 1024,
+        max_size: 1 << 30,
+        flush_interval: Duration::from_secs(3600),
+        batch_lines: 1000,
+    };
+
+    #[derive(Default, Clone)]
+    struct Sink {
+        events: Rc<RefCell<Vec<Value>>>,
+        reports: Rc<RefCell<Vec<Progress>>>,
+    }
+
+    impl Sink {
+        fn seqs(&self) -> Vec<u64> {
+            let events = self.events.borrow();
+            events
+                .iter()
+                .map(|event| event["seq"].as_u64().unwrap())
+                .collect()
+        }
+    }
+
+    // the body in chunks of `size` bytes, ending with `end` when given
+    fn chunked(
+        body: &str,
+        size: usize,
+        end: Option<&str>,
+    ) -> impl futures::Stream<Item = Result<Bytes, String>> + Unpin {
+        let mut chunks: Vec<Result<Bytes, String>> = body
+            .as_bytes()
+            .chunks(size)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        chunks.extend(end.map(|err| Err(err.to_string())));
+        stream::iter(chunks)
+    }
+
+    fn lines(seqs: impl IntoIterator<Item = u64>) -> String {
+        seqs.into_iter()
+            .map(|seq| format!("{}\n", json!({"seq": seq, "message": "connection reset"})))
+            .collect()
+    }
+
+    async fn run(
+        body: impl futures::Stream<Item = Result<Bytes, String>> + Unpin,
+        limits: StreamLimits,
+        strict: bool,
+        claim: Option<super::Claim>,
+        sink: &Sink,
+    ) -> Progress {
+        let events = sink.events.clone();
+        let reports = sink.reports.clone();
+        ingest(
+            body,
+            limits,
+            strict,
+            claim,
+            move |batch, _| {
+                let events = events.clone();
+                async move {
+                    events.borrow_mut().extend(batch);
+                    Ok(())
+                }
+            },
+            move |progress| reports.borrow_mut().push(progress.clone()),
+        )
+        .await
+    }
+
+    #[test]
+    fn content_types() {
+        for content_type in [
+            "application/x-ndjson",
+            "application/x-ndjson; charset=utf-8",
+            "application/ndjson",
+            "Application/JSONL",
+        ] {
+            assert!(is_ndjson(content_type), "{content_type}");
+        }
+        assert!(!is_ndjson("application/json"));
+    }
+
+    #[actix_web::test]
+    async fn malformed_lines_are_reported_and_skipped() {
+        let mut body = String::new();
+        let mut malformed = Vec::new();
+        for seq in 1..=10_000u64 {
+            if seq % 997 == 0 {
+                body.push_str("{\"seq\": \n");
+                malformed.push(seq);
+            } else if seq % 1499 == 0 {
+                body.push_str("[1, 2]\n");
+                malformed.push(seq);
+            } else {
+                body.push_str(&lines([seq]));
+            }
+        }
+        let sink = Sink::default();
+        let progress = run(chunked(&body, 7, None), LIMITS, false, None, &sink).await;
+
+        assert!(progress.done, "{progress:?}");
+        assert_eq!(progress.lines, 10_000);
+        assert_eq!(progress.committed, 10_000);
+        assert_eq!(progress.rejected, malformed.len() as u64);
+        assert_eq!(progress.ingested, 10_000 - malformed.len() as u64);
+        let expected: Vec<u64> = (1..=10_000)
+            .filter(|seq| !malformed.contains(seq))
+            .collect();
+        assert_eq!(sink.seqs(), expected);
+
+        // every rejected line is reported once, by its line number
+        let reports = sink.reports.borrow();
+        assert!(reports.len() > 9, "{}", reports.len());
+        let reported: Vec<u64> = reports
+            .iter()
+            .flat_map(|progress| progress.errors.iter().map(|error| error.line))
+            .collect();
+        assert_eq!(reported, malformed);
+        assert!(reports
+            .windows(2)
+            .all(|pair| pair[0].committed <= pair[1].committed));
+    }
+
+    #[actix_web::test]
+    async fn strict_mode_stops_at_the_first_malformed_line() {
+        let body = format!("{}not json\n{}", lines(1..=4), lines(6..=8));
+        let sink = Sink::default();
+        let progress = run(chunked(&body, 5, None), LIMITS, true, None, &sink).await;
+
+        let error = progress.error.unwrap();
+        assert!(error.contains("Malformed line 5"), "{error}");
+        assert!(!progress.done);
+        // the lines before it are kept
+        assert_eq!(sink.seqs(), [1, 2, 3, 4]);
+        assert_eq!(progress.committed, 4);
+    }
+
+    #[actix_web::test]
+    async fn limits_apply_to_lines_and_the_whole_body() {
+        let limits = StreamLimits {
+            max_line_size: 64,
+            max_size: 400,
+            ..LIMITS
+        };
+        let long = format!("{}\n", json!({"seq": 0, "message": "x".repeat(100)}));
+        let body = format!("{}{long}{}", lines([1]), lines([2]));
+        let sink = Sink::default();
+        let progress = run(chunked(&body, 16, None), limits, false, None, &sink).await;
+        assert!(progress.done);
+        assert_eq!(sink.seqs(), [1, 2]);
+        let reports = sink.reports.borrow();
+        assert_eq!(reports[0].errors[0].line, 2);
+        assert!(reports[0].errors[0].error.contains("longer than 64 bytes"));
+
+        let body = lines(1..=20);
+        assert!(body.len() > 400);
+        let sink = Sink::default();
+        let progress = run(chunked(&body, 100, None), limits, false, None, &sink).await;
+        assert!(progress.error.unwrap().contains("larger than 400 bytes"));
+        // the chunks within the limit are kept
+        assert!(!sink.seqs().is_empty());
+        assert_eq!(
+            sink.seqs(),
+            (1..=sink.seqs().len() as u64).collect::<Vec<_>>()
+        );
+    }
+
+    #[actix_web::test]
+    async fn open_connections_are_flushed_on_the_interval() {
+        let limits = StreamLimits {
+            flush_interval: Duration::from_millis(20),
+            ..LIMITS
+        };
+        let (sender, body) = mpsc::unbounded::<Result<Bytes, String>>();
+        let sink = Sink::default();
+        let running = {
+            let sink = sink.clone();
+            actix_web::rt::spawn(async move { run(body, limits, false, None, &sink).await })
+        };
+
+        sender.unbounded_send(Ok(lines([1, 2]).into())).unwrap();
+        actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+        // the connection is still open
+        assert_eq!(sink.seqs(), [1, 2]);
+        assert_eq!(sink.reports.borrow().last().unwrap().committed, 2);
+
+        sender.unbounded_send(Ok(lines([3]).into())).unwrap();
+        drop(sender);
+        let progress = running.await.unwrap();
+        assert!(progress.done);
+        assert_eq!(sink.seqs(), [1, 2, 3]);
+    }
+
+    #[actix_web::test]
+    async fn retries_skip_committed_lines() {
+        let checkpoints = Checkpoints::new(16);
+
+        // the connection drops inside line 3001
+        let body = format!("{}{{\"seq\": 30", lines(1..=3000));
+        let sink = Sink::default();
+        let claim = checkpoints.claim("app", "tail-1").unwrap();
+        let progress = run(
+            chunked(&body, 64, Some("reset")),
+            LIMITS,
+            false,
+            Some(claim),
+            &sink,
+        )
+        .await;
+        assert_eq!(progress.error.unwrap(), "Connection closed: reset");
+        assert_eq!(progress.committed, 3000);
+
+        // the retry sends everything again
+        let claim = checkpoints.claim("app", "tail-1").unwrap();
+        let retry = run(
+            chunked(&lines(1..=5000), 64, None),
+            LIMITS,
+            false,
+            Some(claim),
+            &sink,
+        )
+        .await;
+        assert!(retry.done);
+        assert_eq!(retry.skipped, 3000);
+        assert_eq!(retry.ingested, 2000);
+        assert_eq!(sink.seqs(), (1..=5000).collect::<Vec<_>>());
+
+        // keys are per stream and held by one connection at a time
+        let held = checkpoints.claim("app", "tail-2").unwrap();
+        assert!(matches!(
+            checkpoints.claim("app", "tail-2"),
+            Err(NdjsonError::KeyInUse(_))
+        ));
+        assert_eq!(checkpoints.claim("web", "tail-1").unwrap().committed, 0);
+        drop(held);
+        assert!(checkpoints.claim("app", "tail-2").is_ok());
+    }
+}
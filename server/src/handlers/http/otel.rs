@@ -16,13 +16,48 @@
  *
  */
 
+use actix_web::{http::header::ContentType, HttpResponse};
 use bytes::Bytes;
-use serde_json::Value;
+use chrono::{DateTime, SecondsFormat};
+use prost::Message;
+use serde_json::{json, Value};
+mod metrics;
 mod proto;
+mod protobuf;
+mod traces;
+use crate::handlers::http::otel::proto::common::v1::{InstrumentationScope, KeyValue};
+use crate::handlers::http::otel::proto::logs::v1::LogRecord;
 use crate::handlers::http::otel::proto::logs::v1::LogRecordFlags;
 use crate::handlers::http::otel::proto::logs::v1::LogsData;
 use crate::handlers::http::otel::proto::logs::v1::SeverityNumber;
+use crate::handlers::http::otel::proto::metrics::v1::MetricsData;
+use crate::handlers::http::otel::proto::resource::v1::Resource;
+use crate::handlers::http::otel::proto::trace::v1::TracesData;
 use std::collections::BTreeMap;
+use std::fmt::Display;
+
+pub use metrics::{flatten_metrics, OtelDataPoint};
+pub use traces::flatten_traces;
+
+/// Resource attribute naming the stream the logs, spans or metrics of a
+/// resource go to, when the request has no stream header
+pub const STREAM_ATTRIBUTE: &str = "parseable.stream";
+
+/// Stream spans go to when neither the request nor their resource names one
+pub const TRACES_STREAM: &str = "oteltraces";
+
+/// How many spans are staged as a single event
+pub const SPANS_PER_EVENT: usize = 1000;
+
+/// Stream metric data points go to when neither the request nor their
+/// resource names one
+pub const METRICS_STREAM: &str = "otelmetrics";
+
+/// How many metric data points are staged as a single event
+pub const DATA_POINTS_PER_EVENT: usize = 1000;
+
+/// How many distinct errors are reported back in a partial success
+const MAX_REPORTED_ERRORS: usize = 5;
 // Value can be one of types - String, Bool, Int, Double, ArrayValue, AnyValue, KeyValueList, Byte
 fn collect_json_from_any_value(
     key: &String,
@@ -127,201 +162,532 @@ fn value_to_string(value: serde_json::Value) -> String {
     }
 }
 
-pub fn flatten_otel_logs(body: &Bytes) -> Vec<BTreeMap<String, Value>> {
-    let mut vec_otel_json: Vec<BTreeMap<String, Value>> = Vec::new();
-    let body_str = std::str::from_utf8(body).unwrap();
-    let message: LogsData = serde_json::from_str(body_str).unwrap();
-    for records in message.resource_logs.iter() {
-        for record in records.iter() {
-            let mut otel_json: BTreeMap<String, Value> = BTreeMap::new();
-            for resource in record.resource.iter() {
-                let attributes = &resource.attributes;
-                for attributes in attributes.iter() {
-                    for attribute in attributes {
-                        let key = &attribute.key;
-                        let value = &attribute.value;
-                        let value_json =
-                            collect_json_from_values(value, &format!("resource_{}", key));
-                        for key in value_json.keys() {
-                            otel_json.insert(key.to_owned(), value_json[key].to_owned());
-                        }
-                    }
-                }
-                if resource.dropped_attributes_count.is_some() {
-                    otel_json.insert(
-                        "resource_dropped_attributes_count".to_string(),
-                        Value::Number(serde_json::Number::from(
-                            resource.dropped_attributes_count.unwrap(),
-                        )),
-                    );
-                }
+fn insert_attributes(
+    json: &mut BTreeMap<String, Value>,
+    prefix: &str,
+    attributes: &Option<Vec<KeyValue>>,
+) {
+    for attribute in attributes.iter().flatten() {
+        json.extend(collect_json_from_values(
+            &attribute.value,
+            &format!("{}_{}", prefix, attribute.key),
+        ));
+    }
+}
+
+/// Nanoseconds since the epoch of an OTLP time, none when unset
+fn nanos(time: &Option<String>) -> Option<i64> {
+    time.as_ref()?.parse().ok().filter(|time| *time > 0)
+}
+
+fn timestamp(nanos: i64) -> Value {
+    Value::String(
+        DateTime::from_timestamp_nanos(nanos).to_rfc3339_opts(SecondsFormat::AutoSi, true),
+    )
+}
+
+/// Stream named by the [`STREAM_ATTRIBUTE`] of a resource
+fn resource_stream(resource: Option<&Resource>) -> Option<String> {
+    resource
+        .iter()
+        .flat_map(|resource| resource.attributes.iter().flatten())
+        .find(|attribute| attribute.key == STREAM_ATTRIBUTE)
+        .and_then(|attribute| attribute.value.as_ref()?.str_val.clone())
+}
+
+fn flatten_resource(
+    resource: Option<&Resource>,
+    schema_url: Option<&String>,
+) -> BTreeMap<String, Value> {
+    let mut json = BTreeMap::new();
+    if let Some(resource) = resource {
+        insert_attributes(&mut json, "resource", &resource.attributes);
+        if let Some(count) = resource.dropped_attributes_count {
+            json.insert(
+                "resource_dropped_attributes_count".to_string(),
+                Value::from(count),
+            );
+        }
+    }
+    if let Some(schema_url) = schema_url {
+        json.insert(
+            "resource_schema_url".to_string(),
+            Value::String(schema_url.to_owned()),
+        );
+    }
+    json
+}
+
+fn flatten_scope(scope: Option<&InstrumentationScope>) -> BTreeMap<String, Value> {
+    let mut json = BTreeMap::new();
+    if let Some(InstrumentationScope {
+        name,
+        version,
+        attributes,
+        dropped_attributes_count,
+    }) = scope
+    {
+        if let Some(name) = name {
+            json.insert(
+                "instrumentation_scope_name".to_string(),
+                Value::String(name.to_owned()),
+            );
+        }
+        if let Some(version) = version {
+            json.insert(
+                "instrumentation_scope_version".to_string(),
+                Value::String(version.to_owned()),
+            );
+        }
+        insert_attributes(&mut json, "instrumentation_scope", attributes);
+        if let Some(count) = dropped_attributes_count {
+            json.insert(
+                "instrumentation_scope_dropped_attributes_count".to_string(),
+                Value::from(*count),
+            );
+        }
+    }
+    json
+}
+
+fn flatten_record(log_record: &LogRecord, json: &mut BTreeMap<String, Value>) {
+    if let Some(time) = &log_record.time_unix_nano {
+        json.insert("time_unix_nano".to_string(), Value::String(time.to_owned()));
+    }
+    if let Some(time) = &log_record.observed_time_unix_nano {
+        json.insert(
+            "observed_time_unix_nano".to_string(),
+            Value::String(time.to_owned()),
+        );
+    }
+    if let Some(severity_number) = log_record.severity_number {
+        json.insert("severity_number".to_string(), Value::from(severity_number));
+        json.insert(
+            "severity_text".to_string(),
+            Value::String(SeverityNumber::as_str_name(severity_number).to_string()),
+        );
+    }
+    if let Some(severity_text) = &log_record.severity_text {
+        json.insert(
+            "severity_text".to_string(),
+            Value::String(severity_text.to_owned()),
+        );
+    }
+    json.extend(collect_json_from_values(
+        &log_record.body,
+        &"body".to_string(),
+    ));
+    insert_attributes(json, "log_record", &log_record.attributes);
+    if let Some(count) = log_record.dropped_attributes_count {
+        json.insert(
+            "log_record_dropped_attributes_count".to_string(),
+            Value::from(count),
+        );
+    }
+    if let Some(flags) = log_record.flags {
+        json.insert("flags_number".to_string(), Value::from(flags));
+        json.insert(
+            "flags_string".to_string(),
+            Value::String(LogRecordFlags::as_str_name(flags).to_string()),
+        );
+    }
+    // ids are hex, in either case
+    if let Some(span_id) = log_record.span_id.as_ref().filter(|id| !id.is_empty()) {
+        json.insert(
+            "span_id".to_string(),
+            Value::String(span_id.to_ascii_lowercase()),
+        );
+    }
+    if let Some(trace_id) = log_record.trace_id.as_ref().filter(|id| !id.is_empty()) {
+        json.insert(
+            "trace_id".to_string(),
+            Value::String(trace_id.to_ascii_lowercase()),
+        );
+    }
+}
+
+/// A flattened log record or span and the stream its resource names, if any
+#[derive(Debug)]
+pub struct OtelRecord {
+    pub stream: Option<String>,
+    pub record: BTreeMap<String, Value>,
+}
+
+/// Flattens every log record into a single JSON object, along with the
+/// attributes of its resource and scope
+pub fn flatten_logs(logs: &LogsData) -> Vec<OtelRecord> {
+    let mut records = Vec::new();
+    for resource_logs in logs.resource_logs.iter().flatten() {
+        let stream = resource_stream(resource_logs.resource.as_ref());
+        let resource_json = flatten_resource(
+            resource_logs.resource.as_ref(),
+            resource_logs.schema_url.as_ref(),
+        );
+        for scope_logs in resource_logs.scope_logs.iter().flatten() {
+            let mut scope_json = resource_json.clone();
+            scope_json.extend(flatten_scope(scope_logs.scope.as_ref()));
+            if let Some(schema_url) = &scope_logs.schema_url {
+                scope_json.insert(
+                    "scope_log_schema_url".to_string(),
+                    Value::String(schema_url.to_owned()),
+                );
+            }
+            for log_record in &scope_logs.log_records {
+                let mut record = scope_json.clone();
+                flatten_record(log_record, &mut record);
+                records.push(OtelRecord {
+                    stream: stream.clone(),
+                    record,
+                });
             }
+        }
+    }
+    records
+}
 
-            for scope_logs in record.scope_logs.iter() {
-                for scope_log in scope_logs.iter() {
-                    for instrumentation_scope in scope_log.scope.iter() {
-                        if instrumentation_scope.name.is_some() {
-                            otel_json.insert(
-                                "instrumentation_scope_name".to_string(),
-                                Value::String(
-                                    instrumentation_scope.name.as_ref().unwrap().to_string(),
-                                ),
-                            );
-                        }
-                        if instrumentation_scope.version.is_some() {
-                            otel_json.insert(
-                                "instrumentation_scope_version".to_string(),
-                                Value::String(
-                                    instrumentation_scope.version.as_ref().unwrap().to_string(),
-                                ),
-                            );
-                        }
-                        let attributes = &instrumentation_scope.attributes;
-                        for attributes in attributes.iter() {
-                            for attribute in attributes {
-                                let key = &attribute.key;
-                                let value = &attribute.value;
-                                let value_json = collect_json_from_values(
-                                    value,
-                                    &format!("instrumentation_scope_{}", key),
-                                );
-                                for key in value_json.keys() {
-                                    otel_json.insert(key.to_owned(), value_json[key].to_owned());
-                                }
-                            }
-                        }
-                        if instrumentation_scope.dropped_attributes_count.is_some() {
-                            otel_json.insert(
-                                "instrumentation_scope_dropped_attributes_count".to_string(),
-                                Value::Number(serde_json::Number::from(
-                                    instrumentation_scope.dropped_attributes_count.unwrap(),
-                                )),
-                            );
-                        }
-                    }
+pub fn flatten_otel_logs(body: &Bytes) -> Result<Vec<BTreeMap<String, Value>>, serde_json::Error> {
+    let logs: LogsData = serde_json::from_slice(body)?;
+    Ok(flatten_logs(&logs)
+        .into_iter()
+        .map(|otel| otel.record)
+        .collect())
+}
 
-                    for log_record in scope_log.log_records.iter() {
-                        let mut log_record_json: BTreeMap<String, Value> = BTreeMap::new();
-                        if log_record.time_unix_nano.is_some() {
-                            log_record_json.insert(
-                                "time_unix_nano".to_string(),
-                                Value::String(
-                                    log_record.time_unix_nano.as_ref().unwrap().to_string(),
-                                ),
-                            );
-                        }
-                        if log_record.observed_time_unix_nano.is_some() {
-                            log_record_json.insert(
-                                "observed_time_unix_nano".to_string(),
-                                Value::String(
-                                    log_record
-                                        .observed_time_unix_nano
-                                        .as_ref()
-                                        .unwrap()
-                                        .to_string(),
-                                ),
-                            );
-                        }
-                        if log_record.severity_number.is_some() {
-                            let severity_number: i32 = log_record.severity_number.unwrap();
-                            log_record_json.insert(
-                                "severity_number".to_string(),
-                                Value::Number(serde_json::Number::from(severity_number)),
-                            );
-                            if log_record.severity_text.is_none() {
-                                log_record_json.insert(
-                                    "severity_text".to_string(),
-                                    Value::String(
-                                        SeverityNumber::as_str_name(severity_number).to_string(),
-                                    ),
-                                );
-                            }
-                        }
-                        if log_record.severity_text.is_some() {
-                            log_record_json.insert(
-                                "severity_text".to_string(),
-                                Value::String(
-                                    log_record.severity_text.as_ref().unwrap().to_string(),
-                                ),
-                            );
-                        }
+/// Kind of telemetry an OTLP/HTTP request carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Logs,
+    Traces,
+    Metrics,
+}
 
-                        if log_record.body.is_some() {
-                            let body = &log_record.body;
-                            let body_json = collect_json_from_values(body, &"body".to_string());
-                            for key in body_json.keys() {
-                                log_record_json.insert(key.to_owned(), body_json[key].to_owned());
-                            }
-                        }
+/// Encoding of an OTLP/HTTP request, and of its response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Protobuf,
+    Json,
+}
 
-                        for attributes in log_record.attributes.iter() {
-                            for attribute in attributes {
-                                let key = &attribute.key;
-                                let value = &attribute.value;
-                                let value_json =
-                                    collect_json_from_values(value, &format!("log_record_{}", key));
-                                for key in value_json.keys() {
-                                    log_record_json
-                                        .insert(key.to_owned(), value_json[key].to_owned());
-                                }
-                            }
-                        }
+impl Encoding {
+    /// Encoding of a request with the given content type, JSON when it has none
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type.trim().to_ascii_lowercase().as_str() {
+            "application/x-protobuf" | "application/protobuf" => Some(Self::Protobuf),
+            "application/json" | "" => Some(Self::Json),
+            _ => None,
+        }
+    }
 
-                        if log_record.dropped_attributes_count.is_some() {
-                            log_record_json.insert(
-                                "log_record_dropped_attributes_count".to_string(),
-                                Value::Number(serde_json::Number::from(
-                                    log_record.dropped_attributes_count.unwrap(),
-                                )),
-                            );
-                        }
+    pub fn decode_logs(self, body: &[u8]) -> anyhow::Result<LogsData> {
+        Ok(match self {
+            Self::Protobuf => protobuf::ExportLogsServiceRequest::decode(body)?.into(),
+            Self::Json => serde_json::from_slice(body)?,
+        })
+    }
 
-                        if log_record.flags.is_some() {
-                            let flags: u32 = log_record.flags.unwrap();
-                            log_record_json.insert(
-                                "flags_number".to_string(),
-                                Value::Number(serde_json::Number::from(flags)),
-                            );
-                            log_record_json.insert(
-                                "flags_string".to_string(),
-                                Value::String(LogRecordFlags::as_str_name(flags).to_string()),
-                            );
-                        }
+    pub fn decode_traces(self, body: &[u8]) -> anyhow::Result<TracesData> {
+        Ok(match self {
+            Self::Protobuf => protobuf::ExportTraceServiceRequest::decode(body)?.into(),
+            Self::Json => serde_json::from_slice(body)?,
+        })
+    }
 
-                        if log_record.span_id.is_some() {
-                            log_record_json.insert(
-                                "span_id".to_string(),
-                                Value::String(log_record.span_id.as_ref().unwrap().to_string()),
-                            );
-                        }
+    pub fn decode_metrics(self, body: &[u8]) -> anyhow::Result<MetricsData> {
+        Ok(match self {
+            Self::Protobuf => protobuf::ExportMetricsServiceRequest::decode(body)?.into(),
+            Self::Json => serde_json::from_slice(body)?,
+        })
+    }
 
-                        if log_record.trace_id.is_some() {
-                            log_record_json.insert(
-                                "trace_id".to_string(),
-                                Value::String(log_record.trace_id.as_ref().unwrap().to_string()),
-                            );
-                        }
-                        for key in log_record_json.keys() {
-                            otel_json.insert(key.to_owned(), log_record_json[key].to_owned());
-                        }
-                        vec_otel_json.push(otel_json.clone());
+    /// Export service response of the signal, in this encoding
+    pub fn response(self, signal: Signal, partial_success: &PartialSuccess) -> HttpResponse {
+        let rejected = (partial_success.rejected > 0).then(|| partial_success.error_message());
+        match self {
+            Self::Protobuf => {
+                let response = match signal {
+                    Signal::Logs => protobuf::ExportLogsServiceResponse {
+                        partial_success: rejected.map(|error_message| {
+                            protobuf::ExportLogsPartialSuccess {
+                                rejected_log_records: partial_success.rejected,
+                                error_message,
+                            }
+                        }),
                     }
-
-                    if scope_log.schema_url.is_some() {
-                        otel_json.insert(
-                            "scope_log_schema_url".to_string(),
-                            Value::String(scope_log.schema_url.as_ref().unwrap().to_string()),
-                        );
+                    .encode_to_vec(),
+                    Signal::Traces => protobuf::ExportTraceServiceResponse {
+                        partial_success: rejected.map(|error_message| {
+                            protobuf::ExportTracePartialSuccess {
+                                rejected_spans: partial_success.rejected,
+                                error_message,
+                            }
+                        }),
                     }
-                }
+                    .encode_to_vec(),
+                    Signal::Metrics => protobuf::ExportMetricsServiceResponse {
+                        partial_success: rejected.map(|error_message| {
+                            protobuf::ExportMetricsPartialSuccess {
+                                rejected_data_points: partial_success.rejected,
+                                error_message,
+                            }
+                        }),
+                    }
+                    .encode_to_vec(),
+                };
+                HttpResponse::Ok()
+                    .content_type("application/x-protobuf")
+                    .body(response)
             }
-            if record.schema_url.is_some() {
-                otel_json.insert(
-                    "resource_schema_url".to_string(),
-                    Value::String(record.schema_url.as_ref().unwrap().to_string()),
-                );
+            Self::Json => {
+                let rejected_key = match signal {
+                    Signal::Logs => "rejectedLogRecords",
+                    Signal::Traces => "rejectedSpans",
+                    Signal::Metrics => "rejectedDataPoints",
+                };
+                // 64 bit integers are strings in OTLP/JSON
+                let response = match rejected {
+                    Some(error_message) => json!({
+                        "partialSuccess": {
+                            rejected_key: partial_success.rejected.to_string(),
+                            "errorMessage": error_message,
+                        }
+                    }),
+                    None => json!({}),
+                };
+                HttpResponse::Ok()
+                    .insert_header(ContentType::json())
+                    .body(response.to_string())
             }
         }
     }
-    vec_otel_json
+}
+
+/// Log records, spans or data points of a request that could not be ingested
+#[derive(Debug, Default)]
+pub struct PartialSuccess {
+    rejected: i64,
+    errors: Vec<String>,
+}
+
+impl PartialSuccess {
+    pub fn reject(&mut self, error: impl Display) {
+        self.reject_many(1, error);
+    }
+
+    pub fn reject_many(&mut self, count: usize, error: impl Display) {
+        self.rejected += count as i64;
+        let error = error.to_string();
+        if self.errors.len() < MAX_REPORTED_ERRORS && !self.errors.contains(&error) {
+            self.errors.push(error);
+        }
+    }
+
+    pub fn rejected(&self) -> i64 {
+        self.rejected
+    }
+
+    fn error_message(&self) -> String {
+        self.errors.join("; ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io::Write, sync::Arc};
+
+    use actix_web::{
+        body::to_bytes,
+        http::header,
+        test::{call_and_read_body, init_service, TestRequest},
+        web, App, HttpMessage, HttpRequest,
+    };
+    use arrow_array::{cast::AsArray, RecordBatch};
+    use bytes::Bytes;
+    use datafusion::{datasource::MemTable, prelude::SessionContext};
+    use flate2::{write::GzEncoder, Compression};
+    use prost::Message;
+    use serde_json::{json, Value};
+
+    use super::{flatten_logs, protobuf, Encoding, PartialSuccess, Signal};
+    use crate::event::format::{json, EventFormat};
+
+    const LOGS_JSON: &[u8] = include_bytes!("otel/testdata/logs.json");
+    const LOGS_PROTOBUF: &[u8] = include_bytes!("otel/testdata/logs.pb");
+
+    #[test]
+    fn encoding_follows_content_type() {
+        for (content_type, encoding) in [
+            ("application/x-protobuf", Some(Encoding::Protobuf)),
+            ("application/protobuf", Some(Encoding::Protobuf)),
+            ("application/json", Some(Encoding::Json)),
+            ("Application/JSON", Some(Encoding::Json)),
+            ("", Some(Encoding::Json)),
+            ("text/plain", None),
+        ] {
+            assert_eq!(Encoding::from_content_type(content_type), encoding);
+        }
+    }
+
+    #[test]
+    fn json_and_protobuf_flatten_alike() {
+        let from_json = flatten_logs(&Encoding::Json.decode_logs(LOGS_JSON).unwrap());
+        let from_protobuf = flatten_logs(&Encoding::Protobuf.decode_logs(LOGS_PROTOBUF).unwrap());
+        assert_eq!(from_json.len(), 3);
+        for (json, protobuf) in from_json.iter().zip(&from_protobuf) {
+            assert_eq!(json.stream, protobuf.stream);
+            assert_eq!(json.record, protobuf.record);
+        }
+
+        let streams: Vec<_> = from_json
+            .iter()
+            .map(|otel| otel.stream.as_deref())
+            .collect();
+        assert_eq!(streams, [Some("payments"), Some("payments"), None]);
+
+        let first = &from_json[0].record;
+        assert_eq!(first["resource_service.name"], "checkout");
+        assert_eq!(first["instrumentation_scope_name"], "my.library");
+        assert_eq!(
+            first["instrumentation_scope_my.scope.attribute"],
+            "some scope attribute"
+        );
+        assert_eq!(first["severity_number"], 10);
+        assert_eq!(first["severity_text"], "Information");
+        assert_eq!(first["body"], "Example log record");
+        assert_eq!(first["trace_id"], "5b8efff798038103d269b633813fc60c");
+        assert_eq!(first["span_id"], "eee19b7ec3c1b174");
+        assert_eq!(first["log_record_boolean.attribute"], true);
+        assert_eq!(first["log_record_int.attribute"], "10");
+        assert_eq!(first["log_record_double.attribute"], "637.704");
+    }
+
+    #[test]
+    fn records_only_carry_their_own_fields() {
+        let records = flatten_logs(&Encoding::Json.decode_logs(LOGS_JSON).unwrap());
+
+        let second = &records[1].record;
+        assert_eq!(second["body"], "Payment failed");
+        assert_eq!(second["severity_text"], "SEVERITY_NUMBER_ERROR");
+        assert_eq!(second["instrumentation_scope_name"], "my.library");
+        for key in [
+            "log_record_string.attribute",
+            "trace_id",
+            "observed_time_unix_nano",
+        ] {
+            assert!(!second.contains_key(key), "{key}");
+        }
+
+        let third = &records[2].record;
+        assert_eq!(third["resource_service.name"], "frontend");
+        assert!(!third.contains_key("instrumentation_scope_name"));
+        assert!(!third.contains_key("resource_parseable.stream"));
+    }
+
+    #[actix_web::test]
+    async fn flattened_records_can_be_queried() {
+        let payments: Vec<Value> =
+            flatten_logs(&Encoding::Protobuf.decode_logs(LOGS_PROTOBUF).unwrap())
+                .into_iter()
+                .filter(|otel| otel.stream.as_deref() == Some("payments"))
+                .map(|otel| Value::Object(otel.record.into_iter().collect()))
+                .collect();
+        let event = json::Event {
+            data: Value::Array(payments),
+            tags: String::default(),
+            metadata: String::default(),
+        };
+        let (rb, _) = event.into_recordbatch(HashMap::new(), None, None).unwrap();
+
+        let ctx = SessionContext::new();
+        ctx.register_table(
+            "payments",
+            Arc::new(MemTable::try_new(rb.schema(), vec![vec![rb]]).unwrap()),
+        )
+        .unwrap();
+        let batches = ctx
+            .sql(
+                r#"SELECT body, severity_text, trace_id, "log_record_int.attribute"
+                   FROM payments WHERE "resource_service.name" = 'checkout'
+                   ORDER BY time_unix_nano"#,
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let batch: &RecordBatch = &batches[0];
+        let column = |index: usize| -> Vec<Option<&str>> {
+            batch.column(index).as_string::<i32>().iter().collect()
+        };
+        assert_eq!(
+            column(0),
+            [Some("Example log record"), Some("Payment failed")]
+        );
+        assert_eq!(
+            column(1),
+            [Some("Information"), Some("SEVERITY_NUMBER_ERROR")]
+        );
+        assert_eq!(column(2), [Some("5b8efff798038103d269b633813fc60c"), None]);
+        assert_eq!(column(3), [Some("10"), None]);
+    }
+
+    async fn count_records(req: HttpRequest, body: Bytes) -> String {
+        let encoding = Encoding::from_content_type(req.content_type()).unwrap();
+        flatten_logs(&encoding.decode_logs(&body).unwrap())
+            .len()
+            .to_string()
+    }
+
+    #[actix_web::test]
+    async fn gzipped_requests_are_decoded() {
+        let app = init_service(App::new().route("/v1/logs", web::post().to(count_records))).await;
+        for (content_type, payload) in [
+            ("application/x-protobuf", LOGS_PROTOBUF),
+            ("application/json", LOGS_JSON),
+        ] {
+            let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+            gzip.write_all(payload).unwrap();
+            let req = TestRequest::post()
+                .uri("/v1/logs")
+                .insert_header((header::CONTENT_TYPE, content_type))
+                .insert_header((header::CONTENT_ENCODING, "gzip"))
+                .set_payload(gzip.finish().unwrap())
+                .to_request();
+            assert_eq!(call_and_read_body(&app, req).await, "3");
+        }
+    }
+
+    #[actix_web::test]
+    async fn rejections_are_a_partial_success() {
+        let accepted = Encoding::Json.response(Signal::Logs, &PartialSuccess::default());
+        let body = to_bytes(accepted.into_body()).await.unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), json!({}));
+
+        let mut partial_success = PartialSuccess::default();
+        partial_success.reject("no stream");
+        partial_success.reject("no stream");
+        partial_success.reject("schema mismatch");
+
+        let response = Encoding::Json.response(Signal::Logs, &partial_success);
+        let body = to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap(),
+            json!({
+                "partialSuccess": {
+                    "rejectedLogRecords": "3",
+                    "errorMessage": "no stream; schema mismatch",
+                }
+            })
+        );
+
+        let response = Encoding::Protobuf.response(Signal::Logs, &partial_success);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/x-protobuf"
+        );
+        let body = to_bytes(response.into_body()).await.unwrap();
+        let response = protobuf::ExportLogsServiceResponse::decode(body).unwrap();
+        let partial_success = response.partial_success.unwrap();
+        assert_eq!(partial_success.rejected_log_records, 3);
+        assert_eq!(partial_success.error_message, "no stream; schema mismatch");
+    }
 }
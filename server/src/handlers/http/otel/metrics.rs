@@ -0,0 +1,539 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use super::proto::common::v1::KeyValue;
+use super::proto::metrics::v1::{
+    AggregationTemporality, Buckets, ExponentialHistogramDataPoint, HistogramDataPoint, Metric,
+    MetricsData, NumberDataPoint, SummaryDataPoint,
+};
+use super::{
+    flatten_resource, flatten_scope, insert_attributes, nanos, resource_stream, timestamp,
+};
+
+/// The rows of a single metric data point and the stream its resource names,
+/// if any
+///
+/// Gauge and sum data points are a single row, histograms a row per bucket
+/// and summaries a row per quantile.
+#[derive(Debug)]
+pub struct OtelDataPoint {
+    pub stream: Option<String>,
+    pub rows: Vec<BTreeMap<String, Value>>,
+}
+
+fn count(count: &Option<String>) -> Option<f64> {
+    count.as_ref()?.parse().ok()
+}
+
+fn counts(counts: &Option<Vec<String>>) -> Vec<f64> {
+    counts
+        .iter()
+        .flatten()
+        .map(|count| count.parse().unwrap_or_default())
+        .collect()
+}
+
+fn insert_number(json: &mut BTreeMap<String, Value>, key: &str, number: Option<f64>) {
+    if let Some(number) = number {
+        json.insert(key.to_string(), Value::from(number));
+    }
+}
+
+fn insert_temporality(json: &mut BTreeMap<String, Value>, temporality: Option<i32>) {
+    json.insert(
+        "aggregation_temporality".to_string(),
+        Value::String(
+            AggregationTemporality::as_str_name(temporality.unwrap_or_default()).to_string(),
+        ),
+    );
+}
+
+/// Columns common to every kind of data point
+fn flatten_data_point(
+    json: &mut BTreeMap<String, Value>,
+    attributes: &Option<Vec<KeyValue>>,
+    start_time: &Option<String>,
+    time: &Option<String>,
+    flags: Option<u32>,
+) {
+    insert_attributes(json, "data_point", attributes);
+    if let Some(start_time) = nanos(start_time) {
+        json.insert("start_time".to_string(), timestamp(start_time));
+    }
+    if let Some(time) = nanos(time) {
+        json.insert("time".to_string(), timestamp(time));
+    }
+    if let Some(flags) = flags {
+        json.insert("flags".to_string(), Value::from(flags));
+    }
+}
+
+fn number_value(point: &NumberDataPoint) -> Option<f64> {
+    // every value is a double, so that gauges reporting ints and doubles share a column
+    point.as_double.or_else(|| {
+        point
+            .as_int
+            .as_ref()?
+            .parse::<i64>()
+            .ok()
+            .map(|value| value as f64)
+    })
+}
+
+fn number_rows(
+    metric_json: &BTreeMap<String, Value>,
+    points: &[NumberDataPoint],
+) -> Vec<Vec<BTreeMap<String, Value>>> {
+    points
+        .iter()
+        .map(|point| {
+            let mut json = metric_json.clone();
+            flatten_data_point(
+                &mut json,
+                &point.attributes,
+                &point.start_time_unix_nano,
+                &point.time_unix_nano,
+                point.flags,
+            );
+            insert_number(&mut json, "value", number_value(point));
+            vec![json]
+        })
+        .collect()
+}
+
+/// Label of a bucket upper bound, as understood by `histogram_quantile`
+fn bucket_label(bound: f64) -> Value {
+    if bound == f64::INFINITY {
+        Value::String("+Inf".to_string())
+    } else {
+        Value::String(bound.to_string())
+    }
+}
+
+/// A row per bucket, with the bucket's upper bound in `le` and the cumulative
+/// count of observations at or below it in `bucket_count`
+fn bucket_rows(
+    json: &BTreeMap<String, Value>,
+    buckets: impl IntoIterator<Item = (f64, f64)>,
+) -> Vec<BTreeMap<String, Value>> {
+    let mut cumulative = 0.0;
+    let mut rows: Vec<_> = buckets
+        .into_iter()
+        .map(|(bound, count)| {
+            cumulative += count;
+            let mut row = json.clone();
+            row.insert("le".to_string(), bucket_label(bound));
+            row.insert("bucket_count".to_string(), Value::from(cumulative));
+            row
+        })
+        .collect();
+    if rows.is_empty() {
+        rows.push(json.clone());
+    }
+    rows
+}
+
+fn histogram_rows(
+    metric_json: &BTreeMap<String, Value>,
+    temporality: Option<i32>,
+    point: &HistogramDataPoint,
+) -> Vec<BTreeMap<String, Value>> {
+    let mut json = metric_json.clone();
+    flatten_data_point(
+        &mut json,
+        &point.attributes,
+        &point.start_time_unix_nano,
+        &point.time_unix_nano,
+        point.flags,
+    );
+    insert_temporality(&mut json, temporality);
+    insert_number(&mut json, "count", count(&point.count));
+    insert_number(&mut json, "sum", point.sum);
+    insert_number(&mut json, "min", point.min);
+    insert_number(&mut json, "max", point.max);
+
+    // the bucket past the last explicit bound is unbounded
+    let bounds = point.explicit_bounds.as_deref().unwrap_or_default();
+    let buckets = counts(&point.bucket_counts)
+        .into_iter()
+        .enumerate()
+        .map(|(index, count)| (bounds.get(index).copied().unwrap_or(f64::INFINITY), count));
+    bucket_rows(&json, buckets)
+}
+
+/// Explicit buckets of an exponential histogram, in increasing order of
+/// their upper bounds
+///
+/// Bucket `index` of a scale spans `(base^index, base^(index + 1)]` for
+/// positive values, with `base = 2^(2^-scale)`, and is mirrored for negative
+/// ones. Observations within the zero threshold are counted in a bucket up to
+/// the threshold, and every histogram ends with a `+Inf` bucket.
+fn exponential_buckets(point: &ExponentialHistogramDataPoint) -> Vec<(f64, f64)> {
+    let exponent = 2f64.powi(-point.scale.unwrap_or_default());
+    let bound = |index: i64| 2f64.powf(index as f64 * exponent);
+    let indexed = |buckets: &Option<Buckets>| -> Vec<(i64, f64)> {
+        buckets
+            .iter()
+            .flat_map(|buckets| {
+                let offset = buckets.offset.unwrap_or_default() as i64;
+                counts(&buckets.bucket_counts)
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(index, count)| (offset + index as i64, count))
+            })
+            .collect()
+    };
+
+    let negative = indexed(&point.negative);
+    let zero_count = count(&point.zero_count).unwrap_or_default();
+    let mut buckets: Vec<(f64, f64)> = negative
+        .iter()
+        .rev()
+        .map(|(index, count)| (-bound(*index), *count))
+        .collect();
+    if zero_count > 0.0 || !negative.is_empty() {
+        buckets.push((point.zero_threshold.unwrap_or_default(), zero_count));
+    }
+    buckets.extend(
+        indexed(&point.positive)
+            .into_iter()
+            .map(|(index, count)| (bound(index + 1), count)),
+    );
+    buckets.push((f64::INFINITY, 0.0));
+    buckets
+}
+
+fn exponential_histogram_rows(
+    metric_json: &BTreeMap<String, Value>,
+    temporality: Option<i32>,
+    point: &ExponentialHistogramDataPoint,
+) -> Vec<BTreeMap<String, Value>> {
+    let mut json = metric_json.clone();
+    flatten_data_point(
+        &mut json,
+        &point.attributes,
+        &point.start_time_unix_nano,
+        &point.time_unix_nano,
+        point.flags,
+    );
+    insert_temporality(&mut json, temporality);
+    insert_number(&mut json, "count", count(&point.count));
+    insert_number(&mut json, "sum", point.sum);
+    insert_number(&mut json, "min", point.min);
+    insert_number(&mut json, "max", point.max);
+    bucket_rows(&json, exponential_buckets(point))
+}
+
+fn summary_rows(
+    metric_json: &BTreeMap<String, Value>,
+    point: &SummaryDataPoint,
+) -> Vec<BTreeMap<String, Value>> {
+    let mut json = metric_json.clone();
+    flatten_data_point(
+        &mut json,
+        &point.attributes,
+        &point.start_time_unix_nano,
+        &point.time_unix_nano,
+        point.flags,
+    );
+    insert_number(&mut json, "count", count(&point.count));
+    insert_number(&mut json, "sum", point.sum);
+
+    let mut rows: Vec<_> = point
+        .quantile_values
+        .iter()
+        .flatten()
+        .map(|quantile| {
+            let mut row = json.clone();
+            insert_number(&mut row, "quantile", quantile.quantile);
+            insert_number(&mut row, "value", quantile.value);
+            row
+        })
+        .collect();
+    if rows.is_empty() {
+        rows.push(json);
+    }
+    rows
+}
+
+fn flatten_metric(
+    metric: &Metric,
+    scope_json: &BTreeMap<String, Value>,
+    stream: &Option<String>,
+    data_points: &mut Vec<OtelDataPoint>,
+) {
+    let mut metric_json = scope_json.clone();
+    for (key, value) in [
+        ("metric_name", &metric.name),
+        ("metric_description", &metric.description),
+        ("metric_unit", &metric.unit),
+    ] {
+        if let Some(value) = value {
+            metric_json.insert(key.to_string(), Value::String(value.to_owned()));
+        }
+    }
+    let mut push = |metric_type: &str, rows: Vec<Vec<BTreeMap<String, Value>>>| {
+        data_points.extend(rows.into_iter().map(|mut rows| {
+            for row in &mut rows {
+                row.insert(
+                    "metric_type".to_string(),
+                    Value::String(metric_type.to_string()),
+                );
+            }
+            OtelDataPoint {
+                stream: stream.clone(),
+                rows,
+            }
+        }))
+    };
+
+    if let Some(gauge) = &metric.gauge {
+        let rows = number_rows(&metric_json, &gauge.data_points);
+        push("gauge", rows);
+    }
+    if let Some(sum) = &metric.sum {
+        let mut sum_json = metric_json.clone();
+        insert_temporality(&mut sum_json, sum.aggregation_temporality);
+        sum_json.insert(
+            "is_monotonic".to_string(),
+            Value::Bool(sum.is_monotonic.unwrap_or_default()),
+        );
+        let rows = number_rows(&sum_json, &sum.data_points);
+        push("sum", rows);
+    }
+    if let Some(histogram) = &metric.histogram {
+        let rows = histogram
+            .data_points
+            .iter()
+            .map(|point| histogram_rows(&metric_json, histogram.aggregation_temporality, point));
+        push("histogram", rows.collect());
+    }
+    if let Some(histogram) = &metric.exponential_histogram {
+        let rows = histogram.data_points.iter().map(|point| {
+            exponential_histogram_rows(&metric_json, histogram.aggregation_temporality, point)
+        });
+        push("exponential_histogram", rows.collect());
+    }
+    if let Some(summary) = &metric.summary {
+        let rows = summary
+            .data_points
+            .iter()
+            .map(|point| summary_rows(&metric_json, point));
+        push("summary", rows.collect());
+    }
+}
+
+/// Flattens every metric data point into rows, along with the attributes of
+/// its resource, scope and metric
+///
+/// Sums keep their `aggregation_temporality` and `is_monotonic` flags, so
+/// that cumulative monotonic counters can be turned into per second rates
+/// with the `rate` window function at query time. Histograms are a row per
+/// bucket, with the bucket's upper bound in `le` and its cumulative count in
+/// `bucket_count`, ready for the `histogram_quantile` aggregate. Exponential
+/// histograms are converted to explicit buckets the same way.
+pub fn flatten_metrics(metrics: &MetricsData) -> Vec<OtelDataPoint> {
+    let mut data_points = Vec::new();
+    for resource_metrics in metrics.resource_metrics.iter().flatten() {
+        let stream = resource_stream(resource_metrics.resource.as_ref());
+        let resource_json = flatten_resource(
+            resource_metrics.resource.as_ref(),
+            resource_metrics.schema_url.as_ref(),
+        );
+        for scope_metrics in resource_metrics.scope_metrics.iter().flatten() {
+            let mut scope_json = resource_json.clone();
+            scope_json.extend(flatten_scope(scope_metrics.scope.as_ref()));
+            if let Some(schema_url) = &scope_metrics.schema_url {
+                scope_json.insert(
+                    "scope_metric_schema_url".to_string(),
+                    Value::String(schema_url.to_owned()),
+                );
+            }
+            for metric in &scope_metrics.metrics {
+                flatten_metric(metric, &scope_json, &stream, &mut data_points);
+            }
+        }
+    }
+    data_points
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use arrow_array::{cast::AsArray, types::Float64Type};
+    use datafusion::{datasource::MemTable, prelude::SessionContext};
+    use serde_json::Value;
+
+    use super::flatten_metrics;
+    use crate::event::format::{json, EventFormat};
+    use crate::handlers::http::otel::{Encoding, METRICS_STREAM};
+
+    const METRICS_JSON: &[u8] = include_bytes!("testdata/metrics.json");
+    const METRICS_PROTOBUF: &[u8] = include_bytes!("testdata/metrics.pb");
+
+    fn column<'a>(rows: &'a [super::BTreeMap<String, Value>], key: &str) -> Vec<&'a Value> {
+        rows.iter().map(|row| &row[key]).collect()
+    }
+
+    #[test]
+    fn json_and_protobuf_flatten_alike() {
+        let from_json = flatten_metrics(&Encoding::Json.decode_metrics(METRICS_JSON).unwrap());
+        let from_protobuf =
+            flatten_metrics(&Encoding::Protobuf.decode_metrics(METRICS_PROTOBUF).unwrap());
+        assert_eq!(from_json.len(), 7);
+        assert_eq!(from_json.len(), from_protobuf.len());
+        for (json, protobuf) in from_json.iter().zip(&from_protobuf) {
+            assert_eq!(json.stream, protobuf.stream);
+            assert_eq!(json.rows, protobuf.rows);
+        }
+    }
+
+    #[test]
+    fn gauges_and_sums_are_a_row_per_data_point() {
+        let data_points = flatten_metrics(&Encoding::Json.decode_metrics(METRICS_JSON).unwrap());
+
+        let cpu = &data_points[0].rows;
+        assert_eq!(cpu.len(), 1);
+        assert_eq!(cpu[0]["resource_service.name"], "checkout");
+        assert_eq!(cpu[0]["metric_name"], "process.cpu.utilization");
+        assert_eq!(cpu[0]["metric_type"], "gauge");
+        assert_eq!(cpu[0]["metric_unit"], "1");
+        assert_eq!(cpu[0]["data_point_state"], "user");
+        assert_eq!(cpu[0]["time"], "2018-12-13T14:51:00Z");
+        assert_eq!(cpu[0]["value"], 0.42);
+        assert!(!cpu[0].contains_key("aggregation_temporality"));
+        assert_eq!(data_points[1].rows[0]["data_point_state"], "system");
+
+        let requests = &data_points[2].rows;
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["metric_type"], "sum");
+        assert_eq!(requests[0]["start_time"], "2018-12-13T14:50:00Z");
+        assert_eq!(requests[0]["value"], 1024.0);
+        assert_eq!(
+            requests[0]["aggregation_temporality"],
+            "AGGREGATION_TEMPORALITY_CUMULATIVE"
+        );
+        assert_eq!(requests[0]["is_monotonic"], true);
+
+        let memory = &data_points[6];
+        assert_eq!(memory.stream.as_deref(), Some("infra"));
+        assert_eq!(memory.rows[0]["value"], 2048.0);
+        assert!(data_points[..6].iter().all(|point| point.stream.is_none()));
+    }
+
+    #[test]
+    fn histograms_are_a_row_per_bucket() {
+        let data_points = flatten_metrics(&Encoding::Json.decode_metrics(METRICS_JSON).unwrap());
+
+        let duration = &data_points[3].rows;
+        assert_eq!(duration.len(), 9);
+        assert!(duration.iter().all(|row| row["metric_type"] == "histogram"
+            && row["count"] == 100.0
+            && row["sum"] == 55.5
+            && row["max"] == 9.5
+            && row["data_point_http.route"] == "/checkout"));
+        assert_eq!(
+            column(duration, "le"),
+            ["0.05", "0.1", "0.25", "0.5", "1", "2.5", "5", "10", "+Inf"]
+        );
+        assert_eq!(
+            column(duration, "bucket_count"),
+            [10.0, 30.0, 60.0, 80.0, 90.0, 97.0, 98.0, 100.0, 100.0]
+        );
+
+        let client = &data_points[4].rows;
+        assert!(client
+            .iter()
+            .all(|row| row["metric_type"] == "exponential_histogram"
+                && row["aggregation_temporality"] == "AGGREGATION_TEMPORALITY_DELTA"));
+        assert_eq!(
+            column(client, "le"),
+            [
+                "-1",
+                "0",
+                "1.4142135623730951",
+                "2",
+                "2.8284271247461903",
+                "+Inf"
+            ]
+        );
+        assert_eq!(
+            column(client, "bucket_count"),
+            [1.0, 2.0, 3.0, 5.0, 6.0, 6.0]
+        );
+    }
+
+    #[test]
+    fn summaries_are_a_row_per_quantile() {
+        let data_points = flatten_metrics(&Encoding::Json.decode_metrics(METRICS_JSON).unwrap());
+
+        let latency = &data_points[5].rows;
+        assert_eq!(latency.len(), 2);
+        assert!(latency.iter().all(|row| row["metric_type"] == "summary"
+            && row["count"] == 50.0
+            && row["sum"] == 12.5));
+        assert_eq!(column(latency, "quantile"), [0.5, 0.99]);
+        assert_eq!(column(latency, "value"), [0.2, 1.5]);
+    }
+
+    #[actix_web::test]
+    async fn histogram_buckets_can_be_aggregated() {
+        let rows: Vec<Value> =
+            flatten_metrics(&Encoding::Protobuf.decode_metrics(METRICS_PROTOBUF).unwrap())
+                .into_iter()
+                .flat_map(|point| point.rows)
+                .map(|row| Value::Object(row.into_iter().collect()))
+                .collect();
+        let event = json::Event {
+            data: Value::Array(rows),
+            tags: String::default(),
+            metadata: String::default(),
+        };
+        let (rb, _) = event.into_recordbatch(HashMap::new(), None, None).unwrap();
+
+        let ctx = SessionContext::new();
+        crate::query::functions::register_all(&ctx);
+        ctx.register_table(
+            METRICS_STREAM,
+            Arc::new(MemTable::try_new(rb.schema(), vec![vec![rb]]).unwrap()),
+        )
+        .unwrap();
+
+        let batches = ctx
+            .sql(
+                r#"SELECT histogram_quantile(0.5, le, bucket_count),
+                          histogram_quantile(0.9, le, bucket_count)
+                   FROM otelmetrics
+                   WHERE metric_name = 'http.server.duration'"#,
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let p50 = batches[0].column(0).as_primitive::<Float64Type>().value(0);
+        let p90 = batches[0].column(1).as_primitive::<Float64Type>().value(0);
+        assert!((p50 - 0.2).abs() < 1e-9);
+        assert!((p90 - 1.0).abs() < 1e-9);
+    }
+}
@@ -19,6 +19,7 @@
  // This file was generated by protoc-gen-rust-protobuf. The file was edited after the generation.
  // All the repeated fields were changed to Option<Vec<T>> and the `oneof` fields were changed to Option<T>.
 
+ use crate::handlers::http::otel::proto::string_or_number;
  use serde::{Deserialize, Serialize};
  #[derive(Serialize, Deserialize, Debug, Clone)]
  /// AnyValue is used to represent any type of attribute value. AnyValue may contain a
@@ -36,13 +37,13 @@
      pub str_val: Option<String>,
      #[serde(rename = "boolValue")]
      pub bool_val: Option<bool>,
-     #[serde(rename = "intValue")]
+     #[serde(rename = "intValue", default, deserialize_with = "string_or_number")]
      pub int_val: Option<String>,
-     #[serde(rename = "doubleValue")]
+     #[serde(rename = "doubleValue", default, deserialize_with = "string_or_number")]
      pub double_val: Option<String>,
      #[serde(rename = "arrayValue")]
      pub array_val: Option<ArrayValue>,
-     #[serde(rename = "keyVauleList")]
+     #[serde(rename = "kvlistValue", alias = "keyVauleList")]
      pub kv_list_val: Option<KeyValueList>,
      #[serde(rename = "bytesValue")]
      pub bytes_val: Option<String>,
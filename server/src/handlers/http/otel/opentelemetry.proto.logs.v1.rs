@@ -23,6 +23,7 @@
  use crate::handlers::http::otel::proto::common::v1::KeyValue;
  use crate::handlers::http::otel::proto::common::v1::Value;
  use crate::handlers::http::otel::proto::resource::v1::Resource;
+ use crate::handlers::http::otel::proto::string_or_number;
  use serde::{Deserialize, Serialize};
  
  #[derive(Serialize, Deserialize, Debug)]
@@ -69,7 +70,7 @@
      /// an empty instrumentation scope name (unknown).
      pub scope: Option<InstrumentationScope>,
      /// A list of log records.
-     #[serde(rename = "logRecords")]
+     #[serde(rename = "logRecords", default)]
      pub log_records: Vec<LogRecord>,
      /// This schema_url applies to all logs in the "logs" field.
      #[serde(rename = "schemaUrl")]
@@ -83,7 +84,7 @@
      /// time_unix_nano is the time when the event occurred.
      /// Value is UNIX Epoch time in nanoseconds since 00:00:00 UTC on 1 January 1970.
      /// Value of 0 indicates unknown or missing timestamp.
-     #[serde(rename = "timeUnixNano")]
+     #[serde(rename = "timeUnixNano", default, deserialize_with = "string_or_number")]
      pub time_unix_nano: Option<String>,
      /// Time when the event was observed by the collection system.
      /// For events that originate in OpenTelemetry (e.g. using OpenTelemetry Logging SDK)
@@ -100,7 +101,7 @@
      ///
      /// Value is UNIX Epoch time in nanoseconds since 00:00:00 UTC on 1 January 1970.
      /// Value of 0 indicates unknown or missing timestamp.
-     #[serde(rename = "observedTimeUnixNano")]
+     #[serde(rename = "observedTimeUnixNano", default, deserialize_with = "string_or_number")]
      pub observed_time_unix_nano: Option<String>,
      /// Numerical value of the severity, normalized to values described in Log Data Model.
      /// \[Optional\].
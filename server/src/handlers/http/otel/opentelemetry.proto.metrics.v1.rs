@@ -0,0 +1,379 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+// This file was generated by protoc-gen-rust-protobuf. The file was edited after the generation.
+ // All the repeated fields were changed to Option<Vec<T>> and the `oneof` fields were changed to Option<T>.
+
+ use crate::handlers::http::otel::proto::common::v1::InstrumentationScope;
+ use crate::handlers::http::otel::proto::common::v1::KeyValue;
+ use crate::handlers::http::otel::proto::resource::v1::Resource;
+ use crate::handlers::http::otel::proto::{string_or_number, strings_or_numbers};
+ use serde::{Deserialize, Serialize};
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// MetricsData represents the metrics data that can be stored in a persistent
+ /// storage, OR can be embedded by other protocols that transfer OTLP metrics
+ /// data but do not implement the OTLP protocol.
+ ///
+ /// The main difference between this message and collector protocol is that
+ /// in this message there will not be any "control" or "metadata" specific to
+ /// OTLP protocol.
+ ///
+ /// When new fields are added into this message, the OTLP request MUST be updated
+ /// as well.
+ pub struct MetricsData {
+     /// An array of ResourceMetrics.
+     /// For data coming from a single resource this array will typically contain
+     /// one element. Intermediary nodes that receive data from multiple origins
+     /// typically batch the data before forwarding further and in that case this
+     /// array will contain multiple elements.
+     #[serde(rename = "resourceMetrics")]
+     pub resource_metrics: Option<Vec<ResourceMetrics>>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// A collection of ScopeMetrics from a Resource.
+ pub struct ResourceMetrics {
+     /// The resource for the metrics in this message.
+     /// If this field is not set then no resource info is known.
+     pub resource: Option<Resource>,
+     /// A list of metrics that originate from a resource.
+     #[serde(rename = "scopeMetrics")]
+     pub scope_metrics: Option<Vec<ScopeMetrics>>,
+     /// This schema_url applies to the data in the "resource" field. It does not apply
+     /// to the data in the "scope_metrics" field which have their own schema_url field.
+     #[serde(rename = "schemaUrl")]
+     pub schema_url: Option<String>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// A collection of Metrics produced by an Scope.
+ pub struct ScopeMetrics {
+     /// The instrumentation scope information for the metrics in this message.
+     /// Semantically when InstrumentationScope isn't set, it is equivalent with
+     /// an empty instrumentation scope name (unknown).
+     pub scope: Option<InstrumentationScope>,
+     /// A list of metrics that originate from an instrumentation library.
+     #[serde(default)]
+     pub metrics: Vec<Metric>,
+     /// This schema_url applies to all metrics in the "metrics" field.
+     #[serde(rename = "schemaUrl")]
+     pub schema_url: Option<String>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// Defines a Metric which has one or more timeseries. The data model and
+ /// relation between entities is described in
+ /// <https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/metrics/data-model.md>
+ ///
+ /// A metric carries at most one of gauge, sum, histogram,
+ /// exponential_histogram or summary.
+ pub struct Metric {
+     /// name of the metric.
+     pub name: Option<String>,
+     /// description of the metric, which can be used in documentation.
+     pub description: Option<String>,
+     /// unit in which the metric value is reported. Follows the format
+     /// described by <http://unitsofmeasure.org/ucum.html>.
+     pub unit: Option<String>,
+     pub gauge: Option<Gauge>,
+     pub sum: Option<Sum>,
+     pub histogram: Option<Histogram>,
+     #[serde(rename = "exponentialHistogram")]
+     pub exponential_histogram: Option<ExponentialHistogram>,
+     pub summary: Option<Summary>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// Gauge represents the type of a scalar metric that always exports the
+ /// "current value" for every data point. It should be used for an "unknown"
+ /// aggregation.
+ pub struct Gauge {
+     #[serde(rename = "dataPoints", default)]
+     pub data_points: Vec<NumberDataPoint>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// Sum represents the type of a scalar metric that is calculated as a sum of all
+ /// reported measurements over a time interval.
+ pub struct Sum {
+     #[serde(rename = "dataPoints", default)]
+     pub data_points: Vec<NumberDataPoint>,
+     /// aggregation_temporality describes if the aggregator reports delta changes
+     /// since last report time, or cumulative changes since a fixed start time.
+     #[serde(rename = "aggregationTemporality")]
+     pub aggregation_temporality: Option<i32>,
+     /// If "true" means that the sum is monotonic.
+     #[serde(rename = "isMonotonic")]
+     pub is_monotonic: Option<bool>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// Histogram represents the type of a metric that is calculated by aggregating
+ /// as a Histogram of all reported measurements over a time interval.
+ pub struct Histogram {
+     #[serde(rename = "dataPoints", default)]
+     pub data_points: Vec<HistogramDataPoint>,
+     /// aggregation_temporality describes if the aggregator reports delta changes
+     /// since last report time, or cumulative changes since a fixed start time.
+     #[serde(rename = "aggregationTemporality")]
+     pub aggregation_temporality: Option<i32>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// ExponentialHistogram represents the type of a metric that is calculated by aggregating
+ /// as a ExponentialHistogram of all reported double measurements over a time interval.
+ pub struct ExponentialHistogram {
+     #[serde(rename = "dataPoints", default)]
+     pub data_points: Vec<ExponentialHistogramDataPoint>,
+     /// aggregation_temporality describes if the aggregator reports delta changes
+     /// since last report time, or cumulative changes since a fixed start time.
+     #[serde(rename = "aggregationTemporality")]
+     pub aggregation_temporality: Option<i32>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// Summary metric data are used to convey quantile summaries,
+ /// a Prometheus (see: <https://prometheus.io/docs/concepts/metric_types/#summary>)
+ /// and OpenMetrics (see: <https://github.com/OpenObservability/OpenMetrics/blob/4dbf6075567ab43296eed941037c12951faafb92/protos/prometheus.proto#L45>)
+ /// data type. These data points cannot always be merged in a meaningful way.
+ pub struct Summary {
+     #[serde(rename = "dataPoints", default)]
+     pub data_points: Vec<SummaryDataPoint>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// NumberDataPoint is a single data point in a timeseries that describes the
+ /// time-varying scalar value of a metric.
+ pub struct NumberDataPoint {
+     /// The set of key/value pairs that uniquely identify the timeseries from
+     /// where this point belongs. The list may be empty (may contain 0 elements).
+     /// Attribute keys MUST be unique (it is not allowed to have more than one
+     /// attribute with the same key).
+     pub attributes: Option<Vec<KeyValue>>,
+     /// StartTimeUnixNano is optional but strongly encouraged, see the
+     /// the detailed comments above Metric.
+     ///
+     /// Value is UNIX Epoch time in nanoseconds since 00:00:00 UTC on 1 January
+     /// 1970.
+     #[serde(rename = "startTimeUnixNano", default, deserialize_with = "string_or_number")]
+     pub start_time_unix_nano: Option<String>,
+     /// TimeUnixNano is required, see the detailed comments above Metric.
+     ///
+     /// Value is UNIX Epoch time in nanoseconds since 00:00:00 UTC on 1 January
+     /// 1970.
+     #[serde(rename = "timeUnixNano", default, deserialize_with = "string_or_number")]
+     pub time_unix_nano: Option<String>,
+     #[serde(rename = "asDouble")]
+     pub as_double: Option<f64>,
+     #[serde(rename = "asInt", default, deserialize_with = "string_or_number")]
+     pub as_int: Option<String>,
+     /// Flags that apply to this specific data point. See DataPointFlags
+     /// for the available flags and their meaning.
+     pub flags: Option<u32>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// HistogramDataPoint is a single data point in a timeseries that describes the
+ /// time-varying values of a Histogram. A Histogram contains summary statistics
+ /// for a population of values, it may optionally contain the distribution of
+ /// those values across a set of buckets.
+ pub struct HistogramDataPoint {
+     /// The set of key/value pairs that uniquely identify the timeseries from
+     /// where this point belongs. The list may be empty (may contain 0 elements).
+     /// Attribute keys MUST be unique (it is not allowed to have more than one
+     /// attribute with the same key).
+     pub attributes: Option<Vec<KeyValue>>,
+     #[serde(rename = "startTimeUnixNano", default, deserialize_with = "string_or_number")]
+     pub start_time_unix_nano: Option<String>,
+     #[serde(rename = "timeUnixNano", default, deserialize_with = "string_or_number")]
+     pub time_unix_nano: Option<String>,
+     /// count is the number of values in the population. Must be non-negative. This
+     /// value must be equal to the sum of the "count" fields in buckets if a
+     /// histogram is provided.
+     #[serde(default, deserialize_with = "string_or_number")]
+     pub count: Option<String>,
+     /// sum of the values in the population. If count is zero then this field
+     /// must be zero.
+     pub sum: Option<f64>,
+     /// bucket_counts is an optional field contains the count values of histogram
+     /// for each bucket.
+     ///
+     /// The sum of the bucket_counts must equal the value in the count field.
+     ///
+     /// The number of elements in bucket_counts array must be by one greater than
+     /// the number of elements in explicit_bounds array.
+     #[serde(rename = "bucketCounts", default, deserialize_with = "strings_or_numbers")]
+     pub bucket_counts: Option<Vec<String>>,
+     /// explicit_bounds specifies buckets with explicitly defined bounds for values.
+     ///
+     /// The boundaries for bucket at index i are:
+     ///
+     /// (-infinity, explicit_bounds\[i]\] for i == 0
+     /// (explicit_bounds\[i-1\], explicit_bounds\[i\]\] for 0 < i < size(explicit_bounds)
+     /// (explicit_bounds\[i-1\], +infinity) for i == size(explicit_bounds)
+     #[serde(rename = "explicitBounds")]
+     pub explicit_bounds: Option<Vec<f64>>,
+     /// Flags that apply to this specific data point. See DataPointFlags
+     /// for the available flags and their meaning.
+     pub flags: Option<u32>,
+     /// min is the minimum value over (start_time, end_time].
+     pub min: Option<f64>,
+     /// max is the maximum value over (start_time, end_time].
+     pub max: Option<f64>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// ExponentialHistogramDataPoint is a single data point in a timeseries that describes the
+ /// time-varying values of a ExponentialHistogram of double values. A ExponentialHistogram contains
+ /// summary statistics for a population of values, it may optionally contain the
+ /// distribution of those values across a set of buckets.
+ pub struct ExponentialHistogramDataPoint {
+     /// The set of key/value pairs that uniquely identify the timeseries from
+     /// where this point belongs. The list may be empty (may contain 0 elements).
+     /// Attribute keys MUST be unique (it is not allowed to have more than one
+     /// attribute with the same key).
+     pub attributes: Option<Vec<KeyValue>>,
+     #[serde(rename = "startTimeUnixNano", default, deserialize_with = "string_or_number")]
+     pub start_time_unix_nano: Option<String>,
+     #[serde(rename = "timeUnixNano", default, deserialize_with = "string_or_number")]
+     pub time_unix_nano: Option<String>,
+     /// count is the number of values in the population. Must be
+     /// non-negative. This value must be equal to the sum of the "bucket_counts"
+     /// values in the positive and negative Buckets plus the "zero_count" field.
+     #[serde(default, deserialize_with = "string_or_number")]
+     pub count: Option<String>,
+     /// sum of the values in the population. If count is zero then this field
+     /// must be zero.
+     pub sum: Option<f64>,
+     /// scale describes the resolution of the histogram. Boundaries are
+     /// located at powers of the base, where:
+     ///
+     ///    base = (2^(2^-scale))
+     ///
+     /// The histogram bucket identified by `index`, a signed integer,
+     /// contains values that are greater than (base^index) and
+     /// less than or equal to (base^(index+1)).
+     pub scale: Option<i32>,
+     /// zero_count is the count of values that are either exactly zero or
+     /// within the region considered zero by the instrumentation at the
+     /// tolerated degree of precision.
+     #[serde(rename = "zeroCount", default, deserialize_with = "string_or_number")]
+     pub zero_count: Option<String>,
+     /// positive carries the positive range of exponential bucket counts.
+     pub positive: Option<Buckets>,
+     /// negative carries the negative range of exponential bucket counts.
+     pub negative: Option<Buckets>,
+     /// Flags that apply to this specific data point. See DataPointFlags
+     /// for the available flags and their meaning.
+     pub flags: Option<u32>,
+     /// min is the minimum value over (start_time, end_time].
+     pub min: Option<f64>,
+     /// max is the maximum value over (start_time, end_time].
+     pub max: Option<f64>,
+     /// ZeroThreshold may be optionally set to convey the width of the zero
+     /// region. Where the zero region is defined as the closed interval
+     /// \[-ZeroThreshold, ZeroThreshold\].
+     #[serde(rename = "zeroThreshold")]
+     pub zero_threshold: Option<f64>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// Buckets are a set of bucket counts, encoded in a contiguous array
+ /// of counts.
+ pub struct Buckets {
+     /// Offset is the bucket index of the first entry in the bucket_counts array.
+     pub offset: Option<i32>,
+     /// bucket_counts is an array of count values, where bucket_counts\[i\] carries
+     /// the count of the bucket at index (offset+i).
+     #[serde(rename = "bucketCounts", default, deserialize_with = "strings_or_numbers")]
+     pub bucket_counts: Option<Vec<String>>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// SummaryDataPoint is a single data point in a timeseries that describes the
+ /// time-varying values of a Summary metric.
+ pub struct SummaryDataPoint {
+     /// The set of key/value pairs that uniquely identify the timeseries from
+     /// where this point belongs. The list may be empty (may contain 0 elements).
+     /// Attribute keys MUST be unique (it is not allowed to have more than one
+     /// attribute with the same key).
+     pub attributes: Option<Vec<KeyValue>>,
+     #[serde(rename = "startTimeUnixNano", default, deserialize_with = "string_or_number")]
+     pub start_time_unix_nano: Option<String>,
+     #[serde(rename = "timeUnixNano", default, deserialize_with = "string_or_number")]
+     pub time_unix_nano: Option<String>,
+     /// count is the number of values in the population. Must be non-negative.
+     #[serde(default, deserialize_with = "string_or_number")]
+     pub count: Option<String>,
+     /// sum of the values in the population. If count is zero then this field
+     /// must be zero.
+     pub sum: Option<f64>,
+     /// (Optional) list of values at different quantiles of the distribution calculated
+     /// from the current snapshot. The quantiles must be strictly increasing.
+     #[serde(rename = "quantileValues")]
+     pub quantile_values: Option<Vec<ValueAtQuantile>>,
+     /// Flags that apply to this specific data point. See DataPointFlags
+     /// for the available flags and their meaning.
+     pub flags: Option<u32>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// Represents the value at a given quantile of a distribution.
+ pub struct ValueAtQuantile {
+     /// The quantile of a distribution. Must be in the interval
+     /// \[0.0, 1.0\].
+     pub quantile: Option<f64>,
+     /// The value at the given quantile of a distribution.
+     ///
+     /// Quantile values must NOT be negative.
+     pub value: Option<f64>,
+ }
+
+ /// AggregationTemporality defines how a metric aggregator reports aggregated
+ /// values. It describes how those values relate to the time interval over
+ /// which they are aggregated.
+ #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+ #[repr(i32)]
+ pub enum AggregationTemporality {
+     /// UNSPECIFIED is the default AggregationTemporality, it MUST not be used.
+     Unspecified = 0,
+     /// DELTA is an AggregationTemporality for a metric aggregator which reports
+     /// changes since last report time. Successive metrics contain aggregation of
+     /// values from continuous and non-overlapping intervals.
+     Delta = 1,
+     /// CUMULATIVE is an AggregationTemporality for a metric aggregator which
+     /// reports changes since a fixed start time. This means that current values
+     /// of a CUMULATIVE metric depend on all previous measurements since the
+     /// start time.
+     Cumulative = 2,
+ }
+ impl AggregationTemporality {
+     /// String value of the enum field names used in the ProtoBuf definition.
+     ///
+     /// The values are not transformed in any way and thus are considered stable
+     /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+     pub fn as_str_name(temporality: i32) -> &'static str {
+         match temporality {
+             0 => "AGGREGATION_TEMPORALITY_UNSPECIFIED",
+             1 => "AGGREGATION_TEMPORALITY_DELTA",
+             2 => "AGGREGATION_TEMPORALITY_CUMULATIVE",
+             _ => "Invalid aggregation temporality",
+         }
+     }
+ }
@@ -0,0 +1,296 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+// This file was generated by protoc-gen-rust-protobuf. The file was edited after the generation.
+ // All the repeated fields were changed to Option<Vec<T>>.
+
+ use crate::handlers::http::otel::proto::common::v1::InstrumentationScope;
+ use crate::handlers::http::otel::proto::common::v1::KeyValue;
+ use crate::handlers::http::otel::proto::resource::v1::Resource;
+ use crate::handlers::http::otel::proto::string_or_number;
+ use serde::{Deserialize, Serialize};
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// TracesData represents the traces data that can be stored in a persistent storage,
+ /// OR can be embedded by other protocols that transfer OTLP traces data but do
+ /// not implement the OTLP protocol.
+ ///
+ /// The main difference between this message and collector protocol is that
+ /// in this message there will not be any "control" or "metadata" specific to
+ /// OTLP protocol.
+ ///
+ /// When new fields are added into this message, the OTLP request MUST be updated
+ /// as well.
+ pub struct TracesData {
+     /// An array of ResourceSpans.
+     /// For data coming from a single resource this array will typically contain
+     /// one element. Intermediary nodes that receive data from multiple origins
+     /// typically batch the data before forwarding further and in that case this
+     /// array will contain multiple elements.
+     #[serde(rename = "resourceSpans")]
+     pub resource_spans: Option<Vec<ResourceSpans>>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// A collection of ScopeSpans from a Resource.
+ pub struct ResourceSpans {
+     /// The resource for the spans in this message.
+     /// If this field is not set then no resource info is known.
+     pub resource: Option<Resource>,
+     /// A list of ScopeSpans that originate from a resource.
+     #[serde(rename = "scopeSpans")]
+     pub scope_spans: Option<Vec<ScopeSpans>>,
+     /// This schema_url applies to the data in the "resource" field. It does not apply
+     /// to the data in the "scope_spans" field which have their own schema_url field.
+     #[serde(rename = "schemaUrl")]
+     pub schema_url: Option<String>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// A collection of Spans produced by an InstrumentationScope.
+ pub struct ScopeSpans {
+     /// The instrumentation scope information for the spans in this message.
+     /// Semantically when InstrumentationScope isn't set, it is equivalent with
+     /// an empty instrumentation scope name (unknown).
+     pub scope: Option<InstrumentationScope>,
+     /// A list of Spans that originate from an instrumentation scope.
+     #[serde(default)]
+     pub spans: Vec<Span>,
+     /// This schema_url applies to all spans and span events in the "spans" field.
+     #[serde(rename = "schemaUrl")]
+     pub schema_url: Option<String>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// A Span represents a single operation performed by a single component of the system.
+ pub struct Span {
+     /// A unique identifier for a trace. All spans from the same trace share
+     /// the same `trace_id`. The ID is a 16-byte array. An ID with all zeroes OR
+     /// of length other than 16 bytes is considered invalid (empty string in OTLP/JSON
+     /// is zero-length and thus is also invalid).
+     ///
+     /// This field is required.
+     #[serde(rename = "traceId")]
+     pub trace_id: Option<String>,
+     /// A unique identifier for a span within a trace, assigned when the span
+     /// is created. The ID is an 8-byte array. An ID with all zeroes OR of length
+     /// other than 8 bytes is considered invalid (empty string in OTLP/JSON
+     /// is zero-length and thus is also invalid).
+     ///
+     /// This field is required.
+     #[serde(rename = "spanId")]
+     pub span_id: Option<String>,
+     /// trace_state conveys information about request position in multiple distributed tracing graphs.
+     /// It is a trace_state in w3c-trace-context format: <https://www.w3.org/TR/trace-context/#tracestate-header>
+     /// See also <https://github.com/w3c/distributed-tracing> for more details about this field.
+     #[serde(rename = "traceState")]
+     pub trace_state: Option<String>,
+     /// The `span_id` of this span's parent span. If this is a root span, then this
+     /// field must be empty. The ID is an 8-byte array.
+     #[serde(rename = "parentSpanId")]
+     pub parent_span_id: Option<String>,
+     /// A description of the span's operation.
+     ///
+     /// For example, the name can be a qualified method name or a file name
+     /// and a line number where the operation is called. A best practice is to use
+     /// the same display name at the same call point in an application.
+     /// This makes it easier to correlate spans in different traces.
+     ///
+     /// This field is semantically required to be set to non-empty string.
+     /// Empty value is equivalent to an unknown span name.
+     ///
+     /// This field is required.
+     pub name: Option<String>,
+     /// Distinguishes between spans generated in a particular context. For example,
+     /// two spans with the same name may be distinguished using `CLIENT` (caller)
+     /// and `SERVER` (callee) to identify queueing latency associated with the span.
+     pub kind: Option<i32>,
+     /// start_time_unix_nano is the start time of the span. On the client side, this is the time
+     /// kept by the local machine where the span execution starts. On the server side, this
+     /// is the time when the server's application handler starts running.
+     /// Value is UNIX Epoch time in nanoseconds since 00:00:00 UTC on 1 January 1970.
+     ///
+     /// This field is semantically required and it is expected that end_time >= start_time.
+     #[serde(rename = "startTimeUnixNano", default, deserialize_with = "string_or_number")]
+     pub start_time_unix_nano: Option<String>,
+     /// end_time_unix_nano is the end time of the span. On the client side, this is the time
+     /// kept by the local machine where the span execution ends. On the server side, this
+     /// is the time when the server application handler stops running.
+     /// Value is UNIX Epoch time in nanoseconds since 00:00:00 UTC on 1 January 1970.
+     ///
+     /// This field is semantically required and it is expected that end_time >= start_time.
+     #[serde(rename = "endTimeUnixNano", default, deserialize_with = "string_or_number")]
+     pub end_time_unix_nano: Option<String>,
+     /// attributes is a collection of key/value pairs. Note, global attributes
+     /// like server name can be set using the resource API.
+     ///
+     /// The OpenTelemetry API specification further restricts the allowed value types:
+     /// <https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/common/README.md#attribute>
+     /// Attribute keys MUST be unique (it is not allowed to have more than one
+     /// attribute with the same key).
+     pub attributes: Option<Vec<KeyValue>>,
+     /// dropped_attributes_count is the number of attributes that were discarded. Attributes
+     /// can be discarded because their keys are too long or because there are too many
+     /// attributes. If this value is 0, then no attributes were dropped.
+     #[serde(rename = "droppedAttributesCount")]
+     pub dropped_attributes_count: Option<u32>,
+     /// events is a collection of Event items.
+     pub events: Option<Vec<Event>>,
+     /// dropped_events_count is the number of dropped events. If the value is 0, then no
+     /// events were dropped.
+     #[serde(rename = "droppedEventsCount")]
+     pub dropped_events_count: Option<u32>,
+     /// links is a collection of Links, which are references from this span to a span
+     /// in the same or different trace.
+     pub links: Option<Vec<Link>>,
+     /// dropped_links_count is the number of dropped links after the maximum size was
+     /// enforced. If this value is 0, then no links were dropped.
+     #[serde(rename = "droppedLinksCount")]
+     pub dropped_links_count: Option<u32>,
+     /// An optional final status for this span. Semantically when Status isn't set, it means
+     /// span's status code is unset, i.e. assume STATUS_CODE_UNSET (code = 0).
+     pub status: Option<Status>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// Event is a time-stamped annotation of the span, consisting of user-supplied
+ /// text description and key-value pairs.
+ pub struct Event {
+     /// time_unix_nano is the time the event occurred.
+     #[serde(rename = "timeUnixNano", default, deserialize_with = "string_or_number")]
+     pub time_unix_nano: Option<String>,
+     /// name of the event.
+     /// This field is semantically required to be set to non-empty string.
+     pub name: Option<String>,
+     /// attributes is a collection of attribute key/value pairs on the event.
+     /// Attribute keys MUST be unique (it is not allowed to have more than one
+     /// attribute with the same key).
+     pub attributes: Option<Vec<KeyValue>>,
+     /// dropped_attributes_count is the number of dropped attributes. If the value is 0,
+     /// then no attributes were dropped.
+     #[serde(rename = "droppedAttributesCount")]
+     pub dropped_attributes_count: Option<u32>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// A pointer from the current span to another span in the same trace or in a
+ /// different trace. For example, this can be used in batching operations,
+ /// where a single batch handler processes multiple requests from different
+ /// traces or when the handler receives a request from a different project.
+ pub struct Link {
+     /// A unique identifier of a trace that this linked span is part of. The ID is a
+     /// 16-byte array.
+     #[serde(rename = "traceId")]
+     pub trace_id: Option<String>,
+     /// A unique identifier for the linked span. The ID is an 8-byte array.
+     #[serde(rename = "spanId")]
+     pub span_id: Option<String>,
+     /// The trace_state associated with the link.
+     #[serde(rename = "traceState")]
+     pub trace_state: Option<String>,
+     /// attributes is a collection of attribute key/value pairs on the link.
+     /// Attribute keys MUST be unique (it is not allowed to have more than one
+     /// attribute with the same key).
+     pub attributes: Option<Vec<KeyValue>>,
+     /// dropped_attributes_count is the number of dropped attributes. If the value is 0,
+     /// then no attributes were dropped.
+     #[serde(rename = "droppedAttributesCount")]
+     pub dropped_attributes_count: Option<u32>,
+ }
+
+ #[derive(Serialize, Deserialize, Debug)]
+ /// The Status type defines a logical error model that is suitable for different
+ /// programming environments, including REST APIs and RPC APIs.
+ pub struct Status {
+     /// A developer-facing human readable error message.
+     pub message: Option<String>,
+     /// The status code.
+     pub code: Option<i32>,
+ }
+
+ /// SpanKind is the type of span. Can be used to specify additional relationships between spans
+ /// in addition to a parent/child relationship.
+ #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+ #[repr(i32)]
+ pub enum SpanKind {
+     /// Unspecified. Do NOT use as default.
+     /// Implementations MAY assume SpanKind to be INTERNAL when receiving UNSPECIFIED.
+     Unspecified = 0,
+     /// Indicates that the span represents an internal operation within an application,
+     /// as opposed to an operation happening at the boundaries. Default value.
+     Internal = 1,
+     /// Indicates that the span covers server-side handling of an RPC or other
+     /// remote network request.
+     Server = 2,
+     /// Indicates that the span describes a request to some remote service.
+     Client = 3,
+     /// Indicates that the span describes a producer sending a message to a broker.
+     /// Unlike CLIENT and SERVER, there is often no direct critical path latency relationship
+     /// between producer and consumer spans. A PRODUCER span ends when the message was accepted
+     /// by the broker while the logical processing of the message might span a much longer time.
+     Producer = 4,
+     /// Indicates that the span describes consumer receiving a message from a broker.
+     /// Like the PRODUCER kind, there is often no direct critical path latency relationship
+     /// between producer and consumer spans.
+     Consumer = 5,
+ }
+ impl SpanKind {
+     /// String value of the enum field names used in the ProtoBuf definition.
+     ///
+     /// The values are not transformed in any way and thus are considered stable
+     /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+     pub fn as_str_name(kind: i32) -> &'static str {
+         match kind {
+             0 => "SPAN_KIND_UNSPECIFIED",
+             1 => "SPAN_KIND_INTERNAL",
+             2 => "SPAN_KIND_SERVER",
+             3 => "SPAN_KIND_CLIENT",
+             4 => "SPAN_KIND_PRODUCER",
+             5 => "SPAN_KIND_CONSUMER",
+             _ => "Invalid span kind",
+         }
+     }
+ }
+
+ /// For the semantics of status codes see
+ /// <https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/trace/api.md#set-status>
+ #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+ #[repr(i32)]
+ pub enum StatusCode {
+     /// The default status.
+     Unset = 0,
+     /// The Span has been validated by an Application developer or Operator to
+     /// have completed successfully.
+     Ok = 1,
+     /// The Span contains an error.
+     Error = 2,
+ }
+ impl StatusCode {
+     /// String value of the enum field names used in the ProtoBuf definition.
+     ///
+     /// The values are not transformed in any way and thus are considered stable
+     /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+     pub fn as_str_name(code: i32) -> &'static str {
+         match code {
+             0 => "STATUS_CODE_UNSET",
+             1 => "STATUS_CODE_OK",
+             2 => "STATUS_CODE_ERROR",
+             _ => "Invalid status code",
+         }
+     }
+ }
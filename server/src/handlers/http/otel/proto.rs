@@ -30,9 +30,63 @@ pub mod logs {
     }
 }
 
+/// Generated types used for metrics.
+pub mod metrics {
+    pub mod v1 {
+        include!("opentelemetry.proto.metrics.v1.rs");
+    }
+}
+
 /// Generated types used in resources.
 pub mod resource {
     pub mod v1 {
         include!("opentelemetry.proto.resource.v1.rs");
     }
 }
+
+/// Generated types used for traces.
+pub mod trace {
+    pub mod v1 {
+        include!("opentelemetry.proto.trace.v1.rs");
+    }
+}
+
+/// OTLP/JSON writes 64 bit integers as strings, but numbers are accepted too
+pub fn string_or_number<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::{de::Error, Deserialize};
+
+    match Option::<serde_json::Value>::deserialize(deserializer)? {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(serde_json::Value::String(value)) => Ok(Some(value)),
+        Some(serde_json::Value::Number(value)) => Ok(Some(value.to_string())),
+        Some(other) => Err(D::Error::custom(format!(
+            "expected a string or a number, got {other}"
+        ))),
+    }
+}
+
+/// [`string_or_number`] for a list of 64 bit integers
+pub fn strings_or_numbers<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::{de::Error, Deserialize};
+
+    Option::<Vec<serde_json::Value>>::deserialize(deserializer)?
+        .map(|values| {
+            values
+                .into_iter()
+                .map(|value| match value {
+                    serde_json::Value::String(value) => Ok(value),
+                    serde_json::Value::Number(value) => Ok(value.to_string()),
+                    other => Err(D::Error::custom(format!(
+                        "expected a string or a number, got {other}"
+                    ))),
+                })
+                .collect()
+        })
+        .transpose()
+}
@@ -0,0 +1,884 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Protobuf encoding of the OTLP logs, trace and metrics services, as sent to
+//! `/v1/logs`, `/v1/traces` and `/v1/metrics` with
+//! `Content-Type: application/x-protobuf`.
+//!
+//! Requests are converted into the JSON data model in [`super::proto`] so that
+//! both encodings are flattened the same way. Field tags follow
+//! opentelemetry-proto v1.
+
+use base64::Engine;
+
+use super::proto::{
+    common::v1 as json_common, logs::v1 as json_logs, metrics::v1 as json_metrics,
+    resource::v1 as json_resource, trace::v1 as json_trace,
+};
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExportLogsServiceRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub resource_logs: Vec<ResourceLogs>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExportLogsServiceResponse {
+    #[prost(message, optional, tag = "1")]
+    pub partial_success: Option<ExportLogsPartialSuccess>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExportLogsPartialSuccess {
+    #[prost(int64, tag = "1")]
+    pub rejected_log_records: i64,
+    #[prost(string, tag = "2")]
+    pub error_message: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExportTraceServiceRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub resource_spans: Vec<ResourceSpans>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExportTraceServiceResponse {
+    #[prost(message, optional, tag = "1")]
+    pub partial_success: Option<ExportTracePartialSuccess>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExportTracePartialSuccess {
+    #[prost(int64, tag = "1")]
+    pub rejected_spans: i64,
+    #[prost(string, tag = "2")]
+    pub error_message: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExportMetricsServiceRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub resource_metrics: Vec<ResourceMetrics>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExportMetricsServiceResponse {
+    #[prost(message, optional, tag = "1")]
+    pub partial_success: Option<ExportMetricsPartialSuccess>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExportMetricsPartialSuccess {
+    #[prost(int64, tag = "1")]
+    pub rejected_data_points: i64,
+    #[prost(string, tag = "2")]
+    pub error_message: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ResourceLogs {
+    #[prost(message, optional, tag = "1")]
+    pub resource: Option<Resource>,
+    #[prost(message, repeated, tag = "2")]
+    pub scope_logs: Vec<ScopeLogs>,
+    #[prost(string, tag = "3")]
+    pub schema_url: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Resource {
+    #[prost(message, repeated, tag = "1")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(uint32, tag = "2")]
+    pub dropped_attributes_count: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ScopeLogs {
+    #[prost(message, optional, tag = "1")]
+    pub scope: Option<InstrumentationScope>,
+    #[prost(message, repeated, tag = "2")]
+    pub log_records: Vec<LogRecord>,
+    #[prost(string, tag = "3")]
+    pub schema_url: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct InstrumentationScope {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub version: String,
+    #[prost(message, repeated, tag = "3")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(uint32, tag = "4")]
+    pub dropped_attributes_count: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LogRecord {
+    #[prost(fixed64, tag = "1")]
+    pub time_unix_nano: u64,
+    #[prost(fixed64, tag = "11")]
+    pub observed_time_unix_nano: u64,
+    #[prost(int32, tag = "2")]
+    pub severity_number: i32,
+    #[prost(string, tag = "3")]
+    pub severity_text: String,
+    #[prost(message, optional, tag = "5")]
+    pub body: Option<AnyValue>,
+    #[prost(message, repeated, tag = "6")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(uint32, tag = "7")]
+    pub dropped_attributes_count: u32,
+    #[prost(fixed32, tag = "8")]
+    pub flags: u32,
+    #[prost(bytes = "vec", tag = "9")]
+    pub trace_id: Vec<u8>,
+    #[prost(bytes = "vec", tag = "10")]
+    pub span_id: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ResourceSpans {
+    #[prost(message, optional, tag = "1")]
+    pub resource: Option<Resource>,
+    #[prost(message, repeated, tag = "2")]
+    pub scope_spans: Vec<ScopeSpans>,
+    #[prost(string, tag = "3")]
+    pub schema_url: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ScopeSpans {
+    #[prost(message, optional, tag = "1")]
+    pub scope: Option<InstrumentationScope>,
+    #[prost(message, repeated, tag = "2")]
+    pub spans: Vec<Span>,
+    #[prost(string, tag = "3")]
+    pub schema_url: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Span {
+    #[prost(bytes = "vec", tag = "1")]
+    pub trace_id: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub span_id: Vec<u8>,
+    #[prost(string, tag = "3")]
+    pub trace_state: String,
+    #[prost(bytes = "vec", tag = "4")]
+    pub parent_span_id: Vec<u8>,
+    #[prost(string, tag = "5")]
+    pub name: String,
+    #[prost(int32, tag = "6")]
+    pub kind: i32,
+    #[prost(fixed64, tag = "7")]
+    pub start_time_unix_nano: u64,
+    #[prost(fixed64, tag = "8")]
+    pub end_time_unix_nano: u64,
+    #[prost(message, repeated, tag = "9")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(uint32, tag = "10")]
+    pub dropped_attributes_count: u32,
+    #[prost(message, repeated, tag = "11")]
+    pub events: Vec<Event>,
+    #[prost(uint32, tag = "12")]
+    pub dropped_events_count: u32,
+    #[prost(message, repeated, tag = "13")]
+    pub links: Vec<Link>,
+    #[prost(uint32, tag = "14")]
+    pub dropped_links_count: u32,
+    #[prost(message, optional, tag = "15")]
+    pub status: Option<Status>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Event {
+    #[prost(fixed64, tag = "1")]
+    pub time_unix_nano: u64,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(message, repeated, tag = "3")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(uint32, tag = "4")]
+    pub dropped_attributes_count: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Link {
+    #[prost(bytes = "vec", tag = "1")]
+    pub trace_id: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub span_id: Vec<u8>,
+    #[prost(string, tag = "3")]
+    pub trace_state: String,
+    #[prost(message, repeated, tag = "4")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(uint32, tag = "5")]
+    pub dropped_attributes_count: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Status {
+    #[prost(string, tag = "2")]
+    pub message: String,
+    #[prost(int32, tag = "3")]
+    pub code: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ResourceMetrics {
+    #[prost(message, optional, tag = "1")]
+    pub resource: Option<Resource>,
+    #[prost(message, repeated, tag = "2")]
+    pub scope_metrics: Vec<ScopeMetrics>,
+    #[prost(string, tag = "3")]
+    pub schema_url: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ScopeMetrics {
+    #[prost(message, optional, tag = "1")]
+    pub scope: Option<InstrumentationScope>,
+    #[prost(message, repeated, tag = "2")]
+    pub metrics: Vec<Metric>,
+    #[prost(string, tag = "3")]
+    pub schema_url: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Metric {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub description: String,
+    #[prost(string, tag = "3")]
+    pub unit: String,
+    #[prost(oneof = "metric::Data", tags = "5, 7, 9, 10, 11")]
+    pub data: Option<metric::Data>,
+}
+
+pub mod metric {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Data {
+        #[prost(message, tag = "5")]
+        Gauge(super::Gauge),
+        #[prost(message, tag = "7")]
+        Sum(super::Sum),
+        #[prost(message, tag = "9")]
+        Histogram(super::Histogram),
+        #[prost(message, tag = "10")]
+        ExponentialHistogram(super::ExponentialHistogram),
+        #[prost(message, tag = "11")]
+        Summary(super::Summary),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Gauge {
+    #[prost(message, repeated, tag = "1")]
+    pub data_points: Vec<NumberDataPoint>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Sum {
+    #[prost(message, repeated, tag = "1")]
+    pub data_points: Vec<NumberDataPoint>,
+    #[prost(int32, tag = "2")]
+    pub aggregation_temporality: i32,
+    #[prost(bool, tag = "3")]
+    pub is_monotonic: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Histogram {
+    #[prost(message, repeated, tag = "1")]
+    pub data_points: Vec<HistogramDataPoint>,
+    #[prost(int32, tag = "2")]
+    pub aggregation_temporality: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExponentialHistogram {
+    #[prost(message, repeated, tag = "1")]
+    pub data_points: Vec<ExponentialHistogramDataPoint>,
+    #[prost(int32, tag = "2")]
+    pub aggregation_temporality: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Summary {
+    #[prost(message, repeated, tag = "1")]
+    pub data_points: Vec<SummaryDataPoint>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct NumberDataPoint {
+    #[prost(message, repeated, tag = "7")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(fixed64, tag = "2")]
+    pub start_time_unix_nano: u64,
+    #[prost(fixed64, tag = "3")]
+    pub time_unix_nano: u64,
+    #[prost(oneof = "number_data_point::Value", tags = "4, 6")]
+    pub value: Option<number_data_point::Value>,
+    #[prost(uint32, tag = "8")]
+    pub flags: u32,
+}
+
+pub mod number_data_point {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Value {
+        #[prost(double, tag = "4")]
+        AsDouble(f64),
+        #[prost(sfixed64, tag = "6")]
+        AsInt(i64),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct HistogramDataPoint {
+    #[prost(message, repeated, tag = "9")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(fixed64, tag = "2")]
+    pub start_time_unix_nano: u64,
+    #[prost(fixed64, tag = "3")]
+    pub time_unix_nano: u64,
+    #[prost(fixed64, tag = "4")]
+    pub count: u64,
+    #[prost(double, optional, tag = "5")]
+    pub sum: Option<f64>,
+    #[prost(fixed64, repeated, tag = "6")]
+    pub bucket_counts: Vec<u64>,
+    #[prost(double, repeated, tag = "7")]
+    pub explicit_bounds: Vec<f64>,
+    #[prost(uint32, tag = "10")]
+    pub flags: u32,
+    #[prost(double, optional, tag = "11")]
+    pub min: Option<f64>,
+    #[prost(double, optional, tag = "12")]
+    pub max: Option<f64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExponentialHistogramDataPoint {
+    #[prost(message, repeated, tag = "1")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(fixed64, tag = "2")]
+    pub start_time_unix_nano: u64,
+    #[prost(fixed64, tag = "3")]
+    pub time_unix_nano: u64,
+    #[prost(fixed64, tag = "4")]
+    pub count: u64,
+    #[prost(double, optional, tag = "5")]
+    pub sum: Option<f64>,
+    #[prost(sint32, tag = "6")]
+    pub scale: i32,
+    #[prost(fixed64, tag = "7")]
+    pub zero_count: u64,
+    #[prost(message, optional, tag = "8")]
+    pub positive: Option<Buckets>,
+    #[prost(message, optional, tag = "9")]
+    pub negative: Option<Buckets>,
+    #[prost(uint32, tag = "10")]
+    pub flags: u32,
+    #[prost(double, optional, tag = "12")]
+    pub min: Option<f64>,
+    #[prost(double, optional, tag = "13")]
+    pub max: Option<f64>,
+    #[prost(double, tag = "14")]
+    pub zero_threshold: f64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Buckets {
+    #[prost(sint32, tag = "1")]
+    pub offset: i32,
+    #[prost(uint64, repeated, tag = "2")]
+    pub bucket_counts: Vec<u64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SummaryDataPoint {
+    #[prost(message, repeated, tag = "7")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(fixed64, tag = "2")]
+    pub start_time_unix_nano: u64,
+    #[prost(fixed64, tag = "3")]
+    pub time_unix_nano: u64,
+    #[prost(fixed64, tag = "4")]
+    pub count: u64,
+    #[prost(double, tag = "5")]
+    pub sum: f64,
+    #[prost(message, repeated, tag = "6")]
+    pub quantile_values: Vec<ValueAtQuantile>,
+    #[prost(uint32, tag = "8")]
+    pub flags: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ValueAtQuantile {
+    #[prost(double, tag = "1")]
+    pub quantile: f64,
+    #[prost(double, tag = "2")]
+    pub value: f64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct KeyValue {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(message, optional, tag = "2")]
+    pub value: Option<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AnyValue {
+    #[prost(oneof = "any_value::Value", tags = "1, 2, 3, 4, 5, 6, 7")]
+    pub value: Option<any_value::Value>,
+}
+
+pub mod any_value {
+    // variants are named after the proto fields
+    #[allow(clippy::enum_variant_names)]
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Value {
+        #[prost(string, tag = "1")]
+        StringValue(String),
+        #[prost(bool, tag = "2")]
+        BoolValue(bool),
+        #[prost(int64, tag = "3")]
+        IntValue(i64),
+        #[prost(double, tag = "4")]
+        DoubleValue(f64),
+        #[prost(message, tag = "5")]
+        ArrayValue(super::ArrayValue),
+        #[prost(message, tag = "6")]
+        KvlistValue(super::KeyValueList),
+        #[prost(bytes = "vec", tag = "7")]
+        BytesValue(Vec<u8>),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ArrayValue {
+    #[prost(message, repeated, tag = "1")]
+    pub values: Vec<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct KeyValueList {
+    #[prost(message, repeated, tag = "1")]
+    pub values: Vec<KeyValue>,
+}
+
+// unset proto3 fields decode to their defaults, the JSON model leaves them out
+fn non_empty(value: String) -> Option<String> {
+    (!value.is_empty()).then_some(value)
+}
+
+fn non_zero<T: Default + PartialEq>(value: T) -> Option<T> {
+    (value != T::default()).then_some(value)
+}
+
+fn attributes(attributes: Vec<KeyValue>) -> Option<Vec<json_common::KeyValue>> {
+    (!attributes.is_empty()).then(|| attributes.into_iter().map(Into::into).collect())
+}
+
+// 64 bit integers are strings in OTLP/JSON
+fn time(nanos: u64) -> Option<String> {
+    non_zero(nanos).map(|nanos| nanos.to_string())
+}
+
+fn counts(counts: Vec<u64>) -> Option<Vec<String>> {
+    (!counts.is_empty()).then(|| counts.iter().map(ToString::to_string).collect())
+}
+
+// ids are hex encoded in OTLP/JSON
+fn id(bytes: Vec<u8>) -> Option<String> {
+    non_empty(hex::encode(bytes))
+}
+
+fn resource(resource: Resource) -> json_resource::Resource {
+    json_resource::Resource {
+        attributes: attributes(resource.attributes),
+        dropped_attributes_count: non_zero(resource.dropped_attributes_count),
+    }
+}
+
+fn scope(scope: InstrumentationScope) -> json_common::InstrumentationScope {
+    json_common::InstrumentationScope {
+        name: non_empty(scope.name),
+        version: non_empty(scope.version),
+        attributes: attributes(scope.attributes),
+        dropped_attributes_count: non_zero(scope.dropped_attributes_count),
+    }
+}
+
+impl From<ExportLogsServiceRequest> for json_logs::LogsData {
+    fn from(request: ExportLogsServiceRequest) -> Self {
+        Self {
+            resource_logs: Some(request.resource_logs.into_iter().map(Into::into).collect()),
+        }
+    }
+}
+
+impl From<ResourceLogs> for json_logs::ResourceLogs {
+    fn from(resource_logs: ResourceLogs) -> Self {
+        Self {
+            resource: resource_logs.resource.map(resource),
+            scope_logs: Some(
+                resource_logs
+                    .scope_logs
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+            ),
+            schema_url: non_empty(resource_logs.schema_url),
+        }
+    }
+}
+
+impl From<ScopeLogs> for json_logs::ScopeLogs {
+    fn from(scope_logs: ScopeLogs) -> Self {
+        Self {
+            scope: scope_logs.scope.map(scope),
+            log_records: scope_logs.log_records.into_iter().map(Into::into).collect(),
+            schema_url: non_empty(scope_logs.schema_url),
+        }
+    }
+}
+
+impl From<LogRecord> for json_logs::LogRecord {
+    fn from(record: LogRecord) -> Self {
+        Self {
+            time_unix_nano: time(record.time_unix_nano),
+            observed_time_unix_nano: time(record.observed_time_unix_nano),
+            severity_number: non_zero(record.severity_number),
+            severity_text: non_empty(record.severity_text),
+            body: record.body.map(Into::into),
+            attributes: attributes(record.attributes),
+            dropped_attributes_count: non_zero(record.dropped_attributes_count),
+            flags: non_zero(record.flags),
+            trace_id: id(record.trace_id),
+            span_id: id(record.span_id),
+        }
+    }
+}
+
+impl From<ExportTraceServiceRequest> for json_trace::TracesData {
+    fn from(request: ExportTraceServiceRequest) -> Self {
+        Self {
+            resource_spans: Some(request.resource_spans.into_iter().map(Into::into).collect()),
+        }
+    }
+}
+
+impl From<ResourceSpans> for json_trace::ResourceSpans {
+    fn from(resource_spans: ResourceSpans) -> Self {
+        Self {
+            resource: resource_spans.resource.map(resource),
+            scope_spans: Some(
+                resource_spans
+                    .scope_spans
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+            ),
+            schema_url: non_empty(resource_spans.schema_url),
+        }
+    }
+}
+
+impl From<ScopeSpans> for json_trace::ScopeSpans {
+    fn from(scope_spans: ScopeSpans) -> Self {
+        Self {
+            scope: scope_spans.scope.map(scope),
+            spans: scope_spans.spans.into_iter().map(Into::into).collect(),
+            schema_url: non_empty(scope_spans.schema_url),
+        }
+    }
+}
+
+impl From<Span> for json_trace::Span {
+    fn from(span: Span) -> Self {
+        Self {
+            trace_id: id(span.trace_id),
+            span_id: id(span.span_id),
+            trace_state: non_empty(span.trace_state),
+            parent_span_id: id(span.parent_span_id),
+            name: non_empty(span.name),
+            kind: non_zero(span.kind),
+            start_time_unix_nano: time(span.start_time_unix_nano),
+            end_time_unix_nano: time(span.end_time_unix_nano),
+            attributes: attributes(span.attributes),
+            dropped_attributes_count: non_zero(span.dropped_attributes_count),
+            events: (!span.events.is_empty())
+                .then(|| span.events.into_iter().map(Into::into).collect()),
+            dropped_events_count: non_zero(span.dropped_events_count),
+            links: (!span.links.is_empty())
+                .then(|| span.links.into_iter().map(Into::into).collect()),
+            dropped_links_count: non_zero(span.dropped_links_count),
+            status: span.status.map(|status| json_trace::Status {
+                message: non_empty(status.message),
+                code: non_zero(status.code),
+            }),
+        }
+    }
+}
+
+impl From<Event> for json_trace::Event {
+    fn from(event: Event) -> Self {
+        Self {
+            time_unix_nano: time(event.time_unix_nano),
+            name: non_empty(event.name),
+            attributes: attributes(event.attributes),
+            dropped_attributes_count: non_zero(event.dropped_attributes_count),
+        }
+    }
+}
+
+impl From<Link> for json_trace::Link {
+    fn from(link: Link) -> Self {
+        Self {
+            trace_id: id(link.trace_id),
+            span_id: id(link.span_id),
+            trace_state: non_empty(link.trace_state),
+            attributes: attributes(link.attributes),
+            dropped_attributes_count: non_zero(link.dropped_attributes_count),
+        }
+    }
+}
+
+impl From<KeyValue> for json_common::KeyValue {
+    fn from(key_value: KeyValue) -> Self {
+        Self {
+            key: key_value.key,
+            value: key_value.value.map(Into::into),
+        }
+    }
+}
+
+impl From<AnyValue> for json_common::Value {
+    fn from(value: AnyValue) -> Self {
+        let mut json = json_common::Value {
+            str_val: None,
+            bool_val: None,
+            int_val: None,
+            double_val: None,
+            array_val: None,
+            kv_list_val: None,
+            bytes_val: None,
+        };
+        match value.value {
+            Some(any_value::Value::StringValue(value)) => json.str_val = Some(value),
+            Some(any_value::Value::BoolValue(value)) => json.bool_val = Some(value),
+            Some(any_value::Value::IntValue(value)) => json.int_val = Some(value.to_string()),
+            Some(any_value::Value::DoubleValue(value)) => json.double_val = Some(value.to_string()),
+            Some(any_value::Value::ArrayValue(array)) => {
+                json.array_val = Some(json_common::ArrayValue {
+                    values: array.values.into_iter().map(Into::into).collect(),
+                })
+            }
+            Some(any_value::Value::KvlistValue(list)) => {
+                json.kv_list_val = Some(json_common::KeyValueList {
+                    values: list.values.into_iter().map(Into::into).collect(),
+                })
+            }
+            // bytes are base64 encoded in OTLP/JSON
+            Some(any_value::Value::BytesValue(bytes)) => {
+                json.bytes_val = Some(base64::prelude::BASE64_STANDARD.encode(bytes))
+            }
+            None => {}
+        }
+        json
+    }
+}
+
+impl From<ExportMetricsServiceRequest> for json_metrics::MetricsData {
+    fn from(request: ExportMetricsServiceRequest) -> Self {
+        Self {
+            resource_metrics: Some(
+                request
+                    .resource_metrics
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<ResourceMetrics> for json_metrics::ResourceMetrics {
+    fn from(resource_metrics: ResourceMetrics) -> Self {
+        Self {
+            resource: resource_metrics.resource.map(resource),
+            scope_metrics: Some(
+                resource_metrics
+                    .scope_metrics
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+            ),
+            schema_url: non_empty(resource_metrics.schema_url),
+        }
+    }
+}
+
+impl From<ScopeMetrics> for json_metrics::ScopeMetrics {
+    fn from(scope_metrics: ScopeMetrics) -> Self {
+        Self {
+            scope: scope_metrics.scope.map(scope),
+            metrics: scope_metrics.metrics.into_iter().map(Into::into).collect(),
+            schema_url: non_empty(scope_metrics.schema_url),
+        }
+    }
+}
+
+impl From<Metric> for json_metrics::Metric {
+    fn from(metric: Metric) -> Self {
+        let mut json = json_metrics::Metric {
+            name: non_empty(metric.name),
+            description: non_empty(metric.description),
+            unit: non_empty(metric.unit),
+            gauge: None,
+            sum: None,
+            histogram: None,
+            exponential_histogram: None,
+            summary: None,
+        };
+        let data_points = |points: Vec<_>| points.into_iter().map(Into::into).collect();
+        match metric.data {
+            Some(metric::Data::Gauge(gauge)) => {
+                json.gauge = Some(json_metrics::Gauge {
+                    data_points: data_points(gauge.data_points),
+                })
+            }
+            Some(metric::Data::Sum(sum)) => {
+                json.sum = Some(json_metrics::Sum {
+                    data_points: data_points(sum.data_points),
+                    aggregation_temporality: non_zero(sum.aggregation_temporality),
+                    is_monotonic: Some(sum.is_monotonic),
+                })
+            }
+            Some(metric::Data::Histogram(histogram)) => {
+                json.histogram = Some(json_metrics::Histogram {
+                    data_points: histogram.data_points.into_iter().map(Into::into).collect(),
+                    aggregation_temporality: non_zero(histogram.aggregation_temporality),
+                })
+            }
+            Some(metric::Data::ExponentialHistogram(histogram)) => {
+                json.exponential_histogram = Some(json_metrics::ExponentialHistogram {
+                    data_points: histogram.data_points.into_iter().map(Into::into).collect(),
+                    aggregation_temporality: non_zero(histogram.aggregation_temporality),
+                })
+            }
+            Some(metric::Data::Summary(summary)) => {
+                json.summary = Some(json_metrics::Summary {
+                    data_points: summary.data_points.into_iter().map(Into::into).collect(),
+                })
+            }
+            None => {}
+        }
+        json
+    }
+}
+
+impl From<NumberDataPoint> for json_metrics::NumberDataPoint {
+    fn from(point: NumberDataPoint) -> Self {
+        let (as_double, as_int) = match point.value {
+            Some(number_data_point::Value::AsDouble(value)) => (Some(value), None),
+            Some(number_data_point::Value::AsInt(value)) => (None, Some(value.to_string())),
+            None => (None, None),
+        };
+        Self {
+            attributes: attributes(point.attributes),
+            start_time_unix_nano: time(point.start_time_unix_nano),
+            time_unix_nano: time(point.time_unix_nano),
+            as_double,
+            as_int,
+            flags: non_zero(point.flags),
+        }
+    }
+}
+
+impl From<HistogramDataPoint> for json_metrics::HistogramDataPoint {
+    fn from(point: HistogramDataPoint) -> Self {
+        Self {
+            attributes: attributes(point.attributes),
+            start_time_unix_nano: time(point.start_time_unix_nano),
+            time_unix_nano: time(point.time_unix_nano),
+            count: Some(point.count.to_string()),
+            sum: point.sum,
+            bucket_counts: counts(point.bucket_counts),
+            explicit_bounds: (!point.explicit_bounds.is_empty()).then_some(point.explicit_bounds),
+            flags: non_zero(point.flags),
+            min: point.min,
+            max: point.max,
+        }
+    }
+}
+
+impl From<ExponentialHistogramDataPoint> for json_metrics::ExponentialHistogramDataPoint {
+    fn from(point: ExponentialHistogramDataPoint) -> Self {
+        let buckets = |buckets: Buckets| json_metrics::Buckets {
+            offset: non_zero(buckets.offset),
+            bucket_counts: counts(buckets.bucket_counts),
+        };
+        Self {
+            attributes: attributes(point.attributes),
+            start_time_unix_nano: time(point.start_time_unix_nano),
+            time_unix_nano: time(point.time_unix_nano),
+            count: Some(point.count.to_string()),
+            sum: point.sum,
+            scale: non_zero(point.scale),
+            zero_count: time(point.zero_count),
+            positive: point.positive.map(buckets),
+            negative: point.negative.map(buckets),
+            flags: non_zero(point.flags),
+            min: point.min,
+            max: point.max,
+            zero_threshold: non_zero(point.zero_threshold),
+        }
+    }
+}
+
+impl From<SummaryDataPoint> for json_metrics::SummaryDataPoint {
+    fn from(point: SummaryDataPoint) -> Self {
+        Self {
+            attributes: attributes(point.attributes),
+            start_time_unix_nano: time(point.start_time_unix_nano),
+            time_unix_nano: time(point.time_unix_nano),
+            count: Some(point.count.to_string()),
+            sum: Some(point.sum),
+            quantile_values: (!point.quantile_values.is_empty()).then(|| {
+                point
+                    .quantile_values
+                    .into_iter()
+                    .map(|value| json_metrics::ValueAtQuantile {
+                        quantile: Some(value.quantile),
+                        value: Some(value.value),
+                    })
+                    .collect()
+            }),
+            flags: non_zero(point.flags),
+        }
+    }
+}
@@ -0,0 +1,328 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value};
+
+use super::proto::common::v1::KeyValue;
+use super::proto::trace::v1::{Event, Link, Span, SpanKind, StatusCode, TracesData};
+use super::{
+    collect_json_from_values, flatten_resource, flatten_scope, insert_attributes, nanos,
+    resource_stream, timestamp, OtelRecord,
+};
+
+fn insert_id(json: &mut BTreeMap<String, Value>, key: &str, id: &Option<String>) {
+    // ids are hex, in either case
+    if let Some(id) = id.as_ref().filter(|id| !id.is_empty()) {
+        json.insert(key.to_string(), Value::String(id.to_ascii_lowercase()));
+    }
+}
+
+/// Attributes as a single JSON object, flattened the same way as columns
+fn attributes_object(attributes: &Option<Vec<KeyValue>>) -> Map<String, Value> {
+    attributes
+        .iter()
+        .flatten()
+        .flat_map(|attribute| collect_json_from_values(&attribute.value, &attribute.key))
+        .collect()
+}
+
+fn event_json(event: &Event) -> Value {
+    let mut json = Map::new();
+    if let Some(time) = nanos(&event.time_unix_nano) {
+        json.insert("time".to_string(), timestamp(time));
+    }
+    if let Some(name) = &event.name {
+        json.insert("name".to_string(), Value::String(name.to_owned()));
+    }
+    let attributes = attributes_object(&event.attributes);
+    if !attributes.is_empty() {
+        json.insert("attributes".to_string(), Value::Object(attributes));
+    }
+    Value::Object(json)
+}
+
+fn link_json(link: &Link) -> Value {
+    let mut json = BTreeMap::new();
+    insert_id(&mut json, "trace_id", &link.trace_id);
+    insert_id(&mut json, "span_id", &link.span_id);
+    if let Some(trace_state) = &link.trace_state {
+        json.insert(
+            "trace_state".to_string(),
+            Value::String(trace_state.to_owned()),
+        );
+    }
+    let attributes = attributes_object(&link.attributes);
+    if !attributes.is_empty() {
+        json.insert("attributes".to_string(), Value::Object(attributes));
+    }
+    Value::Object(json.into_iter().collect())
+}
+
+fn insert_count(json: &mut BTreeMap<String, Value>, key: &str, count: Option<u32>) {
+    if let Some(count) = count {
+        json.insert(key.to_string(), Value::from(count));
+    }
+}
+
+fn flatten_span(span: &Span, json: &mut BTreeMap<String, Value>) {
+    insert_id(json, "trace_id", &span.trace_id);
+    insert_id(json, "span_id", &span.span_id);
+    insert_id(json, "parent_span_id", &span.parent_span_id);
+    if let Some(trace_state) = &span.trace_state {
+        json.insert(
+            "trace_state".to_string(),
+            Value::String(trace_state.to_owned()),
+        );
+    }
+    if let Some(name) = &span.name {
+        json.insert("name".to_string(), Value::String(name.to_owned()));
+    }
+    if let Some(kind) = span.kind {
+        json.insert(
+            "kind".to_string(),
+            Value::String(SpanKind::as_str_name(kind).to_string()),
+        );
+    }
+
+    let start = nanos(&span.start_time_unix_nano);
+    let end = nanos(&span.end_time_unix_nano);
+    if let Some(start) = start {
+        json.insert("start_time".to_string(), timestamp(start));
+    }
+    if let Some(end) = end {
+        json.insert("end_time".to_string(), timestamp(end));
+    }
+    // computed here rather than trusted from the client
+    if let (Some(start), Some(end)) = (start, end) {
+        if end >= start {
+            json.insert(
+                "duration_ms".to_string(),
+                Value::from((end - start) as f64 / 1_000_000.0),
+            );
+        }
+    }
+
+    insert_attributes(json, "span", &span.attributes);
+    insert_count(
+        json,
+        "span_dropped_attributes_count",
+        span.dropped_attributes_count,
+    );
+    // kept whole, a column per event attribute would not line up across spans
+    if let Some(events) = span.events.as_ref().filter(|events| !events.is_empty()) {
+        let events: Vec<Value> = events.iter().map(event_json).collect();
+        json.insert(
+            "events".to_string(),
+            Value::String(Value::Array(events).to_string()),
+        );
+    }
+    insert_count(json, "dropped_events_count", span.dropped_events_count);
+    if let Some(links) = span.links.as_ref().filter(|links| !links.is_empty()) {
+        let links: Vec<Value> = links.iter().map(link_json).collect();
+        json.insert(
+            "links".to_string(),
+            Value::String(Value::Array(links).to_string()),
+        );
+    }
+    insert_count(json, "dropped_links_count", span.dropped_links_count);
+
+    if let Some(status) = &span.status {
+        json.insert(
+            "status_code".to_string(),
+            Value::String(StatusCode::as_str_name(status.code.unwrap_or_default()).to_string()),
+        );
+        if let Some(message) = &status.message {
+            json.insert(
+                "status_message".to_string(),
+                Value::String(message.to_owned()),
+            );
+        }
+    }
+}
+
+/// Flattens every span into a single JSON object, along with the attributes
+/// of its resource and scope
+///
+/// Start and end times are RFC 3339 timestamps and `duration_ms` is computed
+/// from them. Events and links are kept as JSON arrays in the `events` and
+/// `links` columns.
+pub fn flatten_traces(traces: &TracesData) -> Vec<OtelRecord> {
+    let mut records = Vec::new();
+    for resource_spans in traces.resource_spans.iter().flatten() {
+        let stream = resource_stream(resource_spans.resource.as_ref());
+        let resource_json = flatten_resource(
+            resource_spans.resource.as_ref(),
+            resource_spans.schema_url.as_ref(),
+        );
+        for scope_spans in resource_spans.scope_spans.iter().flatten() {
+            let mut scope_json = resource_json.clone();
+            scope_json.extend(flatten_scope(scope_spans.scope.as_ref()));
+            if let Some(schema_url) = &scope_spans.schema_url {
+                scope_json.insert(
+                    "scope_span_schema_url".to_string(),
+                    Value::String(schema_url.to_owned()),
+                );
+            }
+            for span in &scope_spans.spans {
+                let mut record = scope_json.clone();
+                flatten_span(span, &mut record);
+                records.push(OtelRecord {
+                    stream: stream.clone(),
+                    record,
+                });
+            }
+        }
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use arrow_array::{cast::AsArray, types::Float64Type};
+    use datafusion::{datasource::MemTable, prelude::SessionContext};
+    use serde_json::{json, Value};
+
+    use super::flatten_traces;
+    use crate::event::format::{json, EventFormat};
+    use crate::handlers::http::otel::{Encoding, TRACES_STREAM};
+
+    const TRACES_JSON: &[u8] = include_bytes!("testdata/traces.json");
+    const TRACES_PROTOBUF: &[u8] = include_bytes!("testdata/traces.pb");
+
+    #[test]
+    fn json_and_protobuf_flatten_alike() {
+        let from_json = flatten_traces(&Encoding::Json.decode_traces(TRACES_JSON).unwrap());
+        let from_protobuf =
+            flatten_traces(&Encoding::Protobuf.decode_traces(TRACES_PROTOBUF).unwrap());
+        assert_eq!(from_json.len(), 5);
+        for (json, protobuf) in from_json.iter().zip(&from_protobuf) {
+            assert_eq!(json.stream, protobuf.stream);
+            assert_eq!(json.record, protobuf.record);
+        }
+    }
+
+    #[test]
+    fn spans_are_flattened() {
+        let spans = flatten_traces(&Encoding::Json.decode_traces(TRACES_JSON).unwrap());
+        assert!(spans.iter().all(|otel| otel.stream.is_none()));
+
+        let root = &spans[0].record;
+        assert_eq!(root["resource_service.name"], "frontend");
+        assert_eq!(root["instrumentation_scope_name"], "io.opentelemetry.http");
+        assert_eq!(root["trace_id"], "5b8efff798038103d269b633813fc60c");
+        assert_eq!(root["span_id"], "eee19b7ec3c1b174");
+        assert!(!root.contains_key("parent_span_id"));
+        assert_eq!(root["name"], "GET /checkout");
+        assert_eq!(root["kind"], "SPAN_KIND_SERVER");
+        assert_eq!(root["start_time"], "2018-12-13T14:51:00Z");
+        assert_eq!(root["end_time"], "2018-12-13T14:51:00.900Z");
+        assert_eq!(root["duration_ms"], 900.0);
+        assert_eq!(root["span_http.method"], "GET");
+        assert_eq!(root["span_http.status_code"], "200");
+        assert_eq!(root["status_code"], "STATUS_CODE_OK");
+
+        let charge = &spans[2].record;
+        assert_eq!(charge["parent_span_id"], "eee19b7ec3c1b174");
+        assert_eq!(charge["status_code"], "STATUS_CODE_ERROR");
+        assert_eq!(charge["status_message"], "card declined");
+        let events: Value = serde_json::from_str(charge["events"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            events,
+            json!([{
+                "time": "2018-12-13T14:51:00.400Z",
+                "name": "retry",
+                "attributes": { "attempt": "2" },
+            }])
+        );
+        let links: Value = serde_json::from_str(charge["links"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            links,
+            json!([{
+                "trace_id": "0af7651916cd43dd8448eb211c80319c",
+                "span_id": "b7ad6b7169203331",
+            }])
+        );
+        assert!(!spans[1].record.contains_key("events"));
+        assert!(!spans[1].record.contains_key("status_code"));
+    }
+
+    #[actix_web::test]
+    async fn slowest_spans_per_service() {
+        let spans: Vec<Value> =
+            flatten_traces(&Encoding::Protobuf.decode_traces(TRACES_PROTOBUF).unwrap())
+                .into_iter()
+                .map(|otel| Value::Object(otel.record.into_iter().collect()))
+                .collect();
+        let event = json::Event {
+            data: Value::Array(spans),
+            tags: String::default(),
+            metadata: String::default(),
+        };
+        let (rb, _) = event.into_recordbatch(HashMap::new(), None, None).unwrap();
+
+        let ctx = SessionContext::new();
+        ctx.register_table(
+            TRACES_STREAM,
+            Arc::new(MemTable::try_new(rb.schema(), vec![vec![rb]]).unwrap()),
+        )
+        .unwrap();
+
+        let batches = ctx
+            .sql(
+                r#"SELECT service, name, duration_ms FROM (
+                       SELECT "resource_service.name" AS service, name, duration_ms,
+                              ROW_NUMBER() OVER (
+                                  PARTITION BY "resource_service.name"
+                                  ORDER BY duration_ms DESC
+                              ) AS slowest
+                       FROM oteltraces
+                   )
+                   WHERE slowest <= 2
+                   ORDER BY service, duration_ms DESC"#,
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let rows: Vec<(&str, &str, f64)> = batches
+            .iter()
+            .flat_map(|batch| {
+                let services = batch.column(0).as_string::<i32>();
+                let names = batch.column(1).as_string::<i32>();
+                let durations = batch.column(2).as_primitive::<Float64Type>();
+                (0..batch.num_rows())
+                    .map(|row| (services.value(row), names.value(row), durations.value(row)))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("checkout", "charge card", 600.0),
+                ("checkout", "SELECT carts", 250.0),
+                ("frontend", "GET /checkout", 900.0),
+                ("frontend", "render page", 90.0),
+            ]
+        );
+    }
+}
@@ -16,15 +16,16 @@
  *
  */
 
-use actix_web::http::header::ContentType;
+use actix_web::http::header::{ContentDisposition, ContentType};
 use actix_web::web::{self, Json};
-use actix_web::{FromRequest, HttpRequest, Responder};
+use actix_web::{FromRequest, HttpRequest, HttpResponse, Responder};
 use anyhow::anyhow;
 use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use datafusion::common::tree_node::TreeNode;
 use datafusion::error::DataFusionError;
 use datafusion::execution::context::SessionState;
-use futures_util::Future;
+use futures_util::{Future, StreamExt};
 use http::StatusCode;
 use std::collections::HashMap;
 use std::pin::Pin;
@@ -36,20 +37,28 @@ use crate::handlers::http::fetch_schema;
 use arrow_array::RecordBatch;
 
 use crate::event::commit_schema;
-use crate::handlers::{CACHE_RESULTS_HEADER_KEY, CACHE_VIEW_HEADER_KEY, USER_ID_HEADER_KEY};
+use crate::handlers::{
+    CACHE_RESULTS_HEADER_KEY, CACHE_VIEW_HEADER_KEY, QUERY_ID_HEADER_KEY, RESULT_CACHE_HEADER_KEY,
+    TIME_ZONE_HEADER_KEY, USER_ID_HEADER_KEY,
+};
 use crate::localcache::CacheError;
-use crate::metrics::QUERY_EXECUTE_TIME;
+use crate::metrics::{QUERY_EXECUTE_TIME, QUERY_RESULT_CACHE};
 use crate::option::{Mode, CONFIG};
+use crate::query::concurrency::{QueryClass, QueryLimitError, QUERY_LIMITER};
 use crate::query::error::ExecuteError;
+use crate::query::registry::{Cancelled, QUERY_REGISTRY};
+use crate::query::result_cache::{ResultKey, RESULT_CACHE};
 use crate::query::Query as LogicalQuery;
-use crate::query::{TableScanVisitor, QUERY_SESSION};
+use crate::query::{functions, TableScanVisitor, QUERY_SESSION};
 use crate::querycache::{CacheMetadata, QueryCacheManager};
+use crate::rbac::map::SessionKey;
 use crate::rbac::role::{Action, Permission};
-use crate::rbac::Users;
-use crate::response::QueryResponse;
+use crate::rbac::{self, Users};
+use crate::response::{stream_records, QueryResponse, StreamFormat};
 use crate::storage::object_storage::commit_schema_to_storage;
 use crate::storage::ObjectStorageError;
 use crate::utils::actix::extract_session_key_from_req;
+use crate::utils::arrow::timestamps_in_time_zone;
 
 /// Query Request through http endpoint.
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
@@ -60,14 +69,45 @@ pub struct Query {
     pub end_time: String,
     #[serde(default)]
     pub send_null: bool,
+    /// return the query plan instead of the results
+    #[serde(default)]
+    pub explain: bool,
     #[serde(skip)]
     pub fields: bool,
     #[serde(skip)]
     pub filter_tags: Option<Vec<String>>,
+    /// send results as NDJSON chunks while the query runs
+    #[serde(skip)]
+    pub stream: bool,
+    /// export the results as `csv` or `parquet`
+    #[serde(skip)]
+    pub format: Option<String>,
+    /// separator of CSV exports
+    #[serde(skip)]
+    pub delimiter: Option<String>,
+    /// IANA name of the time zone dates are handled and returned in, UTC when unset
+    #[serde(default)]
+    pub time_zone: Option<String>,
 }
 
-pub async fn query(req: HttpRequest, query_request: Query) -> Result<impl Responder, QueryError> {
-    let session_state = QUERY_SESSION.state();
+pub async fn query(req: HttpRequest, query_request: Query) -> Result<HttpResponse, QueryError> {
+    let time_zone = query_request
+        .time_zone
+        .as_deref()
+        .or_else(|| {
+            req.headers()
+                .get(TIME_ZONE_HEADER_KEY)
+                .and_then(|value| value.to_str().ok())
+        })
+        .map(|name| {
+            name.parse::<Tz>()
+                .map_err(|_| QueryError::InvalidTimeZone(name.to_owned()))
+        })
+        .transpose()?;
+    let session_state = match time_zone {
+        Some(time_zone) => functions::in_time_zone(QUERY_SESSION.state(), time_zone).await?,
+        None => QUERY_SESSION.state(),
+    };
 
     // get the logical plan and extract the table name
     let raw_logical_plan = session_state
@@ -85,14 +125,17 @@ pub async fn query(req: HttpRequest, query_request: Query) -> Result<impl Respon
         .await
         .unwrap_or(None);
 
+    // cached query results don't record the time zone they were computed in
     let cache_results = req
         .headers()
         .get(CACHE_RESULTS_HEADER_KEY)
-        .and_then(|value| value.to_str().ok());
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| time_zone.is_none());
     let show_cached = req
         .headers()
         .get(CACHE_VIEW_HEADER_KEY)
-        .and_then(|value| value.to_str().ok());
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| time_zone.is_none());
     let user_id = req
         .headers()
         .get(USER_ID_HEADER_KEY)
@@ -112,10 +155,11 @@ pub async fn query(req: HttpRequest, query_request: Query) -> Result<impl Respon
     )
     .await
     {
-        return results.to_http();
+        return Ok(results.to_http()?.respond_to(&req).map_into_boxed_body());
     };
 
     let tables = visitor.into_inner();
+    let streams = tables.clone();
     update_schema_when_distributed(tables).await?;
     let mut query: LogicalQuery = into_query(&query_request, &session_state).await?;
 
@@ -128,8 +172,102 @@ pub async fn query(req: HttpRequest, query_request: Query) -> Result<impl Respon
 
     authorize_and_set_filter_tags(&mut query, permissions, &table_name)?;
 
+    let mut handle = QUERY_REGISTRY.register(
+        Users.get_username(&creds),
+        table_name.clone(),
+        query_request.query.clone(),
+    );
+    let query_id = handle.id().to_string();
+    let flag = handle.flag().clone();
+
+    if query_request.explain || query.is_explain() {
+        let explanation = handle
+            .run(async {
+                // EXPLAIN ANALYZE runs the query
+                let _permit = QUERY_LIMITER.acquire(QueryClass::User).await?;
+                Ok::<_, QueryError>(query.explain(table_name, false).await?)
+            })
+            .await??;
+        return Ok(HttpResponse::Ok()
+            .insert_header((QUERY_ID_HEADER_KEY, query_id))
+            .json(explanation));
+    }
+
+    let accept = req
+        .headers()
+        .get(http::header::ACCEPT)
+        .and_then(|value| value.to_str().ok());
+    // streamed responses skip both caches, they never hold the full result
+    if let Some(format) = StreamFormat::requested(
+        accept,
+        query_request.format.as_deref(),
+        query_request.delimiter.as_deref(),
+        query_request.stream,
+    )? {
+        let file_name = format.file_name(&table_name, query.start, query.end);
+        let (permit, (records, fields)) = handle
+            .run(async {
+                let permit = QUERY_LIMITER.acquire(QueryClass::User).await?;
+                Ok::<_, QueryError>((permit, query.execute_stream(table_name, &flag).await?))
+            })
+            .await??;
+        // stays cancellable until the last chunk is sent
+        let records = handle.stream(records);
+        let fill_null_fields = query_request.send_null.then_some(fields);
+        // the slot is held until the last chunk is sent
+        let body = stream_records(
+            records,
+            format,
+            fill_null_fields,
+            CONFIG.parseable.parquet_compression,
+        )
+        .map(move |chunk| {
+            let _permit = &permit;
+            chunk
+        });
+        let mut response = HttpResponse::Ok();
+        response
+            .insert_header((QUERY_ID_HEADER_KEY, query_id))
+            .content_type(format.content_type());
+        if let Some(file_name) = file_name {
+            response.insert_header(ContentDisposition::attachment(file_name));
+        }
+        return Ok(response.streaming(body));
+    }
+
     let time = Instant::now();
-    let (records, fields) = query.execute(table_name.clone()).await?;
+    // cached results are served without waiting for a query slot
+    let execute = async {
+        handle
+            .run(async {
+                let _permit = QUERY_LIMITER.acquire(QueryClass::User).await?;
+                Ok::<_, QueryError>(query.execute(table_name.clone(), &flag).await?)
+            })
+            .await?
+    };
+    // looked up only after authorization, the key includes the user's filter tags
+    let (records, fields, cache_hit) = match RESULT_CACHE.as_ref() {
+        Some(result_cache) => {
+            let key = ResultKey::new(
+                &query_request.query,
+                streams,
+                query.start,
+                query.end,
+                query.filter_tag.as_deref(),
+                Utc::now(),
+            )
+            .with_time_zone(time_zone.map(|time_zone| time_zone.name()));
+            let (result, hit) = result_cache.get_or_execute(key, execute).await?;
+            QUERY_RESULT_CACHE
+                .with_label_values(&[&table_name, if hit { "hit" } else { "miss" }])
+                .inc();
+            (result.records.clone(), result.fields.clone(), Some(hit))
+        }
+        None => {
+            let (records, fields) = execute.await?;
+            (records, fields, None)
+        }
+    };
     // deal with cache saving
     if let Err(err) = put_results_in_cache(
         cache_results,
@@ -146,13 +284,27 @@ pub async fn query(req: HttpRequest, query_request: Query) -> Result<impl Respon
         log::error!("{}", err);
     };
 
-    let response = QueryResponse {
+    let records = match time_zone {
+        Some(time_zone) => records
+            .iter()
+            .map(|batch| timestamps_in_time_zone(batch, time_zone.name()))
+            .collect::<Result<_, _>>()
+            .map_err(DataFusionError::from)?,
+        None => records,
+    };
+    let mut response = QueryResponse {
         records,
         fields,
         fill_null: query_request.send_null,
         with_fields: query_request.fields,
     }
-    .to_http()?;
+    .to_http()?
+    .customize()
+    .insert_header((QUERY_ID_HEADER_KEY, query_id));
+    if let Some(hit) = cache_hit {
+        response =
+            response.insert_header((RESULT_CACHE_HEADER_KEY, if hit { "hit" } else { "miss" }));
+    }
 
     let time = time.elapsed().as_secs_f64();
 
@@ -160,7 +312,83 @@ pub async fn query(req: HttpRequest, query_request: Query) -> Result<impl Respon
         .with_label_values(&[&table_name])
         .observe(time);
 
-    Ok(response)
+    Ok(response.respond_to(&req).map_into_boxed_body())
+}
+
+/// Limit of concurrently executed queries, unlimited when null
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryConcurrency {
+    pub max_concurrent_queries: Option<usize>,
+}
+
+pub async fn get_concurrency() -> impl Responder {
+    web::Json(QueryConcurrency {
+        max_concurrent_queries: QUERY_LIMITER.limit(),
+    })
+}
+
+/// Changes the limit on this node until it restarts
+pub async fn put_concurrency(
+    Json(concurrency): Json<QueryConcurrency>,
+) -> Result<impl Responder, QueryError> {
+    if concurrency.max_concurrent_queries == Some(0) {
+        return Err(QueryError::MalformedQuery(
+            "maxConcurrentQueries must be at least 1",
+        ));
+    }
+    QUERY_LIMITER.set_limit(concurrency.max_concurrent_queries);
+    log::info!(
+        "Limit of concurrent queries changed to {:?}",
+        concurrency.max_concurrent_queries
+    );
+    Ok(web::Json(concurrency))
+}
+
+// admins see and cancel every query, other users only their own
+fn query_visible(creds: &SessionKey, owner: Option<&str>) -> bool {
+    matches!(
+        Users.authorize(creds.clone(), Action::ManageQueries, None, None),
+        rbac::Response::Authorized
+    ) || owner.is_some() && owner == Users.get_username(creds).as_deref()
+}
+
+/// Queries running on this node
+pub async fn running(req: HttpRequest) -> Result<impl Responder, QueryError> {
+    let creds = extract_session_key_from_req(&req)?;
+    let queries: Vec<_> = QUERY_REGISTRY
+        .running()
+        .into_iter()
+        .filter(|query| query_visible(&creds, query.user.as_deref()))
+        .collect();
+    Ok(web::Json(queries))
+}
+
+/// Functions queries can call with their signatures, for the console to
+/// offer as completions
+pub async fn list_functions() -> impl Responder {
+    web::Json(functions::list(&QUERY_SESSION.state()))
+}
+
+/// Cancels a running query, its request fails with a cancelled error
+pub async fn cancel(
+    req: HttpRequest,
+    query_id: web::Path<String>,
+) -> Result<impl Responder, QueryError> {
+    let creds = extract_session_key_from_req(&req)?;
+    let id = query_id
+        .parse()
+        .map_err(|_| QueryError::MalformedQuery("Invalid query id"))?;
+    // other users' queries are reported as not running
+    let query = QUERY_REGISTRY
+        .get(id)
+        .filter(|query| query_visible(&creds, query.user.as_deref()))
+        .ok_or(QueryError::NotRunning(id))?;
+    if !QUERY_REGISTRY.cancel(id) {
+        return Err(QueryError::NotRunning(id));
+    }
+    log::info!("Query {id} on stream {} was cancelled", query.stream);
+    Ok(web::Json(query))
 }
 
 pub async fn update_schema_when_distributed(tables: Vec<String>) -> Result<(), QueryError> {
@@ -341,19 +569,28 @@ impl FromRequest for Query {
 
     fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
         let query = Json::<Query>::from_request(req, payload);
-        let params = web::Query::<HashMap<String, bool>>::from_request(req, payload)
+        let mut params = web::Query::<HashMap<String, String>>::from_request(req, payload)
             .into_inner()
             .map(|x| x.0)
             .unwrap_or_default();
+        let flag = |params: &HashMap<String, String>, name: &str| {
+            params
+                .get(name)
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(false)
+        };
 
         let fut = async move {
             let mut query = query.await?.into_inner();
             // format output json to include field names
-            query.fields = params.get("fields").cloned().unwrap_or(false);
+            query.fields = flag(&params, "fields");
 
             if !query.send_null {
-                query.send_null = params.get("sendNull").cloned().unwrap_or(false);
+                query.send_null = flag(&params, "sendNull");
             }
+            query.stream = flag(&params, "stream");
+            query.format = params.remove("format");
+            query.delimiter = params.remove("delimiter");
 
             Ok(query)
         };
@@ -392,7 +629,7 @@ pub async fn into_query(
     })
 }
 
-fn parse_human_time(
+pub fn parse_human_time(
     start_time: &str,
     end_time: &str,
 ) -> Result<(DateTime<Utc>, DateTime<Utc>), QueryError> {
@@ -444,8 +681,13 @@ fn transform_query_for_ingestor(query: &Query) -> Option<Query> {
         fields: false,
         filter_tags: query.filter_tags.clone(),
         send_null: query.send_null,
+        explain: false,
         start_time: start_time.to_rfc3339(),
         end_time: end_time.to_rfc3339(),
+        stream: false,
+        format: None,
+        delimiter: None,
+        time_zone: query.time_zone.clone(),
     };
 
     Some(q)
@@ -495,12 +737,23 @@ Description: {0}"#
     ActixError(#[from] actix_web::Error),
     #[error("Error: {0}")]
     Anyhow(#[from] anyhow::Error),
+    #[error("{0}")]
+    Limit(#[from] QueryLimitError),
+    #[error("{0}")]
+    Cancelled(#[from] Cancelled),
+    #[error("Query {0} is not running")]
+    NotRunning(ulid::Ulid),
+    #[error("Unknown time zone {0}, expected an IANA name like Europe/Berlin")]
+    InvalidTimeZone(String),
 }
 
 impl actix_web::ResponseError for QueryError {
     fn status_code(&self) -> http::StatusCode {
         match self {
             QueryError::Execute(_) | QueryError::JsonParse(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            QueryError::Limit(_) => StatusCode::TOO_MANY_REQUESTS,
+            QueryError::Cancelled(_) => StatusCode::CONFLICT,
+            QueryError::NotRunning(_) => StatusCode::NOT_FOUND,
             _ => StatusCode::BAD_REQUEST,
         }
     }
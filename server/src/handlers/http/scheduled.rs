@@ -0,0 +1,131 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse, Responder};
+use bytes::Bytes;
+use chrono::Utc;
+use http::StatusCode;
+use serde_json::Error as SerdeError;
+
+use crate::{
+    option::CONFIG,
+    scheduled::{scheduled_query_path, state_path, RunState, ScheduledQuery, SCHEDULED_QUERIES},
+    storage::ObjectStorageError,
+};
+
+pub async fn list() -> Result<impl Responder, ScheduledQueryError> {
+    Ok((web::Json(SCHEDULED_QUERIES.list()), StatusCode::OK))
+}
+
+pub async fn get(req: HttpRequest) -> Result<impl Responder, ScheduledQueryError> {
+    let (query, _) = find(&req)?;
+    Ok((web::Json(query), StatusCode::OK))
+}
+
+/// Progress of the query, the last window it materialized and its failures
+pub async fn status(req: HttpRequest) -> Result<impl Responder, ScheduledQueryError> {
+    let (_, state) = find(&req)?;
+    Ok((web::Json(state), StatusCode::OK))
+}
+
+pub async fn post(req: HttpRequest, body: Bytes) -> Result<impl Responder, ScheduledQueryError> {
+    let id = req
+        .match_info()
+        .get("id")
+        .ok_or(ScheduledQueryError::Metadata(
+            "No Scheduled Query Id Provided",
+        ))?;
+
+    let query: ScheduledQuery = serde_json::from_slice(&body)?;
+    query.validate().map_err(ScheduledQueryError::Metadata)?;
+    if query.id() != id {
+        return Err(ScheduledQueryError::Metadata(
+            "Scheduled Query Id does not match the one in the path",
+        ));
+    }
+
+    let store = CONFIG.storage().get_object_store();
+    store
+        .put_object(&scheduled_query_path(&format!("{id}.json")), body)
+        .await?;
+    let state = SCHEDULED_QUERIES.update(query, Utc::now());
+    store
+        .put_object(&state_path(id), Bytes::from(serde_json::to_vec(&state)?))
+        .await?;
+
+    Ok((web::Json(state), StatusCode::OK))
+}
+
+pub async fn delete(req: HttpRequest) -> Result<HttpResponse, ScheduledQueryError> {
+    let id = req
+        .match_info()
+        .get("id")
+        .ok_or(ScheduledQueryError::Metadata(
+            "No Scheduled Query Id Provided",
+        ))?;
+
+    let store = CONFIG.storage().get_object_store();
+    store
+        .delete_object(&scheduled_query_path(&format!("{id}.json")))
+        .await?;
+    store.delete_object(&state_path(id)).await?;
+    SCHEDULED_QUERIES.delete(id);
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn find(req: &HttpRequest) -> Result<(ScheduledQuery, RunState), ScheduledQueryError> {
+    let id = req
+        .match_info()
+        .get("id")
+        .ok_or(ScheduledQueryError::Metadata(
+            "No Scheduled Query Id Provided",
+        ))?;
+    SCHEDULED_QUERIES
+        .find(id)
+        .ok_or_else(|| ScheduledQueryError::NotFound(id.to_owned()))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScheduledQueryError {
+    #[error("Failed to connect to storage: {0}")]
+    ObjectStorage(#[from] ObjectStorageError),
+    #[error("Serde Error: {0}")]
+    Serde(#[from] SerdeError),
+    #[error("Cannot perform this operation: {0}")]
+    Metadata(&'static str),
+    #[error("Scheduled query {0} does not exist")]
+    NotFound(String),
+}
+
+impl actix_web::ResponseError for ScheduledQueryError {
+    fn status_code(&self) -> http::StatusCode {
+        match self {
+            Self::ObjectStorage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Serde(_) => StatusCode::BAD_REQUEST,
+            Self::Metadata(_) => StatusCode::BAD_REQUEST,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
+        actix_web::HttpResponse::build(self.status_code())
+            .insert_header(ContentType::plaintext())
+            .body(self.to_string())
+    }
+}
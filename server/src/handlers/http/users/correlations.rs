@@ -0,0 +1,245 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use crate::{
+    handlers::http::query::{parse_human_time, update_schema_when_distributed, QueryError},
+    option::CONFIG,
+    query::{
+        concurrency::{QueryClass, QUERY_LIMITER},
+        correlation, QUERY_SESSION,
+    },
+    rbac::{
+        role::{Action, Permission},
+        Users,
+    },
+    response::QueryResponse,
+    storage::{object_storage::correlation_path, ObjectStorageError},
+    users::correlations::{Correlation, CORRELATIONS},
+    utils::actix::extract_session_key_from_req,
+};
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse, Responder};
+use bytes::Bytes;
+use http::StatusCode;
+use serde_json::{Error as SerdeError, Value as JsonValue};
+
+pub async fn list(req: HttpRequest) -> Result<impl Responder, CorrelationError> {
+    let user_id = req
+        .match_info()
+        .get("user_id")
+        .ok_or(CorrelationError::Metadata("No User Id Provided"))?;
+
+    // .users/user_id/correlations/
+    let path = correlation_path(user_id, "");
+
+    let store = CONFIG.storage().get_object_store();
+    let correlations = store
+        .get_objects(
+            Some(&path),
+            Box::new(|file_name: String| file_name.ends_with("json")),
+        )
+        .await?;
+
+    let mut corr = vec![];
+    for correlation in correlations {
+        corr.push(serde_json::from_slice::<JsonValue>(&correlation)?)
+    }
+
+    Ok((web::Json(corr), StatusCode::OK))
+}
+
+pub async fn get(req: HttpRequest) -> Result<impl Responder, CorrelationError> {
+    let correlation = find(&req).await?;
+    Ok((web::Json(correlation), StatusCode::OK))
+}
+
+pub async fn post(req: HttpRequest, body: Bytes) -> Result<HttpResponse, CorrelationError> {
+    let user_id = req
+        .match_info()
+        .get("user_id")
+        .ok_or(CorrelationError::Metadata("No User Id Provided"))?;
+
+    let corr_id = req
+        .match_info()
+        .get("correlation_id")
+        .ok_or(CorrelationError::Metadata("No Correlation Id Provided"))?;
+
+    let correlation: Correlation = serde_json::from_slice(&body)?;
+    correlation.validate().map_err(CorrelationError::Metadata)?;
+    if correlation.correlation_id() != corr_id {
+        return Err(CorrelationError::Metadata(
+            "Correlation Id does not match the one in the path",
+        ));
+    }
+
+    let path = correlation_path(user_id, &format!("{}.json", corr_id));
+    let store = CONFIG.storage().get_object_store();
+    store.put_object(&path, body).await?;
+    CORRELATIONS.update(correlation);
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn delete(req: HttpRequest) -> Result<HttpResponse, CorrelationError> {
+    let user_id = req
+        .match_info()
+        .get("user_id")
+        .ok_or(CorrelationError::Metadata("No User Id Provided"))?;
+
+    let corr_id = req
+        .match_info()
+        .get("correlation_id")
+        .ok_or(CorrelationError::Metadata("No Correlation Id Provided"))?;
+
+    let path = correlation_path(user_id, &format!("{}.json", corr_id));
+    let store = CONFIG.storage().get_object_store();
+    store.delete_object(&path).await?;
+    CORRELATIONS.delete(corr_id);
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Time range a correlation is executed over, in the same format as queries
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecuteRequest {
+    start_time: String,
+    end_time: String,
+}
+
+/// Joins the streams of a correlation, the user needs query rights on all of them
+pub async fn execute(
+    req: HttpRequest,
+    body: web::Json<ExecuteRequest>,
+) -> Result<impl Responder, CorrelationError> {
+    let correlation = find(&req).await?;
+
+    let creds = extract_session_key_from_req(&req).map_err(QueryError::from)?;
+    let permissions = Users.get_permissions(&creds);
+    // tag restricted access can't be enforced on the joined events
+    let can_query = |stream: &str| {
+        permissions.iter().any(|permission| match permission {
+            Permission::Stream(Action::All, _) => true,
+            Permission::StreamWithTag(Action::Query, allowed, None) => {
+                allowed == stream || allowed == "*"
+            }
+            _ => false,
+        })
+    };
+    if let Some(stream) = correlation.unauthorized_stream(can_query) {
+        return Err(CorrelationError::Unauthorized(stream.to_owned()));
+    }
+
+    let (start, end) = parse_human_time(&body.start_time, &body.end_time)?;
+    let streams = correlation
+        .table_configs()
+        .iter()
+        .map(|table| table.stream_name.clone())
+        .collect();
+    update_schema_when_distributed(streams).await?;
+
+    let _permit = QUERY_LIMITER
+        .acquire(QueryClass::User)
+        .await
+        .map_err(QueryError::from)?;
+    let records = correlation::execute(
+        &QUERY_SESSION,
+        &correlation,
+        start,
+        end,
+        CONFIG.parseable.correlation_join_window,
+    )
+    .await
+    .map_err(QueryError::from)?;
+    let fields = records
+        .first()
+        .map(|batch| {
+            batch
+                .schema()
+                .fields()
+                .iter()
+                .map(|field| field.name().clone())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(QueryResponse {
+        records,
+        fields,
+        fill_null: false,
+        with_fields: false,
+    }
+    .to_http()?)
+}
+
+// the correlation named in the path, from memory or else from storage
+async fn find(req: &HttpRequest) -> Result<Correlation, CorrelationError> {
+    let user_id = req
+        .match_info()
+        .get("user_id")
+        .ok_or(CorrelationError::Metadata("No User Id Provided"))?;
+
+    let corr_id = req
+        .match_info()
+        .get("correlation_id")
+        .ok_or(CorrelationError::Metadata("No Correlation Id Provided"))?;
+
+    if let Some(correlation) = CORRELATIONS.find(corr_id) {
+        return Ok(correlation);
+    }
+
+    let path = correlation_path(user_id, &format!("{}.json", corr_id));
+    let resource = CONFIG
+        .storage()
+        .get_object_store()
+        .get_object(&path)
+        .await?;
+
+    Ok(serde_json::from_slice::<Correlation>(&resource)?)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CorrelationError {
+    #[error("Failed to connect to storage: {0}")]
+    ObjectStorage(#[from] ObjectStorageError),
+    #[error("Serde Error: {0}")]
+    Serde(#[from] SerdeError),
+    #[error("Cannot perform this operation: {0}")]
+    Metadata(&'static str),
+    #[error("User is not authorized to query stream {0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Query(#[from] QueryError),
+}
+
+impl actix_web::ResponseError for CorrelationError {
+    fn status_code(&self) -> http::StatusCode {
+        match self {
+            Self::ObjectStorage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Serde(_) => StatusCode::BAD_REQUEST,
+            Self::Metadata(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::FORBIDDEN,
+            Self::Query(err) => err.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
+        actix_web::HttpResponse::build(self.status_code())
+            .insert_header(ContentType::plaintext())
+            .body(self.to_string())
+    }
+}
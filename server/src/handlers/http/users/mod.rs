@@ -16,9 +16,13 @@
  *
  */
 
+pub mod correlations;
 pub mod dashboards;
 pub mod filters;
+pub mod saved_queries;
 
 pub const USERS_ROOT_DIR: &str = ".users";
 pub const DASHBOARDS_DIR: &str = "dashboards";
+pub const CORRELATIONS_DIR: &str = "correlations";
 pub const FILTER_DIR: &str = "filters";
+pub const SAVED_QUERIES_DIR: &str = "queries";
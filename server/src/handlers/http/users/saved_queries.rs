@@ -0,0 +1,223 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use crate::{
+    handlers::http::query::{self, QueryError},
+    option::CONFIG,
+    rbac::Users,
+    storage::{object_storage::saved_query_path, ObjectStorageError},
+    users::saved_queries::{ParameterError, SavedQuery, SAVED_QUERIES},
+    utils::actix::extract_session_key_from_req,
+};
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse, Responder};
+use bytes::Bytes;
+use http::StatusCode;
+use serde_json::{Error as SerdeError, Map, Value as JsonValue};
+
+pub async fn list(req: HttpRequest) -> Result<impl Responder, SavedQueryError> {
+    let user_id = req
+        .match_info()
+        .get("user_id")
+        .ok_or(SavedQueryError::Metadata("No User Id Provided"))?;
+
+    // .users/user_id/queries/
+    let path = saved_query_path(user_id, "");
+
+    let store = CONFIG.storage().get_object_store();
+    let queries = store
+        .get_objects(
+            Some(&path),
+            Box::new(|file_name: String| file_name.ends_with("json")),
+        )
+        .await?;
+
+    let mut saved = vec![];
+    for query in queries {
+        saved.push(serde_json::from_slice::<JsonValue>(&query)?)
+    }
+
+    Ok((web::Json(saved), StatusCode::OK))
+}
+
+pub async fn get(req: HttpRequest) -> Result<impl Responder, SavedQueryError> {
+    let saved = find(&req).await?;
+    Ok((web::Json(saved), StatusCode::OK))
+}
+
+/// Saves the query as a new revision, the body names the revision it replaces
+pub async fn post(req: HttpRequest, body: Bytes) -> Result<impl Responder, SavedQueryError> {
+    let user_id = req
+        .match_info()
+        .get("user_id")
+        .ok_or(SavedQueryError::Metadata("No User Id Provided"))?;
+
+    let query_id = req
+        .match_info()
+        .get("query_id")
+        .ok_or(SavedQueryError::Metadata("No Query Id Provided"))?;
+
+    let mut saved: SavedQuery = serde_json::from_slice(&body)?;
+    saved.validate()?;
+    if saved.query_id() != query_id {
+        return Err(SavedQueryError::Metadata(
+            "Query Id does not match the one in the path",
+        ));
+    }
+
+    let revision = match SAVED_QUERIES.find(user_id, query_id) {
+        Some(current) if current.revision() != saved.revision() => {
+            return Err(SavedQueryError::Conflict(current.revision()))
+        }
+        Some(current) => current.revision() + 1,
+        None => 1,
+    };
+    saved.save_as(user_id, revision);
+
+    let path = saved_query_path(user_id, &format!("{}.json", query_id));
+    let store = CONFIG.storage().get_object_store();
+    store
+        .put_object(&path, Bytes::from(serde_json::to_vec(&saved)?))
+        .await?;
+    SAVED_QUERIES.update(saved.clone());
+
+    Ok((web::Json(saved), StatusCode::OK))
+}
+
+pub async fn delete(req: HttpRequest) -> Result<HttpResponse, SavedQueryError> {
+    let user_id = req
+        .match_info()
+        .get("user_id")
+        .ok_or(SavedQueryError::Metadata("No User Id Provided"))?;
+
+    let query_id = req
+        .match_info()
+        .get("query_id")
+        .ok_or(SavedQueryError::Metadata("No Query Id Provided"))?;
+
+    let path = saved_query_path(user_id, &format!("{}.json", query_id));
+    let store = CONFIG.storage().get_object_store();
+    store.delete_object(&path).await?;
+    SAVED_QUERIES.delete(user_id, query_id);
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Values for the saved query's parameters and the time range to query
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecuteRequest {
+    start_time: String,
+    end_time: String,
+    #[serde(default)]
+    parameters: Map<String, JsonValue>,
+    #[serde(default)]
+    send_null: bool,
+}
+
+/// Runs the saved query through the query API with the caller's permissions
+pub async fn execute(
+    req: HttpRequest,
+    body: web::Json<ExecuteRequest>,
+) -> Result<HttpResponse, SavedQueryError> {
+    let saved = find(&req).await?;
+
+    let creds = extract_session_key_from_req(&req).map_err(QueryError::from)?;
+    if !saved.is_shared() && Users.get_username(&creds).as_deref() != Some(saved.user_id()) {
+        return Err(SavedQueryError::NotShared);
+    }
+
+    let body = body.into_inner();
+    let query_request = query::Query {
+        query: saved.render(&body.parameters)?,
+        start_time: body.start_time,
+        end_time: body.end_time,
+        send_null: body.send_null,
+        explain: false,
+        fields: false,
+        filter_tags: None,
+        stream: false,
+        format: None,
+        delimiter: None,
+        time_zone: None,
+    };
+    Ok(query::query(req, query_request).await?)
+}
+
+// the saved query named in the path, from memory or else from storage
+async fn find(req: &HttpRequest) -> Result<SavedQuery, SavedQueryError> {
+    let user_id = req
+        .match_info()
+        .get("user_id")
+        .ok_or(SavedQueryError::Metadata("No User Id Provided"))?;
+
+    let query_id = req
+        .match_info()
+        .get("query_id")
+        .ok_or(SavedQueryError::Metadata("No Query Id Provided"))?;
+
+    if let Some(saved) = SAVED_QUERIES.find(user_id, query_id) {
+        return Ok(saved);
+    }
+
+    let path = saved_query_path(user_id, &format!("{}.json", query_id));
+    let resource = CONFIG
+        .storage()
+        .get_object_store()
+        .get_object(&path)
+        .await?;
+
+    Ok(serde_json::from_slice::<SavedQuery>(&resource)?)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SavedQueryError {
+    #[error("Failed to connect to storage: {0}")]
+    ObjectStorage(#[from] ObjectStorageError),
+    #[error("Serde Error: {0}")]
+    Serde(#[from] SerdeError),
+    #[error("Cannot perform this operation: {0}")]
+    Metadata(&'static str),
+    #[error("{0}")]
+    Parameter(#[from] ParameterError),
+    #[error("Saved query was updated since, its current revision is {0}")]
+    Conflict(u64),
+    #[error("Saved query is not shared with other users")]
+    NotShared,
+    #[error("{0}")]
+    Query(#[from] QueryError),
+}
+
+impl actix_web::ResponseError for SavedQueryError {
+    fn status_code(&self) -> http::StatusCode {
+        match self {
+            Self::ObjectStorage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Serde(_) => StatusCode::BAD_REQUEST,
+            Self::Metadata(_) => StatusCode::BAD_REQUEST,
+            Self::Parameter(_) => StatusCode::BAD_REQUEST,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::NotShared => StatusCode::FORBIDDEN,
+            Self::Query(err) => err.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
+        actix_web::HttpResponse::build(self.status_code())
+            .insert_header(ContentType::plaintext())
+            .body(self.to_string())
+    }
+}
@@ -0,0 +1,1378 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+mod auth;
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    error::Error,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use rdkafka::{
+    client::OAuthToken,
+    config::ClientConfig,
+    consumer::{CommitMode, Consumer, ConsumerContext, Rebalance, StreamConsumer},
+    ClientContext, Message, Offset, TopicPartitionList,
+};
+use regex::Regex;
+use serde_json::Value;
+use tokio::{
+    sync::watch,
+    task::JoinHandle,
+    time::{sleep, sleep_until, Instant},
+};
+
+use self::auth::{KafkaAuth, TokenProvider};
+use super::http::ingest::{create_stream_if_not_exists, push_labelled_logs, PostError};
+use crate::{
+    cli::Cli,
+    event::{STAGING_FLUSHES, STREAM_WRITERS},
+    metadata::STREAM_INFO,
+    metrics::{
+        KAFKA_CONSUMER_LAG, KAFKA_REBALANCES, KAFKA_RECORDS_CONSUMED, KAFKA_RECORDS_INGESTED,
+        KAFKA_RECORDS_INGESTED_SIZE, KAFKA_RECORDS_REJECTED,
+    },
+    option::{Mode, CONFIG},
+    validator::{self, error::StreamNameValidationError},
+};
+
+/// Part of the stream template replaced by the topic name
+const TOPIC_PLACEHOLDER: &str = "{topic}";
+
+/// How many records are ingested at once
+const RECORDS_PER_BATCH: usize = 1000;
+
+/// How long a batch waits for more records after its first one
+const BATCH_LINGER: Duration = Duration::from_millis(500);
+
+/// How often the lag of the assigned partitions is refreshed
+const LAG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long the brokers get to answer the queries for the lag
+const LAG_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum KafkaError {
+    #[error("Kafka connector has neither topics nor a topic pattern to consume")]
+    NoTopics,
+    #[error("Kafka topic pattern {pattern:?} is not a valid regex: {source}")]
+    Pattern {
+        pattern: String,
+        #[source]
+        source: regex::Error,
+    },
+    #[error("Kafka topic stream {0:?} is not of the form topic=stream")]
+    MalformedMapping(String),
+    #[error("Kafka topic {topic} is mapped to both {first} and {second}")]
+    DuplicateMapping {
+        topic: String,
+        first: String,
+        second: String,
+    },
+    #[error("Kafka topic {0} is mapped to a stream but is neither in the topics nor matched by the topic pattern")]
+    Unconsumed(String),
+    #[error("Kafka stream template {0:?} has no {{topic}}, every topic of the pattern would go to the same stream")]
+    Template(String),
+    #[error("Kafka topics {first} and {second} both go to stream {stream}")]
+    Overlap {
+        first: String,
+        second: String,
+        stream: String,
+    },
+    #[error("Kafka topic {topic} goes to {stream:?} which is not a valid stream name: {source}")]
+    InvalidStream {
+        topic: String,
+        stream: String,
+        #[source]
+        source: StreamNameValidationError,
+    },
+    #[error("Kafka commit strategy {0:?} is not one of flush, interval or records")]
+    CommitStrategy(String),
+    #[error("Invalid Kafka authentication settings, {0}")]
+    Auth(String),
+    #[error("{0}")]
+    Client(#[from] rdkafka::error::KafkaError),
+}
+
+/// Topics the connector consumes, those listed and those matching the pattern
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    topics: Vec<String>,
+    pattern: Option<Regex>,
+}
+
+impl Subscription {
+    pub fn new(topics: &[String], pattern: Option<&str>) -> Result<Self, KafkaError> {
+        let topics: Vec<String> = topics
+            .iter()
+            .map(|topic| topic.trim())
+            .filter(|topic| !topic.is_empty())
+            .map(str::to_owned)
+            .collect();
+        // librdkafka takes topics starting with ^ as patterns, the same
+        // anchored regex decides which topics are consumed here
+        let pattern = pattern
+            .map(|pattern| {
+                let anchored = if pattern.starts_with('^') {
+                    pattern.to_owned()
+                } else {
+                    format!("^{pattern}")
+                };
+                Regex::new(&anchored).map_err(|source| KafkaError::Pattern {
+                    pattern: pattern.to_owned(),
+                    source,
+                })
+            })
+            .transpose()?;
+        if topics.is_empty() && pattern.is_none() {
+            return Err(KafkaError::NoTopics);
+        }
+        Ok(Self { topics, pattern })
+    }
+
+    pub fn contains(&self, topic: &str) -> bool {
+        self.topics.iter().any(|listed| listed == topic)
+            || self
+                .pattern
+                .as_ref()
+                .is_some_and(|pattern| pattern.is_match(topic))
+    }
+
+    fn client_topics(&self) -> Vec<&str> {
+        self.topics
+            .iter()
+            .map(String::as_str)
+            .chain(self.pattern.as_ref().map(Regex::as_str))
+            .collect()
+    }
+}
+
+/// Decides the stream the records of each topic go to, topics mapped to a
+/// stream go there and the others to the stream of the template. A stream only
+/// ever gets the records of one topic
+#[derive(Debug, Clone)]
+pub struct TopicMapping {
+    streams: BTreeMap<String, String>,
+    template: String,
+    // the topic whose records each stream gets
+    claimed: BTreeMap<String, String>,
+}
+
+impl TopicMapping {
+    /// Mapping of `entries` given as topic=stream over `template`
+    pub fn new(entries: &[String], template: &str) -> Result<Self, KafkaError> {
+        let mut streams = BTreeMap::new();
+        for entry in entries {
+            let (topic, stream) = entry
+                .split_once('=')
+                .map(|(topic, stream)| (topic.trim(), stream.trim()))
+                .filter(|(topic, stream)| !topic.is_empty() && !stream.is_empty())
+                .ok_or_else(|| KafkaError::MalformedMapping(entry.clone()))?;
+            match streams.insert(topic.to_owned(), stream.to_owned()) {
+                Some(first) if first != stream => {
+                    return Err(KafkaError::DuplicateMapping {
+                        topic: topic.to_owned(),
+                        first,
+                        second: stream.to_owned(),
+                    })
+                }
+                _ => {}
+            }
+        }
+
+        let mut mapping = Self {
+            streams: BTreeMap::new(),
+            template: template.to_owned(),
+            claimed: BTreeMap::new(),
+        };
+        // mapped streams are claimed first so no templated topic takes them
+        for (topic, stream) in &streams {
+            mapping.claim(topic, stream.clone())?;
+        }
+        mapping.streams = streams;
+        Ok(mapping)
+    }
+
+    /// The stream records of `topic` go to, which then only gets records of `topic`
+    pub fn stream(&mut self, topic: &str) -> Result<String, KafkaError> {
+        let stream = match self.streams.get(topic) {
+            Some(stream) => stream.clone(),
+            None => self.render(topic),
+        };
+        self.claim(topic, stream)
+    }
+
+    // the template with the topic in place, lowercased and without the
+    // characters stream names can't have
+    fn render(&self, topic: &str) -> String {
+        self.template
+            .replace(TOPIC_PLACEHOLDER, topic)
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .map(|c| c.to_ascii_lowercase())
+            .collect()
+    }
+
+    fn claim(&mut self, topic: &str, stream: String) -> Result<String, KafkaError> {
+        match self.claimed.get(&stream) {
+            Some(first) if first == topic => Ok(stream),
+            Some(first) => Err(KafkaError::Overlap {
+                first: first.clone(),
+                second: topic.to_owned(),
+                stream,
+            }),
+            None => {
+                validator::stream_name(&stream).map_err(|source| KafkaError::InvalidStream {
+                    topic: topic.to_owned(),
+                    stream: stream.clone(),
+                    source,
+                })?;
+                self.claimed.insert(stream.clone(), topic.to_owned());
+                Ok(stream)
+            }
+        }
+    }
+}
+
+/// When the connector commits the offsets of the records it ingested
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitStrategy {
+    /// Once the staging flush has the records on disk, the default. A crash
+    /// then only has records consumed again, never lost
+    Flush,
+    /// At most this often, records not flushed from staging yet are lost on a crash
+    Interval(Duration),
+    /// Once this many records were ingested, records not flushed from staging
+    /// yet are lost on a crash
+    Records(usize),
+}
+
+impl CommitStrategy {
+    pub fn from_cli(cli: &Cli) -> Result<Self, KafkaError> {
+        match cli.kafka_commit_strategy.as_str() {
+            "flush" => Ok(Self::Flush),
+            "interval" => Ok(Self::Interval(Duration::from_secs(
+                cli.kafka_commit_interval,
+            ))),
+            "records" => Ok(Self::Records(cli.kafka_commit_records)),
+            other => Err(KafkaError::CommitStrategy(other.to_owned())),
+        }
+    }
+}
+
+/// Settings of the Kafka connector
+#[derive(Debug, Clone)]
+pub struct KafkaConfig {
+    pub brokers: String,
+    pub group_id: String,
+    pub subscription: Subscription,
+    pub mapping: TopicMapping,
+    pub create_streams: bool,
+    pub commit: CommitStrategy,
+    pub auth: KafkaAuth,
+}
+
+impl KafkaConfig {
+    /// The connector settings of `cli`, none when it sets no brokers. Mappings
+    /// that overlap or can't be reached are rejected here so that the server
+    /// fails to start over them
+    pub fn from_cli(cli: &Cli) -> Result<Option<Self>, KafkaError> {
+        let Some(brokers) = &cli.kafka_brokers else {
+            return Ok(None);
+        };
+        let subscription =
+            Subscription::new(&cli.kafka_topics, cli.kafka_topic_pattern.as_deref())?;
+        if subscription.pattern.is_some() && !cli.kafka_stream_template.contains(TOPIC_PLACEHOLDER)
+        {
+            return Err(KafkaError::Template(cli.kafka_stream_template.clone()));
+        }
+        let mut mapping = TopicMapping::new(&cli.kafka_topic_streams, &cli.kafka_stream_template)?;
+        if let Some(topic) = mapping
+            .streams
+            .keys()
+            .find(|topic| !subscription.contains(topic))
+        {
+            return Err(KafkaError::Unconsumed(topic.clone()));
+        }
+        for topic in &subscription.topics {
+            mapping.stream(topic)?;
+        }
+
+        Ok(Some(Self {
+            brokers: brokers.clone(),
+            group_id: cli.kafka_group_id.clone(),
+            subscription,
+            mapping,
+            create_streams: cli.kafka_create_streams,
+            commit: CommitStrategy::from_cli(cli)?,
+            auth: KafkaAuth::from_cli(cli)?,
+        }))
+    }
+}
+
+/// A record as it was consumed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub payload: Option<Vec<u8>>,
+}
+
+/// What polling the consumer gives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Polled {
+    Record(Record),
+    /// Partitions a rebalance is taking away, as topic and partition
+    Revoked(Vec<(String, i32)>),
+}
+
+/// Where records are consumed from
+#[async_trait]
+pub trait Source: Send {
+    /// The next record or revocation, none once the source is closed
+    async fn recv(&mut self) -> Option<Result<Polled, KafkaError>>;
+
+    /// Commits the offset of the next record to consume for each partition
+    fn commit(&mut self, offsets: &BTreeMap<(String, i32), i64>) -> Result<(), KafkaError>;
+}
+
+/// Where the events of a topic are ingested, staged until flushed to disk
+#[async_trait]
+pub trait Sink: Send {
+    async fn push(
+        &mut self,
+        stream_name: &str,
+        topic: &str,
+        events: Vec<Value>,
+    ) -> Result<(), PostError>;
+
+    /// The staging flush that has the events pushed so far on disk once it completed
+    fn next_flush(&self) -> u64;
+
+    /// Waits until staging flush `flush` completed, gives the flushes completed by then
+    async fn flushed(&mut self, flush: u64) -> u64;
+
+    /// Flushes the staged events to disk right away
+    async fn flush(&mut self);
+}
+
+/// The running connector, stopped before the server shuts down
+pub struct Connector {
+    stop: watch::Sender<bool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Connector {
+    /// Starts consuming the configured topics into their streams, nothing is
+    /// consumed when no brokers are set
+    pub fn spawn(config: Option<KafkaConfig>) -> Self {
+        let (stop, stopped) = watch::channel(false);
+        Self {
+            stop,
+            handle: config.map(|config| tokio::spawn(server(config, stopped))),
+        }
+    }
+
+    /// Stops consuming, returns once the records consumed are flushed from
+    /// staging and their offsets committed
+    pub async fn stop(&mut self) {
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+        self.stop.send_replace(true);
+        if let Err(err) = handle.await {
+            log::error!("Kafka connector failed while stopping: {err}");
+        }
+    }
+}
+
+async fn server(mut config: KafkaConfig, stop: watch::Receiver<bool>) {
+    // the client needs a token as soon as it connects
+    let tokens = match config.auth.oauth() {
+        Some(oauth) => {
+            let tokens = Arc::new(TokenProvider::new(oauth.clone()));
+            if let Err(err) = tokens.fetch().await {
+                log::error!("Kafka connector failed to get an OAuth token: {err}");
+                return;
+            }
+            tokio::spawn(tokens.clone().refresh());
+            Some(tokens)
+        }
+        None => None,
+    };
+    let mut source = match KafkaSource::new(&config, tokens, stop.clone()) {
+        Ok(source) => source,
+        Err(err) => {
+            log::error!("Kafka connector failed: {err}");
+            return;
+        }
+    };
+    log::info!(
+        "Kafka connector consuming {} from {}",
+        source.topics.join(", "),
+        config.brokers
+    );
+    tokio::spawn(refresh_lag(source.consumer.clone(), stop));
+    let mut sink = Streams {
+        create_streams: config.create_streams,
+        flushes: STAGING_FLUSHES.subscribe(),
+    };
+    consume(&mut source, &mut sink, &mut config.mapping, config.commit).await;
+}
+
+/// Ingests what `source` polls into the streams of `mapping` until the source
+/// closes, committing offsets as `strategy` has it. Offsets are only committed
+/// once the records before them are ingested, records of revoked partitions
+/// are dropped and their next owner reads them from the committed offsets.
+///
+/// Once the source closes what was consumed is ingested, flushed from staging
+/// and then committed.
+pub async fn consume(
+    source: &mut impl Source,
+    sink: &mut impl Sink,
+    mapping: &mut TopicMapping,
+    strategy: CommitStrategy,
+) {
+    let mut batch = Batch::default();
+    let mut progress = Progress::new(strategy);
+    // when the batch stops waiting for more records
+    let mut linger = None;
+    loop {
+        let now = Instant::now();
+        let awaited_flush = progress.awaited_flush();
+        let commit_at = progress.commit_at();
+        tokio::select! {
+            polled = source.recv() => match polled {
+                Some(Ok(Polled::Record(record))) => {
+                    KAFKA_RECORDS_CONSUMED
+                        .with_label_values(&[&record.topic, &record.partition.to_string()])
+                        .inc();
+                    batch.push(record);
+                    linger.get_or_insert(now + BATCH_LINGER);
+                    if batch.len < RECORDS_PER_BATCH {
+                        continue;
+                    }
+                }
+                Some(Ok(Polled::Revoked(partitions))) => {
+                    batch.revoke(&partitions);
+                    progress.revoke(&partitions);
+                    continue;
+                }
+                Some(Err(err)) => {
+                    log::warn!("Failed to poll Kafka: {err}");
+                    continue;
+                }
+                None => break,
+            },
+            () = sleep_until(linger.unwrap_or(now)), if linger.is_some() => {}
+            flushed = sink.flushed(awaited_flush.unwrap_or_default()), if awaited_flush.is_some() => {
+                commit(source, progress.take(Some(flushed)));
+                continue;
+            }
+            () = sleep_until(commit_at.unwrap_or(now)), if commit_at.is_some() => {
+                commit(source, progress.take(None));
+                continue;
+            }
+        }
+
+        // the batch is full or done waiting
+        linger = None;
+        std::mem::take(&mut batch)
+            .ingest(sink, mapping, &mut progress)
+            .await;
+        if progress.records_due() {
+            commit(source, progress.take(None));
+        }
+    }
+
+    batch.ingest(sink, mapping, &mut progress).await;
+    sink.flush().await;
+    commit(source, progress.take(None));
+}
+
+// commits the next offset to consume of each partition, the records since the
+// last commit are consumed again when that fails
+fn commit(source: &mut impl Source, offsets: BTreeMap<(String, i32), i64>) {
+    if offsets.is_empty() {
+        return;
+    }
+    if let Err(err) = source.commit(&offsets) {
+        log::error!("Failed to commit Kafka offsets, records since the last commit will be consumed again: {err}");
+    }
+}
+
+// records polled and not ingested yet by topic and partition
+#[derive(Debug, Default)]
+struct Batch {
+    partitions: BTreeMap<(String, i32), Vec<Record>>,
+    len: usize,
+}
+
+impl Batch {
+    fn push(&mut self, record: Record) {
+        self.partitions
+            .entry((record.topic.clone(), record.partition))
+            .or_default()
+            .push(record);
+        self.len += 1;
+    }
+
+    fn revoke(&mut self, partitions: &[(String, i32)]) {
+        for partition in partitions {
+            if let Some(records) = self.partitions.remove(partition) {
+                self.len -= records.len();
+            }
+        }
+    }
+
+    async fn ingest(
+        self,
+        sink: &mut impl Sink,
+        mapping: &mut TopicMapping,
+        progress: &mut Progress,
+    ) {
+        if self.partitions.is_empty() {
+            return;
+        }
+        let mut offsets = BTreeMap::new();
+        let mut topics: BTreeMap<String, Vec<Record>> = BTreeMap::new();
+        for ((topic, partition), records) in self.partitions {
+            if let Some(last) = records.last() {
+                offsets.insert((topic.clone(), partition), last.offset + 1);
+            }
+            topics.entry(topic).or_default().extend(records);
+        }
+
+        for (topic, records) in topics {
+            ingest_topic(&topic, records, sink, mapping).await;
+        }
+        progress.push(sink.next_flush(), offsets, self.len);
+    }
+}
+
+// offsets of the records ingested but not committed yet
+#[derive(Debug)]
+struct Progress {
+    strategy: CommitStrategy,
+    // in the order ingested, along with the staging flush that has the records on disk
+    ingested: VecDeque<(u64, BTreeMap<(String, i32), i64>)>,
+    records: usize,
+    committed_at: Instant,
+}
+
+impl Progress {
+    fn new(strategy: CommitStrategy) -> Self {
+        Self {
+            strategy,
+            ingested: VecDeque::new(),
+            records: 0,
+            committed_at: Instant::now(),
+        }
+    }
+
+    fn push(&mut self, flush: u64, offsets: BTreeMap<(String, i32), i64>, records: usize) {
+        self.ingested.push_back((flush, offsets));
+        self.records += records;
+    }
+
+    // offsets of revoked partitions can't be committed anymore
+    fn revoke(&mut self, partitions: &[(String, i32)]) {
+        for (_, offsets) in &mut self.ingested {
+            for partition in partitions {
+                offsets.remove(partition);
+            }
+        }
+        self.ingested.retain(|(_, offsets)| !offsets.is_empty());
+    }
+
+    // the staging flush the oldest records wait for to be committed
+    fn awaited_flush(&self) -> Option<u64> {
+        match self.strategy {
+            CommitStrategy::Flush => self.ingested.front().map(|(flush, _)| *flush),
+            _ => None,
+        }
+    }
+
+    fn commit_at(&self) -> Option<Instant> {
+        match self.strategy {
+            CommitStrategy::Interval(interval) if !self.ingested.is_empty() => {
+                Some(self.committed_at + interval)
+            }
+            _ => None,
+        }
+    }
+
+    fn records_due(&self) -> bool {
+        match self.strategy {
+            CommitStrategy::Records(records) => {
+                !self.ingested.is_empty() && self.records >= records
+            }
+            _ => false,
+        }
+    }
+
+    // the offsets to commit of the records the `flushed` staging flushes have
+    // on disk, or of every record when none is given
+    fn take(&mut self, flushed: Option<u64>) -> BTreeMap<(String, i32), i64> {
+        let mut offsets = BTreeMap::new();
+        while self
+            .ingested
+            .front()
+            .is_some_and(|(flush, _)| *flush <= flushed.unwrap_or(u64::MAX))
+        {
+            if let Some((_, ingested)) = self.ingested.pop_front() {
+                offsets.extend(ingested);
+            }
+        }
+        if self.ingested.is_empty() {
+            self.records = 0;
+        }
+        self.committed_at = Instant::now();
+        offsets
+    }
+}
+
+// records that aren't JSON or that the stream rejects are dropped, they would
+// otherwise hold back every later record of their partition
+async fn ingest_topic(
+    topic: &str,
+    records: Vec<Record>,
+    sink: &mut impl Sink,
+    mapping: &mut TopicMapping,
+) {
+    let stream_name = match mapping.stream(topic) {
+        Ok(stream_name) => stream_name,
+        Err(err) => {
+            log::error!("Dropping {} records: {err}", records.len());
+            KAFKA_RECORDS_REJECTED
+                .with_label_values(&[topic, ""])
+                .inc_by(records.len() as u64);
+            return;
+        }
+    };
+
+    let mut events = Vec::with_capacity(records.len());
+    let (mut ingested, mut rejected, mut size) = (0, 0, 0);
+    for record in records {
+        let Some(Ok(value)) = record
+            .payload
+            .as_deref()
+            .map(serde_json::from_slice::<Value>)
+        else {
+            rejected += 1;
+            continue;
+        };
+        match value {
+            Value::Array(values) => events.extend(values),
+            value => events.push(value),
+        }
+        ingested += 1;
+        size += record.payload.as_ref().map_or(0, Vec::len) as u64;
+    }
+    if rejected > 0 {
+        log::warn!("Dropping {rejected} records of Kafka topic {topic} that are not JSON");
+        KAFKA_RECORDS_REJECTED
+            .with_label_values(&[topic, &stream_name])
+            .inc_by(rejected);
+    }
+    if events.is_empty() {
+        return;
+    }
+
+    match sink.push(&stream_name, topic, events).await {
+        Ok(()) => {
+            KAFKA_RECORDS_INGESTED
+                .with_label_values(&[topic, &stream_name])
+                .inc_by(ingested);
+            KAFKA_RECORDS_INGESTED_SIZE
+                .with_label_values(&[topic, &stream_name])
+                .inc_by(size);
+        }
+        Err(err) => {
+            log::warn!(
+                "Failed to ingest {ingested} records of Kafka topic {topic} into {stream_name}: {err}"
+            );
+            KAFKA_RECORDS_REJECTED
+                .with_label_values(&[topic, &stream_name])
+                .inc_by(ingested);
+        }
+    }
+}
+
+// keeps the partitions revoked by rebalances until the consumer loop sees them,
+// they're reported while the partitions are still assigned, and counts the
+// rebalances. Hands the client the latest OAuth token whenever it asks for one
+struct ConnectorContext {
+    revoked: Mutex<Vec<(String, i32)>>,
+    tokens: Option<Arc<TokenProvider>>,
+}
+
+impl ConnectorContext {
+    fn take_revoked(&self) -> Vec<(String, i32)> {
+        std::mem::take(&mut *self.revoked.lock().expect("revoked lock is not poisoned"))
+    }
+}
+
+impl ClientContext for ConnectorContext {
+    const ENABLE_REFRESH_OAUTH_TOKEN: bool = true;
+
+    fn generate_oauth_token(&self, _: Option<&str>) -> Result<OAuthToken, Box<dyn Error>> {
+        let tokens = self
+            .tokens
+            .as_ref()
+            .ok_or("Kafka connector is not set up for OAUTHBEARER")?;
+        Ok(tokens.oauth_token()?)
+    }
+}
+
+impl ConsumerContext for ConnectorContext {
+    fn pre_rebalance(&self, rebalance: &Rebalance<'_>) {
+        if let Rebalance::Revoke(partitions) = rebalance {
+            let revoked: Vec<(String, i32)> = partitions
+                .elements()
+                .iter()
+                .map(|element| (element.topic().to_owned(), element.partition()))
+                .collect();
+            for (topic, partition) in &revoked {
+                let partition = partition.to_string();
+                KAFKA_REBALANCES
+                    .with_label_values(&[topic, &partition, "revoked"])
+                    .inc();
+                // the next owner reports its lag
+                let _ = KAFKA_CONSUMER_LAG.remove_label_values(&[topic, &partition]);
+            }
+            self.revoked
+                .lock()
+                .expect("revoked lock is not poisoned")
+                .extend(revoked);
+        }
+    }
+
+    fn post_rebalance(&self, rebalance: &Rebalance<'_>) {
+        if let Rebalance::Assign(partitions) = rebalance {
+            for element in partitions.elements() {
+                KAFKA_REBALANCES
+                    .with_label_values(&[
+                        element.topic(),
+                        &element.partition().to_string(),
+                        "assigned",
+                    ])
+                    .inc();
+            }
+        }
+    }
+}
+
+// closes once the connector is stopped
+struct KafkaSource {
+    consumer: Arc<StreamConsumer<ConnectorContext>>,
+    topics: Vec<String>,
+    // a record received along with a revocation, given out after it
+    pending: Option<Record>,
+    stop: watch::Receiver<bool>,
+}
+
+impl KafkaSource {
+    fn new(
+        config: &KafkaConfig,
+        tokens: Option<Arc<TokenProvider>>,
+        stop: watch::Receiver<bool>,
+    ) -> Result<Self, KafkaError> {
+        let mut client_config = ClientConfig::new();
+        client_config
+            .set("bootstrap.servers", &config.brokers)
+            .set("group.id", &config.group_id)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest");
+        config.auth.apply(&mut client_config);
+        let consumer: StreamConsumer<ConnectorContext> =
+            client_config.create_with_context(ConnectorContext {
+                revoked: Mutex::default(),
+                tokens,
+            })?;
+        let topics = config.subscription.client_topics();
+        consumer.subscribe(&topics)?;
+        Ok(Self {
+            consumer: Arc::new(consumer),
+            topics: topics.into_iter().map(str::to_owned).collect(),
+            pending: None,
+            stop,
+        })
+    }
+
+    fn revoked(&self) -> Option<Polled> {
+        let revoked = self.consumer.context().take_revoked();
+        (!revoked.is_empty()).then_some(Polled::Revoked(revoked))
+    }
+}
+
+#[async_trait]
+impl Source for KafkaSource {
+    // rebalances happen while receiving, revocations are given out before the
+    // record received along with them
+    async fn recv(&mut self) -> Option<Result<Polled, KafkaError>> {
+        if *self.stop.borrow() {
+            return None;
+        }
+        if let Some(revoked) = self.revoked() {
+            return Some(Ok(revoked));
+        }
+        if let Some(record) = self.pending.take() {
+            return Some(Ok(Polled::Record(record)));
+        }
+        let received = tokio::select! {
+            received = self.consumer.recv() => received.map(|message| Record {
+                topic: message.topic().to_owned(),
+                partition: message.partition(),
+                offset: message.offset(),
+                payload: message.payload().map(<[u8]>::to_vec),
+            }),
+            // stopped, or the connector is gone
+            _ = self.stop.changed() => return None,
+        };
+        let record = match received {
+            Ok(record) => record,
+            Err(err) => return Some(Err(err.into())),
+        };
+        match self.revoked() {
+            Some(revoked) => {
+                self.pending = Some(record);
+                Some(Ok(revoked))
+            }
+            None => Some(Ok(Polled::Record(record))),
+        }
+    }
+
+    fn commit(&mut self, offsets: &BTreeMap<(String, i32), i64>) -> Result<(), KafkaError> {
+        let mut partitions = TopicPartitionList::new();
+        for ((topic, partition), offset) in offsets {
+            partitions.add_partition_offset(topic, *partition, Offset::Offset(*offset))?;
+        }
+        self.consumer.commit(&partitions, CommitMode::Sync)?;
+        Ok(())
+    }
+}
+
+// sets the lag of the assigned partitions every LAG_INTERVAL until the
+// connector is stopped
+async fn refresh_lag(
+    consumer: Arc<StreamConsumer<ConnectorContext>>,
+    mut stop: watch::Receiver<bool>,
+) {
+    loop {
+        tokio::select! {
+            () = sleep(LAG_INTERVAL) => {}
+            _ = stop.changed() => return,
+        }
+        let consumer = consumer.clone();
+        match tokio::task::spawn_blocking(move || set_lag(&consumer)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => log::warn!("Failed to get the lag of Kafka partitions: {err}"),
+            Err(err) => log::warn!("Failed to get the lag of Kafka partitions: {err}"),
+        }
+    }
+}
+
+// the lag of a partition is its end offset less the offset consumed from, or
+// the committed offset before anything was consumed
+fn set_lag(consumer: &StreamConsumer<ConnectorContext>) -> Result<(), KafkaError> {
+    let assignment = consumer.assignment()?;
+    let positions = consumer.position()?;
+    let committed = consumer.committed_offsets(assignment.clone(), LAG_TIMEOUT)?;
+    for element in assignment.elements() {
+        let (topic, partition) = (element.topic(), element.partition());
+        let (start, end) = consumer.fetch_watermarks(topic, partition, LAG_TIMEOUT)?;
+        let consumed = [&positions, &committed]
+            .into_iter()
+            .filter_map(|offsets| offsets.find_partition(topic, partition))
+            .find_map(|element| match element.offset() {
+                Offset::Offset(offset) => Some(offset),
+                _ => None,
+            })
+            .unwrap_or(start);
+        KAFKA_CONSUMER_LAG
+            .with_label_values(&[topic, &partition.to_string()])
+            .set((end - consumed).max(0));
+    }
+    Ok(())
+}
+
+// the streams of the server
+struct Streams {
+    create_streams: bool,
+    flushes: watch::Receiver<u64>,
+}
+
+#[async_trait]
+impl Sink for Streams {
+    async fn push(
+        &mut self,
+        stream_name: &str,
+        topic: &str,
+        events: Vec<Value>,
+    ) -> Result<(), PostError> {
+        // ingestors only load streams created elsewhere here
+        if self.create_streams || CONFIG.parseable.mode == Mode::Ingest {
+            create_stream_if_not_exists(stream_name, false).await?;
+        } else if !STREAM_INFO.stream_exists(stream_name) {
+            return Err(PostError::StreamNotFound(stream_name.to_owned()));
+        }
+        let body: Bytes = serde_json::to_vec(&events)?.into();
+        push_labelled_logs(
+            stream_name.to_owned(),
+            "",
+            &format!("kafka_topic={topic}"),
+            body,
+        )
+        .await
+    }
+
+    fn next_flush(&self) -> u64 {
+        STAGING_FLUSHES.next()
+    }
+
+    async fn flushed(&mut self, flush: u64) -> u64 {
+        wait_for_flush(&mut self.flushes, flush).await
+    }
+
+    async fn flush(&mut self) {
+        if let Err(err) = tokio::task::spawn_blocking(|| STREAM_WRITERS.unset_all()).await {
+            log::error!("Failed to flush the staged Kafka records: {err}");
+        }
+    }
+}
+
+// waits until `flushes` counts `flush`, gives the count by then
+async fn wait_for_flush(flushes: &mut watch::Receiver<u64>, flush: u64) -> u64 {
+    loop {
+        let completed = *flushes.borrow_and_update();
+        if completed >= flush {
+            return completed;
+        }
+        if flushes.changed().await.is_err() {
+            // no flush is ever counted again
+            return std::future::pending().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, VecDeque},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use serde_json::{json, Value};
+    use tokio::sync::watch;
+
+    use super::{
+        consume, wait_for_flush, CommitStrategy, KafkaConfig, KafkaError, Polled, Record, Sink,
+        Source, Subscription, TopicMapping, BATCH_LINGER,
+    };
+    use crate::{cli::Cli, handlers::http::ingest::PostError};
+
+    // what the connector did, in order
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Event {
+        Flushed(u64),
+        Committed(BTreeMap<(String, i32), i64>),
+    }
+
+    enum Step {
+        Poll(Polled),
+        // long enough for the batch to be ingested
+        Idle,
+        // a staging flush of the server completes
+        Flush,
+    }
+
+    type Log = Arc<Mutex<Vec<Event>>>;
+
+    fn complete_flush(flushes: &watch::Sender<u64>, log: &Log) {
+        flushes.send_modify(|completed| *completed += 1);
+        log.lock().unwrap().push(Event::Flushed(*flushes.borrow()));
+    }
+
+    struct MockSource {
+        steps: VecDeque<Step>,
+        committed: Vec<BTreeMap<(String, i32), i64>>,
+        flushes: Arc<watch::Sender<u64>>,
+        log: Log,
+    }
+
+    #[async_trait]
+    impl Source for MockSource {
+        async fn recv(&mut self) -> Option<Result<Polled, KafkaError>> {
+            loop {
+                match self.steps.pop_front()? {
+                    Step::Poll(polled) => return Some(Ok(polled)),
+                    Step::Idle => tokio::time::sleep(BATCH_LINGER * 2).await,
+                    Step::Flush => complete_flush(&self.flushes, &self.log),
+                }
+            }
+        }
+
+        fn commit(&mut self, offsets: &BTreeMap<(String, i32), i64>) -> Result<(), KafkaError> {
+            self.committed.push(offsets.clone());
+            self.log
+                .lock()
+                .unwrap()
+                .push(Event::Committed(offsets.clone()));
+            Ok(())
+        }
+    }
+
+    struct MockSink {
+        pushed: BTreeMap<String, Vec<Value>>,
+        flushes: Arc<watch::Sender<u64>>,
+        completed: watch::Receiver<u64>,
+        log: Log,
+    }
+
+    #[async_trait]
+    impl Sink for MockSink {
+        async fn push(
+            &mut self,
+            stream_name: &str,
+            _: &str,
+            events: Vec<Value>,
+        ) -> Result<(), PostError> {
+            self.pushed
+                .entry(stream_name.to_owned())
+                .or_default()
+                .extend(events);
+            Ok(())
+        }
+
+        fn next_flush(&self) -> u64 {
+            *self.flushes.borrow() + 1
+        }
+
+        async fn flushed(&mut self, flush: u64) -> u64 {
+            wait_for_flush(&mut self.completed, flush).await
+        }
+
+        async fn flush(&mut self) {
+            complete_flush(&self.flushes, &self.log)
+        }
+    }
+
+    fn mocks(steps: impl IntoIterator<Item = Step>) -> (MockSource, MockSink, Log) {
+        let flushes = Arc::new(watch::channel(0).0);
+        let log = Log::default();
+        let source = MockSource {
+            steps: steps.into_iter().collect(),
+            committed: Vec::new(),
+            flushes: flushes.clone(),
+            log: log.clone(),
+        };
+        let sink = MockSink {
+            pushed: BTreeMap::new(),
+            completed: flushes.subscribe(),
+            flushes,
+            log: log.clone(),
+        };
+        (source, sink, log)
+    }
+
+    fn record(topic: &str, partition: i32, offset: i64, payload: Value) -> Step {
+        Step::Poll(Polled::Record(Record {
+            topic: topic.to_owned(),
+            partition,
+            offset,
+            payload: Some(serde_json::to_vec(&payload).unwrap()),
+        }))
+    }
+
+    fn offsets(offsets: &[(&str, i32, i64)]) -> BTreeMap<(String, i32), i64> {
+        offsets
+            .iter()
+            .map(|(topic, partition, offset)| ((topic.to_string(), *partition), *offset))
+            .collect()
+    }
+
+    fn cli(topics: &[&str], pattern: Option<&str>, streams: &[&str], template: &str) -> Cli {
+        Cli {
+            kafka_brokers: Some("localhost:9092".to_owned()),
+            kafka_group_id: "parseable".to_owned(),
+            kafka_topics: topics.iter().map(|topic| topic.to_string()).collect(),
+            kafka_topic_pattern: pattern.map(str::to_owned),
+            kafka_topic_streams: streams.iter().map(|stream| stream.to_string()).collect(),
+            kafka_stream_template: template.to_owned(),
+            kafka_commit_strategy: "flush".to_owned(),
+            kafka_security_protocol: "plaintext".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pattern_subscription_is_anchored() {
+        let subscription = Subscription::new(&[], Some("app-.*")).unwrap();
+        assert!(subscription.contains("app-orders"));
+        assert!(!subscription.contains("legacy-app-orders"));
+        assert_eq!(subscription.client_topics(), ["^app-.*"]);
+
+        let subscription = Subscription::new(&["audit".to_owned()], Some("^app-.*")).unwrap();
+        assert!(subscription.contains("audit"));
+        assert_eq!(subscription.client_topics(), ["audit", "^app-.*"]);
+    }
+
+    #[test]
+    fn template_maps_topics_to_valid_streams() {
+        let mut mapping =
+            TopicMapping::new(&["app-billing=billing".to_owned()], "kafka-{topic}").unwrap();
+        assert_eq!(mapping.stream("app-orders").unwrap(), "kafkaapporders");
+        assert_eq!(mapping.stream("App.Users").unwrap(), "kafkaappusers");
+        assert_eq!(mapping.stream("app-billing").unwrap(), "billing");
+        assert!(matches!(
+            mapping.stream("app.orders"),
+            Err(KafkaError::Overlap { first, second, stream })
+                if first == "app-orders" && second == "app.orders" && stream == "kafkaapporders"
+        ));
+        // a topic keeps its stream after another was refused it
+        assert_eq!(mapping.stream("app-orders").unwrap(), "kafkaapporders");
+    }
+
+    #[test]
+    fn invalid_configs_fail() {
+        let invalid = [
+            (cli(&[], None, &[], "{topic}"), "neither topics"),
+            (
+                cli(&[], Some("app-(.*"), &[], "{topic}"),
+                "not a valid regex",
+            ),
+            (cli(&[], Some("app-.*"), &[], "kafka"), "has no {topic}"),
+            (cli(&["a"], None, &["a"], "{topic}"), "not of the form"),
+            (
+                cli(&["a"], None, &["a=x", "a=y"], "{topic}"),
+                "both x and y",
+            ),
+            (
+                cli(&["a"], Some("app-.*"), &["b=x"], "{topic}"),
+                "neither in the topics",
+            ),
+            (
+                cli(&["a", "b"], None, &["a=x", "b=x"], "{topic}"),
+                "both go to stream x",
+            ),
+            (
+                cli(&["x", "y"], None, &["y=x"], "{topic}"),
+                "both go to stream x",
+            ),
+            (cli(&["1"], None, &[], "{topic}"), "not a valid stream name"),
+            (
+                Cli {
+                    kafka_commit_strategy: "never".to_owned(),
+                    ..cli(&["a"], None, &[], "{topic}")
+                },
+                "not one of flush",
+            ),
+        ];
+        for (cli, message) in invalid {
+            let err = KafkaConfig::from_cli(&cli).unwrap_err().to_string();
+            assert!(err.contains(message), "{err} should contain {message}");
+        }
+
+        assert!(KafkaConfig::from_cli(&Cli::default()).unwrap().is_none());
+        assert!(
+            KafkaConfig::from_cli(&cli(&["a"], Some("app-.*"), &["app-b=b"], "k{topic}"))
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[actix_web::test]
+    async fn pattern_topics_go_to_their_streams() {
+        let config = KafkaConfig::from_cli(&cli(
+            &[],
+            Some("app-.*"),
+            &["app-billing=billing"],
+            "kafka-{topic}",
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(config.commit, CommitStrategy::Flush);
+        let mut mapping = config.mapping;
+        let (mut source, mut sink, _) = mocks([
+            record("app-orders", 0, 0, json!({"id": 1})),
+            record("app-billing", 0, 7, json!([{"amount": 2}, {"amount": 3}])),
+            record("app-orders", 1, 3, json!({"id": 2})),
+            record("app-orders", 0, 1, json!({"id": 3})),
+        ]);
+
+        consume(&mut source, &mut sink, &mut mapping, config.commit).await;
+
+        assert_eq!(
+            sink.pushed,
+            BTreeMap::from([
+                (
+                    "billing".to_owned(),
+                    vec![json!({"amount": 2}), json!({"amount": 3})]
+                ),
+                (
+                    "kafkaapporders".to_owned(),
+                    vec![json!({"id": 1}), json!({"id": 3}), json!({"id": 2})]
+                ),
+            ])
+        );
+        assert_eq!(
+            source.committed,
+            [offsets(&[
+                ("app-billing", 0, 8),
+                ("app-orders", 0, 2),
+                ("app-orders", 1, 4)
+            ])]
+        );
+    }
+
+    #[actix_web::test]
+    async fn revoked_records_are_left_to_the_next_owner() {
+        let mut mapping = TopicMapping::new(&[], "{topic}").unwrap();
+        let (mut source, mut sink, _) = mocks([
+            record("logs", 0, 0, json!({"n": 0})),
+            record("logs", 1, 0, json!({"n": 1})),
+            record("logs", 0, 1, json!({"n": 2})),
+            Step::Poll(Polled::Revoked(vec![("logs".to_owned(), 0)])),
+            // the partition comes back and is read from the committed offset
+            record("logs", 0, 0, json!({"n": 0})),
+            record("logs", 0, 1, json!({"n": 2})),
+            Step::Idle,
+            // ingested but not flushed yet, it isn't committed either
+            record("logs", 2, 5, json!({"n": 3})),
+            Step::Idle,
+            Step::Poll(Polled::Revoked(vec![("logs".to_owned(), 2)])),
+        ]);
+
+        consume(&mut source, &mut sink, &mut mapping, CommitStrategy::Flush).await;
+
+        assert_eq!(
+            sink.pushed["logs"],
+            [
+                json!({"n": 0}),
+                json!({"n": 2}),
+                json!({"n": 1}),
+                json!({"n": 3})
+            ]
+        );
+        assert_eq!(
+            source.committed,
+            [offsets(&[("logs", 0, 2), ("logs", 1, 1)])]
+        );
+    }
+
+    #[actix_web::test]
+    async fn records_are_committed_once_flushed_from_staging() {
+        let mut mapping = TopicMapping::new(&[], "{topic}").unwrap();
+        let (mut source, mut sink, log) = mocks([
+            // a flush before the records were ingested doesn't have them
+            record("logs", 0, 0, json!({"n": 0})),
+            Step::Flush,
+            record("logs", 0, 1, json!({"n": 1})),
+            Step::Idle,
+            Step::Flush,
+            Step::Idle,
+            record("logs", 0, 2, json!({"n": 2})),
+            record("logs", 1, 0, json!({"n": 3})),
+            Step::Idle,
+            record("logs", 1, 1, json!({"n": 4})),
+            Step::Idle,
+            Step::Flush,
+            Step::Idle,
+            record("logs", 1, 2, json!({"n": 5})),
+        ]);
+
+        consume(&mut source, &mut sink, &mut mapping, CommitStrategy::Flush).await;
+
+        assert_eq!(sink.pushed["logs"].len(), 6);
+        // stopping flushes and commits what was consumed since
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                Event::Flushed(1),
+                Event::Flushed(2),
+                Event::Committed(offsets(&[("logs", 0, 2)])),
+                Event::Flushed(3),
+                Event::Committed(offsets(&[("logs", 0, 3), ("logs", 1, 2)])),
+                Event::Flushed(4),
+                Event::Committed(offsets(&[("logs", 1, 3)])),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn records_and_interval_strategies_commit_without_flushes() {
+        let steps = || {
+            [
+                record("logs", 0, 0, json!({"n": 0})),
+                Step::Idle,
+                Step::Idle,
+                record("logs", 0, 1, json!({"n": 1})),
+                record("logs", 0, 2, json!({"n": 2})),
+                Step::Idle,
+                Step::Idle,
+                record("logs", 0, 3, json!({"n": 3})),
+            ]
+        };
+
+        let mut mapping = TopicMapping::new(&[], "{topic}").unwrap();
+        let (mut source, mut sink, log) = mocks(steps());
+        consume(
+            &mut source,
+            &mut sink,
+            &mut mapping,
+            CommitStrategy::Records(2),
+        )
+        .await;
+        // the first record alone is too few
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                Event::Committed(offsets(&[("logs", 0, 3)])),
+                Event::Flushed(1),
+                Event::Committed(offsets(&[("logs", 0, 4)])),
+            ]
+        );
+
+        let (mut source, mut sink, log) = mocks(steps());
+        consume(
+            &mut source,
+            &mut sink,
+            &mut mapping,
+            CommitStrategy::Interval(Duration::from_millis(100)),
+        )
+        .await;
+        // each ingested batch is committed before the next, while idle
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                Event::Committed(offsets(&[("logs", 0, 1)])),
+                Event::Committed(offsets(&[("logs", 0, 3)])),
+                Event::Flushed(1),
+                Event::Committed(offsets(&[("logs", 0, 4)])),
+            ]
+        );
+    }
+}
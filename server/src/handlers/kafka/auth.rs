@@ -0,0 +1,640 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use rdkafka::{client::OAuthToken, config::ClientConfig};
+use serde::Deserialize;
+use url::Url;
+
+use super::KafkaError;
+use crate::cli::Cli;
+
+/// Share of a token's lifetime after which a new one is fetched
+const REFRESH_AFTER: f64 = 0.5;
+
+/// How long to wait before fetching a token again after failing to
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Protocol the connector talks to the brokers over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityProtocol {
+    Plaintext,
+    Ssl,
+    SaslPlaintext,
+    SaslSsl,
+}
+
+impl SecurityProtocol {
+    fn parse(protocol: &str) -> Result<Self, KafkaError> {
+        match protocol {
+            "plaintext" => Ok(Self::Plaintext),
+            "ssl" => Ok(Self::Ssl),
+            "sasl_plaintext" => Ok(Self::SaslPlaintext),
+            "sasl_ssl" => Ok(Self::SaslSsl),
+            _ => Err(KafkaError::Auth(format!(
+                "unknown security protocol {protocol}"
+            ))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Plaintext => "plaintext",
+            Self::Ssl => "ssl",
+            Self::SaslPlaintext => "sasl_plaintext",
+            Self::SaslSsl => "sasl_ssl",
+        }
+    }
+
+    fn uses_tls(&self) -> bool {
+        matches!(self, Self::Ssl | Self::SaslSsl)
+    }
+
+    fn uses_sasl(&self) -> bool {
+        matches!(self, Self::SaslPlaintext | Self::SaslSsl)
+    }
+}
+
+/// SASL mechanisms authenticating with a username and password
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordMechanism {
+    Plain,
+    ScramSha256,
+    ScramSha512,
+}
+
+impl PasswordMechanism {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Plain => "PLAIN",
+            Self::ScramSha256 => "SCRAM-SHA-256",
+            Self::ScramSha512 => "SCRAM-SHA-512",
+        }
+    }
+}
+
+/// How the connector gets OAUTHBEARER tokens, with the client credentials grant
+#[derive(Debug, Clone)]
+pub struct OAuthConfig {
+    pub token_endpoint: Url,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scope: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Sasl {
+    Password {
+        mechanism: PasswordMechanism,
+        username: String,
+        password: String,
+    },
+    OAuthBearer(OAuthConfig),
+}
+
+/// Client certificate and CA the connector uses over TLS
+#[derive(Debug, Clone, Default)]
+pub struct Tls {
+    pub ca_path: Option<PathBuf>,
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    pub key_password: Option<String>,
+}
+
+/// How the connector authenticates to the brokers
+#[derive(Debug, Clone)]
+pub struct KafkaAuth {
+    pub protocol: SecurityProtocol,
+    pub sasl: Option<Sasl>,
+    pub tls: Tls,
+}
+
+impl KafkaAuth {
+    /// The authentication settings of `cli`, settings that don't go together
+    /// or that the protocol doesn't use are rejected rather than ignored
+    pub fn from_cli(cli: &Cli) -> Result<Self, KafkaError> {
+        let protocol = SecurityProtocol::parse(&cli.kafka_security_protocol)?;
+        let invalid = |reason: &str| Err(KafkaError::Auth(reason.to_owned()));
+
+        let password = (
+            cli.kafka_sasl_username.clone(),
+            cli.kafka_sasl_password.clone(),
+        );
+        let oauth = (
+            cli.kafka_oauth_token_endpoint.clone(),
+            cli.kafka_oauth_client_id.clone(),
+            cli.kafka_oauth_client_secret.clone(),
+        );
+        let has_password = password.0.is_some() || password.1.is_some();
+        let has_oauth = oauth.0.is_some()
+            || oauth.1.is_some()
+            || oauth.2.is_some()
+            || cli.kafka_oauth_scope.is_some();
+        let password_mechanism = match cli.kafka_sasl_mechanism.as_deref() {
+            None => None,
+            Some("PLAIN") => Some(PasswordMechanism::Plain),
+            Some("SCRAM-SHA-256") => Some(PasswordMechanism::ScramSha256),
+            Some("SCRAM-SHA-512") => Some(PasswordMechanism::ScramSha512),
+            Some("OAUTHBEARER") => None,
+            Some(mechanism) => return invalid(&format!("unknown SASL mechanism {mechanism}")),
+        };
+
+        let sasl = match (cli.kafka_sasl_mechanism.as_deref(), password_mechanism) {
+            (None, _) if protocol.uses_sasl() => {
+                return invalid("a SASL security protocol needs a SASL mechanism")
+            }
+            (None, _) if has_password || has_oauth => {
+                return invalid("SASL credentials are set without a SASL mechanism")
+            }
+            (None, _) => None,
+            (Some(_), _) if !protocol.uses_sasl() => {
+                return invalid(
+                    "a SASL mechanism needs the sasl_plaintext or sasl_ssl security protocol",
+                )
+            }
+            (Some(_), Some(mechanism)) => {
+                if has_oauth {
+                    return invalid(&format!(
+                        "OAuth settings can't be used with the {} SASL mechanism",
+                        mechanism.as_str()
+                    ));
+                }
+                let (Some(username), Some(password)) = password else {
+                    return invalid(&format!(
+                        "the {} SASL mechanism needs a username and a password",
+                        mechanism.as_str()
+                    ));
+                };
+                Some(Sasl::Password {
+                    mechanism,
+                    username,
+                    password,
+                })
+            }
+            (Some(_), None) => {
+                if has_password {
+                    return invalid(
+                        "a SASL username and password can't be used with the OAUTHBEARER SASL mechanism",
+                    );
+                }
+                let (Some(token_endpoint), Some(client_id), Some(client_secret)) = oauth else {
+                    return invalid(
+                        "the OAUTHBEARER SASL mechanism needs a token endpoint, a client id and a client secret",
+                    );
+                };
+                Some(Sasl::OAuthBearer(OAuthConfig {
+                    token_endpoint,
+                    client_id,
+                    client_secret,
+                    scope: cli.kafka_oauth_scope.clone(),
+                }))
+            }
+        };
+
+        let tls = Tls {
+            ca_path: cli.kafka_tls_ca_path.clone(),
+            cert_path: cli.kafka_tls_cert_path.clone(),
+            key_path: cli.kafka_tls_key_path.clone(),
+            key_password: cli.kafka_tls_key_password.clone(),
+        };
+        let has_tls = tls.ca_path.is_some()
+            || tls.cert_path.is_some()
+            || tls.key_path.is_some()
+            || tls.key_password.is_some();
+        if has_tls && !protocol.uses_tls() {
+            return invalid("TLS settings need the ssl or sasl_ssl security protocol");
+        }
+        if tls.cert_path.is_some() != tls.key_path.is_some() {
+            return invalid("a TLS client certificate and its key are set together");
+        }
+        if tls.key_password.is_some() && tls.key_path.is_none() {
+            return invalid("a TLS key password is set without a TLS key");
+        }
+
+        Ok(Self {
+            protocol,
+            sasl,
+            tls,
+        })
+    }
+
+    /// Sets the client properties of these settings on `config`
+    pub fn apply(&self, config: &mut ClientConfig) {
+        config.set("security.protocol", self.protocol.as_str());
+        match &self.sasl {
+            Some(Sasl::Password {
+                mechanism,
+                username,
+                password,
+            }) => {
+                config
+                    .set("sasl.mechanisms", mechanism.as_str())
+                    .set("sasl.username", username)
+                    .set("sasl.password", password);
+            }
+            // tokens come from the client context
+            Some(Sasl::OAuthBearer(_)) => {
+                config.set("sasl.mechanisms", "OAUTHBEARER");
+            }
+            None => {}
+        }
+        let paths = [
+            ("ssl.ca.location", &self.tls.ca_path),
+            ("ssl.certificate.location", &self.tls.cert_path),
+            ("ssl.key.location", &self.tls.key_path),
+        ];
+        for (key, path) in paths
+            .into_iter()
+            .filter_map(|(key, path)| Some((key, path.as_ref()?)))
+        {
+            config.set(key, path.to_string_lossy());
+        }
+        if let Some(password) = &self.tls.key_password {
+            config.set("ssl.key.password", password);
+        }
+    }
+
+    pub fn oauth(&self) -> Option<&OAuthConfig> {
+        match &self.sasl {
+            Some(Sasl::OAuthBearer(oauth)) => Some(oauth),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TokenError {
+    #[error("{0}")]
+    Request(#[from] reqwest::Error),
+    #[error("token endpoint answered {status}: {body}")]
+    Status {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+}
+
+// the fields of a token response the connector uses
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+/// An OAUTHBEARER token and when it expires
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub value: String,
+    pub fetched_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Token {
+    /// When to fetch the next token, well before this one expires
+    pub fn refresh_at(&self) -> DateTime<Utc> {
+        let lifetime = self.expires_at - self.fetched_at;
+        let refresh_after = lifetime.num_milliseconds() as f64 * REFRESH_AFTER;
+        self.fetched_at + chrono::Duration::milliseconds(refresh_after as i64)
+    }
+}
+
+/// Fetches OAUTHBEARER tokens from the token endpoint and keeps the latest one
+/// for the client to take whenever it needs a token
+#[derive(Debug)]
+pub struct TokenProvider {
+    config: OAuthConfig,
+    client: reqwest::Client,
+    current: RwLock<Option<Token>>,
+}
+
+impl TokenProvider {
+    pub fn new(config: OAuthConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+            current: RwLock::new(None),
+        }
+    }
+
+    /// Fetches a new token, which is then the current one
+    pub async fn fetch(&self) -> Result<Token, TokenError> {
+        let mut form = vec![
+            ("grant_type", "client_credentials"),
+            ("client_id", self.config.client_id.as_str()),
+            ("client_secret", self.config.client_secret.as_str()),
+        ];
+        if let Some(scope) = &self.config.scope {
+            form.push(("scope", scope.as_str()));
+        }
+        let fetched_at = Utc::now();
+        let response = self
+            .client
+            .post(self.config.token_endpoint.clone())
+            .form(&form)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(TokenError::Status { status, body });
+        }
+        let response: TokenResponse = response.json().await?;
+        let token = Token {
+            value: response.access_token,
+            fetched_at,
+            expires_at: fetched_at + chrono::Duration::seconds(response.expires_in as i64),
+        };
+        *self.current.write().expect("token lock is not poisoned") = Some(token.clone());
+        Ok(token)
+    }
+
+    pub fn current(&self) -> Option<Token> {
+        self.current
+            .read()
+            .expect("token lock is not poisoned")
+            .clone()
+    }
+
+    /// The current token as the client takes it
+    pub fn oauth_token(&self) -> Result<OAuthToken, String> {
+        let token = self
+            .current()
+            .filter(|token| token.expires_at > Utc::now())
+            .ok_or_else(|| "no unexpired OAuth token was fetched".to_owned())?;
+        Ok(OAuthToken {
+            token: token.value,
+            principal_name: self.config.client_id.clone(),
+            lifetime_ms: token.expires_at.timestamp_millis(),
+        })
+    }
+
+    /// Keeps fetching tokens before the current one expires, failed fetches
+    /// are retried while the current token is still valid. The client takes the
+    /// new token once librdkafka asks for one, without reconnecting
+    pub async fn refresh(self: Arc<Self>) {
+        let mut next = self.current().map(|token| token.refresh_at());
+        loop {
+            if let Some(next) = next {
+                let wait = (next - Utc::now()).to_std().unwrap_or_default();
+                tokio::time::sleep(wait).await;
+            }
+            next = match self.fetch().await {
+                Ok(token) => Some(token.refresh_at()),
+                Err(err) => {
+                    log::warn!("Failed to refresh the Kafka OAuth token: {err}");
+                    Some(Utc::now() + chrono::Duration::from_std(RETRY_INTERVAL).unwrap())
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc, time::Duration};
+
+    use chrono::Utc;
+    use rdkafka::config::ClientConfig;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    use super::{KafkaAuth, OAuthConfig, SecurityProtocol, TokenProvider};
+    use crate::cli::Cli;
+
+    fn cli(protocol: &str, mechanism: Option<&str>) -> Cli {
+        Cli {
+            kafka_security_protocol: protocol.to_owned(),
+            kafka_sasl_mechanism: mechanism.map(str::to_owned),
+            ..Default::default()
+        }
+    }
+
+    fn scram() -> Cli {
+        Cli {
+            kafka_sasl_username: Some("parseable".to_owned()),
+            kafka_sasl_password: Some("secret".to_owned()),
+            ..cli("sasl_ssl", Some("SCRAM-SHA-512"))
+        }
+    }
+
+    fn oauth() -> Cli {
+        Cli {
+            kafka_oauth_token_endpoint: Some("https://idp.example.com/token".parse().unwrap()),
+            kafka_oauth_client_id: Some("parseable".to_owned()),
+            kafka_oauth_client_secret: Some("secret".to_owned()),
+            ..cli("sasl_ssl", Some("OAUTHBEARER"))
+        }
+    }
+
+    #[test]
+    fn valid_combinations() {
+        let mut config = ClientConfig::new();
+        let auth = KafkaAuth::from_cli(&scram()).unwrap();
+        auth.apply(&mut config);
+        assert_eq!(config.get("security.protocol"), Some("sasl_ssl"));
+        assert_eq!(config.get("sasl.mechanisms"), Some("SCRAM-SHA-512"));
+        assert_eq!(config.get("sasl.password"), Some("secret"));
+
+        let auth = KafkaAuth::from_cli(&oauth()).unwrap();
+        assert_eq!(auth.oauth().unwrap().client_id, "parseable");
+
+        let mtls = Cli {
+            kafka_tls_ca_path: Some(PathBuf::from("/etc/kafka/ca.pem")),
+            kafka_tls_cert_path: Some(PathBuf::from("/etc/kafka/client.pem")),
+            kafka_tls_key_path: Some(PathBuf::from("/etc/kafka/client.key")),
+            ..cli("ssl", None)
+        };
+        let mut config = ClientConfig::new();
+        let auth = KafkaAuth::from_cli(&mtls).unwrap();
+        auth.apply(&mut config);
+        assert_eq!(auth.protocol, SecurityProtocol::Ssl);
+        assert_eq!(
+            config.get("ssl.key.location"),
+            Some("/etc/kafka/client.key")
+        );
+        assert_eq!(config.get("sasl.mechanisms"), None);
+
+        assert!(KafkaAuth::from_cli(&cli("plaintext", None))
+            .unwrap()
+            .sasl
+            .is_none());
+    }
+
+    #[test]
+    fn invalid_combinations_fail() {
+        let invalid = [
+            (cli("sasl_ssl", None), "needs a SASL mechanism"),
+            (
+                cli("ssl", Some("PLAIN")),
+                "needs the sasl_plaintext or sasl_ssl",
+            ),
+            (
+                cli("sasl_plaintext", Some("PLAIN")),
+                "needs a username and a password",
+            ),
+            (
+                cli("sasl_ssl", Some("OAUTHBEARER")),
+                "needs a token endpoint",
+            ),
+            (
+                Cli {
+                    kafka_oauth_client_id: Some("parseable".to_owned()),
+                    ..scram()
+                },
+                "OAuth settings can't be used",
+            ),
+            (
+                Cli {
+                    kafka_sasl_username: Some("parseable".to_owned()),
+                    ..oauth()
+                },
+                "can't be used with the OAUTHBEARER",
+            ),
+            (
+                Cli {
+                    kafka_sasl_password: Some("secret".to_owned()),
+                    ..cli("plaintext", None)
+                },
+                "without a SASL mechanism",
+            ),
+            (
+                Cli {
+                    kafka_security_protocol: "sasl_plaintext".to_owned(),
+                    kafka_tls_ca_path: Some(PathBuf::from("/etc/kafka/ca.pem")),
+                    ..scram()
+                },
+                "need the ssl or sasl_ssl",
+            ),
+            (
+                Cli {
+                    kafka_tls_cert_path: Some(PathBuf::from("/etc/kafka/client.pem")),
+                    ..cli("ssl", None)
+                },
+                "set together",
+            ),
+            (
+                Cli {
+                    kafka_tls_key_password: Some("secret".to_owned()),
+                    ..cli("ssl", None)
+                },
+                "without a TLS key",
+            ),
+        ];
+        for (cli, message) in invalid {
+            let err = KafkaAuth::from_cli(&cli).unwrap_err().to_string();
+            assert!(err.contains(message), "{err} should contain {message}");
+        }
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        let config = serde_json::to_string(&Cli {
+            kafka_tls_key_password: Some("tls-secret".to_owned()),
+            ..oauth()
+        })
+        .unwrap();
+        assert!(!config.contains("\"secret\""));
+        assert!(!config.contains("tls-secret"));
+        assert!(config.contains("https://idp.example.com/token"));
+    }
+
+    // answers each token request with the next of `tokens`, sending the
+    // request bodies it got
+    async fn token_endpoint(tokens: Vec<(&'static str, u64)>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel(tokens.len());
+        tokio::spawn(async move {
+            for (token, expires_in) in tokens {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = String::new();
+                let mut buf = [0; 4096];
+                let body = loop {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    request.push_str(std::str::from_utf8(&buf[..read]).unwrap());
+                    let Some((head, body)) = request.split_once("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length: usize = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length: "))
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                    if body.len() >= length {
+                        break body.to_owned();
+                    }
+                };
+                tx.send(body).await.unwrap();
+                let response = format!(
+                    r#"{{"access_token":"{token}","token_type":"Bearer","expires_in":{expires_in}}}"#
+                );
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{response}",
+                    response.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    #[actix_web::test]
+    async fn tokens_are_refreshed_before_they_expire() {
+        let (url, mut requests) = token_endpoint(vec![("first", 2), ("second", 3600)]).await;
+        let provider = Arc::new(TokenProvider::new(OAuthConfig {
+            token_endpoint: url.parse().unwrap(),
+            client_id: "parseable".to_owned(),
+            client_secret: "secret".to_owned(),
+            scope: Some("kafka".to_owned()),
+        }));
+
+        let token = provider.fetch().await.unwrap();
+        let body = requests.recv().await.unwrap();
+        assert!(body.contains("grant_type=client_credentials"));
+        assert!(body.contains("client_secret=secret"));
+        assert!(body.contains("scope=kafka"));
+        assert_eq!(token.value, "first");
+        assert!(token.refresh_at() < token.expires_at);
+        let oauth_token = provider.oauth_token().unwrap();
+        assert_eq!(oauth_token.token, "first");
+        assert_eq!(oauth_token.principal_name, "parseable");
+        assert_eq!(oauth_token.lifetime_ms, token.expires_at.timestamp_millis());
+
+        // the first token lives two seconds, the second one is fetched after one
+        tokio::spawn(provider.clone().refresh());
+        tokio::time::timeout(Duration::from_secs(5), requests.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let refreshed = provider.current().unwrap();
+        assert_eq!(refreshed.value, "second");
+        assert!(refreshed.fetched_at < token.expires_at);
+        assert!(Utc::now() < token.expires_at);
+        assert_eq!(provider.oauth_token().unwrap().token, "second");
+    }
+}
@@ -23,11 +23,13 @@ use arrow_flight::encode::FlightDataEncoderBuilder;
 use arrow_flight::PollInfo;
 use cookie::Cookie;
 use futures::stream::BoxStream;
-use futures_util::{Future, StreamExt, TryFutureExt, TryStreamExt};
+use futures_util::{StreamExt, TryStreamExt};
+use http::HeaderValue;
 use http_auth_basic::Credentials;
 use rand::distributions::{Alphanumeric, DistString};
+use tokio::net::TcpListener;
 use tonic::metadata::MetadataMap;
-use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tonic::transport::Server;
 use tonic::{Request, Response, Status, Streaming};
 
 use arrow_flight::{
@@ -36,15 +38,16 @@ use arrow_flight::{
     HandshakeResponse, PutResult, SchemaResult, Ticket,
 };
 use tonic_web::GrpcWebLayer;
-use tower_http::cors::CorsLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
 
-use crate::livetail::{Message, LIVETAIL};
+use crate::livetail::{LiveTailFilter, Message, LIVETAIL};
 use crate::metadata::STREAM_INFO;
 use crate::option::CONFIG;
 use crate::rbac::map::SessionKey;
 use crate::rbac::{self, Users};
 use crate::utils;
 
+use super::http::modal::ssl_acceptor::{get_ssl_acceptor, grpc_incoming, spawn_reload};
 use super::SESSION_COOKIE_NAME;
 
 #[derive(Clone)]
@@ -119,9 +122,24 @@ impl FlightService for FlightServiceImpl {
             .schema(stream)
             .map_err(|err| Status::failed_precondition(err.to_string()))?;
 
+        // invalid filters are rejected here rather than once events arrive
+        let sql = extract_filter(&ticket).map_err(Status::invalid_argument)?;
+        let fields = extract_fields(&ticket).map_err(Status::invalid_argument)?;
+        let filter = match (sql, fields) {
+            (None, None) => None,
+            (sql, fields) => Some(
+                LiveTailFilter::new(sql, fields, schema.clone())
+                    .map_err(|err| Status::invalid_argument(err.to_string()))?,
+            ),
+        };
+        let schema = filter
+            .as_ref()
+            .map_or(schema, |filter| filter.output_schema());
+
         let rx = LIVETAIL.new_pipe(
             Alphanumeric.sample_string(&mut rand::thread_rng(), 32),
             stream.to_string(),
+            filter,
         );
 
         let adapter_schema = schema.clone();
@@ -131,6 +149,10 @@ impl FlightService for FlightServiceImpl {
                 log::warn!("livetail channel capacity is full.");
                 Ok(RecordBatch::new_empty(adapter_schema.clone()))
             }
+            Message::FilterDisabled(reason) => {
+                log::warn!("livetail filter disabled, sending all events. {reason}");
+                Ok(RecordBatch::new_empty(adapter_schema.clone()))
+            }
         });
 
         let rb_stream = FlightDataEncoderBuilder::new()
@@ -170,11 +192,11 @@ impl FlightService for FlightServiceImpl {
     }
 }
 
-pub fn server() -> impl Future<Output = Result<(), Box<dyn std::error::Error + Send>>> + Send {
+pub async fn server() -> Result<(), Box<dyn std::error::Error + Send>> {
     let mut addr: SocketAddr = CONFIG
         .parseable
-        .address
-        .parse()
+        .bind_addrs()
+        .map(|addrs| addrs[0])
         .expect("valid socket address");
     addr.set_port(CONFIG.parseable.grpc_port);
 
@@ -184,50 +206,36 @@ pub fn server() -> impl Future<Output = Result<(), Box<dyn std::error::Error + S
 
     let cors = cross_origin_config();
 
-    let identity = match (
-        &CONFIG.parseable.tls_cert_path,
-        &CONFIG.parseable.tls_key_path,
-    ) {
-        (Some(cert), Some(key)) => {
-            match (std::fs::read_to_string(cert), std::fs::read_to_string(key)) {
-                (Ok(cert_file), Ok(key_file)) => {
-                    let identity = Identity::from_pem(cert_file, key_file);
-                    Some(identity)
-                }
-                _ => None,
-            }
-        }
-        (_, _) => None,
-    };
-
-    let config = identity.map(|id| ServerTlsConfig::new().identity(id));
+    // the certificate is served through the resolver the HTTP server uses,
+    // so that it is reloaded along with it
+    let tls = get_ssl_acceptor(
+        CONFIG.parseable.tls_identity(),
+        CONFIG.parseable.tls_min_version,
+        &CONFIG.parseable.tls_cipher_suites,
+    )?;
 
     // rust is treating closures as different types
     let err_map_fn = |err| Box::new(err) as Box<dyn std::error::Error + Send>;
 
-    // match on config to decide if we want to use tls or not
-    match config {
-        Some(config) => {
-            let server = match Server::builder().tls_config(config) {
-                Ok(server) => server,
-                Err(_) => Server::builder(),
-            };
-
-            server
-                .accept_http1(true)
-                .layer(cors)
-                .layer(GrpcWebLayer::new())
-                .add_service(svc)
-                .serve(addr)
+    let router = Server::builder()
+        .accept_http1(true)
+        .layer(cors)
+        .layer(GrpcWebLayer::new())
+        .add_service(svc);
+
+    // match on tls to decide if we want to use tls or not
+    match tls {
+        Some((config, resolver)) => {
+            spawn_reload(resolver);
+            let listener = TcpListener::bind(addr)
+                .await
+                .map_err(|err| Box::new(err) as Box<dyn std::error::Error + Send>)?;
+            router
+                .serve_with_incoming(grpc_incoming(listener, config))
+                .await
                 .map_err(err_map_fn)
         }
-        None => Server::builder()
-            .accept_http1(true)
-            .layer(cors)
-            .layer(GrpcWebLayer::new())
-            .add_service(svc)
-            .serve(addr)
-            .map_err(err_map_fn),
+        None => router.serve(addr).await.map_err(err_map_fn),
     }
 }
 
@@ -240,6 +248,27 @@ pub fn extract_stream(body: &serde_json::Value) -> Result<&str, Status> {
         .ok_or(Status::invalid_argument("stream key value is invalid"))
 }
 
+/// Optional WHERE expression applied to the events, e.g. `{"filter": "status >= 500"}`
+pub fn extract_filter(body: &serde_json::Value) -> Result<Option<String>, &'static str> {
+    match body.get("filter") {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(filter) => filter
+            .as_str()
+            .map(|filter| Some(filter.to_owned()))
+            .ok_or("filter key value is invalid"),
+    }
+}
+
+/// Optional list of the fields to send, e.g. `{"fields": ["status", "host"]}`
+pub fn extract_fields(body: &serde_json::Value) -> Result<Option<Vec<String>>, &'static str> {
+    match body.get("fields") {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(fields) => serde_json::from_value(fields.clone())
+            .map(Some)
+            .map_err(|_| "fields key value is invalid"),
+    }
+}
+
 pub fn extract_session_key(headers: &MetadataMap) -> Result<SessionKey, Status> {
     // Extract username and password from the request using basic auth extractor.
     let basic = extract_basic_auth(headers).map(|creds| SessionKey::BasicAuth {
@@ -286,7 +315,33 @@ fn extract_cookie(header: &MetadataMap) -> Option<Cookie> {
         .find(|cookie| cookie.name() == SESSION_COOKIE_NAME)
 }
 
-#[inline(always)]
+/// CORS layer of the gRPC-web endpoints, allowing the same origins as the
+/// HTTP server does
 pub fn cross_origin_config() -> CorsLayer {
-    CorsLayer::very_permissive().allow_credentials(true)
+    if cfg!(feature = "debug") {
+        return CorsLayer::very_permissive();
+    }
+
+    cors_layer(CONFIG.parseable.cors, &CONFIG.parseable.cors_origins)
+}
+
+/// CORS layer for the given settings, the counterpart of the HTTP server's
+/// `cors_config`. Only listed origins are echoed back, with credentials, and
+/// other origins get no CORS headers. Without a list `enabled` toggles
+/// between allowing no origin and allowing all of them.
+pub(crate) fn cors_layer(enabled: bool, origins: &[String]) -> CorsLayer {
+    if !origins.is_empty() {
+        let origins = origins
+            .iter()
+            .filter_map(|origin| HeaderValue::from_str(origin).ok());
+        return CorsLayer::very_permissive()
+            .allow_origin(AllowOrigin::list(origins))
+            .allow_credentials(true);
+    }
+
+    if enabled {
+        CorsLayer::new()
+    } else {
+        CorsLayer::very_permissive()
+    }
 }
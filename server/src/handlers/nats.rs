@@ -0,0 +1,1164 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{collections::BTreeMap, fmt, sync::RwLock, time::Duration};
+
+use async_nats::{
+    jetstream::{
+        self,
+        consumer::{pull, AckPolicy, Consumer},
+        AckKind,
+    },
+    ConnectOptions, Event,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::time::sleep;
+
+use super::http::ingest::{create_stream_if_not_exists, push_labelled_logs, PostError};
+use crate::{
+    cli::Cli,
+    metadata::STREAM_INFO,
+    metrics::{
+        NATS_CONNECTED, NATS_MESSAGES_INGESTED, NATS_MESSAGES_REDELIVERED, NATS_MESSAGES_REJECTED,
+        NATS_RECONNECTS,
+    },
+    option::{Mode, CONFIG},
+    validator::{self, error::StreamNameValidationError},
+};
+
+/// How long a fetch waits for its first message
+const FETCH_EXPIRES: Duration = Duration::from_secs(5);
+
+/// Delay of the first retry of a failed connection, fetch or ingest
+const BACKOFF_BASE: Duration = Duration::from_millis(100);
+
+/// Longest delay between retries
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// Deliveries after which a message the streams keep failing is terminated
+const MAX_DELIVERIES: u64 = 10;
+
+#[derive(Debug, thiserror::Error)]
+pub enum NatsError {
+    #[error("NATS connector has no JetStream stream to consume")]
+    NoStream,
+    #[error("NATS connector has neither subject streams nor a default stream, every message would be dropped")]
+    NoMapping,
+    #[error("NATS subject stream {0:?} is not of the form subject=stream")]
+    MalformedMapping(String),
+    #[error(
+        "NATS subject {0:?} is not valid, tokens can't be empty and > may only be the last one"
+    )]
+    Subject(String),
+    #[error(
+        "NATS subject {subject} goes to {stream:?} which is not a valid stream name: {source}"
+    )]
+    InvalidStream {
+        subject: String,
+        stream: String,
+        #[source]
+        source: StreamNameValidationError,
+    },
+    #[error("Invalid NATS authentication settings, {0}")]
+    Auth(String),
+    #[error("{0}")]
+    Client(String),
+}
+
+// the errors of the client are of many types, only their message is kept
+fn client_error(err: impl fmt::Display) -> NatsError {
+    NatsError::Client(err.to_string())
+}
+
+/// Streams the messages of a subject go to. Subjects match as NATS has them
+/// match, `*` stands for one token and a trailing `>` for one or more
+#[derive(Debug, Clone)]
+pub struct SubjectMapping {
+    routes: Vec<(String, String)>,
+    default: Option<String>,
+}
+
+impl SubjectMapping {
+    /// Mapping of `entries` given as subject=stream, in the order they're
+    /// tried, with subjects no entry matches going to `default`
+    pub fn new(entries: &[String], default: Option<&str>) -> Result<Self, NatsError> {
+        let mut routes = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let (subject, stream) = entry
+                .split_once('=')
+                .map(|(subject, stream)| (subject.trim(), stream.trim()))
+                .filter(|(subject, stream)| !subject.is_empty() && !stream.is_empty())
+                .ok_or_else(|| NatsError::MalformedMapping(entry.clone()))?;
+            if !is_valid_subject(subject) {
+                return Err(NatsError::Subject(subject.to_owned()));
+            }
+            validate_stream(subject, stream)?;
+            routes.push((subject.to_owned(), stream.to_owned()));
+        }
+        if let Some(stream) = default {
+            validate_stream(">", stream)?;
+        }
+        if routes.is_empty() && default.is_none() {
+            return Err(NatsError::NoMapping);
+        }
+        Ok(Self {
+            routes,
+            default: default.map(str::to_owned),
+        })
+    }
+
+    /// The stream messages of `subject` go to, none when nothing matches it
+    pub fn stream(&self, subject: &str) -> Option<&str> {
+        self.routes
+            .iter()
+            .find(|(pattern, _)| subject_matches(pattern, subject))
+            .map(|(_, stream)| stream.as_str())
+            .or(self.default.as_deref())
+    }
+}
+
+fn validate_stream(subject: &str, stream: &str) -> Result<(), NatsError> {
+    validator::stream_name(stream).map_err(|source| NatsError::InvalidStream {
+        subject: subject.to_owned(),
+        stream: stream.to_owned(),
+        source,
+    })
+}
+
+// tokens are separated by dots, wildcards take up a whole token
+fn is_valid_subject(subject: &str) -> bool {
+    let tokens: Vec<&str> = subject.split('.').collect();
+    tokens.iter().enumerate().all(|(i, token)| match *token {
+        "" => false,
+        "*" => true,
+        ">" => i == tokens.len() - 1,
+        token => !token.contains(['*', '>']),
+    })
+}
+
+fn subject_matches(pattern: &str, subject: &str) -> bool {
+    let mut subject = subject.split('.');
+    for token in pattern.split('.') {
+        match (token, subject.next()) {
+            (">", Some(_)) => return true,
+            ("*", Some(_)) => {}
+            (token, Some(next)) if token == next => {}
+            _ => return false,
+        }
+    }
+    subject.next().is_none()
+}
+
+/// Credentials the connector authenticates with
+#[derive(Clone, PartialEq, Eq)]
+pub enum NatsAuth {
+    None,
+    Token(String),
+    NKey(String),
+    UserPassword { user: String, password: String },
+}
+
+impl NatsAuth {
+    pub fn from_cli(cli: &Cli) -> Result<Self, NatsError> {
+        let given = [
+            cli.nats_token.is_some(),
+            cli.nats_nkey_seed.is_some(),
+            cli.nats_user.is_some() || cli.nats_password.is_some(),
+        ];
+        if given.into_iter().filter(|given| *given).count() > 1 {
+            return Err(NatsError::Auth(
+                "only one of a token, an nkey seed or a user and password may be set".to_owned(),
+            ));
+        }
+        match (
+            &cli.nats_token,
+            &cli.nats_nkey_seed,
+            &cli.nats_user,
+            &cli.nats_password,
+        ) {
+            (Some(token), _, _, _) => Ok(Self::Token(token.clone())),
+            (_, Some(seed), _, _) => Ok(Self::NKey(seed.clone())),
+            (_, _, Some(user), Some(password)) => Ok(Self::UserPassword {
+                user: user.clone(),
+                password: password.clone(),
+            }),
+            (_, _, Some(_), None) => Err(NatsError::Auth("the user has no password".to_owned())),
+            (_, _, None, Some(_)) => Err(NatsError::Auth("the password has no user".to_owned())),
+            (None, None, None, None) => Ok(Self::None),
+        }
+    }
+
+    fn apply(&self, options: ConnectOptions) -> ConnectOptions {
+        match self {
+            Self::None => options,
+            Self::Token(token) => options.token(token.clone()),
+            Self::NKey(seed) => options.nkey(seed.clone()),
+            Self::UserPassword { user, password } => {
+                options.user_and_password(user.clone(), password.clone())
+            }
+        }
+    }
+}
+
+// the secrets stay out of logs
+impl fmt::Debug for NatsAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "None"),
+            Self::Token(_) => write!(f, "Token"),
+            Self::NKey(_) => write!(f, "NKey"),
+            Self::UserPassword { user, .. } => write!(f, "UserPassword({user})"),
+        }
+    }
+}
+
+/// How many messages the connector holds at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flow {
+    /// Messages delivered but not acked yet at most, the server holds back
+    /// further messages until they are
+    pub max_in_flight: usize,
+    /// Messages fetched and ingested at once
+    pub batch_size: usize,
+}
+
+impl Flow {
+    // a batch is settled before the next fetch so it's all that's in flight
+    fn fetch_size(&self) -> usize {
+        self.batch_size.min(self.max_in_flight)
+    }
+}
+
+/// Settings of the NATS connector
+#[derive(Debug, Clone)]
+pub struct NatsConfig {
+    pub servers: String,
+    pub stream: String,
+    pub consumer: String,
+    pub subjects: Vec<String>,
+    pub mapping: SubjectMapping,
+    pub create_streams: bool,
+    pub flow: Flow,
+    pub ack_wait: Duration,
+    pub auth: NatsAuth,
+}
+
+impl NatsConfig {
+    /// The connector settings of `cli`, none when it sets no servers. Invalid
+    /// subjects and mappings are rejected here so that the server fails to
+    /// start over them
+    pub fn from_cli(cli: &Cli) -> Result<Option<Self>, NatsError> {
+        let Some(servers) = &cli.nats_servers else {
+            return Ok(None);
+        };
+        let stream = cli.nats_stream.clone().ok_or(NatsError::NoStream)?;
+        if let Some(subject) = cli.nats_subjects.iter().find(|s| !is_valid_subject(s)) {
+            return Err(NatsError::Subject(subject.clone()));
+        }
+        let mapping = SubjectMapping::new(
+            &cli.nats_subject_streams,
+            cli.nats_default_stream.as_deref(),
+        )?;
+
+        Ok(Some(Self {
+            servers: servers.clone(),
+            stream,
+            consumer: cli.nats_consumer.clone(),
+            subjects: cli.nats_subjects.clone(),
+            mapping,
+            create_streams: cli.nats_create_streams,
+            flow: Flow {
+                max_in_flight: cli.nats_max_in_flight,
+                batch_size: cli.nats_batch_size,
+            },
+            ack_wait: Duration::from_secs(cli.nats_ack_wait),
+            auth: NatsAuth::from_cli(cli)?,
+        }))
+    }
+}
+
+/// Health of the connector as the connectors endpoint reports it
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub servers: String,
+    pub stream: String,
+    pub consumer: String,
+    pub connected: bool,
+    /// When the connector last connected or lost its connection
+    pub since: DateTime<Utc>,
+    pub reconnects: u64,
+    pub last_error: Option<String>,
+}
+
+static STATUS: Lazy<RwLock<Option<Status>>> = Lazy::new(|| RwLock::new(None));
+
+/// Health of the connector, none when it isn't running
+pub fn status() -> Option<Status> {
+    STATUS.read().unwrap().clone()
+}
+
+fn update_status(update: impl FnOnce(&mut Status)) {
+    if let Some(status) = STATUS.write().unwrap().as_mut() {
+        update(status)
+    }
+}
+
+fn set_connected(connected: bool) {
+    NATS_CONNECTED.set(connected as i64);
+    update_status(|status| {
+        if status.connected == connected {
+            return;
+        }
+        if status.connected {
+            status.reconnects += 1;
+            NATS_RECONNECTS.inc();
+        }
+        status.connected = connected;
+        status.since = Utc::now();
+    });
+}
+
+fn set_error(err: &impl fmt::Display) {
+    let err = err.to_string();
+    update_status(|status| status.last_error = Some(err));
+}
+
+/// Delay before retry `attempts`, doubling up to [`BACKOFF_MAX`]
+pub fn backoff(attempts: usize) -> Duration {
+    BACKOFF_BASE
+        .saturating_mul(1u32 << attempts.min(16))
+        .min(BACKOFF_MAX)
+}
+
+/// A message as it was delivered
+pub trait Delivery: Send {
+    fn subject(&self) -> &str;
+
+    fn payload(&self) -> &[u8];
+
+    /// Times the message was delivered, more than once when it's redelivered
+    fn delivered(&self) -> u64;
+}
+
+/// What becomes of a delivered message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Its events are staged, it's never delivered again
+    Ack,
+    /// It's delivered again after the delay
+    Nak(Duration),
+    /// It can't ever be ingested, it's never delivered again
+    Term,
+}
+
+/// Where messages are fetched from
+#[async_trait]
+pub trait Source: Send {
+    type Message: Delivery;
+
+    /// Up to `max` messages, waiting a while for the first. None once the
+    /// source is closed
+    async fn fetch(&mut self, max: usize) -> Option<Result<Vec<Self::Message>, NatsError>>;
+
+    async fn settle(&mut self, message: Self::Message, outcome: Outcome) -> Result<(), NatsError>;
+}
+
+/// Where the events of a subject are ingested
+#[async_trait]
+pub trait Sink: Send {
+    async fn push(
+        &mut self,
+        stream_name: &str,
+        subject: &str,
+        events: Vec<Value>,
+    ) -> Result<(), PostError>;
+}
+
+/// Consumes the configured JetStream stream into the streams of its mapping
+/// until the server shuts down. Messages are only acked once their events are
+/// staged, those not acked by a crash are delivered again
+pub async fn server(config: NatsConfig) {
+    *STATUS.write().unwrap() = Some(Status {
+        servers: config.servers.clone(),
+        stream: config.stream.clone(),
+        consumer: config.consumer.clone(),
+        connected: false,
+        since: Utc::now(),
+        reconnects: 0,
+        last_error: None,
+    });
+    // the client reconnects by itself, backing off the same as the fetches
+    let options = config
+        .auth
+        .apply(ConnectOptions::new())
+        .name("parseable")
+        .retry_on_initial_connect()
+        .reconnect_delay_callback(backoff)
+        .event_callback(|event| async move { on_event(event) });
+    let client = match options.connect(config.servers.as_str()).await {
+        Ok(client) => client,
+        Err(err) => {
+            log::error!("NATS connector failed: {err}");
+            set_error(&err);
+            return;
+        }
+    };
+
+    let mut attempts = 0;
+    let consumer = loop {
+        match bind(client.clone(), &config).await {
+            Ok(consumer) => break consumer,
+            Err(err) => {
+                log::warn!(
+                    "NATS connector failed to bind consumer {} of stream {}: {err}",
+                    config.consumer,
+                    config.stream
+                );
+                set_error(&err);
+                sleep(backoff(attempts)).await;
+                attempts += 1;
+            }
+        }
+    };
+    log::info!(
+        "NATS connector consuming {} from {}",
+        config.stream,
+        config.servers
+    );
+    let mut source = JetStreamSource { consumer };
+    let mut sink = Streams {
+        create_streams: config.create_streams,
+    };
+    consume(&mut source, &mut sink, &config.mapping, config.flow).await;
+}
+
+fn on_event(event: Event) {
+    match event {
+        Event::Connected => {
+            log::info!("NATS connector connected");
+            set_connected(true);
+        }
+        Event::Disconnected => {
+            log::warn!("NATS connector lost its connection, reconnecting");
+            set_connected(false);
+        }
+        Event::ServerError(err) => {
+            log::warn!("NATS connector got a server error: {err}");
+            set_error(&err);
+        }
+        Event::ClientError(err) => {
+            log::warn!("NATS connector failed: {err}");
+            set_error(&err);
+        }
+        _ => {}
+    }
+}
+
+// the durable pull consumer of the stream, created when it doesn't exist. An
+// existing consumer keeps its settings
+async fn bind(
+    client: async_nats::Client,
+    config: &NatsConfig,
+) -> Result<Consumer<pull::Config>, NatsError> {
+    let stream = jetstream::new(client)
+        .get_stream(&config.stream)
+        .await
+        .map_err(client_error)?;
+    stream
+        .get_or_create_consumer(
+            &config.consumer,
+            pull::Config {
+                durable_name: Some(config.consumer.clone()),
+                filter_subjects: config.subjects.clone(),
+                ack_policy: AckPolicy::Explicit,
+                ack_wait: config.ack_wait,
+                max_ack_pending: config.flow.max_in_flight as i64,
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(client_error)
+}
+
+/// Ingests what `source` fetches into the streams of `mapping` until the
+/// source closes. A fetch only follows once every message of the one before
+/// is settled, so at most a batch is in flight.
+///
+/// Messages are acked once their events are staged. Those the streams fail
+/// are naked to be delivered again, backing off further with each delivery,
+/// and terminated after [`MAX_DELIVERIES`]. Messages that aren't JSON or that
+/// no stream is mapped to are terminated right away.
+pub async fn consume(
+    source: &mut impl Source,
+    sink: &mut impl Sink,
+    mapping: &SubjectMapping,
+    flow: Flow,
+) {
+    let mut attempts = 0;
+    while let Some(fetched) = source.fetch(flow.fetch_size()).await {
+        let messages = match fetched {
+            Ok(messages) => {
+                attempts = 0;
+                messages
+            }
+            Err(err) => {
+                log::warn!("NATS connector failed to fetch messages: {err}");
+                set_error(&err);
+                sleep(backoff(attempts)).await;
+                attempts += 1;
+                continue;
+            }
+        };
+
+        // messages of a subject are ingested together, in the order they came
+        let mut subjects: BTreeMap<String, Vec<_>> = BTreeMap::new();
+        for message in messages {
+            if message.delivered() > 1 {
+                NATS_MESSAGES_REDELIVERED
+                    .with_label_values(&[message.subject()])
+                    .inc();
+            }
+            subjects
+                .entry(message.subject().to_owned())
+                .or_default()
+                .push(message);
+        }
+        for (subject, messages) in subjects {
+            ingest_subject(&subject, messages, source, sink, mapping).await;
+        }
+    }
+}
+
+async fn ingest_subject<S: Source>(
+    subject: &str,
+    messages: Vec<S::Message>,
+    source: &mut S,
+    sink: &mut impl Sink,
+    mapping: &SubjectMapping,
+) {
+    let Some(stream_name) = mapping.stream(subject) else {
+        log::warn!(
+            "Terminating {} NATS messages of subject {subject}, no stream is mapped to it",
+            messages.len()
+        );
+        NATS_MESSAGES_REJECTED
+            .with_label_values(&[subject, ""])
+            .inc_by(messages.len() as u64);
+        for message in messages {
+            settle(source, message, Outcome::Term).await;
+        }
+        return;
+    };
+
+    let mut events = Vec::new();
+    let mut parsed = Vec::with_capacity(messages.len());
+    for message in messages {
+        match parse_events(message.payload()) {
+            Ok(values) => {
+                events.extend(values);
+                parsed.push(message);
+            }
+            Err(err) => {
+                log::warn!("Terminating a NATS message of subject {subject}: {err}");
+                NATS_MESSAGES_REJECTED
+                    .with_label_values(&[subject, stream_name])
+                    .inc();
+                settle(source, message, Outcome::Term).await;
+            }
+        }
+    }
+    if parsed.is_empty() {
+        return;
+    }
+
+    let pushed = if events.is_empty() {
+        Ok(())
+    } else {
+        sink.push(stream_name, subject, events).await
+    };
+    match pushed {
+        Ok(()) => {
+            NATS_MESSAGES_INGESTED
+                .with_label_values(&[subject, stream_name])
+                .inc_by(parsed.len() as u64);
+            for message in parsed {
+                settle(source, message, Outcome::Ack).await;
+            }
+        }
+        Err(err) => {
+            log::warn!(
+                "Failed to ingest {} NATS messages of subject {subject} into {stream_name}: {err}",
+                parsed.len()
+            );
+            for message in parsed {
+                let delivered = message.delivered();
+                let outcome = if delivered >= MAX_DELIVERIES {
+                    NATS_MESSAGES_REJECTED
+                        .with_label_values(&[subject, stream_name])
+                        .inc();
+                    Outcome::Term
+                } else {
+                    Outcome::Nak(backoff(delivered as usize))
+                };
+                settle(source, message, outcome).await;
+            }
+        }
+    }
+}
+
+// a message that fails to settle is delivered again once its ack wait is over
+async fn settle<S: Source>(source: &mut S, message: S::Message, outcome: Outcome) {
+    if let Err(err) = source.settle(message, outcome).await {
+        log::warn!("NATS connector failed to settle a message: {err}");
+        set_error(&err);
+    }
+}
+
+// the events of a payload read as HTTP ingest reads them, a JSON object or an
+// array of them, or NDJSON with an object per line and blank lines skipped
+fn parse_events(payload: &[u8]) -> Result<Vec<Value>, String> {
+    let values = match serde_json::from_slice::<Value>(payload) {
+        Ok(Value::Array(values)) => values,
+        Ok(value) => vec![value],
+        Err(_) => payload
+            .split(|byte| *byte == b'\n')
+            .enumerate()
+            .filter(|(_, line)| !line.iter().all(u8::is_ascii_whitespace))
+            .map(|(i, line)| {
+                serde_json::from_slice(line).map_err(|err| format!("line {}: {err}", i + 1))
+            })
+            .collect::<Result<_, _>>()?,
+    };
+    if !values.iter().all(Value::is_object) {
+        return Err("events must be JSON objects".to_owned());
+    }
+    Ok(values)
+}
+
+// the durable pull consumer
+struct JetStreamSource {
+    consumer: Consumer<pull::Config>,
+}
+
+#[async_trait]
+impl Source for JetStreamSource {
+    type Message = jetstream::Message;
+
+    async fn fetch(&mut self, max: usize) -> Option<Result<Vec<Self::Message>, NatsError>> {
+        let mut batch = match self
+            .consumer
+            .batch()
+            .max_messages(max)
+            .expires(FETCH_EXPIRES)
+            .messages()
+            .await
+        {
+            Ok(batch) => batch,
+            Err(err) => return Some(Err(client_error(err))),
+        };
+        let mut messages = Vec::new();
+        // messages of a batch that fails are delivered again once their ack wait is over
+        while let Some(message) = batch.next().await {
+            match message {
+                Ok(message) => messages.push(message),
+                Err(err) => return Some(Err(client_error(err))),
+            }
+        }
+        Some(Ok(messages))
+    }
+
+    async fn settle(&mut self, message: Self::Message, outcome: Outcome) -> Result<(), NatsError> {
+        let kind = match outcome {
+            Outcome::Ack => AckKind::Ack,
+            Outcome::Nak(delay) => AckKind::Nak(Some(delay)),
+            Outcome::Term => AckKind::Term,
+        };
+        message.ack_with(kind).await.map_err(client_error)
+    }
+}
+
+impl Delivery for jetstream::Message {
+    fn subject(&self) -> &str {
+        self.subject.as_str()
+    }
+
+    fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    fn delivered(&self) -> u64 {
+        self.info().map_or(1, |info| info.delivered as u64)
+    }
+}
+
+// the streams of the server
+struct Streams {
+    create_streams: bool,
+}
+
+#[async_trait]
+impl Sink for Streams {
+    async fn push(
+        &mut self,
+        stream_name: &str,
+        subject: &str,
+        events: Vec<Value>,
+    ) -> Result<(), PostError> {
+        // ingestors only load streams created elsewhere here
+        if self.create_streams || CONFIG.parseable.mode == Mode::Ingest {
+            create_stream_if_not_exists(stream_name, false).await?;
+        } else if !STREAM_INFO.stream_exists(stream_name) {
+            return Err(PostError::StreamNotFound(stream_name.to_owned()));
+        }
+        let body: Bytes = serde_json::to_vec(&events)?.into();
+        push_labelled_logs(
+            stream_name.to_owned(),
+            "",
+            &format!("nats_subject={subject}"),
+            body,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use serde_json::{json, Value};
+
+    use super::{
+        backoff, consume, parse_events, Delivery, Flow, NatsAuth, NatsConfig, NatsError, Outcome,
+        Sink, Source, SubjectMapping, MAX_DELIVERIES,
+    };
+    use crate::{cli::Cli, handlers::http::ingest::PostError};
+
+    const FLOW: Flow = Flow {
+        max_in_flight: 100,
+        batch_size: 10,
+    };
+
+    // what the connector did, in order
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Event {
+        Fetched(usize),
+        Pushed(String, Vec<Value>),
+        Settled(u64, Outcome),
+    }
+
+    type Log = Arc<Mutex<Vec<Event>>>;
+
+    #[derive(Debug)]
+    struct MockMessage {
+        id: u64,
+        subject: String,
+        payload: Vec<u8>,
+        delivered: u64,
+    }
+
+    impl Delivery for MockMessage {
+        fn subject(&self) -> &str {
+            &self.subject
+        }
+
+        fn payload(&self) -> &[u8] {
+            &self.payload
+        }
+
+        fn delivered(&self) -> u64 {
+            self.delivered
+        }
+    }
+
+    struct MockSource {
+        fetches: VecDeque<Result<Vec<MockMessage>, NatsError>>,
+        log: Log,
+    }
+
+    #[async_trait]
+    impl Source for MockSource {
+        type Message = MockMessage;
+
+        async fn fetch(&mut self, max: usize) -> Option<Result<Vec<MockMessage>, NatsError>> {
+            self.log.lock().unwrap().push(Event::Fetched(max));
+            self.fetches.pop_front()
+        }
+
+        async fn settle(
+            &mut self,
+            message: MockMessage,
+            outcome: Outcome,
+        ) -> Result<(), NatsError> {
+            self.log
+                .lock()
+                .unwrap()
+                .push(Event::Settled(message.id, outcome));
+            Ok(())
+        }
+    }
+
+    struct MockSink {
+        // whether each push fails, those past the end succeed
+        failures: VecDeque<bool>,
+        log: Log,
+    }
+
+    #[async_trait]
+    impl Sink for MockSink {
+        async fn push(
+            &mut self,
+            stream_name: &str,
+            _: &str,
+            events: Vec<Value>,
+        ) -> Result<(), PostError> {
+            if self.failures.pop_front().unwrap_or(false) {
+                return Err(PostError::StreamNotFound(stream_name.to_owned()));
+            }
+            self.log
+                .lock()
+                .unwrap()
+                .push(Event::Pushed(stream_name.to_owned(), events));
+            Ok(())
+        }
+    }
+
+    fn message(id: u64, subject: &str, payload: &[u8], delivered: u64) -> MockMessage {
+        MockMessage {
+            id,
+            subject: subject.to_owned(),
+            payload: payload.to_vec(),
+            delivered,
+        }
+    }
+
+    fn json_message(id: u64, subject: &str, payload: Value, delivered: u64) -> MockMessage {
+        message(
+            id,
+            subject,
+            &serde_json::to_vec(&payload).unwrap(),
+            delivered,
+        )
+    }
+
+    async fn run(
+        fetches: impl IntoIterator<Item = Result<Vec<MockMessage>, NatsError>>,
+        failures: impl IntoIterator<Item = bool>,
+        mapping: &SubjectMapping,
+    ) -> Vec<Event> {
+        let log = Log::default();
+        let mut source = MockSource {
+            fetches: fetches.into_iter().collect(),
+            log: log.clone(),
+        };
+        let mut sink = MockSink {
+            failures: failures.into_iter().collect(),
+            log: log.clone(),
+        };
+        consume(&mut source, &mut sink, mapping, FLOW).await;
+        let events = log.lock().unwrap().clone();
+        events
+    }
+
+    fn mapping(entries: &[&str], default: Option<&str>) -> SubjectMapping {
+        let entries: Vec<String> = entries.iter().map(|entry| entry.to_string()).collect();
+        SubjectMapping::new(&entries, default).unwrap()
+    }
+
+    fn cli() -> Cli {
+        Cli {
+            nats_servers: Some("nats://localhost:4222".to_owned()),
+            nats_stream: Some("LOGS".to_owned()),
+            nats_consumer: "parseable".to_owned(),
+            nats_default_stream: Some("nats".to_owned()),
+            nats_max_in_flight: 1000,
+            nats_batch_size: 100,
+            nats_ack_wait: 30,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn subjects_match_nats_wildcards() {
+        let mapping = mapping(
+            &[
+                "logs.app.billing=billing",
+                "logs.app.>=app",
+                "logs.*.audit=audit",
+            ],
+            Some("other"),
+        );
+        assert_eq!(mapping.stream("logs.app.billing"), Some("billing"));
+        assert_eq!(mapping.stream("logs.app.orders.eu"), Some("app"));
+        assert_eq!(mapping.stream("logs.db.audit"), Some("audit"));
+        // > takes at least one token and * exactly one
+        assert_eq!(mapping.stream("logs.app"), Some("other"));
+        assert_eq!(mapping.stream("logs.db.audit.extra"), Some("other"));
+
+        let mapping = SubjectMapping::new(&["metrics.>=metrics".to_owned()], None).unwrap();
+        assert_eq!(mapping.stream("logs.app"), None);
+    }
+
+    #[test]
+    fn payloads_are_read_as_json_or_ndjson() {
+        assert_eq!(parse_events(br#"{"a":1}"#).unwrap(), [json!({"a": 1})]);
+        assert_eq!(
+            parse_events(br#"[{"a":1},{"a":2}]"#).unwrap(),
+            [json!({"a": 1}), json!({"a": 2})]
+        );
+        assert_eq!(
+            parse_events(b"{\"a\":1}\n\n{\"a\":2}\r\n").unwrap(),
+            [json!({"a": 1}), json!({"a": 2})]
+        );
+        assert!(parse_events(b"").unwrap().is_empty());
+        assert!(parse_events(b"{\"a\":1}\nnot json")
+            .unwrap_err()
+            .starts_with("line 2"));
+        assert!(parse_events(b"[1, 2]").is_err());
+    }
+
+    #[actix_web::test]
+    async fn messages_are_acked_once_their_events_are_staged() {
+        let events = run(
+            [Ok(vec![
+                json_message(1, "logs.app", json!({"n": 1}), 1),
+                json_message(2, "logs.db", json!({"n": 2}), 1),
+                json_message(3, "logs.app", json!([{"n": 3}, {"n": 4}]), 1),
+            ])],
+            [],
+            &mapping(&["logs.app=app"], Some("db")),
+        )
+        .await;
+
+        assert_eq!(
+            events,
+            [
+                Event::Fetched(10),
+                Event::Pushed(
+                    "app".to_owned(),
+                    vec![json!({"n": 1}), json!({"n": 3}), json!({"n": 4})]
+                ),
+                Event::Settled(1, Outcome::Ack),
+                Event::Settled(3, Outcome::Ack),
+                Event::Pushed("db".to_owned(), vec![json!({"n": 2})]),
+                Event::Settled(2, Outcome::Ack),
+                Event::Fetched(10),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn failed_pushes_are_naked_and_acked_once_redelivered() {
+        let events = run(
+            [
+                Ok(vec![
+                    json_message(1, "logs", json!({"n": 1}), 1),
+                    json_message(2, "logs", json!({"n": 2}), 1),
+                ]),
+                Ok(vec![
+                    json_message(1, "logs", json!({"n": 1}), 2),
+                    json_message(2, "logs", json!({"n": 2}), 2),
+                ]),
+            ],
+            [true],
+            &mapping(&[], Some("logs")),
+        )
+        .await;
+
+        assert_eq!(
+            events,
+            [
+                Event::Fetched(10),
+                Event::Settled(1, Outcome::Nak(backoff(1))),
+                Event::Settled(2, Outcome::Nak(backoff(1))),
+                Event::Fetched(10),
+                Event::Pushed("logs".to_owned(), vec![json!({"n": 1}), json!({"n": 2})]),
+                Event::Settled(1, Outcome::Ack),
+                Event::Settled(2, Outcome::Ack),
+                Event::Fetched(10),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn messages_that_cant_be_ingested_are_terminated() {
+        let events = run(
+            [Ok(vec![
+                message(1, "logs.app", b"not json", 1),
+                json_message(2, "logs.app", json!({"n": 2}), 1),
+                json_message(3, "metrics.cpu", json!({"n": 3}), 1),
+                json_message(4, "logs.db", json!({"n": 4}), MAX_DELIVERIES),
+            ])],
+            [false, true],
+            &mapping(&["logs.>=logs"], None),
+        )
+        .await;
+
+        assert_eq!(
+            events,
+            [
+                Event::Fetched(10),
+                Event::Settled(1, Outcome::Term),
+                Event::Pushed("logs".to_owned(), vec![json!({"n": 2})]),
+                Event::Settled(2, Outcome::Ack),
+                // the push of logs.db fails on its last delivery
+                Event::Settled(4, Outcome::Term),
+                Event::Settled(3, Outcome::Term),
+                Event::Fetched(10),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn fetches_resume_after_failing() {
+        let events = run(
+            [
+                Err(NatsError::Client("connection lost".to_owned())),
+                Ok(vec![json_message(1, "logs", json!({"n": 1}), 1)]),
+            ],
+            [],
+            &mapping(&[], Some("logs")),
+        )
+        .await;
+
+        assert_eq!(
+            events,
+            [
+                Event::Fetched(10),
+                Event::Fetched(10),
+                Event::Pushed("logs".to_owned(), vec![json!({"n": 1})]),
+                Event::Settled(1, Outcome::Ack),
+                Event::Fetched(10),
+            ]
+        );
+    }
+
+    #[test]
+    fn fetches_stay_within_the_messages_in_flight() {
+        let flow = Flow {
+            max_in_flight: 5,
+            batch_size: 10,
+        };
+        assert_eq!(flow.fetch_size(), 5);
+        assert_eq!(FLOW.fetch_size(), 10);
+    }
+
+    #[test]
+    fn retries_back_off_up_to_a_limit() {
+        assert_eq!(backoff(0), Duration::from_millis(100));
+        assert_eq!(backoff(3), Duration::from_millis(800));
+        assert_eq!(backoff(20), Duration::from_secs(30));
+        assert_eq!(backoff(usize::MAX), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn invalid_configs_fail() {
+        let invalid = [
+            (
+                Cli {
+                    nats_stream: None,
+                    ..cli()
+                },
+                "no JetStream stream",
+            ),
+            (
+                Cli {
+                    nats_default_stream: None,
+                    ..cli()
+                },
+                "neither subject streams",
+            ),
+            (
+                Cli {
+                    nats_subject_streams: vec!["logs".to_owned()],
+                    ..cli()
+                },
+                "not of the form",
+            ),
+            (
+                Cli {
+                    nats_subject_streams: vec!["logs.>.app=app".to_owned()],
+                    ..cli()
+                },
+                "is not valid",
+            ),
+            (
+                Cli {
+                    nats_subjects: vec!["logs..app".to_owned()],
+                    ..cli()
+                },
+                "is not valid",
+            ),
+            (
+                Cli {
+                    nats_subject_streams: vec!["logs=1".to_owned()],
+                    ..cli()
+                },
+                "not a valid stream name",
+            ),
+            (
+                Cli {
+                    nats_token: Some("token".to_owned()),
+                    nats_user: Some("user".to_owned()),
+                    ..cli()
+                },
+                "only one of",
+            ),
+            (
+                Cli {
+                    nats_user: Some("user".to_owned()),
+                    ..cli()
+                },
+                "has no password",
+            ),
+        ];
+        for (cli, message) in invalid {
+            let err = NatsConfig::from_cli(&cli).unwrap_err().to_string();
+            assert!(err.contains(message), "{err} should contain {message}");
+        }
+
+        assert!(NatsConfig::from_cli(&Cli::default()).unwrap().is_none());
+        let config = NatsConfig::from_cli(&Cli {
+            nats_subjects: vec!["logs.>".to_owned()],
+            nats_user: Some("user".to_owned()),
+            nats_password: Some("secret".to_owned()),
+            ..cli()
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            config.auth,
+            NatsAuth::UserPassword {
+                user: "user".to_owned(),
+                password: "secret".to_owned(),
+            }
+        );
+        assert!(!format!("{config:?}").contains("secret"));
+    }
+}
@@ -0,0 +1,575 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+mod parser;
+
+use std::{collections::BTreeMap, io, net::SocketAddr, sync::Arc};
+
+use bytes::Bytes;
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader},
+    net::{TcpListener, UdpSocket},
+    sync::mpsc,
+};
+use tokio_rustls::TlsAcceptor;
+
+use self::parser::{ParseError, SyslogMessage};
+use super::http::{
+    ingest::{create_stream_if_not_exists, push_labelled_logs, PostError},
+    modal::ssl_acceptor::{get_ssl_acceptor, spawn_reload},
+};
+use crate::{option::CONFIG, validator};
+
+/// Largest message accepted, longer newline framed messages are split and
+/// longer octet counted frames close the connection
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Digits in the octet count of a frame, enough for `MAX_MESSAGE_SIZE`
+const MAX_OCTET_COUNT_DIGITS: u64 = 5;
+
+/// How many received messages may wait to be ingested
+const CHANNEL_CAPACITY: usize = 10_000;
+
+/// How many messages are staged as a single event
+const MESSAGES_PER_EVENT: usize = 1000;
+
+/// Stream messages go to when their hostname doesn't make a valid stream name
+const DEFAULT_STREAM: &str = "syslog";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Tcp,
+    Tls,
+    Udp,
+}
+
+impl Transport {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Transport::Tcp => "tcp",
+            Transport::Tls => "tls",
+            Transport::Udp => "udp",
+        }
+    }
+}
+
+/// A message as it came off the wire along with the result of parsing it
+#[derive(Debug)]
+pub struct Received {
+    pub transport: Transport,
+    pub peer: SocketAddr,
+    pub raw: String,
+    pub message: Result<SyslogMessage, ParseError>,
+}
+
+impl Received {
+    fn new(transport: Transport, peer: SocketAddr, frame: &[u8]) -> Self {
+        let raw = String::from_utf8_lossy(frame).into_owned();
+        let message = parser::parse(&raw);
+        Self {
+            transport,
+            peer,
+            raw,
+            message,
+        }
+    }
+}
+
+/// Decides the stream each message goes to
+#[derive(Debug, Clone)]
+pub struct Routing {
+    /// Static stream for all messages, derived from their hostname when unset
+    pub stream: Option<String>,
+    /// Stream messages that can't be parsed go to
+    pub quarantine_stream: String,
+}
+
+impl Routing {
+    fn from_config() -> Self {
+        Self {
+            stream: CONFIG.parseable.syslog_stream.clone(),
+            quarantine_stream: CONFIG.parseable.syslog_quarantine_stream.clone(),
+        }
+    }
+
+    /// The stream a message goes to along with its row, messages that could not
+    /// be parsed go to the quarantine stream as the raw message and the error
+    pub fn route(&self, received: Received) -> (String, BTreeMap<String, Value>) {
+        let (stream_name, mut record) = match received.message {
+            Ok(message) => {
+                let stream_name = self
+                    .stream
+                    .clone()
+                    .unwrap_or_else(|| hostname_stream(message.hostname.as_deref()));
+                (stream_name, message.into_record())
+            }
+            Err(err) => {
+                let mut record = BTreeMap::new();
+                record.insert("raw".to_string(), Value::String(received.raw));
+                record.insert("error".to_string(), Value::String(err.to_string()));
+                (self.quarantine_stream.clone(), record)
+            }
+        };
+        record.insert(
+            "transport".to_string(),
+            Value::String(received.transport.as_str().to_owned()),
+        );
+        record.insert(
+            "source_addr".to_string(),
+            Value::String(received.peer.to_string()),
+        );
+        (stream_name, record)
+    }
+}
+
+// the lowercased alphanumerics of the hostname, when that is a valid stream name
+fn hostname_stream(hostname: Option<&str>) -> String {
+    hostname
+        .map(|hostname| {
+            hostname
+                .chars()
+                .filter(char::is_ascii_alphanumeric)
+                .map(|c| c.to_ascii_lowercase())
+                .collect::<String>()
+        })
+        .filter(|stream_name| validator::stream_name(stream_name).is_ok())
+        .unwrap_or_else(|| DEFAULT_STREAM.to_owned())
+}
+
+/// Runs the configured syslog listeners and ingests the messages they receive,
+/// returns right away when neither a TCP nor a UDP address is set
+pub async fn server() {
+    if let Err(err) = run().await {
+        log::error!("Syslog server failed: {err}");
+    }
+}
+
+async fn run() -> anyhow::Result<()> {
+    let config = &CONFIG.parseable;
+    if config.syslog_tcp_addr.is_none() && config.syslog_udp_addr.is_none() {
+        return Ok(());
+    }
+
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+    if let Some(addr) = &config.syslog_tcp_addr {
+        let listener = TcpListener::bind(addr).await?;
+        let acceptor = if config.syslog_tls {
+            get_ssl_acceptor(
+                config.tls_identity(),
+                config.tls_min_version,
+                &config.tls_cipher_suites,
+            )?
+            .map(|(server_config, resolver)| {
+                spawn_reload(resolver);
+                TlsAcceptor::from(Arc::new(server_config))
+            })
+        } else {
+            None
+        };
+        log::info!(
+            "Syslog server listening on {addr} over {}",
+            if acceptor.is_some() { "TLS" } else { "TCP" }
+        );
+        tokio::spawn(serve_tcp(listener, acceptor, tx.clone()));
+    }
+    if let Some(addr) = &config.syslog_udp_addr {
+        let socket = UdpSocket::bind(addr).await?;
+        log::info!("Syslog server listening on {addr} over UDP");
+        tokio::spawn(serve_udp(socket, tx.clone()));
+    }
+    drop(tx);
+
+    ingest(rx, Routing::from_config()).await;
+    Ok(())
+}
+
+// messages received while the previous batch was ingested are staged together
+async fn ingest(mut rx: mpsc::Receiver<Received>, routing: Routing) {
+    while let Some(received) = rx.recv().await {
+        let mut streams: BTreeMap<String, Vec<BTreeMap<String, Value>>> = BTreeMap::new();
+        let mut next = Some(received);
+        let mut count = 0;
+        while let Some(received) = next {
+            let (stream_name, record) = routing.route(received);
+            streams.entry(stream_name).or_default().push(record);
+            count += 1;
+            next = if count < MESSAGES_PER_EVENT {
+                rx.try_recv().ok()
+            } else {
+                None
+            };
+        }
+
+        for (stream_name, records) in streams {
+            if let Err(err) = push(&stream_name, &records).await {
+                log::warn!(
+                    "Failed to ingest {} syslog messages into {stream_name}: {err}",
+                    records.len()
+                );
+            }
+        }
+    }
+}
+
+async fn push(stream_name: &str, records: &[BTreeMap<String, Value>]) -> Result<(), PostError> {
+    create_stream_if_not_exists(stream_name, false).await?;
+    let body: Bytes = serde_json::to_vec(records)?.into();
+    push_labelled_logs(stream_name.to_owned(), "", "", body).await
+}
+
+async fn serve_tcp(
+    listener: TcpListener,
+    acceptor: Option<TlsAcceptor>,
+    tx: mpsc::Sender<Received>,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                log::warn!("Failed to accept syslog connection: {err}");
+                continue;
+            }
+        };
+        let tx = tx.clone();
+        match acceptor.clone() {
+            Some(acceptor) => {
+                tokio::spawn(async move {
+                    match acceptor.accept(stream).await {
+                        Ok(stream) => read_frames(stream, peer, Transport::Tls, tx).await,
+                        Err(err) => {
+                            log::warn!("TLS handshake with syslog client {peer} failed: {err}")
+                        }
+                    }
+                });
+            }
+            None => {
+                tokio::spawn(read_frames(stream, peer, Transport::Tcp, tx));
+            }
+        }
+    }
+}
+
+async fn read_frames<S: AsyncRead + Unpin>(
+    stream: S,
+    peer: SocketAddr,
+    transport: Transport,
+    tx: mpsc::Sender<Received>,
+) {
+    let mut reader = BufReader::new(stream);
+    let mut frame = Vec::new();
+    loop {
+        match read_frame(&mut reader, &mut frame).await {
+            Ok(false) => return,
+            // blank lines between newline framed messages
+            Ok(true) if frame.is_empty() => continue,
+            Ok(true) => {
+                if tx
+                    .send(Received::new(transport, peer, &frame))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            Err(err) => {
+                log::warn!("Closing syslog connection from {peer}: {err}");
+                return;
+            }
+        }
+    }
+}
+
+// RFC 6587 framing, a frame starting with a digit is octet counted as messages
+// themselves start with "<", anything else is a message ended by a newline.
+// Returns false once the connection is closed.
+async fn read_frame<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    frame: &mut Vec<u8>,
+) -> io::Result<bool> {
+    frame.clear();
+    let Some(first) = reader.fill_buf().await?.first().copied() else {
+        return Ok(false);
+    };
+
+    if first.is_ascii_digit() {
+        let mut octet_count = Vec::new();
+        (&mut *reader)
+            .take(MAX_OCTET_COUNT_DIGITS + 1)
+            .read_until(b' ', &mut octet_count)
+            .await?;
+        let len = std::str::from_utf8(&octet_count)
+            .ok()
+            .and_then(|count| count.strip_suffix(' '))
+            .and_then(|count| count.parse::<usize>().ok())
+            .filter(|len| *len <= MAX_MESSAGE_SIZE)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid octet count"))?;
+        frame.resize(len, 0);
+        reader.read_exact(frame).await?;
+    } else {
+        (&mut *reader)
+            .take(MAX_MESSAGE_SIZE as u64)
+            .read_until(b'\n', frame)
+            .await?;
+        while matches!(frame.last(), Some(b'\n' | b'\r')) {
+            frame.pop();
+        }
+    }
+    Ok(true)
+}
+
+async fn serve_udp(socket: UdpSocket, tx: mpsc::Sender<Received>) {
+    let mut buf = vec![0; MAX_MESSAGE_SIZE];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(datagram) => datagram,
+            Err(err) => {
+                log::warn!("Failed to receive syslog datagram: {err}");
+                continue;
+            }
+        };
+        if tx
+            .send(Received::new(Transport::Udp, peer, &buf[..len]))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, net::SocketAddr, sync::Arc};
+
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use rustls::{pki_types::ServerName, ClientConfig, RootCertStore};
+    use serde_json::json;
+    use tempfile::TempDir;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream, UdpSocket},
+        sync::mpsc,
+    };
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    use super::{
+        hostname_stream, serve_tcp, serve_udp, Received, Routing, Transport, CHANNEL_CAPACITY,
+    };
+    use crate::{
+        handlers::http::modal::ssl_acceptor::{get_ssl_acceptor, TlsIdentity},
+        option::TlsVersion,
+    };
+
+    const RFC5424: &str = concat!(
+        r#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 "#,
+        r#"[exampleSDID@32473 iut="3" eventSource="Application"] An application event"#
+    );
+    const RFC3164: &str = "<34>Oct 11 22:14:15 router1 su[230]: 'su root' failed";
+    const MALFORMED: &str = "not a syslog message";
+
+    async fn tcp_server(acceptor: Option<TlsAcceptor>) -> (SocketAddr, mpsc::Receiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        tokio::spawn(serve_tcp(listener, acceptor, tx));
+        (addr, rx)
+    }
+
+    fn routing() -> Routing {
+        Routing {
+            stream: None,
+            quarantine_stream: "syslogquarantine".to_string(),
+        }
+    }
+
+    fn assert_rfc5424(received: Received) {
+        let message = received.message.unwrap();
+        assert_eq!(message.facility_name(), "local4");
+        assert_eq!(message.severity_name(), "notice");
+        assert_eq!(message.hostname.as_deref(), Some("mymachine.example.com"));
+        assert_eq!(message.app_name.as_deref(), Some("evntslog"));
+        assert_eq!(message.msgid.as_deref(), Some("ID47"));
+        assert_eq!(
+            message.structured_data["exampleSDID@32473"]["eventSource"],
+            "Application"
+        );
+        assert_eq!(message.message.as_deref(), Some("An application event"));
+    }
+
+    fn assert_rfc3164(received: Received) {
+        let message = received.message.unwrap();
+        assert_eq!(message.facility_name(), "auth");
+        assert_eq!(message.severity_name(), "crit");
+        assert_eq!(message.hostname.as_deref(), Some("router1"));
+        assert_eq!(message.app_name.as_deref(), Some("su"));
+        assert_eq!(message.procid.as_deref(), Some("230"));
+        assert_eq!(message.message.as_deref(), Some("'su root' failed"));
+    }
+
+    #[actix_web::test]
+    async fn tcp_octet_counted_and_newline_framing() {
+        let (addr, mut rx) = tcp_server(None).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+
+        let payload = format!(
+            "{} {RFC5424}{RFC3164}\r\n\n{MALFORMED}\n{} {RFC3164}",
+            RFC5424.len(),
+            RFC3164.len()
+        );
+        client.write_all(payload.as_bytes()).await.unwrap();
+        client.shutdown().await.unwrap();
+
+        let received = rx.recv().await.unwrap();
+        assert_eq!(received.transport, Transport::Tcp);
+        assert_rfc5424(received);
+        assert_rfc3164(rx.recv().await.unwrap());
+
+        let malformed = rx.recv().await.unwrap();
+        assert_eq!(malformed.raw, MALFORMED);
+        assert!(malformed.message.is_err());
+
+        assert_rfc3164(rx.recv().await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn tcp_invalid_octet_count_closes_connection() {
+        let (addr, mut rx) = tcp_server(None).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+
+        let payload = format!("{} {RFC3164}999999999 {RFC3164}", RFC3164.len());
+        client.write_all(payload.as_bytes()).await.unwrap();
+        assert_rfc3164(rx.recv().await.unwrap());
+
+        // framing is lost so the connection is closed, others are still served
+        let mut buf = [0; 1];
+        assert!(matches!(client.read(&mut buf).await, Ok(0) | Err(_)));
+        let mut other = TcpStream::connect(addr).await.unwrap();
+        other
+            .write_all(format!("{RFC5424}\n").as_bytes())
+            .await
+            .unwrap();
+        assert_rfc5424(rx.recv().await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn udp_datagrams() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (tx, mut rx) = mpsc::channel(CHANNEL_CAPACITY);
+        tokio::spawn(serve_udp(socket, tx));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(RFC5424.as_bytes(), addr).await.unwrap();
+        client
+            .send_to(format!("{RFC3164}\n").as_bytes(), addr)
+            .await
+            .unwrap();
+
+        let received = rx.recv().await.unwrap();
+        assert_eq!(received.transport, Transport::Udp);
+        assert_eq!(received.peer, client.local_addr().unwrap());
+        assert_rfc5424(received);
+        assert_rfc3164(rx.recv().await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn tls_listener_uses_server_certificate() {
+        let dir = TempDir::new().unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let der = cert.serialize_der().unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        fs::write(
+            &cert_path,
+            format!(
+                "-----BEGIN CERTIFICATE-----\n{}\n-----END CERTIFICATE-----\n",
+                BASE64_STANDARD.encode(&der)
+            ),
+        )
+        .unwrap();
+        fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+
+        let identity = TlsIdentity::Pem {
+            cert: cert_path,
+            key: key_path,
+        };
+        let (server_config, _) = get_ssl_acceptor(Some(identity), TlsVersion::V1_2, &[])
+            .unwrap()
+            .unwrap();
+        let (addr, mut rx) = tcp_server(Some(TlsAcceptor::from(Arc::new(server_config)))).await;
+
+        let mut roots = RootCertStore::empty();
+        roots.add(der.into()).unwrap();
+        let client_config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut client = TlsConnector::from(Arc::new(client_config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        client
+            .write_all(format!("{} {RFC5424}", RFC5424.len()).as_bytes())
+            .await
+            .unwrap();
+        client.flush().await.unwrap();
+
+        let received = rx.recv().await.unwrap();
+        assert_eq!(received.transport, Transport::Tls);
+        assert_rfc5424(received);
+    }
+
+    #[test]
+    fn routes_by_hostname_static_stream_and_quarantine() {
+        let peer: SocketAddr = "10.0.0.1:514".parse().unwrap();
+        let received = |raw: &str| Received::new(Transport::Udp, peer, raw.as_bytes());
+
+        let (stream_name, record) = routing().route(received(RFC5424));
+        assert_eq!(stream_name, "mymachineexamplecom");
+        assert_eq!(record["transport"], json!("udp"));
+        assert_eq!(record["source_addr"], json!("10.0.0.1:514"));
+        assert_eq!(record["hostname"], json!("mymachine.example.com"));
+
+        let (stream_name, _) = routing().route(received(RFC3164));
+        assert_eq!(stream_name, "router1");
+
+        let routing_static = Routing {
+            stream: Some("network".to_string()),
+            ..routing()
+        };
+        let (stream_name, _) = routing_static.route(received(RFC3164));
+        assert_eq!(stream_name, "network");
+
+        let (stream_name, record) = routing_static.route(received(MALFORMED));
+        assert_eq!(stream_name, "syslogquarantine");
+        assert_eq!(record["raw"], json!(MALFORMED));
+        assert_eq!(
+            record["error"],
+            json!("message does not start with a <PRI> priority")
+        );
+    }
+
+    #[test]
+    fn hostname_stream_falls_back_to_default() {
+        assert_eq!(hostname_stream(Some("Core-Switch.lan")), "coreswitchlan");
+        assert_eq!(hostname_stream(Some("10.0.0.99")), "syslog");
+        assert_eq!(hostname_stream(Some("1router")), "syslog");
+        assert_eq!(hostname_stream(None), "syslog");
+    }
+}
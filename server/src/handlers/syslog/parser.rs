@@ -0,0 +1,608 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Datelike, NaiveDateTime, SecondsFormat, Utc};
+use serde_json::{Map, Value};
+
+const FACILITIES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+// the value RFC 5424 uses for a header field or structured data that is absent
+const NIL: &str = "-";
+
+// RFC 5424 messages may start with a UTF-8 byte order mark
+const BOM: char = '\u{feff}';
+
+/// Which RFC a message was parsed as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Rfc5424,
+    Rfc3164,
+}
+
+impl Format {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Format::Rfc5424 => "rfc5424",
+            Format::Rfc3164 => "rfc3164",
+        }
+    }
+}
+
+/// A syslog message, fields a message leaves out or sets to `-` are `None`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyslogMessage {
+    pub format: Format,
+    pub facility: u8,
+    pub severity: u8,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub hostname: Option<String>,
+    pub app_name: Option<String>,
+    pub procid: Option<String>,
+    pub msgid: Option<String>,
+    /// SD-ID to its params, RFC 5424 only
+    pub structured_data: BTreeMap<String, BTreeMap<String, String>>,
+    pub message: Option<String>,
+}
+
+impl SyslogMessage {
+    pub fn facility_name(&self) -> &'static str {
+        FACILITIES[self.facility as usize]
+    }
+
+    pub fn severity_name(&self) -> &'static str {
+        SEVERITIES[self.severity as usize]
+    }
+
+    /// The message as a row, structured data is nested under `structured_data`
+    /// and flattened to a column per param on ingestion
+    pub fn into_record(self) -> BTreeMap<String, Value> {
+        let mut record = BTreeMap::new();
+        record.insert(
+            "syslog_format".to_string(),
+            Value::String(self.format.as_str().to_owned()),
+        );
+        record.insert(
+            "facility".to_string(),
+            Value::String(self.facility_name().to_owned()),
+        );
+        record.insert("facility_code".to_string(), Value::from(self.facility));
+        record.insert(
+            "severity".to_string(),
+            Value::String(self.severity_name().to_owned()),
+        );
+        record.insert("severity_code".to_string(), Value::from(self.severity));
+        if let Some(timestamp) = self.timestamp {
+            record.insert(
+                "timestamp".to_string(),
+                Value::String(timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+            );
+        }
+
+        let fields = [
+            ("hostname", self.hostname),
+            ("app_name", self.app_name),
+            ("procid", self.procid),
+            ("msgid", self.msgid),
+            ("message", self.message),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                record.insert(key.to_string(), Value::String(value));
+            }
+        }
+
+        if !self.structured_data.is_empty() {
+            let structured_data = self
+                .structured_data
+                .into_iter()
+                .map(|(id, params)| {
+                    let params: Map<String, Value> = params
+                        .into_iter()
+                        .map(|(name, value)| (name, Value::String(value)))
+                        .collect();
+                    (id, Value::Object(params))
+                })
+                .collect();
+            record.insert(
+                "structured_data".to_string(),
+                Value::Object(structured_data),
+            );
+        }
+        record
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum ParseError {
+    #[error("message is empty")]
+    Empty,
+    #[error("message does not start with a <PRI> priority")]
+    MissingPriority,
+    #[error("invalid priority {0}, expected a number between 0 and 191")]
+    InvalidPriority(String),
+    #[error("missing {0} in RFC 5424 header")]
+    MissingField(&'static str),
+    #[error("invalid RFC 5424 timestamp {0}")]
+    InvalidTimestamp(String),
+    #[error("invalid RFC 5424 structured data, {0}")]
+    InvalidStructuredData(&'static str),
+}
+
+/// Parses an RFC 5424 message, or an RFC 3164 message when the priority is
+/// not followed by version 1.
+///
+/// RFC 3164 has no strict format, anything after a valid priority parses and
+/// the parts that don't match the conventional layout end up in the message.
+pub fn parse(frame: &str) -> Result<SyslogMessage, ParseError> {
+    let frame = frame.trim_end_matches(['\r', '\n', '\0']);
+    if frame.is_empty() {
+        return Err(ParseError::Empty);
+    }
+    let (facility, severity, rest) = parse_priority(frame)?;
+
+    match rest.split_once(' ') {
+        Some(("1", rest)) => parse_rfc5424(facility, severity, rest),
+        _ => Ok(parse_rfc3164(facility, severity, rest, Utc::now())),
+    }
+}
+
+fn parse_priority(frame: &str) -> Result<(u8, u8, &str), ParseError> {
+    let rest = frame.strip_prefix('<').ok_or(ParseError::MissingPriority)?;
+    let (priority, rest) = rest.split_once('>').ok_or(ParseError::MissingPriority)?;
+    let valid = (1..=3).contains(&priority.len()) && priority.bytes().all(|b| b.is_ascii_digit());
+    let value = priority
+        .parse::<u8>()
+        .ok()
+        .filter(|value| valid && *value < 192)
+        .ok_or_else(|| ParseError::InvalidPriority(priority.to_owned()))?;
+    Ok((value / 8, value % 8, rest))
+}
+
+// HEADER after the version: TIMESTAMP HOSTNAME APP-NAME PROCID MSGID, then
+// STRUCTURED-DATA and an optional MSG
+fn parse_rfc5424(facility: u8, severity: u8, rest: &str) -> Result<SyslogMessage, ParseError> {
+    let mut fields = rest.splitn(6, ' ');
+    let mut next = |name| {
+        fields
+            .next()
+            .filter(|field| !field.is_empty())
+            .ok_or(ParseError::MissingField(name))
+    };
+    let timestamp = nil(next("timestamp")?);
+    let hostname = nil(next("hostname")?);
+    let app_name = nil(next("app-name")?);
+    let procid = nil(next("procid")?);
+    let msgid = nil(next("msgid")?);
+    let rest = next("structured data")?;
+
+    let timestamp = timestamp
+        .map(|timestamp| {
+            DateTime::parse_from_rfc3339(&timestamp)
+                .map(|timestamp| timestamp.with_timezone(&Utc))
+                .map_err(|_| ParseError::InvalidTimestamp(timestamp))
+        })
+        .transpose()?;
+
+    let (structured_data, rest) = parse_structured_data(rest)?;
+    let message = match rest {
+        "" => None,
+        rest => Some(
+            rest.strip_prefix(' ')
+                .ok_or(ParseError::InvalidStructuredData(
+                    "expected a space before the message",
+                ))?
+                .trim_start_matches(BOM)
+                .to_owned(),
+        ),
+    }
+    .filter(|message| !message.is_empty());
+
+    Ok(SyslogMessage {
+        format: Format::Rfc5424,
+        facility,
+        severity,
+        timestamp,
+        hostname,
+        app_name,
+        procid,
+        msgid,
+        structured_data,
+        message,
+    })
+}
+
+fn nil(field: &str) -> Option<String> {
+    (field != NIL).then(|| field.to_owned())
+}
+
+// STRUCTURED-DATA = NILVALUE / 1*SD-ELEMENT
+// SD-ELEMENT = "[" SD-ID *(SP PARAM-NAME "=" %d34 PARAM-VALUE %d34) "]"
+// returns the elements and what follows them
+#[allow(clippy::type_complexity)]
+fn parse_structured_data(
+    input: &str,
+) -> Result<(BTreeMap<String, BTreeMap<String, String>>, &str), ParseError> {
+    let mut elements = BTreeMap::new();
+    if let Some(rest) = input.strip_prefix(NIL) {
+        return Ok((elements, rest));
+    }
+    if !input.starts_with('[') {
+        return Err(ParseError::InvalidStructuredData("expected - or ["));
+    }
+
+    let mut rest = input;
+    while let Some(element) = rest.strip_prefix('[') {
+        let end = element
+            .find([' ', ']'])
+            .ok_or(ParseError::InvalidStructuredData("unterminated element"))?;
+        let id = &element[..end];
+        if id.is_empty() {
+            return Err(ParseError::InvalidStructuredData("empty SD-ID"));
+        }
+        let mut params = BTreeMap::new();
+        let mut remaining = &element[end..];
+        loop {
+            if let Some(after) = remaining.strip_prefix(']') {
+                remaining = after;
+                break;
+            }
+            let param = remaining
+                .strip_prefix(' ')
+                .ok_or(ParseError::InvalidStructuredData("unterminated element"))?;
+            let (name, value) =
+                param
+                    .split_once("=\"")
+                    .ok_or(ParseError::InvalidStructuredData(
+                        "expected PARAM-NAME=\"PARAM-VALUE\"",
+                    ))?;
+            if name.is_empty() || name.contains([' ', ']', '"', '=']) {
+                return Err(ParseError::InvalidStructuredData("invalid PARAM-NAME"));
+            }
+            let (value, after) = parse_param_value(value)?;
+            params.insert(name.to_owned(), value);
+            remaining = after;
+        }
+        elements.insert(id.to_owned(), params);
+        rest = remaining;
+    }
+    Ok((elements, rest))
+}
+
+// reads up to the closing quote, unescaping \" \\ and \]
+fn parse_param_value(input: &str) -> Result<(String, &str), ParseError> {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &input[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, escaped @ ('"' | '\\' | ']'))) => value.push(escaped),
+                Some((_, other)) => {
+                    value.push('\\');
+                    value.push(other);
+                }
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    Err(ParseError::InvalidStructuredData(
+        "unterminated PARAM-VALUE",
+    ))
+}
+
+// <PRI>Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG
+// the timestamp has no year or zone, it is taken to be in the current year in
+// UTC, or the previous year when that would place it in the future
+fn parse_rfc3164(facility: u8, severity: u8, rest: &str, now: DateTime<Utc>) -> SyslogMessage {
+    let mut message = SyslogMessage {
+        format: Format::Rfc3164,
+        facility,
+        severity,
+        timestamp: None,
+        hostname: None,
+        app_name: None,
+        procid: None,
+        msgid: None,
+        structured_data: BTreeMap::new(),
+        message: None,
+    };
+
+    let mut rest = rest;
+    if let Some((timestamp, after)) = rfc3164_timestamp(rest, now) {
+        message.timestamp = Some(timestamp);
+        rest = after;
+        // a hostname only follows a timestamp, and is never followed directly by a colon
+        if let Some((hostname, after)) = rest.split_once(' ') {
+            if !hostname.is_empty() && !hostname.ends_with(':') && !hostname.contains('[') {
+                message.hostname = Some(hostname.to_owned());
+                rest = after;
+            }
+        }
+    }
+
+    if let Some((tag, after)) = rest.split_once(':') {
+        let (app_name, procid) = match tag.split_once('[') {
+            Some((app_name, procid)) => (app_name, procid.strip_suffix(']')),
+            None => (tag, None),
+        };
+        let is_tag = !app_name.is_empty()
+            && app_name.len() <= 48
+            && !app_name.contains(' ')
+            && procid.map_or(true, |procid| !procid.is_empty() && !procid.contains(' '));
+        if is_tag {
+            message.app_name = Some(app_name.to_owned());
+            message.procid = procid.map(str::to_owned);
+            rest = after.strip_prefix(' ').unwrap_or(after);
+        }
+    }
+
+    message.message = Some(rest.to_owned()).filter(|rest| !rest.is_empty());
+    message
+}
+
+fn rfc3164_timestamp(input: &str, now: DateTime<Utc>) -> Option<(DateTime<Utc>, &str)> {
+    // "Mmm dd hh:mm:ss", single digit days are padded with a space
+    let timestamp = input.get(..15)?;
+    let rest = input[15..].strip_prefix(' ').unwrap_or(&input[15..]);
+    let parse = |year: i32| {
+        NaiveDateTime::parse_from_str(&format!("{year} {timestamp}"), "%Y %b %e %H:%M:%S")
+            .ok()
+            .map(|timestamp| timestamp.and_utc())
+    };
+    let timestamp = parse(now.year())?;
+    // messages from late December arriving just after new year
+    let timestamp = if timestamp > now + chrono::Duration::days(1) {
+        parse(now.year() - 1)?
+    } else {
+        timestamp
+    };
+    Some((timestamp, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use maplit::btreemap;
+    use serde_json::json;
+
+    use super::{parse, parse_rfc3164, Format, ParseError};
+
+    #[test]
+    fn rfc5424_with_structured_data() {
+        let message = parse(concat!(
+            r#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 "#,
+            r#"[exampleSDID@32473 iut="3" eventSource="Application" eventID="1011"]"#,
+            r#"[examplePriority@32473 class="high"] "#,
+            "\u{feff}An application event log entry..."
+        ))
+        .unwrap();
+
+        assert_eq!(message.format, Format::Rfc5424);
+        assert_eq!(message.facility_name(), "local4");
+        assert_eq!(message.severity_name(), "notice");
+        assert_eq!(
+            message.timestamp,
+            Some(
+                Utc.with_ymd_and_hms(2003, 10, 11, 22, 14, 15).unwrap()
+                    + chrono::Duration::milliseconds(3)
+            )
+        );
+        assert_eq!(message.hostname.as_deref(), Some("mymachine.example.com"));
+        assert_eq!(message.app_name.as_deref(), Some("evntslog"));
+        assert_eq!(message.procid, None);
+        assert_eq!(message.msgid.as_deref(), Some("ID47"));
+        assert_eq!(
+            message.structured_data,
+            btreemap! {
+                "exampleSDID@32473".to_string() => btreemap! {
+                    "iut".to_string() => "3".to_string(),
+                    "eventSource".to_string() => "Application".to_string(),
+                    "eventID".to_string() => "1011".to_string(),
+                },
+                "examplePriority@32473".to_string() => btreemap! {
+                    "class".to_string() => "high".to_string(),
+                },
+            }
+        );
+        assert_eq!(
+            message.message.as_deref(),
+            Some("An application event log entry...")
+        );
+    }
+
+    #[test]
+    fn rfc5424_nil_fields_and_no_message() {
+        let message = parse("<34>1 - - - - - -").unwrap();
+        assert_eq!(message.facility_name(), "auth");
+        assert_eq!(message.severity_name(), "crit");
+        assert_eq!(message.timestamp, None);
+        assert_eq!(message.hostname, None);
+        assert_eq!(message.app_name, None);
+        assert!(message.structured_data.is_empty());
+        assert_eq!(message.message, None);
+    }
+
+    #[test]
+    fn rfc5424_escaped_param_values() {
+        let message =
+            parse(r#"<14>1 2024-01-02T03:04:05+01:00 host app 42 - [meta q="a \"b\" \] \\c"] hi"#)
+                .unwrap();
+        assert_eq!(message.structured_data["meta"]["q"], r#"a "b" ] \c"#);
+        assert_eq!(message.procid.as_deref(), Some("42"));
+        assert_eq!(
+            message.timestamp,
+            Some(Utc.with_ymd_and_hms(2024, 1, 2, 2, 4, 5).unwrap())
+        );
+        assert_eq!(message.message.as_deref(), Some("hi"));
+    }
+
+    #[test]
+    fn rfc5424_malformed() {
+        assert_eq!(
+            parse("<14>1 2024-01-02T03:04:05Z host app"),
+            Err(ParseError::MissingField("procid"))
+        );
+        assert_eq!(
+            parse("<14>1 yesterday host app - - -"),
+            Err(ParseError::InvalidTimestamp("yesterday".to_string()))
+        );
+        assert!(matches!(
+            parse(r#"<14>1 - host app - - [meta q="open] msg"#),
+            Err(ParseError::InvalidStructuredData(_))
+        ));
+        assert!(matches!(
+            parse("<14>1 - host app - - {not sd}"),
+            Err(ParseError::InvalidStructuredData(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_priority() {
+        assert_eq!(parse(""), Err(ParseError::Empty));
+        assert_eq!(parse("hello"), Err(ParseError::MissingPriority));
+        assert_eq!(parse("<14 hello"), Err(ParseError::MissingPriority));
+        assert_eq!(
+            parse("<192>hello"),
+            Err(ParseError::InvalidPriority("192".to_string()))
+        );
+        assert_eq!(
+            parse("<>hello"),
+            Err(ParseError::InvalidPriority(String::new()))
+        );
+    }
+
+    #[test]
+    fn rfc3164_conventional_layout() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+        let message = parse_rfc3164(
+            4,
+            2,
+            "Oct 11 22:14:15 mymachine su[230]: 'su root' failed for lonvick on /dev/pts/8",
+            now,
+        );
+
+        assert_eq!(message.format, Format::Rfc3164);
+        assert_eq!(message.facility_name(), "auth");
+        assert_eq!(message.severity_name(), "crit");
+        // in the future for this year, so it is from the previous one
+        assert_eq!(
+            message.timestamp,
+            Some(Utc.with_ymd_and_hms(2023, 10, 11, 22, 14, 15).unwrap())
+        );
+        assert_eq!(message.hostname.as_deref(), Some("mymachine"));
+        assert_eq!(message.app_name.as_deref(), Some("su"));
+        assert_eq!(message.procid.as_deref(), Some("230"));
+        assert_eq!(
+            message.message.as_deref(),
+            Some("'su root' failed for lonvick on /dev/pts/8")
+        );
+    }
+
+    #[test]
+    fn rfc3164_best_effort() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+
+        // padded single digit day and no pid
+        let message = parse_rfc3164(1, 6, "Feb  5 17:32:18 10.0.0.99 sshd: accepted", now);
+        assert_eq!(
+            message.timestamp,
+            Some(Utc.with_ymd_and_hms(2024, 2, 5, 17, 32, 18).unwrap())
+        );
+        assert_eq!(message.hostname.as_deref(), Some("10.0.0.99"));
+        assert_eq!(message.app_name.as_deref(), Some("sshd"));
+        assert_eq!(message.procid, None);
+        assert_eq!(message.message.as_deref(), Some("accepted"));
+
+        // no timestamp means no hostname either
+        let message = parse_rfc3164(1, 6, "kernel: link up", now);
+        assert_eq!(message.timestamp, None);
+        assert_eq!(message.hostname, None);
+        assert_eq!(message.app_name.as_deref(), Some("kernel"));
+        assert_eq!(message.message.as_deref(), Some("link up"));
+
+        // a version other than 1 is not RFC 5424
+        let message = parse("<14>2 - host app - - -").unwrap();
+        assert_eq!(message.format, Format::Rfc3164);
+        assert_eq!(message.message.as_deref(), Some("2 - host app - - -"));
+
+        // nothing conventional, all of it is the message
+        let message = parse_rfc3164(1, 6, "%LINK-3-UPDOWN Interface down, state: down", now);
+        assert_eq!(message.app_name, None);
+        assert_eq!(
+            message.message.as_deref(),
+            Some("%LINK-3-UPDOWN Interface down, state: down")
+        );
+    }
+
+    #[test]
+    fn record_columns() {
+        let record = parse(r#"<165>1 2003-10-11T22:14:15Z host app 7 ID1 [a@1 k="v"] body"#)
+            .unwrap()
+            .into_record();
+
+        assert_eq!(
+            serde_json::to_value(record).unwrap(),
+            json!({
+                "syslog_format": "rfc5424",
+                "facility": "local4",
+                "facility_code": 20,
+                "severity": "notice",
+                "severity_code": 5,
+                "timestamp": "2003-10-11T22:14:15Z",
+                "hostname": "host",
+                "app_name": "app",
+                "procid": "7",
+                "msgid": "ID1",
+                "structured_data": { "a@1": { "k": "v" } },
+                "message": "body",
+            })
+        );
+    }
+}
@@ -16,9 +16,11 @@
  *
  */
 
+mod filter;
+
 use std::{
     collections::HashMap,
-    sync::{Arc, RwLock, Weak},
+    sync::{Arc, Mutex, RwLock, Weak},
     task::Poll,
 };
 
@@ -30,6 +32,8 @@ use tokio::sync::mpsc::{
 use arrow_array::RecordBatch;
 use once_cell::sync::Lazy;
 
+pub use self::filter::LiveTailFilter;
+
 pub static LIVETAIL: Lazy<LiveTail> = Lazy::new(LiveTail::default);
 
 pub type LiveTailRegistry = RwLock<HashMap<String, Vec<SenderPipe>>>;
@@ -39,8 +43,14 @@ pub struct LiveTail {
 }
 
 impl LiveTail {
-    pub fn new_pipe(&self, id: String, stream: String) -> ReceiverPipe {
-        let (sender, revc) = channel(id, stream.clone(), Arc::downgrade(&self.pipes));
+    /// Subscribes to the events of `stream`, only rows passing `filter` are sent
+    pub fn new_pipe(
+        &self,
+        id: String,
+        stream: String,
+        filter: Option<LiveTailFilter>,
+    ) -> ReceiverPipe {
+        let (sender, revc) = channel(id, stream.clone(), filter, Arc::downgrade(&self.pipes));
         self.pipes
             .write()
             .unwrap()
@@ -73,11 +83,14 @@ impl Default for LiveTail {
 pub enum Message {
     Record(RecordBatch),
     Skipped(usize),
+    // the filter stopped applying to the stream, events are sent unfiltered
+    FilterDisabled(String),
 }
 
 // Receiver should swap out channel for a new one when full
 pub enum Command {
     Skipping(usize),
+    FilterDisabled(String),
 }
 
 type Id = String;
@@ -85,10 +98,27 @@ pub struct SenderPipe {
     pub id: Id,
     inner: Sender<RecordBatch>,
     command: UnboundedSender<Command>,
+    filter: Mutex<Option<LiveTailFilter>>,
 }
 
 impl SenderPipe {
     pub fn send(&self, rb: RecordBatch) {
+        let mut filter = self.filter.lock().unwrap();
+        let rb = match filter.as_mut().map(|filter| filter.apply(&rb)) {
+            None => rb,
+            Some(Ok(Some(filtered))) => filtered,
+            Some(Ok(None)) => return,
+            Some(Err(err)) => {
+                log::warn!("livetail filter of {} disabled: {err}", self.id);
+                *filter = None;
+                self.command
+                    .send(Command::FilterDisabled(err.to_string()))
+                    .expect("receiver is not dropped before sender");
+                rb
+            }
+        };
+        drop(filter);
+
         if let Err(TrySendError::Full(rb)) = self.inner.try_send(rb) {
             self.command
                 .send(Command::Skipping(rb.num_rows()))
@@ -102,12 +132,15 @@ pub struct ReceiverPipe {
     pub stream: String,
     inner: Receiver<RecordBatch>,
     command: UnboundedReceiver<Command>,
+    // received while draining skipped batches, reported next
+    filter_disabled: Option<String>,
     _ref: Weak<LiveTailRegistry>,
 }
 
 fn channel(
     id: String,
     stream: String,
+    filter: Option<LiveTailFilter>,
     weak_ptr: Weak<LiveTailRegistry>,
 ) -> (SenderPipe, ReceiverPipe) {
     let (command_tx, command_rx) = mpsc::unbounded_channel::<Command>();
@@ -118,6 +151,7 @@ fn channel(
             id: id.clone(),
             inner: rb_tx,
             command: command_tx,
+            filter: Mutex::new(filter),
         },
         ReceiverPipe {
             id,
@@ -125,6 +159,7 @@ fn channel(
             _ref: weak_ptr,
             inner: rb_rx,
             command: command_rx,
+            filter_disabled: None,
         },
     )
 }
@@ -137,16 +172,25 @@ impl Stream for ReceiverPipe {
         cx: &mut std::task::Context<'_>,
     ) -> Poll<Option<Self::Item>> {
         let this = self.get_mut();
+        if let Some(reason) = this.filter_disabled.take() {
+            return Poll::Ready(Some(Message::FilterDisabled(reason)));
+        }
         match this.command.poll_recv(cx) {
             Poll::Ready(Some(Command::Skipping(mut row_count))) => {
                 while let Poll::Ready(Some(rb)) = this.inner.poll_recv(cx) {
                     row_count += rb.num_rows();
                 }
-                while let Poll::Ready(Some(Command::Skipping(count))) = this.command.poll_recv(cx) {
-                    row_count += count
+                while let Poll::Ready(Some(command)) = this.command.poll_recv(cx) {
+                    match command {
+                        Command::Skipping(count) => row_count += count,
+                        Command::FilterDisabled(reason) => this.filter_disabled = Some(reason),
+                    }
                 }
                 Poll::Ready(Some(Message::Skipped(row_count)))
             }
+            Poll::Ready(Some(Command::FilterDisabled(reason))) => {
+                Poll::Ready(Some(Message::FilterDisabled(reason)))
+            }
             Poll::Pending => match this.inner.poll_recv(cx) {
                 Poll::Ready(Some(rb)) => Poll::Ready(Some(Message::Record(rb))),
                 Poll::Ready(None) => Poll::Ready(None),
@@ -167,3 +211,53 @@ impl Drop for ReceiverPipe {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{cast::AsArray, types::Int64Type, Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use futures_util::{FutureExt, StreamExt};
+
+    use super::{LiveTail, LiveTailFilter, Message};
+
+    fn events(status: Vec<i64>) -> RecordBatch {
+        let schema = Schema::new(vec![Field::new("status", DataType::Int64, true)]);
+        RecordBatch::try_new(Arc::new(schema), vec![Arc::new(Int64Array::from(status))]).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn only_matching_events_arrive() {
+        let livetail = LiveTail::default();
+        let filter = LiveTailFilter::new(
+            Some("status >= 500".to_owned()),
+            None,
+            events(vec![]).schema(),
+        )
+        .unwrap();
+        let mut filtered = livetail.new_pipe("a".to_owned(), "app".to_owned(), Some(filter));
+        let mut unfiltered = livetail.new_pipe("b".to_owned(), "app".to_owned(), None);
+
+        livetail.process("app", &events(vec![200, 201]));
+        livetail.process("app", &events(vec![503, 200, 500]));
+        livetail.process("other", &events(vec![500]));
+
+        let Some(Message::Record(matched)) = filtered.next().await else {
+            panic!("expected the matching events");
+        };
+        let status: Vec<_> = matched
+            .column(0)
+            .as_primitive::<Int64Type>()
+            .values()
+            .to_vec();
+        assert_eq!(status, [503, 500]);
+        assert!(filtered.next().now_or_never().is_none());
+
+        let mut rows = 0;
+        while let Some(Some(Message::Record(batch))) = unfiltered.next().now_or_never() {
+            rows += batch.num_rows();
+        }
+        assert_eq!(rows, 5);
+    }
+}
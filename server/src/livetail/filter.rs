@@ -0,0 +1,374 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Filters and projections applied to livetail batches before they are queued
+
+use std::sync::Arc;
+
+use arrow_array::{cast::AsArray, RecordBatch};
+use arrow_schema::{ArrowError, DataType, Schema, SchemaRef};
+use datafusion::{
+    arrow::compute::filter_record_batch,
+    common::{plan_err, ToDFSchema},
+    config::ConfigOptions,
+    error::{DataFusionError, Result},
+    execution::context::{ExecutionProps, SessionState},
+    logical_expr::{
+        logical_plan::builder::table_scan, AggregateUDF, ExprSchemable, LogicalPlan, ScalarUDF,
+        TableSource, WindowUDF,
+    },
+    optimizer::analyzer::Analyzer,
+    physical_expr::{create_physical_expr, PhysicalExpr},
+    prelude::{Expr, SessionContext},
+    sql::{
+        planner::{ContextProvider, PlannerContext, SqlToRel},
+        sqlparser::{dialect::GenericDialect, parser::Parser},
+        TableReference,
+    },
+};
+use once_cell::sync::Lazy;
+
+use crate::{query::functions, utils::arrow::adapt_batch};
+
+// functions available to filters, the same ones queries can call
+static FUNCTIONS: Lazy<SessionState> =
+    Lazy::new(|| functions::session_context(SessionContext::new().state()).state());
+
+#[derive(Debug, thiserror::Error)]
+pub enum FilterError {
+    #[error("Invalid livetail filter: {0}")]
+    Invalid(#[from] DataFusionError),
+    #[error("Livetail filter must be a boolean expression, got {0}")]
+    NotBoolean(DataType),
+    #[error("Field {0} does not exist in the stream")]
+    UnknownField(String),
+    #[error("Livetail filter no longer applies to the stream schema: {0}")]
+    Schema(#[from] ArrowError),
+}
+
+/// A livetail subscription's WHERE expression and field list, compiled
+/// against the stream schema.
+///
+/// When events bring new fields the filter is compiled again against the
+/// merged schema.
+#[derive(Debug)]
+pub struct LiveTailFilter {
+    sql: Option<String>,
+    fields: Option<Vec<String>>,
+    // schema batches are adapted to before the predicate is evaluated
+    schema: SchemaRef,
+    predicate: Option<Arc<dyn PhysicalExpr>>,
+    projection: Option<Vec<usize>>,
+}
+
+impl LiveTailFilter {
+    /// Compiles `sql`, an expression as it would appear in a WHERE clause,
+    /// and the projection on `fields` against `schema`
+    pub fn new(
+        sql: Option<String>,
+        fields: Option<Vec<String>>,
+        schema: SchemaRef,
+    ) -> Result<Self, FilterError> {
+        let mut filter = Self {
+            sql,
+            fields,
+            schema,
+            predicate: None,
+            projection: None,
+        };
+        filter.compile()?;
+        Ok(filter)
+    }
+
+    fn compile(&mut self) -> Result<(), FilterError> {
+        self.predicate = self
+            .sql
+            .as_deref()
+            .map(|sql| compile_predicate(sql, &self.schema))
+            .transpose()?;
+        self.projection = self
+            .fields
+            .as_ref()
+            .map(|fields| {
+                fields
+                    .iter()
+                    .map(|field| {
+                        self.schema
+                            .index_of(field)
+                            .map_err(|_| FilterError::UnknownField(field.clone()))
+                    })
+                    .collect::<Result<_, _>>()
+            })
+            .transpose()?;
+        Ok(())
+    }
+
+    /// Schema of the batches the filter returns
+    pub fn output_schema(&self) -> SchemaRef {
+        match &self.projection {
+            Some(projection) => Arc::new(self.schema.project(projection).unwrap()),
+            None => self.schema.clone(),
+        }
+    }
+
+    /// Matching rows of `batch` with only the requested fields, None when no
+    /// row matches. Errors once the filter can't be applied to the stream anymore.
+    pub fn apply(&mut self, batch: &RecordBatch) -> Result<Option<RecordBatch>, FilterError> {
+        let batch_schema = batch.schema();
+        let fits = batch_schema.fields().iter().all(|field| {
+            self.schema
+                .field_with_name(field.name())
+                .is_ok_and(|known| known.data_type() == field.data_type())
+        });
+        if !fits {
+            self.schema = Arc::new(Schema::try_merge([
+                self.schema.as_ref().clone(),
+                batch_schema.as_ref().clone(),
+            ])?);
+            self.compile()?;
+        }
+
+        let mut batch = adapt_batch(&self.schema, batch);
+        if let Some(predicate) = &self.predicate {
+            let matches = predicate
+                .evaluate(&batch)
+                .and_then(|matches| matches.into_array(batch.num_rows()))?;
+            batch = filter_record_batch(&batch, matches.as_boolean())?;
+        }
+        if batch.num_rows() == 0 {
+            return Ok(None);
+        }
+        match &self.projection {
+            Some(projection) => Ok(Some(batch.project(projection)?)),
+            None => Ok(Some(batch)),
+        }
+    }
+}
+
+fn compile_predicate(sql: &str, schema: &SchemaRef) -> Result<Arc<dyn PhysicalExpr>, FilterError> {
+    let df_schema = schema.clone().to_dfschema()?;
+    let expr = Parser::new(&GenericDialect {})
+        .try_with_sql(sql)
+        .and_then(|mut parser| parser.parse_expr())
+        .map_err(DataFusionError::from)?;
+    let expr = SqlToRel::new(&FunctionProvider).sql_to_expr(
+        expr,
+        &df_schema,
+        &mut PlannerContext::new(),
+    )?;
+    match expr.get_type(&df_schema)? {
+        DataType::Boolean => Ok(coerce(expr, schema)?),
+        other => Err(FilterError::NotBoolean(other)),
+    }
+}
+
+// applies the same type coercion as queries, so that `status = '200'`
+// compares a number column with a number
+fn coerce(expr: Expr, schema: &Schema) -> Result<Arc<dyn PhysicalExpr>> {
+    let plan = table_scan(None::<&str>, schema, None)?
+        .filter(expr)?
+        .build()?;
+    match Analyzer::new().execute_and_check(&plan, &ConfigOptions::default(), |_, _| {})? {
+        LogicalPlan::Filter(filter) => create_physical_expr(
+            &filter.predicate,
+            filter.input.schema(),
+            &ExecutionProps::new(),
+        ),
+        _ => plan_err!("livetail filter was not planned as a filter"),
+    }
+}
+
+struct FunctionProvider;
+
+impl ContextProvider for FunctionProvider {
+    fn get_table_source(&self, name: TableReference) -> Result<Arc<dyn TableSource>> {
+        plan_err!("livetail filters can't reference table {name}")
+    }
+
+    fn get_function_meta(&self, name: &str) -> Option<Arc<ScalarUDF>> {
+        FUNCTIONS.scalar_functions().get(name).cloned()
+    }
+
+    fn get_aggregate_meta(&self, _name: &str) -> Option<Arc<AggregateUDF>> {
+        None
+    }
+
+    fn get_window_meta(&self, _name: &str) -> Option<Arc<WindowUDF>> {
+        None
+    }
+
+    fn get_variable_type(&self, _variable_names: &[String]) -> Option<DataType> {
+        None
+    }
+
+    fn options(&self) -> &ConfigOptions {
+        FUNCTIONS.config_options()
+    }
+
+    fn udfs_names(&self) -> Vec<String> {
+        FUNCTIONS.scalar_functions().keys().cloned().collect()
+    }
+
+    fn udafs_names(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn udwfs_names(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{cast::AsArray, types::Int64Type, Int64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+
+    use datafusion::{catalog::schema::MemorySchemaProvider, prelude::SessionContext};
+
+    use super::{FilterError, LiveTailFilter, FUNCTIONS};
+    use crate::query::{
+        functions::{self, FunctionKind},
+        Query,
+    };
+
+    fn schema() -> Arc<Schema> {
+        Arc::new(Schema::new(vec![
+            Field::new("status", DataType::Int64, true),
+            Field::new("host", DataType::Utf8, true),
+        ]))
+    }
+
+    fn batch(status: Vec<i64>, host: Vec<&str>) -> RecordBatch {
+        RecordBatch::try_new(
+            schema(),
+            vec![
+                Arc::new(Int64Array::from(status)),
+                Arc::new(StringArray::from(host)),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        for (sql, fields) in [
+            ("status >", None),
+            ("missing = 1", None),
+            ("status + 1", None),
+            ("sum(status) > 1", None),
+            ("status = 1", Some(vec!["missing".to_owned()])),
+        ] {
+            let filter = LiveTailFilter::new(Some(sql.to_owned()), fields, schema());
+            assert!(filter.is_err(), "{sql}");
+        }
+        assert!(matches!(
+            LiveTailFilter::new(Some("status".to_owned()), None, schema()),
+            Err(FilterError::NotBoolean(DataType::Int64))
+        ));
+    }
+
+    #[test]
+    fn filters_and_projects_rows() {
+        let mut filter = LiveTailFilter::new(
+            Some("status >= '500' AND lower(host) <> 'a'".to_owned()),
+            Some(vec!["status".to_owned()]),
+            schema(),
+        )
+        .unwrap();
+        assert_eq!(filter.output_schema().fields().len(), 1);
+
+        let filtered = filter
+            .apply(&batch(vec![200, 500, 503, 502], vec!["a", "b", "A", "c"]))
+            .unwrap()
+            .unwrap();
+        let status: Vec<_> = filtered
+            .column(0)
+            .as_primitive::<Int64Type>()
+            .values()
+            .to_vec();
+        assert_eq!(status, [500, 502]);
+
+        let none = filter.apply(&batch(vec![200], vec!["b"])).unwrap();
+        assert!(none.is_none());
+    }
+
+    #[test]
+    fn new_fields_recompile_and_conflicts_error() {
+        let mut filter =
+            LiveTailFilter::new(Some("status = 500".to_owned()), None, schema()).unwrap();
+
+        // an event with a field the stream didn't have when subscribing
+        let wider = Arc::new(Schema::new(vec![
+            Field::new("status", DataType::Int64, true),
+            Field::new("region", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            wider,
+            vec![
+                Arc::new(Int64Array::from(vec![500, 200])),
+                Arc::new(StringArray::from(vec!["eu", "us"])),
+            ],
+        )
+        .unwrap();
+        let filtered = filter.apply(&batch).unwrap().unwrap();
+        assert_eq!(filtered.num_rows(), 1);
+        assert_eq!(filtered.num_columns(), 3);
+
+        let conflicting = Arc::new(Schema::new(vec![Field::new(
+            "status",
+            DataType::Utf8,
+            true,
+        )]));
+        let batch =
+            RecordBatch::try_new(conflicting, vec![Arc::new(StringArray::from(vec!["500"]))])
+                .unwrap();
+        assert!(matches!(filter.apply(&batch), Err(FilterError::Schema(_))));
+    }
+
+    #[test]
+    fn filters_know_the_functions_queries_do() {
+        let query = Query::session_context(
+            SessionContext::new().state(),
+            Arc::new(MemorySchemaProvider::new()),
+        );
+        let listed = functions::list(&query.state());
+        assert_eq!(listed, functions::list(&FUNCTIONS));
+
+        let rolling_mean = listed
+            .iter()
+            .find(|function| function.name == "rolling_mean")
+            .unwrap();
+        assert_eq!(rolling_mean.kind, FunctionKind::Window);
+        assert_eq!(
+            rolling_mean.signatures,
+            ["any", "any, any", "any, any, any"]
+        );
+        for name in ["approx_percentile", "regexp_extract", "date_trunc", "rate"] {
+            assert!(
+                listed.iter().any(|function| function.name == name),
+                "{name}"
+            );
+        }
+
+        // registering again replaces the functions
+        functions::register_all(&query);
+        assert_eq!(functions::list(&query.state()), listed);
+    }
+}
@@ -210,6 +210,33 @@ impl LocalCacheManager {
         Ok(())
     }
 
+    /// Removes cached files of `stream`, least recently used first, until
+    /// `enough` holds or none is left and returns the bytes freed. They are
+    /// copies of uploaded files, queries read them from storage again
+    pub async fn evict_stream(
+        &self,
+        stream: &str,
+        enough: impl Fn() -> bool,
+    ) -> Result<u64, CacheError> {
+        let lock = self.semaphore.lock().await;
+        let mut cache = self.get_cache(stream).await?;
+        let mut freed = 0;
+        while !enough() {
+            let Some((_, file)) = cache.files.pop_lru() else {
+                break;
+            };
+            let Ok(metadata) = fs::metadata(&file).await else {
+                continue;
+            };
+            fs::remove_file(&file).await?;
+            cache.current_size = cache.current_size.saturating_sub(metadata.len());
+            freed += metadata.len();
+        }
+        self.put_cache(stream, &cache).await?;
+        drop(lock);
+        Ok(freed)
+    }
+
     pub async fn partition_on_cached<T>(
         &self,
         stream: &str,
@@ -266,3 +293,46 @@ pub enum CacheError {
     #[error("Error: {0}")]
     Other(&'static str),
 }
+
+#[cfg(test)]
+mod tests {
+    use fs_extra::file::CopyOptions;
+    use object_store::local::LocalFileSystem;
+    use tokio::sync::Mutex;
+
+    use super::LocalCacheManager;
+
+    #[tokio::test]
+    async fn eviction_stops_once_enough_is_freed() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = LocalCacheManager {
+            filesystem: LocalFileSystem::new(),
+            cache_path: dir.path().join("cache"),
+            cache_capacity: u64::MAX,
+            copy_options: CopyOptions {
+                overwrite: true,
+                skip_exist: false,
+                ..CopyOptions::new()
+            },
+            semaphore: Mutex::new(()),
+        };
+        let mut cached = Vec::new();
+        for name in ["first", "second", "third"] {
+            let staged = dir.path().join(format!("{name}.parquet"));
+            std::fs::write(&staged, name).unwrap();
+            manager
+                .move_to_cache("app", name.to_string(), staged)
+                .await
+                .unwrap();
+            cached.push(dir.path().join("cache/app").join(format!("{name}.parquet")));
+        }
+
+        let enough = || cached.iter().filter(|file| !file.exists()).count() >= 2;
+        let freed = manager.evict_stream("app", enough).await.unwrap();
+
+        // least recently used first
+        assert_eq!(freed, ("first".len() + "second".len()) as u64);
+        assert!(!cached[0].exists() && !cached[1].exists());
+        assert!(cached[2].exists());
+    }
+}
@@ -31,10 +31,12 @@ mod metrics;
 mod migration;
 mod oidc;
 mod option;
+mod preflight;
 mod query;
 mod querycache;
 mod rbac;
 mod response;
+mod scheduled;
 mod static_schema;
 mod stats;
 mod storage;
@@ -57,6 +59,13 @@ pub const STORAGE_UPLOAD_INTERVAL: u32 = 60;
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
 
+    if CONFIG.parseable.validate_only {
+        let storage = CONFIG.storage().get_object_store();
+        let report = preflight::run(&CONFIG.parseable, storage.as_ref()).await;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        std::process::exit(report.exit_code());
+    }
+
     // these are empty ptrs so mem footprint should be minimal
     let server: Arc<dyn ParseableServer> = match CONFIG.parseable.mode {
         Mode::Query => Arc::new(QueryServer),
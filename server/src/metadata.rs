@@ -26,6 +26,10 @@ use std::sync::{Arc, RwLock};
 
 use self::error::stream_info::{CheckAlertError, LoadError, MetadataError};
 use crate::alerts::Alerts;
+use crate::event::format::grok::GrokParser;
+use crate::event::format::protobuf::ProtobufDecoder;
+use crate::event::timestamp::TimestampExtraction;
+use crate::event::transform::Transformer;
 use crate::metrics::{
     EVENTS_INGESTED, EVENTS_INGESTED_DATE, EVENTS_INGESTED_SIZE, EVENTS_INGESTED_SIZE_DATE,
     EVENTS_STORAGE_SIZE_DATE, LIFETIME_EVENTS_INGESTED, LIFETIME_EVENTS_INGESTED_SIZE,
@@ -33,6 +37,7 @@ use crate::metrics::{
 use crate::storage::retention::Retention;
 use crate::storage::{LogStream, ObjectStorage, ObjectStoreFormat, StorageDir};
 use crate::utils::arrow::MergedRecordReader;
+use crate::utils::json::flatten::FlattenOptions;
 use derive_more::{Deref, DerefMut};
 
 // TODO: make return type be of 'static lifetime instead of cloning
@@ -54,6 +59,13 @@ pub struct LogStreamMetadata {
     pub time_partition_limit: Option<String>,
     pub custom_partition: Option<String>,
     pub static_schema_flag: Option<String>,
+    pub protobuf: Option<ProtobufDecoder>,
+    pub grok: Option<GrokParser>,
+    pub timestamp_extraction: Option<TimestampExtraction>,
+    pub flattening: Option<FlattenOptions>,
+    pub allow_type_widening: bool,
+    pub dead_letter_stream: Option<String>,
+    pub transformer: Option<Arc<Transformer>>,
 }
 
 // It is very unlikely that panic will occur when dealing with metadata.
@@ -99,6 +111,23 @@ impl StreamInfo {
             .map(|metadata| metadata.cache_enabled)
     }
 
+    pub fn type_widening_allowed(&self, stream_name: &str) -> Result<bool, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.allow_type_widening)
+    }
+
+    pub fn get_dead_letter_stream(
+        &self,
+        stream_name: &str,
+    ) -> Result<Option<String>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.dead_letter_stream.clone())
+    }
+
     pub fn get_first_event(&self, stream_name: &str) -> Result<Option<String>, MetadataError> {
         let map = self.read().expect(LOCK_EXPECT);
         map.get(stream_name)
@@ -147,6 +176,53 @@ impl StreamInfo {
             .map(|metadata| metadata.retention.clone())
     }
 
+    pub fn get_protobuf(
+        &self,
+        stream_name: &str,
+    ) -> Result<Option<ProtobufDecoder>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.protobuf.clone())
+    }
+
+    pub fn get_grok(&self, stream_name: &str) -> Result<Option<GrokParser>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.grok.clone())
+    }
+
+    pub fn get_timestamp_extraction(
+        &self,
+        stream_name: &str,
+    ) -> Result<Option<TimestampExtraction>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.timestamp_extraction.clone())
+    }
+
+    pub fn get_flattening(
+        &self,
+        stream_name: &str,
+    ) -> Result<Option<FlattenOptions>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.flattening.clone())
+    }
+
+    pub fn get_transformer(
+        &self,
+        stream_name: &str,
+    ) -> Result<Option<Arc<Transformer>>, MetadataError> {
+        let map = self.read().expect(LOCK_EXPECT);
+        map.get(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| metadata.transformer.clone())
+    }
+
     pub fn set_stream_cache(&self, stream_name: &str, enable: bool) -> Result<(), MetadataError> {
         let mut map = self.write().expect(LOCK_EXPECT);
         let stream = map
@@ -156,6 +232,28 @@ impl StreamInfo {
         Ok(())
     }
 
+    pub fn set_type_widening(&self, stream_name: &str, allow: bool) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.allow_type_widening = allow;
+        Ok(())
+    }
+
+    pub fn set_dead_letter_stream(
+        &self,
+        stream_name: &str,
+        dead_letter_stream: Option<String>,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        let stream = map
+            .get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))?;
+        stream.dead_letter_stream = dead_letter_stream;
+        Ok(())
+    }
+
     pub fn schema(&self, stream_name: &str) -> Result<Arc<Schema>, MetadataError> {
         let map = self.read().expect(LOCK_EXPECT);
         let schema = map
@@ -198,6 +296,67 @@ impl StreamInfo {
             })
     }
 
+    pub fn set_protobuf(
+        &self,
+        stream_name: &str,
+        protobuf: ProtobufDecoder,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.protobuf = Some(protobuf);
+            })
+    }
+
+    pub fn set_grok(&self, stream_name: &str, grok: GrokParser) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.grok = Some(grok);
+            })
+    }
+
+    pub fn set_timestamp_extraction(
+        &self,
+        stream_name: &str,
+        timestamp_extraction: TimestampExtraction,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.timestamp_extraction = Some(timestamp_extraction);
+            })
+    }
+
+    pub fn set_flattening(
+        &self,
+        stream_name: &str,
+        flattening: FlattenOptions,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.flattening = Some(flattening);
+            })
+    }
+
+    pub fn set_transformer(
+        &self,
+        stream_name: &str,
+        transformer: Transformer,
+    ) -> Result<(), MetadataError> {
+        let mut map = self.write().expect(LOCK_EXPECT);
+        map.get_mut(stream_name)
+            .ok_or(MetadataError::StreamMetaNotFound(stream_name.to_string()))
+            .map(|metadata| {
+                metadata.transformer = Some(Arc::new(transformer));
+            })
+    }
+
     pub fn set_first_event_at(
         &self,
         stream_name: &str,
@@ -299,6 +458,9 @@ impl StreamInfo {
 
         let schema = storage.upsert_schema_to_storage(&stream.name).await?;
         let meta = storage.upsert_stream_metadata(&stream.name).await?;
+        let protobuf = protobuf_decoder(&stream.name, &meta);
+        let grok = grok_parser(&stream.name, &meta);
+        let transformer = transformer(&stream.name, &meta);
         let retention = meta.retention;
         let schema = update_schema_from_staging(&stream.name, schema);
         let schema = HashMap::from_iter(
@@ -319,6 +481,13 @@ impl StreamInfo {
             time_partition_limit: meta.time_partition_limit,
             custom_partition: meta.custom_partition,
             static_schema_flag: meta.static_schema_flag,
+            protobuf,
+            grok,
+            timestamp_extraction: meta.timestamp_extraction,
+            flattening: meta.flattening,
+            allow_type_widening: meta.allow_type_widening,
+            dead_letter_stream: meta.dead_letter_stream,
+            transformer,
         };
 
         let mut map = self.write().expect(LOCK_EXPECT);
@@ -375,6 +544,40 @@ fn update_schema_from_staging(stream_name: &str, current_schema: Schema) -> Sche
     Schema::try_merge(vec![schema, current_schema]).unwrap()
 }
 
+// a registration that no longer decodes leaves protobuf ingestion off for the
+// stream rather than failing the load
+fn protobuf_decoder(stream_name: &str, meta: &ObjectStoreFormat) -> Option<ProtobufDecoder> {
+    let schema = meta.protobuf.clone()?;
+    ProtobufDecoder::new(schema)
+        .map_err(|err| {
+            log::warn!("protobuf descriptor of stream {stream_name} is ignored: {err}");
+        })
+        .ok()
+}
+
+// rules that no longer compile leave the stream's events as they are rather
+// than failing the load
+fn transformer(stream_name: &str, meta: &ObjectStoreFormat) -> Option<Arc<Transformer>> {
+    let rules = meta.transforms.clone()?;
+    Transformer::new(rules)
+        .map(Arc::new)
+        .map_err(|err| {
+            log::warn!("transform rules of stream {stream_name} are ignored: {err}");
+        })
+        .ok()
+}
+
+// same as protobuf, a pattern that no longer compiles leaves the text of the
+// stream's events unparsed
+fn grok_parser(stream_name: &str, meta: &ObjectStoreFormat) -> Option<GrokParser> {
+    let settings = meta.grok.clone()?;
+    GrokParser::new(settings)
+        .map_err(|err| {
+            log::warn!("grok pattern of stream {stream_name} is ignored: {err}");
+        })
+        .ok()
+}
+
 pub async fn load_stream_metadata_on_server_start(
     storage: &(impl ObjectStorage + ?Sized),
     stream_name: &str,
@@ -401,6 +604,13 @@ pub async fn load_stream_metadata_on_server_start(
         time_partition_limit: meta.time_partition_limit.clone(),
         custom_partition: meta.custom_partition.clone(),
         static_schema_flag: meta.static_schema_flag.clone(),
+        protobuf: protobuf_decoder(stream_name, meta),
+        grok: grok_parser(stream_name, meta),
+        timestamp_extraction: meta.timestamp_extraction.clone(),
+        flattening: meta.flattening.clone(),
+        allow_type_widening: meta.allow_type_widening,
+        dead_letter_stream: meta.dead_letter_stream.clone(),
+        transformer: transformer(stream_name, meta),
     };
 
     let mut map = STREAM_INFO.write().expect(LOCK_EXPECT);
@@ -22,7 +22,9 @@ pub mod storage;
 use crate::{handlers::http::metrics_path, stats::FullStats};
 use actix_web_prometheus::{PrometheusMetrics, PrometheusMetricsBuilder};
 use once_cell::sync::Lazy;
-use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry};
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+};
 
 pub const METRICS_NAMESPACE: &str = env!("CARGO_PKG_NAME");
 
@@ -172,6 +174,294 @@ pub static QUERY_CACHE_HIT: Lazy<IntCounterVec> = Lazy::new(|| {
     .expect("metric can be created")
 });
 
+pub static QUERY_RESULT_CACHE: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new("query_result_cache", "Query result cache lookups").namespace(METRICS_NAMESPACE),
+        &["stream", "result"],
+    )
+    .expect("metric can be created")
+});
+
+pub static QUERIES_RUNNING: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new("queries_running", "Queries being executed").namespace(METRICS_NAMESPACE),
+        &["class"],
+    )
+    .expect("metric can be created")
+});
+
+pub static QUERIES_QUEUED: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::with_opts(
+        Opts::new("queries_queued", "Queries waiting for a free slot").namespace(METRICS_NAMESPACE),
+    )
+    .expect("metric can be created")
+});
+
+pub static QUERIES_REJECTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "queries_rejected",
+            "Queries rejected by the concurrency limit",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["reason"],
+    )
+    .expect("metric can be created")
+});
+
+pub static DEAD_LETTER_EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "dead_letter_events",
+            "Rejected events sent to a dead letter stream",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream", "reason"],
+    )
+    .expect("metric can be created")
+});
+
+pub static TRANSFORM_RULE_TIME: Lazy<HistogramVec> = Lazy::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new(
+            "transform_rule_time",
+            "Time spent applying a transform rule to an ingested body",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream", "rule"],
+    )
+    .expect("metric can be created")
+});
+
+pub static TRANSFORM_DROPPED_FIELDS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "transform_dropped_fields",
+            "Fields removed from events by a transform rule",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream", "rule"],
+    )
+    .expect("metric can be created")
+});
+
+pub static KAFKA_RECORDS_INGESTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "kafka_records_ingested",
+            "Kafka records ingested by topic and stream",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["topic", "stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static KAFKA_RECORDS_INGESTED_SIZE: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "kafka_records_ingested_size",
+            "Bytes of Kafka records ingested by topic and stream",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["topic", "stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static KAFKA_RECORDS_REJECTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "kafka_records_rejected",
+            "Kafka records that could not be ingested by topic and stream",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["topic", "stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static KAFKA_RECORDS_CONSUMED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "kafka_records_consumed",
+            "Kafka records consumed by topic and partition, its rate is the consumption rate",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["topic", "partition"],
+    )
+    .expect("metric can be created")
+});
+
+pub static KAFKA_CONSUMER_LAG: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "kafka_consumer_lag",
+            "Records of each assigned Kafka partition after the last one consumed",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["topic", "partition"],
+    )
+    .expect("metric can be created")
+});
+
+pub static KAFKA_REBALANCES: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "kafka_rebalances",
+            "Kafka partitions assigned to and revoked from the connector by rebalances",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["topic", "partition", "event"],
+    )
+    .expect("metric can be created")
+});
+
+pub static NATS_MESSAGES_INGESTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "nats_messages_ingested",
+            "NATS messages ingested and acked by subject and stream",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["subject", "stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static NATS_MESSAGES_REJECTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "nats_messages_rejected",
+            "NATS messages terminated by subject and stream as they can't be ingested",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["subject", "stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static NATS_MESSAGES_REDELIVERED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "nats_messages_redelivered",
+            "NATS messages delivered to the connector again by subject",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["subject"],
+    )
+    .expect("metric can be created")
+});
+
+pub static NATS_CONNECTED: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::with_opts(
+        Opts::new(
+            "nats_connected",
+            "Whether the NATS connector is connected to a server",
+        )
+        .namespace(METRICS_NAMESPACE),
+    )
+    .expect("metric can be created")
+});
+
+pub static NATS_RECONNECTS: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::with_opts(
+        Opts::new(
+            "nats_reconnects",
+            "Times the NATS connector lost its connection",
+        )
+        .namespace(METRICS_NAMESPACE),
+    )
+    .expect("metric can be created")
+});
+
+pub static AMQP_MESSAGES_INGESTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "amqp_messages_ingested",
+            "AMQP messages ingested and acked by queue and stream",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["queue", "stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static AMQP_MESSAGES_REJECTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "amqp_messages_rejected",
+            "AMQP messages nacked by queue and stream as they can't be decoded",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["queue", "stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static AMQP_CONNECTED: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::with_opts(
+        Opts::new(
+            "amqp_connected",
+            "Whether the AMQP connector is connected and consuming",
+        )
+        .namespace(METRICS_NAMESPACE),
+    )
+    .expect("metric can be created")
+});
+
+pub static AMQP_RECONNECTS: Lazy<IntCounter> = Lazy::new(|| {
+    IntCounter::with_opts(
+        Opts::new(
+            "amqp_reconnects",
+            "Times the AMQP connector lost its connection or consumers",
+        )
+        .namespace(METRICS_NAMESPACE),
+    )
+    .expect("metric can be created")
+});
+
+pub static FILE_INPUT_RECORDS_INGESTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "file_input_records_ingested",
+            "Lines and multiline blocks of tailed files ingested by stream",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["stream"],
+    )
+    .expect("metric can be created")
+});
+
+pub static FILE_INPUT_FILES: Lazy<IntGauge> = Lazy::new(|| {
+    IntGauge::with_opts(
+        Opts::new("file_input_files", "Files the file input is tailing")
+            .namespace(METRICS_NAMESPACE),
+    )
+    .expect("metric can be created")
+});
+
+pub static SCHEDULED_QUERY_RUNS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new("scheduled_query_runs", "Windows run by scheduled queries")
+            .namespace(METRICS_NAMESPACE),
+        &["query", "result"],
+    )
+    .expect("metric can be created")
+});
+
+pub static SCHEDULED_QUERY_LAG: Lazy<IntGaugeVec> = Lazy::new(|| {
+    IntGaugeVec::new(
+        Opts::new(
+            "scheduled_query_lag",
+            "Seconds between now and the last window a scheduled query materialized",
+        )
+        .namespace(METRICS_NAMESPACE),
+        &["query"],
+    )
+    .expect("metric can be created")
+});
+
 pub static ALERTS_STATES: Lazy<IntCounterVec> = Lazy::new(|| {
     IntCounterVec::new(
         Opts::new("alerts_states", "Alerts States").namespace(METRICS_NAMESPACE),
@@ -226,9 +516,87 @@ fn custom_metrics(registry: &Registry) {
     registry
         .register(Box::new(QUERY_CACHE_HIT.clone()))
         .expect("metric can be registered");
+    registry
+        .register(Box::new(QUERY_RESULT_CACHE.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(QUERIES_RUNNING.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(QUERIES_QUEUED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(QUERIES_REJECTED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(DEAD_LETTER_EVENTS.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(TRANSFORM_RULE_TIME.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(TRANSFORM_DROPPED_FIELDS.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(KAFKA_RECORDS_INGESTED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(KAFKA_RECORDS_INGESTED_SIZE.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(KAFKA_RECORDS_REJECTED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(KAFKA_RECORDS_CONSUMED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(KAFKA_CONSUMER_LAG.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(KAFKA_REBALANCES.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(NATS_MESSAGES_INGESTED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(NATS_MESSAGES_REJECTED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(NATS_MESSAGES_REDELIVERED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(NATS_CONNECTED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(NATS_RECONNECTS.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(AMQP_MESSAGES_INGESTED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(AMQP_MESSAGES_REJECTED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(AMQP_CONNECTED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(AMQP_RECONNECTS.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(FILE_INPUT_RECORDS_INGESTED.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(FILE_INPUT_FILES.clone()))
+        .expect("metric can be registered");
     registry
         .register(Box::new(ALERTS_STATES.clone()))
         .expect("metric can be registered");
+    registry
+        .register(Box::new(SCHEDULED_QUERY_RUNS.clone()))
+        .expect("metric can be registered");
+    registry
+        .register(Box::new(SCHEDULED_QUERY_LAG.clone()))
+        .expect("metric can be registered");
 }
 
 pub fn build_metrics_handler() -> PrometheusMetrics {
@@ -19,13 +19,16 @@
 use std::collections::HashMap;
 
 use openid::{Client, CompactJson, CustomClaims, Discovered, StandardClaims};
+use serde::Serialize;
 use url::Url;
 
+use crate::option::redacted;
+
 pub type DiscoveredClient = Client<Discovered, Claims>;
 
 // If domain is not configured then
 // we can assume running in a development mode or private environment
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub enum Origin {
     // socket address
     Local { socket_addr: String, https: bool },
@@ -34,11 +37,12 @@ pub enum Origin {
 }
 
 /// Configuration for OpenID Connect
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct OpenidConfig {
     /// Client id
     pub id: String,
     /// Client Secret
+    #[serde(serialize_with = "redacted::serialize")]
     pub secret: String,
     /// OP host address over which discovery can be done
     pub issuer: Url,
@@ -21,14 +21,18 @@ use crate::storage::object_storage::parseable_json_path;
 use crate::storage::{FSConfig, ObjectStorageError, ObjectStorageProvider, S3Config};
 use bytes::Bytes;
 use clap::error::ErrorKind;
-use clap::{command, Args, Command, FromArgMatches};
+use clap::{command, ArgMatches, Args, Command, FromArgMatches};
 use core::fmt;
 use once_cell::sync::Lazy;
 use parquet::basic::{BrotliLevel, GzipLevel, ZstdLevel};
+use serde::Serialize;
 use std::env;
 use std::path::PathBuf;
 use std::sync::Arc;
 pub const MIN_CACHE_SIZE_BYTES: u64 = 1000u64.pow(3); // 1 GiB
+pub const DEFAULT_ROW_GROUP_SIZE: usize = 16384;
+pub const MIN_ROW_GROUP_SIZE: usize = 1024;
+pub const MAX_ROW_GROUP_SIZE: usize = 1024 * 1024;
 pub const JOIN_COMMUNITY: &str =
     "Join us on Parseable Slack community for questions : https://logg.ing/community";
 pub static CONFIG: Lazy<Arc<Config>> = Lazy::new(|| Arc::new(Config::new()));
@@ -54,6 +58,17 @@ Log Lake for the cloud-native world
             .color(clap::ColorChoice::Always)
             .get_matches();
 
+        let config = Self::from_matches(&cli);
+        if config.parseable.print_config {
+            let effective = serde_json::to_string_pretty(&config.effective_config())
+                .expect("config is serializable");
+            println!("{effective}");
+            std::process::exit(0);
+        }
+        config
+    }
+
+    fn from_matches(cli: &ArgMatches) -> Self {
         match cli.subcommand() {
             Some(("local-store", m)) => {
                 let cli = match Cli::from_arg_matches(m) {
@@ -143,6 +158,15 @@ Log Lake for the cloud-native world
         Err(ObjectStorageError::Custom(format!("Could not start the server because bucket '{}' contains stale data, please use an empty bucket and restart the server.\n{}", self.storage.get_endpoint(), JOIN_COMMUNITY)))
     }
 
+    /// Fully resolved server and storage configuration with secrets redacted
+    pub fn effective_config(&self) -> serde_json::Value {
+        serde_json::json!({
+            "storage_name": self.storage_name,
+            "parseable": self.parseable,
+            "storage": self.storage.effective_config(),
+        })
+    }
+
     pub fn storage(&self) -> Arc<dyn ObjectStorageProvider + Send + Sync> {
         self.storage.clone()
     }
@@ -214,7 +238,7 @@ Join the community at https://logg.ing/community.
         .subcommands([local, s3])
 }
 
-#[derive(Debug, Default, Eq, PartialEq)]
+#[derive(Debug, Default, Eq, PartialEq, Serialize)]
 pub enum Mode {
     Query,
     Ingest,
@@ -247,7 +271,7 @@ impl fmt::Display for Mode {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
 #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 pub enum Compression {
     UNCOMPRESSED,
@@ -274,17 +298,96 @@ impl From<Compression> for parquet::basic::Compression {
     }
 }
 
+impl Compression {
+    /// Checks the codec is compiled into the linked parquet build by writing a
+    /// single row with it, unsupported codecs would otherwise only fail on sync
+    pub fn validate(self) -> Result<(), String> {
+        use arrow_array::{ArrayRef, Int32Array, RecordBatch};
+        use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
+
+        let probe = move || -> Result<(), parquet::errors::ParquetError> {
+            let batch = RecordBatch::try_from_iter([(
+                "probe",
+                Arc::new(Int32Array::from(vec![1])) as ArrayRef,
+            )])?;
+            let props = WriterProperties::builder()
+                .set_compression(self.into())
+                .build();
+            let mut writer = ArrowWriter::try_new(Vec::new(), batch.schema(), Some(props))?;
+            writer.write(&batch)?;
+            writer.close()?;
+            Ok(())
+        };
+
+        // the parquet column writer panics instead of erroring on codecs it lacks
+        match std::panic::catch_unwind(probe) {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(err)) => Err(format!(
+                "Compression {self:?} is not supported by this build: {err}"
+            )),
+            Err(_) => Err(format!(
+                "Compression {self:?} is not supported by this build"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum TlsVersion {
+    #[default]
+    V1_2,
+    V1_3,
+}
+
+/// What happens to ingestion once disk usage crosses `max_disk_usage`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum DiskUsageAction {
+    #[default]
+    Reject,
+    Evict,
+    ReadOnly,
+}
+
+/// Which of the addresses a hostname in `P_ADDR` resolves to the server binds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum AddrResolution {
+    First,
+    #[default]
+    All,
+}
+
+/// Serializers masking secrets when the configuration is printed
+pub mod redacted {
+    use serde::Serializer;
+
+    const MASK: &str = "********";
+
+    pub fn serialize<T, S: Serializer>(_value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(MASK)
+    }
+
+    pub fn option<T, S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(_) => serializer.serialize_str(MASK),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
 pub mod validation {
     use std::{
         env, io,
-        net::ToSocketAddrs,
+        net::SocketAddr,
         path::{Path, PathBuf},
         str::FromStr,
+        time::Duration,
     };
 
     use path_clean::PathClean;
 
-    use crate::option::MIN_CACHE_SIZE_BYTES;
+    use crate::option::{
+        DEFAULT_ROW_GROUP_SIZE, MAX_ROW_GROUP_SIZE, MIN_CACHE_SIZE_BYTES, MIN_ROW_GROUP_SIZE,
+    };
     use human_size::{multiples, SpecificSize};
 
     pub fn file_path(s: &str) -> Result<PathBuf, String> {
@@ -318,17 +421,85 @@ pub mod validation {
         Ok(absolute_path(path).unwrap())
     }
 
+    // hostnames are only checked for syntax here and resolved when binding,
+    // in containers their DNS records may not exist until the pod is running
     pub fn socket_addr(s: &str) -> Result<String, String> {
-        s.to_socket_addrs()
-            .is_ok()
-            .then_some(s.to_string())
-            .ok_or_else(|| "Socket Address for server is invalid".to_string())
+        if s.parse::<SocketAddr>().is_ok() {
+            return Ok(s.to_string());
+        }
+        s.rsplit_once(':')
+            .filter(|(host, port)| {
+                !host.is_empty() && url::Host::parse(host).is_ok() && port.parse::<u16>().is_ok()
+            })
+            .map(|_| s.to_string())
+            .ok_or_else(|| {
+                format!("Socket Address for server is invalid, expected <host>:<port> but got {s}")
+            })
+    }
+
+    pub fn stream_name(s: &str) -> Result<String, String> {
+        crate::validator::stream_name(s).map_err(|err| err.to_string())?;
+        Ok(s.to_string())
+    }
+
+    pub fn file_permissions(s: &str) -> Result<u32, String> {
+        u32::from_str_radix(s.trim(), 8)
+            .ok()
+            .filter(|mode| *mode <= 0o777)
+            .ok_or_else(|| format!("Invalid permissions {s}, expected octal like 660"))
     }
 
     pub fn url(s: &str) -> Result<url::Url, String> {
         url::Url::parse(s).map_err(|_| "Invalid URL provided".to_string())
     }
 
+    pub fn cors_origin(s: &str) -> Result<String, String> {
+        let url = url::Url::parse(s.trim()).map_err(|_| {
+            format!("Invalid CORS origin {s}, expected a value like https://example.com")
+        })?;
+
+        if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+            return Err(format!(
+                "Invalid CORS origin {s}, origin must be an http(s) scheme followed by a host"
+            ));
+        }
+
+        if url.path() != "/" || url.query().is_some() || url.fragment().is_some() {
+            return Err(format!(
+                "Invalid CORS origin {s}, origin must not contain a path, query or fragment"
+            ));
+        }
+
+        Ok(url.origin().ascii_serialization())
+    }
+
+    pub fn health_check_path(s: &str) -> Result<String, String> {
+        let s = s.trim();
+        if !s.starts_with('/') || s.len() == 1 {
+            return Err(format!(
+                "Invalid health check path {s}, expected a path like /liveness"
+            ));
+        }
+
+        if s.contains(['?', '#', ' ', '{', '}']) {
+            return Err(format!(
+                "Invalid health check path {s}, path must not contain a query, fragment or pattern"
+            ));
+        }
+
+        Ok(s.trim_end_matches('/').to_string())
+    }
+
+    pub fn tls_cipher_suite(s: &str) -> Result<String, String> {
+        let s = s.trim();
+        rustls::crypto::ring::ALL_CIPHER_SUITES
+            .iter()
+            .filter_map(|suite| suite.suite().as_str())
+            .find(|name| name.eq_ignore_ascii_case(s))
+            .map(|name| name.to_string())
+            .ok_or_else(|| format!("Unsupported TLS cipher suite {s}"))
+    }
+
     fn human_size_to_bytes(s: &str) -> Result<u64, String> {
         fn parse_and_map<T: human_size::Multiple>(
             s: &str,
@@ -344,15 +515,43 @@ pub mod validation {
             .or(parse_and_map::<multiples::Terabyte>(s))
             .map_err(|_| "Could not parse given size".to_string())?;
 
-        if size < MIN_CACHE_SIZE_BYTES {
-            return Err(
-                "Specified value of cache size is smaller than current minimum of 1GiB".to_string(),
-            );
+        Ok(size)
+    }
+
+    pub fn row_group_size(s: &str) -> Result<usize, String> {
+        let size = s
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid row group size {s}, expected a number of rows"))?;
+
+        if !(MIN_ROW_GROUP_SIZE..=MAX_ROW_GROUP_SIZE).contains(&size) {
+            return Err(format!(
+                "Row group size {size} is out of range, expected {MIN_ROW_GROUP_SIZE} to {MAX_ROW_GROUP_SIZE} rows (default {DEFAULT_ROW_GROUP_SIZE})"
+            ));
         }
 
         Ok(size)
     }
 
+    pub fn memory_size(s: &str) -> Result<u64, String> {
+        human_size_to_bytes(s)
+    }
+
+    pub fn disk_usage_percent(s: &str) -> Result<f64, String> {
+        s.trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|percent| (0.0..=100.0).contains(percent) && *percent > 0.0)
+            .ok_or_else(|| {
+                format!("Invalid disk usage {s}, expected a percentage above 0 up to 100")
+            })
+    }
+
+    pub fn duration(s: &str) -> Result<Duration, String> {
+        humantime::parse_duration(s.trim())
+            .map_err(|err| format!("Invalid duration {s}, expected a value like 5m: {err}"))
+    }
+
     pub fn cache_size(s: &str) -> Result<u64, String> {
         let size = human_size_to_bytes(s)?;
         if size < MIN_CACHE_SIZE_BYTES {
@@ -363,3 +562,203 @@ pub mod validation {
         Ok(size)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{create_parseable_cli_command, validation, Compression, Config};
+
+    #[test]
+    fn row_group_size_bounds() {
+        assert!(validation::row_group_size("0")
+            .unwrap_err()
+            .contains("default 16384"));
+        assert_eq!(validation::row_group_size("262144"), Ok(262144));
+        assert_eq!(validation::row_group_size("1024"), Ok(1024));
+        assert_eq!(validation::row_group_size("1048576"), Ok(1048576));
+        assert!(validation::row_group_size("1048577").is_err());
+        assert!(validation::row_group_size("100000000").is_err());
+        assert!(validation::row_group_size("-1").is_err());
+    }
+
+    #[test]
+    fn supported_compressions_validate() {
+        for compression in [
+            Compression::UNCOMPRESSED,
+            Compression::SNAPPY,
+            Compression::GZIP,
+            Compression::BROTLI,
+            Compression::LZ4,
+            Compression::ZSTD,
+        ] {
+            assert_eq!(compression.validate(), Ok(()), "{compression:?}");
+        }
+    }
+
+    #[test]
+    fn unsupported_compression_is_rejected() {
+        // parquet-rs has no LZO codec
+        let err = Compression::LZO.validate().unwrap_err();
+        assert!(err.contains("LZO"), "{err}");
+    }
+
+    #[test]
+    fn effective_config_masks_secrets() {
+        let matches = create_parseable_cli_command()
+            .try_get_matches_from([
+                "parseable",
+                "s3-store",
+                "--username",
+                "operator",
+                "--password",
+                "hunter2",
+                "--row-group-size",
+                "4096",
+                "--endpoint-url",
+                "http://localhost:9000",
+                "--region",
+                "us-east-1",
+                "--bucket-name",
+                "logs",
+                "--access-key-id",
+                "minio",
+                "--secret-key",
+                "minio-secret",
+                "--print-config",
+            ])
+            .unwrap();
+        let config = Config::from_matches(&matches);
+        assert!(config.parseable.print_config);
+
+        let effective = config.effective_config();
+        assert_eq!(effective["storage_name"], "s3");
+        assert_eq!(effective["parseable"]["username"], "operator");
+        assert_eq!(effective["parseable"]["password"], "********");
+        assert_eq!(effective["parseable"]["row_group_size"], 4096);
+        assert_eq!(effective["parseable"]["parquet_compression"], "LZ4");
+        assert!(effective["parseable"]["open_ai_key"].is_null());
+        assert_eq!(effective["storage"]["bucket_name"], "logs");
+        assert_eq!(effective["storage"]["access_key_id"], "minio");
+        assert_eq!(effective["storage"]["secret_key"], "********");
+
+        let printed = effective.to_string();
+        assert!(!printed.contains("hunter2"));
+        assert!(!printed.contains("minio-secret"));
+    }
+
+    fn parse_local(tls_args: &[&str]) -> Result<Config, clap::Error> {
+        let args = ["parseable", "local-store"].iter().chain(tls_args);
+        create_parseable_cli_command()
+            .try_get_matches_from(args)
+            .map(|matches| Config::from_matches(&matches))
+    }
+
+    #[test]
+    fn tls_cert_and_key_are_required_together() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cert = dir.path().join("cert.pem");
+        let key = dir.path().join("key.pem");
+        std::fs::write(&cert, "").unwrap();
+        std::fs::write(&key, "").unwrap();
+        let (cert, key) = (cert.to_str().unwrap(), key.to_str().unwrap());
+
+        let both = parse_local(&["--tls-cert-path", cert, "--tls-key-path", key]).unwrap();
+        assert_eq!(both.parseable.get_scheme(), "https");
+
+        let neither = parse_local(&[]).unwrap();
+        assert_eq!(neither.parseable.get_scheme(), "http");
+
+        let err = parse_local(&["--tls-cert-path", cert]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+        assert!(err.to_string().contains("--tls-key-path"), "{err}");
+
+        let err = parse_local(&["--tls-key-path", key]).unwrap_err();
+        assert!(err.to_string().contains("--tls-cert-path"), "{err}");
+    }
+
+    #[test]
+    fn tls_pkcs12_conflicts_with_pem_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let bundle = dir.path().join("identity.p12");
+        let cert = dir.path().join("cert.pem");
+        let key = dir.path().join("key.pem");
+        for path in [&bundle, &cert, &key] {
+            std::fs::write(path, "").unwrap();
+        }
+        let (bundle, cert, key) = (
+            bundle.to_str().unwrap(),
+            cert.to_str().unwrap(),
+            key.to_str().unwrap(),
+        );
+
+        let config = parse_local(&[
+            "--tls-pkcs12-path",
+            bundle,
+            "--tls-pkcs12-password",
+            "changeit",
+        ])
+        .unwrap();
+        assert_eq!(config.parseable.get_scheme(), "https");
+        assert_eq!(
+            config.effective_config()["parseable"]["tls_pkcs12_password"],
+            "********"
+        );
+
+        let err = parse_local(&[
+            "--tls-pkcs12-path",
+            bundle,
+            "--tls-cert-path",
+            cert,
+            "--tls-key-path",
+            key,
+        ])
+        .unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+
+        let err = parse_local(&["--tls-pkcs12-password", "changeit"]).unwrap_err();
+        assert!(err.to_string().contains("--tls-pkcs12-path"), "{err}");
+    }
+
+    #[test]
+    fn address_accepts_resolvable_hostname() {
+        let config = parse_local(&["--address", "localhost:8000"]).unwrap();
+        let addrs = config.parseable.bind_addrs().unwrap();
+        assert!(!addrs.is_empty());
+        for addr in &addrs {
+            assert!(addr.ip().is_loopback(), "{addr}");
+            assert_eq!(addr.port(), 8000);
+        }
+        // port 8000 may be taken on the host running the tests
+        std::net::TcpListener::bind((addrs[0].ip(), 0)).unwrap();
+
+        let config = parse_local(&[
+            "--address",
+            "localhost:8000",
+            "--address-resolution",
+            "first",
+        ])
+        .unwrap();
+        assert_eq!(config.parseable.bind_addrs().unwrap(), addrs[..1]);
+
+        let config = parse_local(&["--address", "127.0.0.1:8000"]).unwrap();
+        assert_eq!(
+            config.parseable.bind_addrs().unwrap(),
+            ["127.0.0.1:8000".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn address_must_be_host_and_port() {
+        for address in ["localhost", "localhost:http", ":8000", "exa mple.com:8000"] {
+            let err = parse_local(&["--address", address]).unwrap_err();
+            assert_eq!(
+                err.kind(),
+                clap::error::ErrorKind::ValueValidation,
+                "{address}"
+            );
+        }
+
+        let config = parse_local(&["--address", "parseable.invalid:8000"]).unwrap();
+        let err = config.parseable.bind_addrs().unwrap_err();
+        assert!(err.to_string().contains("parseable.invalid:8000"), "{err}");
+    }
+}
@@ -0,0 +1,203 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Startup validations run by `--validate` without binding ports or starting ingestion
+
+use std::{fs, path::Path};
+
+use serde::Serialize;
+use url::Url;
+
+use crate::{cli::Cli, handlers::http::modal::ssl_acceptor::check_tls, storage::ObjectStorage};
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    name: &'static str,
+    passed: bool,
+    detail: String,
+}
+
+/// Outcome of every startup validation
+#[derive(Debug, Serialize)]
+pub struct Report {
+    passed: bool,
+    checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn exit_code(&self) -> i32 {
+        if self.passed {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+/// Runs all startup validations against the resolved options and storage
+pub async fn run(cli: &Cli, storage: &(dyn ObjectStorage + Send)) -> Report {
+    let mut checks = vec![check("staging_path", writable_dir(&cli.local_staging_path))];
+    if let Some(path) = &cli.local_cache_path {
+        checks.push(check("cache_path", writable_dir(path)));
+    }
+    if let Some(path) = &cli.query_cache_path {
+        checks.push(check("query_cache_path", writable_dir(path)));
+    }
+    checks.push(check("tls", tls(cli)));
+    if let Some(origin) = &cli.domain_address {
+        checks.push(check("origin", origin_url(origin)));
+    }
+    if !cli.ingestor_endpoint.is_empty() {
+        checks.push(check(
+            "ingestor_endpoint",
+            ingestor_endpoint(&cli.ingestor_endpoint),
+        ));
+    }
+    checks.push(check(
+        "storage",
+        storage
+            .check()
+            .await
+            .map(|_| "reachable".to_string())
+            .map_err(|err| err.to_string()),
+    ));
+
+    Report {
+        passed: checks.iter().all(|check| check.passed),
+        checks,
+    }
+}
+
+fn check(name: &'static str, result: Result<String, String>) -> Check {
+    let (passed, detail) = match result {
+        Ok(detail) => (true, detail),
+        Err(detail) => (false, detail),
+    };
+    Check {
+        name,
+        passed,
+        detail,
+    }
+}
+
+fn writable_dir(path: &Path) -> Result<String, String> {
+    let describe = |err: std::io::Error| format!("{}: {err}", path.display());
+    fs::create_dir_all(path).map_err(describe)?;
+    let probe = path.join(".parseable-validate");
+    fs::write(&probe, b"").map_err(describe)?;
+    fs::remove_file(&probe).map_err(describe)?;
+    Ok(format!("{} is writable", path.display()))
+}
+
+fn tls(cli: &Cli) -> Result<String, String> {
+    match (cli.tls_identity(), &cli.tls_cert_path, &cli.tls_key_path) {
+        (Some(identity), _, _) => check_tls(&identity, cli.tls_min_version, &cli.tls_cipher_suites)
+            .map(|_| format!("certificate {} matches its key", identity.path().display()))
+            .map_err(|err| err.to_string()),
+        (None, Some(_), None) => Err("tls cert path is set without a tls key path".to_string()),
+        (None, None, Some(_)) => Err("tls key path is set without a tls cert path".to_string()),
+        _ => Ok("disabled".to_string()),
+    }
+}
+
+fn origin_url(origin: &Url) -> Result<String, String> {
+    match (origin.scheme(), origin.host_str()) {
+        ("http" | "https", Some(_)) => Ok(origin.to_string()),
+        _ => Err(format!("{origin} is not an http(s) url with a host")),
+    }
+}
+
+fn ingestor_endpoint(endpoint: &str) -> Result<String, String> {
+    let url = Url::parse(&format!("http://{endpoint}")).map_err(|err| err.to_string())?;
+    match (url.host_str(), url.port(), url.path()) {
+        (Some(_), Some(_), "/") => Ok(endpoint.to_string()),
+        _ => Err(format!("{endpoint} is not in the form <host>:<port>")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::run;
+    use crate::{
+        cli::Cli,
+        storage::{localfs::LocalFS, StorePermits},
+    };
+
+    fn cli(dir: &TempDir) -> Cli {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+
+        Cli {
+            local_staging_path: dir.path().join("staging"),
+            tls_cert_path: Some(cert_path),
+            tls_key_path: Some(key_path),
+            domain_address: Some("https://logs.example.com".parse().unwrap()),
+            ingestor_endpoint: "ingestor-0.parseable:8000".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn storage(dir: &TempDir) -> LocalFS {
+        LocalFS::with_permits(dir.path().join("data"), StorePermits::new(1))
+    }
+
+    #[actix_web::test]
+    async fn valid_config_passes() {
+        let dir = TempDir::new().unwrap();
+        let report = run(&cli(&dir), &storage(&dir)).await;
+
+        assert_eq!(report.exit_code(), 0, "{report:?}");
+        let names: Vec<_> = report.checks.iter().map(|check| check.name).collect();
+        assert_eq!(
+            names,
+            [
+                "staging_path",
+                "tls",
+                "origin",
+                "ingestor_endpoint",
+                "storage"
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn missing_tls_key_fails() {
+        let dir = TempDir::new().unwrap();
+        let cli = Cli {
+            tls_key_path: None,
+            ..cli(&dir)
+        };
+        let report = run(&cli, &storage(&dir)).await;
+
+        assert_ne!(report.exit_code(), 0);
+        let failed: Vec<_> = report
+            .checks
+            .iter()
+            .filter(|check| !check.passed)
+            .map(|check| check.name)
+            .collect();
+        assert_eq!(failed, ["tls"]);
+    }
+}
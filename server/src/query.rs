@@ -16,20 +16,33 @@
  *
  */
 
+pub mod concurrency;
+pub mod correlation;
+pub mod explain;
 mod filter_optimizer;
+pub mod functions;
 mod listing_table_builder;
+pub mod manifest_stats;
+mod partition_filter;
+pub mod registry;
+pub mod result_cache;
 pub mod stream_schema_provider;
 
 use chrono::{DateTime, Utc};
 use chrono::{NaiveDateTime, TimeZone};
 use datafusion::arrow::record_batch::RecordBatch;
 
+use datafusion::catalog::schema::SchemaProvider;
 use datafusion::common::tree_node::{Transformed, TreeNode, TreeNodeRecursion, TreeNodeVisitor};
 use datafusion::error::DataFusionError;
 use datafusion::execution::context::SessionState;
 use datafusion::execution::disk_manager::DiskManagerConfig;
 use datafusion::execution::runtime_env::RuntimeEnv;
-use datafusion::logical_expr::{Explain, Filter, LogicalPlan, PlanType, ToStringifiedPlan};
+use datafusion::execution::SendableRecordBatchStream;
+use datafusion::logical_expr::{
+    Explain, Filter, LogicalPlan, PlanType, TableScan, TableType, ToStringifiedPlan,
+};
+use datafusion::physical_plan::{collect, execute_stream};
 use datafusion::prelude::*;
 use itertools::Itertools;
 use once_cell::sync::Lazy;
@@ -40,6 +53,8 @@ use std::sync::Arc;
 use sysinfo::System;
 
 use self::error::ExecuteError;
+use self::explain::Explanation;
+use self::registry::CancelFlag;
 use self::stream_schema_provider::GlobalSchemaProvider;
 pub use self::stream_schema_provider::PartialTimeFilter;
 use crate::event;
@@ -81,15 +96,21 @@ impl Query {
         let runtime_config = runtime_config.with_memory_limit(pool_size, fraction);
         let runtime = Arc::new(RuntimeEnv::new(runtime_config).unwrap());
 
-        let config = SessionConfig::default()
-            .with_parquet_pruning(true)
-            .with_prefer_existing_sort(true)
-            .with_round_robin_repartition(true);
-
-        let state = SessionState::new_with_config_rt(config, runtime);
+        let config = Self::session_config(CONFIG.parseable.query_threads);
         let schema_provider = Arc::new(GlobalSchemaProvider {
             storage: storage.get_object_store(),
         });
+        Self::session_context(
+            SessionState::new_with_config_rt(config, runtime),
+            schema_provider,
+        )
+    }
+
+    /// Context queries are planned in, streams are the tables of `schema_provider`
+    pub fn session_context(
+        state: SessionState,
+        schema_provider: Arc<dyn SchemaProvider>,
+    ) -> SessionContext {
         state
             .catalog_list()
             .catalog(&state.config_options().catalog.default_catalog)
@@ -99,19 +120,45 @@ impl Query {
                 schema_provider,
             )
             .unwrap();
+        functions::session_context(state)
+    }
 
-        SessionContext::new_with_state(state)
+    // partitions are executed concurrently, one per query thread
+    fn session_config(query_threads: usize) -> SessionConfig {
+        SessionConfig::default()
+            .with_target_partitions(query_threads)
+            .with_parquet_pruning(true)
+            .with_prefer_existing_sort(true)
+            .with_round_robin_repartition(true)
+            .with_option_extension(functions::FunctionOptions::default())
     }
 
+    /// Runs the query, its operators stop once `flag` is set
     pub async fn execute(
         &self,
         stream_name: String,
+        flag: &CancelFlag,
     ) -> Result<(Vec<RecordBatch>, Vec<String>), ExecuteError> {
         let time_partition = STREAM_INFO.get_time_partition(&stream_name)?;
+        let plan = self.final_logical_plan(&time_partition);
+
+        // manifests only track statistics for p_timestamp bounded streams
+        if time_partition.is_none() {
+            if let Some(batch) =
+                manifest_stats::try_execute(&QUERY_SESSION.state(), &plan, flag).await?
+            {
+                let fields = batch
+                    .schema()
+                    .fields()
+                    .iter()
+                    .map(|f| f.name())
+                    .cloned()
+                    .collect_vec();
+                return Ok((vec![batch], fields));
+            }
+        }
 
-        let df = QUERY_SESSION
-            .execute_logical_plan(self.final_logical_plan(&time_partition))
-            .await?;
+        let df = QUERY_SESSION.execute_logical_plan(plan).await?;
 
         let fields = df
             .schema()
@@ -125,10 +172,59 @@ impl Query {
             return Ok((vec![], fields));
         }
 
-        let results = df.collect().await?;
+        let task_ctx = df.task_ctx();
+        let physical_plan = flag.guard(df.create_physical_plan().await?)?;
+        let results = collect(physical_plan, Arc::new(task_ctx)).await?;
         Ok((results, fields))
     }
 
+    /// Like [`Query::execute`] but yields record batches as they are produced
+    pub async fn execute_stream(
+        &self,
+        stream_name: String,
+        flag: &CancelFlag,
+    ) -> Result<(SendableRecordBatchStream, Vec<String>), ExecuteError> {
+        let time_partition = STREAM_INFO.get_time_partition(&stream_name)?;
+
+        let df = QUERY_SESSION
+            .execute_logical_plan(self.final_logical_plan(&time_partition))
+            .await?;
+
+        let fields = df
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name())
+            .cloned()
+            .collect_vec();
+
+        let task_ctx = df.task_ctx();
+        let physical_plan = flag.guard(df.create_physical_plan().await?)?;
+        Ok((execute_stream(physical_plan, Arc::new(task_ctx))?, fields))
+    }
+
+    /// Plans the query without returning its results, see [`explain::explain`].
+    /// EXPLAIN and EXPLAIN ANALYZE statements are unwrapped, `analyze` applies to other statements.
+    pub async fn explain(
+        &self,
+        stream_name: String,
+        analyze: bool,
+    ) -> Result<Explanation, ExecuteError> {
+        let time_partition = STREAM_INFO.get_time_partition(&stream_name)?;
+        let plan = self.final_logical_plan(&time_partition);
+        let (plan, analyze) = explain::explained_plan(&plan).unwrap_or((plan, analyze));
+
+        Ok(explain::explain(&QUERY_SESSION.state(), plan, analyze).await?)
+    }
+
+    /// Whether the query is an EXPLAIN or EXPLAIN ANALYZE statement
+    pub fn is_explain(&self) -> bool {
+        matches!(
+            self.raw_logical_plan,
+            LogicalPlan::Explain(_) | LogicalPlan::Analyze(_)
+        )
+    }
+
     /// return logical plan with all time filters applied through
     fn final_logical_plan(&self, time_partition: &Option<String>) -> LogicalPlan {
         let filters = self.filter_tag.clone().and_then(tag_filter);
@@ -196,7 +292,9 @@ impl TreeNodeVisitor for TableScanVisitor {
     fn f_down(&mut self, node: &Self::Node) -> Result<TreeNodeRecursion, DataFusionError> {
         match node {
             LogicalPlan::TableScan(table) => {
-                self.tables.push(table.table_name.table().to_string());
+                if is_stream(table) {
+                    self.tables.push(table.table_name.table().to_string());
+                }
                 Ok(TreeNodeRecursion::Jump)
             }
             _ => Ok(TreeNodeRecursion::Continue),
@@ -204,6 +302,12 @@ impl TreeNodeVisitor for TableScanVisitor {
     }
 }
 
+/// Streams are base tables, the rows of table functions like `gap_fill` are
+/// generated and neither filtered by time nor authorized
+pub fn is_stream(scan: &TableScan) -> bool {
+    scan.source.table_type() == TableType::Base
+}
+
 fn tag_filter(filters: Vec<String>) -> Option<Expr> {
     filters
         .iter()
@@ -222,7 +326,7 @@ fn transform(
     time_partition: &Option<String>,
 ) -> Transformed<LogicalPlan> {
     plan.transform(&|plan| match plan {
-        LogicalPlan::TableScan(table) => {
+        LogicalPlan::TableScan(table) if is_stream(&table) => {
             let mut new_filters = vec![];
             if !table_contains_any_time_filters(&table, time_partition) {
                 let mut _start_time_filter: Expr;
@@ -404,12 +508,80 @@ pub mod error {
 
 #[cfg(test)]
 mod tests {
+    use arrow_array::{cast::AsArray, types::Int64Type, RecordBatch, TimestampMillisecondArray};
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use chrono::{DateTime, Utc};
+    use datafusion::common::tree_node::TreeNode;
+    use datafusion::datasource::MemTable;
+    use datafusion::prelude::SessionContext;
     use serde_json::json;
 
     use crate::query::flatten_objects_for_count;
 
-    use super::time_from_path;
+    use super::{functions, time_from_path, Query, TableScanVisitor};
     use std::path::PathBuf;
+    use std::sync::Arc;
+
+    #[actix_web::test]
+    async fn generated_tables_are_not_streams() {
+        let utc = |value: &str| -> DateTime<Utc> { value.parse().unwrap() };
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "p_timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            true,
+        )]));
+        let timestamps = TimestampMillisecondArray::from_iter_values(
+            ["2024-01-01T00:10:00Z", "2024-01-01T02:10:00Z"]
+                .map(|value| utc(value).timestamp_millis()),
+        );
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(timestamps)]).unwrap();
+        let ctx = functions::session_context(SessionContext::new().state());
+        ctx.register_table(
+            "app",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+
+        let raw_logical_plan = ctx
+            .state()
+            .create_logical_plan(
+                "SELECT g.bucket, count(a.p_timestamp) AS events \
+                 FROM gap_fill('1 hour', '2024-01-01T00:00:00Z', '2024-01-01T03:00:00Z') g \
+                 LEFT JOIN app a ON g.bucket = time_bucket('1 hour', a.p_timestamp) \
+                 GROUP BY g.bucket ORDER BY g.bucket",
+            )
+            .await
+            .unwrap();
+        let mut visitor = TableScanVisitor::default();
+        let _ = raw_logical_plan.visit(&mut visitor);
+        assert_eq!(visitor.into_inner(), vec!["app".to_string()]);
+
+        // only the stream is filtered by the time range of the query
+        let query = Query {
+            raw_logical_plan,
+            start: utc("2024-01-01T00:00:00Z"),
+            end: utc("2024-01-01T02:00:00Z"),
+            filter_tag: None,
+        };
+        let batches = ctx
+            .execute_logical_plan(query.final_logical_plan(&None))
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let events: Vec<i64> = batches
+            .iter()
+            .flat_map(|batch| {
+                batch
+                    .column(1)
+                    .as_primitive::<Int64Type>()
+                    .values()
+                    .to_vec()
+            })
+            .collect();
+        assert_eq!(events, vec![1, 0, 0]);
+    }
 
     #[test]
     fn test_time_from_parquet_path() {
@@ -495,4 +667,25 @@ mod tests {
         let out = flatten_objects_for_count(val.clone());
         assert_eq!(val, out);
     }
+
+    #[test]
+    fn query_threads_flow_into_session_config() {
+        use clap::FromArgMatches;
+
+        use crate::cli::Cli;
+
+        let args = ["test", "--username", "admin", "--password", "admin"];
+        let matches = Cli::create_cli_command_with_clap("test")
+            .try_get_matches_from(args.into_iter().chain(["--query-threads", "3"]))
+            .unwrap();
+        let cli = Cli::from_arg_matches(&matches).unwrap();
+        assert_eq!(cli.query_threads, 3);
+
+        let config = super::Query::session_config(cli.query_threads);
+        assert_eq!(config.target_partitions(), 3);
+
+        assert!(Cli::create_cli_command_with_clap("test")
+            .try_get_matches_from(args.into_iter().chain(["--query-threads", "0"]))
+            .is_err());
+    }
 }
@@ -0,0 +1,403 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Limits how many queries are executed at once, see `P_MAX_CONCURRENT_QUERIES`
+
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use tokio::sync::oneshot;
+
+use crate::{
+    metrics::{QUERIES_QUEUED, QUERIES_REJECTED, QUERIES_RUNNING},
+    option::CONFIG,
+};
+
+pub static QUERY_LIMITER: Lazy<QueryLimiter> = Lazy::new(|| {
+    QueryLimiter::new(
+        CONFIG.parseable.max_concurrent_queries,
+        CONFIG.parseable.query_queue_size,
+        CONFIG.parseable.query_queue_timeout,
+    )
+});
+
+/// Slots only internal queries may use, on top of the configured limit
+pub const RESERVED_SLOTS: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryClass {
+    /// Queries sent by users and dashboards
+    User,
+    /// Queries the server runs for itself, e.g. a querier fetching recent
+    /// events from ingestors, which must not starve behind user queries
+    Internal,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum QueryLimitError {
+    #[error("Too many queries, {queued} are already waiting for one of {limit} query slots")]
+    QueueFull { queued: usize, limit: usize },
+    #[error("Query timed out after {waited:?} at position {position} of {queued} waiting for one of {limit} query slots")]
+    Timeout {
+        waited: Duration,
+        position: usize,
+        queued: usize,
+        limit: usize,
+    },
+}
+
+/// Query slots handed out first come first served.
+///
+/// Queries that find every slot taken wait in a FIFO queue of at most
+/// `queue_size` entries for up to `queue_timeout`.
+#[derive(Debug)]
+pub struct QueryLimiter {
+    state: Mutex<State>,
+    queue_size: usize,
+    queue_timeout: Duration,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    limit: Option<usize>,
+    running: usize,
+    running_reserved: usize,
+    queue: VecDeque<Waiter>,
+    next_id: u64,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    wake: oneshot::Sender<()>,
+}
+
+impl State {
+    fn has_capacity(&self) -> bool {
+        self.limit.map_or(true, |limit| self.running < limit)
+    }
+
+    fn position(&self, id: u64) -> Option<usize> {
+        self.queue.iter().position(|waiter| waiter.id == id)
+    }
+
+    // hands free slots to the longest waiting queries
+    fn wake_waiters(&mut self) {
+        while self.has_capacity() {
+            let Some(waiter) = self.queue.pop_front() else {
+                break;
+            };
+            if waiter.wake.send(()).is_ok() {
+                self.running += 1;
+            }
+        }
+    }
+
+    fn report(&self) {
+        QUERIES_RUNNING
+            .with_label_values(&["user"])
+            .set(self.running as i64);
+        QUERIES_RUNNING
+            .with_label_values(&["internal"])
+            .set(self.running_reserved as i64);
+        QUERIES_QUEUED.set(self.queue.len() as i64);
+    }
+}
+
+impl QueryLimiter {
+    pub fn new(limit: Option<usize>, queue_size: usize, queue_timeout: Duration) -> Self {
+        Self {
+            state: Mutex::new(State {
+                limit,
+                ..State::default()
+            }),
+            queue_size,
+            queue_timeout,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.state().limit
+    }
+
+    /// Changes the limit of running queries, queries already running above a
+    /// lowered limit finish normally
+    pub fn set_limit(&self, limit: Option<usize>) {
+        let mut state = self.state();
+        state.limit = limit;
+        state.wake_waiters();
+        state.report();
+    }
+
+    /// Waits for a slot to execute a query in, the slot is held until the permit is dropped
+    pub async fn acquire(&self, class: QueryClass) -> Result<QueryPermit<'_>, QueryLimitError> {
+        let (id, mut wake) = {
+            let mut state = self.state();
+            if class == QueryClass::Internal && state.running_reserved < RESERVED_SLOTS {
+                state.running_reserved += 1;
+                state.report();
+                return Ok(self.permit(true));
+            }
+            if state.queue.is_empty() && state.has_capacity() {
+                state.running += 1;
+                state.report();
+                return Ok(self.permit(false));
+            }
+            let limit = state.limit.unwrap_or_default();
+            if state.queue.len() >= self.queue_size {
+                QUERIES_REJECTED.with_label_values(&["queue_full"]).inc();
+                return Err(QueryLimitError::QueueFull {
+                    queued: state.queue.len(),
+                    limit,
+                });
+            }
+            let id = state.next_id;
+            state.next_id += 1;
+            let (wake, woken) = oneshot::channel();
+            state.queue.push_back(Waiter { id, wake });
+            state.report();
+            (id, woken)
+        };
+        let queued = Queued { limiter: self, id };
+
+        let woken = tokio::time::timeout(self.queue_timeout, &mut wake).await;
+        let mut state = self.state();
+        std::mem::forget(queued);
+        // a slot may have been handed over right as the timeout fired
+        if matches!(woken, Ok(Ok(()))) || wake.try_recv().is_ok() {
+            state.report();
+            return Ok(self.permit(false));
+        }
+
+        let position = state.position(id).unwrap_or_default();
+        state.queue.remove(position);
+        state.report();
+        QUERIES_REJECTED.with_label_values(&["timeout"]).inc();
+        Err(QueryLimitError::Timeout {
+            waited: self.queue_timeout,
+            position: position + 1,
+            queued: state.queue.len() + 1,
+            limit: state.limit.unwrap_or_default(),
+        })
+    }
+
+    fn permit(&self, reserved: bool) -> QueryPermit<'_> {
+        QueryPermit {
+            limiter: self,
+            reserved,
+        }
+    }
+}
+
+/// A running query's slot, freed on drop
+#[derive(Debug)]
+pub struct QueryPermit<'a> {
+    limiter: &'a QueryLimiter,
+    reserved: bool,
+}
+
+impl Drop for QueryPermit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state();
+        if self.reserved {
+            state.running_reserved -= 1;
+        } else {
+            state.running -= 1;
+            state.wake_waiters();
+        }
+        state.report();
+    }
+}
+
+// leaves the queue, or gives back the slot it was just handed, when the
+// waiting request is dropped, e.g. because the client went away
+struct Queued<'a> {
+    limiter: &'a QueryLimiter,
+    id: u64,
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state();
+        match state.position(self.id) {
+            Some(position) => {
+                state.queue.remove(position);
+            }
+            None => {
+                state.running -= 1;
+                state.wake_waiters();
+            }
+        }
+        state.report();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use futures::FutureExt;
+    use tokio::sync::{mpsc, Notify};
+
+    use super::{QueryClass, QueryLimitError, QueryLimiter, RESERVED_SLOTS};
+
+    fn limiter(limit: usize, queue_size: usize, timeout: Duration) -> &'static QueryLimiter {
+        Box::leak(Box::new(QueryLimiter::new(
+            Some(limit),
+            queue_size,
+            timeout,
+        )))
+    }
+
+    // a query that holds its slot until `done` is notified
+    fn slow_query(
+        limiter: &'static QueryLimiter,
+        id: usize,
+        done: Arc<Notify>,
+        started: mpsc::UnboundedSender<usize>,
+    ) -> tokio::task::JoinHandle<Result<(), QueryLimitError>> {
+        tokio::spawn(async move {
+            let _permit = limiter.acquire(QueryClass::User).await?;
+            started.send(id).unwrap();
+            done.notified().await;
+            Ok(())
+        })
+    }
+
+    #[actix_web::test]
+    async fn queued_queries_run_in_arrival_order() {
+        let limiter = limiter(2, 10, Duration::from_secs(60));
+        let done = Arc::new(Notify::new());
+        let (started, mut started_rx) = mpsc::unbounded_channel();
+
+        let mut queries = vec![];
+        for id in 0..6 {
+            queries.push(slow_query(limiter, id, done.clone(), started.clone()));
+            // let each query reach the limiter before the next one arrives
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(started_rx.recv().await, Some(0));
+        assert_eq!(started_rx.recv().await, Some(1));
+        assert!(started_rx.try_recv().is_err());
+
+        for expected in 2..6 {
+            done.notify_one();
+            assert_eq!(started_rx.recv().await, Some(expected));
+        }
+        done.notify_waiters();
+        for query in queries {
+            query.await.unwrap().unwrap();
+        }
+        assert_eq!(limiter.state().running, 0);
+    }
+
+    #[actix_web::test]
+    async fn waiting_too_long_is_rejected_with_position() {
+        let limiter = limiter(1, 10, Duration::from_millis(50));
+        let _running = limiter.acquire(QueryClass::User).await.unwrap();
+
+        let first = tokio::spawn(limiter.acquire(QueryClass::User).map(|r| r.map(|_| ())));
+        tokio::task::yield_now().await;
+        let second = tokio::spawn(limiter.acquire(QueryClass::User).map(|r| r.map(|_| ())));
+
+        match first.await.unwrap() {
+            Err(QueryLimitError::Timeout {
+                position,
+                queued,
+                limit,
+                ..
+            }) => assert_eq!((position, queued, limit), (1, 2, 1)),
+            other => panic!("expected a timeout, got {other:?}"),
+        }
+        match second.await.unwrap() {
+            Err(QueryLimitError::Timeout {
+                position, queued, ..
+            }) => assert_eq!((position, queued), (1, 1)),
+            other => panic!("expected a timeout, got {other:?}"),
+        }
+        assert!(limiter.state().queue.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn full_queue_rejects_immediately() {
+        let limiter = limiter(1, 1, Duration::from_secs(60));
+        let _running = limiter.acquire(QueryClass::User).await.unwrap();
+        let _queued = tokio::spawn(limiter.acquire(QueryClass::User).map(|_| ()));
+        tokio::task::yield_now().await;
+
+        let err = limiter.acquire(QueryClass::User).await.unwrap_err();
+        assert!(matches!(
+            err,
+            QueryLimitError::QueueFull {
+                queued: 1,
+                limit: 1
+            }
+        ));
+    }
+
+    #[actix_web::test]
+    async fn internal_queries_use_reserved_slots() {
+        let limiter = limiter(1, 0, Duration::from_millis(50));
+        let _user = limiter.acquire(QueryClass::User).await.unwrap();
+        assert!(limiter.acquire(QueryClass::User).await.is_err());
+
+        let internal: Vec<_> = futures::future::join_all(
+            (0..RESERVED_SLOTS).map(|_| limiter.acquire(QueryClass::Internal)),
+        )
+        .await;
+        assert!(internal.iter().all(Result::is_ok));
+        // reserved slots are used up, further internal queries share the user limit
+        assert!(limiter.acquire(QueryClass::Internal).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn raising_the_limit_wakes_waiters() {
+        let limiter = limiter(1, 10, Duration::from_secs(60));
+        let _running = limiter.acquire(QueryClass::User).await.unwrap();
+        let waiting = tokio::spawn(limiter.acquire(QueryClass::User).map(|r| r.map(|_| ())));
+        tokio::task::yield_now().await;
+        assert_eq!(limiter.state().queue.len(), 1);
+
+        limiter.set_limit(Some(2));
+        waiting.await.unwrap().unwrap();
+        assert_eq!(limiter.limit(), Some(2));
+    }
+
+    #[actix_web::test]
+    async fn abandoned_waiter_leaves_the_queue() {
+        let limiter = limiter(1, 10, Duration::from_secs(60));
+        let running = limiter.acquire(QueryClass::User).await.unwrap();
+        let abandoned = tokio::spawn(limiter.acquire(QueryClass::User).map(|_| ()));
+        tokio::task::yield_now().await;
+        abandoned.abort();
+        let _ = abandoned.await;
+        assert!(limiter.state().queue.is_empty());
+
+        drop(running);
+        assert_eq!(limiter.state().running, 0);
+        limiter.acquire(QueryClass::User).await.unwrap();
+    }
+}
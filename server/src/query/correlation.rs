@@ -0,0 +1,299 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Executes correlations as a single join over their streams
+
+use std::ops::Bound;
+
+use arrow_array::RecordBatch;
+use arrow_schema::DataType;
+use chrono::{DateTime, Duration, Utc};
+use datafusion::{
+    common::JoinType,
+    error::{DataFusionError, Result},
+    prelude::{cast, ident, lit, DataFrame, Expr, SessionContext},
+    sql::TableReference,
+};
+
+use super::PartialTimeFilter;
+use crate::{
+    event::DEFAULT_TIMESTAMP_KEY,
+    users::correlations::{Correlation, TableConfig},
+};
+
+// output columns are named after the stream they come from
+fn column_name(stream: &str, field: &str) -> String {
+    format!("{stream}.{field}")
+}
+
+/// Joins the events of the correlation's streams between `start` and `end`.
+///
+/// Every column of the result is prefixed with its stream, e.g.
+/// `frontend.trace_id`. The range is joined `join_window` at a time so that
+/// the join only holds the events of one window in memory.
+pub async fn execute(
+    ctx: &SessionContext,
+    correlation: &Correlation,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    join_window: std::time::Duration,
+) -> Result<Vec<RecordBatch>> {
+    correlation
+        .validate()
+        .map_err(|err| DataFusionError::Plan(err.to_owned()))?;
+    let join_window = Duration::from_std(join_window)
+        .unwrap_or(Duration::max_value())
+        .max(Duration::milliseconds(1));
+
+    let mut batches = vec![];
+    let mut window_start = start;
+    while window_start < end {
+        let window_end = window_start
+            .checked_add_signed(join_window)
+            .map_or(end, |window_end| window_end.min(end));
+        let joined = join(ctx, correlation, (window_start, window_end), (start, end)).await?;
+        batches.extend(joined.collect().await?);
+        window_start = window_end;
+    }
+    Ok(batches)
+}
+
+// events of the first stream within `window`, joined with the events of the
+// other streams close enough in time to them
+async fn join(
+    ctx: &SessionContext,
+    correlation: &Correlation,
+    window: (DateTime<Utc>, DateTime<Utc>),
+    range: (DateTime<Utc>, DateTime<Utc>),
+) -> Result<DataFrame> {
+    let tolerance = correlation.time_tolerance_ms() as i64;
+    let tables = correlation.table_configs();
+    let first = &tables[0];
+    let first_timestamp = ident(column_name(&first.stream_name, DEFAULT_TIMESTAMP_KEY));
+
+    let (mut joined, mut selection) = scan(ctx, first, window).await?;
+    for table in &tables[1..] {
+        // neighbouring windows overlap by the tolerance, the time condition
+        // below still pairs each event of the first stream once
+        let reach = (
+            (window.0 - Duration::milliseconds(tolerance)).max(range.0),
+            (window.1 + Duration::milliseconds(tolerance)).min(range.1),
+        );
+        let (events, fields) = scan(ctx, table, reach).await?;
+        let timestamp = ident(column_name(&table.stream_name, DEFAULT_TIMESTAMP_KEY));
+        let on = [
+            ident(column_name(&first.stream_name, &first.join_field))
+                .eq(ident(column_name(&table.stream_name, &table.join_field))),
+            (cast(first_timestamp.clone(), DataType::Int64) - cast(timestamp, DataType::Int64))
+                .between(lit(-tolerance), lit(tolerance)),
+        ];
+        joined = joined.join_on(events, JoinType::Inner, on)?;
+        selection.extend(fields);
+    }
+
+    joined
+        .select(selection)?
+        .sort(vec![first_timestamp.sort(true, false)])
+}
+
+// events of `table` within `range` with their columns renamed after the
+// stream, and the columns selected for the result
+async fn scan(
+    ctx: &SessionContext,
+    table: &TableConfig,
+    range: (DateTime<Utc>, DateTime<Utc>),
+) -> Result<(DataFrame, Vec<Expr>)> {
+    let stream = &table.stream_name;
+    let timestamp = ident(DEFAULT_TIMESTAMP_KEY);
+    let events = ctx
+        .table(TableReference::bare(stream.as_str()))
+        .await?
+        .filter(
+            PartialTimeFilter::Low(Bound::Included(range.0.naive_utc()))
+                .binary_expr(timestamp.clone())
+                .and(
+                    PartialTimeFilter::High(Bound::Excluded(range.1.naive_utc()))
+                        .binary_expr(timestamp),
+                ),
+        )?;
+
+    let fields: Vec<String> = events
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.name().clone())
+        .collect();
+    if let Some(missing) = [&table.join_field, &DEFAULT_TIMESTAMP_KEY.to_owned()]
+        .into_iter()
+        .chain(table.selected_fields.iter().flatten())
+        .find(|field| !fields.contains(field))
+    {
+        return Err(DataFusionError::Plan(format!(
+            "Field {missing} does not exist in stream {stream}"
+        )));
+    }
+
+    let renamed = fields
+        .iter()
+        .map(|field| ident(field).alias(column_name(stream, field)))
+        .collect::<Vec<_>>();
+    let selection = table
+        .selected_fields
+        .as_ref()
+        .unwrap_or(&fields)
+        .iter()
+        .map(|field| ident(column_name(stream, field)))
+        .collect();
+    Ok((events.select(renamed)?, selection))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray,
+        types::{Int64Type, TimestampMillisecondType},
+        Int64Array, RecordBatch, StringArray, TimestampMillisecondArray,
+    };
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use chrono::{DateTime, Utc};
+    use datafusion::{datasource::MemTable, prelude::SessionContext};
+    use serde_json::json;
+
+    use super::execute;
+    use crate::users::correlations::Correlation;
+
+    fn events(ctx: &SessionContext, stream: &str, rows: &[(i64, &str, i64)]) {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("trace_id", DataType::Utf8, true),
+            Field::new("latency", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(TimestampMillisecondArray::from_iter_values(
+                    rows.iter().map(|row| row.0),
+                )),
+                Arc::new(StringArray::from_iter_values(rows.iter().map(|row| row.1))),
+                Arc::new(Int64Array::from_iter_values(rows.iter().map(|row| row.2))),
+            ],
+        )
+        .unwrap();
+        let table = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+        ctx.register_table(stream, Arc::new(table)).unwrap();
+    }
+
+    fn correlation(selected_fields: serde_json::Value) -> Correlation {
+        serde_json::from_value(json!({
+            "version": "v1",
+            "correlation_id": "c1",
+            "correlation_name": "requests",
+            "table_configs": [
+                {"stream_name": "frontend", "join_field": "trace_id"},
+                {"stream_name": "backend", "join_field": "trace_id", "selected_fields": selected_fields},
+            ],
+            "time_tolerance_ms": 1000,
+        }))
+        .unwrap()
+    }
+
+    fn context() -> SessionContext {
+        let ctx = SessionContext::new();
+        events(
+            &ctx,
+            "frontend",
+            &[(0, "a", 1), (1000, "b", 2), (1900, "d", 3), (5000, "c", 4)],
+        );
+        // c is too far from the frontend event to correlate, e has no frontend event
+        events(
+            &ctx,
+            "backend",
+            &[
+                (200, "a", 10),
+                (1500, "b", 20),
+                (2500, "d", 30),
+                (9000, "c", 40),
+                (300, "e", 50),
+            ],
+        );
+        ctx
+    }
+
+    fn at(millis: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(millis).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn joins_on_key_within_tolerance() {
+        let ctx = context();
+        let correlation = correlation(json!(["latency"]));
+
+        // windows smaller than the gap between correlated events give the same result
+        for join_window in [3_600_000, 2000, 700] {
+            let window = std::time::Duration::from_millis(join_window);
+            let batches = execute(&ctx, &correlation, at(0), at(10_000), window)
+                .await
+                .unwrap();
+            let schema = batches[0].schema();
+            let names: Vec<_> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+            assert_eq!(
+                names,
+                [
+                    "frontend.p_timestamp",
+                    "frontend.trace_id",
+                    "frontend.latency",
+                    "backend.latency"
+                ]
+            );
+
+            let mut rows = vec![];
+            for batch in &batches {
+                let timestamps = batch.column(0).as_primitive::<TimestampMillisecondType>();
+                let traces = batch.column(1).as_string::<i32>();
+                let latencies = batch.column(3).as_primitive::<Int64Type>();
+                for row in 0..batch.num_rows() {
+                    rows.push((
+                        timestamps.value(row),
+                        traces.value(row).to_owned(),
+                        latencies.value(row),
+                    ));
+                }
+            }
+            let expected = [(0, "a", 10), (1000, "b", 20), (1900, "d", 30)]
+                .map(|(time, trace, latency)| (time, trace.to_owned(), latency));
+            assert_eq!(rows, expected, "join window {join_window}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn unknown_fields_are_rejected() {
+        let ctx = context();
+        let correlation = correlation(json!(["missing"]));
+        let window = std::time::Duration::from_secs(3600);
+        let err = execute(&ctx, &correlation, at(0), at(10_000), window)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("missing"), "{err}");
+    }
+}
@@ -0,0 +1,290 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Structured output for EXPLAIN and EXPLAIN ANALYZE
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use datafusion::{
+    datasource::physical_plan::ParquetExec,
+    error::DataFusionError,
+    execution::context::SessionState,
+    logical_expr::LogicalPlan,
+    physical_plan::{collect, displayable, ExecutionPlan},
+};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Explanation {
+    pub analyzed: bool,
+    pub logical_plan: LogicalNode,
+    pub physical_plan: PhysicalNode,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LogicalNode {
+    pub operator: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<LogicalNode>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PhysicalNode {
+    pub operator: String,
+    /// Files read by a parquet scan, grouped per partition
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<Vec<String>>>,
+    /// Metrics summed over partitions, only present after ANALYZE
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<BTreeMap<String, usize>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<PhysicalNode>,
+}
+
+impl LogicalNode {
+    fn new(plan: &LogicalPlan) -> Self {
+        Self {
+            operator: plan.display().to_string(),
+            children: plan.inputs().into_iter().map(Self::new).collect(),
+        }
+    }
+}
+
+impl PhysicalNode {
+    fn new(plan: &Arc<dyn ExecutionPlan>, analyzed: bool) -> Self {
+        let files = plan.as_any().downcast_ref::<ParquetExec>().map(|exec| {
+            exec.base_config()
+                .file_groups
+                .iter()
+                .map(|group| {
+                    group
+                        .iter()
+                        .map(|file| file.object_meta.location.to_string())
+                        .collect()
+                })
+                .collect()
+        });
+        let metrics = analyzed.then(|| {
+            plan.metrics()
+                .map(|metrics| {
+                    metrics
+                        .aggregate_by_name()
+                        .timestamps_removed()
+                        .iter()
+                        .map(|metric| {
+                            (metric.value().name().to_string(), metric.value().as_usize())
+                        })
+                        .collect()
+                })
+                .unwrap_or_default()
+        });
+
+        Self {
+            operator: displayable(plan.as_ref()).one_line().to_string(),
+            files,
+            metrics,
+            children: plan
+                .children()
+                .iter()
+                .map(|child| Self::new(child, analyzed))
+                .collect(),
+        }
+    }
+}
+
+/// Plans `plan` and, when `analyze` is set, runs it to collect per operator metrics.
+///
+/// The results of the analyzed run are discarded.
+pub async fn explain(
+    state: &SessionState,
+    plan: LogicalPlan,
+    analyze: bool,
+) -> Result<Explanation, DataFusionError> {
+    let plan = state.optimize(&plan)?;
+    let physical_plan = state.create_physical_plan(&plan).await?;
+    if analyze {
+        collect(physical_plan.clone(), state.task_ctx()).await?;
+    }
+
+    Ok(Explanation {
+        analyzed: analyze,
+        logical_plan: LogicalNode::new(&plan),
+        physical_plan: PhysicalNode::new(&physical_plan, analyze),
+    })
+}
+
+/// Splits an EXPLAIN or EXPLAIN ANALYZE statement into the plan it describes
+/// and whether it asks for analysis, None for any other statement
+pub fn explained_plan(plan: &LogicalPlan) -> Option<(LogicalPlan, bool)> {
+    match plan {
+        LogicalPlan::Explain(explain) => Some((explain.plan.as_ref().clone(), false)),
+        LogicalPlan::Analyze(analyze) => Some((analyze.input.as_ref().clone(), true)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, sync::Arc};
+
+    use arrow_array::{Int64Array, RecordBatch, TimestampMillisecondArray};
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use chrono::{TimeZone, Utc};
+    use datafusion::{
+        datasource::{
+            file_format::parquet::ParquetFormat,
+            listing::{ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl},
+        },
+        prelude::SessionContext,
+    };
+    use parquet::arrow::ArrowWriter;
+
+    use super::{explain, explained_plan, PhysicalNode};
+    use crate::utils::TimePeriod;
+
+    fn scanned_files(node: &PhysicalNode) -> Vec<String> {
+        node.files
+            .iter()
+            .flatten()
+            .flatten()
+            .cloned()
+            .chain(node.children.iter().flat_map(scanned_files))
+            .collect()
+    }
+
+    fn metric(node: &PhysicalNode, name: &str) -> usize {
+        node.metrics
+            .as_ref()
+            .and_then(|m| m.get(name))
+            .copied()
+            .unwrap_or(0)
+            + node
+                .children
+                .iter()
+                .map(|child| metric(child, name))
+                .sum::<usize>()
+    }
+
+    // one parquet file per minute from 10:00 to 10:05, listed only for 10:01..10:03
+    async fn time_bounded_context(dir: &std::path::Path) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("status", DataType::Int64, false),
+        ]));
+        for minute in 0..6 {
+            let prefix = dir.join(format!("date=2024-01-01/hour=10/minute={minute:02}"));
+            std::fs::create_dir_all(&prefix).unwrap();
+            let time = Utc.with_ymd_and_hms(2024, 1, 1, 10, minute, 0).unwrap();
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(TimestampMillisecondArray::from(vec![
+                        time.timestamp_millis();
+                        10
+                    ])),
+                    Arc::new(Int64Array::from_iter_values(200..210)),
+                ],
+            )
+            .unwrap();
+            let file = File::create(prefix.join("host.data.parquet")).unwrap();
+            let mut writer = ArrowWriter::try_new(file, schema.clone(), None).unwrap();
+            writer.write(&batch).unwrap();
+            writer.close().unwrap();
+        }
+
+        let prefixes = TimePeriod::new(
+            Utc.with_ymd_and_hms(2024, 1, 1, 10, 1, 0).unwrap(),
+            Utc.with_ymd_and_hms(2024, 1, 1, 10, 3, 0).unwrap(),
+            1,
+        )
+        .generate_prefixes();
+        let urls = prefixes
+            .iter()
+            .map(|prefix| ListingTableUrl::parse(format!("{}/{}", dir.display(), prefix)).unwrap())
+            .collect();
+        let options =
+            ListingOptions::new(Arc::new(ParquetFormat::default())).with_file_extension(".parquet");
+        let config = ListingTableConfig::new_with_multi_paths(urls)
+            .with_listing_options(options)
+            .with_schema(schema);
+
+        let ctx = SessionContext::new();
+        ctx.register_table("app", Arc::new(ListingTable::try_new(config).unwrap()))
+            .unwrap();
+        ctx
+    }
+
+    #[actix_web::test]
+    async fn explain_lists_scanned_prefixes() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = time_bounded_context(dir.path()).await;
+        let plan = ctx
+            .state()
+            .create_logical_plan("EXPLAIN SELECT status FROM app WHERE status > 205")
+            .await
+            .unwrap();
+        let (plan, analyze) = explained_plan(&plan).unwrap();
+        assert!(!analyze);
+
+        let explanation = explain(&ctx.state(), plan, analyze).await.unwrap();
+        let logical_plan = serde_json::to_string(&explanation.logical_plan).unwrap();
+        assert!(logical_plan.contains("TableScan: app"), "{logical_plan}");
+
+        let files = scanned_files(&explanation.physical_plan);
+        assert_eq!(files.len(), 2, "{files:?}");
+        for minute in ["01", "02"] {
+            let prefix = format!("date=2024-01-01/hour=10/minute={minute}/");
+            assert!(files.iter().any(|file| file.contains(&prefix)), "{files:?}");
+        }
+        assert!(explanation.physical_plan.metrics.is_none());
+    }
+
+    #[actix_web::test]
+    async fn explain_analyze_reports_metrics() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = time_bounded_context(dir.path()).await;
+        let plan = ctx
+            .state()
+            .create_logical_plan("EXPLAIN ANALYZE SELECT status FROM app WHERE status > 205")
+            .await
+            .unwrap();
+        let (plan, analyze) = explained_plan(&plan).unwrap();
+        assert!(analyze);
+
+        let explanation = explain(&ctx.state(), plan, analyze).await.unwrap();
+        let json = serde_json::to_value(&explanation).unwrap();
+        assert_eq!(json["analyzed"], true);
+        assert_eq!(
+            json["physicalPlan"]["metrics"]["output_rows"].as_u64(),
+            Some(8)
+        );
+        assert!(metric(&explanation.physical_plan, "bytes_scanned") > 0);
+    }
+
+    #[actix_web::test]
+    async fn other_statements_are_not_explained() {
+        let ctx = SessionContext::new();
+        let plan = ctx.state().create_logical_plan("SELECT 1").await.unwrap();
+        assert!(explained_plan(&plan).is_none());
+    }
+}
@@ -26,6 +26,8 @@ use datafusion::{
     scalar::ScalarValue,
 };
 
+use super::is_stream;
+
 /// Rewrites logical plan for source using projection and filter
 pub struct FilterOptimizerRule {
     pub column: String,
@@ -54,7 +56,8 @@ impl OptimizerRule for FilterOptimizerRule {
         }
 
         if let LogicalPlan::TableScan(table) = plan {
-            if table.projection.is_none()
+            if !is_stream(table)
+                || table.projection.is_none()
                 || table
                     .filters
                     .iter()
@@ -0,0 +1,886 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+mod anomaly;
+mod approx_distinct;
+mod approx_percentile;
+mod approx_top_k;
+mod cumulative_extrema;
+mod cumulative_sum;
+mod delta;
+mod derivative;
+mod ewma;
+mod fuzzy;
+mod gap_fill;
+mod geohash;
+mod histogram;
+mod histogram_quantile;
+mod ip;
+mod json;
+mod over_time;
+mod rate;
+mod regexp;
+mod rolling;
+mod rolling_count;
+mod rolling_extrema;
+mod rolling_mean;
+mod rolling_mean_within;
+mod rolling_median;
+mod rolling_percentile;
+mod rolling_sum;
+mod sessionize;
+mod time_bucket;
+mod time_zone;
+mod top_k;
+mod url;
+mod user_agent;
+mod value_by;
+mod z_score;
+
+use std::sync::Arc;
+
+use arrow_schema::DataType;
+use datafusion::{
+    arrow::array::ArrayRef,
+    common::{
+        extensions_options,
+        tree_node::{Transformed, TreeNode, TreeNodeRecursion},
+    },
+    config::{ConfigExtension, ConfigOptions},
+    error::{DataFusionError, Result},
+    execution::context::SessionState,
+    logical_expr::{
+        expr::{AggregateFunction, AggregateFunctionDefinition, Sort, WindowFunction},
+        AggregateUDF, ColumnarValue, LogicalPlan, ScalarUDF, Signature, TypeSignature, Window,
+        WindowFrame, WindowFrameBound, WindowFunctionDefinition, WindowUDF,
+    },
+    optimizer::analyzer::AnalyzerRule,
+    prelude::{Expr, SessionContext},
+    scalar::ScalarValue,
+};
+use itertools::Itertools;
+
+pub use self::time_zone::in_time_zone;
+
+use self::{
+    anomaly::AnomalyZScore,
+    approx_distinct::ApproxDistinct,
+    approx_percentile::{ApproxPercentile, Percentiles},
+    approx_top_k::ApproxTopK,
+    cumulative_extrema::{CumulativeMax, CumulativeMin},
+    cumulative_sum::CumulativeSum,
+    delta::Delta,
+    derivative::Derivative,
+    ewma::EwmaUdf,
+    fuzzy::Fuzzy,
+    gap_fill::{GapFill, Interpolate, Locf},
+    geohash::{GeohashDecode, GeohashEncode},
+    histogram::Histogram,
+    histogram_quantile::HistogramQuantile,
+    ip::{IpFunction, IpMatch, IpToInt},
+    json::JsonGet,
+    over_time::{FirstOverTime, LastOverTime},
+    rate::Rate,
+    regexp::{RegexpExtract, RegexpExtractAll},
+    rolling::{Extremum, Framed},
+    rolling_count::RollingCountUdf,
+    rolling_extrema::{RollingMaxUdf, RollingMinUdf},
+    rolling_mean::{RollingMeanUdf, DEFAULT_WINDOW, MAX_WINDOW},
+    rolling_mean_within::RollingMeanWithinUdf,
+    rolling_median::RollingMedianUdf,
+    rolling_percentile::RollingPercentileUdf,
+    rolling_sum::RollingSumUdf,
+    sessionize::Sessionize,
+    time_bucket::TimeBucket,
+    top_k::{Rank, TopK},
+    url::UrlExtract,
+    user_agent::{UaExtract, UaParser},
+    value_by::ValueBy,
+    z_score::ZScoreUdf,
+};
+
+/// Context over `state` with every custom function and the analyzer rules
+/// they rely on. Every context queries or filters are planned in is created
+/// here, so that they all know the same functions.
+pub fn session_context(state: SessionState) -> SessionContext {
+    let ctx = SessionContext::new_with_state(add_analyzer_rules(state));
+    register_all(&ctx);
+    ctx
+}
+
+/// Register all custom functions on the given session context, registering
+/// them again replaces them
+pub fn register_all(ctx: &SessionContext) {
+    ctx.register_udaf(AggregateUDF::from(ApproxDistinct::new()));
+    ctx.register_udaf(AggregateUDF::from(ApproxTopK::new()));
+    ctx.register_udaf(AggregateUDF::from(Histogram::new()));
+    ctx.register_udaf(AggregateUDF::from(HistogramQuantile::new()));
+    for approx_percentile in ApproxPercentile::all() {
+        ctx.register_udaf(AggregateUDF::from(approx_percentile));
+    }
+    for value_by in ValueBy::all() {
+        ctx.register_udaf(AggregateUDF::from(value_by));
+    }
+    for top_k in TopK::all() {
+        ctx.register_udaf(AggregateUDF::from(top_k));
+    }
+    register_window_functions(ctx);
+    ctx.register_udf(ScalarUDF::from(RegexpExtract::new()));
+    ctx.register_udf(ScalarUDF::from(RegexpExtractAll::new()));
+    ctx.register_udf(ScalarUDF::from(TimeBucket::new()));
+    ctx.register_udtf("gap_fill", Arc::new(GapFill));
+    ctx.register_udf(ScalarUDF::from(IpMatch::new(IpFunction::InCidr)));
+    ctx.register_udf(ScalarUDF::from(IpMatch::new(IpFunction::ContainsAny)));
+    ctx.register_udf(ScalarUDF::from(IpToInt::new()));
+    ctx.register_udf(ScalarUDF::from(GeohashEncode::new()));
+    ctx.register_udf(ScalarUDF::from(GeohashDecode::new()));
+    for json_get in JsonGet::all() {
+        ctx.register_udf(ScalarUDF::from(json_get));
+    }
+    for fuzzy in Fuzzy::all() {
+        ctx.register_udf(ScalarUDF::from(fuzzy));
+    }
+    for url_extract in UrlExtract::all() {
+        ctx.register_udf(ScalarUDF::from(url_extract));
+    }
+    let ua_parser = Arc::new(UaParser::new());
+    for ua_extract in UaExtract::all(ua_parser) {
+        ctx.register_udf(ScalarUDF::from(ua_extract));
+    }
+}
+
+/// Register the custom window functions, the rolling and analytic ones, on
+/// the given session context. Their literal arguments are only validated
+/// while planning in contexts with [`add_analyzer_rules`].
+pub fn register_window_functions(ctx: &SessionContext) {
+    ctx.register_udwf(WindowUDF::from(Sessionize::new()));
+    ctx.register_udwf(WindowUDF::from(AnomalyZScore::new()));
+    ctx.register_udwf(WindowUDF::from(RollingMeanUdf::new()));
+    ctx.register_udwf(WindowUDF::from(RollingSumUdf::new()));
+    ctx.register_udwf(WindowUDF::from(RollingMinUdf::new()));
+    ctx.register_udwf(WindowUDF::from(RollingMaxUdf::new()));
+    ctx.register_udwf(WindowUDF::from(RollingMedianUdf::new()));
+    ctx.register_udwf(WindowUDF::from(RollingCountUdf::new()));
+    ctx.register_udwf(WindowUDF::from(EwmaUdf::new()));
+    ctx.register_udwf(WindowUDF::from(RollingPercentileUdf::new()));
+    ctx.register_udwf(WindowUDF::from(RollingMeanWithinUdf::new()));
+    ctx.register_udwf(WindowUDF::from(ZScoreUdf::new()));
+    ctx.register_udwf(WindowUDF::from(Rate::new()));
+    ctx.register_udwf(WindowUDF::from(Delta::new()));
+    ctx.register_udwf(WindowUDF::from(Derivative::new()));
+    ctx.register_udwf(WindowUDF::from(CumulativeSum::new()));
+    ctx.register_udwf(WindowUDF::from(CumulativeMin::new()));
+    ctx.register_udwf(WindowUDF::from(CumulativeMax::new()));
+    ctx.register_udwf(WindowUDF::from(FirstOverTime::new()));
+    ctx.register_udwf(WindowUDF::from(LastOverTime::new()));
+    ctx.register_udwf(WindowUDF::from(Locf::new()));
+    ctx.register_udwf(WindowUDF::from(Interpolate::new()));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FunctionKind {
+    Scalar,
+    Aggregate,
+    Window,
+}
+
+/// A function registered in a session, as listed for autocompletion
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub kind: FunctionKind,
+    pub aliases: Vec<String>,
+    /// argument types of each accepted call, `any` standing for any type
+    pub signatures: Vec<String>,
+}
+
+// argument lists accepted by `signature`, the array specific ones only by name
+fn describe(signature: &TypeSignature) -> Vec<String> {
+    let join = |types: &[DataType]| types.iter().map(ToString::to_string).join(", ");
+    match signature {
+        TypeSignature::Any(count) => vec![vec!["any"; *count].join(", ")],
+        TypeSignature::Exact(types) => vec![join(types)],
+        TypeSignature::Uniform(count, types) => types
+            .iter()
+            .map(|data_type| vec![data_type.to_string(); *count].join(", "))
+            .collect(),
+        TypeSignature::Variadic(types) => types
+            .iter()
+            .map(|data_type| format!("{data_type}, ..."))
+            .collect(),
+        TypeSignature::VariadicEqual | TypeSignature::VariadicAny => vec!["any, ...".to_string()],
+        TypeSignature::OneOf(signatures) => signatures.iter().flat_map(describe).collect(),
+        TypeSignature::ArraySignature(signature) => vec![signature.to_string()],
+    }
+}
+
+fn info(name: &str, kind: FunctionKind, aliases: &[String], signature: &Signature) -> FunctionInfo {
+    FunctionInfo {
+        name: name.to_owned(),
+        kind,
+        aliases: aliases.to_vec(),
+        signatures: describe(&signature.type_signature),
+    }
+}
+
+/// Functions registered in `state` by name, built-in ones included. Aliases
+/// are listed with the function rather than on their own.
+pub fn list(state: &SessionState) -> Vec<FunctionInfo> {
+    let scalar = state
+        .scalar_functions()
+        .iter()
+        .filter(|(name, udf)| *name == udf.name())
+        .map(|(name, udf)| info(name, FunctionKind::Scalar, udf.aliases(), udf.signature()));
+    let aggregate = state
+        .aggregate_functions()
+        .iter()
+        .filter(|(name, udaf)| *name == udaf.name())
+        .map(|(name, udaf)| {
+            info(
+                name,
+                FunctionKind::Aggregate,
+                udaf.aliases(),
+                udaf.signature(),
+            )
+        });
+    let window = state
+        .window_functions()
+        .iter()
+        .filter(|(name, udwf)| *name == udwf.name())
+        .map(|(name, udwf)| info(name, FunctionKind::Window, udwf.aliases(), udwf.signature()));
+    scalar
+        .chain(aggregate)
+        .chain(window)
+        .sorted_by(|a, b| a.name.cmp(&b.name))
+        .collect()
+}
+
+extensions_options! {
+    /// Session options of the custom functions, set with `SET parseable.<option> = <value>`
+    pub struct FunctionOptions {
+        /// Window of the rolling functions called without one
+        pub rolling_default_window: usize, default = DEFAULT_WINDOW
+    }
+}
+
+impl ConfigExtension for FunctionOptions {
+    const PREFIX: &'static str = "parseable";
+}
+
+/// Add the analyzer rules custom functions rely on to the given session state
+pub fn add_analyzer_rules(state: SessionState) -> SessionState {
+    state
+        .add_analyzer_rule(Arc::new(ValidateLiteralArgs))
+        .add_analyzer_rule(Arc::new(AscendingRollingWindows))
+        .add_analyzer_rule(Arc::new(FramedRollingWindows))
+        .add_analyzer_rule(Arc::new(DefaultRollingWindows))
+}
+
+/// Validates literal arguments of custom aggregate and window functions while planning.
+///
+/// Unlike scalar UDFs, which validate their literals in `simplify`, these have
+/// no planning hook of their own.
+struct ValidateLiteralArgs;
+
+impl ValidateLiteralArgs {
+    fn validate(expr: &Expr) -> Result<()> {
+        let (name, args) = match expr {
+            Expr::AggregateFunction(AggregateFunction {
+                func_def: AggregateFunctionDefinition::UDF(udf),
+                args,
+                ..
+            }) => (udf.name(), args),
+            Expr::WindowFunction(WindowFunction {
+                fun: WindowFunctionDefinition::WindowUDF(udwf),
+                args,
+                ..
+            }) => (udwf.name(), args),
+            _ => return Ok(()),
+        };
+        match name {
+            "approx_distinct" => approx_distinct::validate_args(args),
+            "histogram" => histogram::validate_args(args),
+            "histogram_quantile" => histogram_quantile::validate_args(args),
+            "approx_percentile" => approx_percentile::validate_args(Percentiles::One, args),
+            "approx_percentiles" => approx_percentile::validate_args(Percentiles::Many, args),
+            "top_k" => top_k::validate_args(Rank::Largest, args),
+            "top_k_frequent" => top_k::validate_args(Rank::Frequent, args),
+            "sessionize" => sessionize::validate_args(args),
+            "anomaly_zscore" => anomaly::validate_args(args),
+            "rolling_mean" => rolling_mean::validate_args(args),
+            "rolling_sum" => rolling_sum::validate_args(args),
+            "rolling_min" => rolling_extrema::validate_args(Extremum::Min, args),
+            "rolling_max" => rolling_extrema::validate_args(Extremum::Max, args),
+            "rolling_median" => rolling_median::validate_args(args),
+            "rolling_count" => rolling_count::validate_args(args),
+            "ewma" => ewma::validate_args(args),
+            "rolling_percentile" => rolling_percentile::validate_args(args),
+            "rolling_mean_within" => rolling_mean_within::validate_args(args),
+            "z_score" => z_score::validate_args(args),
+            "delta" => delta::validate_args(args),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl AnalyzerRule for ValidateLiteralArgs {
+    fn analyze(&self, plan: LogicalPlan, _config: &ConfigOptions) -> Result<LogicalPlan> {
+        plan.apply(&mut |node| {
+            node.inspect_expressions(|expr| {
+                expr.apply(&mut |expr| {
+                    Self::validate(expr)?;
+                    Ok(TreeNodeRecursion::Continue)
+                })
+                .map(|_| ())
+            })?;
+            Ok(TreeNodeRecursion::Continue)
+        })?;
+        Ok(plan)
+    }
+
+    fn name(&self) -> &str {
+        "validate_literal_args"
+    }
+}
+
+/// Window functions whose window is made of the rows before the current one
+const ROLLING_FUNCTIONS: [&str; 8] = [
+    "rolling_mean",
+    "rolling_sum",
+    "rolling_min",
+    "rolling_max",
+    "rolling_median",
+    "rolling_count",
+    "rolling_percentile",
+    "rolling_mean_within",
+];
+
+/// Evaluates rolling functions over a descending window order in ascending order.
+///
+/// Rolling windows look back in time, but over `ORDER BY time DESC` the rows
+/// before the current one are the later ones. Reversing the order, the frame
+/// as written again takes the rows earlier in time, while the query's own
+/// ORDER BY still decides the order rows come out in.
+struct AscendingRollingWindows;
+
+impl AscendingRollingWindows {
+    // the rolling function call `expr` in reverse order, if that is descending
+    fn reverse(expr: &Expr) -> Option<Expr> {
+        let Expr::WindowFunction(
+            function @ WindowFunction {
+                fun: WindowFunctionDefinition::WindowUDF(udwf),
+                order_by,
+                ..
+            },
+        ) = expr
+        else {
+            return None;
+        };
+        if !ROLLING_FUNCTIONS.contains(&udwf.name())
+            || !matches!(order_by.first(), Some(Expr::Sort(Sort { asc: false, .. })))
+        {
+            return None;
+        }
+        let order_by = order_by
+            .iter()
+            .map(|sort| match sort {
+                Expr::Sort(Sort {
+                    expr,
+                    asc,
+                    nulls_first,
+                }) => Expr::Sort(Sort::new(expr.clone(), !asc, !nulls_first)),
+                other => other.clone(),
+            })
+            .collect();
+        Some(Expr::WindowFunction(WindowFunction {
+            order_by,
+            ..function.clone()
+        }))
+    }
+}
+
+impl AnalyzerRule for AscendingRollingWindows {
+    fn analyze(&self, plan: LogicalPlan, _config: &ConfigOptions) -> Result<LogicalPlan> {
+        plan.transform(&|plan| {
+            let LogicalPlan::Window(window) = plan else {
+                return Ok(Transformed::no(plan));
+            };
+            let mut kept = Vec::new();
+            let mut reversed = Vec::new();
+            for expr in &window.window_expr {
+                match Self::reverse(expr) {
+                    // under its former name, which the plan above refers to it by
+                    Some(reversed_expr) => reversed.push(reversed_expr.alias(expr.display_name()?)),
+                    None => kept.push(expr.clone()),
+                }
+            }
+            if reversed.is_empty() {
+                return Ok(Transformed::no(LogicalPlan::Window(window)));
+            }
+
+            // the expressions of a window node share their order, the others
+            // are evaluated in a node of their own below
+            let input = if kept.is_empty() {
+                window.input
+            } else {
+                Arc::new(LogicalPlan::Window(Window::try_new(kept, window.input)?))
+            };
+            Ok(Transformed::yes(LogicalPlan::Window(Window::try_new(
+                reversed, input,
+            )?)))
+        })
+        .map(|transformed| transformed.data)
+    }
+
+    fn name(&self) -> &str {
+        "ascending_rolling_windows"
+    }
+}
+
+/// Evaluates rolling functions over the window frame when the call gives one.
+///
+/// Without a frame a rolling function takes the last `window` rows up to the
+/// current one. A frame that doesn't reach back to the partition start or
+/// ends after the current row, like `ROWS BETWEEN 1 PRECEDING AND 1 FOLLOWING`,
+/// is taken as written and the window holds the last values in it. Frames over
+/// the whole partition, the default without ORDER BY, keep to the rows up to
+/// the current one.
+struct FramedRollingWindows;
+
+impl FramedRollingWindows {
+    fn uses_frame(frame: &WindowFrame) -> bool {
+        let from_start =
+            matches!(&frame.start_bound, WindowFrameBound::Preceding(rows) if rows.is_null());
+        let to_current = match &frame.end_bound {
+            WindowFrameBound::CurrentRow => true,
+            WindowFrameBound::Following(rows) => rows.is_null(),
+            WindowFrameBound::Preceding(_) => false,
+        };
+        !(from_start && to_current)
+    }
+
+    // the rolling function calls in `expr` evaluated over their frame
+    fn frame(expr: Expr) -> Result<Transformed<Expr>> {
+        expr.transform(&|expr| {
+            let Expr::WindowFunction(mut function) = expr else {
+                return Ok(Transformed::no(expr));
+            };
+            let framed = match &function.fun {
+                WindowFunctionDefinition::WindowUDF(udwf)
+                    if ROLLING_FUNCTIONS.contains(&udwf.name())
+                        && Self::uses_frame(&function.window_frame) =>
+                {
+                    WindowUDF::new_from_impl(Framed::new(udwf.clone()))
+                }
+                _ => return Ok(Transformed::no(Expr::WindowFunction(function))),
+            };
+            function.fun = WindowFunctionDefinition::WindowUDF(Arc::new(framed));
+            Ok(Transformed::yes(Expr::WindowFunction(function)))
+        })
+    }
+}
+
+impl AnalyzerRule for FramedRollingWindows {
+    fn analyze(&self, plan: LogicalPlan, _config: &ConfigOptions) -> Result<LogicalPlan> {
+        plan.transform(&|plan| {
+            let LogicalPlan::Window(window) = plan else {
+                return Ok(Transformed::no(plan));
+            };
+            let mut framed = false;
+            let mut window_expr = Vec::with_capacity(window.window_expr.len());
+            for expr in &window.window_expr {
+                let transformed = Self::frame(expr.clone())?;
+                framed |= transformed.transformed;
+                window_expr.push(transformed.data);
+            }
+            if !framed {
+                return Ok(Transformed::no(LogicalPlan::Window(window)));
+            }
+            // the functions keep their names, so does the node's schema
+            Ok(Transformed::yes(LogicalPlan::Window(Window::try_new(
+                window_expr,
+                window.input,
+            )?)))
+        })
+        .map(|transformed| transformed.data)
+    }
+
+    fn name(&self) -> &str {
+        "framed_rolling_windows"
+    }
+}
+
+/// Passes the session's `parseable.rolling_default_window` to the rolling
+/// functions called without a window, which otherwise default to 300.
+///
+/// Runs after the rules that look for the calls at the top of the window node,
+/// the calls given a window are renamed back to what the plan above knows them by.
+struct DefaultRollingWindows;
+
+impl DefaultRollingWindows {
+    // position of the window argument of the functions defaulting it
+    fn window_index(function: &str) -> Option<usize> {
+        match function {
+            "rolling_mean" | "rolling_sum" | "rolling_min" | "rolling_max" | "rolling_median"
+            | "rolling_count" | "z_score" => Some(1),
+            "rolling_percentile" => Some(2),
+            _ => None,
+        }
+    }
+
+    fn with_window(expr: Expr, window: usize) -> Result<Transformed<Expr>> {
+        expr.transform(&|expr| {
+            let Expr::WindowFunction(mut function) = expr else {
+                return Ok(Transformed::no(expr));
+            };
+            let index = match &function.fun {
+                WindowFunctionDefinition::WindowUDF(udwf) => Self::window_index(udwf.name()),
+                _ => None,
+            };
+            if index != Some(function.args.len()) {
+                return Ok(Transformed::no(Expr::WindowFunction(function)));
+            }
+            function
+                .args
+                .push(Expr::Literal(ScalarValue::Int64(Some(window as i64))));
+            Ok(Transformed::yes(Expr::WindowFunction(function)))
+        })
+    }
+}
+
+impl AnalyzerRule for DefaultRollingWindows {
+    fn analyze(&self, plan: LogicalPlan, config: &ConfigOptions) -> Result<LogicalPlan> {
+        let window = config
+            .extensions
+            .get::<FunctionOptions>()
+            .map_or(DEFAULT_WINDOW, |options| options.rolling_default_window);
+        // the functions default to it themselves
+        if window == DEFAULT_WINDOW {
+            return Ok(plan);
+        }
+        if !(1..=MAX_WINDOW as usize).contains(&window) {
+            return Err(DataFusionError::Plan(format!(
+                "parseable.rolling_default_window must be between 1 and {MAX_WINDOW}, got {window}"
+            )));
+        }
+
+        plan.transform(&|plan| {
+            let LogicalPlan::Window(node) = plan else {
+                return Ok(Transformed::no(plan));
+            };
+            let mut defaulted = false;
+            let mut window_expr = Vec::with_capacity(node.window_expr.len());
+            for expr in &node.window_expr {
+                let transformed = Self::with_window(expr.clone(), window)?;
+                if !transformed.transformed {
+                    window_expr.push(transformed.data);
+                    continue;
+                }
+                defaulted = true;
+                window_expr.push(match transformed.data {
+                    aliased @ Expr::Alias(_) => aliased,
+                    // under its former name, which the plan above refers to it by
+                    defaulted_expr => defaulted_expr.alias(expr.display_name()?),
+                });
+            }
+            if !defaulted {
+                return Ok(Transformed::no(LogicalPlan::Window(node)));
+            }
+            Ok(Transformed::yes(LogicalPlan::Window(Window::try_new(
+                window_expr,
+                node.input,
+            )?)))
+        })
+        .map(|transformed| transformed.data)
+    }
+
+    fn name(&self) -> &str {
+        "default_rolling_windows"
+    }
+}
+
+/// Fails planning unless the argument at `index` (if present) is a literal
+fn require_literal(function: &str, args: &[Expr], index: usize) -> Result<()> {
+    match args.get(index) {
+        None | Some(Expr::Literal(_)) => Ok(()),
+        Some(expr) => Err(DataFusionError::Plan(format!(
+            "{function} expects argument {} to be a literal, got {expr}",
+            index + 1
+        ))),
+    }
+}
+
+/// Scalar value of a literal argument at `index`, if present
+fn literal_arg<'a>(
+    function: &str,
+    args: &'a [ColumnarValue],
+    index: usize,
+) -> Result<Option<&'a ScalarValue>> {
+    match args.get(index) {
+        None => Ok(None),
+        Some(ColumnarValue::Scalar(value)) => Ok(Some(value)),
+        Some(ColumnarValue::Array(_)) => Err(DataFusionError::Execution(format!(
+            "{function} expects argument {} to be a literal",
+            index + 1
+        ))),
+    }
+}
+
+/// Number of rows for a scalar function call, scalar only calls are evaluated as one row
+fn num_rows(args: &[ColumnarValue]) -> usize {
+    args.iter()
+        .find_map(|arg| match arg {
+            ColumnarValue::Array(array) => Some(array.len()),
+            ColumnarValue::Scalar(_) => None,
+        })
+        .unwrap_or(1)
+}
+
+/// Wraps a computed array back into a scalar when every argument was a scalar
+fn to_columnar_value(args: &[ColumnarValue], result: ArrayRef) -> Result<ColumnarValue> {
+    if args
+        .iter()
+        .all(|arg| matches!(arg, ColumnarValue::Scalar(_)))
+    {
+        Ok(ColumnarValue::Scalar(ScalarValue::try_from_array(
+            &result, 0,
+        )?))
+    } else {
+        Ok(ColumnarValue::Array(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray, types::Float64Type, Float64Array, RecordBatch, TimestampMillisecondArray,
+    };
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use datafusion::{
+        datasource::MemTable,
+        prelude::{SessionConfig, SessionContext},
+    };
+
+    use super::{add_analyzer_rules, register_window_functions, FunctionOptions};
+
+    #[actix_web::test]
+    async fn window_functions_resolve_in_sql() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("value", DataType::Float64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(TimestampMillisecondArray::from(vec![0, 60_000, 120_000])),
+                Arc::new(Float64Array::from(vec![1.0, 4.0, 2.0])),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        register_window_functions(&ctx);
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+
+        for call in [
+            "rolling_mean(value)",
+            "rolling_sum(value, 2)",
+            "rolling_min(value)",
+            "rolling_max(value)",
+            "rolling_median(value)",
+            "rolling_count(value)",
+            "rolling_percentile(value, 0.9)",
+            "rolling_mean_within(value, p_timestamp, '5m')",
+            "z_score(value)",
+            "anomaly_zscore(value, 2)",
+            "ewma(value, 0.5)",
+            "rate(value, p_timestamp)",
+            "delta(value)",
+            "derivative(value, p_timestamp)",
+            "cumulative_sum(value)",
+            "cumulative_min(value)",
+            "cumulative_max(value)",
+            "first_over_time(value, p_timestamp)",
+            "last_over_time(value, p_timestamp)",
+            "sessionize(p_timestamp, INTERVAL '30 minutes')",
+        ] {
+            let sql = format!("SELECT {call} OVER (ORDER BY p_timestamp) FROM metrics");
+            let batches = ctx
+                .sql(&sql)
+                .await
+                .unwrap_or_else(|err| panic!("{call}: {err}"))
+                .collect()
+                .await
+                .unwrap_or_else(|err| panic!("{call}: {err}"));
+            let rows: usize = batches.iter().map(RecordBatch::num_rows).sum();
+            assert_eq!(rows, 3, "{call}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn rolling_windows_over_descending_order_look_back_in_time() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("value", DataType::Float64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(TimestampMillisecondArray::from_iter_values(
+                    (0..6).map(|minute| minute * 60_000),
+                )),
+                Arc::new(Float64Array::from(vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0])),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        register_window_functions(&ctx);
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+
+        let sql = "SELECT \
+                rolling_sum(value, 3) OVER descending, \
+                rolling_max(value, 3) OVER descending, \
+                first_value(value) OVER descending, \
+                rolling_sum(value, 3) OVER (ORDER BY p_timestamp) \
+            FROM metrics \
+            WINDOW descending AS (ORDER BY p_timestamp DESC ROWS BETWEEN 2 PRECEDING AND CURRENT ROW) \
+            ORDER BY p_timestamp DESC";
+        let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        let column = |index: usize| -> Vec<f64> {
+            batches
+                .iter()
+                .flat_map(|batch| {
+                    batch
+                        .column(index)
+                        .as_primitive::<Float64Type>()
+                        .values()
+                        .to_vec()
+                })
+                .collect()
+        };
+
+        // newest row first, each window holds the row and the two before it in time
+        assert_eq!(column(0), [56.0, 28.0, 14.0, 7.0, 3.0, 1.0]);
+        assert_eq!(column(1), [32.0, 16.0, 8.0, 4.0, 2.0, 1.0]);
+        assert_eq!(column(0), column(3));
+        // built-in functions keep the frame of the descending order
+        assert_eq!(column(2), [32.0, 32.0, 32.0, 16.0, 8.0, 4.0]);
+    }
+
+    #[actix_web::test]
+    async fn rolling_windows_default_to_the_session_option() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("seq", DataType::Float64, false),
+            Field::new("value", DataType::Float64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Float64Array::from(vec![0.0, 1.0, 2.0, 3.0])),
+                Arc::new(Float64Array::from(vec![1.0, 4.0, 2.0, 8.0])),
+            ],
+        )
+        .unwrap();
+        let ctx = || {
+            let config = SessionConfig::new().with_option_extension(FunctionOptions::default());
+            let ctx = SessionContext::new_with_state(add_analyzer_rules(
+                SessionContext::new_with_config(config).state(),
+            ));
+            register_window_functions(&ctx);
+            ctx.register_table(
+                "metrics",
+                Arc::new(MemTable::try_new(schema.clone(), vec![vec![batch.clone()]]).unwrap()),
+            )
+            .unwrap();
+            ctx
+        };
+        let sql = "SELECT \
+                rolling_mean(value) OVER (ORDER BY seq), \
+                rolling_mean(value, 3) OVER (ORDER BY seq) \
+            FROM metrics ORDER BY seq";
+        let query = |ctx: SessionContext| async move {
+            let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+            let names: Vec<String> = batches[0]
+                .schema()
+                .fields()
+                .iter()
+                .map(|field| field.name().clone())
+                .collect();
+            let column = |index: usize| -> Vec<f64> {
+                batches
+                    .iter()
+                    .flat_map(|batch| {
+                        batch
+                            .column(index)
+                            .as_primitive::<Float64Type>()
+                            .values()
+                            .to_vec()
+                    })
+                    .collect()
+            };
+            (names, column(0), column(1))
+        };
+
+        let (names, defaulted, given) = query(ctx()).await;
+        assert_eq!(defaulted, [1.0, 2.5, 7.0 / 3.0, 15.0 / 4.0]);
+
+        let tuned = ctx();
+        tuned
+            .sql("SET parseable.rolling_default_window = 2")
+            .await
+            .unwrap();
+        let (tuned_names, tuned_defaulted, tuned_given) = query(tuned).await;
+        assert_eq!(tuned_defaulted, [1.0, 2.5, 3.0, 5.0]);
+        // calls passing a window keep it, and the columns keep their names
+        assert_eq!(tuned_given, given);
+        assert_eq!(tuned_names, names);
+
+        let invalid = ctx();
+        invalid
+            .sql("SET parseable.rolling_default_window = 0")
+            .await
+            .unwrap();
+        let err = invalid
+            .sql(sql)
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("between 1 and"), "{err}");
+    }
+}
@@ -0,0 +1,281 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{cast::AsArray, types::Float64Type, ArrayRef, Float64Array};
+use arrow_schema::DataType;
+use datafusion::arrow::compute::cast;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, TypeSignature, Volatility, WindowUDFImpl},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::{require_literal, rolling::TrailingWindow};
+
+/// Largest trailing window accepted by anomaly_zscore
+pub const MAX_WINDOW: i64 = 100_000;
+
+/// Window size and minimum number of points, the minimum defaults to the window size
+fn window_args(window: &ScalarValue, min_points: Option<&ScalarValue>) -> Result<(usize, usize)> {
+    let window = match window {
+        ScalarValue::Int64(Some(window)) if (2..=MAX_WINDOW).contains(window) => *window,
+        other => {
+            return Err(DataFusionError::Plan(format!(
+                "anomaly_zscore expects a window between 2 and {MAX_WINDOW}, got {other}"
+            )))
+        }
+    };
+    let min_points = match min_points {
+        None => window,
+        Some(ScalarValue::Int64(Some(min))) if (2..=window).contains(min) => *min,
+        Some(other) => {
+            return Err(DataFusionError::Plan(format!(
+            "anomaly_zscore expects a minimum between 2 and the window size {window}, got {other}"
+        )))
+        }
+    };
+    Ok((window as usize, min_points as usize))
+}
+
+/// Checks the window and minimum points of an anomaly_zscore call while planning
+pub fn validate_args(args: &[Expr]) -> Result<()> {
+    for index in 1..args.len() {
+        require_literal("anomaly_zscore", args, index)?;
+    }
+    let literal = |index| match args.get(index) {
+        Some(Expr::Literal(value)) => Some(value),
+        _ => None,
+    };
+    match literal(1) {
+        Some(window) => window_args(window, literal(2)).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+/// `anomaly_zscore(value, window [, min_points])`
+///
+/// Scores each row by `(value - mean) / stddev` of the `window` non NULL
+/// values preceding it in the window order. The current row is left out of
+/// its own baseline, so a spike scores high instead of raising the mean it
+/// is compared against.
+///
+/// Returns NULL for NULL values, while fewer than `min_points` (default
+/// `window`) values precede the row, and when the baseline has zero variance
+/// since any deviation from a constant series would score infinitely high.
+#[derive(Debug)]
+pub struct AnomalyZScore {
+    signature: Signature,
+}
+
+impl AnomalyZScore {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::one_of(
+                vec![TypeSignature::Any(2), TypeSignature::Any(3)],
+                Volatility::Immutable,
+            ),
+        }
+    }
+}
+
+impl WindowUDFImpl for AnomalyZScore {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "anomaly_zscore"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        if !arg_types[0].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "anomaly_zscore expects a numeric value, got {}",
+                arg_types[0]
+            )));
+        }
+        Ok(DataType::Float64)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(AnomalyZScoreEvaluator))
+    }
+}
+
+#[derive(Debug)]
+struct AnomalyZScoreEvaluator;
+
+impl PartitionEvaluator for AnomalyZScoreEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+        if num_rows == 0 {
+            return Ok(Arc::new(Float64Array::from(Vec::<f64>::new())));
+        }
+        let window = ScalarValue::try_from_array(&values[1], 0)?;
+        let min_points = values
+            .get(2)
+            .map(|min| ScalarValue::try_from_array(min, 0))
+            .transpose()?;
+        let (size, min_points) = window_args(&window, min_points.as_ref())?;
+
+        let input = cast(&values[0], &DataType::Float64)?;
+        let mut window = TrailingWindow::new(size);
+        let scores = input
+            .as_primitive::<Float64Type>()
+            .iter()
+            .map(|value| {
+                let value = value?;
+                let stats = window.stats();
+                let score = match (stats.mean(), stats.stddev()) {
+                    (Some(mean), Some(stddev))
+                        if stats.count() >= min_points as u64 && stddev > 0.0 =>
+                    {
+                        Some((value - mean) / stddev)
+                    }
+                    _ => None,
+                };
+                window.push(value);
+                score
+            })
+            .collect::<Float64Array>();
+        Ok(Arc::new(scores))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{f64::consts::FRAC_1_SQRT_2, sync::Arc};
+
+    use arrow_array::{cast::AsArray, types::Float64Type, Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{datasource::MemTable, logical_expr::WindowUDF, prelude::SessionContext};
+
+    use super::AnomalyZScore;
+    use crate::query::functions::add_analyzer_rules;
+
+    // steady around 11, one spike to 50 and a level shift to 20
+    const SERIES: [i64; 15] = [10, 12, 10, 12, 10, 50, 12, 10, 12, 10, 20, 20, 20, 20, 20];
+
+    fn context() -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("seq", DataType::Int64, false),
+            Field::new("latency", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(0..SERIES.len() as i64)),
+                Arc::new(Int64Array::from(SERIES.to_vec())),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        ctx.register_udwf(WindowUDF::from(AnomalyZScore::new()));
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn scores(
+        ctx: &SessionContext,
+        call: &str,
+    ) -> datafusion::error::Result<Vec<Option<f64>>> {
+        let sql = format!("SELECT {call} OVER (ORDER BY seq) FROM metrics ORDER BY seq");
+        let batches = ctx.sql(&sql).await?.collect().await?;
+        Ok(batches
+            .iter()
+            .flat_map(|batch| batch.column(0).as_primitive::<Float64Type>().iter())
+            .collect())
+    }
+
+    fn assert_scores(actual: &[Option<f64>], expected: &[Option<f64>]) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (actual, expected)) in actual.iter().zip(expected).enumerate() {
+            match (actual, expected) {
+                (Some(actual), Some(expected)) => {
+                    assert!(
+                        (actual - expected).abs() < 1e-5,
+                        "row {i}: {actual} != {expected}"
+                    )
+                }
+                _ => assert_eq!(actual, expected, "row {i}"),
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn scores_spike_and_level_shift() {
+        let ctx = context();
+        let scores = scores(&ctx, "anomaly_zscore(latency, 4)").await.unwrap();
+
+        // each baseline is the sample mean and stddev of the four previous values,
+        // e.g. the spike is compared against 12, 10, 12, 10: (50 - 11) / 1.154701
+        let expected = [
+            None,
+            None,
+            None,
+            None,
+            Some(-0.866025),
+            Some(33.774991),
+            Some(-0.431708),
+            Some(-0.568290),
+            Some(-0.431708),
+            Some(-0.568290),
+            Some(7.794229),
+            Some(1.470294),
+            Some(0.855528),
+            Some(0.5),
+            // baseline of four 20s has no variance
+            None,
+        ];
+        assert_scores(&scores, &expected);
+    }
+
+    #[actix_web::test]
+    async fn min_points_allows_partial_windows() {
+        let ctx = context();
+        let scores = scores(&ctx, "anomaly_zscore(latency, 4, 2)").await.unwrap();
+        // 10 against 10, 12: (10 - 11) / 1.414214
+        assert_scores(
+            &scores[..4],
+            &[None, None, Some(-FRAC_1_SQRT_2), Some(1.154701)],
+        );
+    }
+
+    #[actix_web::test]
+    async fn window_is_validated_while_planning() {
+        let ctx = context();
+        for call in [
+            "anomaly_zscore(latency, 1)",
+            "anomaly_zscore(latency, 4, 5)",
+            "anomaly_zscore(latency, seq)",
+        ] {
+            let err = scores(&ctx, call).await.unwrap_err();
+            assert!(err.to_string().contains("anomaly_zscore"), "{call}: {err}");
+        }
+    }
+}
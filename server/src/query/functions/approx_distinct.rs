@@ -0,0 +1,564 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::any::Any;
+
+use arrow_array::{
+    cast::AsArray,
+    types::{Int64Type, UInt64Type},
+    Array, ArrayRef,
+};
+use arrow_schema::DataType;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{Accumulator, AggregateUDFImpl, Signature, TypeSignature, Volatility},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+use xxhash_rust::xxh3::xxh3_64;
+
+use super::require_literal;
+
+pub const DEFAULT_PRECISION: u8 = 14;
+pub const MIN_PRECISION: u8 = 4;
+pub const MAX_PRECISION: u8 = 18;
+
+pub fn validate_args(args: &[Expr]) -> Result<()> {
+    require_literal("approx_distinct", args, 1)?;
+    match args.get(1) {
+        Some(Expr::Literal(precision)) => precision_literal(precision).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+// a NULL precision falls back to the default
+fn precision_literal(precision: &ScalarValue) -> Result<u8> {
+    match precision {
+        ScalarValue::Int64(None) => Ok(DEFAULT_PRECISION),
+        ScalarValue::Int64(Some(value))
+            if (MIN_PRECISION as i64..=MAX_PRECISION as i64).contains(value) =>
+        {
+            Ok(*value as u8)
+        }
+        other => Err(DataFusionError::Plan(format!(
+            "approx_distinct precision must be between {MIN_PRECISION} and {MAX_PRECISION}, got {other}"
+        ))),
+    }
+}
+
+/// `approx_distinct(col [, precision])`
+///
+/// Estimates the number of distinct non null values using a HyperLogLog sketch
+/// with `2^precision` registers. Relative standard error is about `1.04 / sqrt(2^precision)`.
+#[derive(Debug)]
+pub struct ApproxDistinct {
+    signature: Signature,
+}
+
+impl ApproxDistinct {
+    pub fn new() -> Self {
+        let input_types = [
+            DataType::Utf8,
+            DataType::LargeUtf8,
+            DataType::Int64,
+            DataType::UInt64,
+            DataType::Binary,
+            DataType::LargeBinary,
+        ];
+        let signatures = input_types
+            .iter()
+            .flat_map(|t| {
+                [
+                    TypeSignature::Exact(vec![t.clone()]),
+                    TypeSignature::Exact(vec![t.clone(), DataType::Int64]),
+                ]
+            })
+            .collect();
+
+        Self {
+            signature: Signature::one_of(signatures, Volatility::Immutable),
+        }
+    }
+}
+
+impl AggregateUDFImpl for ApproxDistinct {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "approx_distinct"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::UInt64)
+    }
+
+    fn accumulator(&self, _arg: &DataType) -> Result<Box<dyn Accumulator>> {
+        Ok(Box::<HyperLogLogAccumulator>::default())
+    }
+
+    fn state_type(&self, _return_type: &DataType) -> Result<Vec<DataType>> {
+        Ok(vec![DataType::Binary])
+    }
+}
+
+/// Dense HyperLogLog sketch, one byte per register
+#[derive(Debug, Clone, PartialEq)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub fn new(precision: u8) -> Result<Self> {
+        if !(MIN_PRECISION..=MAX_PRECISION).contains(&precision) {
+            return Err(DataFusionError::Plan(format!(
+                "approx_distinct precision must be between {MIN_PRECISION} and {MAX_PRECISION}, got {precision}"
+            )));
+        }
+
+        Ok(Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        })
+    }
+
+    fn from_registers(registers: &[u8]) -> Result<Self> {
+        let precision = registers.len().trailing_zeros() as u8;
+        if !registers.len().is_power_of_two()
+            || !(MIN_PRECISION..=MAX_PRECISION).contains(&precision)
+        {
+            return Err(DataFusionError::Internal(format!(
+                "invalid approx_distinct state of {} registers",
+                registers.len()
+            )));
+        }
+
+        Ok(Self {
+            precision,
+            registers: registers.to_vec(),
+        })
+    }
+
+    pub fn add_hash(&mut self, hash: u64) {
+        let index = (hash >> (64 - self.precision)) as usize;
+        // rank is the position of the first set bit in the remaining bits, capped so
+        // that a hash of all zeros still lands in the last bucket
+        let rest = hash << self.precision;
+        let rank = (rest.leading_zeros() as u8).min(64 - self.precision) + 1;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    pub fn add(&mut self, bytes: &[u8]) {
+        self.add_hash(xxh3_64(bytes))
+    }
+
+    pub fn merge(&mut self, other: &HyperLogLog) -> Result<()> {
+        if self.precision != other.precision {
+            return Err(DataFusionError::Execution(format!(
+                "cannot merge approx_distinct sketches of precision {} and {}",
+                self.precision, other.precision
+            )));
+        }
+
+        self.registers
+            .iter_mut()
+            .zip(other.registers.iter())
+            .for_each(|(this, other)| *this = (*this).max(*other));
+
+        Ok(())
+    }
+
+    // Cardinality estimate using the improved estimator from Otmar Ertl,
+    // "New cardinality estimation algorithms for HyperLogLog sketches" (2017).
+    // It stays unbiased over the whole range without empirical bias tables
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let q = 64 - self.precision as usize;
+
+        let mut histogram = vec![0u32; q + 2];
+        for register in &self.registers {
+            histogram[*register as usize] += 1;
+        }
+
+        let mut z = m * tau((m - histogram[q + 1] as f64) / m);
+        for count in histogram[1..=q].iter().rev() {
+            z = 0.5 * (z + *count as f64);
+        }
+        z += m * sigma(histogram[0] as f64 / m);
+
+        let alpha = 0.5 / std::f64::consts::LN_2;
+        (alpha * m * m / z).round() as u64
+    }
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1. {
+        return f64::INFINITY;
+    }
+    let mut y = 1.;
+    let mut z = x;
+    loop {
+        x *= x;
+        let prev = z;
+        z += x * y;
+        y += y;
+        if z == prev {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0. || x == 1. {
+        return 0.;
+    }
+    let mut y = 1.;
+    let mut z = 1. - x;
+    loop {
+        x = x.sqrt();
+        let prev = z;
+        y *= 0.5;
+        z -= (1. - x).powi(2) * y;
+        if z == prev {
+            return z / 3.;
+        }
+    }
+}
+
+/// The sketch is created lazily as the precision argument is only known once values arrive
+#[derive(Debug, Default)]
+pub struct HyperLogLogAccumulator {
+    hll: Option<HyperLogLog>,
+}
+
+impl HyperLogLogAccumulator {
+    fn sketch(&mut self, precision: u8) -> Result<&mut HyperLogLog> {
+        if let Some(hll) = &self.hll {
+            if hll.precision != precision {
+                return Err(DataFusionError::Execution(
+                    "approx_distinct precision must be a constant".to_string(),
+                ));
+            }
+        } else {
+            self.hll = Some(HyperLogLog::new(precision)?);
+        }
+        Ok(self.hll.as_mut().expect("sketch is initialized"))
+    }
+}
+
+fn precision_arg(values: &[ArrayRef]) -> Result<u8> {
+    let Some(precision) = values.get(1) else {
+        return Ok(DEFAULT_PRECISION);
+    };
+    if precision.is_empty() {
+        return Ok(DEFAULT_PRECISION);
+    }
+    precision_literal(&ScalarValue::try_from_array(precision, 0)?)
+}
+
+impl Accumulator for HyperLogLogAccumulator {
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        let array = &values[0];
+        if array.is_empty() {
+            return Ok(());
+        }
+        let hll = self.sketch(precision_arg(values)?)?;
+
+        match array.data_type() {
+            DataType::Utf8 => array
+                .as_string::<i32>()
+                .iter()
+                .flatten()
+                .for_each(|v| hll.add(v.as_bytes())),
+            DataType::LargeUtf8 => array
+                .as_string::<i64>()
+                .iter()
+                .flatten()
+                .for_each(|v| hll.add(v.as_bytes())),
+            DataType::Binary => array
+                .as_binary::<i32>()
+                .iter()
+                .flatten()
+                .for_each(|v| hll.add(v)),
+            DataType::LargeBinary => array
+                .as_binary::<i64>()
+                .iter()
+                .flatten()
+                .for_each(|v| hll.add(v)),
+            DataType::Int64 => array
+                .as_primitive::<Int64Type>()
+                .iter()
+                .flatten()
+                .for_each(|v| hll.add(&v.to_le_bytes())),
+            DataType::UInt64 => array
+                .as_primitive::<UInt64Type>()
+                .iter()
+                .flatten()
+                .for_each(|v| hll.add(&v.to_le_bytes())),
+            other => {
+                return Err(DataFusionError::Execution(format!(
+                    "approx_distinct does not support input of type {other}"
+                )))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(&mut self) -> Result<ScalarValue> {
+        let estimate = self.hll.as_ref().map(HyperLogLog::estimate).unwrap_or(0);
+        Ok(ScalarValue::UInt64(Some(estimate)))
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self
+                .hll
+                .as_ref()
+                .map(|hll| hll.registers.capacity())
+                .unwrap_or(0)
+    }
+
+    fn state(&mut self) -> Result<Vec<ScalarValue>> {
+        let registers = self.hll.as_ref().map(|hll| hll.registers.clone());
+        Ok(vec![ScalarValue::Binary(registers)])
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        for registers in states[0].as_binary::<i32>().iter().flatten() {
+            let other = HyperLogLog::from_registers(registers)?;
+            match &mut self.hll {
+                Some(hll) => hll.merge(&other)?,
+                None => self.hll = Some(other),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Array, ArrayRef, Int64Array, RecordBatch, StringArray, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{
+        datasource::MemTable,
+        logical_expr::{Accumulator, AggregateUDF},
+        prelude::SessionContext,
+        scalar::ScalarValue,
+    };
+
+    use super::{ApproxDistinct, HyperLogLogAccumulator};
+    use crate::query::functions::add_analyzer_rules;
+
+    fn estimate(values: ArrayRef) -> u64 {
+        let mut acc = HyperLogLogAccumulator::default();
+        acc.update_batch(&[values]).unwrap();
+        match acc.evaluate().unwrap() {
+            ScalarValue::UInt64(Some(v)) => v,
+            v => panic!("unexpected {v:?}"),
+        }
+    }
+
+    fn relative_error(estimate: u64, exact: u64) -> f64 {
+        (estimate as f64 - exact as f64).abs() / exact as f64
+    }
+
+    #[test]
+    fn estimate_within_error_bounds() {
+        for cardinality in [1_000i64, 100_000, 1_000_000] {
+            // every value appears twice so duplicates are exercised as well
+            let values: Int64Array = (0..cardinality * 2).map(|i| i % cardinality).collect();
+            let estimate = estimate(Arc::new(values));
+            let error = relative_error(estimate, cardinality as u64);
+            assert!(
+                error < 0.03,
+                "estimate {estimate} for {cardinality} has error {error}"
+            );
+
+            let values: StringArray = (0..cardinality)
+                .map(|i| Some(format!("user-{i}")))
+                .collect();
+            let estimate = estimate_strings(values);
+            let error = relative_error(estimate, cardinality as u64);
+            assert!(
+                error < 0.03,
+                "estimate {estimate} for {cardinality} has error {error}"
+            );
+        }
+    }
+
+    fn estimate_strings(values: StringArray) -> u64 {
+        estimate(Arc::new(values))
+    }
+
+    #[test]
+    fn nulls_are_ignored() {
+        let values = StringArray::from(vec![Some("a"), None, Some("b"), None, Some("a")]);
+        assert_eq!(estimate_strings(values), 2);
+
+        let values = StringArray::from(vec![None::<&str>, None]);
+        assert_eq!(estimate_strings(values), 0);
+    }
+
+    #[test]
+    fn merge_matches_single_pass() {
+        let values: Int64Array = (0..100_000).collect();
+        let single = estimate(Arc::new(values.clone()));
+
+        let mut merged = HyperLogLogAccumulator::default();
+        for chunk in 0..4 {
+            let mut partial = HyperLogLogAccumulator::default();
+            partial
+                .update_batch(&[Arc::new(values.slice(chunk * 25_000, 25_000))])
+                .unwrap();
+            let state = partial.state().unwrap();
+            let state = state[0].to_array().unwrap();
+            merged.merge_batch(&[state]).unwrap();
+        }
+
+        assert_eq!(
+            merged.evaluate().unwrap(),
+            ScalarValue::UInt64(Some(single))
+        );
+    }
+
+    #[test]
+    fn precision_out_of_range_errors() {
+        let mut acc = HyperLogLogAccumulator::default();
+        let values: ArrayRef = Arc::new(Int64Array::from(vec![1, 2]));
+        let precision: ArrayRef = Arc::new(Int64Array::from(vec![30, 30]));
+        assert!(acc.update_batch(&[values, precision]).is_err());
+    }
+
+    #[actix_web::test]
+    async fn approx_distinct_in_sql() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("user_id", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec!["a", "a", "b", "b"])),
+                Arc::new(Int64Array::from(vec![Some(1), Some(2), Some(2), None])),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_udaf(AggregateUDF::from(ApproxDistinct::new()));
+        ctx.register_table(
+            "logs",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+
+        let batches = ctx
+            .sql("SELECT host, approx_distinct(user_id, 10) AS users FROM logs GROUP BY host ORDER BY host")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let users = batches[0]
+            .column(1)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(users.values(), &[2, 1]);
+        assert_eq!(users.len(), 2);
+    }
+
+    // 100k user ids with 10k distinct, spread over partitions so that the
+    // estimate goes through merging partial sketches
+    fn user_ids() -> (Arc<Schema>, Vec<Vec<RecordBatch>>) {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "user_id",
+            DataType::Utf8,
+            true,
+        )]));
+        let partitions = (0..4)
+            .map(|partition| {
+                let ids: StringArray = (partition * 25_000..(partition + 1) * 25_000)
+                    .map(|i| Some(format!("user-{}", (i * 7_919) % 10_000)))
+                    .collect();
+                vec![RecordBatch::try_new(schema.clone(), vec![Arc::new(ids)]).unwrap()]
+            })
+            .collect();
+        (schema, partitions)
+    }
+
+    fn context() -> SessionContext {
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        ctx.register_udaf(AggregateUDF::from(ApproxDistinct::new()));
+        let (schema, partitions) = user_ids();
+        ctx.register_table(
+            "logs",
+            Arc::new(MemTable::try_new(schema, partitions).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    #[actix_web::test]
+    async fn estimate_over_partitions() {
+        let ctx = context();
+        for query in [
+            "SELECT approx_distinct(user_id) FROM logs",
+            "SELECT approx_distinct(user_id, 16) FROM logs",
+        ] {
+            let batches = ctx.sql(query).await.unwrap().collect().await.unwrap();
+            let estimate = batches[0]
+                .column(0)
+                .as_any()
+                .downcast_ref::<UInt64Array>()
+                .unwrap()
+                .value(0);
+            let error = relative_error(estimate, 10_000);
+            assert!(error < 0.03, "{query} estimated {estimate}, error {error}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn precision_is_validated_while_planning() {
+        let ctx = context();
+        for query in [
+            "SELECT approx_distinct(user_id, 3) FROM logs",
+            "SELECT approx_distinct(user_id, 19) FROM logs",
+            "SELECT approx_distinct(user_id, CAST(length(user_id) AS BIGINT)) FROM logs",
+        ] {
+            let err = match ctx.sql(query).await {
+                Ok(df) => df.collect().await.unwrap_err(),
+                Err(e) => e,
+            };
+            assert!(
+                err.to_string().contains("approx_distinct"),
+                "{query}: {err}"
+            );
+        }
+    }
+}
@@ -0,0 +1,757 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, f64::consts::PI, sync::Arc};
+
+use arrow_array::{cast::AsArray, types::Float64Type, Array, ArrayRef, Float64Array, ListArray};
+use arrow_schema::{DataType, Field};
+use datafusion::arrow::{buffer::OffsetBuffer, compute::cast};
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{Accumulator, AggregateUDFImpl, Signature, TypeSignature, Volatility},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::require_literal;
+
+/// Compression of the digest when the call doesn't pass one
+pub const DEFAULT_COMPRESSION: i64 = 100;
+pub const MIN_COMPRESSION: i64 = 10;
+pub const MAX_COMPRESSION: i64 = 10_000;
+
+/// Percentiles asked for in one call, either a single one or a list of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Percentiles {
+    One,
+    Many,
+}
+
+impl Percentiles {
+    fn name(self) -> &'static str {
+        match self {
+            Percentiles::One => "approx_percentile",
+            Percentiles::Many => "approx_percentiles",
+        }
+    }
+
+    // the percentiles out of the literal second argument
+    fn parse(self, value: &ScalarValue) -> Result<Vec<f64>> {
+        match (self, value) {
+            (Percentiles::One, value) => self.parse_items(std::slice::from_ref(value)),
+            (Percentiles::Many, ScalarValue::List(list)) if list.len() == 1 && list.is_valid(0) => {
+                let values = list.value(0);
+                let items = (0..values.len())
+                    .map(|index| ScalarValue::try_from_array(&values, index))
+                    .collect::<Result<Vec<_>>>()?;
+                self.parse_items(&items)
+            }
+            (Percentiles::Many, other) => Err(DataFusionError::Plan(format!(
+                "approx_percentiles expects a list of percentiles, got {other}"
+            ))),
+        }
+    }
+
+    fn parse_items(self, items: &[ScalarValue]) -> Result<Vec<f64>> {
+        if items.is_empty() {
+            return Err(DataFusionError::Plan(
+                "approx_percentiles expects at least one percentile".to_string(),
+            ));
+        }
+        items
+            .iter()
+            .map(|percentile| match percentile.cast_to(&DataType::Float64) {
+                Ok(ScalarValue::Float64(Some(value))) if (0.0..=1.0).contains(&value) => Ok(value),
+                _ => Err(DataFusionError::Plan(format!(
+                    "{} expects percentiles between 0 and 1, got {percentile}",
+                    self.name()
+                ))),
+            })
+            .collect()
+    }
+}
+
+fn compression_arg(function: Percentiles, compression: Option<&ScalarValue>) -> Result<f64> {
+    match compression {
+        None => Ok(DEFAULT_COMPRESSION as f64),
+        Some(ScalarValue::Int64(Some(compression)))
+            if (MIN_COMPRESSION..=MAX_COMPRESSION).contains(compression) =>
+        {
+            Ok(*compression as f64)
+        }
+        Some(other) => Err(DataFusionError::Plan(format!(
+            "{} expects a compression between {MIN_COMPRESSION} and {MAX_COMPRESSION}, got {other}",
+            function.name()
+        ))),
+    }
+}
+
+/// Checks the percentiles and compression of an approx_percentile or
+/// approx_percentiles call while planning
+pub fn validate_args(function: Percentiles, args: &[Expr]) -> Result<()> {
+    let name = function.name();
+    match args.get(1) {
+        // `[0.5, 0.95]` is planned as make_array, only folded into a literal later
+        Some(Expr::ScalarFunction(call))
+            if function == Percentiles::Many && call.name() == "make_array" =>
+        {
+            let items = call
+                .args
+                .iter()
+                .map(|item| match item {
+                    Expr::Literal(value) => Ok(value.clone()),
+                    other => Err(DataFusionError::Plan(format!(
+                        "{name} expects a list of literal percentiles, got {other}"
+                    ))),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            function.parse_items(&items)?;
+        }
+        Some(Expr::Literal(percentiles)) => {
+            function.parse(percentiles)?;
+        }
+        _ => require_literal(name, args, 1)?,
+    }
+    require_literal(name, args, 2)?;
+    match args.get(2) {
+        Some(Expr::Literal(compression)) => {
+            compression_arg(function, Some(compression)).map(|_| ())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// `approx_percentile(value, percentile [, compression])`
+///
+/// Estimates the percentile (0 to 1) of the non NULL values with a t-digest
+/// instead of sorting them. A larger `compression` (default 100, between 10
+/// and 10000) keeps more centroids, trading memory for accuracy; at 100 the
+/// rank of the estimate is typically within 0.5% of the requested one and
+/// closer still towards the tails. Partial digests merge across partitions.
+///
+/// `approx_percentiles(value, [p1, p2, ...] [, compression])` estimates
+/// several percentiles in one pass and returns them as a list.
+#[derive(Debug)]
+pub struct ApproxPercentile {
+    function: Percentiles,
+    signature: Signature,
+}
+
+impl ApproxPercentile {
+    pub fn new(function: Percentiles) -> Self {
+        Self {
+            function,
+            signature: Signature::one_of(
+                vec![TypeSignature::Any(2), TypeSignature::Any(3)],
+                Volatility::Immutable,
+            ),
+        }
+    }
+
+    pub fn all() -> [Self; 2] {
+        [Self::new(Percentiles::One), Self::new(Percentiles::Many)]
+    }
+}
+
+fn list_of(data_type: DataType) -> DataType {
+    DataType::List(Arc::new(Field::new("item", data_type, true)))
+}
+
+fn list_scalar(values: ArrayRef) -> ScalarValue {
+    let field = Arc::new(Field::new("item", values.data_type().clone(), true));
+    let offsets = OffsetBuffer::from_lengths([values.len()]);
+    ScalarValue::List(Arc::new(ListArray::new(field, offsets, values, None)))
+}
+
+impl AggregateUDFImpl for ApproxPercentile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        self.function.name()
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        if !arg_types[0].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "{} expects a numeric value, got {}",
+                self.function.name(),
+                arg_types[0]
+            )));
+        }
+        match self.function {
+            Percentiles::One => Ok(DataType::Float64),
+            Percentiles::Many => Ok(list_of(DataType::Float64)),
+        }
+    }
+
+    fn accumulator(&self, _arg: &DataType) -> Result<Box<dyn Accumulator>> {
+        Ok(Box::new(TDigestAccumulator::new(self.function)))
+    }
+
+    fn state_type(&self, _return_type: &DataType) -> Result<Vec<DataType>> {
+        Ok(vec![list_of(DataType::Float64), DataType::Binary])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+/// Merging t-digest after Dunning, "Computing extremely accurate quantiles
+/// using t-digests" (2019), with the k1 scale function. Centroids are small
+/// towards both ends, which keeps the tails accurate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TDigest {
+    compression: f64,
+    // by mean, merged as far as the scale function allows
+    centroids: Vec<Centroid>,
+    // added since the last compression, in no order
+    buffer: Vec<Centroid>,
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    pub fn new(compression: f64) -> Self {
+        Self {
+            compression,
+            centroids: vec![],
+            buffer: vec![],
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.push(Centroid {
+            mean: value,
+            weight: 1.0,
+        });
+    }
+
+    /// Adds the values summarized by `other`
+    pub fn merge(&mut self, other: &TDigest) {
+        other
+            .centroids
+            .iter()
+            .chain(&other.buffer)
+            .for_each(|centroid| self.push(*centroid));
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    fn push(&mut self, centroid: Centroid) {
+        self.min = self.min.min(centroid.mean);
+        self.max = self.max.max(centroid.mean);
+        self.buffer.push(centroid);
+        if self.buffer.len() >= 5 * self.compression as usize {
+            self.compress();
+        }
+    }
+
+    // position of quantile `q` on the k1 scale, one unit per centroid
+    fn scale(&self, q: f64) -> f64 {
+        self.compression / (2.0 * PI) * (2.0 * q.clamp(0.0, 1.0) - 1.0).asin()
+    }
+
+    fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut sorted = std::mem::take(&mut self.centroids);
+        sorted.append(&mut self.buffer);
+        sorted.sort_by(|a, b| a.mean.total_cmp(&b.mean));
+        let total: f64 = sorted.iter().map(|centroid| centroid.weight).sum();
+
+        let mut merged = Vec::with_capacity(self.compression as usize);
+        let mut current = sorted[0];
+        // weight of the centroids before `current`
+        let mut before = 0.0;
+        let mut k_start = self.scale(0.0);
+        for next in &sorted[1..] {
+            let q_end = (before + current.weight + next.weight) / total;
+            if self.scale(q_end) - k_start <= 1.0 {
+                let weight = current.weight + next.weight;
+                current.mean += (next.mean - current.mean) * next.weight / weight;
+                current.weight = weight;
+            } else {
+                before += current.weight;
+                k_start = self.scale(before / total);
+                merged.push(current);
+                current = *next;
+            }
+        }
+        merged.push(current);
+        self.centroids = merged;
+    }
+
+    /// Number of values added
+    pub fn count(&self) -> f64 {
+        self.centroids
+            .iter()
+            .chain(&self.buffer)
+            .map(|centroid| centroid.weight)
+            .sum()
+    }
+
+    /// Estimated value at quantile `q`, None while the digest is empty
+    pub fn quantile(&mut self, q: f64) -> Option<f64> {
+        self.compress();
+        let centroids = &self.centroids;
+        let first = centroids.first()?;
+        let last = centroids.last()?;
+        let total = self.count();
+        let target = q.clamp(0.0, 1.0) * total;
+        if centroids.len() == 1 {
+            return Some(self.min + (self.max - self.min) * q);
+        }
+        if target >= total {
+            return Some(self.max);
+        }
+
+        // values are taken to be spread around each centroid, its mean sitting
+        // in the middle of its weight, and min and max are known exactly
+        if target < first.weight / 2.0 {
+            return Some(self.min + (first.mean - self.min) * target / (first.weight / 2.0));
+        }
+        let mut before = 0.0;
+        for pair in centroids.windows(2) {
+            let center = before + pair[0].weight / 2.0;
+            let next_center = before + pair[0].weight + pair[1].weight / 2.0;
+            if target < next_center {
+                let fraction = (target - center) / (next_center - center);
+                return Some(pair[0].mean + (pair[1].mean - pair[0].mean) * fraction);
+            }
+            before += pair[0].weight;
+        }
+        let center = total - last.weight / 2.0;
+        let estimate = last.mean + (self.max - last.mean) * (target - center) / (last.weight / 2.0);
+        Some(estimate.min(self.max))
+    }
+
+    /// Compression, min and max followed by the mean and weight of every
+    /// centroid, all little endian f64
+    pub fn encode(&mut self) -> Vec<u8> {
+        self.compress();
+        [self.compression, self.min, self.max]
+            .into_iter()
+            .chain(
+                self.centroids
+                    .iter()
+                    .flat_map(|centroid| [centroid.mean, centroid.weight]),
+            )
+            .flat_map(f64::to_le_bytes)
+            .collect()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 24 || (bytes.len() - 24) % 16 != 0 {
+            return Err(DataFusionError::Internal(format!(
+                "invalid approx_percentile state of {} bytes",
+                bytes.len()
+            )));
+        }
+        let mut values = bytes
+            .chunks_exact(8)
+            .map(|chunk| f64::from_le_bytes(chunk.try_into().expect("chunks are 8 bytes")));
+        let mut next = || values.next().expect("length is checked");
+        let mut digest = Self::new(next());
+        digest.min = next();
+        digest.max = next();
+        digest.centroids = (0..(bytes.len() - 24) / 16)
+            .map(|_| Centroid {
+                mean: next(),
+                weight: next(),
+            })
+            .collect();
+        Ok(digest)
+    }
+}
+
+/// Percentiles and digest are created as values arrive, the literal
+/// arguments are only known then
+#[derive(Debug)]
+pub struct TDigestAccumulator {
+    function: Percentiles,
+    percentiles: Vec<f64>,
+    digest: Option<TDigest>,
+}
+
+impl TDigestAccumulator {
+    pub fn new(function: Percentiles) -> Self {
+        Self {
+            function,
+            percentiles: vec![],
+            digest: None,
+        }
+    }
+
+    fn init_percentiles(&mut self, percentiles: &[f64]) -> Result<()> {
+        if self.percentiles.is_empty() {
+            self.percentiles = percentiles.to_vec();
+        } else if self.percentiles != percentiles {
+            return Err(DataFusionError::Execution(format!(
+                "{} expects percentiles to be a constant",
+                self.function.name()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Accumulator for TDigestAccumulator {
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        let array = &values[0];
+        if array.is_empty() {
+            return Ok(());
+        }
+        let percentiles = self
+            .function
+            .parse(&ScalarValue::try_from_array(&values[1], 0)?)?;
+        self.init_percentiles(&percentiles)?;
+        let compression = values
+            .get(2)
+            .map(|compression| ScalarValue::try_from_array(compression, 0))
+            .transpose()?;
+        let compression = compression_arg(self.function, compression.as_ref())?;
+        let digest = self.digest.get_or_insert_with(|| TDigest::new(compression));
+
+        let array = cast(array, &DataType::Float64)?;
+        for value in array.as_primitive::<Float64Type>().iter().flatten() {
+            if !value.is_nan() {
+                digest.add(value);
+            }
+        }
+        Ok(())
+    }
+
+    fn evaluate(&mut self) -> Result<ScalarValue> {
+        let Some(digest) = &mut self.digest else {
+            return match self.function {
+                Percentiles::One => Ok(ScalarValue::Float64(None)),
+                Percentiles::Many => ScalarValue::try_from(&list_of(DataType::Float64)),
+            };
+        };
+        let estimates: Vec<_> = self
+            .percentiles
+            .iter()
+            .map(|percentile| digest.quantile(*percentile))
+            .collect();
+        match self.function {
+            Percentiles::One => Ok(ScalarValue::Float64(estimates[0])),
+            Percentiles::Many => Ok(list_scalar(Arc::new(Float64Array::from(estimates)))),
+        }
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self.percentiles.capacity() * std::mem::size_of::<f64>()
+            + self
+                .digest
+                .as_ref()
+                .map(|digest| {
+                    (digest.centroids.capacity() + digest.buffer.capacity())
+                        * std::mem::size_of::<Centroid>()
+                })
+                .unwrap_or(0)
+    }
+
+    fn state(&mut self) -> Result<Vec<ScalarValue>> {
+        Ok(vec![
+            list_scalar(Arc::new(Float64Array::from(self.percentiles.clone()))),
+            ScalarValue::Binary(self.digest.as_mut().map(TDigest::encode)),
+        ])
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        let percentiles = states[0].as_list::<i32>();
+        let digests = states[1].as_binary::<i32>();
+
+        for row in 0..digests.len() {
+            // partitions which saw no values have neither
+            if digests.is_null(row) || percentiles.is_null(row) {
+                continue;
+            }
+            let row_percentiles = percentiles.value(row);
+            self.init_percentiles(row_percentiles.as_primitive::<Float64Type>().values())?;
+            let other = TDigest::decode(digests.value(row))?;
+            match &mut self.digest {
+                Some(digest) => digest.merge(&other),
+                None => self.digest = Some(other),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray, types::Float64Type, Array, ArrayRef, Float64Array, Int64Array, RecordBatch,
+    };
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{
+        datasource::MemTable,
+        logical_expr::{Accumulator, AggregateUDF},
+        prelude::{SessionConfig, SessionContext},
+        scalar::ScalarValue,
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{ApproxPercentile, Percentiles, TDigest, TDigestAccumulator};
+    use crate::query::functions::add_analyzer_rules;
+
+    const PERCENTILES: [f64; 7] = [0.001, 0.01, 0.25, 0.5, 0.9, 0.99, 0.999];
+
+    fn uniform(rng: &mut StdRng) -> f64 {
+        rng.gen_range(0.0..1000.0)
+    }
+
+    // Box-Muller
+    fn normal(rng: &mut StdRng) -> f64 {
+        let (u, v): (f64, f64) = (rng.gen_range(f64::EPSILON..1.0), rng.gen());
+        100.0 + 15.0 * (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+    }
+
+    // Pareto with shape 1.2, most values near 1 and a few enormous ones
+    fn pareto(rng: &mut StdRng) -> f64 {
+        rng.gen_range(f64::EPSILON..1.0f64).powf(-1.0 / 1.2)
+    }
+
+    fn samples(distribution: fn(&mut StdRng) -> f64, seed: u64) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..200_000).map(|_| distribution(&mut rng)).collect()
+    }
+
+    // share of the values below the estimate, compared to the percentile
+    // asked for; unlike the value error it doesn't depend on the distribution
+    fn rank_error(sorted: &[f64], percentile: f64, estimate: f64) -> f64 {
+        let below = sorted.partition_point(|value| *value < estimate);
+        let up_to = sorted.partition_point(|value| *value <= estimate);
+        let n = sorted.len() as f64;
+        let target = percentile * n;
+        if (below as f64..=up_to as f64).contains(&target) {
+            0.0
+        } else {
+            (below as f64 - target)
+                .abs()
+                .min((up_to as f64 - target).abs())
+                / n
+        }
+    }
+
+    // centroids grow with sqrt(q(1 - q)) on the k1 scale, so the tails are tighter
+    fn rank_bound(percentile: f64) -> f64 {
+        0.001 + 0.01 * (percentile * (1.0 - percentile)).sqrt()
+    }
+
+    #[test]
+    fn rank_error_is_bounded() {
+        for (name, distribution) in [
+            ("uniform", uniform as fn(&mut StdRng) -> f64),
+            ("normal", normal),
+            ("pareto", pareto),
+        ] {
+            let values = samples(distribution, 3);
+            let mut digest = TDigest::new(100.0);
+            values.iter().for_each(|value| digest.add(*value));
+            let mut sorted = values.clone();
+            sorted.sort_by(f64::total_cmp);
+
+            assert_eq!(digest.quantile(0.0), Some(sorted[0]));
+            assert_eq!(digest.quantile(1.0), sorted.last().copied());
+            for percentile in PERCENTILES {
+                let estimate = digest.quantile(percentile).unwrap();
+                let error = rank_error(&sorted, percentile, estimate);
+                assert!(
+                    error <= rank_bound(percentile),
+                    "{name} p{percentile}: estimate {estimate} has rank error {error}"
+                );
+            }
+            // the digest stays small however many values it saw
+            assert!(digest.centroids.len() <= 100, "{name}");
+        }
+        assert_eq!(TDigest::new(100.0).quantile(0.5), None);
+    }
+
+    fn accumulate(values: &[f64]) -> TDigestAccumulator {
+        let mut acc = TDigestAccumulator::new(Percentiles::Many);
+        let percentiles = ScalarValue::List(ScalarValue::new_list(
+            &PERCENTILES.map(|p| ScalarValue::Float64(Some(p))),
+            &DataType::Float64,
+        ));
+        let input: ArrayRef = Arc::new(Float64Array::from(values.to_vec()));
+        acc.update_batch(&[input, percentiles.to_array_of_size(values.len()).unwrap()])
+            .unwrap();
+        acc
+    }
+
+    fn estimates(acc: &mut TDigestAccumulator) -> Vec<f64> {
+        match acc.evaluate().unwrap() {
+            ScalarValue::List(list) => list
+                .value(0)
+                .as_primitive::<Float64Type>()
+                .values()
+                .to_vec(),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[test]
+    fn merged_partials_match_a_single_pass() {
+        let values = samples(pareto, 7);
+        let mut sorted = values.clone();
+        sorted.sort_by(f64::total_cmp);
+        let single = estimates(&mut accumulate(&values));
+
+        // partial digests go through their serialized state, as between nodes
+        let mut merged = TDigestAccumulator::new(Percentiles::Many);
+        for chunk in values.chunks(values.len() / 8) {
+            let state = accumulate(chunk).state().unwrap();
+            let state: Vec<ArrayRef> = state.iter().map(|s| s.to_array().unwrap()).collect();
+            merged.merge_batch(&state).unwrap();
+        }
+        // an empty partition leaves its state unset
+        let empty = TDigestAccumulator::new(Percentiles::Many).state().unwrap();
+        let empty: Vec<ArrayRef> = empty.iter().map(|s| s.to_array().unwrap()).collect();
+        merged.merge_batch(&empty).unwrap();
+        let merged = estimates(&mut merged);
+
+        for ((percentile, single), merged) in PERCENTILES.iter().zip(single).zip(merged) {
+            for estimate in [single, merged] {
+                let error = rank_error(&sorted, *percentile, estimate);
+                assert!(
+                    error <= rank_bound(*percentile),
+                    "p{percentile}: estimate {estimate} has rank error {error}"
+                );
+            }
+            // the merged estimate sits where the single pass one does
+            let rank = sorted.partition_point(|value| *value < single) as f64 / sorted.len() as f64;
+            let apart = rank_error(&sorted, rank, merged);
+            assert!(apart <= rank_bound(*percentile), "p{percentile}: {apart}");
+        }
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let mut digest = TDigest::new(50.0);
+        samples(normal, 11)
+            .iter()
+            .for_each(|value| digest.add(*value));
+        let bytes = digest.encode();
+        assert_eq!(TDigest::decode(&bytes).unwrap(), digest);
+        assert!(TDigest::decode(&bytes[..bytes.len() - 8]).is_err());
+        assert!(TDigest::decode(&[]).is_err());
+    }
+
+    fn context() -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Int64, false),
+            Field::new("latency", DataType::Int64, true),
+        ]));
+        // spread over partitions so partial digests get merged
+        let batches = (0..4)
+            .map(|partition| {
+                let rows = (0..1000).filter(|i| i % 4 == partition);
+                vec![RecordBatch::try_new(
+                    schema.clone(),
+                    vec![
+                        Arc::new(Int64Array::from_iter_values(rows.clone().map(|i| i % 2))),
+                        Arc::new(Int64Array::from_iter(
+                            rows.map(|i| (i % 10 != 9).then_some(i)),
+                        )),
+                    ],
+                )
+                .unwrap()]
+            })
+            .collect();
+        let config = SessionConfig::new().with_target_partitions(4);
+        let ctx = SessionContext::new_with_config(config);
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(ctx.state()));
+        for function in ApproxPercentile::all() {
+            ctx.register_udaf(AggregateUDF::from(function));
+        }
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, batches).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    #[actix_web::test]
+    async fn percentiles_in_sql() {
+        let ctx = context();
+        let sql = "SELECT host, approx_percentile(latency, 0.5), \
+                   approx_percentiles(latency, [0.0, 0.5, 1.0], 200) \
+                   FROM metrics GROUP BY host ORDER BY host";
+        let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        let batch = &batches[0];
+        let medians = batch.column(1).as_primitive::<Float64Type>();
+        let lists = batch.column(2).as_list::<i32>();
+        for host in 0..2 {
+            // even or odd values below 1000, without those ending in 9
+            let median = medians.value(host);
+            assert!((median - 500.0).abs() < 10.0, "host {host}: {median}");
+            let list = lists.value(host);
+            let list = list.as_primitive::<Float64Type>();
+            assert_eq!(list.len(), 3);
+            assert_eq!(list.value(0), host as f64);
+            assert!((list.value(1) - 500.0).abs() < 10.0);
+            assert_eq!(list.value(2), [998.0, 997.0][host]);
+        }
+
+        let empty = "SELECT approx_percentile(latency, 0.5) FROM metrics WHERE host > 5";
+        let batches = ctx.sql(empty).await.unwrap().collect().await.unwrap();
+        assert!(batches[0].column(0).is_null(0));
+    }
+
+    #[actix_web::test]
+    async fn arguments_are_validated_while_planning() {
+        let ctx = context();
+        for call in [
+            "approx_percentile(latency, 1.5)",
+            "approx_percentile(latency, host)",
+            "approx_percentile(latency, 0.5, 1)",
+            "approx_percentile(latency, 0.5, host)",
+            "approx_percentiles(latency, 0.5)",
+            "approx_percentiles(latency, [0.5, 2])",
+            "approx_percentiles(latency, [0.5, host])",
+        ] {
+            let sql = format!("SELECT {call} FROM metrics");
+            let err = match ctx.sql(&sql).await {
+                Ok(df) => df.collect().await.unwrap_err(),
+                Err(err) => err,
+            };
+            assert!(
+                err.to_string().contains("approx_percentile"),
+                "{call}: {err}"
+            );
+        }
+    }
+}
@@ -0,0 +1,514 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{
+    any::Any,
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+};
+
+use arrow_array::{
+    cast::AsArray, types::Int64Type, Array, ArrayRef, Int64Array, ListArray, StringArray,
+    StructArray,
+};
+use arrow_schema::{DataType, Field, Fields};
+use datafusion::arrow::buffer::OffsetBuffer;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{Accumulator, AggregateUDFImpl, Signature, TypeSignature, Volatility},
+    scalar::ScalarValue,
+};
+use itertools::Itertools;
+
+/// Largest `k` accepted by approx_top_k
+pub const MAX_K: i64 = 1000;
+// number of counters kept per requested element, more counters tighten the error bound
+const COUNTERS_PER_ELEMENT: usize = 10;
+
+/// `approx_top_k(col, k)`
+///
+/// Returns the `k` most frequent values with their estimated counts using the
+/// SpaceSaving algorithm. Memory is bounded by `k * 10` counters per group and
+/// estimated counts never undercount the true frequency.
+#[derive(Debug)]
+pub struct ApproxTopK {
+    signature: Signature,
+}
+
+impl ApproxTopK {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::one_of(
+                vec![
+                    TypeSignature::Exact(vec![DataType::Utf8, DataType::Int64]),
+                    TypeSignature::Exact(vec![DataType::Int64, DataType::Int64]),
+                ],
+                Volatility::Immutable,
+            ),
+        }
+    }
+}
+
+fn entry_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("value", DataType::Utf8, true),
+        Field::new("count", DataType::Int64, false),
+    ])
+}
+
+fn list_of(data_type: DataType) -> DataType {
+    DataType::List(Arc::new(Field::new("item", data_type, true)))
+}
+
+impl AggregateUDFImpl for ApproxTopK {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "approx_top_k"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(list_of(DataType::Struct(entry_fields())))
+    }
+
+    fn accumulator(&self, _arg: &DataType) -> Result<Box<dyn Accumulator>> {
+        Ok(Box::<ApproxTopKAccumulator>::default())
+    }
+
+    fn state_type(&self, _return_type: &DataType) -> Result<Vec<DataType>> {
+        Ok(vec![
+            DataType::Int64,
+            list_of(DataType::Utf8),
+            list_of(DataType::Int64),
+            list_of(DataType::Int64),
+        ])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Counter {
+    count: u64,
+    // overestimation inherited from the counter this value evicted
+    error: u64,
+}
+
+/// SpaceSaving summary holding at most `capacity` counters
+#[derive(Debug, Default, Clone)]
+pub struct SpaceSaving {
+    capacity: usize,
+    counters: HashMap<String, Counter>,
+    // counters ordered by count so the minimum can be evicted in O(log n)
+    by_count: BTreeSet<(u64, String)>,
+}
+
+impl SpaceSaving {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.counters.len() >= self.capacity
+    }
+
+    fn min_count(&self) -> u64 {
+        self.by_count.first().map(|(count, _)| *count).unwrap_or(0)
+    }
+
+    pub fn offer(&mut self, value: &str) {
+        if let Some(counter) = self.counters.get_mut(value) {
+            self.by_count.remove(&(counter.count, value.to_string()));
+            counter.count += 1;
+            self.by_count.insert((counter.count, value.to_string()));
+            return;
+        }
+
+        let counter = if self.is_full() {
+            let (min, evicted) = self.by_count.pop_first().expect("summary is full");
+            self.counters.remove(&evicted);
+            Counter {
+                count: min + 1,
+                error: min,
+            }
+        } else {
+            Counter { count: 1, error: 0 }
+        };
+        self.insert(value.to_string(), counter);
+    }
+
+    fn insert(&mut self, value: String, counter: Counter) {
+        self.by_count.insert((counter.count, value.clone()));
+        self.counters.insert(value, counter);
+    }
+
+    // Merge following Agarwal et al. "Mergeable Summaries": a value missing from a
+    // full summary may have been evicted, so it is charged that summary's minimum
+    pub fn merge(&mut self, other: &SpaceSaving) {
+        let self_min = if self.is_full() { self.min_count() } else { 0 };
+        let other_min = if other.is_full() {
+            other.min_count()
+        } else {
+            0
+        };
+
+        let mut combined: HashMap<String, Counter> = HashMap::new();
+        for (value, counter) in &self.counters {
+            let other = other.counters.get(value).copied().unwrap_or(Counter {
+                count: other_min,
+                error: other_min,
+            });
+            combined.insert(
+                value.clone(),
+                Counter {
+                    count: counter.count + other.count,
+                    error: counter.error + other.error,
+                },
+            );
+        }
+        for (value, counter) in &other.counters {
+            combined.entry(value.clone()).or_insert(Counter {
+                count: counter.count + self_min,
+                error: counter.error + self_min,
+            });
+        }
+
+        let capacity = self.capacity.max(other.capacity);
+        let mut merged = SpaceSaving::new(capacity);
+        combined
+            .into_iter()
+            .sorted_by(|(a_value, a), (b_value, b)| {
+                b.count.cmp(&a.count).then_with(|| a_value.cmp(b_value))
+            })
+            .take(capacity)
+            .for_each(|(value, counter)| merged.insert(value, counter));
+        *self = merged;
+    }
+
+    /// Top `k` values by estimated count, ties broken by value
+    pub fn top(&self, k: usize) -> Vec<(&str, u64)> {
+        self.counters
+            .iter()
+            .map(|(value, counter)| (value.as_str(), counter.count))
+            .sorted_by(|(a_value, a), (b_value, b)| b.cmp(a).then_with(|| a_value.cmp(b_value)))
+            .take(k)
+            .collect()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ApproxTopKAccumulator {
+    k: Option<usize>,
+    summary: SpaceSaving,
+}
+
+impl ApproxTopKAccumulator {
+    fn init(&mut self, k: i64) -> Result<()> {
+        if !(1..=MAX_K).contains(&k) {
+            return Err(DataFusionError::Plan(format!(
+                "approx_top_k expects k between 1 and {MAX_K}, got {k}"
+            )));
+        }
+        match self.k {
+            Some(current) if current as i64 != k => Err(DataFusionError::Plan(
+                "approx_top_k expects k to be a constant".to_string(),
+            )),
+            Some(_) => Ok(()),
+            None => {
+                self.k = Some(k as usize);
+                self.summary = SpaceSaving::new(k as usize * COUNTERS_PER_ELEMENT);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn list_scalar(values: ArrayRef) -> ScalarValue {
+    let field = Arc::new(Field::new("item", values.data_type().clone(), true));
+    let offsets = OffsetBuffer::from_lengths([values.len()]);
+    ScalarValue::List(Arc::new(ListArray::new(field, offsets, values, None)))
+}
+
+impl Accumulator for ApproxTopKAccumulator {
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        let array = &values[0];
+        if array.is_empty() {
+            return Ok(());
+        }
+        let k = values[1].as_primitive::<Int64Type>();
+        if k.is_null(0) {
+            return Err(DataFusionError::Plan(
+                "approx_top_k expects a non null k".to_string(),
+            ));
+        }
+        self.init(k.value(0))?;
+
+        match array.data_type() {
+            DataType::Utf8 => array
+                .as_string::<i32>()
+                .iter()
+                .flatten()
+                .for_each(|value| self.summary.offer(value)),
+            DataType::Int64 => array
+                .as_primitive::<Int64Type>()
+                .iter()
+                .flatten()
+                .for_each(|value| self.summary.offer(&value.to_string())),
+            other => {
+                return Err(DataFusionError::Execution(format!(
+                    "approx_top_k does not support input of type {other}"
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn evaluate(&mut self) -> Result<ScalarValue> {
+        let top = self.summary.top(self.k.unwrap_or(0));
+        let values: ArrayRef = Arc::new(StringArray::from_iter_values(
+            top.iter().map(|(value, _)| *value),
+        ));
+        let counts: ArrayRef = Arc::new(Int64Array::from_iter_values(
+            top.iter().map(|(_, count)| *count as i64),
+        ));
+        let entries = StructArray::try_new(entry_fields(), vec![values, counts], None)?;
+        Ok(list_scalar(Arc::new(entries)))
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self
+                .summary
+                .counters
+                .keys()
+                .map(|value| 2 * value.capacity() + std::mem::size_of::<(u64, Counter)>())
+                .sum::<usize>()
+    }
+
+    fn state(&mut self) -> Result<Vec<ScalarValue>> {
+        let (values, (counts, errors)): (Vec<&str>, (Vec<i64>, Vec<i64>)) = self
+            .summary
+            .counters
+            .iter()
+            .map(|(value, counter)| (value.as_str(), (counter.count as i64, counter.error as i64)))
+            .unzip();
+
+        Ok(vec![
+            ScalarValue::Int64(self.k.map(|k| k as i64)),
+            list_scalar(Arc::new(StringArray::from(values))),
+            list_scalar(Arc::new(Int64Array::from(counts))),
+            list_scalar(Arc::new(Int64Array::from(errors))),
+        ])
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        let ks = states[0].as_primitive::<Int64Type>();
+        let values = states[1].as_list::<i32>();
+        let counts = states[2].as_list::<i32>();
+        let errors = states[3].as_list::<i32>();
+
+        for row in 0..ks.len() {
+            if ks.is_null(row) {
+                continue;
+            }
+            self.init(ks.value(row))?;
+
+            let row_values = values.value(row);
+            let row_counts = counts.value(row);
+            let row_errors = errors.value(row);
+            let mut other = SpaceSaving::new(self.summary.capacity);
+            row_values
+                .as_string::<i32>()
+                .iter()
+                .zip(row_counts.as_primitive::<Int64Type>().values())
+                .zip(row_errors.as_primitive::<Int64Type>().values())
+                .for_each(|((value, count), error)| {
+                    other.insert(
+                        value.unwrap_or_default().to_string(),
+                        Counter {
+                            count: *count as u64,
+                            error: *error as u64,
+                        },
+                    )
+                });
+            self.summary.merge(&other);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use arrow_array::{
+        cast::AsArray, types::Int64Type, Array, ArrayRef, Int64Array, RecordBatch, StringArray,
+    };
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{
+        datasource::MemTable,
+        logical_expr::{Accumulator, AggregateUDF},
+        prelude::SessionContext,
+        scalar::ScalarValue,
+    };
+    use itertools::Itertools;
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+    use super::{ApproxTopK, ApproxTopKAccumulator};
+
+    // zipf distributed endpoints, the endpoint of rank r appears 50_000 / r times
+    fn zipfian() -> Vec<String> {
+        let mut values = (1..=2000)
+            .flat_map(|rank| std::iter::repeat(format!("/endpoint/{rank}")).take(50_000 / rank))
+            .collect_vec();
+        values.shuffle(&mut StdRng::seed_from_u64(7));
+        values
+    }
+
+    fn exact_top_k(values: &[String], k: usize) -> Vec<(String, i64)> {
+        let mut counts: HashMap<&String, i64> = HashMap::new();
+        values
+            .iter()
+            .for_each(|v| *counts.entry(v).or_default() += 1);
+        counts
+            .into_iter()
+            .sorted_by(|(a_value, a), (b_value, b)| b.cmp(a).then_with(|| a_value.cmp(b_value)))
+            .take(k)
+            .map(|(value, count)| (value.clone(), count))
+            .collect()
+    }
+
+    fn update(acc: &mut ApproxTopKAccumulator, values: &[String], k: i64) {
+        let values: ArrayRef = Arc::new(StringArray::from_iter_values(values));
+        let k: ArrayRef = Arc::new(Int64Array::from(vec![k; values.len()]));
+        acc.update_batch(&[values, k]).unwrap();
+    }
+
+    fn result(acc: &mut ApproxTopKAccumulator) -> Vec<(String, i64)> {
+        let ScalarValue::List(list) = acc.evaluate().unwrap() else {
+            panic!("approx_top_k returns a list")
+        };
+        let entries = list.value(0);
+        let entries = entries.as_struct();
+        entries
+            .column(0)
+            .as_string::<i32>()
+            .iter()
+            .zip(entries.column(1).as_primitive::<Int64Type>().values())
+            .map(|(value, count)| (value.unwrap().to_string(), *count))
+            .collect()
+    }
+
+    fn names(top: &[(String, i64)]) -> Vec<&str> {
+        top.iter().map(|(value, _)| value.as_str()).collect()
+    }
+
+    #[test]
+    fn matches_exact_top_k_on_skewed_data() {
+        let values = zipfian();
+        let exact = exact_top_k(&values, 10);
+
+        let mut acc = ApproxTopKAccumulator::default();
+        update(&mut acc, &values, 10);
+        let approx = result(&mut acc);
+
+        assert_eq!(names(&approx), names(&exact));
+        // SpaceSaving never underestimates
+        for ((_, approx), (_, exact)) in approx.iter().zip(exact.iter()) {
+            assert!(approx >= exact);
+        }
+    }
+
+    #[test]
+    fn merge_keeps_heavy_hitters() {
+        let values = zipfian();
+        let exact = exact_top_k(&values, 10);
+
+        let mut merged = ApproxTopKAccumulator::default();
+        for chunk in values.chunks(values.len() / 4 + 1) {
+            let mut partial = ApproxTopKAccumulator::default();
+            update(&mut partial, chunk, 10);
+            let state = partial
+                .state()
+                .unwrap()
+                .iter()
+                .map(|v| v.to_array().unwrap())
+                .collect_vec();
+            merged.merge_batch(&state).unwrap();
+        }
+
+        assert_eq!(names(&result(&mut merged)), names(&exact));
+    }
+
+    #[test]
+    fn k_is_validated() {
+        for k in [0, -1, super::MAX_K + 1] {
+            let mut acc = ApproxTopKAccumulator::default();
+            let values: ArrayRef = Arc::new(StringArray::from(vec!["a"]));
+            let k: ArrayRef = Arc::new(Int64Array::from(vec![k]));
+            assert!(acc.update_batch(&[values, k]).is_err());
+        }
+    }
+
+    #[actix_web::test]
+    async fn approx_top_k_in_sql() {
+        let schema = Arc::new(Schema::new(vec![Field::new("path", DataType::Utf8, true)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(StringArray::from(vec![
+                Some("/a"),
+                Some("/b"),
+                Some("/a"),
+                None,
+                Some("/c"),
+                Some("/a"),
+                Some("/b"),
+            ]))],
+        )
+        .unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_udaf(AggregateUDF::from(ApproxTopK::new()));
+        ctx.register_table(
+            "logs",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+
+        let batches = ctx
+            .sql("SELECT approx_top_k(path, 2) AS top FROM logs")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let top = batches[0].column(0).as_list::<i32>().value(0);
+        let top = top.as_struct();
+        assert_eq!(top.len(), 2);
+        assert_eq!(top.column(0).as_string::<i32>().value(0), "/a");
+        assert_eq!(top.column(1).as_primitive::<Int64Type>().value(0), 3);
+        assert_eq!(top.column(0).as_string::<i32>().value(1), "/b");
+        assert_eq!(top.column(1).as_primitive::<Int64Type>().value(1), 2);
+    }
+}
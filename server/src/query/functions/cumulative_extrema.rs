@@ -0,0 +1,240 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{cast::AsArray, types::Float64Type, ArrayRef, Float64Array};
+use arrow_schema::DataType;
+use datafusion::arrow::compute::cast;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, Volatility, WindowUDFImpl},
+};
+
+use super::rolling::Extremum;
+
+fn name(extremum: Extremum) -> &'static str {
+    match extremum {
+        Extremum::Min => "cumulative_min",
+        Extremum::Max => "cumulative_max",
+    }
+}
+
+fn return_type(extremum: Extremum, arg_types: &[DataType]) -> Result<DataType> {
+    if !arg_types[0].is_numeric() {
+        return Err(DataFusionError::Plan(format!(
+            "{} expects a numeric value, got {}",
+            name(extremum),
+            arg_types[0]
+        )));
+    }
+    Ok(DataType::Float64)
+}
+
+/// `cumulative_min(value)`
+///
+/// Smallest non NULL value from the start of the partition up to and
+/// including the current row in the window order. The frame of the call is
+/// ignored.
+///
+/// Returns NULL until the partition has a non NULL value.
+#[derive(Debug)]
+pub struct CumulativeMin {
+    signature: Signature,
+}
+
+impl CumulativeMin {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::any(1, Volatility::Immutable),
+        }
+    }
+}
+
+impl WindowUDFImpl for CumulativeMin {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        name(Extremum::Min)
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        return_type(Extremum::Min, arg_types)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(CumulativeExtremumEvaluator(Extremum::Min)))
+    }
+}
+
+/// `cumulative_max(value)`
+///
+/// Largest non NULL value from the start of the partition up to and
+/// including the current row in the window order, the high-water mark of
+/// the value. The frame of the call is ignored.
+///
+/// Returns NULL until the partition has a non NULL value.
+#[derive(Debug)]
+pub struct CumulativeMax {
+    signature: Signature,
+}
+
+impl CumulativeMax {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::any(1, Volatility::Immutable),
+        }
+    }
+}
+
+impl WindowUDFImpl for CumulativeMax {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        name(Extremum::Max)
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        return_type(Extremum::Max, arg_types)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(CumulativeExtremumEvaluator(Extremum::Max)))
+    }
+}
+
+#[derive(Debug)]
+struct CumulativeExtremumEvaluator(Extremum);
+
+impl PartitionEvaluator for CumulativeExtremumEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], _num_rows: usize) -> Result<ArrayRef> {
+        let input = cast(&values[0], &DataType::Float64)?;
+        let mut extremum: Option<f64> = None;
+        let extrema = input
+            .as_primitive::<Float64Type>()
+            .iter()
+            .map(|value| {
+                if let Some(value) = value {
+                    extremum = Some(match (self.0, extremum) {
+                        (_, None) => value,
+                        (Extremum::Min, Some(current)) => current.min(value),
+                        (Extremum::Max, Some(current)) => current.max(value),
+                    });
+                }
+                extremum
+            })
+            .collect::<Float64Array>();
+        Ok(Arc::new(extrema))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{cast::AsArray, types::Float64Type, Int64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{datasource::MemTable, logical_expr::WindowUDF, prelude::SessionContext};
+
+    use super::{CumulativeMax, CumulativeMin};
+
+    fn context(hosts: Vec<&str>, values: Vec<Option<i64>>) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("seq", DataType::Int64, false),
+            Field::new("host", DataType::Utf8, false),
+            Field::new("value", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(0..values.len() as i64)),
+                Arc::new(StringArray::from(hosts)),
+                Arc::new(Int64Array::from(values)),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_udwf(WindowUDF::from(CumulativeMin::new()));
+        ctx.register_udwf(WindowUDF::from(CumulativeMax::new()));
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn extrema(ctx: &SessionContext, call: &str, window: &str) -> Vec<Option<f64>> {
+        let sql = format!("SELECT {call} OVER ({window}) FROM metrics ORDER BY seq");
+        let batches = ctx.sql(&sql).await.unwrap().collect().await.unwrap();
+        batches
+            .iter()
+            .flat_map(|batch| batch.column(0).as_primitive::<Float64Type>().iter())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn max_plateaus_at_the_high_water_mark() {
+        let ctx = context(
+            vec!["a"; 6],
+            vec![None, Some(3), Some(7), Some(5), None, Some(7)],
+        );
+        let maxima = extrema(&ctx, "cumulative_max(value)", "ORDER BY seq").await;
+        assert_eq!(
+            maxima,
+            [None, Some(3.0), Some(7.0), Some(7.0), Some(7.0), Some(7.0)]
+        );
+    }
+
+    #[actix_web::test]
+    async fn min_follows_a_decreasing_series() {
+        let ctx = context(vec!["a"; 4], [9, 4, 1, -2].map(Some).to_vec());
+        let minima = extrema(&ctx, "cumulative_min(value)", "ORDER BY seq").await;
+        assert_eq!(minima, [9.0, 4.0, 1.0, -2.0].map(Some));
+
+        // the frame doesn't limit the rows looked at
+        let framed = "ORDER BY seq ROWS BETWEEN CURRENT ROW AND CURRENT ROW";
+        let minima = extrema(&ctx, "cumulative_min(value)", framed).await;
+        assert_eq!(minima, [9.0, 4.0, 1.0, -2.0].map(Some));
+    }
+
+    #[actix_web::test]
+    async fn partitions_start_over() {
+        let ctx = context(
+            vec!["a", "b", "a", "b", "a"],
+            [5, 1, 2, 8, 6].map(Some).to_vec(),
+        );
+        let window = "PARTITION BY host ORDER BY seq";
+        let maxima = extrema(&ctx, "cumulative_max(value)", window).await;
+        assert_eq!(maxima, [5.0, 1.0, 5.0, 8.0, 6.0].map(Some));
+        let minima = extrema(&ctx, "cumulative_min(value)", window).await;
+        assert_eq!(minima, [5.0, 1.0, 2.0, 1.0, 2.0].map(Some));
+    }
+}
@@ -0,0 +1,172 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{cast::AsArray, types::Float64Type, ArrayRef, Float64Array};
+use arrow_schema::DataType;
+use datafusion::arrow::compute::cast;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, Volatility, WindowUDFImpl},
+};
+
+/// `cumulative_sum(value)`
+///
+/// Sum of every non NULL value from the start of the partition up to and
+/// including the current row in the window order. The frame of the call is
+/// ignored. Rows with a NULL value carry the previous sum forward.
+///
+/// Returns NULL until the partition has a non NULL value.
+#[derive(Debug)]
+pub struct CumulativeSum {
+    signature: Signature,
+}
+
+impl CumulativeSum {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::any(1, Volatility::Immutable),
+        }
+    }
+}
+
+impl WindowUDFImpl for CumulativeSum {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "cumulative_sum"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        if !arg_types[0].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "cumulative_sum expects a numeric value, got {}",
+                arg_types[0]
+            )));
+        }
+        Ok(DataType::Float64)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(CumulativeSumEvaluator))
+    }
+}
+
+#[derive(Debug)]
+struct CumulativeSumEvaluator;
+
+impl PartitionEvaluator for CumulativeSumEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], _num_rows: usize) -> Result<ArrayRef> {
+        let input = cast(&values[0], &DataType::Float64)?;
+        let mut total: Option<f64> = None;
+        let sums = input
+            .as_primitive::<Float64Type>()
+            .iter()
+            .map(|value| {
+                if let Some(value) = value {
+                    total = Some(total.unwrap_or_default() + value);
+                }
+                total
+            })
+            .collect::<Float64Array>();
+        Ok(Arc::new(sums))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{cast::AsArray, types::Float64Type, Int64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{datasource::MemTable, logical_expr::WindowUDF, prelude::SessionContext};
+
+    use super::CumulativeSum;
+
+    fn context(hosts: Vec<&str>, values: Vec<Option<i64>>) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("seq", DataType::Int64, false),
+            Field::new("host", DataType::Utf8, false),
+            Field::new("value", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(0..values.len() as i64)),
+                Arc::new(StringArray::from(hosts)),
+                Arc::new(Int64Array::from(values)),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_udwf(WindowUDF::from(CumulativeSum::new()));
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn sums(ctx: &SessionContext, window: &str) -> Vec<Option<f64>> {
+        let sql = format!("SELECT cumulative_sum(value) OVER ({window}) FROM metrics ORDER BY seq");
+        let batches = ctx.sql(&sql).await.unwrap().collect().await.unwrap();
+        batches
+            .iter()
+            .flat_map(|batch| batch.column(0).as_primitive::<Float64Type>().iter())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn sums_from_the_start_of_the_partition() {
+        let ctx = context(vec!["a"; 5], vec![None, Some(3), None, Some(4), Some(-2)]);
+        let expected = [None, Some(3.0), Some(3.0), Some(7.0), Some(5.0)];
+        assert_eq!(sums(&ctx, "ORDER BY seq").await, expected);
+
+        // the frame doesn't limit the sum
+        let framed = "ORDER BY seq ROWS BETWEEN 1 PRECEDING AND CURRENT ROW";
+        assert_eq!(sums(&ctx, framed).await, expected);
+    }
+
+    #[actix_web::test]
+    async fn partitions_accumulate_independently() {
+        let ctx = context(
+            vec!["a", "b", "a", "b", "a"],
+            [1, 10, 2, 20, 3].map(Some).to_vec(),
+        );
+        let sums = sums(&ctx, "PARTITION BY host ORDER BY seq").await;
+        assert_eq!(sums, [1.0, 10.0, 3.0, 30.0, 6.0].map(Some));
+    }
+
+    #[actix_web::test]
+    async fn value_must_be_numeric() {
+        let ctx = context(vec!["a"], vec![Some(1)]);
+        let err = ctx
+            .sql("SELECT cumulative_sum(host) OVER (ORDER BY seq) FROM metrics")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("cumulative_sum"), "{err}");
+    }
+}
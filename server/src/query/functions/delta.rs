@@ -0,0 +1,350 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, collections::VecDeque, sync::Arc};
+
+use arrow_array::{
+    cast::AsArray,
+    types::{Float64Type, Int64Type},
+    Array, ArrayRef, Float64Array,
+};
+use arrow_schema::DataType;
+use datafusion::arrow::compute::cast;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, TypeSignature, Volatility, WindowUDFImpl},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::{
+    require_literal, rolling_mean::MAX_WINDOW, rolling_mean_within::interval_nanos,
+    sessionize::nanos_per_unit,
+};
+
+fn window_arg(window: &ScalarValue) -> Result<usize> {
+    match window {
+        ScalarValue::Int64(Some(window)) if (2..=MAX_WINDOW).contains(window) => {
+            Ok(*window as usize)
+        }
+        other => Err(DataFusionError::Plan(format!(
+            "delta expects a window between 2 and {MAX_WINDOW}, got {other}"
+        ))),
+    }
+}
+
+/// Checks the window or interval of a delta call while planning
+pub fn validate_args(args: &[Expr]) -> Result<()> {
+    match args {
+        [_, window] => {
+            require_literal("delta", args, 1)?;
+            if let Expr::Literal(window) = window {
+                window_arg(window)?;
+            }
+        }
+        [_, _, interval] => {
+            require_literal("delta", args, 2)?;
+            if let Expr::Literal(interval) = interval {
+                interval_nanos("delta", interval)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Which values of the partition the delta is taken over
+#[derive(Debug, Clone, Copy)]
+enum Span {
+    /// Every value up to the current row
+    Partition,
+    /// The last this many non NULL values
+    Rows(usize),
+    /// The values less than this many nanoseconds before the current row
+    Time(i64),
+}
+
+/// `delta(value [, window])` or `delta(value, timestamp, interval)`
+///
+/// Difference between the last and the first non NULL value up to and
+/// including the current row in the window order, for gauges. Unlike `rate`
+/// a decrease is not taken as a reset, so the delta can be negative.
+///
+/// Without a window the delta is taken since the start of the partition.
+/// `window` counts the last non NULL values the same way as `rolling_mean`,
+/// and `interval`, a string such as `'5m'`, takes the values less than that
+/// long before the current row, in which case the window must be ordered by
+/// the timestamp and rows with a NULL timestamp get NULL.
+///
+/// Returns NULL until the window has two values.
+#[derive(Debug)]
+pub struct Delta {
+    signature: Signature,
+}
+
+impl Delta {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::one_of(
+                vec![
+                    TypeSignature::Any(1),
+                    TypeSignature::Any(2),
+                    TypeSignature::Any(3),
+                ],
+                Volatility::Immutable,
+            ),
+        }
+    }
+}
+
+impl WindowUDFImpl for Delta {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "delta"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        if !arg_types[0].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "delta expects a numeric value, got {}",
+                arg_types[0]
+            )));
+        }
+        match arg_types {
+            [_, window] if !window.is_integer() => Err(DataFusionError::Plan(format!(
+                "delta expects an integer window, got {window}"
+            ))),
+            [_, timestamp, _] if !matches!(timestamp, DataType::Timestamp(_, _)) => Err(
+                DataFusionError::Plan(format!("delta expects a timestamp, got {timestamp}")),
+            ),
+            _ => Ok(DataType::Float64),
+        }
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(DeltaEvaluator))
+    }
+}
+
+#[derive(Debug)]
+struct DeltaEvaluator;
+
+impl PartitionEvaluator for DeltaEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+        if num_rows == 0 {
+            return Ok(Arc::new(Float64Array::from(Vec::<f64>::new())));
+        }
+        // window and interval are literals, every row carries the same value
+        let (span, timestamps) = match values {
+            [_, window] => (
+                Span::Rows(window_arg(&ScalarValue::try_from_array(window, 0)?)?),
+                None,
+            ),
+            [_, timestamps, interval] => {
+                let DataType::Timestamp(unit, _) = timestamps.data_type() else {
+                    return Err(DataFusionError::Execution(format!(
+                        "delta expects a timestamp, got {}",
+                        timestamps.data_type()
+                    )));
+                };
+                let interval = interval_nanos("delta", &ScalarValue::try_from_array(interval, 0)?)?;
+                // the raw value, casting to another timestamp type would shift zoned values
+                let timestamps = cast(timestamps, &DataType::Int64)?;
+                let timestamps = timestamps.as_primitive::<Int64Type>().clone();
+                (
+                    Span::Time(interval),
+                    Some((timestamps, nanos_per_unit(unit))),
+                )
+            }
+            _ => (Span::Partition, None),
+        };
+        let input = cast(&values[0], &DataType::Float64)?;
+        let mut window: VecDeque<(i64, f64)> = VecDeque::new();
+        let mut previous = i64::MIN;
+        let deltas = input
+            .as_primitive::<Float64Type>()
+            .iter()
+            .enumerate()
+            .map(|(row, value)| {
+                let timestamp = match &timestamps {
+                    Some((timestamps, scale)) if timestamps.is_valid(row) => {
+                        timestamps.value(row).saturating_mul(*scale)
+                    }
+                    Some(_) => return Ok(None),
+                    None => 0,
+                };
+                if timestamp < previous {
+                    return Err(DataFusionError::Execution(
+                        "delta requires the window to be ordered by the timestamp".to_string(),
+                    ));
+                }
+                previous = timestamp;
+                if let Span::Time(interval) = span {
+                    let start = timestamp.saturating_sub(interval);
+                    while window.front().is_some_and(|(time, _)| *time <= start) {
+                        window.pop_front();
+                    }
+                }
+                if let Some(value) = value {
+                    match span {
+                        Span::Rows(size) if window.len() == size => {
+                            window.pop_front();
+                        }
+                        // only the first and the last value are needed
+                        Span::Partition if window.len() == 2 => {
+                            window.pop_back();
+                        }
+                        _ => {}
+                    }
+                    window.push_back((timestamp, value));
+                }
+                Ok(match (window.front(), window.back()) {
+                    (Some(first), Some(last)) if window.len() >= 2 => Some(last.1 - first.1),
+                    _ => None,
+                })
+            })
+            .collect::<Result<Float64Array>>()?;
+        Ok(Arc::new(deltas))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray, types::Float64Type, Int64Array, RecordBatch, TimestampSecondArray,
+    };
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use datafusion::{datasource::MemTable, logical_expr::WindowUDF, prelude::SessionContext};
+
+    use super::Delta;
+    use crate::query::functions::add_analyzer_rules;
+
+    fn context(rows: &[(i64, Option<i64>)]) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Second, None),
+                false,
+            ),
+            Field::new("temperature", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(TimestampSecondArray::from_iter_values(
+                    rows.iter().map(|row| row.0),
+                )),
+                Arc::new(Int64Array::from_iter(rows.iter().map(|row| row.1))),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        ctx.register_udwf(WindowUDF::from(Delta::new()));
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn deltas(
+        ctx: &SessionContext,
+        call: &str,
+    ) -> datafusion::error::Result<Vec<Option<f64>>> {
+        let sql =
+            format!("SELECT {call} OVER (ORDER BY p_timestamp) FROM metrics ORDER BY p_timestamp");
+        let batches = ctx.sql(&sql).await?.collect().await?;
+        Ok(batches
+            .iter()
+            .flat_map(|batch| batch.column(0).as_primitive::<Float64Type>().iter())
+            .collect())
+    }
+
+    #[actix_web::test]
+    async fn gauge_goes_up_and_down() {
+        let ctx = context(&[
+            (0, Some(20)),
+            (10, Some(25)),
+            (20, Some(15)),
+            (30, Some(12)),
+        ]);
+        let since_start = deltas(&ctx, "delta(temperature)").await.unwrap();
+        assert_eq!(since_start, [None, Some(5.0), Some(-5.0), Some(-8.0)]);
+
+        let last_two = deltas(&ctx, "delta(temperature, 2)").await.unwrap();
+        assert_eq!(last_two, [None, Some(5.0), Some(-10.0), Some(-3.0)]);
+
+        let last_15s = deltas(&ctx, "delta(temperature, p_timestamp, '15s')")
+            .await
+            .unwrap();
+        assert_eq!(last_15s, [None, Some(5.0), Some(-10.0), Some(-3.0)]);
+    }
+
+    #[actix_web::test]
+    async fn nulls_are_skipped() {
+        let ctx = context(&[
+            (0, None),
+            (10, Some(20)),
+            (20, None),
+            (30, Some(14)),
+            (40, None),
+            (50, Some(17)),
+        ]);
+        let since_start = deltas(&ctx, "delta(temperature)").await.unwrap();
+        assert_eq!(
+            since_start,
+            [None, None, None, Some(-6.0), Some(-6.0), Some(-3.0)]
+        );
+
+        let last_two = deltas(&ctx, "delta(temperature, 2)").await.unwrap();
+        assert_eq!(
+            last_two,
+            [None, None, None, Some(-6.0), Some(-6.0), Some(3.0)]
+        );
+
+        // the window holds a single value at 40s
+        let last_25s = deltas(&ctx, "delta(temperature, p_timestamp, '25s')")
+            .await
+            .unwrap();
+        assert_eq!(last_25s, [None, None, None, Some(-6.0), None, Some(3.0)]);
+    }
+
+    #[actix_web::test]
+    async fn window_is_validated_while_planning() {
+        let ctx = context(&[(0, Some(1))]);
+        for call in [
+            "delta(temperature, 1)",
+            "delta(temperature, temperature)",
+            "delta(temperature, p_timestamp, 'soon')",
+            "delta(temperature, temperature, '5m')",
+        ] {
+            let err = deltas(&ctx, call).await.unwrap_err();
+            assert!(err.to_string().contains("delta"), "{call}: {err}");
+        }
+    }
+}
@@ -0,0 +1,212 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, ops::Range};
+
+use arrow_array::ArrayRef;
+use arrow_schema::DataType;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, Volatility, WindowUDFImpl},
+    scalar::ScalarValue,
+};
+
+use super::rate::{frame_points, NANOS_PER_SECOND};
+
+/// Slope of the least squares line through `points`, per second
+fn least_squares_slope(points: &[(i64, f64)]) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    // seconds from the first point, so that the sums keep their precision
+    let origin = points[0].0;
+    let seconds = |time: i64| (time - origin) as f64 / NANOS_PER_SECOND;
+    let count = points.len() as f64;
+    let mean_time = points.iter().map(|(time, _)| seconds(*time)).sum::<f64>() / count;
+    let mean_value = points.iter().map(|(_, value)| value).sum::<f64>() / count;
+
+    let (mut covariance, mut variance) = (0.0, 0.0);
+    for (time, value) in points {
+        let time = seconds(*time) - mean_time;
+        covariance += time * (value - mean_value);
+        variance += time * time;
+    }
+    // every point at the same time
+    (variance > 0.0).then(|| covariance / variance)
+}
+
+/// `derivative(value, timestamp)`
+///
+/// Per second rate of change of a gauge over the window frame, the slope of
+/// the least squares line through its (timestamp, value) points, like
+/// Prometheus' `deriv`. Unlike `rate` it follows the trend of every point
+/// rather than the first and the last, and can be negative.
+///
+/// Follows the SQL frame like `rate`. Rows with a NULL value or timestamp are
+/// skipped and frames with fewer than two points, or no time between them,
+/// give NULL.
+#[derive(Debug)]
+pub struct Derivative {
+    signature: Signature,
+}
+
+impl Derivative {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::any(2, Volatility::Immutable),
+        }
+    }
+}
+
+impl WindowUDFImpl for Derivative {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "derivative"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        if !arg_types[0].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "derivative expects a numeric value, got {}",
+                arg_types[0]
+            )));
+        }
+        if !matches!(arg_types[1], DataType::Timestamp(_, _)) {
+            return Err(DataFusionError::Plan(format!(
+                "derivative expects a timestamp, got {}",
+                arg_types[1]
+            )));
+        }
+        Ok(DataType::Float64)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(DerivativeEvaluator))
+    }
+}
+
+#[derive(Debug)]
+struct DerivativeEvaluator;
+
+impl PartitionEvaluator for DerivativeEvaluator {
+    fn evaluate(&mut self, values: &[ArrayRef], range: &Range<usize>) -> Result<ScalarValue> {
+        let points = frame_points("derivative", values, range)?;
+        Ok(ScalarValue::Float64(least_squares_slope(&points)))
+    }
+
+    fn uses_window_frame(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray, types::Float64Type, Float64Array, RecordBatch, TimestampSecondArray,
+    };
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use datafusion::{datasource::MemTable, logical_expr::WindowUDF, prelude::SessionContext};
+
+    use super::{least_squares_slope, Derivative};
+
+    fn context(rows: &[(i64, Option<f64>)]) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Second, None),
+                false,
+            ),
+            Field::new("temperature", DataType::Float64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(TimestampSecondArray::from_iter_values(
+                    rows.iter().map(|row| row.0),
+                )),
+                Arc::new(Float64Array::from_iter(rows.iter().map(|row| row.1))),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_udwf(WindowUDF::from(Derivative::new()));
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn derivatives(ctx: &SessionContext, frame: &str) -> Vec<Option<f64>> {
+        let sql = format!(
+            "SELECT derivative(temperature, p_timestamp) OVER (ORDER BY p_timestamp {frame}) \
+             FROM metrics ORDER BY p_timestamp"
+        );
+        let batches = ctx.sql(&sql).await.unwrap().collect().await.unwrap();
+        batches
+            .iter()
+            .flat_map(|batch| batch.column(0).as_primitive::<Float64Type>().iter())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn linear_ramp_has_a_constant_derivative() {
+        // 0.5 a second, sampled irregularly
+        let rows: Vec<_> = [0, 10, 15, 40, 60, 61]
+            .map(|time| (time, Some(20.0 + 0.5 * time as f64)))
+            .to_vec();
+        let ctx = context(&rows);
+        for frame in ["", "ROWS BETWEEN 2 PRECEDING AND CURRENT ROW"] {
+            let derivatives = derivatives(&ctx, frame).await;
+            assert_eq!(derivatives[0], None);
+            for derivative in &derivatives[1..] {
+                assert!((derivative.unwrap() - 0.5).abs() < 1e-9, "{derivatives:?}");
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn flat_series_has_no_derivative() {
+        let ctx = context(&[(0, Some(7.0)), (5, None), (10, Some(7.0)), (30, Some(7.0))]);
+        let derivatives = derivatives(&ctx, "").await;
+        assert_eq!(derivatives, [None, None, Some(0.0), Some(0.0)]);
+    }
+
+    #[test]
+    fn slope_follows_the_trend_of_every_point() {
+        // a spike in the middle raises the line but does not tilt it
+        let points = [(0, 0.0), (1_000_000_000, 10.0), (2_000_000_000, 0.0)];
+        assert_eq!(least_squares_slope(&points), Some(0.0));
+
+        let points = [(0, 1.0), (2_000_000_000, 3.0), (4_000_000_000, 3.0)];
+        assert_eq!(least_squares_slope(&points), Some(0.5));
+
+        assert_eq!(least_squares_slope(&[(0, 1.0)]), None);
+        assert_eq!(least_squares_slope(&[(0, 1.0), (0, 2.0)]), None);
+    }
+}
@@ -0,0 +1,213 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{cast::AsArray, types::Float64Type, ArrayRef, Float64Array};
+use arrow_schema::DataType;
+use datafusion::arrow::compute::cast;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, Volatility, WindowUDFImpl},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::require_literal;
+
+fn alpha_arg(alpha: &ScalarValue) -> Result<f64> {
+    match alpha.cast_to(&DataType::Float64) {
+        Ok(ScalarValue::Float64(Some(value))) if value > 0.0 && value <= 1.0 => Ok(value),
+        _ => Err(DataFusionError::Plan(format!(
+            "ewma expects an alpha in (0, 1], got {alpha}"
+        ))),
+    }
+}
+
+/// Checks the alpha of an ewma call while planning
+pub fn validate_args(args: &[Expr]) -> Result<()> {
+    require_literal("ewma", args, 1)?;
+    match args.get(1) {
+        Some(Expr::Literal(alpha)) => alpha_arg(alpha).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// `ewma(value, alpha)`
+///
+/// Exponentially weighted moving average in the window order,
+/// `alpha * value + (1 - alpha) * previous`, seeded with the first non NULL
+/// value. Rows with a NULL value carry the previous average forward.
+///
+/// Returns NULL until the partition has a non NULL value.
+#[derive(Debug)]
+pub struct EwmaUdf {
+    signature: Signature,
+}
+
+impl EwmaUdf {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::any(2, Volatility::Immutable),
+        }
+    }
+}
+
+impl WindowUDFImpl for EwmaUdf {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "ewma"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        if !arg_types[0].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "ewma expects a numeric value, got {}",
+                arg_types[0]
+            )));
+        }
+        if !arg_types[1].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "ewma expects a numeric alpha, got {}",
+                arg_types[1]
+            )));
+        }
+        Ok(DataType::Float64)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(EwmaEvaluator))
+    }
+}
+
+#[derive(Debug)]
+struct EwmaEvaluator;
+
+impl PartitionEvaluator for EwmaEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+        if num_rows == 0 {
+            return Ok(Arc::new(Float64Array::from(Vec::<f64>::new())));
+        }
+        // alpha is a literal, every row carries the same value
+        let alpha = alpha_arg(&ScalarValue::try_from_array(&values[1], 0)?)?;
+
+        let input = cast(&values[0], &DataType::Float64)?;
+        let mut smoothed: Option<f64> = None;
+        let averages = input
+            .as_primitive::<Float64Type>()
+            .iter()
+            .map(|value| {
+                if let Some(value) = value {
+                    smoothed = Some(match smoothed {
+                        Some(previous) => alpha * value + (1.0 - alpha) * previous,
+                        None => value,
+                    });
+                }
+                smoothed
+            })
+            .collect::<Float64Array>();
+        Ok(Arc::new(averages))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{cast::AsArray, types::Float64Type, Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{datasource::MemTable, logical_expr::WindowUDF, prelude::SessionContext};
+
+    use super::EwmaUdf;
+    use crate::query::functions::add_analyzer_rules;
+
+    fn context(values: Vec<Option<i64>>) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("seq", DataType::Int64, false),
+            Field::new("value", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(0..values.len() as i64)),
+                Arc::new(Int64Array::from(values)),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        ctx.register_udwf(WindowUDF::from(EwmaUdf::new()));
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn averages(
+        ctx: &SessionContext,
+        call: &str,
+    ) -> datafusion::error::Result<Vec<Option<f64>>> {
+        let sql = format!("SELECT {call} OVER (ORDER BY seq) FROM metrics ORDER BY seq");
+        let batches = ctx.sql(&sql).await?.collect().await?;
+        Ok(batches
+            .iter()
+            .flat_map(|batch| batch.column(0).as_primitive::<Float64Type>().iter())
+            .collect())
+    }
+
+    #[actix_web::test]
+    async fn matches_hand_computed_series() {
+        let ctx = context([10, 20, 20, 0].map(Some).to_vec());
+        let smoothed = averages(&ctx, "ewma(value, 0.5)").await.unwrap();
+        assert_eq!(smoothed, [10.0, 15.0, 17.5, 8.75].map(Some));
+    }
+
+    #[actix_web::test]
+    async fn nulls_carry_the_average_forward() {
+        let ctx = context(vec![None, Some(4), None, Some(8), None]);
+        let carried = averages(&ctx, "ewma(value, 0.25)").await.unwrap();
+        assert_eq!(carried, [None, Some(4.0), Some(4.0), Some(5.0), Some(5.0)]);
+
+        // an alpha of 1 follows the values
+        let ctx = context(vec![Some(3), None, Some(7)]);
+        let unsmoothed = averages(&ctx, "ewma(value, 1)").await.unwrap();
+        assert_eq!(unsmoothed, [3.0, 3.0, 7.0].map(Some));
+    }
+
+    #[actix_web::test]
+    async fn alpha_is_validated_while_planning() {
+        let ctx = context(vec![Some(1)]);
+        for call in [
+            "ewma(value, 0)",
+            "ewma(value, 1.5)",
+            "ewma(value, -0.5)",
+            "ewma(value, seq)",
+        ] {
+            let err = averages(&ctx, call).await.unwrap_err();
+            assert!(err.to_string().contains("ewma"), "{call}: {err}");
+        }
+    }
+}
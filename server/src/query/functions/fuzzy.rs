@@ -0,0 +1,465 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{
+    builder::{Float64Builder, Int64Builder},
+    cast::AsArray,
+    Array, ArrayRef, StringArray,
+};
+use arrow_schema::DataType;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{
+        simplify::{ExprSimplifyResult, SimplifyInfo},
+        ColumnarValue, ScalarUDFImpl, Signature, TypeSignature, Volatility,
+    },
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::{literal_arg, num_rows, require_literal, to_columnar_value};
+
+/// Edit distance between `a` and `b` counted in chars, `None` once it exceeds `bound`.
+///
+/// With a bound only the diagonal band of width `2 * bound + 1` is computed
+/// and the search stops as soon as a whole row exceeds the bound. `row` is
+/// scratch space reused across calls.
+pub fn levenshtein(
+    a: &[char],
+    b: &[char],
+    bound: Option<usize>,
+    row: &mut Vec<usize>,
+) -> Option<usize> {
+    let (n, m) = (a.len(), b.len());
+    if bound.is_some_and(|bound| n.abs_diff(m) > bound) {
+        return None;
+    }
+    // anything above the bound is clamped so the band edges never overflow
+    let beyond = bound.map(|bound| bound + 1).unwrap_or(usize::MAX - 1);
+
+    row.clear();
+    row.extend((0..=m).map(|j| j.min(beyond)));
+    for i in 1..=n {
+        let (lo, hi) = match bound {
+            Some(bound) => (i.saturating_sub(bound).max(1), (i + bound).min(m)),
+            None => (1, m),
+        };
+        let mut diagonal = row[lo - 1];
+        row[lo - 1] = if lo == 1 { i.min(beyond) } else { beyond };
+        let mut row_min = row[lo - 1];
+        for j in lo..=hi {
+            let above = row[j];
+            let substitution = diagonal + usize::from(a[i - 1] != b[j - 1]);
+            let distance = substitution.min(above + 1).min(row[j - 1] + 1).min(beyond);
+            diagonal = above;
+            row[j] = distance;
+            row_min = row_min.min(distance);
+        }
+        if row_min >= beyond {
+            return None;
+        }
+    }
+    Some(row[m]).filter(|distance| *distance < beyond)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FuzzyFunction {
+    Levenshtein,
+    Similarity,
+}
+
+/// `levenshtein(a, b [, max_distance])` and `similarity(a, b [, max_distance])`
+///
+/// levenshtein returns the edit distance in chars, similarity normalizes it
+/// to `1 - distance / max(char_length(a), char_length(b))` with two empty
+/// strings being fully similar. NULL in either string yields NULL.
+///
+/// The optional literal `max_distance` skips most of the work for pairs that
+/// are further apart: levenshtein then returns `max_distance + 1` and
+/// similarity returns 0 for them. A literal string is split into chars once
+/// for the whole batch.
+#[derive(Debug)]
+pub struct Fuzzy {
+    function: FuzzyFunction,
+    signature: Signature,
+}
+
+impl Fuzzy {
+    pub fn new(function: FuzzyFunction) -> Self {
+        Self {
+            function,
+            signature: Signature::one_of(
+                vec![
+                    TypeSignature::Exact(vec![DataType::Utf8, DataType::Utf8]),
+                    TypeSignature::Exact(vec![DataType::Utf8, DataType::Utf8, DataType::Int64]),
+                ],
+                Volatility::Immutable,
+            ),
+        }
+    }
+
+    pub fn all() -> Vec<Self> {
+        vec![
+            Self::new(FuzzyFunction::Levenshtein),
+            Self::new(FuzzyFunction::Similarity),
+        ]
+    }
+
+    fn bound(&self, value: &ScalarValue) -> Result<usize> {
+        match value {
+            ScalarValue::Int64(Some(bound)) if *bound >= 0 => Ok(*bound as usize),
+            other => Err(DataFusionError::Plan(format!(
+                "{} expects a non negative max distance, got {other}",
+                self.name()
+            ))),
+        }
+    }
+}
+
+/// One side of the comparison, literals are decoded once per batch
+enum Operand<'a> {
+    Literal(Option<Vec<char>>),
+    Column(&'a StringArray, Vec<char>),
+}
+
+impl<'a> Operand<'a> {
+    fn new(value: &'a ColumnarValue) -> Result<Self> {
+        match value {
+            ColumnarValue::Scalar(ScalarValue::Utf8(value)) => Ok(Self::Literal(
+                value.as_ref().map(|value| value.chars().collect()),
+            )),
+            ColumnarValue::Array(array) => Ok(Self::Column(array.as_string::<i32>(), Vec::new())),
+            ColumnarValue::Scalar(other) => Err(DataFusionError::Execution(format!(
+                "expected a string, got {other}"
+            ))),
+        }
+    }
+
+    fn chars(&mut self, row: usize) -> Option<&[char]> {
+        match self {
+            Self::Literal(chars) => chars.as_deref(),
+            Self::Column(array, buffer) => {
+                if array.is_null(row) {
+                    return None;
+                }
+                buffer.clear();
+                buffer.extend(array.value(row).chars());
+                Some(buffer)
+            }
+        }
+    }
+}
+
+impl ScalarUDFImpl for Fuzzy {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        match self.function {
+            FuzzyFunction::Levenshtein => "levenshtein",
+            FuzzyFunction::Similarity => "similarity",
+        }
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(match self.function {
+            FuzzyFunction::Levenshtein => DataType::Int64,
+            FuzzyFunction::Similarity => DataType::Float64,
+        })
+    }
+
+    fn simplify(&self, args: Vec<Expr>, _info: &dyn SimplifyInfo) -> Result<ExprSimplifyResult> {
+        require_literal(self.name(), &args, 2)?;
+        if let Some(Expr::Literal(bound)) = args.get(2) {
+            self.bound(bound)?;
+        }
+        Ok(ExprSimplifyResult::Original(args))
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let rows = num_rows(args);
+        let bound = literal_arg(self.name(), args, 2)?
+            .map(|bound| self.bound(bound))
+            .transpose()?;
+        let (mut a, mut b) = (Operand::new(&args[0])?, Operand::new(&args[1])?);
+        let mut scratch = Vec::new();
+        let mut distances = (0..rows).map(|row| {
+            let (a, b) = (a.chars(row)?, b.chars(row)?);
+            let distance = levenshtein(a, b, bound, &mut scratch);
+            Some((distance, a.len().max(b.len())))
+        });
+
+        let result: ArrayRef = match self.function {
+            FuzzyFunction::Levenshtein => {
+                let mut builder = Int64Builder::with_capacity(rows);
+                distances.by_ref().for_each(|compared| {
+                    builder.append_option(compared.map(|(distance, _)| {
+                        distance
+                            .or(bound.map(|bound| bound + 1))
+                            .unwrap_or_default() as i64
+                    }))
+                });
+                Arc::new(builder.finish())
+            }
+            FuzzyFunction::Similarity => {
+                let mut builder = Float64Builder::with_capacity(rows);
+                distances.by_ref().for_each(|compared| {
+                    builder.append_option(compared.map(|(distance, len)| match distance {
+                        Some(_) if len == 0 => 1.0,
+                        Some(distance) => 1.0 - distance as f64 / len as f64,
+                        None => 0.0,
+                    }))
+                });
+                Arc::new(builder.finish())
+            }
+        };
+        to_columnar_value(args, result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Instant};
+
+    use arrow_array::{
+        cast::AsArray,
+        types::{Float64Type, Int64Type},
+        Array, ArrayRef, StringArray,
+    };
+    use datafusion::{
+        logical_expr::{ColumnarValue, ScalarUDF, ScalarUDFImpl},
+        prelude::SessionContext,
+        scalar::ScalarValue,
+    };
+    use itertools::Itertools;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{levenshtein, Fuzzy, FuzzyFunction};
+
+    fn distance(a: &str, b: &str, bound: Option<usize>) -> Option<usize> {
+        let (a, b) = (a.chars().collect_vec(), b.chars().collect_vec());
+        levenshtein(&a, &b, bound, &mut Vec::new())
+    }
+
+    // textbook full matrix
+    fn reference(a: &[char], b: &[char]) -> usize {
+        let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+        (0..=a.len()).for_each(|i| d[i][0] = i);
+        (0..=b.len()).for_each(|j| d[0][j] = j);
+        for i in 1..=a.len() {
+            for j in 1..=b.len() {
+                let cost = usize::from(a[i - 1] != b[j - 1]);
+                d[i][j] = (d[i - 1][j - 1] + cost)
+                    .min(d[i - 1][j] + 1)
+                    .min(d[i][j - 1] + 1);
+            }
+        }
+        d[a.len()][b.len()]
+    }
+
+    #[test]
+    fn known_distances() {
+        assert_eq!(distance("kitten", "sitting", None), Some(3));
+        assert_eq!(distance("", "abc", None), Some(3));
+        assert_eq!(distance("abc", "", None), Some(3));
+        assert_eq!(distance("", "", None), Some(0));
+        assert_eq!(
+            distance(
+                "connection reset by peer",
+                "Connection reset by peer.",
+                None
+            ),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn multibyte_strings_compare_chars() {
+        assert_eq!(distance("café", "cafe", None), Some(1));
+        assert_eq!(distance("日本語", "日本", None), Some(1));
+        assert_eq!(distance("🦀rust", "rust🦀", None), Some(2));
+    }
+
+    #[test]
+    fn bounded_distance_exits_early() {
+        // length difference alone exceeds the bound
+        assert_eq!(distance("a", "abcdef", Some(2)), None);
+        assert_eq!(distance("kitten", "sitting", Some(2)), None);
+        assert_eq!(distance("kitten", "sitting", Some(3)), Some(3));
+        assert_eq!(distance("same", "same", Some(0)), Some(0));
+        assert_eq!(distance("same", "sane", Some(0)), None);
+    }
+
+    #[test]
+    fn band_matches_reference() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut row = Vec::new();
+        for _ in 0..2000 {
+            let a = (0..rng.gen_range(0..12))
+                .map(|_| rng.gen_range('a'..'e'))
+                .collect_vec();
+            let b = (0..rng.gen_range(0..12))
+                .map(|_| rng.gen_range('a'..'e'))
+                .collect_vec();
+            let expected = reference(&a, &b);
+            assert_eq!(levenshtein(&a, &b, None, &mut row), Some(expected));
+            for bound in 0..6 {
+                let bounded = levenshtein(&a, &b, Some(bound), &mut row);
+                assert_eq!(bounded, (expected <= bound).then_some(expected));
+            }
+        }
+    }
+
+    fn invoke(function: FuzzyFunction, args: &[ColumnarValue]) -> ArrayRef {
+        Fuzzy::new(function)
+            .invoke(args)
+            .unwrap()
+            .into_array(1)
+            .unwrap()
+    }
+
+    #[test]
+    fn nulls_and_literals() {
+        let column = ColumnarValue::Array(Arc::new(StringArray::from(vec![
+            Some("error: timeout"),
+            None,
+            Some("Error: timeout!"),
+            Some(""),
+        ])));
+        let literal = ColumnarValue::Scalar(ScalarValue::from("error: timeout"));
+
+        let distances = invoke(
+            FuzzyFunction::Levenshtein,
+            &[column.clone(), literal.clone()],
+        );
+        assert_eq!(
+            distances.as_primitive::<Int64Type>().iter().collect_vec(),
+            vec![Some(0), None, Some(2), Some(14)]
+        );
+
+        let similarity = invoke(
+            FuzzyFunction::Similarity,
+            &[literal.clone(), column.clone()],
+        );
+        let similarity = similarity.as_primitive::<Float64Type>();
+        assert_eq!(similarity.value(0), 1.0);
+        assert!(similarity.is_null(1));
+        assert!((similarity.value(2) - (1.0 - 2.0 / 15.0)).abs() < 1e-9);
+        assert_eq!(similarity.value(3), 0.0);
+
+        // pairs beyond the bound
+        let bound = ColumnarValue::Scalar(ScalarValue::Int64(Some(1)));
+        let distances = invoke(
+            FuzzyFunction::Levenshtein,
+            &[column.clone(), literal.clone(), bound.clone()],
+        );
+        assert_eq!(
+            distances.as_primitive::<Int64Type>().iter().collect_vec(),
+            vec![Some(0), None, Some(2), Some(2)]
+        );
+        let similarity = invoke(FuzzyFunction::Similarity, &[column, literal, bound]);
+        assert_eq!(similarity.as_primitive::<Float64Type>().value(2), 0.0);
+
+        let null = ColumnarValue::Scalar(ScalarValue::Utf8(None));
+        let empty = ColumnarValue::Scalar(ScalarValue::from(""));
+        let scalar = invoke(FuzzyFunction::Similarity, &[null, empty.clone()]);
+        assert!(scalar.is_null(0));
+        let scalar = invoke(FuzzyFunction::Similarity, &[empty.clone(), empty]);
+        assert_eq!(scalar.as_primitive::<Float64Type>().value(0), 1.0);
+    }
+
+    #[test]
+    fn literal_against_100k_rows() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let messages: ArrayRef = Arc::new(StringArray::from_iter_values((0..100_000).map(|i| {
+            let peer = rng.gen_range(0..1000);
+            match i % 3 {
+                0 => format!("connection reset by peer {peer}"),
+                1 => format!("Connection reset by peer {peer}."),
+                _ => format!("upstream request timeout after {peer}ms while reading response"),
+            }
+        })));
+        let literal = ColumnarValue::Scalar(ScalarValue::from("connection reset by peer 500"));
+        let args = |bound: Option<i64>| {
+            let mut args = vec![ColumnarValue::Array(messages.clone()), literal.clone()];
+            args.extend(bound.map(|bound| ColumnarValue::Scalar(ScalarValue::Int64(Some(bound)))));
+            args
+        };
+
+        let start = Instant::now();
+        let unbounded = invoke(FuzzyFunction::Levenshtein, &args(None));
+        let unbounded_elapsed = start.elapsed();
+        let start = Instant::now();
+        let bounded = invoke(FuzzyFunction::Levenshtein, &args(Some(5)));
+        let bounded_elapsed = start.elapsed();
+
+        assert_eq!(bounded.len(), 100_000);
+        assert!(
+            unbounded_elapsed.as_secs() < 30,
+            "took {unbounded_elapsed:?}"
+        );
+        assert!(bounded_elapsed.as_secs() < 30, "took {bounded_elapsed:?}");
+        // the bound only changes results which exceed it
+        for (unbounded, bounded) in unbounded
+            .as_primitive::<Int64Type>()
+            .values()
+            .iter()
+            .zip(bounded.as_primitive::<Int64Type>().values())
+        {
+            assert_eq!(*bounded, (*unbounded).min(6));
+        }
+    }
+
+    #[actix_web::test]
+    async fn max_distance_is_validated_while_planning() {
+        let ctx = SessionContext::new();
+        for function in Fuzzy::all() {
+            ctx.register_udf(ScalarUDF::from(function));
+        }
+        let batches = ctx
+            .sql("SELECT levenshtein('kitten', 'sitting'), similarity('abcd', 'abce', 2)")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(batches[0].column(0).as_primitive::<Int64Type>().value(0), 3);
+        assert_eq!(
+            batches[0].column(1).as_primitive::<Float64Type>().value(0),
+            0.75
+        );
+
+        for sql in [
+            "SELECT levenshtein('a', 'b', -1)",
+            "SELECT levenshtein(column1, 'b', column2) FROM (VALUES ('a', 1))",
+        ] {
+            let err = match ctx.sql(sql).await {
+                Ok(df) => df.collect().await.unwrap_err(),
+                Err(err) => err,
+            };
+            assert!(err.to_string().contains("levenshtein"), "{sql}: {err}");
+        }
+    }
+}
@@ -0,0 +1,525 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{
+    cast::AsArray,
+    types::{Float64Type, Int64Type},
+    Array, ArrayRef, Float64Array, RecordBatch, TimestampMillisecondArray, UInt32Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use datafusion::{
+    arrow::compute::{cast, take},
+    datasource::{function::TableFunctionImpl, MemTable, TableProvider},
+    error::{DataFusionError, Result},
+    execution::context::SessionState,
+    logical_expr::{PartitionEvaluator, Signature, TableType, Volatility, WindowUDFImpl},
+    physical_plan::ExecutionPlan,
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::time_bucket::Bucketer;
+
+/// Most buckets a single `gap_fill` call emits
+pub const MAX_BUCKETS: usize = 100_000;
+
+pub const BUCKET_COLUMN: &str = "bucket";
+
+/// `gap_fill(interval, start, end [, timezone [, origin]])`
+///
+/// Table with a `bucket` column holding the start of every bucket `time_bucket`
+/// puts instants from `start` up to `end` in, given the same interval, timezone
+/// and origin. Left joining bucketed aggregates onto it gives empty buckets a row
+/// with NULL values, which `locf` and `interpolate` can fill. The bounds are
+/// RFC 3339 timestamps, every argument is a literal.
+#[derive(Debug)]
+pub struct GapFill;
+
+fn literal_str(args: &[Expr], index: usize) -> Result<Option<&str>> {
+    match args.get(index) {
+        None => Ok(None),
+        Some(Expr::Literal(ScalarValue::Utf8(Some(value)))) => Ok(Some(value)),
+        Some(arg) => Err(DataFusionError::Plan(format!(
+            "gap_fill expects argument {} to be a string literal, got {arg}",
+            index + 1
+        ))),
+    }
+}
+
+fn bound(args: &[Expr], index: usize) -> Result<DateTime<Utc>> {
+    let value = literal_str(args, index)?.ok_or_else(|| {
+        DataFusionError::Plan(
+            "gap_fill expects an interval, the start and the end of its range".to_string(),
+        )
+    })?;
+    DateTime::parse_from_rfc3339(value)
+        .map(|instant| instant.with_timezone(&Utc))
+        .map_err(|_| {
+            DataFusionError::Plan(format!(
+                "gap_fill expects argument {} to be a timestamp like 2024-01-01T00:00:00Z, got {value}",
+                index + 1
+            ))
+        })
+}
+
+/// Starts of the buckets from the one containing `start` up to `end`, in milliseconds
+fn buckets(bucketer: &Bucketer, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<i64>> {
+    if end <= start {
+        return Err(DataFusionError::Plan(
+            "gap_fill expects the end of its range to be after its start".to_string(),
+        ));
+    }
+    let mut buckets = Vec::new();
+    let mut bucket = bucketer.bucket(start);
+    while bucket < end {
+        if buckets.len() == MAX_BUCKETS {
+            return Err(DataFusionError::Plan(format!(
+                "gap_fill would emit more than {MAX_BUCKETS} buckets, use a wider interval or a shorter range"
+            )));
+        }
+        buckets.push(bucket.timestamp_millis());
+        bucket = bucketer.next(bucket);
+    }
+    Ok(buckets)
+}
+
+impl TableFunctionImpl for GapFill {
+    fn call(&self, args: &[Expr]) -> Result<Arc<dyn TableProvider>> {
+        if !(3..=5).contains(&args.len()) {
+            return Err(DataFusionError::Plan(format!(
+                "gap_fill expects 3 to 5 arguments, got {}",
+                args.len()
+            )));
+        }
+        let width = literal_str(args, 0)?.ok_or_else(|| {
+            DataFusionError::Plan("gap_fill expects an interval like 5 minutes".to_string())
+        })?;
+        let bucketer = Bucketer::new(width, literal_str(args, 3)?, literal_str(args, 4)?)?;
+        let buckets = buckets(&bucketer, bound(args, 1)?, bound(args, 2)?)?;
+
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            BUCKET_COLUMN,
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            false,
+        )]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(TimestampMillisecondArray::from(buckets))],
+        )?;
+        Ok(Arc::new(GapFillTable {
+            buckets: MemTable::try_new(schema, vec![vec![batch]])?,
+        }))
+    }
+}
+
+/// Buckets of a `gap_fill` call. Unlike streams it is a temporary table, so
+/// queries don't filter it by their time range or authorize it as a stream
+pub struct GapFillTable {
+    buckets: MemTable,
+}
+
+#[async_trait]
+impl TableProvider for GapFillTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.buckets.schema()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Temporary
+    }
+
+    async fn scan(
+        &self,
+        state: &SessionState,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        self.buckets.scan(state, projection, filters, limit).await
+    }
+}
+
+/// `locf(value)`
+///
+/// Last observation carried forward: the value of the row, or when it is NULL
+/// the value of the closest earlier row in the window order that has one. The
+/// frame of the call is ignored.
+///
+/// Returns NULL until the partition has a non NULL value.
+#[derive(Debug)]
+pub struct Locf {
+    signature: Signature,
+}
+
+impl Locf {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::any(1, Volatility::Immutable),
+        }
+    }
+}
+
+impl WindowUDFImpl for Locf {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "locf"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        Ok(arg_types[0].clone())
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(LocfEvaluator))
+    }
+}
+
+#[derive(Debug)]
+struct LocfEvaluator;
+
+impl PartitionEvaluator for LocfEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+        let mut last = None;
+        let indices = (0..num_rows)
+            .map(|row| {
+                if values[0].is_valid(row) {
+                    last = Some(row as u32);
+                }
+                last
+            })
+            .collect::<UInt32Array>();
+        Ok(take(&values[0], &indices, None)?)
+    }
+}
+
+/// `interpolate(value, time)`
+///
+/// The value of the row, or when it is NULL the linear interpolation between the
+/// closest rows before and after it in the window order that have a value, by
+/// the timestamps in `time`. The frame of the call is ignored.
+///
+/// Returns NULL before the first and after the last row with a value.
+#[derive(Debug)]
+pub struct Interpolate {
+    signature: Signature,
+}
+
+impl Interpolate {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::any(2, Volatility::Immutable),
+        }
+    }
+}
+
+impl WindowUDFImpl for Interpolate {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "interpolate"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        if !arg_types[0].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "interpolate expects a numeric value, got {}",
+                arg_types[0]
+            )));
+        }
+        if !matches!(arg_types[1], DataType::Timestamp(_, _)) {
+            return Err(DataFusionError::Plan(format!(
+                "interpolate expects a timestamp to interpolate by, got {}",
+                arg_types[1]
+            )));
+        }
+        Ok(DataType::Float64)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(InterpolateEvaluator))
+    }
+}
+
+#[derive(Debug)]
+struct InterpolateEvaluator;
+
+impl PartitionEvaluator for InterpolateEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+        let input = cast(&values[0], &DataType::Float64)?;
+        let input = input.as_primitive::<Float64Type>();
+        let times = cast(&values[1], &DataType::Int64)?;
+        let times = times.as_primitive::<Int64Type>();
+
+        // rows interpolated between
+        let known: Vec<usize> = (0..num_rows)
+            .filter(|&row| input.is_valid(row) && times.is_valid(row))
+            .collect();
+        let mut after = 0;
+        let result = (0..num_rows)
+            .map(|row| {
+                if input.is_valid(row) {
+                    return Some(input.value(row));
+                }
+                while after < known.len() && known[after] < row {
+                    after += 1;
+                }
+                if after == 0 || after == known.len() || times.is_null(row) {
+                    return None;
+                }
+                let (start, end) = (known[after - 1], known[after]);
+                let (from, to) = (input.value(start), input.value(end));
+                let span = times.value(end) - times.value(start);
+                if span == 0 {
+                    return Some(from);
+                }
+                let elapsed = times.value(row) - times.value(start);
+                Some(from + (to - from) * elapsed as f64 / span as f64)
+            })
+            .collect::<Float64Array>();
+        Ok(Arc::new(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray,
+        types::{Float64Type, TimestampMillisecondType},
+        Float64Array, RecordBatch, TimestampMillisecondArray,
+    };
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use chrono::{DateTime, Utc};
+    use datafusion::{
+        datasource::MemTable,
+        logical_expr::{ScalarUDF, WindowUDF},
+        prelude::SessionContext,
+    };
+
+    use super::{buckets, GapFill, Interpolate, Locf, MAX_BUCKETS};
+    use crate::query::functions::time_bucket::{Bucketer, TimeBucket};
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().into()
+    }
+
+    // events at minutes 0, 3 and 4, minutes 1 and 2 have none
+    fn context() -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("value", DataType::Float64, true),
+        ]));
+        let timestamps = TimestampMillisecondArray::from_iter_values(
+            [
+                "2024-01-01T00:00:10Z",
+                "2024-01-01T00:00:50Z",
+                "2024-01-01T00:03:30Z",
+                "2024-01-01T00:04:00Z",
+            ]
+            .map(|value| utc(value).timestamp_millis()),
+        );
+        let values = Float64Array::from(vec![0.0, 2.0, 4.0, 5.0]);
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(timestamps), Arc::new(values)])
+                .unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_udf(ScalarUDF::from(TimeBucket::new()));
+        ctx.register_udtf("gap_fill", Arc::new(GapFill));
+        ctx.register_udwf(WindowUDF::from(Locf::new()));
+        ctx.register_udwf(WindowUDF::from(Interpolate::new()));
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn filled(ctx: &SessionContext, fill: &str) -> (Vec<i64>, Vec<Option<f64>>) {
+        let sql = format!(
+            "SELECT g.bucket, {fill} AS value \
+             FROM gap_fill('1 minute', '2024-01-01T00:00:00Z', '2024-01-01T00:05:00Z') g \
+             LEFT JOIN ( \
+                 SELECT time_bucket('1 minute', p_timestamp) AS bucket, avg(value) AS value \
+                 FROM metrics GROUP BY time_bucket('1 minute', p_timestamp) \
+             ) s ON g.bucket = s.bucket \
+             ORDER BY g.bucket"
+        );
+        let batches = ctx.sql(&sql).await.unwrap().collect().await.unwrap();
+        let buckets = batches
+            .iter()
+            .flat_map(|batch| {
+                batch
+                    .column(0)
+                    .as_primitive::<TimestampMillisecondType>()
+                    .values()
+                    .to_vec()
+            })
+            .collect();
+        let values = batches
+            .iter()
+            .flat_map(|batch| batch.column(1).as_primitive::<Float64Type>().iter())
+            .collect();
+        (buckets, values)
+    }
+
+    fn minutes() -> Vec<i64> {
+        (0..5)
+            .map(|minute| utc("2024-01-01T00:00:00Z").timestamp_millis() + minute * 60_000)
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn empty_buckets_are_null() {
+        let (buckets, values) = filled(&context(), "s.value").await;
+        assert_eq!(buckets, minutes());
+        assert_eq!(values, [Some(1.0), None, None, Some(4.0), Some(5.0)]);
+    }
+
+    #[actix_web::test]
+    async fn empty_buckets_take_the_previous_value() {
+        let (buckets, values) = filled(&context(), "locf(s.value) OVER (ORDER BY g.bucket)").await;
+        assert_eq!(buckets, minutes());
+        assert_eq!(
+            values,
+            [Some(1.0), Some(1.0), Some(1.0), Some(4.0), Some(5.0)]
+        );
+    }
+
+    #[actix_web::test]
+    async fn empty_buckets_are_interpolated() {
+        let fill = "interpolate(s.value, g.bucket) OVER (ORDER BY g.bucket)";
+        let (buckets, values) = filled(&context(), fill).await;
+        assert_eq!(buckets, minutes());
+        assert_eq!(
+            values,
+            [Some(1.0), Some(2.0), Some(3.0), Some(4.0), Some(5.0)]
+        );
+    }
+
+    #[actix_web::test]
+    async fn leading_gaps_stay_null() {
+        let ctx = context();
+        let sql = "SELECT locf(s.value) OVER (ORDER BY g.bucket), \
+                   interpolate(s.value, g.bucket) OVER (ORDER BY g.bucket) \
+                   FROM gap_fill('1 minute', '2023-12-31T23:59:00Z', '2024-01-01T00:06:00Z') g \
+                   LEFT JOIN (SELECT time_bucket('1 minute', p_timestamp) AS bucket, avg(value) AS value \
+                   FROM metrics GROUP BY time_bucket('1 minute', p_timestamp)) s ON g.bucket = s.bucket \
+                   ORDER BY g.bucket";
+        let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        let column = |index: usize| -> Vec<Option<f64>> {
+            batches
+                .iter()
+                .flat_map(|batch| batch.column(index).as_primitive::<Float64Type>().iter())
+                .collect()
+        };
+        let previous = column(0);
+        let linear = column(1);
+        assert_eq!(previous.first(), Some(&None));
+        assert_eq!(previous.last(), Some(&Some(5.0)));
+        assert_eq!(linear.first(), Some(&None));
+        assert_eq!(linear.last(), Some(&None));
+    }
+
+    #[test]
+    fn buckets_align_with_time_bucket() {
+        let bucketer = Bucketer::new("15 minutes", None, None).unwrap();
+        let starts = buckets(
+            &bucketer,
+            utc("2024-01-01T00:20:00Z"),
+            utc("2024-01-01T01:00:00Z"),
+        )
+        .unwrap();
+        let expected: Vec<_> = [
+            "2024-01-01T00:15:00Z",
+            "2024-01-01T00:30:00Z",
+            "2024-01-01T00:45:00Z",
+        ]
+        .map(|value| utc(value).timestamp_millis())
+        .to_vec();
+        assert_eq!(starts, expected);
+
+        // local days are 25 hours long when the clock moves back
+        let bucketer = Bucketer::new("1 day", Some("America/New_York"), None).unwrap();
+        let starts = buckets(
+            &bucketer,
+            utc("2024-11-03T04:00:00Z"),
+            utc("2024-11-05T05:00:00Z"),
+        )
+        .unwrap();
+        let expected: Vec<_> = ["2024-11-03T04:00:00Z", "2024-11-04T05:00:00Z"]
+            .map(|value| utc(value).timestamp_millis())
+            .to_vec();
+        assert_eq!(starts, expected);
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        let bucketer = Bucketer::new("1 second", None, None).unwrap();
+        let start = utc("2024-01-01T00:00:00Z");
+        assert!(buckets(&bucketer, start, start).is_err());
+        let end = start + chrono::Duration::seconds(MAX_BUCKETS as i64 + 1);
+        let err = buckets(&bucketer, start, end).unwrap_err();
+        assert!(err.to_string().contains("more than"), "{err}");
+    }
+
+    #[actix_web::test]
+    async fn arguments_are_validated_while_planning() {
+        let ctx = context();
+        for (sql, message) in [
+            ("SELECT * FROM gap_fill('1 minute', '2024-01-01T00:00:00Z')", "expects 3 to 5"),
+            (
+                "SELECT * FROM gap_fill('1 minute', 'yesterday', '2024-01-01T00:00:00Z')",
+                "timestamp like",
+            ),
+            (
+                "SELECT * FROM gap_fill('1 fortnight', '2024-01-01T00:00:00Z', '2024-01-02T00:00:00Z')",
+                "Invalid time_bucket interval",
+            ),
+        ] {
+            let err = ctx.sql(sql).await.unwrap_err();
+            assert!(err.to_string().contains(message), "{err}");
+        }
+    }
+}
@@ -0,0 +1,418 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{
+    builder::{Float64Builder, StringBuilder},
+    cast::AsArray,
+    types::{Float64Type, Int64Type},
+    Array, ArrayRef, StructArray,
+};
+use arrow_schema::{DataType, Field, Fields};
+use datafusion::{
+    arrow::buffer::NullBuffer,
+    error::{DataFusionError, Result},
+    logical_expr::{
+        simplify::{ExprSimplifyResult, SimplifyInfo},
+        ColumnarValue, ScalarUDFImpl, Signature, Volatility,
+    },
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::{num_rows, to_columnar_value};
+
+/// Characters of a geohash, each one carries 5 bits
+const BASE32: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Longest geohash encoded, its cells are a few centimeters wide
+pub const MAX_PRECISION: i64 = 12;
+
+fn validate_precision(precision: i64) -> Result<usize> {
+    if (1..=MAX_PRECISION).contains(&precision) {
+        Ok(precision as usize)
+    } else {
+        Err(DataFusionError::Plan(format!(
+            "geohash_encode precision must be between 1 and {MAX_PRECISION}, got {precision}"
+        )))
+    }
+}
+
+/// Geohash of the cell `lat` and `lon` are in, none when they're out of range
+pub fn encode(lat: f64, lon: f64, precision: usize) -> Option<String> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return None;
+    }
+    let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut hash = String::with_capacity(precision);
+    let mut index = 0;
+    // bits alternate between longitude and latitude, longitude first
+    for bit in 0..precision * 5 {
+        let (range, value) = match bit % 2 {
+            0 => (&mut lon_range, lon),
+            _ => (&mut lat_range, lat),
+        };
+        let mid = (range.0 + range.1) / 2.0;
+        index <<= 1;
+        if value >= mid {
+            index |= 1;
+            range.0 = mid;
+        } else {
+            range.1 = mid;
+        }
+        if bit % 5 == 4 {
+            hash.push(BASE32[index] as char);
+            index = 0;
+        }
+    }
+    Some(hash)
+}
+
+/// Latitude and longitude of the center of the cell of `hash`, none when it
+/// is empty or has characters geohashes don't
+pub fn decode(hash: &str) -> Option<(f64, f64)> {
+    if hash.is_empty() {
+        return None;
+    }
+    let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut bit = 0;
+    for c in hash.bytes() {
+        let index = BASE32
+            .iter()
+            .position(|base| *base == c.to_ascii_lowercase())?;
+        for shift in (0..5).rev() {
+            let range = match bit % 2 {
+                0 => &mut lon_range,
+                _ => &mut lat_range,
+            };
+            let mid = (range.0 + range.1) / 2.0;
+            if (index >> shift) & 1 == 1 {
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            bit += 1;
+        }
+    }
+    Some((
+        (lat_range.0 + lat_range.1) / 2.0,
+        (lon_range.0 + lon_range.1) / 2.0,
+    ))
+}
+
+/// `geohash_encode(lat, lon, precision)`
+///
+/// Geohash of `precision` characters of the cell a point is in, so that points
+/// can be grouped by area. The precision must be between 1 and
+/// [`MAX_PRECISION`], a literal one is checked while planning. Coordinates out
+/// of range evaluate to NULL, as does NULL.
+#[derive(Debug)]
+pub struct GeohashEncode {
+    signature: Signature,
+}
+
+impl GeohashEncode {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::exact(
+                vec![DataType::Float64, DataType::Float64, DataType::Int64],
+                Volatility::Immutable,
+            ),
+        }
+    }
+}
+
+impl ScalarUDFImpl for GeohashEncode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "geohash_encode"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Utf8)
+    }
+
+    fn simplify(&self, args: Vec<Expr>, _info: &dyn SimplifyInfo) -> Result<ExprSimplifyResult> {
+        if let Some(Expr::Literal(ScalarValue::Int64(Some(precision)))) = args.get(2) {
+            validate_precision(*precision)?;
+        }
+        Ok(ExprSimplifyResult::Original(args))
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let rows = num_rows(args);
+        let lats = args[0].clone().into_array(rows)?;
+        let lons = args[1].clone().into_array(rows)?;
+        let precisions = args[2].clone().into_array(rows)?;
+        let (lats, lons, precisions) = (
+            lats.as_primitive::<Float64Type>(),
+            lons.as_primitive::<Float64Type>(),
+            precisions.as_primitive::<Int64Type>(),
+        );
+        let mut builder = StringBuilder::with_capacity(rows, rows * MAX_PRECISION as usize);
+
+        for ((lat, lon), precision) in lats.iter().zip(lons.iter()).zip(precisions.iter()) {
+            match (lat, lon, precision) {
+                (Some(lat), Some(lon), Some(precision)) => {
+                    builder.append_option(encode(lat, lon, validate_precision(precision)?))
+                }
+                _ => builder.append_null(),
+            }
+        }
+
+        to_columnar_value(args, Arc::new(builder.finish()) as ArrayRef)
+    }
+}
+
+fn point_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("lat", DataType::Float64, false),
+        Field::new("lon", DataType::Float64, false),
+    ])
+}
+
+/// `geohash_decode(hash)`
+///
+/// Center of the cell of a geohash as a `{lat, lon}` struct, upper case
+/// geohashes decode the same. Values that are not geohashes evaluate to NULL,
+/// as does NULL.
+#[derive(Debug)]
+pub struct GeohashDecode {
+    signature: Signature,
+}
+
+impl GeohashDecode {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::exact(vec![DataType::Utf8], Volatility::Immutable),
+        }
+    }
+}
+
+impl ScalarUDFImpl for GeohashDecode {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "geohash_decode"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Struct(point_fields()))
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let hashes = args[0].clone().into_array(num_rows(args))?;
+        let hashes = hashes.as_string::<i32>();
+        let mut lats = Float64Builder::with_capacity(hashes.len());
+        let mut lons = Float64Builder::with_capacity(hashes.len());
+        let mut valid = Vec::with_capacity(hashes.len());
+
+        for hash in hashes.iter() {
+            let point = hash.and_then(decode);
+            // the fields of a NULL point still need values
+            let (lat, lon) = point.unwrap_or_default();
+            lats.append_value(lat);
+            lons.append_value(lon);
+            valid.push(point.is_some());
+        }
+
+        let points = StructArray::try_new(
+            point_fields(),
+            vec![Arc::new(lats.finish()), Arc::new(lons.finish())],
+            Some(NullBuffer::from(valid)),
+        )?;
+        to_columnar_value(args, Arc::new(points) as ArrayRef)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray, types::Float64Type, Array, ArrayRef, Float64Array, RecordBatch, StringArray,
+    };
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{datasource::MemTable, logical_expr::ScalarUDF, prelude::SessionContext};
+
+    use super::{GeohashDecode, GeohashEncode};
+
+    async fn query(
+        points: &[(Option<f64>, Option<f64>)],
+        hashes: &[Option<&str>],
+        sql: &str,
+    ) -> datafusion::error::Result<ArrayRef> {
+        let ctx = SessionContext::new();
+        ctx.register_udf(ScalarUDF::from(GeohashEncode::new()));
+        ctx.register_udf(ScalarUDF::from(GeohashDecode::new()));
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("lat", DataType::Float64, true),
+            Field::new("lon", DataType::Float64, true),
+        ]));
+        let (lats, lons): (Vec<_>, Vec<_>) = points.iter().copied().unzip();
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Float64Array::from(lats)),
+                Arc::new(Float64Array::from(lons)),
+            ],
+        )
+        .unwrap();
+        ctx.register_table(
+            "points",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+
+        let schema = Arc::new(Schema::new(vec![Field::new("hash", DataType::Utf8, true)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(StringArray::from(hashes.to_vec()))],
+        )
+        .unwrap();
+        ctx.register_table(
+            "hashes",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+
+        let batches = ctx.sql(sql).await?.collect().await?;
+        Ok(batches[0].column(0).clone())
+    }
+
+    fn strings(array: &ArrayRef) -> Vec<Option<&str>> {
+        array.as_string::<i32>().iter().collect()
+    }
+
+    #[actix_web::test]
+    async fn encodes_reference_points() {
+        let points = [
+            (Some(42.6), Some(-5.6)),
+            (Some(57.64911), Some(10.40744)),
+            (Some(-33.8688), Some(151.2093)),
+            (Some(-90.0), Some(-180.0)),
+            (Some(90.0), Some(180.0)),
+            (Some(90.5), Some(0.0)),
+            (Some(0.0), Some(f64::NAN)),
+            (None, Some(0.0)),
+        ];
+        let result = query(
+            &points,
+            &[],
+            "SELECT geohash_encode(lat, lon, 5) FROM points",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            strings(&result),
+            vec![
+                Some("ezs42"),
+                Some("u4pru"),
+                Some("r3gx2"),
+                Some("00000"),
+                Some("zzzzz"),
+                None,
+                None,
+                None
+            ]
+        );
+
+        let result = query(
+            &points[1..2],
+            &[],
+            "SELECT geohash_encode(lat, lon, 11) FROM points",
+        )
+        .await
+        .unwrap();
+        assert_eq!(strings(&result), vec![Some("u4pruydqqvj")]);
+    }
+
+    #[actix_web::test]
+    async fn decodes_to_the_center_of_the_cell() {
+        let hashes = [
+            Some("ezs42"),
+            Some("U4PRUYDQQVJ"),
+            Some("s"),
+            Some("ezs4a"),
+            Some(""),
+            None,
+        ];
+        let result = query(&[], &hashes, "SELECT geohash_decode(hash) FROM hashes")
+            .await
+            .unwrap();
+        let points = result.as_struct();
+        let lats = points.column(0).as_primitive::<Float64Type>();
+        let lons = points.column(1).as_primitive::<Float64Type>();
+        let decoded: Vec<_> = (0..points.len())
+            .map(|i| points.is_valid(i).then(|| (lats.value(i), lons.value(i))))
+            .collect();
+
+        let expected = [(42.605, -5.603), (57.64911, 10.40744), (22.5, 22.5)];
+        for (i, (lat, lon)) in expected.into_iter().enumerate() {
+            let (decoded_lat, decoded_lon) = decoded[i].unwrap();
+            assert!((decoded_lat - lat).abs() < 1e-3, "{decoded_lat} {lat}");
+            assert!((decoded_lon - lon).abs() < 1e-3, "{decoded_lon} {lon}");
+        }
+        assert_eq!(decoded[3..], [None, None, None]);
+    }
+
+    #[actix_web::test]
+    async fn points_group_by_cell() {
+        let points = [
+            (Some(37.7749), Some(-122.4194)),
+            (Some(37.7750), Some(-122.4180)),
+            (Some(40.7128), Some(-74.0060)),
+        ];
+        let result = query(
+            &points,
+            &[],
+            "SELECT geohash_encode(lat, lon, 6) AS cell, count(*) FROM points GROUP BY cell ORDER BY cell",
+        )
+        .await
+        .unwrap();
+        assert_eq!(strings(&result), vec![Some("9q8yyk"), Some("dr5reg")]);
+    }
+
+    #[actix_web::test]
+    async fn invalid_precision_fails() {
+        for sql in [
+            "SELECT geohash_encode(lat, lon, 0) FROM points",
+            "SELECT geohash_encode(lat, lon, 13) FROM points",
+        ] {
+            assert!(
+                query(&[(Some(0.0), Some(0.0))], &[], sql).await.is_err(),
+                "{sql}"
+            );
+        }
+    }
+}
@@ -0,0 +1,478 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{
+    cast::AsArray,
+    types::{Float64Type, Int64Type},
+    Array, ArrayRef, Float64Array, Int64Array, ListArray, StructArray,
+};
+use arrow_schema::{DataType, Field, Fields};
+use datafusion::arrow::{buffer::OffsetBuffer, compute::cast};
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{Accumulator, AggregateUDFImpl, Signature, TypeSignature, Volatility},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::require_literal;
+
+/// Largest number of edges accepted by histogram
+pub const MAX_EDGES: usize = 10_000;
+
+/// `histogram(col, 'e0,e1,...,en')` or `histogram(col, count, min, max)`
+///
+/// Counts values into the buckets `(e[i-1], e[i]]`, each reported with its
+/// upper bound as `le`. Values up to `e0` land in the underflow bucket and
+/// values above `en` in the overflow bucket whose `le` is infinity. The
+/// count, min, max form splits `[min, max]` into `count` uniform buckets.
+/// NULL and NaN values are ignored.
+#[derive(Debug)]
+pub struct Histogram {
+    signature: Signature,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::one_of(
+                vec![TypeSignature::Any(2), TypeSignature::Any(4)],
+                Volatility::Immutable,
+            ),
+        }
+    }
+}
+
+fn entry_fields() -> Fields {
+    Fields::from(vec![
+        Field::new("le", DataType::Float64, false),
+        Field::new("count", DataType::Int64, false),
+    ])
+}
+
+fn list_of(data_type: DataType) -> DataType {
+    DataType::List(Arc::new(Field::new("item", data_type, true)))
+}
+
+fn list_scalar(values: ArrayRef) -> ScalarValue {
+    let field = Arc::new(Field::new("item", values.data_type().clone(), true));
+    let offsets = OffsetBuffer::from_lengths([values.len()]);
+    ScalarValue::List(Arc::new(ListArray::new(field, offsets, values, None)))
+}
+
+/// Parses the bucket edges out of the literal arguments following the value
+pub fn edges(args: &[ScalarValue]) -> Result<Vec<f64>> {
+    let edges = match args {
+        [ScalarValue::Utf8(Some(spec))] => spec
+            .split(',')
+            .map(|edge| {
+                edge.trim().parse::<f64>().map_err(|_| {
+                    DataFusionError::Plan(format!("histogram expects numeric edges, got '{edge}'"))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?,
+        [count, min, max] => {
+            let count = match count {
+                ScalarValue::Int64(Some(count)) if (1..MAX_EDGES as i64).contains(count) => {
+                    *count as usize
+                }
+                other => {
+                    return Err(DataFusionError::Plan(format!(
+                        "histogram expects a bucket count between 1 and {}, got {other}",
+                        MAX_EDGES - 1
+                    )))
+                }
+            };
+            let (min, max) = (as_f64(min)?, as_f64(max)?);
+            (0..=count)
+                .map(|i| min + (max - min) * i as f64 / count as f64)
+                .collect()
+        }
+        _ => {
+            return Err(DataFusionError::Plan(
+                "histogram expects a comma separated list of edges or a count, min and max"
+                    .to_string(),
+            ))
+        }
+    };
+
+    if edges.len() > MAX_EDGES {
+        return Err(DataFusionError::Plan(format!(
+            "histogram accepts at most {MAX_EDGES} edges, got {}",
+            edges.len()
+        )));
+    }
+    if edges.iter().any(|edge| !edge.is_finite()) {
+        return Err(DataFusionError::Plan(
+            "histogram expects finite edges".to_string(),
+        ));
+    }
+    if edges.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(DataFusionError::Plan(
+            "histogram expects strictly increasing edges".to_string(),
+        ));
+    }
+    Ok(edges)
+}
+
+fn as_f64(value: &ScalarValue) -> Result<f64> {
+    match value.cast_to(&DataType::Float64)? {
+        ScalarValue::Float64(Some(value)) => Ok(value),
+        _ => Err(DataFusionError::Plan(format!(
+            "histogram expects a numeric bound, got {value}"
+        ))),
+    }
+}
+
+/// Checks the edges of a histogram call while planning
+pub fn validate_args(args: &[Expr]) -> Result<()> {
+    let literals = args
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(index, arg)| {
+            require_literal("histogram", args, index)?;
+            match arg {
+                Expr::Literal(value) => Ok(value.clone()),
+                _ => unreachable!("checked to be a literal"),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    edges(&literals).map(|_| ())
+}
+
+impl AggregateUDFImpl for Histogram {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "histogram"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        if !arg_types[0].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "histogram expects a numeric value, got {}",
+                arg_types[0]
+            )));
+        }
+        Ok(list_of(DataType::Struct(entry_fields())))
+    }
+
+    fn accumulator(&self, _arg: &DataType) -> Result<Box<dyn Accumulator>> {
+        Ok(Box::<HistogramAccumulator>::default())
+    }
+
+    fn state_type(&self, _return_type: &DataType) -> Result<Vec<DataType>> {
+        Ok(vec![list_of(DataType::Float64), list_of(DataType::Int64)])
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct HistogramAccumulator {
+    edges: Vec<f64>,
+    // one count per edge plus the overflow bucket, empty until edges are known
+    counts: Vec<i64>,
+}
+
+impl HistogramAccumulator {
+    fn init(&mut self, edges: &[f64]) -> Result<()> {
+        if self.counts.is_empty() {
+            self.edges = edges.to_vec();
+            self.counts = vec![0; edges.len() + 1];
+            Ok(())
+        } else if self.edges != edges {
+            Err(DataFusionError::Execution(
+                "histogram expects edges to be a constant".to_string(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn bucket(&self, value: f64) -> usize {
+        self.edges.partition_point(|edge| *edge < value)
+    }
+}
+
+impl Accumulator for HistogramAccumulator {
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        let array = &values[0];
+        if array.is_empty() {
+            return Ok(());
+        }
+        if self.counts.is_empty() {
+            let literals = values[1..]
+                .iter()
+                .map(|arg| ScalarValue::try_from_array(arg, 0))
+                .collect::<Result<Vec<_>>>()?;
+            self.init(&edges(&literals)?)?;
+        }
+
+        let array = cast(array, &DataType::Float64)?;
+        for value in array.as_primitive::<Float64Type>().iter().flatten() {
+            if !value.is_nan() {
+                let bucket = self.bucket(value);
+                self.counts[bucket] += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn evaluate(&mut self) -> Result<ScalarValue> {
+        if self.counts.is_empty() {
+            return ScalarValue::try_from(&list_of(DataType::Struct(entry_fields())));
+        }
+        let le: ArrayRef = Arc::new(Float64Array::from_iter_values(
+            self.edges.iter().copied().chain([f64::INFINITY]),
+        ));
+        let counts: ArrayRef = Arc::new(Int64Array::from(self.counts.clone()));
+        let entries = StructArray::try_new(entry_fields(), vec![le, counts], None)?;
+        Ok(list_scalar(Arc::new(entries)))
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self.edges.capacity() * std::mem::size_of::<f64>()
+            + self.counts.capacity() * std::mem::size_of::<i64>()
+    }
+
+    fn state(&mut self) -> Result<Vec<ScalarValue>> {
+        Ok(vec![
+            list_scalar(Arc::new(Float64Array::from(self.edges.clone()))),
+            list_scalar(Arc::new(Int64Array::from(self.counts.clone()))),
+        ])
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        let edges = states[0].as_list::<i32>();
+        let counts = states[1].as_list::<i32>();
+
+        for row in 0..edges.len() {
+            let row_counts = counts.value(row);
+            // partitions which saw no rows never learnt their edges
+            if edges.is_null(row) || row_counts.is_empty() {
+                continue;
+            }
+            let row_edges = edges.value(row);
+            self.init(row_edges.as_primitive::<Float64Type>().values())?;
+            self.counts
+                .iter_mut()
+                .zip(row_counts.as_primitive::<Int64Type>().values())
+                .for_each(|(count, other)| *count += other);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray,
+        types::{Float64Type, Int64Type},
+        ArrayRef, Float64Array, RecordBatch, StringArray,
+    };
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{
+        datasource::MemTable,
+        logical_expr::{Accumulator, AggregateUDF},
+        prelude::{SessionConfig, SessionContext},
+    };
+    use itertools::Itertools;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{Histogram, HistogramAccumulator};
+    use crate::query::functions::add_analyzer_rules;
+
+    const EDGES: [f64; 5] = [0., 10., 25., 50., 100.];
+
+    fn values() -> Vec<Option<f64>> {
+        let mut rng = StdRng::seed_from_u64(11);
+        (0..20_000)
+            .map(|i| match i % 97 {
+                0 => None,
+                // exact edges belong to the bucket they close
+                1 => Some(EDGES[i % EDGES.len()]),
+                _ => Some(rng.gen_range(-20.0..120.0)),
+            })
+            .collect()
+    }
+
+    // counts every bucket by scanning all values against its bounds
+    fn brute_force(values: &[Option<f64>], edges: &[f64]) -> Vec<(f64, i64)> {
+        let values = values.iter().flatten().copied().collect_vec();
+        let mut buckets = vec![(
+            edges[0],
+            values.iter().filter(|v| **v <= edges[0]).count() as i64,
+        )];
+        for pair in edges.windows(2) {
+            let count = values
+                .iter()
+                .filter(|v| **v > pair[0] && **v <= pair[1])
+                .count();
+            buckets.push((pair[1], count as i64));
+        }
+        let last = edges[edges.len() - 1];
+        buckets.push((
+            f64::INFINITY,
+            values.iter().filter(|v| **v > last).count() as i64,
+        ));
+        buckets
+    }
+
+    fn entries(list: &dyn arrow_array::Array) -> Vec<(f64, i64)> {
+        let entries = list.as_list::<i32>().value(0);
+        let entries = entries.as_struct();
+        entries
+            .column(0)
+            .as_primitive::<Float64Type>()
+            .values()
+            .iter()
+            .copied()
+            .zip(
+                entries
+                    .column(1)
+                    .as_primitive::<Int64Type>()
+                    .values()
+                    .iter()
+                    .copied(),
+            )
+            .collect()
+    }
+
+    fn update(acc: &mut HistogramAccumulator, values: &[Option<f64>]) {
+        let values: ArrayRef = Arc::new(Float64Array::from(values.to_vec()));
+        let edges: ArrayRef = Arc::new(StringArray::from(vec!["0,10,25,50,100"; values.len()]));
+        acc.update_batch(&[values, edges]).unwrap();
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let values = values();
+        let mut acc = HistogramAccumulator::default();
+        update(&mut acc, &values);
+        let result = acc.evaluate().unwrap().to_array().unwrap();
+        assert_eq!(entries(&result), brute_force(&values, &EDGES));
+    }
+
+    #[test]
+    fn merge_sums_counts() {
+        let values = values();
+        let mut merged = HistogramAccumulator::default();
+        // an empty partition contributes no edges and must not break the merge
+        let mut empty = HistogramAccumulator::default();
+        let state = empty
+            .state()
+            .unwrap()
+            .iter()
+            .map(|v| v.to_array().unwrap())
+            .collect_vec();
+        merged.merge_batch(&state).unwrap();
+        for chunk in values.chunks(values.len() / 4 + 1) {
+            let mut partial = HistogramAccumulator::default();
+            update(&mut partial, chunk);
+            let state = partial
+                .state()
+                .unwrap()
+                .iter()
+                .map(|v| v.to_array().unwrap())
+                .collect_vec();
+            merged.merge_batch(&state).unwrap();
+        }
+        let result = merged.evaluate().unwrap().to_array().unwrap();
+        assert_eq!(entries(&result), brute_force(&values, &EDGES));
+    }
+
+    fn context(values: &[Option<f64>]) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "latency",
+            DataType::Float64,
+            true,
+        )]));
+        let partitions = values
+            .chunks(values.len() / 4 + 1)
+            .map(|chunk| {
+                vec![RecordBatch::try_new(
+                    schema.clone(),
+                    vec![Arc::new(Float64Array::from(chunk.to_vec()))],
+                )
+                .unwrap()]
+            })
+            .collect_vec();
+        let config = SessionConfig::new().with_target_partitions(4);
+        let state = SessionContext::new_with_config(config).state();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(state));
+        ctx.register_udaf(AggregateUDF::from(Histogram::new()));
+        ctx.register_table(
+            "logs",
+            Arc::new(MemTable::try_new(schema, partitions).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn query(ctx: &SessionContext, sql: &str) -> datafusion::error::Result<Vec<(f64, i64)>> {
+        let batches = ctx.sql(sql).await?.collect().await?;
+        Ok(entries(batches[0].column(0)))
+    }
+
+    #[actix_web::test]
+    async fn histogram_in_sql_across_partitions() {
+        let values = values();
+        let ctx = context(&values);
+
+        let result = query(
+            &ctx,
+            "SELECT histogram(latency, '0, 10, 25, 50, 100') FROM logs",
+        )
+        .await
+        .unwrap();
+        assert_eq!(result, brute_force(&values, &EDGES));
+
+        let result = query(&ctx, "SELECT histogram(latency, 4, 0, 100) FROM logs")
+            .await
+            .unwrap();
+        assert_eq!(result, brute_force(&values, &[0., 25., 50., 75., 100.]));
+    }
+
+    #[actix_web::test]
+    async fn edges_are_validated_while_planning() {
+        let ctx = context(&[Some(1.)]);
+        // no row reaches the accumulator, so only planning can reject these
+        for sql in [
+            "SELECT histogram(latency, '0,10,10') FROM logs WHERE latency IS NULL",
+            "SELECT histogram(latency, '10,0') FROM logs WHERE latency IS NULL",
+            "SELECT histogram(latency, '0,ten') FROM logs WHERE latency IS NULL",
+            "SELECT histogram(latency, 0, 0, 100) FROM logs WHERE latency IS NULL",
+            "SELECT histogram(latency, 4, 100, 0) FROM logs WHERE latency IS NULL",
+            "SELECT histogram(latency, CAST(latency AS VARCHAR)) FROM logs WHERE latency IS NULL",
+        ] {
+            let err = query(&ctx, sql).await.unwrap_err();
+            assert!(err.to_string().contains("histogram"), "{sql}: {err}");
+        }
+    }
+}
@@ -0,0 +1,499 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, collections::HashMap, sync::Arc};
+
+use arrow_array::{cast::AsArray, types::Float64Type, Array, ArrayRef, Float64Array, ListArray};
+use arrow_schema::{DataType, Field};
+use datafusion::arrow::{buffer::OffsetBuffer, compute::cast};
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{Accumulator, AggregateUDFImpl, Signature, Volatility},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::require_literal;
+
+fn quantile_arg(quantile: &ScalarValue) -> Result<f64> {
+    match quantile.cast_to(&DataType::Float64) {
+        Ok(ScalarValue::Float64(Some(value))) if (0.0..=1.0).contains(&value) => Ok(value),
+        _ => Err(DataFusionError::Plan(format!(
+            "histogram_quantile expects a quantile between 0 and 1, got {quantile}"
+        ))),
+    }
+}
+
+/// Checks the quantile of a histogram_quantile call while planning
+pub fn validate_args(args: &[Expr]) -> Result<()> {
+    require_literal("histogram_quantile", args, 0)?;
+    match args.first() {
+        Some(Expr::Literal(quantile)) => quantile_arg(quantile).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// `histogram_quantile(quantile, le, count)`
+///
+/// Quantile (0 to 1) of a histogram stored as cumulative buckets, one row
+/// per bucket with its upper bound `le` and the `count` of observations up to
+/// it, the way Prometheus' `histogram_quantile` reads them. `le` is numeric
+/// or a label such as `'0.5'` or `'+Inf'`, and rows sharing a bound are
+/// summed, so the buckets of several series aggregate into one histogram.
+///
+/// The quantile is interpolated linearly within the bucket it falls in, the
+/// lowest bucket starting at 0, or given as its bound when that isn't
+/// positive. A quantile in the `+Inf` bucket gives the highest finite bound. Groups without a
+/// `+Inf` bucket, with fewer than two buckets or no observations give NULL
+/// where Prometheus gives NaN. Rows with a NULL or NaN bound or count are
+/// ignored.
+#[derive(Debug)]
+pub struct HistogramQuantile {
+    signature: Signature,
+}
+
+impl HistogramQuantile {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::any(3, Volatility::Immutable),
+        }
+    }
+}
+
+fn list_of(data_type: DataType) -> DataType {
+    DataType::List(Arc::new(Field::new("item", data_type, true)))
+}
+
+fn list_scalar(values: ArrayRef) -> ScalarValue {
+    let field = Arc::new(Field::new("item", values.data_type().clone(), true));
+    let offsets = OffsetBuffer::from_lengths([values.len()]);
+    ScalarValue::List(Arc::new(ListArray::new(field, offsets, values, None)))
+}
+
+impl AggregateUDFImpl for HistogramQuantile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "histogram_quantile"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        let le = &arg_types[1];
+        if !le.is_numeric() && !matches!(le, DataType::Utf8 | DataType::LargeUtf8) {
+            return Err(DataFusionError::Plan(format!(
+                "histogram_quantile expects a numeric or string bucket bound, got {le}"
+            )));
+        }
+        if !arg_types[2].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "histogram_quantile expects a numeric count, got {}",
+                arg_types[2]
+            )));
+        }
+        Ok(DataType::Float64)
+    }
+
+    fn accumulator(&self, _arg: &DataType) -> Result<Box<dyn Accumulator>> {
+        Ok(Box::<HistogramQuantileAccumulator>::default())
+    }
+
+    fn state_type(&self, _return_type: &DataType) -> Result<Vec<DataType>> {
+        Ok(vec![
+            DataType::Float64,
+            list_of(DataType::Float64),
+            list_of(DataType::Float64),
+        ])
+    }
+}
+
+/// Bucket bounds as numbers, labels parsed the way Prometheus writes them
+fn bounds(le: &ArrayRef) -> Result<Float64Array> {
+    match le.data_type() {
+        DataType::Utf8 | DataType::LargeUtf8 => {
+            let le = cast(le, &DataType::Utf8)?;
+            le.as_string::<i32>()
+                .iter()
+                .map(|bound| {
+                    bound
+                        .map(|bound| {
+                            // also takes `+Inf`, `Inf` and `inf`
+                            bound.trim().parse::<f64>().map_err(|_| {
+                                DataFusionError::Execution(format!(
+                                    "histogram_quantile expects numeric bucket bounds, got '{bound}'"
+                                ))
+                            })
+                        })
+                        .transpose()
+                })
+                .collect()
+        }
+        _ => Ok(cast(le, &DataType::Float64)?
+            .as_primitive::<Float64Type>()
+            .clone()),
+    }
+}
+
+/// Quantile of cumulative `buckets` sorted by bound, after Prometheus'
+/// `bucketQuantile`
+fn bucket_quantile(quantile: f64, buckets: &[(f64, f64)]) -> Option<f64> {
+    if buckets.len() < 2 || buckets.last()?.0 != f64::INFINITY {
+        return None;
+    }
+    // counts of a scrape racing increments can dip, a bucket holds at least
+    // the ones below it
+    let mut counts = Vec::with_capacity(buckets.len());
+    for (_, count) in buckets {
+        let previous = counts.last().copied().unwrap_or(f64::MIN);
+        counts.push(count.max(previous));
+    }
+    let observations = *counts.last()?;
+    if observations <= 0.0 {
+        return None;
+    }
+
+    let mut rank = quantile * observations;
+    let bucket = counts.partition_point(|count| *count < rank);
+    if bucket == buckets.len() - 1 {
+        return Some(buckets[bucket - 1].0);
+    }
+    if bucket == 0 && buckets[0].0 <= 0.0 {
+        return Some(buckets[0].0);
+    }
+    let (mut start, end, mut count) = (0.0, buckets[bucket].0, counts[bucket]);
+    if bucket > 0 {
+        start = buckets[bucket - 1].0;
+        count -= counts[bucket - 1];
+        rank -= counts[bucket - 1];
+    }
+    Some(start + (end - start) * (rank / count))
+}
+
+/// Counts summed per bucket bound, the quantile is known once values arrive
+#[derive(Debug, Default)]
+pub struct HistogramQuantileAccumulator {
+    quantile: Option<f64>,
+    // keyed by the bits of the bound
+    buckets: HashMap<u64, (f64, f64)>,
+}
+
+impl HistogramQuantileAccumulator {
+    fn init(&mut self, quantile: f64) -> Result<()> {
+        match self.quantile {
+            Some(known) if known != quantile => Err(DataFusionError::Execution(
+                "histogram_quantile expects the quantile to be a constant".to_string(),
+            )),
+            _ => {
+                self.quantile = Some(quantile);
+                Ok(())
+            }
+        }
+    }
+
+    fn add(&mut self, le: f64, count: f64) {
+        if le.is_nan() || count.is_nan() {
+            return;
+        }
+        // -0 and 0 are the same bound
+        let le = if le == 0.0 { 0.0 } else { le };
+        self.buckets.entry(le.to_bits()).or_insert((le, 0.0)).1 += count;
+    }
+
+    fn sorted(&self) -> Vec<(f64, f64)> {
+        let mut buckets: Vec<_> = self.buckets.values().copied().collect();
+        buckets.sort_by(|a, b| a.0.total_cmp(&b.0));
+        buckets
+    }
+}
+
+impl Accumulator for HistogramQuantileAccumulator {
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        if values[0].is_empty() {
+            return Ok(());
+        }
+        self.init(quantile_arg(&ScalarValue::try_from_array(&values[0], 0)?)?)?;
+        let bounds = bounds(&values[1])?;
+        let counts = cast(&values[2], &DataType::Float64)?;
+        for (le, count) in bounds
+            .iter()
+            .zip(counts.as_primitive::<Float64Type>().iter())
+        {
+            if let (Some(le), Some(count)) = (le, count) {
+                self.add(le, count);
+            }
+        }
+        Ok(())
+    }
+
+    fn evaluate(&mut self) -> Result<ScalarValue> {
+        let quantile = self
+            .quantile
+            .and_then(|quantile| bucket_quantile(quantile, &self.sorted()));
+        Ok(ScalarValue::Float64(quantile))
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self.buckets.capacity() * std::mem::size_of::<(u64, (f64, f64))>()
+    }
+
+    fn state(&mut self) -> Result<Vec<ScalarValue>> {
+        let (bounds, counts): (Vec<f64>, Vec<f64>) = self.sorted().into_iter().unzip();
+        Ok(vec![
+            ScalarValue::Float64(self.quantile),
+            list_scalar(Arc::new(Float64Array::from(bounds))),
+            list_scalar(Arc::new(Float64Array::from(counts))),
+        ])
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        let quantiles = states[0].as_primitive::<Float64Type>();
+        let bounds = states[1].as_list::<i32>();
+        let counts = states[2].as_list::<i32>();
+
+        for row in 0..quantiles.len() {
+            // partitions which saw no rows never learnt the quantile
+            if quantiles.is_null(row) {
+                continue;
+            }
+            self.init(quantiles.value(row))?;
+            let row_bounds = bounds.value(row);
+            let row_counts = counts.value(row);
+            for (le, count) in row_bounds
+                .as_primitive::<Float64Type>()
+                .values()
+                .iter()
+                .zip(row_counts.as_primitive::<Float64Type>().values())
+            {
+                self.add(*le, *count);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray, types::Float64Type, ArrayRef, Float64Array, Int64Array, RecordBatch,
+        StringArray,
+    };
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{
+        datasource::MemTable,
+        logical_expr::{Accumulator, AggregateUDF},
+        prelude::{SessionConfig, SessionContext},
+        scalar::ScalarValue,
+    };
+
+    use super::{bucket_quantile, HistogramQuantile, HistogramQuantileAccumulator};
+    use crate::query::functions::add_analyzer_rules;
+
+    // a classic latency bucket set in seconds, 100 observations
+    const BUCKETS: [(&str, i64); 9] = [
+        ("0.05", 10),
+        ("0.1", 30),
+        ("0.25", 60),
+        ("0.5", 80),
+        ("1", 90),
+        ("2.5", 97),
+        ("5", 98),
+        ("10", 100),
+        ("+Inf", 100),
+    ];
+
+    fn buckets() -> Vec<(f64, f64)> {
+        BUCKETS
+            .iter()
+            .map(|(le, count)| (le.parse().unwrap(), *count as f64))
+            .collect()
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.unwrap();
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn interpolates_within_the_bucket() {
+        let buckets = buckets();
+        assert_close(bucket_quantile(0.5, &buckets), 0.2);
+        assert_close(bucket_quantile(0.9, &buckets), 1.0);
+        assert_close(bucket_quantile(0.95, &buckets), 1.0 + 1.5 * 5.0 / 7.0);
+        assert_close(bucket_quantile(0.99, &buckets), 7.5);
+        assert_close(bucket_quantile(0.0, &buckets), 0.0);
+        assert_close(bucket_quantile(1.0, &buckets), 10.0);
+    }
+
+    #[test]
+    fn follows_prometheus_edge_cases() {
+        // observations above the last finite bound
+        let mut overflowing = buckets();
+        overflowing.last_mut().unwrap().1 = 110.0;
+        assert_close(bucket_quantile(0.99, &overflowing), 10.0);
+
+        // a count below the one before it is raised to it
+        let mut dipping = buckets();
+        dipping[3].1 = 50.0;
+        assert_close(bucket_quantile(0.5, &dipping), 0.2);
+        assert_close(bucket_quantile(0.7, &dipping), 0.5 + 0.5 / 3.0);
+
+        // a non positive lowest bound is returned as is
+        assert_close(
+            bucket_quantile(0.1, &[(-1.0, 5.0), (f64::INFINITY, 10.0)]),
+            -1.0,
+        );
+
+        assert_eq!(bucket_quantile(0.5, &buckets()[..8]), None);
+        assert_eq!(bucket_quantile(0.5, &[(f64::INFINITY, 10.0)]), None);
+        assert_eq!(
+            bucket_quantile(0.5, &[(1.0, 0.0), (f64::INFINITY, 0.0)]),
+            None
+        );
+    }
+
+    #[test]
+    fn merge_sums_counts_per_bound() {
+        let mut merged = HistogramQuantileAccumulator::default();
+        // an empty partition contributes no quantile and must not break the merge
+        let mut empty = HistogramQuantileAccumulator::default();
+        let states = [empty.state().unwrap()]
+            .into_iter()
+            .chain(BUCKETS.chunks(4).map(|chunk| {
+                let mut partial = HistogramQuantileAccumulator::default();
+                let quantile: ArrayRef = Arc::new(Float64Array::from(vec![0.5; chunk.len()]));
+                let le: ArrayRef = Arc::new(StringArray::from_iter_values(
+                    chunk.iter().map(|bucket| bucket.0),
+                ));
+                // two series splitting every bucket
+                let half: ArrayRef = Arc::new(Int64Array::from_iter_values(
+                    chunk.iter().map(|bucket| bucket.1 / 2),
+                ));
+                let rest: ArrayRef = Arc::new(Int64Array::from_iter_values(
+                    chunk.iter().map(|bucket| bucket.1 - bucket.1 / 2),
+                ));
+                partial
+                    .update_batch(&[quantile.clone(), le.clone(), half])
+                    .unwrap();
+                partial.update_batch(&[quantile, le, rest]).unwrap();
+                partial.state().unwrap()
+            }));
+        for state in states {
+            let state: Vec<_> = state.iter().map(|v| v.to_array().unwrap()).collect();
+            merged.merge_batch(&state).unwrap();
+        }
+        let ScalarValue::Float64(median) = merged.evaluate().unwrap() else {
+            panic!("histogram_quantile returns a double");
+        };
+        assert_close(median, 0.2);
+    }
+
+    fn context() -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("service", DataType::Utf8, false),
+            Field::new("le", DataType::Utf8, true),
+            Field::new("count", DataType::Int64, true),
+        ]));
+        // the buckets of api spread across partitions, web has no +Inf bucket
+        let partitions = BUCKETS
+            .chunks(3)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut rows: Vec<_> = chunk
+                    .iter()
+                    .map(|(le, count)| ("api", Some(*le), Some(*count)))
+                    .collect();
+                rows.push(("web", Some(BUCKETS[index].0), Some(10)));
+                rows.push(("api", None, Some(1000)));
+                vec![RecordBatch::try_new(
+                    schema.clone(),
+                    vec![
+                        Arc::new(StringArray::from_iter_values(rows.iter().map(|row| row.0))),
+                        Arc::new(StringArray::from_iter(rows.iter().map(|row| row.1))),
+                        Arc::new(Int64Array::from_iter(rows.iter().map(|row| row.2))),
+                    ],
+                )
+                .unwrap()]
+            })
+            .collect();
+        let config = SessionConfig::new().with_target_partitions(3);
+        let state = SessionContext::new_with_config(config).state();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(state));
+        ctx.register_udaf(AggregateUDF::from(HistogramQuantile::new()));
+        ctx.register_table(
+            "latency",
+            Arc::new(MemTable::try_new(schema, partitions).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    #[actix_web::test]
+    async fn quantiles_per_group_in_sql() {
+        let ctx = context();
+        let batches = ctx
+            .sql(
+                "SELECT service,
+                        histogram_quantile(0.5, le, count),
+                        histogram_quantile(0.9, le, count),
+                        histogram_quantile(0.99, le, count)
+                 FROM latency GROUP BY service ORDER BY service",
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let batch = &batches[0];
+        let column = |index: usize| -> Vec<Option<f64>> {
+            batch
+                .column(index)
+                .as_primitive::<Float64Type>()
+                .iter()
+                .collect()
+        };
+        assert_eq!(batch.num_rows(), 2);
+        assert_close(column(1)[0], 0.2);
+        assert_close(column(2)[0], 1.0);
+        assert_close(column(3)[0], 7.5);
+        assert_eq!(column(1)[1], None);
+    }
+
+    #[actix_web::test]
+    async fn quantile_is_validated_while_planning() {
+        let ctx = context();
+        // no row reaches the accumulator, so only planning can reject these
+        for quantile in ["1.5", "-0.1", "'half'", "CAST(count AS DOUBLE)"] {
+            let sql = format!(
+                "SELECT histogram_quantile({quantile}, le, count) FROM latency WHERE count IS NULL"
+            );
+            let err = ctx.sql(&sql).await.unwrap().collect().await.unwrap_err();
+            assert!(
+                err.to_string().contains("histogram_quantile"),
+                "{quantile}: {err}"
+            );
+        }
+    }
+}
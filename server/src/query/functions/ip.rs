@@ -0,0 +1,526 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, net::IpAddr, sync::Arc};
+
+use arrow_array::{
+    builder::{BooleanBuilder, Int64Builder},
+    cast::AsArray,
+    Array, ArrayRef,
+};
+use arrow_schema::DataType;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{
+        simplify::{ExprSimplifyResult, SimplifyInfo},
+        ColumnarValue, ScalarUDFImpl, Signature, Volatility,
+    },
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::{literal_arg, num_rows, require_literal, to_columnar_value};
+
+// ipv4 mapped ipv6 addresses (::ffff:a.b.c.d) are treated as the ipv4 address they carry
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    match value.trim().parse().ok()? {
+        IpAddr::V6(v6) => Some(
+            v6.to_ipv4_mapped()
+                .map(IpAddr::V4)
+                .unwrap_or(IpAddr::V6(v6)),
+        ),
+        v4 => Some(v4),
+    }
+}
+
+fn bits(ip: IpAddr) -> (u128, u8) {
+    match ip {
+        IpAddr::V4(v4) => (u32::from(v4) as u128, 32),
+        IpAddr::V6(v6) => (u128::from(v6), 128),
+    }
+}
+
+/// Network in CIDR notation, a bare address is a network of one address
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(value: &str) -> Result<Self> {
+        let invalid = || DataFusionError::Plan(format!("Invalid CIDR {value}"));
+        let (addr, prefix) = match value.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value.trim(), None),
+        };
+        let addr = parse_ip(addr).ok_or_else(invalid)?;
+        let width = bits(addr).1;
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+            None => width,
+        };
+        if prefix > width {
+            return Err(invalid());
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// Binary prefix trie over address bits, one root per address family
+#[derive(Debug, Default)]
+pub struct CidrSet {
+    v4: Trie,
+    v6: Trie,
+}
+
+#[derive(Debug)]
+struct Trie {
+    // children of each node by next bit, index 0 is the root
+    nodes: Vec<[u32; 2]>,
+    terminal: Vec<bool>,
+}
+
+impl Default for Trie {
+    fn default() -> Self {
+        Self {
+            nodes: vec![[0, 0]],
+            terminal: vec![false],
+        }
+    }
+}
+
+impl Trie {
+    fn insert(&mut self, value: u128, width: u8, prefix: u8) {
+        let mut node = 0;
+        for i in 0..prefix {
+            if self.terminal[node] {
+                // a shorter network already covers this one
+                return;
+            }
+            let bit = ((value >> (width - 1 - i)) & 1) as usize;
+            if self.nodes[node][bit] == 0 {
+                self.nodes.push([0, 0]);
+                self.terminal.push(false);
+                self.nodes[node][bit] = (self.nodes.len() - 1) as u32;
+            }
+            node = self.nodes[node][bit] as usize;
+        }
+        self.terminal[node] = true;
+    }
+
+    fn contains(&self, value: u128, width: u8) -> bool {
+        let mut node = 0;
+        for i in 0..width {
+            if self.terminal[node] {
+                return true;
+            }
+            let bit = ((value >> (width - 1 - i)) & 1) as usize;
+            match self.nodes[node][bit] {
+                0 => return false,
+                next => node = next as usize,
+            }
+        }
+        self.terminal[node]
+    }
+}
+
+impl CidrSet {
+    /// Parses a comma separated list of networks
+    pub fn parse(list: &str) -> Result<Self> {
+        let mut set = Self::default();
+        for cidr in list.split(',').filter(|cidr| !cidr.trim().is_empty()) {
+            set.insert(Cidr::parse(cidr)?);
+        }
+        Ok(set)
+    }
+
+    pub fn insert(&mut self, cidr: Cidr) {
+        let (value, width) = bits(cidr.addr);
+        match cidr.addr {
+            IpAddr::V4(_) => self.v4.insert(value, width, cidr.prefix),
+            IpAddr::V6(_) => self.v6.insert(value, width, cidr.prefix),
+        }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (value, width) = bits(ip);
+        match ip {
+            IpAddr::V4(_) => self.v4.contains(value, width),
+            IpAddr::V6(_) => self.v6.contains(value, width),
+        }
+    }
+}
+
+/// Which ip function to evaluate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpFunction {
+    InCidr,
+    ContainsAny,
+}
+
+/// `ip_in_cidr(ip, cidr)` and `cidr_contains_any(ip, 'cidr1,cidr2,...')`
+///
+/// A literal network is checked while planning and parsed once per batch, the networks
+/// of `cidr_contains_any` must be one. `ip_in_cidr` also takes a network per row, then
+/// malformed addresses and networks evaluate to NULL. `cidr_contains_any` has values
+/// that are not valid addresses in no network, evaluating to false. NULL stays NULL.
+#[derive(Debug)]
+pub struct IpMatch {
+    function: IpFunction,
+    signature: Signature,
+}
+
+impl IpMatch {
+    pub fn new(function: IpFunction) -> Self {
+        Self {
+            function,
+            signature: Signature::exact(
+                vec![DataType::Utf8, DataType::Utf8],
+                Volatility::Immutable,
+            ),
+        }
+    }
+
+    fn parse_networks(&self, networks: &str) -> Result<CidrSet> {
+        match self.function {
+            IpFunction::InCidr => {
+                let mut set = CidrSet::default();
+                set.insert(Cidr::parse(networks)?);
+                Ok(set)
+            }
+            IpFunction::ContainsAny => CidrSet::parse(networks),
+        }
+    }
+
+    // none when `ip_in_cidr` gets a malformed address
+    fn contains(&self, networks: &CidrSet, ip: &str) -> Option<bool> {
+        match (parse_ip(ip), self.function) {
+            (Some(ip), _) => Some(networks.contains(ip)),
+            (None, IpFunction::InCidr) => None,
+            (None, IpFunction::ContainsAny) => Some(false),
+        }
+    }
+}
+
+impl ScalarUDFImpl for IpMatch {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        match self.function {
+            IpFunction::InCidr => "ip_in_cidr",
+            IpFunction::ContainsAny => "cidr_contains_any",
+        }
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Boolean)
+    }
+
+    fn simplify(&self, args: Vec<Expr>, _info: &dyn SimplifyInfo) -> Result<ExprSimplifyResult> {
+        if self.function == IpFunction::ContainsAny {
+            require_literal(self.name(), &args, 1)?;
+        }
+        if let Some(Expr::Literal(ScalarValue::Utf8(Some(networks)))) = args.get(1) {
+            self.parse_networks(networks)?;
+        }
+        Ok(ExprSimplifyResult::Original(args))
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let ips = args[0].clone().into_array(num_rows(args))?;
+        let ips = ips.as_string::<i32>();
+        let mut builder = BooleanBuilder::with_capacity(ips.len());
+
+        match &args[1] {
+            ColumnarValue::Scalar(ScalarValue::Utf8(Some(networks))) => {
+                let networks = self.parse_networks(networks)?;
+                for ip in ips.iter() {
+                    builder.append_option(ip.and_then(|ip| self.contains(&networks, ip)));
+                }
+            }
+            ColumnarValue::Array(cidrs) if self.function == IpFunction::InCidr => {
+                let cidrs = cidrs.as_string::<i32>();
+                for (ip, cidr) in ips.iter().zip(cidrs.iter()) {
+                    let network = cidr.and_then(|cidr| {
+                        let mut set = CidrSet::default();
+                        set.insert(Cidr::parse(cidr).ok()?);
+                        Some(set)
+                    });
+                    builder.append_option(
+                        ip.zip(network)
+                            .and_then(|(ip, network)| self.contains(&network, ip)),
+                    );
+                }
+            }
+            _ => {
+                literal_arg(self.name(), args, 1)?;
+                builder.append_nulls(ips.len())
+            }
+        }
+
+        to_columnar_value(args, Arc::new(builder.finish()) as ArrayRef)
+    }
+}
+
+/// `ip_to_int(ip)`
+///
+/// Numeric value of an ipv4 address for range comparisons. IPv6 addresses
+/// do not fit and, like malformed values, evaluate to NULL.
+#[derive(Debug)]
+pub struct IpToInt {
+    signature: Signature,
+}
+
+impl IpToInt {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::exact(vec![DataType::Utf8], Volatility::Immutable),
+        }
+    }
+}
+
+impl ScalarUDFImpl for IpToInt {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "ip_to_int"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Int64)
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let ips = args[0].clone().into_array(num_rows(args))?;
+        let ips = ips.as_string::<i32>();
+        let mut builder = Int64Builder::with_capacity(ips.len());
+
+        for ip in ips.iter() {
+            builder.append_option(ip.and_then(parse_ip).and_then(|ip| match ip {
+                IpAddr::V4(v4) => Some(u32::from(v4) as i64),
+                IpAddr::V6(_) => None,
+            }));
+        }
+
+        to_columnar_value(args, Arc::new(builder.finish()) as ArrayRef)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{cast::AsArray, types::Int64Type, ArrayRef, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{datasource::MemTable, logical_expr::ScalarUDF, prelude::SessionContext};
+
+    use super::{CidrSet, IpFunction, IpMatch, IpToInt};
+
+    async fn query(ips: &[Option<&str>], sql: &str) -> datafusion::error::Result<ArrayRef> {
+        let schema = Arc::new(Schema::new(vec![Field::new("ip", DataType::Utf8, true)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(StringArray::from(ips.to_vec()))],
+        )
+        .unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_udf(ScalarUDF::from(IpMatch::new(IpFunction::InCidr)));
+        ctx.register_udf(ScalarUDF::from(IpMatch::new(IpFunction::ContainsAny)));
+        ctx.register_udf(ScalarUDF::from(IpToInt::new()));
+        ctx.register_table(
+            "access",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+
+        let batches = ctx.sql(sql).await?.collect().await?;
+        Ok(batches[0].column(0).clone())
+    }
+
+    fn booleans(array: &ArrayRef) -> Vec<Option<bool>> {
+        array.as_boolean().iter().collect()
+    }
+
+    #[actix_web::test]
+    async fn ipv4_range_boundaries() {
+        let ips = [
+            Some("10.0.0.0"),
+            Some("10.255.255.255"),
+            Some("11.0.0.0"),
+            Some("9.255.255.255"),
+            Some("::ffff:10.1.2.3"),
+            Some("10.0.0.256"),
+            Some("not-an-ip"),
+            None,
+        ];
+        let result = query(&ips, "SELECT ip_in_cidr(ip, '10.0.0.0/8') FROM access")
+            .await
+            .unwrap();
+        assert_eq!(
+            booleans(&result),
+            vec![
+                Some(true),
+                Some(true),
+                Some(false),
+                Some(false),
+                Some(true),
+                None,
+                None,
+                None
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn ipv6_range_boundaries() {
+        let ips = [
+            Some("2001:db8::"),
+            Some("2001:db8:ffff:ffff:ffff:ffff:ffff:ffff"),
+            Some("2001:db9::"),
+            Some("10.0.0.1"),
+            Some("2001:db8::zz"),
+        ];
+        let result = query(&ips, "SELECT ip_in_cidr(ip, '2001:db8::/32') FROM access")
+            .await
+            .unwrap();
+        assert_eq!(
+            booleans(&result),
+            vec![Some(true), Some(true), Some(false), Some(false), None]
+        );
+    }
+
+    #[actix_web::test]
+    async fn networks_of_each_row() {
+        let ips = [
+            Some("10.1.2.3"),
+            Some("10.9.2.3"),
+            Some("2001:db8::1"),
+            Some("10.1.2.4"),
+            Some("10.1.2.5"),
+        ];
+        // the last address has no network
+        let result = query(
+            &ips,
+            "SELECT ip_in_cidr(ip, CASE ip \
+                 WHEN '10.1.2.3' THEN '10.0.0.0/8' \
+                 WHEN '10.9.2.3' THEN '10.1.3.0/24' \
+                 WHEN '2001:db8::1' THEN '2001:db8::/32' \
+                 WHEN '10.1.2.4' THEN '10.0.0.0/33' END) FROM access",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            booleans(&result),
+            vec![Some(true), Some(false), Some(true), None, None]
+        );
+
+        // every address is a network of itself
+        let result = query(
+            &[Some("10.0.0.1"), Some("bogus")],
+            "SELECT ip_in_cidr(ip, ip) FROM access",
+        )
+        .await
+        .unwrap();
+        assert_eq!(booleans(&result), vec![Some(true), None]);
+    }
+
+    #[actix_web::test]
+    async fn contains_any_mixes_families() {
+        let ips = [
+            Some("192.168.1.10"),
+            Some("172.16.0.1"),
+            Some("172.32.0.1"),
+            Some("fd00::1"),
+            Some("8.8.8.8"),
+            Some("203.0.113.7"),
+        ];
+        let result = query(
+            &ips,
+            "SELECT cidr_contains_any(ip, '192.168.0.0/16, 172.16.0.0/12,fc00::/7,203.0.113.7') FROM access",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            booleans(&result),
+            vec![
+                Some(true),
+                Some(true),
+                Some(false),
+                Some(true),
+                Some(false),
+                Some(true)
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn ip_to_int_values() {
+        let ips = [
+            Some("0.0.0.0"),
+            Some("255.255.255.255"),
+            Some("1.2.3.4"),
+            Some("::1"),
+            Some("bogus"),
+            None,
+        ];
+        let result = query(&ips, "SELECT ip_to_int(ip) FROM access")
+            .await
+            .unwrap();
+        let result: Vec<_> = result.as_primitive::<Int64Type>().iter().collect();
+        assert_eq!(
+            result,
+            vec![Some(0), Some(4294967295), Some(16909060), None, None, None]
+        );
+    }
+
+    #[actix_web::test]
+    async fn invalid_cidr_fails_planning() {
+        for sql in [
+            "SELECT ip_in_cidr(ip, '10.0.0.0/33') FROM access",
+            "SELECT ip_in_cidr(ip, '10.0.0/8') FROM access",
+            "SELECT cidr_contains_any(ip, '10.0.0.0/8,::/129') FROM access",
+            "SELECT cidr_contains_any(ip, ip) FROM access",
+        ] {
+            assert!(query(&[Some("10.0.0.1")], sql).await.is_err(), "{sql}");
+        }
+    }
+
+    #[test]
+    fn trie_handles_overlapping_networks() {
+        let set = CidrSet::parse("10.1.0.0/16,10.0.0.0/8,0.0.0.0/0").unwrap();
+        assert!(set.contains("1.1.1.1".parse().unwrap()));
+        assert!(!set.contains("::1".parse().unwrap()));
+
+        let set = CidrSet::parse("10.0.0.0/8,10.1.0.0/16").unwrap();
+        assert!(set.contains("10.200.0.1".parse().unwrap()));
+        assert!(!set.contains("11.0.0.0".parse().unwrap()));
+    }
+}
@@ -0,0 +1,730 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, borrow::Cow, fmt, sync::Arc};
+
+use arrow_array::{
+    builder::{BooleanBuilder, Float64Builder, Int64Builder, StringBuilder, UInt64Builder},
+    cast::AsArray,
+    Array, ArrayRef, StringArray,
+};
+use arrow_schema::DataType;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{
+        simplify::{ExprSimplifyResult, SimplifyInfo},
+        ColumnarValue, ScalarUDFImpl, Signature, TypeSignature, Volatility,
+    },
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json::value::RawValue;
+
+use super::{literal_arg, num_rows, require_literal, to_columnar_value};
+
+/// One step of a json path
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// Parsed json path like `a.b[2].c`, an optional leading `$` refers to the root
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct JsonPath(Vec<PathSegment>);
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self> {
+        let invalid =
+            |reason: &str| DataFusionError::Plan(format!("Invalid json path {path}, {reason}"));
+        let mut segments = Vec::new();
+        let mut rest = path.strip_prefix('$').unwrap_or(path);
+        let mut expect_key = !path.starts_with('$');
+
+        while !rest.is_empty() {
+            if let Some(tail) = rest.strip_prefix('[') {
+                let end = tail.find(']').ok_or_else(|| invalid("unclosed ["))?;
+                let inner = &tail[..end];
+                let segment = match inner.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                    Some(key) => PathSegment::Key(key.to_string()),
+                    None => PathSegment::Index(
+                        inner
+                            .parse()
+                            .map_err(|_| invalid("array index must be a non negative integer"))?,
+                    ),
+                };
+                segments.push(segment);
+                rest = &tail[end + 1..];
+                expect_key = false;
+                continue;
+            }
+
+            if !expect_key {
+                rest = rest
+                    .strip_prefix('.')
+                    .ok_or_else(|| invalid("expected . or ["))?;
+            }
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            if end == 0 {
+                return Err(invalid("empty key"));
+            }
+            segments.push(PathSegment::Key(rest[..end].to_string()));
+            rest = &rest[end..];
+            expect_key = false;
+        }
+
+        Ok(Self(segments))
+    }
+
+    /// Raw json text of the value at this path, None when the path is missing or the json is malformed
+    pub fn lookup<'a>(&self, json: &'a str) -> Option<&'a RawValue> {
+        let mut value: &RawValue = serde_json::from_str(json).ok()?;
+        for segment in &self.0 {
+            let mut de = serde_json::Deserializer::from_str(value.get());
+            value = Lookup(segment).deserialize(&mut de).ok()??;
+        }
+        Some(value)
+    }
+}
+
+// walks a single level of the document, borrowing the matched value without allocating
+struct Lookup<'p>(&'p PathSegment);
+
+impl<'de, 'p> DeserializeSeed<'de> for Lookup<'p> {
+    type Value = Option<&'de RawValue>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, 'p> Visitor<'de> for Lookup<'p> {
+    type Value = Option<&'de RawValue>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a json value")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut found = None;
+        while let Some(key) = map.next_key::<Key<'de>>()? {
+            match self.0 {
+                PathSegment::Key(wanted) if found.is_none() && key.0 == wanted.as_str() => {
+                    found = Some(map.next_value::<&'de RawValue>()?)
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut found = None;
+        let mut position = 0;
+        loop {
+            match self.0 {
+                PathSegment::Index(index) if position == *index => {
+                    match seq.next_element::<&'de RawValue>()? {
+                        Some(value) => found = Some(value),
+                        None => break,
+                    }
+                }
+                _ => {
+                    if seq.next_element::<IgnoredAny>()?.is_none() {
+                        break;
+                    }
+                }
+            }
+            position += 1;
+        }
+        Ok(found)
+    }
+
+    // scalars have no children
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+}
+
+// object key, borrowed unless it contains escapes
+struct Key<'de>(Cow<'de, str>);
+
+impl<'de> de::Deserialize<'de> for Key<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeyVisitor;
+
+        impl<'de> Visitor<'de> for KeyVisitor {
+            type Value = Key<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an object key")
+            }
+
+            fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<Self::Value, E> {
+                Ok(Key(Cow::Borrowed(v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(Key(Cow::Owned(v.to_string())))
+            }
+        }
+
+        deserializer.deserialize_str(KeyVisitor)
+    }
+}
+
+/// Which json_get function to evaluate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JsonGetKind {
+    Str,
+    Int,
+    Float,
+    Bool,
+    Length,
+    Extract,
+}
+
+impl JsonGetKind {
+    fn name(&self) -> &'static str {
+        match self {
+            JsonGetKind::Str => "json_get_str",
+            JsonGetKind::Int => "json_get_int",
+            JsonGetKind::Float => "json_get_float",
+            JsonGetKind::Bool => "json_get_bool",
+            JsonGetKind::Length => "json_length",
+            JsonGetKind::Extract => "json_extract",
+        }
+    }
+
+    fn return_type(&self) -> DataType {
+        match self {
+            JsonGetKind::Str | JsonGetKind::Extract => DataType::Utf8,
+            JsonGetKind::Int => DataType::Int64,
+            JsonGetKind::Float => DataType::Float64,
+            JsonGetKind::Bool => DataType::Boolean,
+            JsonGetKind::Length => DataType::UInt64,
+        }
+    }
+}
+
+/// `json_get_str(json, path)`, `json_get_int(json, path)`, `json_get_float(json, path)`,
+/// `json_get_bool(json, path)`, `json_length(json [, path])` and `json_extract(json, path)`
+///
+/// Extracts the value at `path` from a json encoded string column. Missing paths,
+/// type mismatches and malformed json all evaluate to NULL. `json_extract` takes
+/// any value, strings unquoted and everything but null as its json text.
+#[derive(Debug)]
+pub struct JsonGet {
+    kind: JsonGetKind,
+    signature: Signature,
+}
+
+impl JsonGet {
+    pub fn new(kind: JsonGetKind) -> Self {
+        let mut signatures = vec![TypeSignature::Exact(vec![DataType::Utf8, DataType::Utf8])];
+        if kind == JsonGetKind::Length {
+            signatures.push(TypeSignature::Exact(vec![DataType::Utf8]));
+        }
+        Self {
+            kind,
+            signature: Signature::one_of(signatures, Volatility::Immutable),
+        }
+    }
+
+    pub fn all() -> [Self; 6] {
+        [
+            JsonGetKind::Str,
+            JsonGetKind::Int,
+            JsonGetKind::Float,
+            JsonGetKind::Bool,
+            JsonGetKind::Length,
+            JsonGetKind::Extract,
+        ]
+        .map(Self::new)
+    }
+
+    fn path(&self, args: &[ColumnarValue]) -> Result<Option<JsonPath>> {
+        match literal_arg(self.name(), args, 1)? {
+            None => Ok(Some(JsonPath::default())),
+            Some(ScalarValue::Utf8(Some(path))) => JsonPath::parse(path).map(Some),
+            Some(_) => Ok(None),
+        }
+    }
+}
+
+fn collect<B, T>(
+    json: &StringArray,
+    path: Option<&JsonPath>,
+    mut builder: B,
+    mut append: impl FnMut(&mut B, Option<T>),
+    extract: impl Fn(&RawValue) -> Option<T>,
+) -> B {
+    for value in json.iter() {
+        let value = path
+            .zip(value)
+            .and_then(|(path, json)| path.lookup(json))
+            .and_then(&extract);
+        append(&mut builder, value);
+    }
+    builder
+}
+
+// strings without their quotes, other values as they are written
+fn json_text(value: &RawValue) -> Option<String> {
+    match serde_json::from_str::<Option<Cow<str>>>(value.get()) {
+        Ok(text) => text.map(Cow::into_owned),
+        Err(_) => Some(value.get().to_string()),
+    }
+}
+
+fn json_length(value: &RawValue) -> Option<u64> {
+    struct LengthVisitor;
+
+    impl<'de> Visitor<'de> for LengthVisitor {
+        type Value = Option<u64>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a json value")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut len = 0;
+            while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {
+                len += 1;
+            }
+            Ok(Some(len))
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut len = 0;
+            while seq.next_element::<IgnoredAny>()?.is_some() {
+                len += 1;
+            }
+            Ok(Some(len))
+        }
+
+        fn visit_bool<E: de::Error>(self, _: bool) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_i64<E: de::Error>(self, _: i64) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_u64<E: de::Error>(self, _: u64) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_f64<E: de::Error>(self, _: f64) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_str<E: de::Error>(self, _: &str) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+    }
+
+    serde_json::Deserializer::from_str(value.get())
+        .deserialize_any(LengthVisitor)
+        .ok()
+        .flatten()
+}
+
+impl ScalarUDFImpl for JsonGet {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        self.kind.name()
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(self.kind.return_type())
+    }
+
+    // path must be a literal so it is parsed once, also validates it at plan time
+    fn simplify(&self, args: Vec<Expr>, _info: &dyn SimplifyInfo) -> Result<ExprSimplifyResult> {
+        require_literal(self.name(), &args, 1)?;
+        if let Some(Expr::Literal(ScalarValue::Utf8(Some(path)))) = args.get(1) {
+            JsonPath::parse(path)?;
+        }
+        Ok(ExprSimplifyResult::Original(args))
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let json = args[0].clone().into_array(num_rows(args))?;
+        let json = json.as_string::<i32>();
+        let path = self.path(args)?;
+        let path = path.as_ref();
+        let len = json.len();
+
+        let result: ArrayRef = match self.kind {
+            JsonGetKind::Str => Arc::new(
+                collect(
+                    json,
+                    path,
+                    StringBuilder::with_capacity(len, 0),
+                    |b, v: Option<String>| b.append_option(v),
+                    |raw| {
+                        serde_json::from_str::<Cow<str>>(raw.get())
+                            .ok()
+                            .map(Cow::into_owned)
+                    },
+                )
+                .finish(),
+            ),
+            JsonGetKind::Int => Arc::new(
+                collect(
+                    json,
+                    path,
+                    Int64Builder::with_capacity(len),
+                    |b, v| b.append_option(v),
+                    |raw| serde_json::from_str::<i64>(raw.get()).ok(),
+                )
+                .finish(),
+            ),
+            JsonGetKind::Float => Arc::new(
+                collect(
+                    json,
+                    path,
+                    Float64Builder::with_capacity(len),
+                    |b, v| b.append_option(v),
+                    |raw| serde_json::from_str::<f64>(raw.get()).ok(),
+                )
+                .finish(),
+            ),
+            JsonGetKind::Bool => Arc::new(
+                collect(
+                    json,
+                    path,
+                    BooleanBuilder::with_capacity(len),
+                    |b, v| b.append_option(v),
+                    |raw| serde_json::from_str::<bool>(raw.get()).ok(),
+                )
+                .finish(),
+            ),
+            JsonGetKind::Length => Arc::new(
+                collect(
+                    json,
+                    path,
+                    UInt64Builder::with_capacity(len),
+                    |b, v| b.append_option(v),
+                    json_length,
+                )
+                .finish(),
+            ),
+            JsonGetKind::Extract => Arc::new(
+                collect(
+                    json,
+                    path,
+                    StringBuilder::with_capacity(len, 0),
+                    |b, v: Option<String>| b.append_option(v),
+                    json_text,
+                )
+                .finish(),
+            ),
+        };
+
+        to_columnar_value(args, result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Instant};
+
+    use arrow_array::{
+        cast::AsArray,
+        types::{Float64Type, Int64Type, UInt64Type},
+        Array, ArrayRef, RecordBatch, StringArray,
+    };
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{datasource::MemTable, logical_expr::ScalarUDF, prelude::SessionContext};
+
+    use super::{JsonGet, JsonPath, PathSegment};
+
+    async fn query(rows: Vec<Option<&str>>, sql: &str) -> datafusion::error::Result<ArrayRef> {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "message",
+            DataType::Utf8,
+            true,
+        )]));
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(StringArray::from(rows))]).unwrap();
+        let ctx = SessionContext::new();
+        for udf in JsonGet::all() {
+            ctx.register_udf(ScalarUDF::from(udf));
+        }
+        ctx.register_table(
+            "logs",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+
+        let batches = ctx.sql(sql).await?.collect().await?;
+        Ok(batches[0].column(0).clone())
+    }
+
+    const ROWS: [Option<&str>; 5] = [
+        Some(
+            r#"{"user": {"name": "alice", "id": 7, "score": 9.5, "admin": true}, "tags": ["a", "b", {"c": "deep"}]}"#,
+        ),
+        Some(r#"{"user": {"name": "bob\n", "id": "8", "admin": "yes"}, "tags": []}"#),
+        Some(r#"{"user": "#),
+        Some("[1, 2, 3]"),
+        None,
+    ];
+
+    #[test]
+    fn parse_paths() {
+        assert_eq!(
+            JsonPath::parse("a.b[2].c").unwrap().0,
+            vec![
+                PathSegment::Key("a".to_string()),
+                PathSegment::Key("b".to_string()),
+                PathSegment::Index(2),
+                PathSegment::Key("c".to_string()),
+            ]
+        );
+        assert_eq!(
+            JsonPath::parse(r#"$[0]["x.y"]"#).unwrap().0,
+            vec![PathSegment::Index(0), PathSegment::Key("x.y".to_string())]
+        );
+        assert!(JsonPath::parse("a..b").is_err());
+        assert!(JsonPath::parse("a[x]").is_err());
+        assert!(JsonPath::parse("a[1").is_err());
+    }
+
+    #[actix_web::test]
+    async fn extract_nested_values() {
+        let rows = ROWS.to_vec();
+
+        let names = query(
+            rows.clone(),
+            "SELECT json_get_str(message, 'user.name') FROM logs",
+        )
+        .await
+        .unwrap();
+        let names: Vec<_> = names.as_string::<i32>().iter().collect();
+        assert_eq!(names, vec![Some("alice"), Some("bob\n"), None, None, None]);
+
+        let deep = query(
+            rows.clone(),
+            "SELECT json_get_str(message, 'tags[2].c') FROM logs",
+        )
+        .await
+        .unwrap();
+        assert_eq!(deep.as_string::<i32>().value(0), "deep");
+        assert_eq!(deep.null_count(), 4);
+
+        let second = query(
+            rows.clone(),
+            "SELECT json_get_int(message, '$[1]') FROM logs",
+        )
+        .await
+        .unwrap();
+        let second = second.as_primitive::<Int64Type>();
+        assert_eq!(second.value(3), 2);
+        assert_eq!(second.null_count(), 4);
+
+        let lengths = query(rows, "SELECT json_length(message, 'tags') FROM logs")
+            .await
+            .unwrap();
+        let lengths: Vec<_> = lengths.as_primitive::<UInt64Type>().iter().collect();
+        assert_eq!(lengths, vec![Some(3), Some(0), None, None, None]);
+    }
+
+    #[actix_web::test]
+    async fn wrong_types_and_malformed_rows_are_null() {
+        let rows = ROWS.to_vec();
+
+        let ids = query(
+            rows.clone(),
+            "SELECT json_get_int(message, 'user.id') FROM logs",
+        )
+        .await
+        .unwrap();
+        let ids: Vec<_> = ids.as_primitive::<Int64Type>().iter().collect();
+        assert_eq!(ids, vec![Some(7), None, None, None, None]);
+
+        let scores = query(
+            rows.clone(),
+            "SELECT json_get_float(message, 'user.score') FROM logs",
+        )
+        .await
+        .unwrap();
+        let scores: Vec<_> = scores.as_primitive::<Float64Type>().iter().collect();
+        assert_eq!(scores, vec![Some(9.5), None, None, None, None]);
+
+        let admin = query(
+            rows.clone(),
+            "SELECT json_get_bool(message, 'user.admin') FROM logs",
+        )
+        .await
+        .unwrap();
+        let admin: Vec<_> = admin.as_boolean().iter().collect();
+        assert_eq!(admin, vec![Some(true), None, None, None, None]);
+
+        // objects are not strings
+        let user = query(
+            rows.clone(),
+            "SELECT json_get_str(message, 'user') FROM logs",
+        )
+        .await
+        .unwrap();
+        assert_eq!(user.null_count(), 5);
+
+        let lengths = query(rows, "SELECT json_length(message) FROM logs")
+            .await
+            .unwrap();
+        let lengths: Vec<_> = lengths.as_primitive::<UInt64Type>().iter().collect();
+        assert_eq!(lengths, vec![Some(2), Some(2), None, Some(3), None]);
+    }
+
+    #[actix_web::test]
+    async fn extract_any_value_as_text() {
+        async fn extract(path: &str) -> datafusion::error::Result<ArrayRef> {
+            let sql = format!("SELECT json_extract(message, '{path}') FROM logs");
+            query(ROWS.to_vec(), &sql).await
+        }
+
+        let names = extract("$.user.name").await.unwrap();
+        let names: Vec<_> = names.as_string::<i32>().iter().collect();
+        assert_eq!(names, vec![Some("alice"), Some("bob\n"), None, None, None]);
+
+        let ids = extract("$.user.id").await.unwrap();
+        let ids: Vec<_> = ids.as_string::<i32>().iter().collect();
+        assert_eq!(ids, vec![Some("7"), Some("8"), None, None, None]);
+
+        let deep = extract("$.tags[2].c").await.unwrap();
+        let deep: Vec<_> = deep.as_string::<i32>().iter().collect();
+        assert_eq!(deep, vec![Some("deep"), None, None, None, None]);
+
+        let tags = extract("$.tags").await.unwrap();
+        let tags: Vec<_> = tags.as_string::<i32>().iter().collect();
+        assert_eq!(
+            tags,
+            vec![
+                Some(r#"["a", "b", {"c": "deep"}]"#),
+                Some("[]"),
+                None,
+                None,
+                None
+            ]
+        );
+
+        let first = extract("$[0]").await.unwrap();
+        let first: Vec<_> = first.as_string::<i32>().iter().collect();
+        assert_eq!(first, vec![None, None, None, Some("1"), None]);
+
+        let missing = extract("$.user.email").await.unwrap();
+        assert_eq!(missing.null_count(), 5);
+        let out_of_bounds = extract("$.tags[3]").await.unwrap();
+        assert_eq!(out_of_bounds.null_count(), 5);
+    }
+
+    #[actix_web::test]
+    async fn extract_json_null_is_null() {
+        let rows = vec![Some(r#"{"a": null, "b": false}"#)];
+        let a = query(
+            rows.clone(),
+            "SELECT json_extract(message, '$.a') FROM logs",
+        )
+        .await
+        .unwrap();
+        assert!(a.is_null(0));
+        let b = query(rows, "SELECT json_extract(message, '$.b') FROM logs")
+            .await
+            .unwrap();
+        assert_eq!(b.as_string::<i32>().value(0), "false");
+    }
+
+    #[actix_web::test]
+    async fn path_must_be_a_valid_literal() {
+        let err = query(
+            vec![Some("{}")],
+            "SELECT json_get_str(message, message) FROM logs",
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("literal"), "{err}");
+
+        let err = query(
+            vec![Some("{}")],
+            "SELECT json_get_str(message, 'a[') FROM logs",
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("Invalid json path"), "{err}");
+    }
+
+    #[actix_web::test]
+    async fn extract_over_100k_rows() {
+        let rows = (0..100_000)
+            .map(|i| {
+                format!(
+                    r#"{{"level": "info", "request": {{"id": {i}, "path": "/api/v1/query", "headers": ["a", "b"]}}, "latency": {}}}"#,
+                    i % 250
+                )
+            })
+            .collect::<Vec<_>>();
+        let rows = rows.iter().map(|r| Some(r.as_str())).collect();
+
+        let start = Instant::now();
+        let result = query(rows, "SELECT json_get_int(message, 'request.id') FROM logs")
+            .await
+            .unwrap();
+        let elapsed = start.elapsed();
+
+        assert_eq!(result.len(), 100_000);
+        assert_eq!(result.as_primitive::<Int64Type>().value(99_999), 99_999);
+        assert!(elapsed.as_secs() < 30, "took {elapsed:?}");
+    }
+}
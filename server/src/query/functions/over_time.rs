@@ -0,0 +1,302 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, ops::Range};
+
+use arrow_array::{cast::AsArray, types::Int64Type, Array, ArrayRef};
+use arrow_schema::DataType;
+use datafusion::arrow::compute::cast;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, Volatility, WindowUDFImpl},
+    scalar::ScalarValue,
+};
+
+/// Which end of the frame's time span the value is taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Boundary {
+    First,
+    Last,
+}
+
+fn name(boundary: Boundary) -> &'static str {
+    match boundary {
+        Boundary::First => "first_over_time",
+        Boundary::Last => "last_over_time",
+    }
+}
+
+fn return_type(boundary: Boundary, arg_types: &[DataType]) -> Result<DataType> {
+    if !matches!(arg_types[1], DataType::Timestamp(_, _)) {
+        return Err(DataFusionError::Plan(format!(
+            "{} expects a timestamp, got {}",
+            name(boundary),
+            arg_types[1]
+        )));
+    }
+    Ok(arg_types[0].clone())
+}
+
+/// `first_over_time(value, timestamp)`
+///
+/// Value of the row with the earliest timestamp in the window frame, which
+/// need not be ordered by the timestamp. Of rows with the same timestamp the
+/// first in the window order is taken.
+///
+/// Rows with a NULL value or timestamp are skipped, frames without any other
+/// row give NULL.
+#[derive(Debug)]
+pub struct FirstOverTime {
+    signature: Signature,
+}
+
+impl FirstOverTime {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::any(2, Volatility::Immutable),
+        }
+    }
+}
+
+impl WindowUDFImpl for FirstOverTime {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        name(Boundary::First)
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        return_type(Boundary::First, arg_types)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(OverTimeEvaluator(Boundary::First)))
+    }
+}
+
+/// `last_over_time(value, timestamp)`
+///
+/// Value of the row with the latest timestamp in the window frame, which
+/// need not be ordered by the timestamp. Of rows with the same timestamp the
+/// last in the window order is taken.
+///
+/// Rows with a NULL value or timestamp are skipped, frames without any other
+/// row give NULL.
+#[derive(Debug)]
+pub struct LastOverTime {
+    signature: Signature,
+}
+
+impl LastOverTime {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::any(2, Volatility::Immutable),
+        }
+    }
+}
+
+impl WindowUDFImpl for LastOverTime {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        name(Boundary::Last)
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        return_type(Boundary::Last, arg_types)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(OverTimeEvaluator(Boundary::Last)))
+    }
+}
+
+#[derive(Debug)]
+struct OverTimeEvaluator(Boundary);
+
+impl PartitionEvaluator for OverTimeEvaluator {
+    fn evaluate(&mut self, values: &[ArrayRef], range: &Range<usize>) -> Result<ScalarValue> {
+        if !matches!(values[1].data_type(), DataType::Timestamp(_, _)) {
+            return Err(DataFusionError::Execution(format!(
+                "{} expects a timestamp, got {}",
+                name(self.0),
+                values[1].data_type()
+            )));
+        }
+        // the raw value, every row of the column has the same unit
+        let timestamps = cast(&values[1].slice(range.start, range.len()), &DataType::Int64)?;
+        let mut chosen: Option<(usize, i64)> = None;
+        for (offset, timestamp) in timestamps.as_primitive::<Int64Type>().iter().enumerate() {
+            let row = range.start + offset;
+            let Some(timestamp) = timestamp.filter(|_| values[0].is_valid(row)) else {
+                continue;
+            };
+            let replaces = match (self.0, chosen) {
+                (_, None) => true,
+                (Boundary::First, Some((_, earliest))) => timestamp < earliest,
+                (Boundary::Last, Some((_, latest))) => timestamp >= latest,
+            };
+            if replaces {
+                chosen = Some((row, timestamp));
+            }
+        }
+        match chosen {
+            Some((row, _)) => ScalarValue::try_from_array(&values[0], row),
+            None => ScalarValue::try_from(values[0].data_type()),
+        }
+    }
+
+    fn uses_window_frame(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{cast::AsArray, Int64Array, RecordBatch, StringArray, TimestampSecondArray};
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use datafusion::{datasource::MemTable, logical_expr::WindowUDF, prelude::SessionContext};
+
+    use super::{FirstOverTime, LastOverTime};
+
+    // rows of (timestamp, status) in arrival order
+    fn context(rows: &[(Option<i64>, Option<&str>)]) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("seq", DataType::Int64, false),
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Second, None),
+                true,
+            ),
+            Field::new("status", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(0..rows.len() as i64)),
+                Arc::new(TimestampSecondArray::from_iter(
+                    rows.iter().map(|row| row.0),
+                )),
+                Arc::new(StringArray::from_iter(rows.iter().map(|row| row.1))),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_udwf(WindowUDF::from(FirstOverTime::new()));
+        ctx.register_udwf(WindowUDF::from(LastOverTime::new()));
+        ctx.register_table(
+            "events",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn boundaries(ctx: &SessionContext, call: &str, frame: &str) -> Vec<Option<String>> {
+        let sql = format!(
+            "SELECT {call}(status, p_timestamp) OVER (ORDER BY seq {frame}) \
+             FROM events ORDER BY seq"
+        );
+        let batches = ctx.sql(&sql).await.unwrap().collect().await.unwrap();
+        batches
+            .iter()
+            .flat_map(|batch| batch.column(0).as_string::<i32>().iter())
+            .map(|status| status.map(str::to_owned))
+            .collect()
+    }
+
+    fn strings(values: &[Option<&str>]) -> Vec<Option<String>> {
+        values
+            .iter()
+            .map(|value| value.map(str::to_owned))
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn boundaries_follow_the_timestamps_not_the_arrival_order() {
+        let ctx = context(&[
+            (Some(30), Some("c")),
+            (Some(10), Some("a")),
+            (Some(50), Some("e")),
+            (Some(20), Some("b")),
+            (Some(40), Some("d")),
+        ]);
+        let whole = "ROWS BETWEEN UNBOUNDED PRECEDING AND UNBOUNDED FOLLOWING";
+        let first = boundaries(&ctx, "first_over_time", whole).await;
+        assert_eq!(first, strings(&[Some("a"); 5]));
+        let last = boundaries(&ctx, "last_over_time", whole).await;
+        assert_eq!(last, strings(&[Some("e"); 5]));
+
+        let sliding = "ROWS BETWEEN 1 PRECEDING AND CURRENT ROW";
+        let first = boundaries(&ctx, "first_over_time", sliding).await;
+        assert_eq!(
+            first,
+            strings(&[Some("c"), Some("a"), Some("a"), Some("b"), Some("b")])
+        );
+        let last = boundaries(&ctx, "last_over_time", sliding).await;
+        assert_eq!(
+            last,
+            strings(&[Some("c"), Some("c"), Some("e"), Some("e"), Some("d")])
+        );
+    }
+
+    #[actix_web::test]
+    async fn ties_and_nulls() {
+        let ctx = context(&[
+            (Some(20), Some("x")),
+            (Some(10), None),
+            (None, Some("y")),
+            (Some(20), Some("z")),
+            (Some(5), Some("w")),
+        ]);
+        let running = "ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW";
+        // rows with the same timestamp go by the window order
+        let first = boundaries(&ctx, "first_over_time", running).await;
+        assert_eq!(
+            first,
+            strings(&[Some("x"), Some("x"), Some("x"), Some("x"), Some("w")])
+        );
+        let last = boundaries(&ctx, "last_over_time", running).await;
+        assert_eq!(
+            last,
+            strings(&[Some("x"), Some("x"), Some("x"), Some("z"), Some("z")])
+        );
+
+        // nothing to take but NULLs
+        let single = "ROWS BETWEEN CURRENT ROW AND CURRENT ROW";
+        let first = boundaries(&ctx, "first_over_time", single).await;
+        assert_eq!(
+            first,
+            strings(&[Some("x"), None, None, Some("z"), Some("w")])
+        );
+    }
+}
@@ -0,0 +1,237 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, ops::Range};
+
+use arrow_array::{
+    cast::AsArray,
+    types::{Float64Type, Int64Type},
+    ArrayRef,
+};
+use arrow_schema::DataType;
+use datafusion::arrow::compute::cast;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, Volatility, WindowUDFImpl},
+    scalar::ScalarValue,
+};
+
+use super::sessionize::nanos_per_unit;
+
+pub const NANOS_PER_SECOND: f64 = 1_000_000_000.0;
+
+/// `rate(counter, timestamp)`
+///
+/// Per second increase of a monotonic counter over the window frame, like
+/// Prometheus' `rate`. A counter going down is taken as a reset to 0, so the
+/// new value is counted as the increase since the previous row. The rate is
+/// the total increase divided by the seconds between the first and the last
+/// row of the frame.
+///
+/// Unlike the rolling functions this follows the SQL frame, e.g.
+/// `OVER (ORDER BY p_timestamp RANGE BETWEEN INTERVAL '5 minutes' PRECEDING AND CURRENT ROW)`.
+/// Rows with a NULL counter or timestamp are skipped and frames with fewer
+/// than two points, or no time between them, give NULL. The window must be
+/// ordered by the timestamp.
+#[derive(Debug)]
+pub struct Rate {
+    signature: Signature,
+}
+
+impl Rate {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::any(2, Volatility::Immutable),
+        }
+    }
+}
+
+impl WindowUDFImpl for Rate {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "rate"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        if !arg_types[0].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "rate expects a numeric counter, got {}",
+                arg_types[0]
+            )));
+        }
+        if !matches!(arg_types[1], DataType::Timestamp(_, _)) {
+            return Err(DataFusionError::Plan(format!(
+                "rate expects a timestamp, got {}",
+                arg_types[1]
+            )));
+        }
+        Ok(DataType::Float64)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(RateEvaluator))
+    }
+}
+
+#[derive(Debug)]
+struct RateEvaluator;
+
+/// Non NULL (nanoseconds, value) points of the window frame, the values are
+/// the first argument and the timestamps the second
+pub fn frame_points(
+    function: &str,
+    values: &[ArrayRef],
+    range: &Range<usize>,
+) -> Result<Vec<(i64, f64)>> {
+    let DataType::Timestamp(unit, _) = values[1].data_type() else {
+        return Err(DataFusionError::Execution(format!(
+            "{function} expects a timestamp, got {}",
+            values[1].data_type()
+        )));
+    };
+    let scale = nanos_per_unit(unit);
+    let numbers = cast(
+        &values[0].slice(range.start, range.len()),
+        &DataType::Float64,
+    )?;
+    // the raw value, casting to another timestamp type would shift zoned values
+    let timestamps = cast(&values[1].slice(range.start, range.len()), &DataType::Int64)?;
+    Ok(numbers
+        .as_primitive::<Float64Type>()
+        .iter()
+        .zip(timestamps.as_primitive::<Int64Type>().iter())
+        .filter_map(|(number, timestamp)| Some((timestamp?.saturating_mul(scale), number?)))
+        .collect())
+}
+
+impl PartitionEvaluator for RateEvaluator {
+    fn evaluate(&mut self, values: &[ArrayRef], range: &Range<usize>) -> Result<ScalarValue> {
+        let mut first = None;
+        let mut last: Option<(i64, f64)> = None;
+        let mut increase = 0.0;
+        for (timestamp, counter) in frame_points("rate", values, range)? {
+            match last {
+                Some((previous, _)) if timestamp < previous => {
+                    return Err(DataFusionError::Execution(
+                        "rate requires the window to be ordered by the timestamp".to_string(),
+                    ))
+                }
+                Some((_, previous)) if counter >= previous => increase += counter - previous,
+                // a reset, the counter started again from 0
+                Some(_) => increase += counter,
+                None => first = Some(timestamp),
+            }
+            last = Some((timestamp, counter));
+        }
+
+        let rate = match (first, last) {
+            (Some(first), Some((last, _))) if last > first => {
+                Some(increase / ((last - first) as f64 / NANOS_PER_SECOND))
+            }
+            _ => None,
+        };
+        Ok(ScalarValue::Float64(rate))
+    }
+
+    fn uses_window_frame(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray, types::Float64Type, Int64Array, RecordBatch, TimestampSecondArray,
+    };
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use datafusion::{datasource::MemTable, logical_expr::WindowUDF, prelude::SessionContext};
+
+    use super::Rate;
+
+    fn context(rows: &[(i64, i64)]) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Second, None),
+                false,
+            ),
+            Field::new("requests", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(TimestampSecondArray::from_iter_values(
+                    rows.iter().map(|row| row.0),
+                )),
+                Arc::new(Int64Array::from_iter_values(rows.iter().map(|row| row.1))),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_udwf(WindowUDF::from(Rate::new()));
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn rates(ctx: &SessionContext, frame: &str) -> Vec<Option<f64>> {
+        let sql = format!(
+            "SELECT rate(requests, p_timestamp) OVER (ORDER BY p_timestamp {frame}) \
+             FROM metrics ORDER BY p_timestamp"
+        );
+        let batches = ctx.sql(&sql).await.unwrap().collect().await.unwrap();
+        batches
+            .iter()
+            .flat_map(|batch| batch.column(0).as_primitive::<Float64Type>().iter())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn monotonic_counter() {
+        let ctx = context(&[(0, 0), (10, 100), (20, 300), (30, 600)]);
+        let rates = rates(&ctx, "ROWS BETWEEN 2 PRECEDING AND CURRENT ROW").await;
+        assert_eq!(rates, [None, Some(10.0), Some(15.0), Some(25.0)]);
+    }
+
+    #[actix_web::test]
+    async fn decrease_is_a_reset() {
+        let ctx = context(&[(0, 100), (10, 200), (20, 50), (40, 150)]);
+        // from the start of the partition, the reset counts 50 rather than -150
+        let since_start = rates(&ctx, "").await;
+        assert_eq!(since_start, [None, Some(10.0), Some(7.5), Some(6.25)]);
+
+        let last_15s = rates(
+            &ctx,
+            "RANGE BETWEEN INTERVAL '15 seconds' PRECEDING AND CURRENT ROW",
+        )
+        .await;
+        assert_eq!(last_15s, [None, Some(10.0), Some(5.0), None]);
+    }
+}
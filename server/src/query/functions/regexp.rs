@@ -0,0 +1,417 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use arrow_array::{
+    builder::{ListBuilder, StringBuilder},
+    cast::AsArray,
+    Array, ArrayRef,
+};
+use arrow_schema::{DataType, Field};
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{
+        simplify::{ExprSimplifyResult, SimplifyInfo},
+        ColumnarValue, ScalarUDFImpl, Signature, TypeSignature, Volatility,
+    },
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+use regex::{Captures, Regex};
+
+use super::{literal_arg, num_rows, require_literal, to_columnar_value};
+
+// compiled patterns kept per function instance, cleared once it grows past this size
+const REGEX_CACHE_CAPACITY: usize = 256;
+
+/// Compiled regex per pattern so a pattern is only compiled once across batches
+#[derive(Debug, Default)]
+pub struct RegexCache(Mutex<HashMap<String, Arc<Regex>>>);
+
+impl RegexCache {
+    pub fn get(&self, pattern: &str) -> Result<Arc<Regex>> {
+        let mut cache = self.0.lock().unwrap();
+        if let Some(regex) = cache.get(pattern) {
+            return Ok(regex.clone());
+        }
+
+        let regex = Arc::new(compile(pattern)?);
+        if cache.len() >= REGEX_CACHE_CAPACITY {
+            cache.clear();
+        }
+        cache.insert(pattern.to_string(), regex.clone());
+        Ok(regex)
+    }
+}
+
+fn compile(pattern: &str) -> Result<Regex> {
+    Regex::new(pattern).map_err(|err| DataFusionError::Plan(format!("Invalid regex: {err}")))
+}
+
+/// Capture group selected by the optional group argument
+#[derive(Debug, Clone, PartialEq)]
+enum Group {
+    Index(usize),
+    Name(String),
+}
+
+impl Group {
+    // without an explicit group the first capture group is used,
+    // or the whole match when the pattern has no groups
+    fn from_arg(value: Option<&ScalarValue>, regex: &Regex) -> Result<Self> {
+        match value {
+            None => Ok(Group::Index(usize::from(regex.captures_len() > 1))),
+            Some(ScalarValue::Int64(Some(index))) if *index >= 0 => {
+                let index = *index as usize;
+                if index >= regex.captures_len() {
+                    return Err(DataFusionError::Plan(format!(
+                        "Regex has {} capture groups, group {index} does not exist",
+                        regex.captures_len() - 1
+                    )));
+                }
+                Ok(Group::Index(index))
+            }
+            Some(ScalarValue::Utf8(Some(name))) => {
+                if !regex.capture_names().flatten().any(|n| n == name) {
+                    return Err(DataFusionError::Plan(format!(
+                        "Regex has no capture group named {name}"
+                    )));
+                }
+                Ok(Group::Name(name.clone()))
+            }
+            Some(value) => Err(DataFusionError::Plan(format!(
+                "Invalid regex group {value}, expected a non negative index or a group name"
+            ))),
+        }
+    }
+
+    fn extract<'t>(&self, captures: &Captures<'t>) -> Option<&'t str> {
+        match self {
+            Group::Index(index) => captures.get(*index),
+            Group::Name(name) => captures.name(name),
+        }
+        .map(|m| m.as_str())
+    }
+}
+
+fn pattern_signature(extra: &[DataType]) -> Signature {
+    let mut signatures = vec![TypeSignature::Exact(vec![DataType::Utf8, DataType::Utf8])];
+    signatures.extend(
+        extra
+            .iter()
+            .map(|t| TypeSignature::Exact(vec![DataType::Utf8, DataType::Utf8, t.clone()])),
+    );
+    Signature::one_of(signatures, Volatility::Immutable)
+}
+
+// pattern and group must be literals, also validates the pattern at plan time
+fn simplify_pattern_args(function: &str, args: Vec<Expr>) -> Result<ExprSimplifyResult> {
+    require_literal(function, &args, 1)?;
+    require_literal(function, &args, 2)?;
+    if let Some(Expr::Literal(ScalarValue::Utf8(Some(pattern)))) = args.get(1) {
+        let regex = compile(pattern)?;
+        if let Some(Expr::Literal(group)) = args.get(2) {
+            Group::from_arg(Some(group), &regex)?;
+        }
+    }
+    Ok(ExprSimplifyResult::Original(args))
+}
+
+fn pattern_args(
+    function: &str,
+    cache: &RegexCache,
+    args: &[ColumnarValue],
+) -> Result<Option<(Arc<Regex>, Group)>> {
+    match literal_arg(function, args, 1)? {
+        Some(ScalarValue::Utf8(Some(pattern))) => {
+            let regex = cache.get(pattern)?;
+            let group = Group::from_arg(literal_arg(function, args, 2)?, &regex)?;
+            Ok(Some((regex, group)))
+        }
+        // a NULL pattern yields NULL for every row
+        _ => Ok(None),
+    }
+}
+
+/// `regexp_extract(text, pattern [, group])`
+///
+/// Returns the text captured by `group` (index or name) of the first match,
+/// NULL when the pattern does not match or the group did not participate.
+#[derive(Debug)]
+pub struct RegexpExtract {
+    signature: Signature,
+    cache: RegexCache,
+}
+
+impl RegexpExtract {
+    pub fn new() -> Self {
+        Self {
+            signature: pattern_signature(&[DataType::Int64, DataType::Utf8]),
+            cache: RegexCache::default(),
+        }
+    }
+}
+
+impl ScalarUDFImpl for RegexpExtract {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "regexp_extract"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Utf8)
+    }
+
+    fn simplify(&self, args: Vec<Expr>, _info: &dyn SimplifyInfo) -> Result<ExprSimplifyResult> {
+        simplify_pattern_args(self.name(), args)
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let text = args[0].clone().into_array(num_rows(args))?;
+        let text = text.as_string::<i32>();
+        let mut builder = StringBuilder::with_capacity(text.len(), text.value_data().len());
+
+        match pattern_args(self.name(), &self.cache, args)? {
+            Some((regex, group)) => text.iter().for_each(|value| {
+                builder.append_option(
+                    value
+                        .and_then(|value| regex.captures(value))
+                        .and_then(|captures| group.extract(&captures)),
+                )
+            }),
+            None => (0..text.len()).for_each(|_| builder.append_null()),
+        }
+
+        to_columnar_value(args, Arc::new(builder.finish()) as ArrayRef)
+    }
+}
+
+/// `regexp_extract_all(text, pattern [, group])`
+///
+/// Returns the text captured by `group` for every non overlapping match.
+#[derive(Debug)]
+pub struct RegexpExtractAll {
+    signature: Signature,
+    cache: RegexCache,
+}
+
+impl RegexpExtractAll {
+    pub fn new() -> Self {
+        Self {
+            signature: pattern_signature(&[DataType::Int64, DataType::Utf8]),
+            cache: RegexCache::default(),
+        }
+    }
+}
+
+impl ScalarUDFImpl for RegexpExtractAll {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "regexp_extract_all"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::List(Arc::new(Field::new(
+            "item",
+            DataType::Utf8,
+            true,
+        ))))
+    }
+
+    fn simplify(&self, args: Vec<Expr>, _info: &dyn SimplifyInfo) -> Result<ExprSimplifyResult> {
+        simplify_pattern_args(self.name(), args)
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let text = args[0].clone().into_array(num_rows(args))?;
+        let text = text.as_string::<i32>();
+        let mut builder = ListBuilder::new(StringBuilder::new());
+
+        let pattern = pattern_args(self.name(), &self.cache, args)?;
+        for value in text.iter() {
+            match (value, &pattern) {
+                (Some(value), Some((regex, group))) => {
+                    regex
+                        .captures_iter(value)
+                        .filter_map(|captures| group.extract(&captures))
+                        .for_each(|m| builder.values().append_value(m));
+                    builder.append(true);
+                }
+                _ => builder.append(false),
+            }
+        }
+
+        to_columnar_value(args, Arc::new(builder.finish()) as ArrayRef)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Instant};
+
+    use arrow_array::{cast::AsArray, Array, ArrayRef, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{datasource::MemTable, logical_expr::ScalarUDF, prelude::SessionContext};
+
+    use super::{RegexpExtract, RegexpExtractAll};
+
+    async fn query(lines: Vec<Option<&str>>, sql: &str) -> datafusion::error::Result<ArrayRef> {
+        let schema = Arc::new(Schema::new(vec![Field::new("line", DataType::Utf8, true)]));
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(StringArray::from(lines))]).unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_udf(ScalarUDF::from(RegexpExtract::new()));
+        ctx.register_udf(ScalarUDF::from(RegexpExtractAll::new()));
+        ctx.register_table(
+            "logs",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+
+        let batches = ctx.sql(sql).await?.collect().await?;
+        Ok(batches[0].column(0).clone())
+    }
+
+    fn strings(array: &ArrayRef) -> Vec<Option<&str>> {
+        array.as_string::<i32>().iter().collect()
+    }
+
+    #[actix_web::test]
+    async fn extract_numbered_and_named_groups() {
+        let lines = vec![
+            Some("GET /api/v1/query status=200 took=12ms"),
+            Some("POST /api/v1/ingest status=500 took=3ms"),
+            Some("no request here"),
+            None,
+        ];
+
+        let result = query(
+            lines.clone(),
+            r"SELECT regexp_extract(line, 'status=(\d+)', 1) FROM logs",
+        )
+        .await
+        .unwrap();
+        assert_eq!(strings(&result), vec![Some("200"), Some("500"), None, None]);
+
+        let result = query(
+            lines.clone(),
+            r"SELECT regexp_extract(line, '^(?P<method>[A-Z]+) (?P<path>\S+)', 'path') FROM logs",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            strings(&result),
+            vec![Some("/api/v1/query"), Some("/api/v1/ingest"), None, None]
+        );
+
+        // whole match when the pattern has no groups
+        let result = query(lines, r"SELECT regexp_extract(line, '\d+ms') FROM logs")
+            .await
+            .unwrap();
+        assert_eq!(
+            strings(&result),
+            vec![Some("12ms"), Some("3ms"), None, None]
+        );
+    }
+
+    #[actix_web::test]
+    async fn extract_all_matches() {
+        let lines = vec![Some("a=1 b=22 c=333"), Some("nothing"), None];
+        let result = query(
+            lines,
+            r"SELECT regexp_extract_all(line, '=(\d+)') FROM logs",
+        )
+        .await
+        .unwrap();
+        let result = result.as_list::<i32>();
+
+        assert_eq!(
+            strings(&result.value(0)),
+            vec![Some("1"), Some("22"), Some("333")]
+        );
+        assert_eq!(result.value(1).len(), 0);
+        assert!(result.is_null(2));
+    }
+
+    #[actix_web::test]
+    async fn invalid_or_non_literal_pattern_fails_planning() {
+        let err = query(
+            vec![Some("x")],
+            "SELECT regexp_extract(line, '(unclosed', 1) FROM logs",
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("Invalid regex"), "{err}");
+
+        let err = query(
+            vec![Some("x")],
+            "SELECT regexp_extract(line, line, 1) FROM logs",
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("literal"), "{err}");
+
+        let err = query(
+            vec![Some("x")],
+            r"SELECT regexp_extract(line, '(\d)', 2) FROM logs",
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("group 2"), "{err}");
+    }
+
+    #[actix_web::test]
+    async fn extract_over_100k_rows() {
+        let lines = (0..100_000)
+            .map(|i| format!("user={i} action=login latency={}ms", i % 250))
+            .collect::<Vec<_>>();
+        let lines = lines.iter().map(|l| Some(l.as_str())).collect();
+
+        let start = Instant::now();
+        let result = query(
+            lines,
+            r"SELECT regexp_extract(line, 'latency=(\d+)ms', 1) FROM logs",
+        )
+        .await
+        .unwrap();
+        let elapsed = start.elapsed();
+
+        assert_eq!(result.len(), 100_000);
+        assert_eq!(result.as_string::<i32>().value(251), "1");
+        assert!(elapsed.as_secs() < 30, "took {elapsed:?}");
+    }
+}
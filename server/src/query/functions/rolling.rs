@@ -0,0 +1,929 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! State shared by the rolling window functions
+
+use std::{
+    any::Any,
+    cmp::Ordering,
+    collections::{BTreeSet, VecDeque},
+    ops::Range,
+    sync::Arc,
+};
+
+use arrow_array::{Array, ArrayRef};
+use arrow_schema::DataType;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, WindowUDF, WindowUDFImpl},
+    scalar::ScalarValue,
+};
+
+/// What a rolling function does with NULL values, its optional last argument
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NullMode {
+    /// `'skip'`, the default: NULLs are left out and the window holds the
+    /// last `window` non NULL values
+    #[default]
+    Skip,
+    /// `'zero'`: NULLs are taken as 0
+    Zero,
+    /// `'propagate'`: the result is NULL while any of the last `window` rows is NULL
+    Propagate,
+}
+
+impl NullMode {
+    /// Mode passed to `function`, Skip when the call doesn't pass one
+    pub fn parse(function: &str, mode: Option<&ScalarValue>) -> Result<Self> {
+        let name = match mode {
+            None => return Ok(Self::Skip),
+            Some(ScalarValue::Utf8(Some(name)) | ScalarValue::LargeUtf8(Some(name))) => {
+                name.as_str()
+            }
+            Some(_) => "",
+        };
+        match name {
+            "skip" => Ok(Self::Skip),
+            "zero" => Ok(Self::Zero),
+            "propagate" => Ok(Self::Propagate),
+            _ => Err(DataFusionError::Plan(format!(
+                "{function} expects a NULL mode of 'skip', 'zero' or 'propagate', got {}",
+                mode.map(ToString::to_string).unwrap_or_default()
+            ))),
+        }
+    }
+}
+
+/// Applies a [`NullMode`] to the rows of a partition in order, counting the
+/// NULLs among the last `size` rows for `Propagate`
+#[derive(Debug, Clone)]
+pub struct NullTracker {
+    mode: NullMode,
+    size: usize,
+    // whether each of the last `size` rows was NULL
+    recent: VecDeque<bool>,
+    nulls: usize,
+}
+
+impl NullTracker {
+    pub fn new(mode: NullMode, size: usize) -> Self {
+        let tracked = if mode == NullMode::Propagate { size } else { 0 };
+        Self {
+            mode,
+            size,
+            recent: VecDeque::with_capacity(tracked),
+            nulls: 0,
+        }
+    }
+
+    /// Takes the next row, returning the value it adds to the window if any
+    pub fn admit(&mut self, value: Option<f64>) -> Option<f64> {
+        match self.mode {
+            NullMode::Skip => value,
+            NullMode::Zero => Some(value.unwrap_or(0.0)),
+            NullMode::Propagate => {
+                self.recent.push_back(value.is_none());
+                self.nulls += value.is_none() as usize;
+                if self.recent.len() > self.size && self.recent.pop_front() == Some(true) {
+                    self.nulls -= 1;
+                }
+                value
+            }
+        }
+    }
+
+    /// Whether `value` is added to the window, without taking the row
+    pub fn admits(&self, value: Option<f64>) -> bool {
+        value.is_some() || self.mode == NullMode::Zero
+    }
+
+    /// Result of the latest row given what the window computed for it.
+    ///
+    /// Without NULLs among the last `size` rows those rows are also the last
+    /// `size` values, so the window holds exactly them.
+    pub fn result<T>(&self, computed: Option<T>) -> Option<T> {
+        if self.nulls > 0 {
+            None
+        } else {
+            computed
+        }
+    }
+}
+
+/// Running count, mean and variance using Welford's algorithm, values can be
+/// evicted again in any order as long as they were pushed before
+#[derive(Debug, Default, Clone)]
+pub struct RollingStats {
+    count: u64,
+    mean: f64,
+    // sum of squared deviations from the mean
+    m2: f64,
+    // total change applied to m2, bounds the rounding error it has accumulated
+    m2_churn: f64,
+}
+
+impl RollingStats {
+    pub fn push(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        let change = delta * (value - self.mean);
+        self.m2 += change;
+        self.m2_churn += change.abs();
+    }
+
+    pub fn evict(&mut self, value: f64) {
+        if self.count <= 1 {
+            *self = Self::default();
+            return;
+        }
+        self.count -= 1;
+        let delta = value - self.mean;
+        self.mean -= delta / self.count as f64;
+        let change = delta * (value - self.mean);
+        // rounding can push the sum slightly below zero after many evictions
+        self.m2 = (self.m2 - change).max(0.0);
+        self.m2_churn += change.abs();
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.mean)
+    }
+
+    /// Sample variance, undefined for fewer than two values
+    pub fn variance(&self) -> Option<f64> {
+        // evicting leaves rounding residue behind, which would otherwise turn a
+        // window of identical values into a tiny but non zero variance
+        let noise = 8.0 * f64::EPSILON * self.m2_churn;
+        let m2 = if self.m2 <= noise { 0.0 } else { self.m2 };
+        (self.count > 1).then(|| m2 / (self.count - 1) as f64)
+    }
+
+    /// Sample standard deviation, undefined for fewer than two values
+    pub fn stddev(&self) -> Option<f64> {
+        self.variance().map(f64::sqrt)
+    }
+}
+
+/// The last `size` values pushed along with their statistics
+#[derive(Debug, Clone)]
+pub struct TrailingWindow {
+    size: usize,
+    values: VecDeque<f64>,
+    stats: RollingStats,
+    sum: f64,
+    // evictions since the statistics were last computed from scratch
+    evictions: usize,
+}
+
+impl TrailingWindow {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            values: VecDeque::with_capacity(size),
+            stats: RollingStats::default(),
+            sum: 0.0,
+            evictions: 0,
+        }
+    }
+
+    pub fn push(&mut self, value: f64) {
+        if self.values.len() == self.size {
+            if let Some(evicted) = self.values.pop_front() {
+                self.stats.evict(evicted);
+                self.sum -= evicted;
+                self.evictions += 1;
+            }
+        }
+        self.values.push_back(value);
+        self.stats.push(value);
+        self.sum += value;
+
+        // rebuilding once per window keeps eviction error from accumulating at amortized O(1)
+        if self.evictions >= self.size {
+            self.stats = RollingStats::default();
+            self.values.iter().for_each(|value| self.stats.push(*value));
+            self.sum = self.values.iter().sum();
+            self.evictions = 0;
+        }
+    }
+
+    pub fn stats(&self) -> &RollingStats {
+        &self.stats
+    }
+
+    /// Sum of the values in the window, None while it is empty
+    pub fn sum(&self) -> Option<f64> {
+        (!self.values.is_empty()).then_some(self.sum)
+    }
+}
+
+/// Values pushed less than `span` before the latest time along with their
+/// statistics, times are any unit as long as `span` uses the same
+#[derive(Debug, Clone)]
+pub struct TimedWindow {
+    span: i64,
+    // (time, value), times ascending
+    values: VecDeque<(i64, f64)>,
+    stats: RollingStats,
+    // evictions since the statistics were last computed from scratch
+    evictions: usize,
+}
+
+impl TimedWindow {
+    pub fn new(span: i64) -> Self {
+        Self {
+            span,
+            values: VecDeque::new(),
+            stats: RollingStats::default(),
+            evictions: 0,
+        }
+    }
+
+    /// Moves the end of the window to `time`, which can't be before the
+    /// previous one, dropping the values that fall out of it
+    pub fn advance(&mut self, time: i64) {
+        let start = time.saturating_sub(self.span);
+        while let Some(&(pushed, value)) = self.values.front() {
+            if pushed > start {
+                break;
+            }
+            self.values.pop_front();
+            self.stats.evict(value);
+            self.evictions += 1;
+        }
+
+        // same as TrailingWindow, the window size just isn't fixed
+        if self.evictions > 0 && self.evictions >= self.values.len() {
+            self.stats = RollingStats::default();
+            self.values
+                .iter()
+                .for_each(|(_, value)| self.stats.push(*value));
+            self.evictions = 0;
+        }
+    }
+
+    /// Adds `value` at `time`, advancing the window to it first
+    pub fn push(&mut self, time: i64, value: f64) {
+        self.advance(time);
+        self.values.push_back((time, value));
+        self.stats.push(value);
+    }
+
+    pub fn stats(&self) -> &RollingStats {
+        &self.stats
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extremum {
+    Min,
+    Max,
+}
+
+impl Extremum {
+    // whether `candidate` can never be the extremum again once `value` is pushed
+    fn supersedes(self, value: f64, candidate: f64) -> bool {
+        match self {
+            Extremum::Min => value <= candidate,
+            Extremum::Max => value >= candidate,
+        }
+    }
+}
+
+/// Minimum or maximum of the last `size` values pushed.
+///
+/// Only values that can still become the extremum are kept, ordered from the
+/// current extremum on, so pushing is amortized O(1).
+#[derive(Debug, Clone)]
+pub struct TrailingExtremum {
+    size: usize,
+    extremum: Extremum,
+    pushed: usize,
+    // (position, value), positions ascending and values monotonic
+    candidates: VecDeque<(usize, f64)>,
+}
+
+impl TrailingExtremum {
+    pub fn new(size: usize, extremum: Extremum) -> Self {
+        Self {
+            size,
+            extremum,
+            pushed: 0,
+            candidates: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, value: f64) {
+        while self
+            .candidates
+            .back()
+            .is_some_and(|(_, candidate)| self.extremum.supersedes(value, *candidate))
+        {
+            self.candidates.pop_back();
+        }
+        self.candidates.push_back((self.pushed, value));
+        self.pushed += 1;
+        while self
+            .candidates
+            .front()
+            .is_some_and(|(position, _)| position + self.size < self.pushed)
+        {
+            self.candidates.pop_front();
+        }
+    }
+
+    /// The extremum of the window, None while it is empty
+    pub fn value(&self) -> Option<f64> {
+        self.candidates.front().map(|(_, value)| *value)
+    }
+}
+
+// a pushed value ordered with f64::total_cmp, ties broken by position so
+// that equal values can be told apart when evicting
+#[derive(Debug, Clone, Copy)]
+struct Ranked {
+    value: f64,
+    position: usize,
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value
+            .total_cmp(&other.value)
+            .then(self.position.cmp(&other.position))
+    }
+}
+
+/// Median of the last `size` values pushed, the mean of the two middle
+/// values when the window holds an even number of them.
+///
+/// The window is split into a lower and an upper ordered half, so pushing
+/// and evicting are O(log size).
+#[derive(Debug, Clone)]
+pub struct TrailingMedian {
+    size: usize,
+    pushed: usize,
+    // in push order, to find the value to evict
+    values: VecDeque<Ranked>,
+    // holds the extra value when the window has an odd number of them
+    low: BTreeSet<Ranked>,
+    high: BTreeSet<Ranked>,
+}
+
+impl TrailingMedian {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            pushed: 0,
+            values: VecDeque::with_capacity(size),
+            low: BTreeSet::new(),
+            high: BTreeSet::new(),
+        }
+    }
+
+    pub fn push(&mut self, value: f64) {
+        if self.values.len() == self.size {
+            if let Some(evicted) = self.values.pop_front() {
+                if !self.low.remove(&evicted) {
+                    self.high.remove(&evicted);
+                }
+            }
+        }
+        let ranked = Ranked {
+            value,
+            position: self.pushed,
+        };
+        self.pushed += 1;
+        self.values.push_back(ranked);
+        if self.low.last().map_or(true, |middle| ranked < *middle) {
+            self.low.insert(ranked);
+        } else {
+            self.high.insert(ranked);
+        }
+
+        while self.low.len() > self.high.len() + 1 {
+            let moved = self.low.pop_last().expect("lower half is not empty");
+            self.high.insert(moved);
+        }
+        while self.high.len() > self.low.len() {
+            let moved = self.high.pop_first().expect("upper half is not empty");
+            self.low.insert(moved);
+        }
+    }
+
+    /// The median of the window, None while it is empty
+    pub fn median(&self) -> Option<f64> {
+        let middle = self.low.last()?.value;
+        if self.low.len() > self.high.len() {
+            return Some(middle);
+        }
+        self.high.first().map(|upper| (middle + upper.value) / 2.0)
+    }
+}
+
+/// Quantile of the last `size` values pushed, interpolated linearly between
+/// the two closest ranks.
+///
+/// The window is also kept sorted, finding where a value goes is O(log size)
+/// but inserting and evicting move up to `size` values.
+#[derive(Debug, Clone)]
+pub struct TrailingQuantile {
+    size: usize,
+    pushed: usize,
+    // in push order, to find the value to evict
+    values: VecDeque<Ranked>,
+    sorted: Vec<Ranked>,
+}
+
+impl TrailingQuantile {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            pushed: 0,
+            values: VecDeque::with_capacity(size),
+            sorted: Vec::with_capacity(size),
+        }
+    }
+
+    pub fn push(&mut self, value: f64) {
+        if self.values.len() == self.size {
+            if let Some(evicted) = self.values.pop_front() {
+                if let Ok(index) = self.sorted.binary_search(&evicted) {
+                    self.sorted.remove(index);
+                }
+            }
+        }
+        let ranked = Ranked {
+            value,
+            position: self.pushed,
+        };
+        self.pushed += 1;
+        self.values.push_back(ranked);
+        let index = self.sorted.partition_point(|other| *other < ranked);
+        self.sorted.insert(index, ranked);
+    }
+
+    /// The `quantile` (between 0 and 1) of the window, None while it is empty
+    pub fn quantile(&self, quantile: f64) -> Option<f64> {
+        let last = self.sorted.len().checked_sub(1)?;
+        let rank = quantile * last as f64;
+        let lower = self.sorted[rank.floor() as usize].value;
+        let upper = self.sorted[(rank.ceil() as usize).min(last)].value;
+        Some(lower + (upper - lower) * rank.fract())
+    }
+}
+
+/// A rolling function evaluated over the window frame of each row rather
+/// than the rows up to it, for calls whose frame ends after the current row
+/// or starts after the partition does.
+///
+/// The window then holds the last values of the frame. Each row is evaluated
+/// over its own frame, so this is only as fast as the frames are short.
+#[derive(Debug)]
+pub struct Framed {
+    inner: Arc<WindowUDF>,
+}
+
+impl Framed {
+    pub fn new(inner: Arc<WindowUDF>) -> Self {
+        Self { inner }
+    }
+}
+
+impl WindowUDFImpl for Framed {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn signature(&self) -> &Signature {
+        self.inner.signature()
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        self.inner.return_type(arg_types)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(FramedEvaluator(
+            self.inner.partition_evaluator_factory()?,
+        )))
+    }
+}
+
+#[derive(Debug)]
+struct FramedEvaluator(Box<dyn PartitionEvaluator>);
+
+impl PartitionEvaluator for FramedEvaluator {
+    fn evaluate(&mut self, values: &[ArrayRef], range: &Range<usize>) -> Result<ScalarValue> {
+        let frame: Vec<ArrayRef> = values
+            .iter()
+            .map(|values| values.slice(range.start, range.len()))
+            .collect();
+        let results = self.0.evaluate_all(&frame, range.len())?;
+        match range.len() {
+            0 => ScalarValue::try_from(results.data_type()),
+            len => ScalarValue::try_from_array(&results, len - 1),
+        }
+    }
+
+    fn uses_window_frame(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Instant};
+
+    use arrow_array::{ArrayRef, Float64Array, Int64Array, TimestampMillisecondArray};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use datafusion::{
+        logical_expr::{PartitionEvaluator, WindowUDF, WindowUDFImpl},
+        scalar::ScalarValue,
+    };
+
+    use super::{
+        Extremum, Framed, NullMode, NullTracker, TimedWindow, TrailingExtremum, TrailingMedian,
+        TrailingQuantile, TrailingWindow,
+    };
+    use crate::query::functions::{
+        rolling_count::RollingCountUdf,
+        rolling_extrema::{RollingMaxUdf, RollingMinUdf},
+        rolling_mean::RollingMeanUdf,
+        rolling_mean_within::RollingMeanWithinUdf,
+        rolling_median::RollingMedianUdf,
+        rolling_percentile::RollingPercentileUdf,
+        rolling_sum::RollingSumUdf,
+    };
+
+    // a literal argument as DataFusion passes it, repeated for every row
+    fn literal(value: impl Into<ScalarValue>, rows: usize) -> ArrayRef {
+        value.into().to_array_of_size(rows).unwrap()
+    }
+
+    // the results of evaluating the whole partition at once, and of evaluating
+    // each row on its own over the rows up to it, which is all a trailing
+    // window depends on
+    fn whole_and_by_row(
+        udf: impl WindowUDFImpl + 'static,
+        args: &[ArrayRef],
+    ) -> (Vec<ScalarValue>, Vec<ScalarValue>) {
+        let rows = args[0].len();
+        let udf = Arc::new(WindowUDF::from(udf));
+        let whole = udf
+            .partition_evaluator_factory()
+            .unwrap()
+            .evaluate_all(args, rows)
+            .unwrap();
+        let whole = (0..rows)
+            .map(|row| ScalarValue::try_from_array(&whole, row).unwrap())
+            .collect();
+        let mut framed = Framed::new(udf).partition_evaluator().unwrap();
+        let by_row = (0..rows)
+            .map(|row| framed.evaluate(args, &(0..row + 1)).unwrap())
+            .collect();
+        (whole, by_row)
+    }
+
+    #[test]
+    fn whole_partition_matches_rows_evaluated_one_by_one() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let rows = 300;
+        let values: ArrayRef = Arc::new(Int64Array::from_iter(
+            (0..rows).map(|_| rng.gen_bool(0.9).then(|| rng.gen_range(-100..100))),
+        ));
+        let mut time = 0;
+        let times: ArrayRef = Arc::new(TimestampMillisecondArray::from_iter((0..rows).map(|_| {
+            time += [0, 400, 1_000, 7_000][rng.gen_range(0..4)];
+            rng.gen_bool(0.95).then_some(time)
+        })));
+        let window = literal(7_i64, rows);
+
+        for mode in ["skip", "zero", "propagate"] {
+            let mode = literal(mode, rows);
+            let trailing = [values.clone(), window.clone(), mode.clone()];
+            let checks: Vec<(&str, _)> = vec![
+                (
+                    "rolling_mean",
+                    whole_and_by_row(RollingMeanUdf::new(), &trailing),
+                ),
+                (
+                    "rolling_sum",
+                    whole_and_by_row(RollingSumUdf::new(), &trailing),
+                ),
+                (
+                    "rolling_min",
+                    whole_and_by_row(RollingMinUdf::new(), &trailing),
+                ),
+                (
+                    "rolling_max",
+                    whole_and_by_row(RollingMaxUdf::new(), &trailing),
+                ),
+                (
+                    "rolling_median",
+                    whole_and_by_row(RollingMedianUdf::new(), &trailing),
+                ),
+                (
+                    "rolling_count",
+                    whole_and_by_row(RollingCountUdf::new(), &trailing),
+                ),
+                (
+                    "rolling_percentile",
+                    whole_and_by_row(
+                        RollingPercentileUdf::new(),
+                        &[
+                            values.clone(),
+                            literal(0.9, rows),
+                            window.clone(),
+                            mode.clone(),
+                        ],
+                    ),
+                ),
+                (
+                    "rolling_mean_within",
+                    whole_and_by_row(
+                        RollingMeanWithinUdf::new(),
+                        &[values.clone(), times.clone(), literal("5s", rows), mode],
+                    ),
+                ),
+            ];
+            for (name, (whole, by_row)) in checks {
+                assert_eq!(whole, by_row, "{name}");
+                assert!(whole.iter().any(|value| !value.is_null()), "{name}");
+            }
+        }
+    }
+
+    // evaluating the partition at once against each row over a frame of the
+    // last `window` rows, as a call with an explicit frame is. Run with
+    // `cargo test --release -- --ignored` to compare them
+    #[test]
+    #[ignore = "benchmark"]
+    fn whole_partition_is_faster_than_rows_one_by_one() {
+        let mut rng = StdRng::seed_from_u64(29);
+        let rows = 100_000;
+        let size = 1_000;
+        let args: Vec<ArrayRef> = vec![
+            Arc::new(Float64Array::from_iter_values(
+                (0..rows).map(|_| rng.gen_range(-1e3..1e3)),
+            )),
+            literal(size as i64, rows),
+        ];
+        let udf = Arc::new(WindowUDF::from(RollingMeanUdf::new()));
+
+        let start = Instant::now();
+        udf.partition_evaluator_factory()
+            .unwrap()
+            .evaluate_all(&args, rows)
+            .unwrap();
+        let elapsed = start.elapsed();
+
+        let mut framed = Framed::new(udf).partition_evaluator().unwrap();
+        let start = Instant::now();
+        for row in 0..rows {
+            let frame = (row + 1).saturating_sub(size)..row + 1;
+            framed.evaluate(&args, &frame).unwrap();
+        }
+        let by_row_elapsed = start.elapsed();
+
+        assert!(
+            elapsed < by_row_elapsed,
+            "whole partition {elapsed:?}, row by row {by_row_elapsed:?}"
+        );
+    }
+
+    fn brute_force(values: &[f64]) -> (f64, f64) {
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.);
+        (mean, variance)
+    }
+
+    #[test]
+    fn eviction_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(5);
+        // a large offset makes naive sum of squares lose all precision
+        let values: Vec<f64> = (0..10_000)
+            .map(|_| 1e9 + rng.gen_range(-50.0..50.0))
+            .collect();
+
+        let mut window = TrailingWindow::new(25);
+        for (i, value) in values.iter().enumerate() {
+            window.push(*value);
+            if i >= 1 {
+                let (mean, variance) = brute_force(&values[i.saturating_sub(24)..=i]);
+                let stats = window.stats();
+                assert!((stats.mean().unwrap() - mean).abs() < 1e-12 * mean);
+                assert!((stats.variance().unwrap() - variance).abs() < 1e-3 * variance);
+            }
+        }
+    }
+
+    #[test]
+    fn statistics_need_enough_values() {
+        let mut window = TrailingWindow::new(3);
+        assert_eq!(window.stats().mean(), None);
+        window.push(4.);
+        assert_eq!(window.stats().mean(), Some(4.));
+        assert_eq!(window.stats().stddev(), None);
+        window.push(4.);
+        assert_eq!(window.stats().stddev(), Some(0.));
+    }
+
+    #[test]
+    fn constant_window_after_eviction_has_no_variance() {
+        // four evictions, one short of rebuilding the statistics
+        let mut window = TrailingWindow::new(5);
+        [0.1, 1e6, 0.3, 7.7, 20., 20., 20., 20., 20.]
+            .iter()
+            .for_each(|value| window.push(*value));
+        assert_eq!(window.stats().variance(), Some(0.));
+
+        // a small spread far from zero is still a variance
+        let mut window = TrailingWindow::new(4);
+        [1e9, 1e9 + 1., 1e9, 1e9 + 1.]
+            .iter()
+            .for_each(|value| window.push(*value));
+        assert!((window.stats().variance().unwrap() - 1. / 3.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn extremum_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(7);
+        // few distinct values so that ties are common
+        let values: Vec<f64> = (0..2_000).map(|_| rng.gen_range(0..20) as f64).collect();
+
+        for size in [1, 4, 50] {
+            let mut min = TrailingExtremum::new(size, Extremum::Min);
+            let mut max = TrailingExtremum::new(size, Extremum::Max);
+            for (i, value) in values.iter().enumerate() {
+                min.push(*value);
+                max.push(*value);
+                let frame = &values[(i + 1).saturating_sub(size)..=i];
+                let expected_min = frame.iter().copied().fold(f64::INFINITY, f64::min);
+                let expected_max = frame.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                assert_eq!(min.value(), Some(expected_min), "size {size} row {i}");
+                assert_eq!(max.value(), Some(expected_max), "size {size} row {i}");
+            }
+        }
+        assert_eq!(TrailingExtremum::new(3, Extremum::Min).value(), None);
+    }
+
+    #[test]
+    fn median_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let values: Vec<f64> = (0..2_000).map(|_| rng.gen_range(0..30) as f64).collect();
+
+        for size in [1, 2, 5, 50] {
+            let mut median = TrailingMedian::new(size);
+            for (i, value) in values.iter().enumerate() {
+                median.push(*value);
+                let mut frame = values[(i + 1).saturating_sub(size)..=i].to_vec();
+                frame.sort_by(f64::total_cmp);
+                let middle = frame.len() / 2;
+                let expected = if frame.len() % 2 == 1 {
+                    frame[middle]
+                } else {
+                    (frame[middle - 1] + frame[middle]) / 2.0
+                };
+                assert_eq!(median.median(), Some(expected), "size {size} row {i}");
+            }
+        }
+        assert_eq!(TrailingMedian::new(3).median(), None);
+    }
+
+    #[test]
+    fn timed_window_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let mut time = 0;
+        let rows: Vec<(i64, f64)> = (0..2_000)
+            .map(|_| {
+                // bursts of equal times as well as long gaps
+                time += [0, 1, 3, 40][rng.gen_range(0..4)];
+                (time, rng.gen_range(-1e3..1e3))
+            })
+            .collect();
+
+        for span in [1, 10, 100] {
+            let mut window = TimedWindow::new(span);
+            for (i, &(time, value)) in rows.iter().enumerate() {
+                window.push(time, value);
+                let frame: Vec<f64> = rows[..=i]
+                    .iter()
+                    .filter(|(pushed, _)| time - pushed < span)
+                    .map(|(_, value)| *value)
+                    .collect();
+                let (mean, _) = brute_force(&frame);
+                let actual = window.stats().mean().unwrap();
+                assert!((actual - mean).abs() < 1e-9, "span {span} row {i}");
+                assert_eq!(window.stats().count(), frame.len() as u64);
+            }
+        }
+
+        let mut window = TimedWindow::new(10);
+        window.push(0, 1.0);
+        window.advance(10);
+        assert_eq!(window.stats().mean(), None);
+    }
+
+    #[test]
+    fn quantile_matches_median_and_extrema() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let values: Vec<f64> = (0..2_000).map(|_| rng.gen_range(0..30) as f64).collect();
+
+        for size in [1, 2, 5, 50] {
+            let mut quantile = TrailingQuantile::new(size);
+            let mut median = TrailingMedian::new(size);
+            let mut min = TrailingExtremum::new(size, Extremum::Min);
+            let mut max = TrailingExtremum::new(size, Extremum::Max);
+            for (i, value) in values.iter().enumerate() {
+                quantile.push(*value);
+                median.push(*value);
+                min.push(*value);
+                max.push(*value);
+                assert_eq!(
+                    quantile.quantile(0.5),
+                    median.median(),
+                    "size {size} row {i}"
+                );
+                assert_eq!(quantile.quantile(0.0), min.value(), "size {size} row {i}");
+                assert_eq!(quantile.quantile(1.0), max.value(), "size {size} row {i}");
+            }
+        }
+        assert_eq!(TrailingQuantile::new(3).quantile(0.5), None);
+    }
+
+    #[test]
+    fn null_modes() {
+        let mode = |name: &str| NullMode::parse("f", Some(&ScalarValue::from(name)));
+        assert_eq!(NullMode::parse("f", None).unwrap(), NullMode::Skip);
+        assert_eq!(mode("zero").unwrap(), NullMode::Zero);
+        assert_eq!(mode("propagate").unwrap(), NullMode::Propagate);
+        assert!(mode("SKIP").is_err());
+        assert!(NullMode::parse("f", Some(&ScalarValue::Int64(Some(1)))).is_err());
+
+        let rows = [Some(1.0), None, Some(2.0), Some(3.0), Some(4.0), None];
+        let admitted = |mode| {
+            let mut tracker = NullTracker::new(mode, 3);
+            rows.iter()
+                .map(|value| {
+                    let admitted = tracker.admit(*value);
+                    (admitted, tracker.result(Some(())).is_some())
+                })
+                .collect::<Vec<_>>()
+        };
+        assert!(admitted(NullMode::Skip)
+            .iter()
+            .zip(rows)
+            .all(|((admitted, kept), row)| *admitted == row && *kept));
+        assert!(admitted(NullMode::Zero)
+            .iter()
+            .zip(rows)
+            .all(|((admitted, kept), row)| *admitted == Some(row.unwrap_or(0.0)) && *kept));
+        // the NULL of the second row is evicted once three rows follow it
+        let kept: Vec<_> = admitted(NullMode::Propagate)
+            .into_iter()
+            .map(|(_, kept)| kept)
+            .collect();
+        assert_eq!(kept, [true, false, false, false, true, false]);
+    }
+}
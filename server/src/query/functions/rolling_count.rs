@@ -0,0 +1,256 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{Array, ArrayRef, Int64Array};
+use arrow_schema::DataType;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, TypeSignature, Volatility, WindowUDFImpl},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::{
+    require_literal,
+    rolling::{NullMode, NullTracker},
+    rolling_mean::{DEFAULT_WINDOW, MAX_WINDOW},
+};
+
+fn window_arg(window: Option<&ScalarValue>) -> Result<usize> {
+    match window {
+        None => Ok(DEFAULT_WINDOW),
+        Some(ScalarValue::Int64(Some(window))) if (1..=MAX_WINDOW).contains(window) => {
+            Ok(*window as usize)
+        }
+        Some(other) => Err(DataFusionError::Plan(format!(
+            "rolling_count expects a window between 1 and {MAX_WINDOW}, got {other}"
+        ))),
+    }
+}
+
+/// Checks the window and NULL mode of a rolling_count call while planning
+pub fn validate_args(args: &[Expr]) -> Result<()> {
+    require_literal("rolling_count", args, 1)?;
+    require_literal("rolling_count", args, 2)?;
+    if let Some(Expr::Literal(mode)) = args.get(2) {
+        NullMode::parse("rolling_count", Some(mode))?;
+    }
+    match args.get(1) {
+        Some(Expr::Literal(window)) => window_arg(Some(window)).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// `rolling_count(value [, window [, null_mode]])`
+///
+/// Number of non NULL values among the last `window` (default 300) rows up
+/// to and including the current row in the window order. Unlike the other
+/// rolling functions the window counts rows, NULLs included, so a stretch of
+/// missing values brings the count down to 0.
+///
+/// With `null_mode` `'zero'` NULLs are counted like values, with
+/// `'propagate'` the count is NULL while the window has a NULL.
+#[derive(Debug)]
+pub struct RollingCountUdf {
+    signature: Signature,
+}
+
+impl RollingCountUdf {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::one_of(
+                vec![
+                    TypeSignature::Any(1),
+                    TypeSignature::Any(2),
+                    TypeSignature::Any(3),
+                ],
+                Volatility::Immutable,
+            ),
+        }
+    }
+}
+
+impl WindowUDFImpl for RollingCountUdf {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "rolling_count"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        if let Some(window) = arg_types.get(1).filter(|window| !window.is_integer()) {
+            return Err(DataFusionError::Plan(format!(
+                "rolling_count expects an integer window, got {window}"
+            )));
+        }
+        Ok(DataType::Int64)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(RollingCountEvaluator))
+    }
+}
+
+#[derive(Debug)]
+struct RollingCountEvaluator;
+
+impl PartitionEvaluator for RollingCountEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+        if num_rows == 0 {
+            return Ok(Arc::new(Int64Array::from(Vec::<i64>::new())));
+        }
+        // window and mode are literals, every row carries the same values
+        let window = values
+            .get(1)
+            .map(|window| ScalarValue::try_from_array(window, 0))
+            .transpose()?;
+        let size = window_arg(window.as_ref())?;
+        let mode = values
+            .get(2)
+            .map(|mode| ScalarValue::try_from_array(mode, 0))
+            .transpose()?;
+        let mode = NullMode::parse("rolling_count", mode.as_ref())?;
+        let mut nulls = NullTracker::new(mode, size);
+
+        let input = &values[0];
+        let counted = |row| mode == NullMode::Zero || input.is_valid(row);
+        let mut count = 0;
+        let counts = (0..num_rows)
+            .map(|row| {
+                nulls.admit(input.is_valid(row).then_some(1.0));
+                count += counted(row) as i64;
+                if row >= size {
+                    count -= counted(row - size) as i64;
+                }
+                nulls.result(Some(count))
+            })
+            .collect::<Int64Array>();
+        Ok(Arc::new(counts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{cast::AsArray, types::Int64Type, Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{datasource::MemTable, logical_expr::WindowUDF, prelude::SessionContext};
+
+    use super::RollingCountUdf;
+    use crate::query::functions::add_analyzer_rules;
+
+    fn context(values: Vec<Option<i64>>) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("seq", DataType::Int64, false),
+            Field::new("value", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(0..values.len() as i64)),
+                Arc::new(Int64Array::from(values)),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        ctx.register_udwf(WindowUDF::from(RollingCountUdf::new()));
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn counts(ctx: &SessionContext, call: &str) -> datafusion::error::Result<Vec<i64>> {
+        let sql = format!("SELECT {call} OVER (ORDER BY seq) FROM metrics ORDER BY seq");
+        let batches = ctx.sql(&sql).await?.collect().await?;
+        Ok(batches
+            .iter()
+            .flat_map(|batch| {
+                batch
+                    .column(0)
+                    .as_primitive::<Int64Type>()
+                    .values()
+                    .to_vec()
+            })
+            .collect())
+    }
+
+    #[actix_web::test]
+    async fn nulls_are_not_counted() {
+        let ctx = context(vec![Some(1), None, Some(2), Some(3), None, Some(4)]);
+        let counts = counts(&ctx, "rolling_count(value, 3)").await.unwrap();
+        assert_eq!(counts, [1, 1, 2, 2, 2, 2]);
+    }
+
+    #[actix_web::test]
+    async fn all_null_window_is_zero() {
+        let ctx = context(vec![Some(1), None, None, None, Some(5)]);
+        let gap = counts(&ctx, "rolling_count(value, 2)").await.unwrap();
+        assert_eq!(gap, [1, 1, 0, 0, 1]);
+
+        let ctx = context(vec![None, None]);
+        let empty = counts(&ctx, "rolling_count(value)").await.unwrap();
+        assert_eq!(empty, [0, 0]);
+    }
+
+    #[actix_web::test]
+    async fn null_modes() {
+        let ctx = context(vec![Some(1), None, Some(2), Some(3), None, Some(4)]);
+        let skip = counts(&ctx, "rolling_count(value, 2, 'skip')")
+            .await
+            .unwrap();
+        assert_eq!(skip, [1, 1, 1, 2, 1, 1]);
+        let zero = counts(&ctx, "rolling_count(value, 2, 'zero')")
+            .await
+            .unwrap();
+        assert_eq!(zero, [1, 2, 2, 2, 2, 2]);
+
+        let sql = "SELECT rolling_count(value, 2, 'propagate') OVER (ORDER BY seq) \
+                   FROM metrics ORDER BY seq";
+        let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        let propagate: Vec<_> = batches
+            .iter()
+            .flat_map(|batch| batch.column(0).as_primitive::<Int64Type>().iter())
+            .collect();
+        assert_eq!(propagate, [Some(1), None, None, Some(2), None, None]);
+    }
+
+    #[actix_web::test]
+    async fn window_is_validated_while_planning() {
+        let ctx = context(vec![Some(1)]);
+        for call in [
+            "rolling_count(value, 0)",
+            "rolling_count(value, seq)",
+            "rolling_count(value, 2, 'none')",
+        ] {
+            let err = counts(&ctx, call).await.unwrap_err();
+            assert!(err.to_string().contains("rolling_count"), "{call}: {err}");
+        }
+    }
+}
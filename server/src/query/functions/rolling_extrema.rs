@@ -0,0 +1,305 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{cast::AsArray, types::Float64Type, ArrayRef, Float64Array};
+use arrow_schema::DataType;
+use datafusion::arrow::compute::cast;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, TypeSignature, Volatility, WindowUDFImpl},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::{
+    require_literal,
+    rolling::{Extremum, NullMode, NullTracker, TrailingExtremum},
+    rolling_mean::{DEFAULT_WINDOW, MAX_WINDOW},
+};
+
+fn name(extremum: Extremum) -> &'static str {
+    match extremum {
+        Extremum::Min => "rolling_min",
+        Extremum::Max => "rolling_max",
+    }
+}
+
+fn window_arg(extremum: Extremum, window: Option<&ScalarValue>) -> Result<usize> {
+    match window {
+        None => Ok(DEFAULT_WINDOW),
+        Some(ScalarValue::Int64(Some(window))) if (1..=MAX_WINDOW).contains(window) => {
+            Ok(*window as usize)
+        }
+        Some(other) => Err(DataFusionError::Plan(format!(
+            "{} expects a window between 1 and {MAX_WINDOW}, got {other}",
+            name(extremum)
+        ))),
+    }
+}
+
+/// Checks the window and NULL mode of a rolling_min or rolling_max call while planning
+pub fn validate_args(extremum: Extremum, args: &[Expr]) -> Result<()> {
+    require_literal(name(extremum), args, 1)?;
+    require_literal(name(extremum), args, 2)?;
+    if let Some(Expr::Literal(mode)) = args.get(2) {
+        NullMode::parse(name(extremum), Some(mode))?;
+    }
+    match args.get(1) {
+        Some(Expr::Literal(window)) => window_arg(extremum, Some(window)).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+fn signature() -> Signature {
+    Signature::one_of(
+        vec![
+            TypeSignature::Any(1),
+            TypeSignature::Any(2),
+            TypeSignature::Any(3),
+        ],
+        Volatility::Immutable,
+    )
+}
+
+fn return_type(extremum: Extremum, arg_types: &[DataType]) -> Result<DataType> {
+    if !arg_types[0].is_numeric() {
+        return Err(DataFusionError::Plan(format!(
+            "{} expects a numeric value, got {}",
+            name(extremum),
+            arg_types[0]
+        )));
+    }
+    if let Some(window) = arg_types.get(1).filter(|window| !window.is_integer()) {
+        return Err(DataFusionError::Plan(format!(
+            "{} expects an integer window, got {window}",
+            name(extremum)
+        )));
+    }
+    Ok(DataType::Float64)
+}
+
+/// `rolling_min(value [, window [, null_mode]])`
+///
+/// Smallest of the last `window` (default 300) non NULL values up to and
+/// including the current row in the window order, counted the same way as
+/// `rolling_mean`.
+///
+/// Returns NULL until the partition has a non NULL value.
+#[derive(Debug)]
+pub struct RollingMinUdf {
+    signature: Signature,
+}
+
+impl RollingMinUdf {
+    pub fn new() -> Self {
+        Self {
+            signature: signature(),
+        }
+    }
+}
+
+impl WindowUDFImpl for RollingMinUdf {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        name(Extremum::Min)
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        return_type(Extremum::Min, arg_types)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(RollingExtremumEvaluator(Extremum::Min)))
+    }
+}
+
+/// `rolling_max(value [, window [, null_mode]])`
+///
+/// Largest of the last `window` (default 300) non NULL values, see `rolling_min`.
+#[derive(Debug)]
+pub struct RollingMaxUdf {
+    signature: Signature,
+}
+
+impl RollingMaxUdf {
+    pub fn new() -> Self {
+        Self {
+            signature: signature(),
+        }
+    }
+}
+
+impl WindowUDFImpl for RollingMaxUdf {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        name(Extremum::Max)
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        return_type(Extremum::Max, arg_types)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(RollingExtremumEvaluator(Extremum::Max)))
+    }
+}
+
+#[derive(Debug)]
+struct RollingExtremumEvaluator(Extremum);
+
+impl PartitionEvaluator for RollingExtremumEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+        if num_rows == 0 {
+            return Ok(Arc::new(Float64Array::from(Vec::<f64>::new())));
+        }
+        // window and mode are literals, every row carries the same values
+        let window = values
+            .get(1)
+            .map(|window| ScalarValue::try_from_array(window, 0))
+            .transpose()?;
+        let size = window_arg(self.0, window.as_ref())?;
+        let mode = values
+            .get(2)
+            .map(|mode| ScalarValue::try_from_array(mode, 0))
+            .transpose()?;
+        let mut nulls = NullTracker::new(NullMode::parse(name(self.0), mode.as_ref())?, size);
+
+        let input = cast(&values[0], &DataType::Float64)?;
+        let mut window = TrailingExtremum::new(size, self.0);
+        let extrema = input
+            .as_primitive::<Float64Type>()
+            .iter()
+            .map(|value| {
+                if let Some(value) = nulls.admit(value) {
+                    window.push(value);
+                }
+                nulls.result(window.value())
+            })
+            .collect::<Float64Array>();
+        Ok(Arc::new(extrema))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{cast::AsArray, types::Float64Type, Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{datasource::MemTable, logical_expr::WindowUDF, prelude::SessionContext};
+
+    use super::{RollingMaxUdf, RollingMinUdf};
+    use crate::query::functions::add_analyzer_rules;
+
+    fn context(values: Vec<Option<i64>>) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("seq", DataType::Int64, false),
+            Field::new("value", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(0..values.len() as i64)),
+                Arc::new(Int64Array::from(values)),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        ctx.register_udwf(WindowUDF::from(RollingMinUdf::new()));
+        ctx.register_udwf(WindowUDF::from(RollingMaxUdf::new()));
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn extrema(
+        ctx: &SessionContext,
+        call: &str,
+    ) -> datafusion::error::Result<Vec<Option<f64>>> {
+        let sql = format!("SELECT {call} OVER (ORDER BY seq) FROM metrics ORDER BY seq");
+        let batches = ctx.sql(&sql).await?.collect().await?;
+        Ok(batches
+            .iter()
+            .flat_map(|batch| batch.column(0).as_primitive::<Float64Type>().iter())
+            .collect())
+    }
+
+    #[actix_web::test]
+    async fn matches_brute_force_over_five_rows() {
+        let values = [7, -3, 12, 0, 5, 5, 40, -8, 1, 9, 9, -8, 2, 30, 4];
+        let ctx = context(values.iter().copied().map(Some).collect());
+        let mins = extrema(&ctx, "rolling_min(value, 5)").await.unwrap();
+        let maxs = extrema(&ctx, "rolling_max(value, 5)").await.unwrap();
+
+        assert_eq!(mins.len(), values.len());
+        for row in 0..values.len() {
+            let frame = &values[row.saturating_sub(4)..=row];
+            let min = *frame.iter().min().unwrap() as f64;
+            let max = *frame.iter().max().unwrap() as f64;
+            assert_eq!(mins[row], Some(min), "row {row}");
+            assert_eq!(maxs[row], Some(max), "row {row}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn nulls_are_skipped() {
+        let ctx = context(vec![Some(3), None, Some(1), None, Some(2), Some(5)]);
+        let mins = extrema(&ctx, "rolling_min(value, 2)").await.unwrap();
+        assert_eq!(mins, [3.0, 3.0, 1.0, 1.0, 1.0, 2.0].map(Some));
+        let maxs = extrema(&ctx, "rolling_max(value, 2)").await.unwrap();
+        assert_eq!(maxs, [3.0, 3.0, 3.0, 3.0, 2.0, 5.0].map(Some));
+    }
+
+    #[actix_web::test]
+    async fn empty_window_is_null() {
+        let ctx = context(vec![None, None, Some(5)]);
+        for call in ["rolling_min(value)", "rolling_max(value)"] {
+            let extrema = extrema(&ctx, call).await.unwrap();
+            assert_eq!(extrema, vec![None, None, Some(5.0)], "{call}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn window_is_validated_while_planning() {
+        let ctx = context(vec![Some(1)]);
+        for call in ["rolling_min(value, 0)", "rolling_max(value, seq)"] {
+            let err = extrema(&ctx, call).await.unwrap_err();
+            let name = &call[..call.find('(').unwrap()];
+            assert!(err.to_string().contains(name), "{call}: {err}");
+        }
+    }
+}
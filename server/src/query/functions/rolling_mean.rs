@@ -0,0 +1,609 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, ops::Range, sync::Arc};
+
+use arrow_array::{cast::AsArray, types::Float64Type, ArrayRef, Float64Array};
+use arrow_schema::DataType;
+use datafusion::arrow::compute::cast;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, TypeSignature, Volatility, WindowUDFImpl},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+use rayon::prelude::*;
+
+use super::{
+    require_literal,
+    rolling::{NullMode, NullTracker, TrailingWindow},
+};
+
+/// Window size of rolling_mean when the call doesn't pass one
+pub const DEFAULT_WINDOW: usize = 300;
+/// Largest window accepted by rolling_mean
+pub const MAX_WINDOW: i64 = 100_000;
+/// Fewest rows a partition is split into for computing in parallel
+const MIN_CHUNK_ROWS: usize = 16_384;
+
+fn window_arg(window: Option<&ScalarValue>) -> Result<usize> {
+    match window {
+        None => Ok(DEFAULT_WINDOW),
+        Some(ScalarValue::Int64(Some(window))) if (1..=MAX_WINDOW).contains(window) => {
+            Ok(*window as usize)
+        }
+        Some(other) => Err(DataFusionError::Plan(format!(
+            "rolling_mean expects a window between 1 and {MAX_WINDOW}, got {other}"
+        ))),
+    }
+}
+
+/// Checks the window and NULL mode of a rolling_mean call while planning
+pub fn validate_args(args: &[Expr]) -> Result<()> {
+    require_literal("rolling_mean", args, 1)?;
+    require_literal("rolling_mean", args, 2)?;
+    if let Some(Expr::Literal(mode)) = args.get(2) {
+        NullMode::parse("rolling_mean", Some(mode))?;
+    }
+    match args.get(1) {
+        Some(Expr::Literal(window)) => window_arg(Some(window)).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// `rolling_mean(value [, window [, null_mode]])`
+///
+/// Mean of the last `window` (default 300) non NULL values up to and
+/// including the current row in the window order. The window counts values
+/// rather than following the SQL frame of the call, unless the frame ends
+/// after the current row or starts after the partition does: the window then
+/// holds the last values of the frame.
+///
+/// `null_mode` is `'skip'` by default, leaving NULLs out of the window.
+/// `'zero'` takes them as 0 and `'propagate'` returns NULL while any of the
+/// last `window` rows is NULL.
+///
+/// Returns NULL until the partition has a non NULL value.
+#[derive(Debug)]
+pub struct RollingMeanUdf {
+    signature: Signature,
+}
+
+impl RollingMeanUdf {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::one_of(
+                vec![
+                    TypeSignature::Any(1),
+                    TypeSignature::Any(2),
+                    TypeSignature::Any(3),
+                ],
+                Volatility::Immutable,
+            ),
+        }
+    }
+}
+
+impl WindowUDFImpl for RollingMeanUdf {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "rolling_mean"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        if !arg_types[0].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "rolling_mean expects a numeric value, got {}",
+                arg_types[0]
+            )));
+        }
+        if let Some(window) = arg_types.get(1).filter(|window| !window.is_integer()) {
+            return Err(DataFusionError::Plan(format!(
+                "rolling_mean expects an integer window, got {window}"
+            )));
+        }
+        Ok(DataType::Float64)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(RollingMeanEvaluator))
+    }
+}
+
+#[derive(Debug)]
+struct RollingMeanEvaluator;
+
+impl PartitionEvaluator for RollingMeanEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+        if num_rows == 0 {
+            return Ok(Arc::new(Float64Array::from(Vec::<f64>::new())));
+        }
+        // window and mode are literals, every row carries the same values
+        let window = values
+            .get(1)
+            .map(|window| ScalarValue::try_from_array(window, 0))
+            .transpose()?;
+        let size = window_arg(window.as_ref())?;
+        let mode = values
+            .get(2)
+            .map(|mode| ScalarValue::try_from_array(mode, 0))
+            .transpose()?;
+        let nulls = NullTracker::new(NullMode::parse("rolling_mean", mode.as_ref())?, size);
+
+        let input = cast(&values[0], &DataType::Float64)?;
+        let input: Vec<Option<f64>> = input.as_primitive::<Float64Type>().iter().collect();
+        let means = parallel_means(&input, size, nulls);
+        Ok(Arc::new(Float64Array::from(means)))
+    }
+}
+
+// means of the rows one after another, the reference the parallel path has
+// to match
+fn sequential_means(
+    values: &[Option<f64>],
+    size: usize,
+    mut nulls: NullTracker,
+) -> Vec<Option<f64>> {
+    let mut window = TrailingWindow::new(size);
+    values
+        .iter()
+        .map(|value| {
+            if let Some(value) = nulls.admit(*value) {
+                window.push(value);
+            }
+            nulls.result(window.stats().mean())
+        })
+        .collect()
+}
+
+// Splits the rows into chunks computed in parallel. A chunk replays the rows
+// before it to rebuild the state the sequential pass has at its start, so the
+// means match it exactly, rounding included.
+//
+// TrailingWindow recomputes its statistics from scratch after every `size`
+// evictions, which happens once the `k`th value is pushed for any `k` that is
+// a multiple of `size` past the first window. Its state after that only
+// depends on the values since the last window it rebuilt from, so a chunk
+// starts its window at the beginning of that and replays at most `2 * size`
+// values. The NULL tracker only looks at the last `size` rows.
+fn parallel_means(values: &[Option<f64>], size: usize, nulls: NullTracker) -> Vec<Option<f64>> {
+    let chunk = values
+        .len()
+        .div_ceil(rayon::current_num_threads())
+        .max(MIN_CHUNK_ROWS)
+        .max(8 * size);
+    if chunk >= values.len() {
+        return sequential_means(values, size, nulls);
+    }
+    chunked_means(values, size, nulls, chunk)
+}
+
+fn chunked_means(
+    values: &[Option<f64>],
+    size: usize,
+    nulls: NullTracker,
+    chunk: usize,
+) -> Vec<Option<f64>> {
+    // rows of the values that make it into the window
+    let admitted: Vec<usize> = values
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| nulls.admits(**value))
+        .map(|(row, _)| row)
+        .collect();
+    let starts: Vec<usize> = (0..values.len()).step_by(chunk).collect();
+    let chunks: Vec<Vec<Option<f64>>> = starts
+        .into_par_iter()
+        .map(|start| {
+            let rows = start..(start + chunk).min(values.len());
+            chunk_means(values, rows, size, nulls.clone(), &admitted)
+        })
+        .collect();
+    chunks.concat()
+}
+
+// means of `rows`, `nulls` has not seen any row yet
+fn chunk_means(
+    values: &[Option<f64>],
+    rows: Range<usize>,
+    size: usize,
+    mut nulls: NullTracker,
+    admitted: &[usize],
+) -> Vec<Option<f64>> {
+    // values pushed before the chunk and the first one of the window the
+    // sequential pass last rebuilt its statistics from
+    let pushed = admitted.partition_point(|row| *row < rows.start);
+    let first = if pushed >= 2 * size {
+        (pushed / size - 1) * size
+    } else {
+        0
+    };
+    let window_row = admitted.get(first).copied().unwrap_or(rows.start);
+    let replay = window_row.min(rows.start.saturating_sub(size));
+
+    let mut window = TrailingWindow::new(size);
+    let mut index = admitted.partition_point(|row| *row < replay);
+    for value in &values[replay..rows.start] {
+        if let Some(value) = nulls.admit(*value) {
+            if index >= first {
+                window.push(value);
+            }
+            index += 1;
+        }
+    }
+    values[rows]
+        .iter()
+        .map(|value| {
+            if let Some(value) = nulls.admit(*value) {
+                window.push(value);
+            }
+            nulls.result(window.stats().mean())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray, types::Float64Type, ArrayRef, Float32Array, Int64Array, RecordBatch,
+        UInt64Array,
+    };
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{
+        datasource::MemTable,
+        logical_expr::{PartitionEvaluator, WindowUDF},
+        prelude::SessionContext,
+    };
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{
+        chunked_means, parallel_means, sequential_means, RollingMeanEvaluator, RollingMeanUdf,
+        DEFAULT_WINDOW,
+    };
+    use crate::query::functions::{
+        add_analyzer_rules,
+        rolling::{NullMode, NullTracker},
+    };
+
+    fn context(values: Vec<Option<i64>>) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("seq", DataType::Int64, false),
+            Field::new("value", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(0..values.len() as i64)),
+                Arc::new(Int64Array::from(values)),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        ctx.register_udwf(WindowUDF::from(RollingMeanUdf::new()));
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn means(
+        ctx: &SessionContext,
+        call: &str,
+    ) -> datafusion::error::Result<Vec<Option<f64>>> {
+        let sql = format!("SELECT {call} FROM metrics ORDER BY seq");
+        let batches = ctx.sql(&sql).await?.collect().await?;
+        Ok(batches
+            .iter()
+            .flat_map(|batch| batch.column(0).as_primitive::<Float64Type>().iter())
+            .collect())
+    }
+
+    #[actix_web::test]
+    async fn window_argument_is_honored_over_the_partition() {
+        let ctx = context((1..=6).map(Some).collect());
+        let expected = [1.0, 1.5, 2.0, 3.0, 4.0, 5.0].map(Some);
+        for over in [
+            "OVER (ORDER BY seq)",
+            "OVER (ORDER BY seq ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW)",
+            "OVER (ORDER BY seq ROWS BETWEEN UNBOUNDED PRECEDING AND UNBOUNDED FOLLOWING)",
+        ] {
+            let means = means(&ctx, &format!("rolling_mean(value, 3) {over}"))
+                .await
+                .unwrap();
+            assert_eq!(means, expected, "{over}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn window_holds_the_last_values_of_a_frame() {
+        let ctx = context((1..=6).map(Some).collect());
+        let trailing = means(
+            &ctx,
+            "rolling_mean(value, 3) OVER (ORDER BY seq ROWS BETWEEN 1 PRECEDING AND CURRENT ROW)",
+        )
+        .await
+        .unwrap();
+        assert_eq!(trailing, [1.0, 1.5, 2.5, 3.5, 4.5, 5.5].map(Some));
+
+        // the two rows after the current one
+        let leading = means(
+            &ctx,
+            "rolling_mean(value, 2) OVER (ORDER BY seq ROWS BETWEEN CURRENT ROW AND 2 FOLLOWING)",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            leading,
+            [
+                Some(2.5),
+                Some(3.5),
+                Some(4.5),
+                Some(5.5),
+                Some(5.5),
+                Some(6.0)
+            ]
+        );
+
+        let empty = means(
+            &ctx,
+            "rolling_mean(value) OVER (ORDER BY seq ROWS BETWEEN 2 FOLLOWING AND 3 FOLLOWING)",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            empty,
+            [Some(3.5), Some(4.5), Some(5.5), Some(6.0), None, None]
+        );
+    }
+
+    #[actix_web::test]
+    async fn centered_frame_matches_brute_force_mean() {
+        let values = [
+            Some(7),
+            Some(-3),
+            None,
+            Some(12),
+            Some(0),
+            Some(5),
+            None,
+            None,
+            Some(40),
+            Some(-8),
+        ];
+        let ctx = context(values.to_vec());
+        let means = means(
+            &ctx,
+            "rolling_mean(value) OVER (ORDER BY seq ROWS BETWEEN 1 PRECEDING AND 1 FOLLOWING)",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(means.len(), values.len());
+        for (row, mean) in means.into_iter().enumerate() {
+            let frame: Vec<i64> = values[row.saturating_sub(1)..(row + 2).min(values.len())]
+                .iter()
+                .flatten()
+                .copied()
+                .collect();
+            let expected =
+                (!frame.is_empty()).then(|| frame.iter().sum::<i64>() as f64 / frame.len() as f64);
+            match (mean, expected) {
+                (Some(mean), Some(expected)) => {
+                    assert!((mean - expected).abs() < 1e-9, "row {row}")
+                }
+                (mean, expected) => assert_eq!(mean, expected, "row {row}"),
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn window_defaults_without_argument() {
+        let ctx = context((0..DEFAULT_WINDOW as i64 + 10).map(Some).collect());
+        let means = means(&ctx, "rolling_mean(value) OVER (ORDER BY seq)")
+            .await
+            .unwrap();
+        let window = DEFAULT_WINDOW as f64;
+        assert_eq!(means[DEFAULT_WINDOW - 1], Some((window - 1.0) / 2.0));
+        // the window slid past the first ten values
+        assert_eq!(means.last().unwrap(), &Some((window - 1.0) / 2.0 + 10.0));
+    }
+
+    #[actix_web::test]
+    async fn nulls_are_skipped() {
+        let ctx = context(vec![None, Some(10), None, Some(20), Some(30)]);
+        let means = means(&ctx, "rolling_mean(value, 2) OVER (ORDER BY seq)")
+            .await
+            .unwrap();
+        assert_eq!(
+            means,
+            vec![None, Some(10.0), Some(10.0), Some(15.0), Some(25.0)]
+        );
+    }
+
+    #[actix_web::test]
+    async fn leading_nulls_are_null_not_nan() {
+        let ctx = context(vec![None, None, Some(4)]);
+        for mode in ["'skip'", "'propagate'"] {
+            let call = format!("rolling_mean(value, 3, {mode}) OVER (ORDER BY seq)");
+            assert_eq!(
+                means(&ctx, &call).await.unwrap()[..2],
+                [None, None],
+                "{mode}"
+            );
+        }
+
+        let mut evaluator = RollingMeanEvaluator;
+        let values: ArrayRef = Arc::new(Int64Array::from(vec![None, Some(4)]));
+        let means = evaluator.evaluate_all(&[values], 2).unwrap();
+        assert!(means.is_null(0));
+        assert_eq!(means.as_primitive::<Float64Type>().value(1), 4.0);
+    }
+
+    #[actix_web::test]
+    async fn null_modes() {
+        let ctx = context(vec![Some(10), None, Some(20), Some(30), None, Some(40)]);
+        let mut by_mode = vec![];
+        for mode in ["", ", 'skip'", ", 'zero'", ", 'propagate'"] {
+            let call = format!("rolling_mean(value, 2{mode}) OVER (ORDER BY seq)");
+            by_mode.push(means(&ctx, &call).await.unwrap());
+        }
+        let skip = [10.0, 10.0, 15.0, 25.0, 25.0, 35.0].map(Some);
+        assert_eq!(by_mode[0], skip);
+        assert_eq!(by_mode[1], skip);
+        assert_eq!(by_mode[2], [10.0, 5.0, 10.0, 25.0, 15.0, 20.0].map(Some));
+        assert_eq!(by_mode[3], [Some(10.0), None, None, Some(25.0), None, None]);
+    }
+
+    #[actix_web::test]
+    async fn matches_brute_force_mean() {
+        let values = [7, -3, 12, 0, 5, 5, 40, -8, 1, 9];
+        let ctx = context(values.iter().copied().map(Some).collect());
+        let means = means(
+            &ctx,
+            "rolling_mean(value, 3) OVER (ORDER BY seq ROWS BETWEEN 2 PRECEDING AND CURRENT ROW)",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(means.len(), values.len());
+        for (row, mean) in means.into_iter().enumerate() {
+            let frame = &values[row.saturating_sub(2)..=row];
+            let expected = frame.iter().sum::<i64>() as f64 / frame.len() as f64;
+            // the window sum is kept incrementally, allow for rounding
+            assert!((mean.unwrap() - expected).abs() < 1e-9, "row {row}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn partitions_are_independent() {
+        let ctx = context((1..=6).map(Some).collect());
+        let means = means(
+            &ctx,
+            "rolling_mean(value, 2) OVER (PARTITION BY seq % 2 ORDER BY seq)",
+        )
+        .await
+        .unwrap();
+        // even rows hold 1, 3, 5 and odd rows 2, 4, 6
+        assert_eq!(means, [1.0, 2.0, 2.0, 3.0, 4.0, 5.0].map(Some));
+    }
+
+    #[actix_web::test]
+    async fn float32_and_int64_columns_are_coerced() {
+        let ctx = context(vec![Some(1), Some(2), None, Some(4)]);
+        let expected = [1.0, 1.5, 1.5, 3.0].map(Some);
+        for value in ["CAST(value AS REAL)", "value", "CAST(value AS SMALLINT)"] {
+            let means = means(
+                &ctx,
+                &format!("rolling_mean({value}, 2) OVER (ORDER BY seq)"),
+            )
+            .await
+            .unwrap();
+            assert_eq!(means, expected, "{value}");
+        }
+
+        let mut evaluator = RollingMeanEvaluator;
+        let float32: ArrayRef = Arc::new(Float32Array::from(vec![0.5, 1.5]));
+        let means = evaluator.evaluate_all(&[float32], 2).unwrap();
+        let means: Vec<_> = means.as_primitive::<Float64Type>().iter().collect();
+        assert_eq!(means, [0.5, 1.0].map(Some));
+    }
+
+    #[actix_web::test]
+    async fn large_integers_do_not_overflow() {
+        // summed as integers these would wrap around
+        let ctx = context(vec![Some(i64::MAX), Some(i64::MAX), Some(i64::MIN)]);
+        let means = means(&ctx, "rolling_mean(value, 2) OVER (ORDER BY seq)")
+            .await
+            .unwrap();
+        let max = i64::MAX as f64;
+        assert_eq!(means[..2], [Some(max), Some(max)]);
+        assert!(means[2].unwrap().abs() < 1e4, "{means:?}");
+
+        let mut evaluator = RollingMeanEvaluator;
+        let unsigned: ArrayRef = Arc::new(UInt64Array::from(vec![u64::MAX, u64::MAX, 0]));
+        let means = evaluator.evaluate_all(&[unsigned], 3).unwrap();
+        let means: Vec<_> = means.as_primitive::<Float64Type>().iter().collect();
+        let max = u64::MAX as f64;
+        assert_eq!(means[..2], [Some(max), Some(max)]);
+        let expected = max * 2.0 / 3.0;
+        assert!((means[2].unwrap() - expected).abs() / expected < 1e-12);
+    }
+
+    #[test]
+    fn reused_evaluator_starts_from_scratch() {
+        let mut evaluator = RollingMeanEvaluator;
+        let window: ArrayRef = Arc::new(Int64Array::from(vec![3; 3]));
+        let first: ArrayRef = Arc::new(Int64Array::from(vec![100, 200, 300]));
+        let second: ArrayRef = Arc::new(Int64Array::from(vec![1, 2, 3]));
+
+        evaluator.evaluate_all(&[first, window.clone()], 3).unwrap();
+        let means = evaluator.evaluate_all(&[second, window], 3).unwrap();
+        let means: Vec<_> = means.as_primitive::<Float64Type>().iter().collect();
+        assert_eq!(means, [1.0, 1.5, 2.0].map(Some));
+    }
+
+    #[test]
+    fn parallel_means_match_sequential() {
+        let mut rng = StdRng::seed_from_u64(3);
+        // far from zero so that any difference in rounding shows
+        let values: Vec<Option<f64>> = (0..100_000)
+            .map(|_| rng.gen_bool(0.9).then(|| 1e9 + rng.gen_range(-50.0..50.0)))
+            .collect();
+
+        for mode in [NullMode::Skip, NullMode::Zero, NullMode::Propagate] {
+            for size in [1, 7, DEFAULT_WINDOW, 4_000] {
+                let nulls = NullTracker::new(mode, size);
+                let sequential = sequential_means(&values, size, nulls.clone());
+                for chunk in [1_000, 4_093, 30_000] {
+                    let chunked = chunked_means(&values, size, nulls.clone(), chunk);
+                    assert_eq!(chunked, sequential, "{mode:?} size {size} chunk {chunk}");
+                }
+                assert_eq!(parallel_means(&values, size, nulls), sequential);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn window_is_validated_while_planning() {
+        let ctx = context(vec![Some(1)]);
+        for call in [
+            "rolling_mean(value, 0)",
+            "rolling_mean(value, seq)",
+            "rolling_mean(value, 1.5)",
+            "rolling_mean(value, 2, 'bogus')",
+            "rolling_mean(value, 2, CAST(seq AS VARCHAR))",
+        ] {
+            let err = means(&ctx, &format!("{call} OVER (ORDER BY seq)"))
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("rolling_mean"), "{call}: {err}");
+        }
+    }
+}
@@ -0,0 +1,365 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{
+    cast::AsArray,
+    types::{Float64Type, Int64Type},
+    ArrayRef, Float64Array,
+};
+use arrow_schema::DataType;
+use datafusion::arrow::compute::cast;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, TypeSignature, Volatility, WindowUDFImpl},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::{
+    require_literal,
+    rolling::{NullMode, TimedWindow},
+    sessionize::nanos_per_unit,
+};
+
+/// Length of the interval in nanoseconds, written like `5m` or `1h 30m`
+pub fn interval_nanos(function: &str, interval: &ScalarValue) -> Result<i64> {
+    let invalid = |reason: String| {
+        DataFusionError::Plan(format!(
+            "{function} expects an interval like '5m', got {interval}: {reason}"
+        ))
+    };
+    let text = match interval {
+        ScalarValue::Utf8(Some(text)) | ScalarValue::LargeUtf8(Some(text)) => text,
+        _ => return Err(invalid("not a string".to_string())),
+    };
+    let duration =
+        humantime::parse_duration(text.trim()).map_err(|err| invalid(err.to_string()))?;
+    match i64::try_from(duration.as_nanos()) {
+        Ok(0) => Err(invalid("the interval must be positive".to_string())),
+        Ok(nanos) => Ok(nanos),
+        Err(_) => Err(invalid("the interval is too large".to_string())),
+    }
+}
+
+/// Checks the interval and NULL mode of a rolling_mean_within call while planning
+pub fn validate_args(args: &[Expr]) -> Result<()> {
+    require_literal("rolling_mean_within", args, 2)?;
+    require_literal("rolling_mean_within", args, 3)?;
+    if let Some(Expr::Literal(mode)) = args.get(3) {
+        NullMode::parse("rolling_mean_within", Some(mode))?;
+    }
+    match args.get(2) {
+        Some(Expr::Literal(interval)) => {
+            interval_nanos("rolling_mean_within", interval).map(|_| ())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// `rolling_mean_within(value, timestamp, interval [, null_mode])`
+///
+/// Mean of the non NULL values of the rows less than `interval` before the
+/// current row, up to and including it, however many rows that is. Unlike
+/// `rolling_mean` the window follows time, so irregular samples are weighed
+/// by when they happened rather than by their count.
+///
+/// The interval is a string such as `'5m'` or `'1h 30m'`. The window must be
+/// ordered by the timestamp, a row going back in time fails the query. Rows
+/// with a NULL timestamp get NULL and leave the window as it was.
+///
+/// `null_mode` works as for `rolling_mean`, except that `'propagate'`
+/// returns NULL while a NULL value is less than `interval` old.
+#[derive(Debug)]
+pub struct RollingMeanWithinUdf {
+    signature: Signature,
+}
+
+impl RollingMeanWithinUdf {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::one_of(
+                vec![TypeSignature::Any(3), TypeSignature::Any(4)],
+                Volatility::Immutable,
+            ),
+        }
+    }
+}
+
+impl WindowUDFImpl for RollingMeanWithinUdf {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "rolling_mean_within"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        if !arg_types[0].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "rolling_mean_within expects a numeric value, got {}",
+                arg_types[0]
+            )));
+        }
+        if !matches!(arg_types[1], DataType::Timestamp(_, _)) {
+            return Err(DataFusionError::Plan(format!(
+                "rolling_mean_within expects a timestamp, got {}",
+                arg_types[1]
+            )));
+        }
+        Ok(DataType::Float64)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(RollingMeanWithinEvaluator))
+    }
+}
+
+#[derive(Debug)]
+struct RollingMeanWithinEvaluator;
+
+impl PartitionEvaluator for RollingMeanWithinEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+        if num_rows == 0 {
+            return Ok(Arc::new(Float64Array::from(Vec::<f64>::new())));
+        }
+        let DataType::Timestamp(unit, _) = values[1].data_type() else {
+            return Err(DataFusionError::Execution(format!(
+                "rolling_mean_within expects a timestamp, got {}",
+                values[1].data_type()
+            )));
+        };
+        let scale = nanos_per_unit(unit);
+        // the raw value, casting to another timestamp type would shift zoned values
+        let timestamps = cast(&values[1], &DataType::Int64)?;
+        // the interval is a literal, every row carries the same value
+        let interval = interval_nanos(
+            "rolling_mean_within",
+            &ScalarValue::try_from_array(&values[2], 0)?,
+        )?;
+        let mode = values
+            .get(3)
+            .map(|mode| ScalarValue::try_from_array(mode, 0))
+            .transpose()?;
+        let mode = NullMode::parse("rolling_mean_within", mode.as_ref())?;
+
+        let input = cast(&values[0], &DataType::Float64)?;
+        let mut window = TimedWindow::new(interval);
+        let mut previous = i64::MIN;
+        // time of the latest NULL value, for Propagate
+        let mut last_null = None;
+        let means = input
+            .as_primitive::<Float64Type>()
+            .iter()
+            .zip(timestamps.as_primitive::<Int64Type>().iter())
+            .map(|(value, timestamp)| {
+                let Some(timestamp) = timestamp else {
+                    return Ok(None);
+                };
+                let timestamp = timestamp.saturating_mul(scale);
+                if timestamp < previous {
+                    return Err(DataFusionError::Execution(
+                        "rolling_mean_within requires the window to be ordered by the timestamp"
+                            .to_string(),
+                    ));
+                }
+                previous = timestamp;
+                let value = match (value, mode) {
+                    (None, NullMode::Zero) => Some(0.0),
+                    (None, NullMode::Propagate) => {
+                        last_null = Some(timestamp);
+                        None
+                    }
+                    (value, _) => value,
+                };
+                match value {
+                    Some(value) => window.push(timestamp, value),
+                    None => window.advance(timestamp),
+                }
+                let start = timestamp.saturating_sub(interval);
+                if last_null.is_some_and(|null| null > start) {
+                    return Ok(None);
+                }
+                Ok(window.stats().mean())
+            })
+            .collect::<Result<Float64Array>>()?;
+        Ok(Arc::new(means))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray, types::Float64Type, Int64Array, RecordBatch, TimestampMillisecondArray,
+    };
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use datafusion::{datasource::MemTable, logical_expr::WindowUDF, prelude::SessionContext};
+
+    use super::RollingMeanWithinUdf;
+    use crate::query::functions::add_analyzer_rules;
+
+    const MINUTE: i64 = 60_000;
+
+    fn context(rows: Vec<(Option<i64>, Option<i64>)>) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("seq", DataType::Int64, false),
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("value", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(0..rows.len() as i64)),
+                Arc::new(TimestampMillisecondArray::from_iter(
+                    rows.iter().map(|row| row.0),
+                )),
+                Arc::new(Int64Array::from_iter(rows.iter().map(|row| row.1))),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        ctx.register_udwf(WindowUDF::from(RollingMeanWithinUdf::new()));
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn means(
+        ctx: &SessionContext,
+        call: &str,
+    ) -> datafusion::error::Result<Vec<Option<f64>>> {
+        let sql = format!("SELECT {call} OVER (ORDER BY seq) FROM metrics ORDER BY seq");
+        let batches = ctx.sql(&sql).await?.collect().await?;
+        Ok(batches
+            .iter()
+            .flat_map(|batch| batch.column(0).as_primitive::<Float64Type>().iter())
+            .collect())
+    }
+
+    #[actix_web::test]
+    async fn window_follows_time_not_rows() {
+        // a burst of samples, then a gap longer than the interval
+        let ctx = context(vec![
+            (Some(0), Some(10)),
+            (Some(MINUTE), Some(20)),
+            (Some(MINUTE + 1), Some(30)),
+            (Some(MINUTE + 2), None),
+            (Some(5 * MINUTE), Some(40)),
+            (Some(5 * MINUTE + 30_000), Some(50)),
+            (None, Some(1000)),
+            (Some(20 * MINUTE), Some(60)),
+        ]);
+        let means = means(&ctx, "rolling_mean_within(value, p_timestamp, '5m')")
+            .await
+            .unwrap();
+        assert_eq!(
+            means,
+            [
+                Some(10.0),
+                Some(15.0),
+                Some(20.0),
+                Some(20.0),
+                // the first sample is exactly 5 minutes old and out of the window
+                Some(30.0),
+                Some(35.0),
+                None,
+                Some(60.0),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn null_modes() {
+        let ctx = context(vec![
+            (Some(0), Some(10)),
+            (Some(MINUTE), None),
+            (Some(2 * MINUTE), Some(20)),
+            (Some(7 * MINUTE), Some(30)),
+            (Some(8 * MINUTE), None),
+            (Some(9 * MINUTE), Some(60)),
+        ]);
+        let mut by_mode = vec![];
+        for mode in ["", ", 'skip'", ", 'zero'", ", 'propagate'"] {
+            let call = format!("rolling_mean_within(value, p_timestamp, '3m'{mode})");
+            by_mode.push(means(&ctx, &call).await.unwrap());
+        }
+        let skip = [10.0, 10.0, 15.0, 30.0, 30.0, 45.0].map(Some);
+        assert_eq!(by_mode[0], skip);
+        assert_eq!(by_mode[1], skip);
+        assert_eq!(by_mode[2], [10.0, 5.0, 10.0, 30.0, 15.0, 30.0].map(Some));
+        // the NULL at one minute has left the window by seven minutes
+        assert_eq!(by_mode[3], [Some(10.0), None, None, Some(30.0), None, None]);
+    }
+
+    #[actix_web::test]
+    async fn emptied_windows_are_null() {
+        // the only sample has aged out by the time of the NULL row
+        let ctx = context(vec![
+            (Some(0), None),
+            (Some(MINUTE), Some(10)),
+            (Some(10 * MINUTE), None),
+        ]);
+        let means = means(&ctx, "rolling_mean_within(value, p_timestamp, '5m')")
+            .await
+            .unwrap();
+        assert_eq!(means, [None, Some(10.0), None]);
+    }
+
+    #[actix_web::test]
+    async fn time_going_back_fails() {
+        let ctx = context(vec![(Some(MINUTE), Some(1)), (Some(0), Some(2))]);
+        let err = means(&ctx, "rolling_mean_within(value, p_timestamp, '1m')")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("ordered"), "{err}");
+    }
+
+    #[actix_web::test]
+    async fn interval_is_validated_while_planning() {
+        let ctx = context(vec![(Some(0), Some(1))]);
+        for call in [
+            "rolling_mean_within(value, p_timestamp, 'soon')",
+            "rolling_mean_within(value, p_timestamp, '0s')",
+            "rolling_mean_within(value, p_timestamp, 5)",
+            "rolling_mean_within(value, p_timestamp, CAST(seq AS VARCHAR))",
+            "rolling_mean_within(value, seq, '5m')",
+            "rolling_mean_within(value, p_timestamp, '5m', 'bogus')",
+        ] {
+            let err = means(&ctx, call).await.unwrap_err();
+            assert!(
+                err.to_string().contains("rolling_mean_within"),
+                "{call}: {err}"
+            );
+        }
+    }
+}
@@ -0,0 +1,297 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{cast::AsArray, types::Float64Type, ArrayRef, Float64Array};
+use arrow_schema::DataType;
+use datafusion::arrow::compute::cast;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, TypeSignature, Volatility, WindowUDFImpl},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::{
+    require_literal,
+    rolling::{NullMode, NullTracker, TrailingQuantile},
+    rolling_mean::{DEFAULT_WINDOW, MAX_WINDOW},
+};
+
+fn percentile_arg(percentile: &ScalarValue) -> Result<f64> {
+    match percentile.cast_to(&DataType::Float64) {
+        Ok(ScalarValue::Float64(Some(value))) if (0.0..=1.0).contains(&value) => Ok(value),
+        _ => Err(DataFusionError::Plan(format!(
+            "rolling_percentile expects a percentile between 0 and 1, got {percentile}"
+        ))),
+    }
+}
+
+fn window_arg(window: Option<&ScalarValue>) -> Result<usize> {
+    match window {
+        None => Ok(DEFAULT_WINDOW),
+        Some(ScalarValue::Int64(Some(window))) if (1..=MAX_WINDOW).contains(window) => {
+            Ok(*window as usize)
+        }
+        Some(other) => Err(DataFusionError::Plan(format!(
+            "rolling_percentile expects a window between 1 and {MAX_WINDOW}, got {other}"
+        ))),
+    }
+}
+
+/// Checks the percentile, window and NULL mode of a rolling_percentile call
+/// while planning
+pub fn validate_args(args: &[Expr]) -> Result<()> {
+    require_literal("rolling_percentile", args, 1)?;
+    require_literal("rolling_percentile", args, 2)?;
+    require_literal("rolling_percentile", args, 3)?;
+    if let Some(Expr::Literal(percentile)) = args.get(1) {
+        percentile_arg(percentile)?;
+    }
+    if let Some(Expr::Literal(mode)) = args.get(3) {
+        NullMode::parse("rolling_percentile", Some(mode))?;
+    }
+    match args.get(2) {
+        Some(Expr::Literal(window)) => window_arg(Some(window)).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// `rolling_percentile(value, percentile [, window [, null_mode]])`
+///
+/// Percentile (0 to 1) of the last `window` (default 300) non NULL values up
+/// to and including the current row in the window order, counted the same
+/// way as `rolling_mean`. Between two ranks the value is interpolated
+/// linearly, so 0.5 is the median and 1 the maximum.
+///
+/// Returns NULL until the partition has a non NULL value.
+#[derive(Debug)]
+pub struct RollingPercentileUdf {
+    signature: Signature,
+}
+
+impl RollingPercentileUdf {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::one_of(
+                vec![
+                    TypeSignature::Any(2),
+                    TypeSignature::Any(3),
+                    TypeSignature::Any(4),
+                ],
+                Volatility::Immutable,
+            ),
+        }
+    }
+}
+
+impl WindowUDFImpl for RollingPercentileUdf {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "rolling_percentile"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        if !arg_types[0].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "rolling_percentile expects a numeric value, got {}",
+                arg_types[0]
+            )));
+        }
+        if !arg_types[1].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "rolling_percentile expects a numeric percentile, got {}",
+                arg_types[1]
+            )));
+        }
+        if let Some(window) = arg_types.get(2).filter(|window| !window.is_integer()) {
+            return Err(DataFusionError::Plan(format!(
+                "rolling_percentile expects an integer window, got {window}"
+            )));
+        }
+        Ok(DataType::Float64)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(RollingPercentileEvaluator))
+    }
+}
+
+#[derive(Debug)]
+struct RollingPercentileEvaluator;
+
+impl PartitionEvaluator for RollingPercentileEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+        if num_rows == 0 {
+            return Ok(Arc::new(Float64Array::from(Vec::<f64>::new())));
+        }
+        // percentile, window and mode are literals, every row carries the same values
+        let percentile = percentile_arg(&ScalarValue::try_from_array(&values[1], 0)?)?;
+        let window = values
+            .get(2)
+            .map(|window| ScalarValue::try_from_array(window, 0))
+            .transpose()?;
+        let size = window_arg(window.as_ref())?;
+        let mode = values
+            .get(3)
+            .map(|mode| ScalarValue::try_from_array(mode, 0))
+            .transpose()?;
+        let mut nulls =
+            NullTracker::new(NullMode::parse("rolling_percentile", mode.as_ref())?, size);
+
+        let input = cast(&values[0], &DataType::Float64)?;
+        let mut window = TrailingQuantile::new(size);
+        let percentiles = input
+            .as_primitive::<Float64Type>()
+            .iter()
+            .map(|value| {
+                if let Some(value) = nulls.admit(value) {
+                    window.push(value);
+                }
+                nulls.result(window.quantile(percentile))
+            })
+            .collect::<Float64Array>();
+        Ok(Arc::new(percentiles))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{cast::AsArray, types::Float64Type, Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{datasource::MemTable, logical_expr::WindowUDF, prelude::SessionContext};
+
+    use super::RollingPercentileUdf;
+    use crate::query::functions::{
+        add_analyzer_rules, rolling_extrema::RollingMaxUdf, rolling_median::RollingMedianUdf,
+    };
+
+    fn context(values: Vec<Option<i64>>) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("seq", DataType::Int64, false),
+            Field::new("value", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(0..values.len() as i64)),
+                Arc::new(Int64Array::from(values)),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        ctx.register_udwf(WindowUDF::from(RollingPercentileUdf::new()));
+        ctx.register_udwf(WindowUDF::from(RollingMedianUdf::new()));
+        ctx.register_udwf(WindowUDF::from(RollingMaxUdf::new()));
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn evaluate(
+        ctx: &SessionContext,
+        call: &str,
+    ) -> datafusion::error::Result<Vec<Option<f64>>> {
+        let sql = format!("SELECT {call} OVER (ORDER BY seq) FROM metrics ORDER BY seq");
+        let batches = ctx.sql(&sql).await?.collect().await?;
+        Ok(batches
+            .iter()
+            .flat_map(|batch| batch.column(0).as_primitive::<Float64Type>().iter())
+            .collect())
+    }
+
+    const VALUES: [i64; 12] = [12, 3, 45, 7, 7, 30, 1, 18, 22, 9, 40, 5];
+
+    #[actix_web::test]
+    async fn p50_matches_median_and_p100_matches_max() {
+        let ctx = context(VALUES.map(Some).to_vec());
+        for window in [4, 5] {
+            let p50 = evaluate(&ctx, &format!("rolling_percentile(value, 0.5, {window})"))
+                .await
+                .unwrap();
+            let median = evaluate(&ctx, &format!("rolling_median(value, {window})"))
+                .await
+                .unwrap();
+            assert_eq!(p50, median, "window {window}");
+
+            let p100 = evaluate(&ctx, &format!("rolling_percentile(value, 1, {window})"))
+                .await
+                .unwrap();
+            let max = evaluate(&ctx, &format!("rolling_max(value, {window})"))
+                .await
+                .unwrap();
+            assert_eq!(p100, max, "window {window}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn p95_interpolates_between_ranks() {
+        let ctx = context(VALUES.map(Some).to_vec());
+        let p95 = evaluate(&ctx, "rolling_percentile(value, 0.95, 5)")
+            .await
+            .unwrap();
+
+        // the last window sorted is 5, 9, 18, 22, 40: rank 3.8 lies between 22 and 40
+        let last = p95.last().copied().flatten().unwrap();
+        assert!((last - (22.0 + 0.8 * 18.0)).abs() < 1e-9, "{last}");
+        // a single value is every percentile of itself
+        assert_eq!(p95[0], Some(12.0));
+        // 3 and 12 sorted, rank 0.95
+        assert!((p95[1].unwrap() - (3.0 + 0.95 * 9.0)).abs() < 1e-9);
+    }
+
+    #[actix_web::test]
+    async fn empty_windows_are_null() {
+        let ctx = context(vec![None, None, Some(8)]);
+        let p90 = evaluate(&ctx, "rolling_percentile(value, 0.9, 2)")
+            .await
+            .unwrap();
+        assert_eq!(p90, [None, None, Some(8.0)]);
+    }
+
+    #[actix_web::test]
+    async fn arguments_are_validated_while_planning() {
+        let ctx = context(vec![Some(1)]);
+        for call in [
+            "rolling_percentile(value, 1.5)",
+            "rolling_percentile(value, -0.1)",
+            "rolling_percentile(value, seq)",
+            "rolling_percentile(value, 0.5, 0)",
+            "rolling_percentile(value, 0.5, seq)",
+        ] {
+            let err = evaluate(&ctx, call).await.unwrap_err();
+            assert!(
+                err.to_string().contains("rolling_percentile"),
+                "{call}: {err}"
+            );
+        }
+    }
+}
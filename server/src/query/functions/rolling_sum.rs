@@ -0,0 +1,239 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{cast::AsArray, types::Float64Type, ArrayRef, Float64Array};
+use arrow_schema::DataType;
+use datafusion::arrow::compute::cast;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, TypeSignature, Volatility, WindowUDFImpl},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::{
+    require_literal,
+    rolling::{NullMode, NullTracker, TrailingWindow},
+    rolling_mean::{DEFAULT_WINDOW, MAX_WINDOW},
+};
+
+fn window_arg(window: Option<&ScalarValue>) -> Result<usize> {
+    match window {
+        None => Ok(DEFAULT_WINDOW),
+        Some(ScalarValue::Int64(Some(window))) if (1..=MAX_WINDOW).contains(window) => {
+            Ok(*window as usize)
+        }
+        Some(other) => Err(DataFusionError::Plan(format!(
+            "rolling_sum expects a window between 1 and {MAX_WINDOW}, got {other}"
+        ))),
+    }
+}
+
+/// Checks the window and NULL mode of a rolling_sum call while planning
+pub fn validate_args(args: &[Expr]) -> Result<()> {
+    require_literal("rolling_sum", args, 1)?;
+    require_literal("rolling_sum", args, 2)?;
+    if let Some(Expr::Literal(mode)) = args.get(2) {
+        NullMode::parse("rolling_sum", Some(mode))?;
+    }
+    match args.get(1) {
+        Some(Expr::Literal(window)) => window_arg(Some(window)).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// `rolling_sum(value [, window [, null_mode]])`
+///
+/// Sum of the last `window` (default 300) non NULL values up to and
+/// including the current row in the window order, counted the same way as
+/// `rolling_mean`.
+///
+/// Returns NULL until the partition has a non NULL value.
+#[derive(Debug)]
+pub struct RollingSumUdf {
+    signature: Signature,
+}
+
+impl RollingSumUdf {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::one_of(
+                vec![
+                    TypeSignature::Any(1),
+                    TypeSignature::Any(2),
+                    TypeSignature::Any(3),
+                ],
+                Volatility::Immutable,
+            ),
+        }
+    }
+}
+
+impl WindowUDFImpl for RollingSumUdf {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "rolling_sum"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        if !arg_types[0].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "rolling_sum expects a numeric value, got {}",
+                arg_types[0]
+            )));
+        }
+        if let Some(window) = arg_types.get(1).filter(|window| !window.is_integer()) {
+            return Err(DataFusionError::Plan(format!(
+                "rolling_sum expects an integer window, got {window}"
+            )));
+        }
+        Ok(DataType::Float64)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(RollingSumEvaluator))
+    }
+}
+
+#[derive(Debug)]
+struct RollingSumEvaluator;
+
+impl PartitionEvaluator for RollingSumEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+        if num_rows == 0 {
+            return Ok(Arc::new(Float64Array::from(Vec::<f64>::new())));
+        }
+        // window and mode are literals, every row carries the same values
+        let window = values
+            .get(1)
+            .map(|window| ScalarValue::try_from_array(window, 0))
+            .transpose()?;
+        let size = window_arg(window.as_ref())?;
+        let mode = values
+            .get(2)
+            .map(|mode| ScalarValue::try_from_array(mode, 0))
+            .transpose()?;
+        let mut nulls = NullTracker::new(NullMode::parse("rolling_sum", mode.as_ref())?, size);
+
+        let input = cast(&values[0], &DataType::Float64)?;
+        let mut window = TrailingWindow::new(size);
+        let sums = input
+            .as_primitive::<Float64Type>()
+            .iter()
+            .map(|value| {
+                if let Some(value) = nulls.admit(value) {
+                    window.push(value);
+                }
+                nulls.result(window.sum())
+            })
+            .collect::<Float64Array>();
+        Ok(Arc::new(sums))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{cast::AsArray, types::Float64Type, Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{datasource::MemTable, logical_expr::WindowUDF, prelude::SessionContext};
+
+    use super::RollingSumUdf;
+    use crate::query::functions::add_analyzer_rules;
+
+    fn context(values: Vec<Option<i64>>) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("seq", DataType::Int64, false),
+            Field::new("value", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(0..values.len() as i64)),
+                Arc::new(Int64Array::from(values)),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        ctx.register_udwf(WindowUDF::from(RollingSumUdf::new()));
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn sums(ctx: &SessionContext, call: &str) -> datafusion::error::Result<Vec<Option<f64>>> {
+        let sql = format!("SELECT {call} OVER (ORDER BY seq) FROM metrics ORDER BY seq");
+        let batches = ctx.sql(&sql).await?.collect().await?;
+        Ok(batches
+            .iter()
+            .flat_map(|batch| batch.column(0).as_primitive::<Float64Type>().iter())
+            .collect())
+    }
+
+    #[actix_web::test]
+    async fn sums_the_trailing_window() {
+        let ctx = context([4, 1, -2, 10, 3, 3].map(Some).to_vec());
+        let sums = sums(&ctx, "rolling_sum(value, 3)").await.unwrap();
+        assert_eq!(sums, [4.0, 5.0, 3.0, 9.0, 11.0, 16.0].map(Some));
+    }
+
+    #[actix_web::test]
+    async fn nulls_are_skipped() {
+        let ctx = context(vec![Some(1), None, Some(2), None, Some(3), Some(4)]);
+        let sums = sums(&ctx, "rolling_sum(value, 2)").await.unwrap();
+        assert_eq!(sums, [1.0, 1.0, 3.0, 3.0, 5.0, 7.0].map(Some));
+    }
+
+    #[actix_web::test]
+    async fn empty_window_is_null() {
+        let ctx = context(vec![None, None, Some(5)]);
+        let sums = sums(&ctx, "rolling_sum(value)").await.unwrap();
+        assert_eq!(sums, vec![None, None, Some(5.0)]);
+    }
+
+    #[actix_web::test]
+    async fn sums_past_the_integer_range() {
+        let ctx = context(vec![Some(i64::MAX), Some(i64::MAX), Some(i64::MIN)]);
+        let sums = sums(&ctx, "rolling_sum(value, 2)").await.unwrap();
+        let max = i64::MAX as f64;
+        assert_eq!(sums[..2], [Some(max), Some(max * 2.0)]);
+        assert!(sums[2].unwrap().abs() < 1e4, "{sums:?}");
+    }
+
+    #[actix_web::test]
+    async fn window_is_validated_while_planning() {
+        let ctx = context(vec![Some(1)]);
+        for call in ["rolling_sum(value, 0)", "rolling_sum(value, seq)"] {
+            let err = sums(&ctx, call).await.unwrap_err();
+            assert!(err.to_string().contains("rolling_sum"), "{call}: {err}");
+        }
+    }
+}
@@ -0,0 +1,397 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{
+    cast::AsArray,
+    types::{Int64Type, IntervalDayTimeType, IntervalMonthDayNanoType},
+    Array, ArrayRef, Int64Array,
+};
+use arrow_schema::{DataType, TimeUnit};
+use datafusion::arrow::compute::cast;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, TypeSignature, Volatility, WindowUDFImpl},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::require_literal;
+
+const NANOS_PER_DAY: i64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// How a row with a NULL timestamp is treated, either way the next row starts a new session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NullTimestamps {
+    /// The row gets a session of its own
+    NewSession,
+    /// The row gets a NULL session index
+    Null,
+}
+
+impl NullTimestamps {
+    fn parse(value: &ScalarValue) -> Result<Self> {
+        match value {
+            ScalarValue::Utf8(Some(mode)) if mode == "new_session" => Ok(Self::NewSession),
+            ScalarValue::Utf8(Some(mode)) if mode == "null" => Ok(Self::Null),
+            other => Err(DataFusionError::Plan(format!(
+                "sessionize expects NULL timestamp handling to be 'new_session' or 'null', got {other}"
+            ))),
+        }
+    }
+}
+
+/// Length of the inactivity gap in nanoseconds, calendar months are not fixed and rejected
+pub fn gap_nanos(gap: &ScalarValue) -> Result<i64> {
+    let nanos = match gap {
+        ScalarValue::IntervalMonthDayNano(Some(gap)) => {
+            match IntervalMonthDayNanoType::to_parts(*gap) {
+                (0, days, nanos) => days as i64 * NANOS_PER_DAY + nanos,
+                _ => {
+                    return Err(DataFusionError::Plan(
+                        "sessionize does not support gaps in months".to_string(),
+                    ))
+                }
+            }
+        }
+        ScalarValue::IntervalDayTime(Some(gap)) => {
+            let (days, millis) = IntervalDayTimeType::to_parts(*gap);
+            days as i64 * NANOS_PER_DAY + millis as i64 * 1_000_000
+        }
+        other => {
+            return Err(DataFusionError::Plan(format!(
+                "sessionize expects the gap to be an interval, got {other}"
+            )))
+        }
+    };
+    if nanos <= 0 {
+        return Err(DataFusionError::Plan(
+            "sessionize expects a positive gap".to_string(),
+        ));
+    }
+    Ok(nanos)
+}
+
+/// Checks the gap and NULL handling of a sessionize call while planning
+pub fn validate_args(args: &[Expr]) -> Result<()> {
+    for index in 1..args.len() {
+        require_literal("sessionize", args, index)?;
+    }
+    if let Some(Expr::Literal(gap)) = args.get(1) {
+        gap_nanos(gap)?;
+    }
+    if let Some(Expr::Literal(mode)) = args.get(2) {
+        NullTimestamps::parse(mode)?;
+    }
+    Ok(())
+}
+
+/// `sessionize(timestamp, gap [, 'new_session' | 'null'])`
+///
+/// Window function numbering sessions from 1 within each partition. A row
+/// starts a new session when more than `gap` passed since the previous row,
+/// a delta exactly equal to the gap stays in the session. Rows with a NULL
+/// timestamp get a NULL session by default or a session of their own with
+/// `'new_session'`, the row after them always starts a new session.
+///
+/// Sessions are assigned in a single pass, so the window must be ordered by
+/// the timestamp (`OVER (PARTITION BY user ORDER BY p_timestamp)`). Input
+/// going back in time fails the query instead of silently splitting sessions.
+#[derive(Debug)]
+pub struct Sessionize {
+    signature: Signature,
+}
+
+impl Sessionize {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::one_of(
+                vec![TypeSignature::Any(2), TypeSignature::Any(3)],
+                Volatility::Immutable,
+            ),
+        }
+    }
+}
+
+impl WindowUDFImpl for Sessionize {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "sessionize"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        match arg_types[0] {
+            DataType::Timestamp(_, _) => Ok(DataType::Int64),
+            ref other => Err(DataFusionError::Plan(format!(
+                "sessionize expects a timestamp, got {other}"
+            ))),
+        }
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(SessionizeEvaluator))
+    }
+}
+
+#[derive(Debug)]
+struct SessionizeEvaluator;
+
+/// Nanoseconds in one `unit` of a timestamp
+pub fn nanos_per_unit(unit: &TimeUnit) -> i64 {
+    match unit {
+        TimeUnit::Second => 1_000_000_000,
+        TimeUnit::Millisecond => 1_000_000,
+        TimeUnit::Microsecond => 1_000,
+        TimeUnit::Nanosecond => 1,
+    }
+}
+
+impl PartitionEvaluator for SessionizeEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+        if num_rows == 0 {
+            return Ok(Arc::new(Int64Array::from(Vec::<i64>::new())));
+        }
+        let DataType::Timestamp(unit, _) = values[0].data_type() else {
+            return Err(DataFusionError::Execution(format!(
+                "sessionize expects a timestamp, got {}",
+                values[0].data_type()
+            )));
+        };
+        let scale = nanos_per_unit(unit);
+        // the raw value, casting to another timestamp type would shift zoned values
+        let timestamps = cast(&values[0], &DataType::Int64)?;
+        let timestamps = timestamps.as_primitive::<Int64Type>();
+        let gap = gap_nanos(&ScalarValue::try_from_array(&values[1], 0)?)?;
+        let nulls = match values.get(2) {
+            Some(mode) => NullTimestamps::parse(&ScalarValue::try_from_array(mode, 0)?)?,
+            None => NullTimestamps::Null,
+        };
+
+        let mut session = 0i64;
+        let mut previous: Option<i64> = None;
+        let sessions = timestamps
+            .iter()
+            .map(|timestamp| match timestamp {
+                Some(timestamp) => {
+                    let timestamp = timestamp.saturating_mul(scale);
+                    match previous {
+                        Some(previous) if timestamp < previous => {
+                            return Err(DataFusionError::Execution(
+                                "sessionize requires the window to be ordered by the timestamp"
+                                    .to_string(),
+                            ))
+                        }
+                        Some(previous) if timestamp - previous <= gap => {}
+                        _ => session += 1,
+                    }
+                    previous = Some(timestamp);
+                    Ok(Some(session))
+                }
+                None => {
+                    previous = None;
+                    match nulls {
+                        NullTimestamps::NewSession => {
+                            session += 1;
+                            Ok(Some(session))
+                        }
+                        NullTimestamps::Null => Ok(None),
+                    }
+                }
+            })
+            .collect::<Result<Int64Array>>()?;
+        Ok(Arc::new(sessions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray,
+        types::{Int64Type, IntervalMonthDayNanoType},
+        ArrayRef, IntervalMonthDayNanoArray, RecordBatch, StringArray, TimestampSecondArray,
+    };
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use datafusion::{
+        datasource::MemTable,
+        logical_expr::{PartitionEvaluator, WindowUDF},
+        prelude::SessionContext,
+    };
+    use itertools::Itertools;
+
+    use super::{Sessionize, SessionizeEvaluator};
+    use crate::query::functions::add_analyzer_rules;
+
+    fn sessions(
+        timestamps: Vec<Option<i64>>,
+        gap_secs: i64,
+        nulls: Option<&str>,
+    ) -> Vec<Option<i64>> {
+        let len = timestamps.len();
+        let gap = IntervalMonthDayNanoArray::from(vec![
+            IntervalMonthDayNanoType::make_value(
+                0,
+                0,
+                gap_secs * 1_000_000_000
+            );
+            len
+        ]);
+        let mut values: Vec<ArrayRef> = vec![
+            Arc::new(TimestampSecondArray::from(timestamps)),
+            Arc::new(gap),
+        ];
+        if let Some(nulls) = nulls {
+            values.push(Arc::new(StringArray::from(vec![nulls; len])));
+        }
+        SessionizeEvaluator
+            .evaluate_all(&values, len)
+            .unwrap()
+            .as_primitive::<Int64Type>()
+            .iter()
+            .collect()
+    }
+
+    #[test]
+    fn gap_equal_to_threshold_stays_in_session() {
+        let result = sessions(
+            vec![Some(0), Some(60), Some(120), Some(181), Some(200)],
+            60,
+            None,
+        );
+        assert_eq!(result, vec![Some(1), Some(1), Some(1), Some(2), Some(2)]);
+    }
+
+    #[test]
+    fn null_timestamps_break_the_chain() {
+        let timestamps = vec![Some(0), None, Some(10), Some(20)];
+        assert_eq!(
+            sessions(timestamps.clone(), 60, None),
+            vec![Some(1), None, Some(2), Some(2)]
+        );
+        assert_eq!(
+            sessions(timestamps, 60, Some("new_session")),
+            vec![Some(1), Some(2), Some(3), Some(3)]
+        );
+    }
+
+    #[test]
+    fn unordered_input_is_rejected() {
+        let values: Vec<ArrayRef> = vec![
+            Arc::new(TimestampSecondArray::from(vec![100, 50])),
+            Arc::new(IntervalMonthDayNanoArray::from(vec![
+                IntervalMonthDayNanoType::make_value(0, 0, 1);
+                2
+            ])),
+        ];
+        let err = SessionizeEvaluator.evaluate_all(&values, 2).unwrap_err();
+        assert!(err.to_string().contains("ordered"), "{err}");
+    }
+
+    fn context() -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("user_id", DataType::Utf8, false),
+            Field::new("ts", DataType::Timestamp(TimeUnit::Second, None), true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec!["a", "b", "a", "b", "a", "b", "a"])),
+                Arc::new(TimestampSecondArray::from(vec![
+                    0, 0, 1800, 3000, 3600, 3100, 9000,
+                ])),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        ctx.register_udwf(WindowUDF::from(Sessionize::new()));
+        ctx.register_table(
+            "events",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    #[actix_web::test]
+    async fn sessions_per_partition_in_sql() {
+        let batches = context()
+            .sql(
+                "SELECT user_id, sessionize(ts, INTERVAL '30 minutes') \
+                 OVER (PARTITION BY user_id ORDER BY ts) AS session \
+                 FROM events ORDER BY user_id, ts",
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let rows = batches
+            .iter()
+            .flat_map(|batch| {
+                let users = batch.column(0).as_string::<i32>();
+                let sessions = batch.column(1).as_primitive::<Int64Type>();
+                users
+                    .iter()
+                    .zip(sessions.iter())
+                    .map(|(user, session)| (user.unwrap().to_string(), session.unwrap()))
+                    .collect_vec()
+            })
+            .collect_vec();
+        let expected = [
+            ("a", 1),
+            ("a", 1),
+            ("a", 1),
+            ("a", 2),
+            ("b", 1),
+            ("b", 2),
+            ("b", 2),
+        ];
+        assert_eq!(
+            rows,
+            expected
+                .iter()
+                .map(|(user, session)| (user.to_string(), *session))
+                .collect_vec()
+        );
+    }
+
+    #[actix_web::test]
+    async fn gap_is_validated_while_planning() {
+        let ctx = context();
+        for sql in [
+            "SELECT sessionize(ts, INTERVAL '1 month') OVER (ORDER BY ts) FROM events",
+            "SELECT sessionize(ts, ts - ts) OVER (ORDER BY ts) FROM events",
+            "SELECT sessionize(ts, INTERVAL '1 minute', 'drop') OVER (ORDER BY ts) FROM events",
+        ] {
+            let err = match ctx.sql(sql).await {
+                Ok(df) => df.collect().await.unwrap_err(),
+                Err(err) => err,
+            };
+            assert!(err.to_string().contains("sessionize"), "{sql}: {err}");
+        }
+    }
+}
@@ -0,0 +1,752 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{
+    cast::AsArray,
+    types::{
+        TimestampMicrosecondType, TimestampMillisecondType, TimestampNanosecondType,
+        TimestampSecondType,
+    },
+    Array, ArrayRef, PrimitiveArray,
+};
+use arrow_schema::{DataType, TimeUnit};
+use chrono::{
+    DateTime, Duration, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{
+        simplify::{ExprSimplifyResult, SimplifyInfo},
+        ColumnarValue, ScalarUDFImpl, Signature, TypeSignature, Volatility,
+    },
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::{literal_arg, num_rows, require_literal, to_columnar_value};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Bucket width parsed from literals like `5 minutes`, `1 day` or `1 week sunday`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketWidth {
+    seconds: i64,
+    week_start: Option<Weekday>,
+}
+
+impl BucketWidth {
+    /// Parses widths like `5 minutes`, `5m`, compound ones like `1 hour 30 minutes`
+    /// or `1h30m`, and ISO 8601 durations like `PT1H30M`. Week widths may be followed
+    /// by the weekday buckets start on.
+    pub fn parse(value: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            DataFusionError::Plan(format!("Invalid time_bucket interval {value}, {reason}"))
+        };
+
+        let trimmed = value.trim();
+        let compound = match trimmed.strip_prefix(['P', 'p']) {
+            Some(duration) => iso_duration(duration).map_err(invalid)?,
+            None => trimmed.to_string(),
+        };
+
+        let mut seconds: i64 = 0;
+        let mut week_start = None;
+        let mut rest = compound.as_str();
+        while !rest.is_empty() {
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            if digits == 0 {
+                if seconds == 0 {
+                    return Err(invalid("expected a count and a unit like 5 minutes"));
+                }
+                if seconds % (7 * SECONDS_PER_DAY) != 0 {
+                    return Err(invalid("only week buckets take a starting weekday"));
+                }
+                week_start = Some(
+                    rest.parse::<Weekday>()
+                        .map_err(|_| invalid("unknown starting weekday"))?,
+                );
+                break;
+            }
+
+            let (count, tail) = rest.split_at(digits);
+            let tail = tail.trim_start();
+            let unit_len = tail
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(tail.len());
+            if unit_len == 0 {
+                return Err(invalid("missing unit"));
+            }
+            let (unit, tail) = tail.split_at(unit_len);
+
+            let count: i64 = count
+                .parse()
+                .ok()
+                .filter(|count| *count > 0)
+                .ok_or_else(|| invalid("count must be a positive integer"))?;
+            let unit_seconds = match unit.to_ascii_lowercase().as_str() {
+                "s" | "sec" | "secs" | "second" | "seconds" => 1,
+                "m" | "min" | "mins" | "minute" | "minutes" => 60,
+                "h" | "hr" | "hrs" | "hour" | "hours" => 60 * 60,
+                "d" | "day" | "days" => SECONDS_PER_DAY,
+                "w" | "week" | "weeks" => 7 * SECONDS_PER_DAY,
+                _ => {
+                    return Err(invalid(
+                        "unit must be one of seconds, minutes, hours, days or weeks",
+                    ))
+                }
+            };
+            seconds = count
+                .checked_mul(unit_seconds)
+                .and_then(|part| seconds.checked_add(part))
+                .ok_or_else(|| invalid("interval is too large"))?;
+            rest = tail.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        }
+
+        if seconds == 0 {
+            return Err(invalid("expected a count and a unit like 5 minutes"));
+        }
+        Ok(Self {
+            seconds,
+            week_start,
+        })
+    }
+
+    fn is_week(&self) -> bool {
+        self.seconds % (7 * SECONDS_PER_DAY) == 0
+    }
+
+    // buckets are counted from the unix epoch, weeks from the first starting weekday after it
+    fn default_origin(&self) -> NaiveDateTime {
+        let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+        let origin = if self.is_week() {
+            let start = self.week_start.unwrap_or(Weekday::Mon);
+            // 1970-01-05 was a monday
+            epoch + Duration::days(4 + start.num_days_from_monday() as i64)
+        } else {
+            epoch
+        };
+        origin.and_hms_opt(0, 0, 0).unwrap()
+    }
+}
+
+// rewrites the part of an ISO 8601 duration after the P to the compact form,
+// P1DT12H to 1d12h. Years and months have no fixed width and are rejected
+fn iso_duration(duration: &str) -> std::result::Result<String, &'static str> {
+    let (date, time) = duration.split_once(['T', 't']).unwrap_or((duration, ""));
+    if date.contains(['Y', 'y', 'M', 'm']) {
+        return Err("years and months have no fixed width");
+    }
+    if date.is_empty() && time.is_empty() {
+        return Err("expected an ISO 8601 duration like PT5M");
+    }
+    Ok(format!("{date}{time}").to_ascii_lowercase())
+}
+
+/// Bucketing of instants into buckets of local wall clock time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucketer {
+    width: BucketWidth,
+    timezone: Option<Tz>,
+    origin: NaiveDateTime,
+}
+
+impl Bucketer {
+    pub fn new(width: &str, timezone: Option<&str>, origin: Option<&str>) -> Result<Self> {
+        let width = BucketWidth::parse(width)?;
+        let timezone = timezone
+            .map(|tz| {
+                tz.parse::<Tz>()
+                    .map_err(|_| DataFusionError::Plan(format!("Unknown timezone {tz}")))
+            })
+            .transpose()?;
+        let origin = match origin {
+            None => width.default_origin(),
+            Some(_) if width.week_start.is_some() => {
+                return Err(DataFusionError::Plan(
+                    "time_bucket takes either a starting weekday or an origin, not both"
+                        .to_string(),
+                ))
+            }
+            Some(origin) => parse_origin(origin)?,
+        };
+        Ok(Self {
+            width,
+            timezone,
+            origin,
+        })
+    }
+
+    /// Start of the bucket containing `instant`
+    pub fn bucket(&self, instant: DateTime<Utc>) -> DateTime<Utc> {
+        match self.timezone {
+            None => self.floor(instant.naive_utc()).and_utc(),
+            Some(tz) => {
+                let local = instant.with_timezone(&tz);
+                resolve(&tz, self.floor(local.naive_local()), local.offset().fix())
+            }
+        }
+    }
+
+    /// Start of the bucket after the one starting at `bucket`
+    pub fn next(&self, bucket: DateTime<Utc>) -> DateTime<Utc> {
+        // a local day is longer than a day of width when the clock moves back
+        let mut probe = bucket + Duration::seconds(self.width.seconds);
+        loop {
+            let next = self.bucket(probe);
+            if next > bucket {
+                return next;
+            }
+            probe += Duration::minutes(30);
+        }
+    }
+
+    // floors wall clock time, arithmetic on naive time ignores dst so days stay calendar days
+    fn floor(&self, local: NaiveDateTime) -> NaiveDateTime {
+        let since_origin = (local - self.origin).num_seconds();
+        self.origin
+            + Duration::seconds(since_origin.div_euclid(self.width.seconds) * self.width.seconds)
+    }
+}
+
+fn parse_origin(origin: &str) -> Result<NaiveDateTime> {
+    [
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(origin, format).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(origin, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+    })
+    .ok_or_else(|| {
+        DataFusionError::Plan(format!(
+            "Invalid time_bucket origin {origin}, expected a local time like 2024-01-01T00:00:00"
+        ))
+    })
+}
+
+// maps a local bucket start back to an instant. When the wall clock repeats, the
+// occurrence sharing the offset of the bucketed instant is used so each repeated
+// hour keeps its own bucket. A start skipped by the clock moving forward resolves
+// to the transition, the first instant that belongs to the bucket.
+fn resolve(tz: &Tz, local: NaiveDateTime, offset: chrono::FixedOffset) -> DateTime<Utc> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(start) => start.with_timezone(&Utc),
+        LocalResult::Ambiguous(earliest, latest) => {
+            if latest.offset().fix() == offset {
+                latest.with_timezone(&Utc)
+            } else {
+                earliest.with_timezone(&Utc)
+            }
+        }
+        LocalResult::None => {
+            let mut before = local;
+            let valid = loop {
+                before -= Duration::minutes(1);
+                if let Some(valid) = tz.from_local_datetime(&before).earliest() {
+                    break valid;
+                }
+            };
+            (valid + Duration::minutes(1)).with_timezone(&Utc)
+        }
+    }
+}
+
+/// `time_bucket(interval, timestamp [, timezone [, origin]])`
+///
+/// Aligns timestamps to the start of fixed width buckets of local time. The interval is a
+/// literal like `5 minutes`, `1h30m`, `PT1H` or `1 week sunday`, the timezone a name like
+/// `Europe/Berlin` and the origin a local time buckets are counted from. All but the
+/// timestamp must be literals.
+#[derive(Debug)]
+pub struct TimeBucket {
+    signature: Signature,
+}
+
+impl TimeBucket {
+    pub fn new() -> Self {
+        let units = [
+            TimeUnit::Second,
+            TimeUnit::Millisecond,
+            TimeUnit::Microsecond,
+            TimeUnit::Nanosecond,
+        ];
+        let signatures = units
+            .into_iter()
+            .flat_map(|unit| {
+                let ts = DataType::Timestamp(unit, None);
+                [
+                    vec![DataType::Utf8, ts.clone()],
+                    vec![DataType::Utf8, ts.clone(), DataType::Utf8],
+                    vec![DataType::Utf8, ts, DataType::Utf8, DataType::Utf8],
+                ]
+            })
+            .map(TypeSignature::Exact)
+            .collect();
+        Self {
+            signature: Signature::one_of(signatures, Volatility::Immutable),
+        }
+    }
+}
+
+fn literal_str(args: &[ColumnarValue], index: usize) -> Result<Option<&str>> {
+    match literal_arg("time_bucket", args, index)? {
+        Some(ScalarValue::Utf8(Some(value))) => Ok(Some(value)),
+        _ => Ok(None),
+    }
+}
+
+fn bucket_array<T>(array: &ArrayRef, bucketer: &Bucketer, per_second: i64) -> ArrayRef
+where
+    T: arrow_array::types::ArrowTimestampType,
+{
+    let array: &PrimitiveArray<T> = array.as_primitive();
+    let result: PrimitiveArray<T> = array.unary_opt(|value| {
+        let seconds = value.div_euclid(per_second);
+        let nanos = (value.rem_euclid(per_second) * (1_000_000_000 / per_second)) as u32;
+        let instant = DateTime::from_timestamp(seconds, nanos)?;
+        bucketer.bucket(instant).timestamp().checked_mul(per_second)
+    });
+    Arc::new(result)
+}
+
+impl ScalarUDFImpl for TimeBucket {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "time_bucket"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        Ok(arg_types[1].clone())
+    }
+
+    fn simplify(&self, args: Vec<Expr>, _info: &dyn SimplifyInfo) -> Result<ExprSimplifyResult> {
+        for index in [0, 2, 3] {
+            require_literal(self.name(), &args, index)?;
+        }
+        let literal = |index| match args.get(index) {
+            Some(Expr::Literal(ScalarValue::Utf8(Some(value)))) => Some(value.as_str()),
+            _ => None,
+        };
+        if let Some(width) = literal(0) {
+            Bucketer::new(width, literal(2), literal(3))?;
+        }
+        Ok(ExprSimplifyResult::Original(args))
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let timestamps = args[1].clone().into_array(num_rows(args))?;
+        let Some(width) = literal_str(args, 0)? else {
+            return to_columnar_value(
+                args,
+                arrow_array::new_null_array(timestamps.data_type(), timestamps.len()),
+            );
+        };
+        let bucketer = Bucketer::new(width, literal_str(args, 2)?, literal_str(args, 3)?)?;
+
+        let result = match timestamps.data_type() {
+            DataType::Timestamp(TimeUnit::Second, _) => {
+                bucket_array::<TimestampSecondType>(&timestamps, &bucketer, 1)
+            }
+            DataType::Timestamp(TimeUnit::Millisecond, _) => {
+                bucket_array::<TimestampMillisecondType>(&timestamps, &bucketer, 1_000)
+            }
+            DataType::Timestamp(TimeUnit::Microsecond, _) => {
+                bucket_array::<TimestampMicrosecondType>(&timestamps, &bucketer, 1_000_000)
+            }
+            DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+                bucket_array::<TimestampNanosecondType>(&timestamps, &bucketer, 1_000_000_000)
+            }
+            other => {
+                return Err(DataFusionError::Execution(format!(
+                    "time_bucket expects a timestamp, got {other}"
+                )))
+            }
+        };
+
+        to_columnar_value(args, result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray,
+        types::{Int64Type, TimestampMillisecondType},
+        RecordBatch, TimestampMillisecondArray,
+    };
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use chrono::{DateTime, Utc};
+    use datafusion::{datasource::MemTable, logical_expr::ScalarUDF, prelude::SessionContext};
+
+    use super::{BucketWidth, Bucketer, TimeBucket};
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        value.parse().unwrap()
+    }
+
+    fn buckets(bucketer: &Bucketer, instants: &[&str]) -> Vec<DateTime<Utc>> {
+        instants.iter().map(|i| bucketer.bucket(utc(i))).collect()
+    }
+
+    #[test]
+    fn fixed_width_buckets_in_utc() {
+        let bucketer = Bucketer::new("5 minutes", None, None).unwrap();
+        assert_eq!(
+            buckets(
+                &bucketer,
+                &["2024-05-01T10:04:59.999Z", "2024-05-01T10:05:00Z"]
+            ),
+            vec![utc("2024-05-01T10:00:00Z"), utc("2024-05-01T10:05:00Z")]
+        );
+
+        let bucketer = Bucketer::new("15m", None, Some("2024-01-01T00:07:00")).unwrap();
+        assert_eq!(
+            bucketer.bucket(utc("2024-05-01T10:00:00Z")),
+            utc("2024-05-01T09:52:00Z")
+        );
+    }
+
+    #[test]
+    fn interval_forms() {
+        let seconds = |value: &str| BucketWidth::parse(value).unwrap().seconds;
+        for value in [
+            "1 minute",
+            "1min",
+            "1m",
+            "  1   MINUTES ",
+            "60 seconds",
+            "PT1M",
+            "pt60s",
+        ] {
+            assert_eq!(seconds(value), 60, "{value}");
+        }
+        for value in [
+            "1 hour 30 minutes",
+            "1h30m",
+            "1h, 30m",
+            "PT1H30M",
+            "90 minutes",
+        ] {
+            assert_eq!(seconds(value), 90 * 60, "{value}");
+        }
+        assert_eq!(seconds("P1DT12H"), 36 * 60 * 60);
+        assert_eq!(seconds("P2W"), 14 * 24 * 60 * 60);
+        assert_eq!(seconds("7 days sunday"), 7 * 24 * 60 * 60);
+
+        for value in [
+            "",
+            "minutes",
+            "1 hour 30",
+            "P1M",
+            "P1Y",
+            "PT",
+            "1 day sunday",
+            "5 fortnights",
+        ] {
+            assert!(BucketWidth::parse(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn minute_and_hour_buckets_in_utc_ignore_dst() {
+        // New York moves its clocks forward at 07:00 UTC, buckets without a
+        // timezone stay evenly spaced through it
+        let minute = Bucketer::new("1 minute", None, None).unwrap();
+        assert_eq!(
+            buckets(
+                &minute,
+                &[
+                    "2024-03-10T06:59:59.999Z",
+                    "2024-03-10T07:00:00Z",
+                    "2024-03-10T07:00:59Z",
+                    "2024-03-10T07:01:00Z",
+                ]
+            ),
+            vec![
+                utc("2024-03-10T06:59:00Z"),
+                utc("2024-03-10T07:00:00Z"),
+                utc("2024-03-10T07:00:00Z"),
+                utc("2024-03-10T07:01:00Z"),
+            ]
+        );
+
+        let hour = Bucketer::new("1 hour", None, None).unwrap();
+        assert_eq!(
+            buckets(
+                &hour,
+                &[
+                    "2024-03-10T05:30:00Z",
+                    "2024-03-10T06:30:00Z",
+                    "2024-03-10T07:30:00Z",
+                    "2024-11-03T05:30:00Z",
+                    "2024-11-03T06:30:00Z",
+                ]
+            ),
+            vec![
+                utc("2024-03-10T05:00:00Z"),
+                utc("2024-03-10T06:00:00Z"),
+                utc("2024-03-10T07:00:00Z"),
+                utc("2024-11-03T05:00:00Z"),
+                utc("2024-11-03T06:00:00Z"),
+            ]
+        );
+
+        // an origin shifts the buckets, here to start 15 minutes past the hour
+        let offset = Bucketer::new("1 hour", None, Some("2024-01-01T00:15:00")).unwrap();
+        assert_eq!(
+            buckets(&offset, &["2024-03-10T07:10:00Z", "2024-03-10T07:20:00Z"]),
+            vec![utc("2024-03-10T06:15:00Z"), utc("2024-03-10T07:15:00Z")]
+        );
+    }
+
+    #[test]
+    fn hour_buckets_across_spring_forward() {
+        // 2024-03-10 02:00 EST jumps to 03:00 EDT in New York
+        let bucketer = Bucketer::new("1 hour", Some("America/New_York"), None).unwrap();
+        assert_eq!(
+            buckets(
+                &bucketer,
+                &[
+                    "2024-03-10T06:30:00Z", // 01:30 EST
+                    "2024-03-10T06:59:59Z", // 01:59:59 EST
+                    "2024-03-10T07:00:00Z", // 03:00 EDT
+                    "2024-03-10T07:10:00Z", // 03:10 EDT
+                ]
+            ),
+            vec![
+                utc("2024-03-10T06:00:00Z"),
+                utc("2024-03-10T06:00:00Z"),
+                utc("2024-03-10T07:00:00Z"),
+                utc("2024-03-10T07:00:00Z"),
+            ]
+        );
+
+        // the 02:00 bucket starts inside the skipped hour and begins at the transition
+        let bucketer = Bucketer::new("2 hours", Some("America/New_York"), None).unwrap();
+        assert_eq!(
+            buckets(&bucketer, &["2024-03-10T06:30:00Z", "2024-03-10T07:10:00Z"]),
+            vec![utc("2024-03-10T05:00:00Z"), utc("2024-03-10T07:00:00Z")]
+        );
+    }
+
+    #[test]
+    fn hour_buckets_across_fall_back() {
+        // 2024-11-03 01:00-02:00 happens twice in New York, once per offset
+        let bucketer = Bucketer::new("1 hour", Some("America/New_York"), None).unwrap();
+        assert_eq!(
+            buckets(
+                &bucketer,
+                &[
+                    "2024-11-03T05:30:00Z", // 01:30 EDT
+                    "2024-11-03T06:30:00Z", // 01:30 EST
+                    "2024-11-03T07:30:00Z", // 02:30 EST
+                ]
+            ),
+            vec![
+                utc("2024-11-03T05:00:00Z"),
+                utc("2024-11-03T06:00:00Z"),
+                utc("2024-11-03T07:00:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn day_buckets_follow_local_days() {
+        let bucketer = Bucketer::new("1 day", Some("America/New_York"), None).unwrap();
+        assert_eq!(
+            buckets(
+                &bucketer,
+                &[
+                    "2024-03-10T04:59:59Z", // 23:59:59 EST on the 9th
+                    "2024-03-10T05:00:00Z", // midnight EST on the 10th
+                    "2024-03-11T03:59:59Z", // 23:59:59 EDT on the 10th, a 23 hour day
+                    "2024-03-11T04:00:00Z", // midnight EDT on the 11th
+                ]
+            ),
+            vec![
+                utc("2024-03-09T05:00:00Z"),
+                utc("2024-03-10T05:00:00Z"),
+                utc("2024-03-10T05:00:00Z"),
+                utc("2024-03-11T04:00:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn week_buckets_start_on_chosen_weekday() {
+        // 2024-05-08 is a wednesday
+        let monday = Bucketer::new("1 week", Some("Europe/Berlin"), None).unwrap();
+        assert_eq!(
+            monday.bucket(utc("2024-05-08T12:00:00Z")),
+            utc("2024-05-05T22:00:00Z")
+        );
+
+        let sunday = Bucketer::new("1 week sunday", Some("Europe/Berlin"), None).unwrap();
+        assert_eq!(
+            sunday.bucket(utc("2024-05-08T12:00:00Z")),
+            utc("2024-05-04T22:00:00Z")
+        );
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert!(Bucketer::new("0 minutes", None, None).is_err());
+        assert!(Bucketer::new("5 fortnights", None, None).is_err());
+        assert!(Bucketer::new("1 day sunday", None, None).is_err());
+        assert!(Bucketer::new("1 hour", Some("Mars/Olympus"), None).is_err());
+        assert!(Bucketer::new("1 hour", None, Some("yesterday")).is_err());
+        assert!(Bucketer::new("1 week monday", None, Some("2024-01-01")).is_err());
+    }
+
+    #[actix_web::test]
+    async fn time_bucket_in_sql() {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "p_timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            true,
+        )]));
+        let timestamps = TimestampMillisecondArray::from(vec![
+            Some(utc("2024-03-10T04:59:59Z").timestamp_millis()),
+            Some(utc("2024-03-10T05:00:00Z").timestamp_millis()),
+            None,
+        ]);
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(timestamps)]).unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_udf(ScalarUDF::from(TimeBucket::new()));
+        ctx.register_table(
+            "logs",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+
+        let batches = ctx
+            .sql("SELECT time_bucket('1 day', p_timestamp, 'America/New_York') FROM logs")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let result: Vec<_> = batches[0]
+            .column(0)
+            .as_primitive::<TimestampMillisecondType>()
+            .iter()
+            .collect();
+        assert_eq!(
+            result,
+            vec![
+                Some(utc("2024-03-09T05:00:00Z").timestamp_millis()),
+                Some(utc("2024-03-10T05:00:00Z").timestamp_millis()),
+                None
+            ]
+        );
+
+        let err = ctx
+            .sql("SELECT time_bucket('1 day', p_timestamp, 'Nowhere/Special') FROM logs")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Unknown timezone"), "{err}");
+    }
+
+    #[actix_web::test]
+    async fn group_by_minute_bucket() {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "p_timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            true,
+        )]));
+        let timestamps = TimestampMillisecondArray::from_iter_values(
+            [
+                "2024-03-10T06:59:10Z",
+                "2024-03-10T06:59:50Z",
+                "2024-03-10T07:00:00Z",
+                "2024-03-10T07:00:30Z",
+                "2024-03-10T07:00:59.999Z",
+            ]
+            .map(|value| utc(value).timestamp_millis()),
+        );
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(timestamps)]).unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_udf(ScalarUDF::from(TimeBucket::new()));
+        ctx.register_table(
+            "logs",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+
+        let batches = ctx
+            .sql(
+                "SELECT time_bucket('1 minute', p_timestamp) AS minute, count(*) AS events \
+                 FROM logs GROUP BY time_bucket('1 minute', p_timestamp) ORDER BY minute",
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let minutes: Vec<_> = batches
+            .iter()
+            .flat_map(|batch| {
+                batch
+                    .column(0)
+                    .as_primitive::<TimestampMillisecondType>()
+                    .values()
+                    .to_vec()
+            })
+            .collect();
+        let events: Vec<_> = batches
+            .iter()
+            .flat_map(|batch| {
+                batch
+                    .column(1)
+                    .as_primitive::<Int64Type>()
+                    .values()
+                    .to_vec()
+            })
+            .collect();
+        assert_eq!(
+            minutes,
+            vec![
+                utc("2024-03-10T06:59:00Z").timestamp_millis(),
+                utc("2024-03-10T07:00:00Z").timestamp_millis(),
+            ]
+        );
+        assert_eq!(events, vec![2, 3]);
+    }
+}
@@ -0,0 +1,290 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Date functions of queries that are asked for in a time zone other than UTC
+
+use std::{any::Any, sync::Arc};
+
+use arrow_schema::DataType;
+use chrono_tz::Tz;
+use datafusion::{
+    common::{DFSchema, ExprSchema},
+    error::Result,
+    execution::{context::SessionState, FunctionRegistry},
+    logical_expr::{
+        simplify::{ExprSimplifyResult, SimplifyInfo},
+        ColumnarValue, FuncMonotonicity, LogicalPlan, ScalarUDF, ScalarUDFImpl, SetVariable,
+        Signature, Statement,
+    },
+    prelude::{Expr, SessionContext},
+    scalar::ScalarValue,
+};
+
+use crate::utils::arrow::with_time_zone;
+
+/// Functions that work on local dates, they see timestamps in the query's time zone
+const LOCAL_FUNCTIONS: [&str; 3] = ["date_trunc", "date_part", "time_bucket"];
+
+/// Planning state for queries in `time_zone`.
+///
+/// Sets the session time zone and makes `date_trunc`, `date_part` and
+/// `time_bucket` without a time zone of its own work on local dates, so that
+/// truncating to a day follows the local day. Timestamps keep their type and
+/// still hold UTC.
+pub async fn in_time_zone(state: SessionState, time_zone: Tz) -> Result<SessionState> {
+    let ctx = SessionContext::new_with_state(state);
+    ctx.execute_logical_plan(LogicalPlan::Statement(Statement::SetVariable(
+        SetVariable {
+            variable: "datafusion.execution.time_zone".to_owned(),
+            value: time_zone.name().to_owned(),
+            schema: Arc::new(DFSchema::empty()),
+        },
+    )))
+    .await?;
+
+    for name in LOCAL_FUNCTIONS {
+        let inner = ctx.udf(name)?;
+        ctx.register_udf(ScalarUDF::from(InTimeZone {
+            inner,
+            time_zone: time_zone.name().into(),
+        }));
+    }
+    Ok(ctx.state())
+}
+
+/// Wraps a date function so that it sees timestamps without a time zone, which
+/// hold UTC, in `time_zone`
+#[derive(Debug)]
+struct InTimeZone {
+    inner: Arc<ScalarUDF>,
+    time_zone: Arc<str>,
+}
+
+// labels the timestamp with a time zone, or removes the label when None
+fn relabel(value: &ColumnarValue, time_zone: Option<Arc<str>>) -> Result<ColumnarValue> {
+    Ok(match value {
+        ColumnarValue::Array(array) => ColumnarValue::Array(with_time_zone(array, time_zone)?),
+        ColumnarValue::Scalar(scalar) => {
+            let array = with_time_zone(&scalar.to_array()?, time_zone)?;
+            ColumnarValue::Scalar(ScalarValue::try_from_array(&array, 0)?)
+        }
+    })
+}
+
+impl ScalarUDFImpl for InTimeZone {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn signature(&self) -> &Signature {
+        self.inner.signature()
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        self.inner.inner().return_type(arg_types)
+    }
+
+    fn return_type_from_exprs(
+        &self,
+        args: &[Expr],
+        schema: &dyn ExprSchema,
+        arg_types: &[DataType],
+    ) -> Result<DataType> {
+        self.inner.return_type_from_exprs(args, schema, arg_types)
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        // time_bucket takes the time zone as its third argument
+        if self.inner.name() == "time_bucket" {
+            if args.len() != 2 {
+                return self.inner.invoke(args);
+            }
+            let time_zone = ScalarValue::Utf8(Some(self.time_zone.to_string()));
+            return self
+                .inner
+                .invoke(&[args[0].clone(), args[1].clone(), time_zone.into()]);
+        }
+
+        // date_trunc and date_part take the timestamp second
+        if !matches!(
+            args.get(1).map(ColumnarValue::data_type),
+            Some(DataType::Timestamp(_, None))
+        ) {
+            return self.inner.invoke(args);
+        }
+        let mut local = args.to_vec();
+        local[1] = relabel(&args[1], Some(self.time_zone.clone()))?;
+        // a truncated timestamp is returned without a time zone, like its input
+        relabel(&self.inner.invoke(&local)?, None)
+    }
+
+    fn aliases(&self) -> &[String] {
+        self.inner.aliases()
+    }
+
+    fn monotonicity(&self) -> Result<Option<FuncMonotonicity>> {
+        self.inner.monotonicity()
+    }
+
+    fn simplify(&self, args: Vec<Expr>, info: &dyn SimplifyInfo) -> Result<ExprSimplifyResult> {
+        self.inner.simplify(args, info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray,
+        types::{Float64Type, Int64Type, TimestampMillisecondType},
+        RecordBatch, TimestampMillisecondArray,
+    };
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use chrono::DateTime;
+    use datafusion::{datasource::MemTable, prelude::SessionContext};
+
+    use super::in_time_zone;
+    use crate::query::functions::{add_analyzer_rules, register_all};
+
+    fn millis(value: &str) -> i64 {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    // New York moved to daylight saving time at 2024-03-10T07:00:00Z
+    fn context() -> SessionContext {
+        let events = [
+            "2024-03-09T03:00:00Z",
+            "2024-03-09T06:00:00Z",
+            "2024-03-10T04:30:00Z",
+            "2024-03-10T05:30:00Z",
+            "2024-03-11T03:30:00Z",
+            "2024-03-11T04:30:00Z",
+        ];
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "p_timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            false,
+        )]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(TimestampMillisecondArray::from_iter_values(
+                events.map(millis),
+            ))],
+        )
+        .unwrap();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        register_all(&ctx);
+        ctx.register_table(
+            "events",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn daily_counts(ctx: &SessionContext) -> Vec<(i64, i64)> {
+        let sql = "SELECT date_trunc('day', p_timestamp) AS day, count(*) AS events \
+                   FROM events GROUP BY day ORDER BY day";
+        let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        batches
+            .iter()
+            .flat_map(|batch| {
+                let days = batch.column(0).as_primitive::<TimestampMillisecondType>();
+                let counts = batch.column(1).as_primitive::<Int64Type>();
+                days.values()
+                    .iter()
+                    .copied()
+                    .zip(counts.values().iter().copied())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn daily_groups_follow_local_days_across_dst() {
+        let ctx = context();
+        let utc = daily_counts(&ctx).await;
+        assert_eq!(
+            utc,
+            [
+                (millis("2024-03-09T00:00:00Z"), 2),
+                (millis("2024-03-10T00:00:00Z"), 2),
+                (millis("2024-03-11T00:00:00Z"), 2),
+            ]
+        );
+
+        let state = in_time_zone(ctx.state(), chrono_tz::America::New_York)
+            .await
+            .unwrap();
+        assert_eq!(
+            state.config_options().execution.time_zone.as_deref(),
+            Some("America/New_York")
+        );
+        let local = daily_counts(&SessionContext::new_with_state(state)).await;
+        // local midnight is 05:00 UTC in winter and 04:00 UTC once clocks moved
+        assert_eq!(
+            local,
+            [
+                (millis("2024-03-08T05:00:00Z"), 1),
+                (millis("2024-03-09T05:00:00Z"), 2),
+                (millis("2024-03-10T05:00:00Z"), 2),
+                (millis("2024-03-11T04:00:00Z"), 1),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn date_part_and_time_bucket_use_local_time() {
+        let state = in_time_zone(context().state(), chrono_tz::America::New_York)
+            .await
+            .unwrap();
+        let ctx = SessionContext::new_with_state(state);
+        let sql = "SELECT date_part('hour', p_timestamp), EXTRACT(day FROM p_timestamp), \
+                   time_bucket('1 day', p_timestamp), time_bucket('1 day', p_timestamp, 'UTC') \
+                   FROM events ORDER BY p_timestamp";
+        let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        let batch = &batches[0];
+
+        let hours: Vec<_> = batch
+            .column(0)
+            .as_primitive::<Float64Type>()
+            .values()
+            .to_vec();
+        assert_eq!(hours, [22.0, 1.0, 23.0, 0.0, 23.0, 0.0]);
+        let days: Vec<_> = batch
+            .column(1)
+            .as_primitive::<Float64Type>()
+            .values()
+            .to_vec();
+        assert_eq!(days, [8.0, 9.0, 9.0, 10.0, 10.0, 11.0]);
+
+        let buckets = batch.column(2).as_primitive::<TimestampMillisecondType>();
+        assert_eq!(buckets.value(0), millis("2024-03-08T05:00:00Z"));
+        assert_eq!(buckets.value(5), millis("2024-03-11T04:00:00Z"));
+        // an explicit time zone still wins
+        let utc_buckets = batch.column(3).as_primitive::<TimestampMillisecondType>();
+        assert_eq!(utc_buckets.value(0), millis("2024-03-09T00:00:00Z"));
+    }
+}
@@ -0,0 +1,534 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, cmp::Ordering, cmp::Reverse, collections::BinaryHeap, sync::Arc};
+
+use arrow_array::{
+    cast::AsArray,
+    types::{Float64Type, Int64Type},
+    Array, ArrayRef, Float64Array, Int64Array, ListArray, StringArray,
+};
+use arrow_schema::{DataType, Field};
+use datafusion::arrow::{buffer::OffsetBuffer, compute::cast};
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{Accumulator, AggregateUDFImpl, Signature, TypeSignature, Volatility},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::approx_top_k::{ApproxTopKAccumulator, MAX_K};
+use super::require_literal;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rank {
+    Largest,
+    Frequent,
+}
+
+impl Rank {
+    fn name(&self) -> &'static str {
+        match self {
+            Rank::Largest => "top_k",
+            Rank::Frequent => "top_k_frequent",
+        }
+    }
+}
+
+/// Checks the `k` of a top_k or top_k_frequent call while planning
+pub fn validate_args(rank: Rank, args: &[Expr]) -> Result<()> {
+    require_literal(rank.name(), args, 1)?;
+    match args.get(1) {
+        Some(Expr::Literal(k)) => k_arg(rank, k).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+fn k_arg(rank: Rank, k: &ScalarValue) -> Result<usize> {
+    match k {
+        ScalarValue::Int64(Some(k)) if (1..=MAX_K).contains(k) => Ok(*k as usize),
+        other => Err(DataFusionError::Plan(format!(
+            "{} expects k to be an integer between 1 and {MAX_K}, got {other}",
+            rank.name()
+        ))),
+    }
+}
+
+/// `top_k(value, k)` and `top_k_frequent(value, k)`
+///
+/// top_k returns the `k` largest values, duplicates included, largest first.
+/// top_k_frequent returns the `k` most frequent values, most frequent first,
+/// with counts estimated by the same SpaceSaving summary as approx_top_k.
+/// Both keep O(k) values per group and return fewer than `k` values when the
+/// group has fewer, NULLs are skipped.
+#[derive(Debug)]
+pub struct TopK {
+    rank: Rank,
+    signature: Signature,
+}
+
+impl TopK {
+    pub fn new(rank: Rank) -> Self {
+        let value_types = match rank {
+            Rank::Largest => vec![DataType::Int64, DataType::Float64, DataType::Utf8],
+            Rank::Frequent => vec![DataType::Int64, DataType::Utf8],
+        };
+        Self {
+            rank,
+            signature: Signature::one_of(
+                value_types
+                    .into_iter()
+                    .map(|value_type| TypeSignature::Exact(vec![value_type, DataType::Int64]))
+                    .collect(),
+                Volatility::Immutable,
+            ),
+        }
+    }
+
+    pub fn all() -> Vec<Self> {
+        vec![Self::new(Rank::Largest), Self::new(Rank::Frequent)]
+    }
+}
+
+fn list_of(data_type: DataType) -> DataType {
+    DataType::List(Arc::new(Field::new("item", data_type, true)))
+}
+
+fn list_scalar(values: ArrayRef) -> ScalarValue {
+    let field = Arc::new(Field::new("item", values.data_type().clone(), true));
+    let offsets = OffsetBuffer::from_lengths([values.len()]);
+    ScalarValue::List(Arc::new(ListArray::new(field, offsets, values, None)))
+}
+
+impl AggregateUDFImpl for TopK {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        self.rank.name()
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        Ok(list_of(arg_types[0].clone()))
+    }
+
+    fn accumulator(&self, arg: &DataType) -> Result<Box<dyn Accumulator>> {
+        // the return type, a list of the values
+        let value_type = match arg {
+            DataType::List(field) => field.data_type().clone(),
+            other => other.clone(),
+        };
+        Ok(match self.rank {
+            Rank::Largest => Box::new(TopKAccumulator::new(value_type)),
+            Rank::Frequent => Box::new(TopKFrequentAccumulator::new(value_type)),
+        })
+    }
+
+    fn state_type(&self, return_type: &DataType) -> Result<Vec<DataType>> {
+        Ok(match self.rank {
+            Rank::Largest => vec![DataType::Int64, return_type.clone()],
+            // the state of approx_top_k
+            Rank::Frequent => vec![
+                DataType::Int64,
+                list_of(DataType::Utf8),
+                list_of(DataType::Int64),
+                list_of(DataType::Int64),
+            ],
+        })
+    }
+}
+
+// a value of one of the supported types, ordered the way top_k ranks them
+#[derive(Debug, Clone)]
+enum Item {
+    Int(i64),
+    Float(f64),
+    Utf8(String),
+}
+
+impl Ord for Item {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Item::Int(a), Item::Int(b)) => a.cmp(b),
+            (Item::Float(a), Item::Float(b)) => a.total_cmp(b),
+            (Item::Utf8(a), Item::Utf8(b)) => a.cmp(b),
+            // an accumulator only ever holds values of one type
+            _ => unreachable!("top_k compares values of one type"),
+        }
+    }
+}
+
+impl PartialOrd for Item {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Item {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Item {}
+
+#[derive(Debug)]
+pub struct TopKAccumulator {
+    value_type: DataType,
+    k: Option<usize>,
+    // min heap of the largest values so far, its root is the first to go
+    heap: BinaryHeap<Reverse<Item>>,
+}
+
+impl TopKAccumulator {
+    fn new(value_type: DataType) -> Self {
+        Self {
+            value_type,
+            k: None,
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    fn init(&mut self, k: &ScalarValue) -> Result<()> {
+        let k = k_arg(Rank::Largest, k)?;
+        match self.k {
+            Some(current) if current != k => Err(DataFusionError::Plan(
+                "top_k expects k to be a constant".to_string(),
+            )),
+            _ => {
+                self.k = Some(k);
+                Ok(())
+            }
+        }
+    }
+
+    fn offer(&mut self, item: Item) {
+        let k = self.k.unwrap_or(0);
+        if self.heap.len() < k {
+            self.heap.push(Reverse(item));
+        } else if self.heap.peek().is_some_and(|Reverse(min)| item > *min) {
+            self.heap.pop();
+            self.heap.push(Reverse(item));
+        }
+    }
+
+    fn offer_all(&mut self, values: &ArrayRef) -> Result<()> {
+        match values.data_type() {
+            DataType::Int64 => values
+                .as_primitive::<Int64Type>()
+                .iter()
+                .flatten()
+                .for_each(|value| self.offer(Item::Int(value))),
+            DataType::Float64 => values
+                .as_primitive::<Float64Type>()
+                .iter()
+                .flatten()
+                .for_each(|value| self.offer(Item::Float(value))),
+            DataType::Utf8 => values
+                .as_string::<i32>()
+                .iter()
+                .flatten()
+                .for_each(|value| self.offer(Item::Utf8(value.to_string()))),
+            other => {
+                return Err(DataFusionError::Execution(format!(
+                    "top_k does not support input of type {other}"
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    // the kept values, largest first
+    fn values(&self) -> Result<ArrayRef> {
+        let mut items = self
+            .heap
+            .iter()
+            .map(|Reverse(item)| item.clone())
+            .collect::<Vec<_>>();
+        items.sort_unstable_by(|a, b| b.cmp(a));
+        let items = items.into_iter();
+        Ok(match self.value_type {
+            DataType::Int64 => {
+                Arc::new(Int64Array::from_iter_values(items.map(|item| match item {
+                    Item::Int(value) => value,
+                    _ => unreachable!("values are of the accumulator type"),
+                })))
+            }
+            DataType::Float64 => Arc::new(Float64Array::from_iter_values(items.map(
+                |item| match item {
+                    Item::Float(value) => value,
+                    _ => unreachable!("values are of the accumulator type"),
+                },
+            ))),
+            DataType::Utf8 => Arc::new(StringArray::from_iter_values(items.map(
+                |item| match item {
+                    Item::Utf8(value) => value,
+                    _ => unreachable!("values are of the accumulator type"),
+                },
+            ))),
+            ref other => {
+                return Err(DataFusionError::Execution(format!(
+                    "top_k does not support input of type {other}"
+                )))
+            }
+        })
+    }
+}
+
+impl Accumulator for TopKAccumulator {
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        if values[0].is_empty() {
+            return Ok(());
+        }
+        self.init(&ScalarValue::try_from_array(&values[1], 0)?)?;
+        self.offer_all(&values[0])
+    }
+
+    fn evaluate(&mut self) -> Result<ScalarValue> {
+        Ok(list_scalar(self.values()?))
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self
+                .heap
+                .iter()
+                .map(|Reverse(item)| match item {
+                    Item::Utf8(value) => std::mem::size_of::<Item>() + value.capacity(),
+                    _ => std::mem::size_of::<Item>(),
+                })
+                .sum::<usize>()
+    }
+
+    fn state(&mut self) -> Result<Vec<ScalarValue>> {
+        Ok(vec![
+            ScalarValue::Int64(self.k.map(|k| k as i64)),
+            list_scalar(self.values()?),
+        ])
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        let ks = states[0].as_primitive::<Int64Type>();
+        let values = states[1].as_list::<i32>();
+        for row in 0..ks.len() {
+            if ks.is_null(row) {
+                continue;
+            }
+            self.init(&ScalarValue::Int64(Some(ks.value(row))))?;
+            self.offer_all(&values.value(row))?;
+        }
+        Ok(())
+    }
+}
+
+/// approx_top_k returning the values alone, in the type they came in
+#[derive(Debug)]
+pub struct TopKFrequentAccumulator {
+    value_type: DataType,
+    summary: ApproxTopKAccumulator,
+}
+
+impl TopKFrequentAccumulator {
+    fn new(value_type: DataType) -> Self {
+        Self {
+            value_type,
+            summary: ApproxTopKAccumulator::default(),
+        }
+    }
+}
+
+impl Accumulator for TopKFrequentAccumulator {
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        if values[0].is_empty() {
+            return Ok(());
+        }
+        k_arg(Rank::Frequent, &ScalarValue::try_from_array(&values[1], 0)?)?;
+        self.summary.update_batch(values)
+    }
+
+    fn evaluate(&mut self) -> Result<ScalarValue> {
+        let ScalarValue::List(top) = self.summary.evaluate()? else {
+            unreachable!("approx_top_k returns a list")
+        };
+        let entries = top.value(0);
+        let values = cast(entries.as_struct().column(0), &self.value_type)?;
+        Ok(list_scalar(values))
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self) + self.summary.size()
+    }
+
+    fn state(&mut self) -> Result<Vec<ScalarValue>> {
+        self.summary.state()
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        self.summary.merge_batch(states)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray,
+        types::{Float64Type, Int64Type},
+        ArrayRef, Float64Array, Int64Array, RecordBatch, StringArray,
+    };
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{
+        datasource::MemTable,
+        logical_expr::{Accumulator, AggregateUDF},
+        prelude::SessionContext,
+        scalar::ScalarValue,
+    };
+    use itertools::Itertools;
+
+    use super::{TopK, TopKAccumulator};
+    use crate::query::functions::add_analyzer_rules;
+
+    fn context(batch: RecordBatch) -> SessionContext {
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        for top_k in TopK::all() {
+            ctx.register_udaf(AggregateUDF::from(top_k));
+        }
+        ctx.register_table(
+            "logs",
+            Arc::new(MemTable::try_new(batch.schema(), vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    fn latencies() -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("latency", DataType::Float64, true),
+            Field::new("status", DataType::Int64, true),
+        ]));
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec![
+                    "a", "a", "a", "a", "a", "a", "b", "b",
+                ])),
+                Arc::new(Float64Array::from(vec![
+                    Some(12.5),
+                    Some(3.0),
+                    None,
+                    Some(40.0),
+                    Some(12.5),
+                    Some(7.25),
+                    Some(1.0),
+                    Some(2.0),
+                ])),
+                Arc::new(Int64Array::from(vec![
+                    200, 500, 200, 404, 200, 500, 200, 200,
+                ])),
+            ],
+        )
+        .unwrap()
+    }
+
+    async fn rows(ctx: &SessionContext, sql: &str) -> Vec<RecordBatch> {
+        ctx.sql(sql).await.unwrap().collect().await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn top_3_per_group() {
+        let ctx = context(latencies());
+        let batches = rows(
+            &ctx,
+            "SELECT host, top_k(latency, 3) FROM logs GROUP BY host ORDER BY host",
+        )
+        .await;
+        let batch = &batches[0];
+        let tops = batch.column(1).as_list::<i32>();
+        let top = |row: usize| {
+            tops.value(row)
+                .as_primitive::<Float64Type>()
+                .values()
+                .to_vec()
+        };
+        // the repeated 12.5 counts twice, the NULL not at all
+        assert_eq!(top(0), [40.0, 12.5, 12.5]);
+        // fewer rows than k
+        assert_eq!(top(1), [2.0, 1.0]);
+    }
+
+    #[actix_web::test]
+    async fn most_frequent() {
+        let ctx = context(latencies());
+        let batches = rows(&ctx, "SELECT top_k_frequent(status, 2) FROM logs").await;
+        let top = batches[0].column(0).as_list::<i32>().value(0);
+        assert_eq!(
+            top.as_primitive::<Int64Type>().values().to_vec(),
+            [200, 500]
+        );
+    }
+
+    #[actix_web::test]
+    async fn k_must_be_positive() {
+        let ctx = context(latencies());
+        for call in [
+            "top_k(latency, 0)",
+            "top_k(latency, -2)",
+            "top_k_frequent(status, 0)",
+            "top_k(latency, status)",
+        ] {
+            let err = match ctx.sql(&format!("SELECT {call} FROM logs")).await {
+                Ok(df) => df.collect().await.unwrap_err(),
+                Err(err) => err,
+            };
+            assert!(err.to_string().contains("top_k"), "{call}: {err}");
+        }
+    }
+
+    #[test]
+    fn merged_partial_states_keep_the_largest() {
+        let update = |values: Vec<i64>| {
+            let mut acc = TopKAccumulator::new(DataType::Int64);
+            let k: ArrayRef = Arc::new(Int64Array::from(vec![3; values.len()]));
+            acc.update_batch(&[Arc::new(Int64Array::from(values)), k])
+                .unwrap();
+            acc.state()
+                .unwrap()
+                .iter()
+                .map(|state| state.to_array().unwrap())
+                .collect_vec()
+        };
+        let mut merged = TopKAccumulator::new(DataType::Int64);
+        merged.merge_batch(&update(vec![5, 1, 9, 2])).unwrap();
+        merged.merge_batch(&update(vec![7, 8])).unwrap();
+
+        let ScalarValue::List(top) = merged.evaluate().unwrap() else {
+            panic!("top_k returns a list")
+        };
+        assert_eq!(
+            top.value(0).as_primitive::<Int64Type>().values().to_vec(),
+            [9, 8, 7]
+        );
+    }
+}
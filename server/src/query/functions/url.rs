@@ -0,0 +1,328 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{builder::StringBuilder, cast::AsArray, Array, ArrayRef};
+use arrow_schema::DataType;
+use datafusion::{
+    error::Result,
+    logical_expr::{ColumnarValue, ScalarUDFImpl, Signature, TypeSignature, Volatility},
+};
+use once_cell::sync::Lazy;
+use url::{Host, Url};
+
+use super::{num_rows, to_columnar_value};
+
+// base used to resolve relative urls like "/search?q=x", only path and query are read back from it
+static RELATIVE_BASE: Lazy<Url> = Lazy::new(|| Url::parse("http://relative.invalid").unwrap());
+
+/// Url parsed from a column value, relative urls keep no scheme and no host
+struct ParsedUrl {
+    url: Url,
+    relative: bool,
+    scheme_relative: bool,
+}
+
+impl ParsedUrl {
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if value.is_empty() {
+            return None;
+        }
+
+        match Url::parse(value) {
+            Ok(url) => Some(Self {
+                url,
+                relative: false,
+                scheme_relative: false,
+            }),
+            Err(url::ParseError::RelativeUrlWithoutBase) => {
+                RELATIVE_BASE.join(value).ok().map(|url| Self {
+                    url,
+                    relative: true,
+                    scheme_relative: value.starts_with("//"),
+                })
+            }
+            Err(_) => None,
+        }
+    }
+
+    fn scheme(&self) -> Option<&str> {
+        (!self.relative).then(|| self.url.scheme())
+    }
+
+    // ipv6 hosts are returned without the surrounding brackets
+    fn host(&self) -> Option<String> {
+        if self.relative && !self.scheme_relative {
+            return None;
+        }
+        match self.url.host()? {
+            Host::Domain(domain) => Some(domain.to_string()),
+            Host::Ipv4(addr) => Some(addr.to_string()),
+            Host::Ipv6(addr) => Some(addr.to_string()),
+        }
+    }
+
+    fn path(&self) -> Option<&str> {
+        (!self.url.cannot_be_a_base()).then(|| self.url.path())
+    }
+
+    // value of the first occurrence of `name`, percent decoded
+    fn query_param(&self, name: &str) -> Option<String> {
+        self.url
+            .query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    }
+}
+
+/// Which part of the url to extract
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UrlPart {
+    Scheme,
+    Host,
+    Path,
+    QueryParam,
+}
+
+impl UrlPart {
+    fn name(&self) -> &'static str {
+        match self {
+            UrlPart::Scheme => "url_scheme",
+            UrlPart::Host => "url_host",
+            UrlPart::Path => "url_path",
+            UrlPart::QueryParam => "url_query_param",
+        }
+    }
+}
+
+/// `url_scheme(url)`, `url_host(url)`, `url_path(url)` and `url_query_param(url, name)`
+///
+/// Extracts a component from a url column. Relative urls like `/search?q=x` have
+/// a path and query but no scheme or host. Unparseable values evaluate to NULL.
+#[derive(Debug)]
+pub struct UrlExtract {
+    part: UrlPart,
+    signature: Signature,
+}
+
+impl UrlExtract {
+    pub fn new(part: UrlPart) -> Self {
+        let args = match part {
+            UrlPart::QueryParam => vec![DataType::Utf8, DataType::Utf8],
+            _ => vec![DataType::Utf8],
+        };
+        Self {
+            part,
+            signature: Signature::one_of(vec![TypeSignature::Exact(args)], Volatility::Immutable),
+        }
+    }
+
+    pub fn all() -> [Self; 4] {
+        [
+            UrlPart::Scheme,
+            UrlPart::Host,
+            UrlPart::Path,
+            UrlPart::QueryParam,
+        ]
+        .map(Self::new)
+    }
+}
+
+impl ScalarUDFImpl for UrlExtract {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        self.part.name()
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Utf8)
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let rows = num_rows(args);
+        let urls = args[0].clone().into_array(rows)?;
+        let urls = urls.as_string::<i32>();
+        let mut builder = StringBuilder::with_capacity(urls.len(), 0);
+
+        match self.part {
+            UrlPart::Scheme => urls.iter().for_each(|url| {
+                builder.append_option(
+                    url.and_then(ParsedUrl::parse)
+                        .as_ref()
+                        .and_then(ParsedUrl::scheme),
+                )
+            }),
+            UrlPart::Host => urls.iter().for_each(|url| {
+                builder.append_option(url.and_then(ParsedUrl::parse).and_then(|url| url.host()))
+            }),
+            UrlPart::Path => urls.iter().for_each(|url| {
+                builder.append_option(
+                    url.and_then(ParsedUrl::parse)
+                        .as_ref()
+                        .and_then(ParsedUrl::path),
+                )
+            }),
+            UrlPart::QueryParam => {
+                let names = args[1].clone().into_array(rows)?;
+                let names = names.as_string::<i32>();
+                urls.iter().zip(names.iter()).for_each(|(url, name)| {
+                    builder.append_option(
+                        url.zip(name)
+                            .and_then(|(url, name)| ParsedUrl::parse(url)?.query_param(name)),
+                    )
+                })
+            }
+        }
+
+        to_columnar_value(args, Arc::new(builder.finish()) as ArrayRef)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{cast::AsArray, ArrayRef, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{datasource::MemTable, logical_expr::ScalarUDF, prelude::SessionContext};
+
+    use super::UrlExtract;
+
+    const URLS: [Option<&str>; 8] = [
+        Some("https://example.com:8443/api/v1/query?stream=app&q=a%20b&stream=other#top"),
+        Some("http://[2001:db8::1]:8000/logstream?x=1"),
+        Some("/search?q=rust+lang&page=2"),
+        Some("//cdn.example.com/assets/app.js"),
+        Some("http://[::1"),
+        Some("not a url at all"),
+        Some(""),
+        None,
+    ];
+
+    async fn query(sql: &str) -> Vec<Option<String>> {
+        let schema = Arc::new(Schema::new(vec![Field::new("url", DataType::Utf8, true)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(StringArray::from(URLS.to_vec()))],
+        )
+        .unwrap();
+        let ctx = SessionContext::new();
+        for udf in UrlExtract::all() {
+            ctx.register_udf(ScalarUDF::from(udf));
+        }
+        ctx.register_table(
+            "access",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+
+        let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        let column: &ArrayRef = batches[0].column(0);
+        column
+            .as_string::<i32>()
+            .iter()
+            .map(|v| v.map(str::to_string))
+            .collect()
+    }
+
+    fn expected(values: [Option<&str>; 8]) -> Vec<Option<String>> {
+        values.iter().map(|v| v.map(str::to_string)).collect()
+    }
+
+    #[actix_web::test]
+    async fn scheme_and_host() {
+        assert_eq!(
+            query("SELECT url_scheme(url) FROM access").await,
+            expected([
+                Some("https"),
+                Some("http"),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None
+            ])
+        );
+        assert_eq!(
+            query("SELECT url_host(url) FROM access").await,
+            expected([
+                Some("example.com"),
+                Some("2001:db8::1"),
+                None,
+                Some("cdn.example.com"),
+                None,
+                None,
+                None,
+                None
+            ])
+        );
+    }
+
+    #[actix_web::test]
+    async fn path() {
+        assert_eq!(
+            query("SELECT url_path(url) FROM access").await,
+            expected([
+                Some("/api/v1/query"),
+                Some("/logstream"),
+                Some("/search"),
+                Some("/assets/app.js"),
+                None,
+                Some("/not%20a%20url%20at%20all"),
+                None,
+                None
+            ])
+        );
+    }
+
+    #[actix_web::test]
+    async fn query_params() {
+        // repeated parameters return the first value, values are percent decoded
+        assert_eq!(
+            query("SELECT url_query_param(url, 'stream') FROM access").await,
+            expected([Some("app"), None, None, None, None, None, None, None])
+        );
+        assert_eq!(
+            query("SELECT url_query_param(url, 'q') FROM access").await,
+            expected([
+                Some("a b"),
+                None,
+                Some("rust lang"),
+                None,
+                None,
+                None,
+                None,
+                None
+            ])
+        );
+        assert_eq!(
+            query("SELECT url_query_param(url, 'missing') FROM access").await,
+            expected([None; 8])
+        );
+    }
+}
@@ -0,0 +1,313 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{
+    any::Any,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use arrow_array::{builder::StringBuilder, cast::AsArray, Array, ArrayRef};
+use arrow_schema::DataType;
+use datafusion::{
+    error::Result,
+    logical_expr::{ColumnarValue, ScalarUDFImpl, Signature, Volatility},
+};
+use lru::LruCache;
+use woothee::parser::Parser;
+
+use super::{num_rows, to_columnar_value};
+
+// distinct user agents remembered, real traffic is dominated by a few thousand agents
+const UA_CACHE_CAPACITY: usize = 10_000;
+const OTHER: &str = "Other";
+
+/// Browser, os and device of a user agent
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserAgent {
+    pub browser: String,
+    pub os: String,
+    pub device: String,
+}
+
+/// User agent parser shared by the ua_* functions, memoizing results per raw agent string
+pub struct UaParser {
+    parser: Parser,
+    cache: Mutex<LruCache<String, Arc<UserAgent>>>,
+    hits: AtomicU64,
+}
+
+impl UaParser {
+    pub fn new() -> Self {
+        let parser = Parser::new();
+        // the agent database is built lazily on first use, load it now instead of in the first query
+        parser.parse("Mozilla/5.0");
+        Self {
+            parser,
+            cache: Mutex::new(LruCache::new(
+                NonZeroUsize::new(UA_CACHE_CAPACITY).expect("non zero capacity"),
+            )),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    pub fn parse(&self, agent: &str) -> Arc<UserAgent> {
+        if let Some(parsed) = self.cache.lock().unwrap().get(agent) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return parsed.clone();
+        }
+
+        let parsed = Arc::new(self.parse_uncached(agent));
+        self.cache
+            .lock()
+            .unwrap()
+            .put(agent.to_string(), parsed.clone());
+        parsed
+    }
+
+    /// Number of lookups answered from the cache
+    pub fn cache_hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    fn parse_uncached(&self, agent: &str) -> UserAgent {
+        let known = |value: &str| match value {
+            "" | "UNKNOWN" => OTHER.to_string(),
+            value => value.to_string(),
+        };
+
+        match self.parser.parse(agent) {
+            Some(result) => UserAgent {
+                browser: known(result.name),
+                os: known(result.os),
+                device: match result.category {
+                    "pc" => "Desktop",
+                    "smartphone" => "Smartphone",
+                    "mobilephone" => "Mobile Phone",
+                    "appliance" => "Appliance",
+                    "crawler" => "Bot",
+                    _ => OTHER,
+                }
+                .to_string(),
+            },
+            None => UserAgent {
+                browser: OTHER.to_string(),
+                os: OTHER.to_string(),
+                device: OTHER.to_string(),
+            },
+        }
+    }
+}
+
+impl std::fmt::Debug for UaParser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UaParser")
+            .field("cache_hits", &self.cache_hits())
+            .finish()
+    }
+}
+
+/// Which part of the user agent to extract
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UaPart {
+    Browser,
+    Os,
+    Device,
+}
+
+impl UaPart {
+    fn name(&self) -> &'static str {
+        match self {
+            UaPart::Browser => "ua_browser",
+            UaPart::Os => "ua_os",
+            UaPart::Device => "ua_device",
+        }
+    }
+
+    fn get<'a>(&self, agent: &'a UserAgent) -> &'a str {
+        match self {
+            UaPart::Browser => &agent.browser,
+            UaPart::Os => &agent.os,
+            UaPart::Device => &agent.device,
+        }
+    }
+}
+
+/// `ua_browser(user_agent)`, `ua_os(user_agent)` and `ua_device(user_agent)`
+///
+/// Unrecognised agents evaluate to `Other` so they group together, NULL stays NULL.
+#[derive(Debug)]
+pub struct UaExtract {
+    part: UaPart,
+    parser: Arc<UaParser>,
+    signature: Signature,
+}
+
+impl UaExtract {
+    pub fn new(part: UaPart, parser: Arc<UaParser>) -> Self {
+        Self {
+            part,
+            parser,
+            signature: Signature::exact(vec![DataType::Utf8], Volatility::Immutable),
+        }
+    }
+
+    pub fn all(parser: Arc<UaParser>) -> [Self; 3] {
+        [UaPart::Browser, UaPart::Os, UaPart::Device].map(|part| Self::new(part, parser.clone()))
+    }
+}
+
+impl ScalarUDFImpl for UaExtract {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        self.part.name()
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Utf8)
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let agents = args[0].clone().into_array(num_rows(args))?;
+        let agents = agents.as_string::<i32>();
+        let mut builder = StringBuilder::with_capacity(agents.len(), 0);
+
+        for agent in agents.iter() {
+            match agent {
+                Some(agent) => builder.append_value(self.part.get(&self.parser.parse(agent))),
+                None => builder.append_null(),
+            }
+        }
+
+        to_columnar_value(args, Arc::new(builder.finish()) as ArrayRef)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{cast::AsArray, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{datasource::MemTable, logical_expr::ScalarUDF, prelude::SessionContext};
+
+    use super::{UaExtract, UaParser};
+
+    const CHROME_WINDOWS: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+    const SAFARI_IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.1 Mobile/15E148 Safari/604.1";
+    const FIREFOX_LINUX: &str =
+        "Mozilla/5.0 (X11; Linux x86_64; rv:121.0) Gecko/20100101 Firefox/121.0";
+    const GOOGLEBOT: &str =
+        "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)";
+
+    #[test]
+    fn known_agents_bots_and_garbage() {
+        let parser = UaParser::new();
+        let cases = [
+            (CHROME_WINDOWS, "Chrome", "Windows 10", "Desktop"),
+            (SAFARI_IPHONE, "Safari", "iPhone", "Smartphone"),
+            (FIREFOX_LINUX, "Firefox", "Linux", "Desktop"),
+            (GOOGLEBOT, "Googlebot", "Other", "Bot"),
+            ("", "Other", "Other", "Other"),
+            ("definitely not a browser", "Other", "Other", "Other"),
+        ];
+
+        for (agent, browser, os, device) in cases {
+            let parsed = parser.parse(agent);
+            assert_eq!(
+                (
+                    parsed.browser.as_str(),
+                    parsed.os.as_str(),
+                    parsed.device.as_str()
+                ),
+                (browser, os, device),
+                "{agent}"
+            );
+        }
+    }
+
+    #[test]
+    fn repeated_agents_hit_the_cache() {
+        let parser = UaParser::new();
+        parser.parse(CHROME_WINDOWS);
+        assert_eq!(parser.cache_hits(), 0);
+
+        for _ in 0..10 {
+            assert_eq!(parser.parse(CHROME_WINDOWS).browser, "Chrome");
+        }
+        assert_eq!(parser.cache_hits(), 10);
+    }
+
+    #[actix_web::test]
+    async fn group_by_device() {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "user_agent",
+            DataType::Utf8,
+            true,
+        )]));
+        let agents = StringArray::from(vec![
+            Some(CHROME_WINDOWS),
+            Some(FIREFOX_LINUX),
+            Some(SAFARI_IPHONE),
+            Some(GOOGLEBOT),
+            Some(""),
+            None,
+        ]);
+        let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(agents)]).unwrap();
+
+        let parser = Arc::new(UaParser::new());
+        let ctx = SessionContext::new();
+        for udf in UaExtract::all(parser.clone()) {
+            ctx.register_udf(ScalarUDF::from(udf));
+        }
+        ctx.register_table(
+            "access",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+
+        let batches = ctx
+            .sql("SELECT ua_device(user_agent) AS device, count(*) FROM access GROUP BY device ORDER BY device")
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let devices: Vec<_> = batches[0].column(0).as_string::<i32>().iter().collect();
+        assert_eq!(
+            devices,
+            vec![
+                Some("Bot"),
+                Some("Desktop"),
+                Some("Other"),
+                Some("Smartphone"),
+                None
+            ]
+        );
+    }
+}
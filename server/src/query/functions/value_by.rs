@@ -0,0 +1,390 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, cmp::Ordering};
+
+use arrow_array::{cast::AsArray, Array, ArrayRef};
+use arrow_schema::DataType;
+use datafusion::arrow::row::{RowConverter, SortField};
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{Accumulator, AggregateUDFImpl, Signature, TypeSignature, Volatility},
+    scalar::ScalarValue,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pick {
+    First,
+    Last,
+}
+
+/// `first_value_by(value, ordering)` and `last_value_by(value, ordering)`
+///
+/// Returns the value of the row with the smallest (first) or largest (last)
+/// ordering, rows with a NULL ordering are skipped. Ties on the ordering are
+/// broken by the value itself, again the smallest for first_value_by and the
+/// largest for last_value_by with NULL values sorting before any other, so
+/// the result never depends on how rows were split across partitions.
+#[derive(Debug)]
+pub struct ValueBy {
+    pick: Pick,
+    signature: Signature,
+}
+
+impl ValueBy {
+    pub fn new(pick: Pick) -> Self {
+        Self {
+            pick,
+            signature: Signature::new(TypeSignature::Any(2), Volatility::Immutable),
+        }
+    }
+
+    pub fn all() -> Vec<Self> {
+        vec![Self::new(Pick::First), Self::new(Pick::Last)]
+    }
+}
+
+impl AggregateUDFImpl for ValueBy {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        match self.pick {
+            Pick::First => "first_value_by",
+            Pick::Last => "last_value_by",
+        }
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        match &arg_types[0] {
+            DataType::Utf8 | DataType::Int64 | DataType::Float64 | DataType::Timestamp(_, _) => {}
+            other => {
+                return Err(DataFusionError::Plan(format!(
+                    "{} does not support values of type {other}",
+                    self.name()
+                )))
+            }
+        }
+        if !RowConverter::supports_fields(&[SortField::new(arg_types[1].clone())]) {
+            return Err(DataFusionError::Plan(format!(
+                "{} can not order by type {}",
+                self.name(),
+                arg_types[1]
+            )));
+        }
+        Ok(arg_types[0].clone())
+    }
+
+    fn accumulator(&self, arg: &DataType) -> Result<Box<dyn Accumulator>> {
+        Ok(Box::new(ValueByAccumulator::new(self.pick, arg)?))
+    }
+
+    // the ordering is kept in row format so partial states compare without its type
+    fn state_type(&self, return_type: &DataType) -> Result<Vec<DataType>> {
+        Ok(vec![return_type.clone(), DataType::Binary])
+    }
+}
+
+#[derive(Debug)]
+pub struct ValueByAccumulator {
+    pick: Pick,
+    // null of the value type, returned when no row had an ordering
+    null: ScalarValue,
+    converter: Option<RowConverter>,
+    // (ordering, value) in row format alongside the value it was built from
+    best: Option<(Vec<u8>, ScalarValue)>,
+}
+
+impl ValueByAccumulator {
+    fn new(pick: Pick, value_type: &DataType) -> Result<Self> {
+        Ok(Self {
+            pick,
+            null: ScalarValue::try_from(value_type)?,
+            converter: None,
+            best: None,
+        })
+    }
+
+    fn is_better(&self, key: &[u8]) -> bool {
+        let Some((best, _)) = &self.best else {
+            return true;
+        };
+        matches!(
+            (self.pick, key.cmp(best)),
+            (Pick::First, Ordering::Less) | (Pick::Last, Ordering::Greater)
+        )
+    }
+
+    fn offer(&mut self, key: &[u8], values: &ArrayRef, row: usize) -> Result<()> {
+        if self.is_better(key) {
+            self.best = Some((key.to_vec(), ScalarValue::try_from_array(values, row)?));
+        }
+        Ok(())
+    }
+}
+
+impl Accumulator for ValueByAccumulator {
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        let (value, ordering) = (&values[0], &values[1]);
+        if value.is_empty() {
+            return Ok(());
+        }
+        let converter = match self.converter.take() {
+            Some(converter) => converter,
+            None => RowConverter::new(vec![
+                SortField::new(ordering.data_type().clone()),
+                SortField::new(value.data_type().clone()),
+            ])?,
+        };
+        let rows = converter.convert_columns(&[ordering.clone(), value.clone()])?;
+
+        // pick the batch candidate first so only one value is materialized
+        let candidate = (0..rows.num_rows())
+            .filter(|row| ordering.is_valid(*row))
+            .reduce(|best, row| {
+                let better = match self.pick {
+                    Pick::First => rows.row(row) < rows.row(best),
+                    Pick::Last => rows.row(row) > rows.row(best),
+                };
+                if better {
+                    row
+                } else {
+                    best
+                }
+            });
+        if let Some(row) = candidate {
+            self.offer(rows.row(row).as_ref(), value, row)?;
+        }
+        self.converter = Some(converter);
+        Ok(())
+    }
+
+    fn evaluate(&mut self) -> Result<ScalarValue> {
+        Ok(self
+            .best
+            .as_ref()
+            .map(|(_, value)| value.clone())
+            .unwrap_or_else(|| self.null.clone()))
+    }
+
+    fn size(&self) -> usize {
+        std::mem::size_of_val(self)
+            + self
+                .converter
+                .as_ref()
+                .map(|c| c.size())
+                .unwrap_or_default()
+            + self
+                .best
+                .as_ref()
+                .map(|(key, value)| key.capacity() + value.size())
+                .unwrap_or_default()
+    }
+
+    fn state(&mut self) -> Result<Vec<ScalarValue>> {
+        Ok(match &self.best {
+            Some((key, value)) => vec![value.clone(), ScalarValue::Binary(Some(key.clone()))],
+            None => vec![self.null.clone(), ScalarValue::Binary(None)],
+        })
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        let values = &states[0];
+        let keys = states[1].as_binary::<i32>();
+        for row in 0..keys.len() {
+            if keys.is_valid(row) {
+                self.offer(keys.value(row), values, row)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        cast::AsArray, types::Int64Type, ArrayRef, Int64Array, RecordBatch, StringArray,
+        TimestampMillisecondArray,
+    };
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use datafusion::{
+        datasource::MemTable,
+        logical_expr::{Accumulator, AggregateUDF},
+        prelude::{SessionConfig, SessionContext},
+        scalar::ScalarValue,
+    };
+    use itertools::Itertools;
+
+    use super::{Pick, ValueBy, ValueByAccumulator};
+
+    fn accumulator(pick: Pick) -> ValueByAccumulator {
+        ValueByAccumulator::new(pick, &DataType::Utf8).unwrap()
+    }
+
+    fn update(acc: &mut ValueByAccumulator, values: &[Option<&str>], ordering: &[Option<i64>]) {
+        let values: ArrayRef = Arc::new(StringArray::from(values.to_vec()));
+        let ordering: ArrayRef = Arc::new(Int64Array::from(ordering.to_vec()));
+        acc.update_batch(&[values, ordering]).unwrap();
+    }
+
+    fn merge(into: &mut ValueByAccumulator, from: &mut ValueByAccumulator) {
+        let state = from
+            .state()
+            .unwrap()
+            .iter()
+            .map(|v| v.to_array().unwrap())
+            .collect_vec();
+        into.merge_batch(&state).unwrap();
+    }
+
+    #[test]
+    fn picks_value_of_extreme_ordering() {
+        let values = [Some("b"), Some("a"), Some("c"), Some("skipped")];
+        let ordering = [Some(20), Some(10), Some(30), None];
+        for (pick, expected) in [(Pick::First, "a"), (Pick::Last, "c")] {
+            let mut acc = accumulator(pick);
+            update(&mut acc, &values, &ordering);
+            assert_eq!(acc.evaluate().unwrap(), ScalarValue::from(expected));
+        }
+    }
+
+    #[test]
+    fn all_null_ordering_is_null() {
+        let mut acc = accumulator(Pick::Last);
+        update(&mut acc, &[Some("a"), Some("b")], &[None, None]);
+        assert_eq!(acc.evaluate().unwrap(), ScalarValue::Utf8(None));
+
+        // an empty partial state leaves the other side untouched
+        let mut merged = accumulator(Pick::Last);
+        merge(&mut merged, &mut acc);
+        assert_eq!(merged.evaluate().unwrap(), ScalarValue::Utf8(None));
+    }
+
+    #[test]
+    fn ties_break_on_value_regardless_of_order() {
+        let rows = [(Some("m"), 5), (None, 5), (Some("z"), 5), (Some("a"), 5)];
+        for permutation in rows.iter().permutations(rows.len()) {
+            let values = permutation.iter().map(|(v, _)| *v).collect_vec();
+            let ordering = permutation.iter().map(|(_, o)| Some(*o)).collect_vec();
+
+            let mut first = accumulator(Pick::First);
+            update(&mut first, &values, &ordering);
+            // NULL values sort first
+            assert_eq!(first.evaluate().unwrap(), ScalarValue::Utf8(None));
+
+            let mut last = accumulator(Pick::Last);
+            update(&mut last, &values, &ordering);
+            assert_eq!(last.evaluate().unwrap(), ScalarValue::from("z"));
+        }
+    }
+
+    #[test]
+    fn merge_matches_single_accumulator() {
+        let values = (0..1000).map(|i| format!("v{}", i % 37)).collect_vec();
+        let ordering = (0..1000).map(|i| Some((i * 7919) % 1000)).collect_vec();
+        let values = values.iter().map(|v| Some(v.as_str())).collect_vec();
+
+        for pick in [Pick::First, Pick::Last] {
+            let mut single = accumulator(pick);
+            update(&mut single, &values, &ordering);
+
+            let mut merged = accumulator(pick);
+            for (values, ordering) in values.chunks(128).zip(ordering.chunks(128)) {
+                let mut partial = accumulator(pick);
+                update(&mut partial, values, ordering);
+                merge(&mut merged, &mut partial);
+            }
+            assert_eq!(merged.evaluate().unwrap(), single.evaluate().unwrap());
+        }
+    }
+
+    #[actix_web::test]
+    async fn latest_status_per_device() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("device", DataType::Utf8, false),
+            Field::new("status", DataType::Int64, true),
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+        ]));
+        let batch = |devices: Vec<&str>, status: Vec<Option<i64>>, ts: Vec<Option<i64>>| {
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(StringArray::from(devices)),
+                    Arc::new(Int64Array::from(status)),
+                    Arc::new(TimestampMillisecondArray::from(ts)),
+                ],
+            )
+            .unwrap()
+        };
+        // each device is spread across partitions so partial states are merged
+        let partitions = vec![
+            vec![batch(
+                vec!["a", "b", "c"],
+                vec![Some(200), Some(500), Some(1)],
+                vec![Some(1), Some(4), None],
+            )],
+            vec![batch(
+                vec!["a", "b", "c"],
+                vec![Some(404), Some(201), Some(2)],
+                vec![Some(3), Some(2), None],
+            )],
+            vec![batch(
+                vec!["a", "b"],
+                vec![Some(301), None],
+                vec![Some(2), Some(5)],
+            )],
+        ];
+
+        let ctx = SessionContext::new_with_config(SessionConfig::new().with_target_partitions(3));
+        for value_by in ValueBy::all() {
+            ctx.register_udaf(AggregateUDF::from(value_by));
+        }
+        ctx.register_table(
+            "logs",
+            Arc::new(MemTable::try_new(schema.clone(), partitions).unwrap()),
+        )
+        .unwrap();
+
+        let batches = ctx
+            .sql(
+                "SELECT device, first_value_by(status, p_timestamp), last_value_by(status, p_timestamp) \
+                 FROM logs GROUP BY device ORDER BY device",
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let result = &batches[0];
+        let first = result.column(1).as_primitive::<Int64Type>();
+        let last = result.column(2).as_primitive::<Int64Type>();
+        assert_eq!(first.iter().collect_vec(), vec![Some(200), Some(201), None]);
+        assert_eq!(last.iter().collect_vec(), vec![Some(404), None, None]);
+    }
+}
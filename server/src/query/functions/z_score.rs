@@ -0,0 +1,274 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::{any::Any, sync::Arc};
+
+use arrow_array::{cast::AsArray, types::Float64Type, ArrayRef, Float64Array};
+use arrow_schema::DataType;
+use datafusion::arrow::compute::cast;
+use datafusion::{
+    error::{DataFusionError, Result},
+    logical_expr::{PartitionEvaluator, Signature, TypeSignature, Volatility, WindowUDFImpl},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::{
+    require_literal,
+    rolling::{NullMode, NullTracker, TrailingWindow},
+    rolling_mean::{DEFAULT_WINDOW, MAX_WINDOW},
+};
+
+fn window_arg(window: Option<&ScalarValue>) -> Result<usize> {
+    match window {
+        None => Ok(DEFAULT_WINDOW),
+        Some(ScalarValue::Int64(Some(window))) if (2..=MAX_WINDOW).contains(window) => {
+            Ok(*window as usize)
+        }
+        Some(other) => Err(DataFusionError::Plan(format!(
+            "z_score expects a window between 2 and {MAX_WINDOW}, got {other}"
+        ))),
+    }
+}
+
+/// Checks the window and NULL mode of a z_score call while planning
+pub fn validate_args(args: &[Expr]) -> Result<()> {
+    require_literal("z_score", args, 1)?;
+    require_literal("z_score", args, 2)?;
+    if let Some(Expr::Literal(mode)) = args.get(2) {
+        NullMode::parse("z_score", Some(mode))?;
+    }
+    match args.get(1) {
+        Some(Expr::Literal(window)) => window_arg(Some(window)).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
+/// `z_score(value [, window [, null_mode]])`
+///
+/// Standardized deviation `(value - mean) / stddev` of each row from the
+/// last `window` (default 300) non NULL values up to and including it,
+/// counted the same way as `rolling_mean`. Unlike `anomaly_zscore` the row
+/// is part of its own window.
+///
+/// Returns NULL for NULL values, while the window holds fewer than two
+/// values and when its standard deviation is 0.
+#[derive(Debug)]
+pub struct ZScoreUdf {
+    signature: Signature,
+}
+
+impl ZScoreUdf {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::one_of(
+                vec![
+                    TypeSignature::Any(1),
+                    TypeSignature::Any(2),
+                    TypeSignature::Any(3),
+                ],
+                Volatility::Immutable,
+            ),
+        }
+    }
+}
+
+impl WindowUDFImpl for ZScoreUdf {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "z_score"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        if !arg_types[0].is_numeric() {
+            return Err(DataFusionError::Plan(format!(
+                "z_score expects a numeric value, got {}",
+                arg_types[0]
+            )));
+        }
+        if let Some(window) = arg_types.get(1).filter(|window| !window.is_integer()) {
+            return Err(DataFusionError::Plan(format!(
+                "z_score expects an integer window, got {window}"
+            )));
+        }
+        Ok(DataType::Float64)
+    }
+
+    fn partition_evaluator(&self) -> Result<Box<dyn PartitionEvaluator>> {
+        Ok(Box::new(ZScoreEvaluator))
+    }
+}
+
+#[derive(Debug)]
+struct ZScoreEvaluator;
+
+impl PartitionEvaluator for ZScoreEvaluator {
+    fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+        if num_rows == 0 {
+            return Ok(Arc::new(Float64Array::from(Vec::<f64>::new())));
+        }
+        // window and mode are literals, every row carries the same values
+        let window = values
+            .get(1)
+            .map(|window| ScalarValue::try_from_array(window, 0))
+            .transpose()?;
+        let size = window_arg(window.as_ref())?;
+        let mode = values
+            .get(2)
+            .map(|mode| ScalarValue::try_from_array(mode, 0))
+            .transpose()?;
+        let mut nulls = NullTracker::new(NullMode::parse("z_score", mode.as_ref())?, size);
+
+        let input = cast(&values[0], &DataType::Float64)?;
+        let mut window = TrailingWindow::new(size);
+        let scores = input
+            .as_primitive::<Float64Type>()
+            .iter()
+            .map(|value| {
+                let value = nulls.admit(value)?;
+                window.push(value);
+                let stats = window.stats();
+                let score = match (stats.mean(), stats.stddev()) {
+                    (Some(mean), Some(stddev)) if stddev > 0.0 => Some((value - mean) / stddev),
+                    _ => None,
+                };
+                nulls.result(score)
+            })
+            .collect::<Float64Array>();
+        Ok(Arc::new(scores))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{cast::AsArray, types::Float64Type, Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{datasource::MemTable, logical_expr::WindowUDF, prelude::SessionContext};
+
+    use super::ZScoreUdf;
+    use crate::query::functions::add_analyzer_rules;
+
+    fn context(values: Vec<Option<i64>>) -> SessionContext {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("seq", DataType::Int64, false),
+            Field::new("value", DataType::Int64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(0..values.len() as i64)),
+                Arc::new(Int64Array::from(values)),
+            ],
+        )
+        .unwrap();
+        let ctx = SessionContext::new_with_state(add_analyzer_rules(SessionContext::new().state()));
+        ctx.register_udwf(WindowUDF::from(ZScoreUdf::new()));
+        ctx.register_table(
+            "metrics",
+            Arc::new(MemTable::try_new(schema, vec![vec![batch]]).unwrap()),
+        )
+        .unwrap();
+        ctx
+    }
+
+    async fn scores(
+        ctx: &SessionContext,
+        call: &str,
+    ) -> datafusion::error::Result<Vec<Option<f64>>> {
+        let sql = format!("SELECT {call} OVER (ORDER BY seq) FROM metrics ORDER BY seq");
+        let batches = ctx.sql(&sql).await?.collect().await?;
+        Ok(batches
+            .iter()
+            .flat_map(|batch| batch.column(0).as_primitive::<Float64Type>().iter())
+            .collect())
+    }
+
+    #[actix_web::test]
+    async fn outlier_scores_high() {
+        let mut values: Vec<_> = [10, 11, 9].into_iter().cycle().take(30).map(Some).collect();
+        values[25] = Some(100);
+        let ctx = context(values);
+        let scores = scores(&ctx, "z_score(value, 20)").await.unwrap();
+
+        assert_eq!(scores[0], None);
+        assert!(scores[25].unwrap() > 4.0, "{scores:?}");
+        for (row, score) in scores
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(row, _)| *row != 25)
+        {
+            assert!(score.unwrap().abs() < 1.5, "row {row}: {score:?}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn matches_brute_force_score() {
+        let values = [7, -3, 12, 0, 5, 5, 40, -8, 1, 9];
+        let ctx = context(values.iter().copied().map(Some).collect());
+        let scores = scores(&ctx, "z_score(value, 4)").await.unwrap();
+
+        assert_eq!(scores[0], None);
+        for (row, score) in scores.into_iter().enumerate().skip(1) {
+            let frame: Vec<f64> = values[row.saturating_sub(3)..=row]
+                .iter()
+                .map(|value| *value as f64)
+                .collect();
+            let mean = frame.iter().sum::<f64>() / frame.len() as f64;
+            let variance = frame
+                .iter()
+                .map(|value| (value - mean).powi(2))
+                .sum::<f64>()
+                / (frame.len() - 1) as f64;
+            let expected = (values[row] as f64 - mean) / variance.sqrt();
+            assert!((score.unwrap() - expected).abs() < 1e-9, "row {row}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn undefined_scores_are_null() {
+        let ctx = context(vec![Some(5), Some(5), Some(5), None, Some(8)]);
+        let scores = scores(&ctx, "z_score(value, 3)").await.unwrap();
+        // a single value, then no deviation, then a NULL value
+        assert_eq!(scores[..4], [None, None, None, None]);
+        // 5, 5, 8 has a mean of 6 and a standard deviation of sqrt(3)
+        assert!((scores[4].unwrap() - 2.0 / 3f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[actix_web::test]
+    async fn window_is_validated_while_planning() {
+        let ctx = context(vec![Some(1)]);
+        for call in [
+            "z_score(value, 1)",
+            "z_score(value, seq)",
+            "z_score(value, 2, 'bogus')",
+        ] {
+            let err = scores(&ctx, call).await.unwrap_err();
+            assert!(err.to_string().contains("z_score"), "{call}: {err}");
+        }
+    }
+}
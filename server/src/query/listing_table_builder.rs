@@ -37,7 +37,7 @@ use crate::{
     utils::TimePeriod,
 };
 
-use super::PartialTimeFilter;
+use super::{partition_filter::PartitionFilter, PartialTimeFilter};
 
 // Listing Table Builder for querying old data
 #[derive(Debug, Default)]
@@ -179,6 +179,12 @@ impl ListingTableBuilder {
         })
     }
 
+    /// Drops listed files outside the custom partitions the query allows
+    pub fn prune(mut self, filter: &PartitionFilter) -> Self {
+        self.listing.retain(|path| filter.matches(path));
+        self
+    }
+
     pub fn build(
         self,
         schema: Arc<Schema>,
@@ -210,3 +216,131 @@ impl ListingTableBuilder {
         Ok(Some(listing_table))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, path::Path, sync::Arc};
+
+    use arrow_array::{Int64Array, RecordBatch, StringArray, TimestampMillisecondArray};
+    use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+    use datafusion::{
+        datasource::listing::ListingTableUrl,
+        logical_expr::{col, lit, Expr},
+        prelude::SessionContext,
+    };
+    use parquet::arrow::ArrowWriter;
+
+    use super::ListingTableBuilder;
+    use crate::query::partition_filter::PartitionFilter;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("namespace", DataType::Utf8, true),
+            Field::new("status", DataType::Int64, true),
+        ]))
+    }
+
+    // one file per namespace and minute, plus one written before partitioning
+    fn listing(dir: &Path) -> Vec<String> {
+        let mut files = vec![(0, None)];
+        for minute in 1..4 {
+            for namespace in ["foo", "bar", "baz"] {
+                files.push((minute, Some(namespace)));
+            }
+        }
+        files
+            .into_iter()
+            .enumerate()
+            .map(|(index, (minute, namespace))| {
+                let mut prefix =
+                    dir.join(format!("app/date=2024-01-01/hour=10/minute={minute:02}"));
+                if let Some(namespace) = namespace {
+                    prefix = prefix.join(format!("namespace={namespace}"));
+                }
+                std::fs::create_dir_all(&prefix).unwrap();
+                let batch = RecordBatch::try_new(
+                    schema(),
+                    vec![
+                        Arc::new(TimestampMillisecondArray::from(vec![index as i64; 4])),
+                        Arc::new(StringArray::from(vec![namespace.unwrap_or("foo"); 4])),
+                        Arc::new(Int64Array::from_iter_values(index as i64..index as i64 + 4)),
+                    ],
+                )
+                .unwrap();
+                let path = prefix.join("host.data.parquet");
+                let mut writer =
+                    ArrowWriter::try_new(File::create(&path).unwrap(), schema(), None).unwrap();
+                writer.write(&batch).unwrap();
+                writer.close().unwrap();
+                // object store paths carry no leading slash
+                path.display().to_string()[1..].to_owned()
+            })
+            .collect()
+    }
+
+    fn urls(listing: Vec<String>) -> Vec<ListingTableUrl> {
+        listing
+            .into_iter()
+            .map(|path| ListingTableUrl::parse(format!("/{path}")).unwrap())
+            .collect()
+    }
+
+    async fn rows(builder: ListingTableBuilder, predicate: Expr) -> Vec<RecordBatch> {
+        let table = builder.build(schema(), urls, None).unwrap().unwrap();
+        let ctx = SessionContext::new();
+        ctx.read_table(table)
+            .unwrap()
+            .filter(predicate)
+            .unwrap()
+            .sort(vec![col("status").sort(true, false)])
+            .unwrap()
+            .collect()
+            .await
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn pruned_listing_matches_unpruned_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let listing = listing(dir.path());
+
+        for (predicate, files) in [
+            (col("namespace").eq(lit("foo")), 4),
+            (
+                col("namespace").in_list(vec![lit("bar"), lit("baz")], false),
+                7,
+            ),
+            (
+                col("namespace")
+                    .eq(lit("foo"))
+                    .or(col("status").gt(lit(20))),
+                10,
+            ),
+        ] {
+            let builder = || ListingTableBuilder {
+                stream: "app".to_owned(),
+                listing: listing.clone(),
+            };
+            let filter = PartitionFilter::new(std::slice::from_ref(&predicate), Some("namespace"));
+            let pruned = builder().prune(&filter);
+
+            let pruned_urls = urls(pruned.listing.clone());
+            assert_eq!(pruned_urls.len(), files, "{predicate}");
+            // the file predating the partition is always listed
+            assert!(pruned_urls
+                .iter()
+                .any(|url| url.as_str().contains("minute=00/host")));
+
+            assert_eq!(
+                rows(pruned, predicate.clone()).await,
+                rows(builder(), predicate.clone()).await,
+                "{predicate}"
+            );
+        }
+    }
+}
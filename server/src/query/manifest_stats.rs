@@ -0,0 +1,543 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Answers count(*), min and max queries from the column statistics kept in
+//! manifests. Files whose events all fall inside the queried time range are
+//! answered from their manifest entry, the rest is scanned as usual and both
+//! parts are combined into the final row.
+
+use std::{collections::HashSet, ops::Bound, sync::Arc};
+
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, SchemaRef, TimeUnit};
+use datafusion::{
+    error::DataFusionError,
+    execution::context::SessionState,
+    logical_expr::{
+        aggregate_function::AggregateFunction, expr::AggregateFunctionDefinition,
+        utils::split_conjunction, LogicalPlan,
+    },
+    physical_plan::collect,
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+
+use super::{
+    is_stream,
+    registry::CancelFlag,
+    stream_schema_provider::{self, PartialTimeFilter},
+};
+use crate::{
+    catalog::{column::TypedStatistics, manifest::File},
+    event::DEFAULT_TIMESTAMP_KEY,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Stat {
+    CountAll,
+    Min(String),
+    Max(String),
+}
+
+impl Stat {
+    fn try_from_expr(expr: &Expr) -> Option<Self> {
+        let Expr::AggregateFunction(aggregate) = expr.clone().unalias() else {
+            return None;
+        };
+        if aggregate.distinct || aggregate.filter.is_some() || aggregate.order_by.is_some() {
+            return None;
+        }
+        let AggregateFunctionDefinition::BuiltIn(fun) = aggregate.func_def else {
+            return None;
+        };
+        match (fun, aggregate.args.as_slice()) {
+            (AggregateFunction::Count, [Expr::Literal(value)]) if !value.is_null() => {
+                Some(Stat::CountAll)
+            }
+            (AggregateFunction::Min, [Expr::Column(column)]) => {
+                Some(Stat::Min(column.name.clone()))
+            }
+            (AggregateFunction::Max, [Expr::Column(column)]) => {
+                Some(Stat::Max(column.name.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    fn column(&self) -> Option<&str> {
+        match self {
+            Stat::CountAll => None,
+            Stat::Min(column) | Stat::Max(column) => Some(column),
+        }
+    }
+
+    // folds a file's contribution into the running value
+    fn merge(&self, acc: Option<i64>, value: Option<i64>) -> Option<i64> {
+        match (acc, value) {
+            (Some(acc), Some(value)) => Some(match self {
+                Stat::CountAll => acc + value,
+                Stat::Min(_) => acc.min(value),
+                Stat::Max(_) => acc.max(value),
+            }),
+            (acc, value) => acc.or(value),
+        }
+    }
+}
+
+// manifests keep integer, timestamp and boolean statistics losslessly as i64
+fn exact_stats(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Boolean
+            | DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::Timestamp(TimeUnit::Millisecond, _)
+    )
+}
+
+/// A count(*), min and max query over a time range of a single stream
+#[derive(Debug)]
+pub struct ManifestAggregate {
+    stream: String,
+    // one per output column of the query
+    stats: Vec<Stat>,
+    predicates: Vec<Expr>,
+    // inclusive range in milliseconds
+    low: i64,
+    high: i64,
+}
+
+/// Values answered from manifests and the files they were answered from
+#[derive(Debug, Default)]
+pub struct Answered {
+    values: Vec<Option<i64>>,
+    pub files: HashSet<String>,
+}
+
+impl ManifestAggregate {
+    /// Matches an optimized plan of the shape
+    /// `Projection? -> Aggregate -> (Filter | Projection)* -> TableScan`
+    /// without grouping, where every predicate bounds the event time.
+    pub fn try_new(plan: &LogicalPlan) -> Option<Self> {
+        let (outputs, aggregate) = match plan {
+            LogicalPlan::Projection(projection) => {
+                let LogicalPlan::Aggregate(aggregate) = projection.input.as_ref() else {
+                    return None;
+                };
+                let outputs = projection
+                    .expr
+                    .iter()
+                    .map(|expr| match expr.clone().unalias() {
+                        Expr::Column(column) => aggregate.schema.index_of_column(&column).ok(),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                (outputs, aggregate)
+            }
+            LogicalPlan::Aggregate(aggregate) => {
+                ((0..aggregate.aggr_expr.len()).collect(), aggregate)
+            }
+            _ => return None,
+        };
+        if !aggregate.group_expr.is_empty() {
+            return None;
+        }
+        let aggregates = aggregate
+            .aggr_expr
+            .iter()
+            .map(Stat::try_from_expr)
+            .collect::<Option<Vec<_>>>()?;
+        let stats = outputs
+            .into_iter()
+            .map(|index| aggregates.get(index).cloned())
+            .collect::<Option<Vec<_>>>()?;
+
+        let mut predicates = Vec::new();
+        let mut input = aggregate.input.as_ref();
+        let scan = loop {
+            match input {
+                LogicalPlan::Filter(filter) => {
+                    predicates.extend(split_conjunction(&filter.predicate).into_iter().cloned());
+                    input = filter.input.as_ref();
+                }
+                LogicalPlan::Projection(projection)
+                    if projection
+                        .expr
+                        .iter()
+                        .all(|expr| matches!(expr, Expr::Column(_))) =>
+                {
+                    input = projection.input.as_ref();
+                }
+                LogicalPlan::TableScan(scan) if scan.fetch.is_none() && is_stream(scan) => {
+                    break scan
+                }
+                _ => return None,
+            }
+        };
+        for filter in &scan.filters {
+            predicates.extend(split_conjunction(filter).into_iter().cloned());
+        }
+
+        let schema = scan.source.schema();
+        for column in stats.iter().filter_map(Stat::column) {
+            let field = schema.field_with_name(column).ok()?;
+            if !exact_stats(field.data_type()) {
+                return None;
+            }
+        }
+
+        let (mut low, mut high) = (i64::MIN, i64::MAX);
+        for predicate in &predicates {
+            let Expr::BinaryExpr(binary) = predicate else {
+                return None;
+            };
+            if !matches!(binary.left.as_ref(), Expr::Column(column) if column.name == DEFAULT_TIMESTAMP_KEY)
+            {
+                return None;
+            }
+            let millis = |time: &chrono::NaiveDateTime| time.and_utc().timestamp_millis();
+            match PartialTimeFilter::try_from_expr(predicate, None)? {
+                PartialTimeFilter::Low(Bound::Included(time)) => low = low.max(millis(&time)),
+                PartialTimeFilter::Low(Bound::Excluded(time)) => low = low.max(millis(&time) + 1),
+                PartialTimeFilter::High(Bound::Included(time)) => high = high.min(millis(&time)),
+                PartialTimeFilter::High(Bound::Excluded(time)) => {
+                    high = high.min(millis(&time) - 1)
+                }
+                PartialTimeFilter::Eq(time) => {
+                    low = low.max(millis(&time));
+                    high = high.min(millis(&time));
+                }
+                _ => return None,
+            }
+        }
+
+        Some(Self {
+            stream: scan.table_name.table().to_string(),
+            stats,
+            predicates,
+            low,
+            high,
+        })
+    }
+
+    // every event of the file matches the time range
+    fn covers(&self, file: &File) -> bool {
+        file.columns.iter().any(|column| {
+            column.name == DEFAULT_TIMESTAMP_KEY
+                && matches!(&column.stats, Some(TypedStatistics::Int(stats))
+                    if self.low <= stats.min && stats.max <= self.high)
+        })
+    }
+
+    // the file's contribution to every stat, None when a statistic is missing
+    fn file_values(&self, file: &File) -> Option<Vec<Option<i64>>> {
+        self.stats
+            .iter()
+            .map(|stat| {
+                let Some(name) = stat.column() else {
+                    return Some(Some(file.num_rows as i64));
+                };
+                // a file without the column only holds NULLs for it
+                let Some(column) = file.columns.iter().find(|column| column.name == name) else {
+                    return Some(None);
+                };
+                let (min, max) = match column.stats.as_ref()? {
+                    TypedStatistics::Int(stats) => (stats.min, stats.max),
+                    TypedStatistics::Bool(stats) => (stats.min as i64, stats.max as i64),
+                    _ => return None,
+                };
+                Some(Some(if matches!(stat, Stat::Min(_)) {
+                    min
+                } else {
+                    max
+                }))
+            })
+            .collect()
+    }
+
+    /// Answers the query for the files whose manifest entry is enough,
+    /// the remaining files still have to be scanned
+    pub fn answer(&self, files: &[File]) -> Answered {
+        let mut answered = Answered {
+            values: vec![None; self.stats.len()],
+            files: HashSet::new(),
+        };
+        if let Some(index) = self.stats.iter().position(|stat| *stat == Stat::CountAll) {
+            answered.values[index] = Some(0);
+        }
+        for file in files.iter().filter(|file| self.covers(file)) {
+            let Some(values) = self.file_values(file) else {
+                continue;
+            };
+            for ((stat, acc), value) in self.stats.iter().zip(&mut answered.values).zip(values) {
+                *acc = stat.merge(*acc, value);
+            }
+            answered.files.insert(file.file_path.clone());
+        }
+        answered
+    }
+
+    /// Combines the result of scanning the remaining files with what was answered
+    pub fn combine(
+        &self,
+        batches: &[RecordBatch],
+        answered: &Answered,
+        schema: SchemaRef,
+    ) -> Result<RecordBatch, DataFusionError> {
+        let row = batches.iter().find(|batch| batch.num_rows() > 0);
+        let columns = self
+            .stats
+            .iter()
+            .zip(&answered.values)
+            .zip(schema.fields())
+            .enumerate()
+            .map(|(index, ((stat, value), field))| {
+                let scanned = match row {
+                    Some(batch) => ScalarValue::try_from_array(batch.column(index), 0)?
+                        .cast_to(&DataType::Int64)?,
+                    None => ScalarValue::Int64(None),
+                };
+                let ScalarValue::Int64(scanned) = scanned else {
+                    unreachable!("cast to Int64")
+                };
+                let merged = ScalarValue::Int64(stat.merge(*value, scanned));
+                merged.cast_to(field.data_type())?.to_array()
+            })
+            .collect::<Result<Vec<ArrayRef>, DataFusionError>>()?;
+        Ok(RecordBatch::try_new(schema, columns)?)
+    }
+}
+
+/// Runs `plan` with count(*), min and max answered from manifests where the
+/// statistics allow it. None when the query doesn't qualify, so that it is
+/// executed normally.
+pub async fn try_execute(
+    state: &SessionState,
+    plan: &LogicalPlan,
+    flag: &CancelFlag,
+) -> Result<Option<RecordBatch>, DataFusionError> {
+    let Some(aggregate) = ManifestAggregate::try_new(&state.optimize(plan)?) else {
+        return Ok(None);
+    };
+    let Some(files) =
+        stream_schema_provider::manifest_files(&aggregate.stream, &aggregate.predicates, state)
+            .await?
+    else {
+        return Ok(None);
+    };
+    let answered = aggregate.answer(&files);
+    if answered.files.is_empty() {
+        return Ok(None);
+    }
+
+    let remainder =
+        stream_schema_provider::without_files(plan.clone(), Arc::new(answered.files.clone()))?;
+    let physical_plan = state.create_physical_plan(&remainder).await?;
+    let schema = physical_plan.schema();
+    let batches = collect(flag.guard(physical_plan)?, state.task_ctx()).await?;
+    aggregate.combine(&batches, &answered, schema).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, sync::Arc};
+
+    use arrow_array::{Int64Array, RecordBatch, TimestampMillisecondArray};
+    use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+    use chrono::{NaiveDateTime, TimeZone, Utc};
+    use datafusion::{
+        datasource::{
+            file_format::parquet::ParquetFormat,
+            listing::{ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl},
+        },
+        logical_expr::LogicalPlan,
+        prelude::SessionContext,
+    };
+    use parquet::{arrow::ArrowWriter, file::properties::WriterProperties, format::SortingColumn};
+
+    use super::ManifestAggregate;
+    use crate::catalog::manifest::{create_from_parquet_file, File};
+    use crate::query::transform;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("status", DataType::Int64, true),
+        ]))
+    }
+
+    fn millis(minute: u32, second: u32) -> i64 {
+        Utc.with_ymd_and_hms(2024, 1, 1, 10, minute, second)
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    fn time(minute: u32, second: u32) -> NaiveDateTime {
+        Utc.with_ymd_and_hms(2024, 1, 1, 10, minute, second)
+            .unwrap()
+            .naive_utc()
+    }
+
+    // twelve files of 50 events spread over 10:00 to 10:12, some status values missing
+    fn generate(dir: &Path) -> Vec<File> {
+        let mut seed = 7u64;
+        let mut next = move || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            seed >> 33
+        };
+        let props = WriterProperties::builder()
+            .set_max_row_group_size(16)
+            .set_sorting_columns(Some(vec![SortingColumn::new(0, true, false)]))
+            .build();
+        (0..12)
+            .map(|index| {
+                let start = millis(index, 0) - 20_000;
+                let mut timestamps: Vec<i64> =
+                    (0..50).map(|_| start + (next() % 60_000) as i64).collect();
+                timestamps.sort_unstable_by(|a, b| b.cmp(a));
+                let status: Vec<Option<i64>> = (0..50)
+                    .map(|_| (next() % 5 != 0).then(|| next() as i64 % 1000 - 200))
+                    .collect();
+                let batch = RecordBatch::try_new(
+                    schema(),
+                    vec![
+                        Arc::new(TimestampMillisecondArray::from(timestamps)),
+                        Arc::new(Int64Array::from(status)),
+                    ],
+                )
+                .unwrap();
+                let path = dir.join(format!("{index:02}.data.parquet"));
+                let file = fs::File::create(&path).unwrap();
+                let mut writer = ArrowWriter::try_new(file, schema(), Some(props.clone())).unwrap();
+                writer.write(&batch).unwrap();
+                writer.close().unwrap();
+                create_from_parquet_file(path.display().to_string(), &path).unwrap()
+            })
+            .collect()
+    }
+
+    fn context(files: &[&File]) -> SessionContext {
+        let ctx = SessionContext::new();
+        if files.is_empty() {
+            ctx.register_batch("app", RecordBatch::new_empty(schema()))
+                .unwrap();
+            return ctx;
+        }
+        let urls = files
+            .iter()
+            .map(|file| ListingTableUrl::parse(&file.file_path).unwrap())
+            .collect();
+        let config = ListingTableConfig::new_with_multi_paths(urls)
+            .with_listing_options(ListingOptions::new(Arc::new(ParquetFormat::default())))
+            .with_schema(schema());
+        ctx.register_table("app", Arc::new(ListingTable::try_new(config).unwrap()))
+            .unwrap();
+        ctx
+    }
+
+    async fn plan(
+        ctx: &SessionContext,
+        sql: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> LogicalPlan {
+        let plan = ctx.state().create_logical_plan(sql).await.unwrap();
+        let plan = transform(plan, start, end, None, &None).data;
+        ctx.state().optimize(&plan).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn answers_match_a_full_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = generate(dir.path());
+        let sql = "SELECT count(*) AS total, max(status), min(status), min(p_timestamp), count(1) FROM app";
+
+        for (start, end) in [
+            (time(2, 30), time(9, 10)),
+            (time(0, 0), time(13, 0)),
+            (time(4, 0), time(5, 0)),
+            (time(7, 45), time(7, 46)),
+        ] {
+            let full_ctx = context(&files.iter().collect::<Vec<_>>());
+            let full_plan = plan(&full_ctx, sql, start, end).await;
+            let expected = full_ctx
+                .execute_logical_plan(full_plan.clone())
+                .await
+                .unwrap()
+                .collect()
+                .await
+                .unwrap();
+
+            let aggregate = ManifestAggregate::try_new(&full_plan).unwrap();
+            let answered = aggregate.answer(&files);
+            let remaining: Vec<_> = files
+                .iter()
+                .filter(|file| !answered.files.contains(&file.file_path))
+                .collect();
+            let ctx = context(&remaining);
+            let remainder = ctx
+                .execute_logical_plan(plan(&ctx, sql, start, end).await)
+                .await
+                .unwrap();
+            let schema: SchemaRef = Arc::new(remainder.schema().into());
+            let batches = remainder.collect().await.unwrap();
+            let combined = aggregate.combine(&batches, &answered, schema).unwrap();
+
+            assert_eq!(combined.columns(), expected[0].columns(), "{start} - {end}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn whole_files_inside_the_range_are_answered() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = generate(dir.path());
+        let ctx = context(&files.iter().collect::<Vec<_>>());
+        let plan = plan(&ctx, "SELECT count(*) FROM app", time(2, 30), time(9, 10)).await;
+
+        let aggregate = ManifestAggregate::try_new(&plan).unwrap();
+        let answered = aggregate.answer(&files);
+        assert!(!answered.files.is_empty());
+        assert!(answered.files.len() < files.len());
+    }
+
+    #[actix_web::test]
+    async fn other_queries_fall_back_to_a_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = generate(dir.path());
+        let ctx = context(&files.iter().collect::<Vec<_>>());
+        for sql in [
+            "SELECT count(status) FROM app",
+            "SELECT count(*) FROM app WHERE status > 10",
+            "SELECT count(DISTINCT status) FROM app",
+            "SELECT status, count(*) FROM app GROUP BY status",
+            "SELECT sum(status) FROM app",
+            "SELECT max(status) + 1 FROM app",
+        ] {
+            let plan = plan(&ctx, sql, time(2, 30), time(9, 10)).await;
+            assert!(ManifestAggregate::try_new(&plan).is_none(), "{sql}");
+        }
+    }
+}
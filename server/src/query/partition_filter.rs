@@ -0,0 +1,215 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use datafusion::{
+    logical_expr::{expr::InList, utils::split_conjunction, BinaryExpr, Operator},
+    prelude::Expr,
+    scalar::ScalarValue,
+};
+use object_store::path::PathPart;
+
+/// Values a query allows for the custom partition columns of a stream.
+///
+/// Events of a custom partitioned stream are stored under `column=value/`
+/// segments, so files whose segments hold other values can't match the query.
+#[derive(Debug, Default)]
+pub struct PartitionFilter {
+    allowed: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl PartitionFilter {
+    /// Collects the equality and IN predicates on the `custom_partition`
+    /// columns (comma separated, as stored for the stream) that every
+    /// matching event satisfies. Anything else is left to the scan.
+    pub fn new(filters: &[Expr], custom_partition: Option<&str>) -> Self {
+        let mut filter = Self::default();
+        let Some(custom_partition) = custom_partition else {
+            return filter;
+        };
+        let columns: Vec<&str> = custom_partition.split(',').map(str::trim).collect();
+
+        for expr in filters.iter().flat_map(|filter| split_conjunction(filter)) {
+            let Some((column, values)) = allowed_values(expr) else {
+                continue;
+            };
+            if !columns.contains(&column.as_str()) {
+                continue;
+            }
+            filter
+                .allowed
+                .entry(column)
+                .and_modify(|allowed| allowed.retain(|value| values.contains(value)))
+                .or_insert(values);
+        }
+        filter
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allowed.is_empty()
+    }
+
+    /// Whether the file at `path` may hold matching events. Files written
+    /// before the stream was partitioned have no segment and are kept.
+    pub fn matches(&self, path: &str) -> bool {
+        self.allowed.iter().all(|(column, values)| {
+            let Some(value) = path.split('/').find_map(|segment| {
+                segment
+                    .strip_prefix(column.as_str())
+                    .and_then(|rest| rest.strip_prefix('='))
+            }) else {
+                return true;
+            };
+            // listed paths are percent encoded by the object store
+            values.iter().any(|allowed| {
+                allowed == value || PathPart::from(allowed.as_str()).as_ref() == value
+            })
+        })
+    }
+}
+
+// the column and its allowed values when `expr` pins a single column
+fn allowed_values(expr: &Expr) -> Option<(String, BTreeSet<String>)> {
+    match expr {
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) if *op == Operator::Eq => {
+            match (left.as_ref(), right.as_ref()) {
+                (Expr::Column(column), Expr::Literal(value))
+                | (Expr::Literal(value), Expr::Column(column)) => {
+                    Some((column.name.clone(), BTreeSet::from([segment_value(value)?])))
+                }
+                _ => None,
+            }
+        }
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) if *op == Operator::Or => {
+            let (column, mut values) = allowed_values(left)?;
+            let (other, other_values) = allowed_values(right)?;
+            if column != other {
+                return None;
+            }
+            values.extend(other_values);
+            Some((column, values))
+        }
+        Expr::InList(InList {
+            expr,
+            list,
+            negated: false,
+        }) => {
+            let Expr::Column(column) = expr.as_ref() else {
+                return None;
+            };
+            let values = list
+                .iter()
+                .map(|value| match value {
+                    Expr::Literal(value) => segment_value(value),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?;
+            Some((column.name.clone(), values))
+        }
+        _ => None,
+    }
+}
+
+// partition values are written the way they appear in the ingested json
+fn segment_value(value: &ScalarValue) -> Option<String> {
+    match value {
+        ScalarValue::Utf8(Some(value)) | ScalarValue::LargeUtf8(Some(value)) => Some(value.clone()),
+        ScalarValue::Int8(Some(_))
+        | ScalarValue::Int16(Some(_))
+        | ScalarValue::Int32(Some(_))
+        | ScalarValue::Int64(Some(_))
+        | ScalarValue::UInt8(Some(_))
+        | ScalarValue::UInt16(Some(_))
+        | ScalarValue::UInt32(Some(_))
+        | ScalarValue::UInt64(Some(_))
+        | ScalarValue::Boolean(Some(_)) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::prelude::{col, lit, lower};
+
+    use super::PartitionFilter;
+
+    const PATHS: [&str; 4] = [
+        "app/date=2024-01-01/hour=10/minute=00/namespace=foo/region=eu/host.data.parquet",
+        "app/date=2024-01-01/hour=10/minute=00/namespace=bar/region=us/host.data.parquet",
+        "app/date=2024-01-01/hour=10/minute=01/namespace=baz/region=eu/host.data.parquet",
+        "app/date=2024-01-01/hour=09/minute=59/host.data.parquet",
+    ];
+
+    fn kept(filters: &[datafusion::prelude::Expr]) -> Vec<usize> {
+        let filter = PartitionFilter::new(filters, Some("namespace,region"));
+        (0..PATHS.len())
+            .filter(|index| filter.matches(PATHS[*index]))
+            .collect()
+    }
+
+    #[test]
+    fn equality_and_in_predicates_prune() {
+        assert_eq!(kept(&[col("namespace").eq(lit("foo"))]), [0, 3]);
+        assert_eq!(kept(&[lit("bar").eq(col("namespace"))]), [1, 3]);
+        assert_eq!(
+            kept(&[col("namespace").in_list(vec![lit("foo"), lit("baz")], false)]),
+            [0, 2, 3]
+        );
+        assert_eq!(
+            kept(&[col("namespace")
+                .eq(lit("bar"))
+                .or(col("namespace").eq(lit("baz")))]),
+            [1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn predicates_combine_across_columns() {
+        let filters = [col("namespace")
+            .in_list(vec![lit("foo"), lit("baz")], false)
+            .and(col("region").eq(lit("eu")))
+            .and(col("status").gt(lit(3)))];
+        assert_eq!(kept(&filters), [0, 2, 3]);
+
+        let filters = [
+            col("namespace").in_list(vec![lit("foo"), lit("bar")], false),
+            col("namespace").eq(lit("bar")),
+        ];
+        assert_eq!(kept(&filters), [1, 3]);
+    }
+
+    #[test]
+    fn unprovable_predicates_keep_every_file() {
+        let all = [0, 1, 2, 3];
+        assert_eq!(kept(&[lower(col("namespace")).eq(lit("foo"))]), all);
+        assert_eq!(
+            kept(&[col("namespace").eq(lit("foo")).or(col("status").gt(lit(3)))]),
+            all
+        );
+        assert_eq!(
+            kept(&[col("namespace").in_list(vec![lit("foo")], true)]),
+            all
+        );
+        assert_eq!(kept(&[col("namespace").not_eq(lit("foo"))]), all);
+        assert_eq!(kept(&[col("host").eq(lit("foo"))]), all);
+
+        let filter = PartitionFilter::new(&[col("namespace").eq(lit("foo"))], None);
+        assert!(filter.is_empty());
+    }
+}
@@ -0,0 +1,490 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Queries running on this node, so that they can be listed and cancelled
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use chrono::{DateTime, Utc};
+use datafusion::{
+    arrow::datatypes::SchemaRef,
+    common::tree_node::{Transformed, TreeNode},
+    error::{DataFusionError, Result},
+    execution::{RecordBatchStream, SendableRecordBatchStream, TaskContext},
+    physical_plan::{
+        DisplayAs, DisplayFormatType, EmptyRecordBatchStream, ExecutionPlan, PlanProperties,
+    },
+};
+use futures_util::{Stream, StreamExt};
+use once_cell::sync::Lazy;
+use tokio::sync::oneshot;
+use ulid::Ulid;
+
+use arrow_array::RecordBatch;
+
+pub static QUERY_REGISTRY: Lazy<QueryRegistry> = Lazy::new(QueryRegistry::default);
+
+#[derive(Debug, thiserror::Error)]
+#[error("Query {0} was cancelled")]
+pub struct Cancelled(pub Ulid);
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunningQuery {
+    pub id: Ulid,
+    pub user: Option<String>,
+    pub stream: String,
+    pub query: String,
+    pub started_at: DateTime<Utc>,
+    pub elapsed_ms: u128,
+}
+
+/// Set once a query is cancelled. Operators guarded by it check the flag
+/// before every batch, so even a query that never waits on IO stops promptly.
+#[derive(Debug, Clone)]
+pub struct CancelFlag {
+    id: Ulid,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelFlag {
+    fn new(id: Ulid) -> Self {
+        Self {
+            id,
+            cancelled: Arc::default(),
+        }
+    }
+
+    /// Flag of a query that isn't in the registry, it is never set
+    pub fn unregistered() -> Self {
+        Self::new(Ulid::nil())
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(DataFusionError::External(Box::new(Cancelled(self.id))));
+        }
+        Ok(())
+    }
+
+    /// Wraps every operator of `plan` so that it fails at its next batch
+    /// once the query is cancelled
+    pub fn guard(&self, plan: Arc<dyn ExecutionPlan>) -> Result<Arc<dyn ExecutionPlan>> {
+        plan.transform_up(&|plan| {
+            Ok(Transformed::yes(Arc::new(CancellableExec {
+                input: plan,
+                flag: self.clone(),
+            }) as Arc<dyn ExecutionPlan>))
+        })
+        .map(|transformed| transformed.data)
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    query: RunningQuery,
+    started: Instant,
+    flag: CancelFlag,
+    cancel: oneshot::Sender<()>,
+}
+
+#[derive(Debug, Default)]
+pub struct QueryRegistry {
+    running: Mutex<HashMap<Ulid, Entry>>,
+}
+
+impl QueryRegistry {
+    fn running_mut(&self) -> MutexGuard<'_, HashMap<Ulid, Entry>> {
+        self.running.lock().unwrap()
+    }
+
+    /// Tracks a query until the returned handle is dropped
+    pub fn register(&self, user: Option<String>, stream: String, query: String) -> QueryHandle<'_> {
+        let id = Ulid::new();
+        let (cancel, cancelled) = oneshot::channel();
+        let flag = CancelFlag::new(id);
+        self.running_mut().insert(
+            id,
+            Entry {
+                query: RunningQuery {
+                    id,
+                    user,
+                    stream,
+                    query,
+                    started_at: Utc::now(),
+                    elapsed_ms: 0,
+                },
+                started: Instant::now(),
+                flag: flag.clone(),
+                cancel,
+            },
+        );
+        QueryHandle {
+            registry: self,
+            id,
+            flag,
+            cancelled,
+            is_cancelled: false,
+        }
+    }
+
+    /// Queries currently running, oldest first
+    pub fn running(&self) -> Vec<RunningQuery> {
+        let mut queries: Vec<_> = self
+            .running_mut()
+            .values()
+            .map(|entry| RunningQuery {
+                elapsed_ms: entry.started.elapsed().as_millis(),
+                ..entry.query.clone()
+            })
+            .collect();
+        queries.sort_by_key(|query| query.id);
+        queries
+    }
+
+    pub fn get(&self, id: Ulid) -> Option<RunningQuery> {
+        self.running().into_iter().find(|query| query.id == id)
+    }
+
+    /// Aborts the query, its request fails with [`Cancelled`].
+    /// False when no query with this id is running.
+    pub fn cancel(&self, id: Ulid) -> bool {
+        match self.running_mut().remove(&id) {
+            Some(entry) => {
+                entry.flag.cancelled.store(true, Ordering::Relaxed);
+                let _ = entry.cancel.send(());
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A registered query, removed from the registry when dropped
+#[derive(Debug)]
+pub struct QueryHandle<'a> {
+    registry: &'a QueryRegistry,
+    id: Ulid,
+    flag: CancelFlag,
+    cancelled: oneshot::Receiver<()>,
+    is_cancelled: bool,
+}
+
+impl QueryHandle<'_> {
+    pub fn id(&self) -> Ulid {
+        self.id
+    }
+
+    /// Flag to guard the physical plans of the query with
+    pub fn flag(&self) -> &CancelFlag {
+        &self.flag
+    }
+
+    /// Runs `fut` to completion unless the query is cancelled first, in
+    /// which case `fut` is dropped along with the execution it drives
+    pub async fn run<F: Future>(&mut self, fut: F) -> Result<F::Output, Cancelled> {
+        if self.is_cancelled {
+            return Err(Cancelled(self.id));
+        }
+        tokio::select! {
+            biased;
+            Ok(()) = &mut self.cancelled => {
+                self.is_cancelled = true;
+                Err(Cancelled(self.id))
+            }
+            output = fut => {
+                // guarded operators may fail on the flag before the cancel is received
+                if self.flag.check().is_err() {
+                    self.is_cancelled = true;
+                    return Err(Cancelled(self.id));
+                }
+                Ok(output)
+            }
+        }
+    }
+}
+
+impl Drop for QueryHandle<'_> {
+    fn drop(&mut self) {
+        self.registry.running_mut().remove(&self.id);
+    }
+}
+
+impl QueryHandle<'static> {
+    /// Keeps the query registered while `records` is consumed. Cancelling
+    /// drops the execution and ends the stream with an error.
+    pub fn stream(self, records: SendableRecordBatchStream) -> SendableRecordBatchStream {
+        Box::pin(CancellableStream {
+            schema: records.schema(),
+            records,
+            handle: self,
+        })
+    }
+}
+
+struct CancellableStream {
+    schema: SchemaRef,
+    records: SendableRecordBatchStream,
+    handle: QueryHandle<'static>,
+}
+
+impl Stream for CancellableStream {
+    type Item = Result<RecordBatch, DataFusionError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.handle.is_cancelled {
+            return Poll::Ready(None);
+        }
+        if let Poll::Ready(Ok(())) = Pin::new(&mut this.handle.cancelled).poll(cx) {
+            this.handle.is_cancelled = true;
+            // release the execution right away rather than when the response is dropped
+            this.records = Box::pin(EmptyRecordBatchStream::new(this.schema.clone()));
+            let cancelled = Cancelled(this.handle.id);
+            return Poll::Ready(Some(Err(DataFusionError::External(Box::new(cancelled)))));
+        }
+        let next = this.records.poll_next_unpin(cx);
+        if let Poll::Ready(Some(Err(_))) = &next {
+            // a guarded operator noticed the cancel first
+            this.handle.is_cancelled = this.handle.flag.check().is_err();
+        }
+        next
+    }
+}
+
+impl RecordBatchStream for CancellableStream {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+/// Passes the batches of `input` through until the query is cancelled
+#[derive(Debug)]
+struct CancellableExec {
+    input: Arc<dyn ExecutionPlan>,
+    flag: CancelFlag,
+}
+
+impl DisplayAs for CancellableExec {
+    fn fmt_as(&self, _: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CancellableExec")
+    }
+}
+
+impl ExecutionPlan for CancellableExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        self.input.properties()
+    }
+
+    fn maintains_input_order(&self) -> Vec<bool> {
+        vec![true]
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![self.input.clone()]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        mut children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Ok(Arc::new(Self {
+            input: children.swap_remove(0),
+            flag: self.flag.clone(),
+        }))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        self.flag.check()?;
+        let records = self.input.execute(partition, context)?;
+        Ok(Box::pin(FlaggedStream {
+            schema: records.schema(),
+            records,
+            flag: self.flag.clone(),
+        }))
+    }
+
+    fn statistics(&self) -> Result<datafusion::common::Statistics> {
+        self.input.statistics()
+    }
+}
+
+struct FlaggedStream {
+    schema: SchemaRef,
+    records: SendableRecordBatchStream,
+    flag: CancelFlag,
+}
+
+impl Stream for FlaggedStream {
+    type Item = Result<RecordBatch>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Err(err) = this.flag.check() {
+            // drops the operators below along with the memory they hold
+            this.records = Box::pin(EmptyRecordBatchStream::new(this.schema.clone()));
+            return Poll::Ready(Some(Err(err)));
+        }
+        this.records.poll_next_unpin(cx)
+    }
+}
+
+impl RecordBatchStream for FlaggedStream {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use arrow_array::{Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use datafusion::{
+        execution::{
+            memory_pool::{GreedyMemoryPool, MemoryPool},
+            runtime_env::{RuntimeConfig, RuntimeEnv},
+        },
+        physical_plan::{collect, execute_stream},
+        prelude::{SessionConfig, SessionContext},
+    };
+    use futures_util::StreamExt;
+    use once_cell::sync::Lazy;
+
+    use super::{QueryRegistry, QUERY_REGISTRY};
+
+    // a cross join of 20k rows with itself takes far longer than the test waits
+    const SLOW_QUERY: &str = "SELECT sum(a.v * b.v) FROM t a CROSS JOIN t b";
+
+    fn slow_context() -> (SessionContext, Arc<dyn MemoryPool>) {
+        let pool: Arc<dyn MemoryPool> = Arc::new(GreedyMemoryPool::new(1 << 30));
+        let runtime = RuntimeEnv::new(RuntimeConfig::new().with_memory_pool(pool.clone())).unwrap();
+        let ctx = SessionContext::new_with_config_rt(SessionConfig::new(), Arc::new(runtime));
+        let schema = Arc::new(Schema::new(vec![Field::new("v", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![Arc::new(Int64Array::from_iter_values(0..20_000))],
+        )
+        .unwrap();
+        ctx.register_batch("t", batch).unwrap();
+        (ctx, pool)
+    }
+
+    fn cancel_soon(registry: &'static QueryRegistry, id: ulid::Ulid) {
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            assert!(registry.cancel(id));
+        });
+    }
+
+    #[actix_web::test]
+    async fn cancelled_query_stops_promptly() {
+        static REGISTRY: Lazy<QueryRegistry> = Lazy::new(QueryRegistry::default);
+        let (ctx, pool) = slow_context();
+        let mut handle = REGISTRY.register(
+            Some("alice".to_owned()),
+            "t".to_owned(),
+            SLOW_QUERY.to_owned(),
+        );
+        let id = handle.id();
+        assert_eq!(REGISTRY.get(id).unwrap().user.as_deref(), Some("alice"));
+
+        let plan = ctx
+            .sql(SLOW_QUERY)
+            .await
+            .unwrap()
+            .create_physical_plan()
+            .await
+            .unwrap();
+        let plan = handle.flag().guard(plan).unwrap();
+
+        cancel_soon(&REGISTRY, id);
+        let started = Instant::now();
+        // the cross join never waits, only the guarded operators notice the cancel
+        let result = handle.run(collect(plan, ctx.task_ctx())).await;
+
+        assert_eq!(result.unwrap_err().0, id);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(REGISTRY.running().is_empty());
+        assert_eq!(pool.reserved(), 0);
+        drop(handle);
+        assert!(!REGISTRY.cancel(id));
+    }
+
+    #[actix_web::test]
+    async fn cancelled_stream_ends_with_an_error() {
+        static REGISTRY: Lazy<QueryRegistry> = Lazy::new(QueryRegistry::default);
+        let (ctx, pool) = slow_context();
+        let handle = REGISTRY.register(None, "t".to_owned(), SLOW_QUERY.to_owned());
+        let id = handle.id();
+        let plan = ctx
+            .sql(SLOW_QUERY)
+            .await
+            .unwrap()
+            .create_physical_plan()
+            .await
+            .unwrap();
+        let plan = handle.flag().guard(plan).unwrap();
+        let records = execute_stream(plan, ctx.task_ctx()).unwrap();
+        let mut records = handle.stream(records);
+
+        cancel_soon(&REGISTRY, id);
+        let started = Instant::now();
+        let err = records.next().await.unwrap().unwrap_err();
+        assert!(err.to_string().contains(&id.to_string()), "{err}");
+        assert!(records.next().await.is_none());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(pool.reserved(), 0);
+
+        drop(records);
+        assert!(REGISTRY.running().is_empty());
+    }
+
+    #[test]
+    fn finished_queries_leave_the_registry() {
+        let handle = QUERY_REGISTRY.register(None, "app".to_owned(), "SELECT 1".to_owned());
+        let id = handle.id();
+        assert!(QUERY_REGISTRY.get(id).is_some());
+        drop(handle);
+        assert!(QUERY_REGISTRY.get(id).is_none());
+    }
+}
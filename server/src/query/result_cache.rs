@@ -0,0 +1,445 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! In memory cache of query results, so dashboards re-running the same query
+//! over the same time range don't execute it again
+
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use datafusion::arrow::record_batch::RecordBatch;
+use lru::LruCache;
+use once_cell::sync::Lazy;
+
+use crate::option::CONFIG;
+
+/// Result cache of this server, disabled unless a TTL is configured
+pub static RESULT_CACHE: Lazy<Option<ResultCache>> = Lazy::new(|| {
+    CONFIG
+        .parseable
+        .query_result_cache_ttl
+        .map(|ttl| ResultCache::new(ttl, CONFIG.parseable.query_result_cache_size))
+});
+
+/// Queries ending within this long of now may still gain events
+pub const HOT_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Longest time a result for a query ending in the hot window is reused
+pub const HOT_TTL: Duration = Duration::from_secs(5);
+
+/// Collapses whitespace outside quotes and drops a trailing semicolon, so
+/// formatting differences between clients share a cache entry
+pub fn normalize_sql(sql: &str) -> String {
+    let mut normalized = String::with_capacity(sql.len());
+    let mut quote = None;
+    let mut pending_space = false;
+    for c in sql.trim().trim_end_matches(';').trim_end().chars() {
+        match quote {
+            Some(open) => {
+                if c == open {
+                    quote = None;
+                }
+            }
+            None if c.is_whitespace() => {
+                pending_space = true;
+                continue;
+            }
+            None => {
+                if c == '\'' || c == '"' {
+                    quote = Some(c);
+                }
+            }
+        }
+        if pending_space {
+            normalized.push(' ');
+            pending_space = false;
+        }
+        normalized.push(c);
+    }
+    normalized
+}
+
+/// Identifies results that can be reused for a query
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResultKey {
+    sql: String,
+    streams: Vec<String>,
+    start: i64,
+    end: i64,
+    // filter tags the user's permissions restrict the query to
+    scope: Vec<String>,
+    hot: bool,
+    // dates are truncated and extracted in the time zone of the query
+    time_zone: Option<String>,
+}
+
+impl ResultKey {
+    pub fn new(
+        sql: &str,
+        streams: impl IntoIterator<Item = String>,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        scope: Option<&[String]>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let hot = now.signed_duration_since(end).to_std().unwrap_or_default() < HOT_WINDOW;
+        // relative ranges like the last 10 minutes move with every request, aligning
+        // them lets requests within the same interval share a result
+        let align = if hot { HOT_TTL } else { Duration::from_secs(1) }.as_millis() as i64;
+        let aligned = |time: DateTime<Utc>| time.timestamp_millis().div_euclid(align) * align;
+
+        let mut streams: Vec<_> = streams.into_iter().collect();
+        streams.sort();
+        streams.dedup();
+        let mut scope = scope.map(<[String]>::to_vec).unwrap_or_default();
+        scope.sort();
+
+        Self {
+            sql: normalize_sql(sql),
+            streams,
+            start: aligned(start),
+            end: aligned(end),
+            scope,
+            hot,
+            time_zone: None,
+        }
+    }
+
+    /// Key of the query asked for in `time_zone`, its results differ from UTC
+    pub fn with_time_zone(mut self, time_zone: Option<&str>) -> Self {
+        self.time_zone = time_zone.map(str::to_owned);
+        self
+    }
+}
+
+/// Records and field names of an executed query
+#[derive(Debug)]
+pub struct CachedResult {
+    pub records: Vec<RecordBatch>,
+    pub fields: Vec<String>,
+    size: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    result: Arc<CachedResult>,
+    expires_at: Instant,
+}
+
+#[derive(Debug)]
+struct Entries {
+    lru: LruCache<ResultKey, Entry>,
+    size: u64,
+}
+
+impl Entries {
+    fn pop(&mut self, key: &ResultKey) {
+        if let Some(entry) = self.lru.pop(key) {
+            self.size -= entry.result.size;
+        }
+    }
+}
+
+/// Bounded LRU of query results, entries expire after the TTL
+#[derive(Debug)]
+pub struct ResultCache {
+    ttl: Duration,
+    capacity: u64,
+    entries: Mutex<Entries>,
+}
+
+impl ResultCache {
+    pub fn new(ttl: Duration, capacity: u64) -> Self {
+        Self {
+            ttl,
+            capacity,
+            entries: Mutex::new(Entries {
+                lru: LruCache::unbounded(),
+                size: 0,
+            }),
+        }
+    }
+
+    pub fn get(&self, key: &ResultKey) -> Option<Arc<CachedResult>> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.lru.get(key) {
+            Some(entry) if entry.expires_at > Instant::now() => Some(entry.result.clone()),
+            Some(_) => {
+                entries.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Caches the result unless it alone exceeds the capacity, evicting the least
+    /// recently used entries to make room
+    pub fn insert(
+        &self,
+        key: ResultKey,
+        records: Vec<RecordBatch>,
+        fields: Vec<String>,
+    ) -> Arc<CachedResult> {
+        let size = records
+            .iter()
+            .map(|batch| batch.get_array_memory_size() as u64)
+            .chain(fields.iter().map(|field| field.len() as u64))
+            .sum();
+        let result = Arc::new(CachedResult {
+            records,
+            fields,
+            size,
+        });
+        if size > self.capacity {
+            return result;
+        }
+
+        let ttl = if key.hot {
+            self.ttl.min(HOT_TTL)
+        } else {
+            self.ttl
+        };
+        let mut entries = self.entries.lock().unwrap();
+        entries.pop(&key);
+        entries.lru.put(
+            key,
+            Entry {
+                result: result.clone(),
+                expires_at: Instant::now() + ttl,
+            },
+        );
+        entries.size += size;
+        while entries.size > self.capacity {
+            match entries.lru.pop_lru() {
+                Some((_, evicted)) => entries.size -= evicted.result.size,
+                None => break,
+            }
+        }
+        result
+    }
+
+    /// Serves the result from the cache or runs `execute` and caches its result,
+    /// the flag tells whether it was a cache hit
+    pub async fn get_or_execute<E>(
+        &self,
+        key: ResultKey,
+        execute: impl Future<Output = Result<(Vec<RecordBatch>, Vec<String>), E>>,
+    ) -> Result<(Arc<CachedResult>, bool), E> {
+        if let Some(result) = self.get(&key) {
+            return Ok((result, true));
+        }
+        let (records, fields) = execute.await?;
+        Ok((self.insert(key, records, fields), false))
+    }
+
+    /// Drops every result read from `stream`, e.g. after retention deleted its data
+    pub fn invalidate_stream(&self, stream: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        let stale: Vec<_> = entries
+            .lru
+            .iter()
+            .filter(|(key, _)| key.streams.iter().any(|s| s == stream))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &stale {
+            entries.pop(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use arrow_array::{Int64Array, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::{normalize_sql, ResultCache, ResultKey, HOT_TTL};
+
+    fn batch(rows: i64) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::Int64, false)]));
+        RecordBatch::try_new(
+            schema,
+            vec![Arc::new(Int64Array::from_iter_values(0..rows))],
+        )
+        .unwrap()
+    }
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 1, hour, 0, 0).unwrap()
+    }
+
+    fn key(sql: &str, stream: &str, end: DateTime<Utc>) -> ResultKey {
+        ResultKey::new(sql, [stream.to_string()], at(0), end, None, at(12))
+    }
+
+    #[actix_web::test]
+    async fn repeated_query_is_served_from_cache() {
+        let cache = ResultCache::new(Duration::from_secs(60), 1 << 20);
+        let executions = AtomicUsize::new(0);
+        let run = |key| {
+            cache.get_or_execute(key, async {
+                executions.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, ()>((vec![batch(10)], vec!["n".to_string()]))
+            })
+        };
+
+        let (first, hit) = run(key("SELECT * FROM app", "app", at(6))).await.unwrap();
+        assert!(!hit);
+        let (second, hit) = run(key("select *  from app;", "app", at(6))).await.unwrap();
+        // differs in case only, identifiers may be case sensitive
+        assert!(!hit);
+        assert_eq!(executions.load(Ordering::SeqCst), 2);
+
+        let (third, hit) = run(key("SELECT *\n  FROM app;", "app", at(6)))
+            .await
+            .unwrap();
+        assert!(hit);
+        assert!(Arc::ptr_eq(&first, &third));
+        assert!(!Arc::ptr_eq(&second, &third));
+        assert_eq!(third.records[0].num_rows(), 10);
+
+        let (_, hit) = run(key("SELECT * FROM app", "app", at(7))).await.unwrap();
+        assert!(!hit, "a later end time is a different range");
+        assert_eq!(executions.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn sql_is_normalized_outside_quotes() {
+        assert_eq!(
+            normalize_sql("  SELECT  a,\tb\nFROM \"my  stream\" WHERE c = 'x  y' ; "),
+            "SELECT a, b FROM \"my  stream\" WHERE c = 'x  y'"
+        );
+    }
+
+    #[test]
+    fn key_covers_streams_scope_and_time() {
+        let now = at(12);
+        let base = key("SELECT 1", "app", at(6));
+        assert_eq!(
+            base,
+            ResultKey::new(
+                "SELECT 1",
+                ["app".to_string(), "app".to_string()],
+                at(0),
+                at(6),
+                Some(&[]),
+                now
+            )
+        );
+        assert_ne!(base, key("SELECT 1", "web", at(6)));
+        let scoped = ResultKey::new(
+            "SELECT 1",
+            ["app".to_string()],
+            at(0),
+            at(6),
+            Some(&["team=a".to_string()]),
+            now,
+        );
+        assert_ne!(base, scoped);
+
+        // sub second jitter of relative time ranges is aligned away
+        let jitter = chrono::Duration::milliseconds(300);
+        assert_eq!(base, key("SELECT 1", "app", at(6) + jitter));
+    }
+
+    #[test]
+    fn hot_queries_expire_sooner() {
+        let cache = ResultCache::new(Duration::from_secs(3600), 1 << 20);
+        let now = Utc::now();
+        let hot = ResultKey::new("SELECT 1", ["app".to_string()], at(0), now, None, now);
+        let cold = key("SELECT 1", "app", at(6));
+        assert!(hot.hot && !cold.hot);
+
+        cache.insert(hot.clone(), vec![batch(1)], vec![]);
+        cache.insert(cold.clone(), vec![batch(1)], vec![]);
+        let mut entries = cache.entries.lock().unwrap();
+        let hot_expiry = entries.lru.get(&hot).unwrap().expires_at;
+        let cold_expiry = entries.lru.get(&cold).unwrap().expires_at;
+        assert!(hot_expiry <= std::time::Instant::now() + HOT_TTL);
+        assert!(cold_expiry > std::time::Instant::now() + Duration::from_secs(3000));
+    }
+
+    #[test]
+    fn expired_entries_miss() {
+        let cache = ResultCache::new(Duration::ZERO, 1 << 20);
+        let key = key("SELECT 1", "app", at(6));
+        cache.insert(key.clone(), vec![batch(1)], vec![]);
+        assert!(cache.get(&key).is_none());
+        assert_eq!(cache.entries.lock().unwrap().size, 0);
+    }
+
+    #[test]
+    fn capacity_evicts_least_recently_used() {
+        let size = batch(1000).get_array_memory_size() as u64;
+        let cache = ResultCache::new(Duration::from_secs(60), size * 2);
+        let (a, b, c) = (
+            key("SELECT a", "app", at(6)),
+            key("SELECT b", "app", at(6)),
+            key("SELECT c", "app", at(6)),
+        );
+        cache.insert(a.clone(), vec![batch(1000)], vec![]);
+        cache.insert(b.clone(), vec![batch(1000)], vec![]);
+        assert!(cache.get(&a).is_some());
+        cache.insert(c.clone(), vec![batch(1000)], vec![]);
+
+        assert!(cache.get(&a).is_some());
+        assert!(cache.get(&b).is_none());
+        assert!(cache.get(&c).is_some());
+
+        // larger than the whole cache
+        let huge = key("SELECT huge", "app", at(6));
+        cache.insert(huge.clone(), vec![batch(10_000)], vec![]);
+        assert!(cache.get(&huge).is_none());
+        assert!(cache.get(&a).is_some());
+    }
+
+    #[test]
+    fn invalidating_a_stream_keeps_other_streams() {
+        let cache = ResultCache::new(Duration::from_secs(60), 1 << 20);
+        let app = key("SELECT 1", "app", at(6));
+        let web = key("SELECT 1", "web", at(6));
+        let joined = ResultKey::new(
+            "SELECT 1",
+            ["web".to_string(), "app".to_string()],
+            at(0),
+            at(6),
+            None,
+            at(12),
+        );
+        for key in [&app, &web, &joined] {
+            cache.insert(key.clone(), vec![batch(1)], vec![]);
+        }
+
+        cache.invalidate_stream("app");
+        assert!(cache.get(&app).is_none());
+        assert!(cache.get(&joined).is_none());
+        assert!(cache.get(&web).is_some());
+    }
+}
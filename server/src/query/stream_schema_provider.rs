@@ -18,7 +18,7 @@
 
 use crate::Mode;
 use crate::{
-    catalog::snapshot::{self, Snapshot},
+    catalog::snapshot::Snapshot,
     storage::{ObjectStoreFormat, STREAM_ROOT_DIRECTORY},
 };
 use arrow_array::RecordBatch;
@@ -30,18 +30,18 @@ use datafusion::logical_expr::utils::conjunction;
 use datafusion::{
     catalog::schema::SchemaProvider,
     common::{
-        tree_node::{TreeNode, TreeNodeRecursion},
+        tree_node::{Transformed, TreeNode, TreeNodeRecursion},
         ToDFSchema,
     },
     datasource::{
         file_format::{parquet::ParquetFormat, FileFormat},
         listing::PartitionedFile,
         physical_plan::FileScanConfig,
-        MemTable, TableProvider,
+        provider_as_source, source_as_provider, MemTable, TableProvider,
     },
     error::{DataFusionError, Result as DataFusionResult},
     execution::{context::SessionState, object_store::ObjectStoreUrl},
-    logical_expr::{BinaryExpr, Operator, TableProviderFilterPushDown, TableType},
+    logical_expr::{BinaryExpr, LogicalPlan, Operator, TableProviderFilterPushDown, TableType},
     physical_expr::{create_physical_expr, PhysicalSortExpr},
     physical_plan::{self, empty::EmptyExec, union::UnionExec, ExecutionPlan, Statistics},
     prelude::Expr,
@@ -52,7 +52,12 @@ use futures_util::{stream::FuturesOrdered, StreamExt, TryFutureExt, TryStreamExt
 use itertools::Itertools;
 use object_store::{path::Path, ObjectStore};
 use relative_path::RelativePathBuf;
-use std::{any::Any, collections::HashMap, ops::Bound, sync::Arc};
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    ops::Bound,
+    sync::Arc,
+};
 use url::Url;
 
 use crate::{
@@ -68,6 +73,7 @@ use crate::{
 };
 
 use super::listing_table_builder::ListingTableBuilder;
+use super::partition_filter::PartitionFilter;
 use crate::catalog::Snapshot as CatalogSnapshot;
 
 // schema provider for stream based on global data
@@ -91,6 +97,7 @@ impl SchemaProvider for GlobalSchemaProvider {
                 schema: STREAM_INFO.schema(name).unwrap(),
                 stream: name.to_owned(),
                 url: self.storage.store_url(),
+                skipped_files: Arc::default(),
             })))
         } else {
             Ok(None)
@@ -102,13 +109,94 @@ impl SchemaProvider for GlobalSchemaProvider {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct StandardTableProvider {
     schema: SchemaRef,
     // prefix under which to find snapshot
     stream: String,
     // url to find right instance of object store
     url: Url,
+    // manifest entries left out of the scan, see `without_files`
+    skipped_files: Arc<HashSet<String>>,
+}
+
+/// Rewrites scans of streams in `plan` to leave out the manifest entries in `files`
+pub fn without_files(
+    plan: LogicalPlan,
+    files: Arc<HashSet<String>>,
+) -> Result<LogicalPlan, DataFusionError> {
+    plan.transform(&|plan| match plan {
+        LogicalPlan::TableScan(mut scan) => {
+            let provider = source_as_provider(&scan.source)?;
+            let Some(provider) = provider.as_any().downcast_ref::<StandardTableProvider>() else {
+                return Ok(Transformed::no(LogicalPlan::TableScan(scan)));
+            };
+            scan.source = provider_as_source(Arc::new(StandardTableProvider {
+                skipped_files: files.clone(),
+                ..provider.clone()
+            }));
+            Ok(Transformed::yes(LogicalPlan::TableScan(scan)))
+        }
+        plan => Ok(Transformed::no(plan)),
+    })
+    .map(|transformed| transformed.data)
+}
+
+/// Manifest entries of the files that may hold events of `stream` matching
+/// `filters`, None when the time range reaches data that predates manifests
+pub async fn manifest_files(
+    stream: &str,
+    filters: &[Expr],
+    state: &SessionState,
+) -> Result<Option<Vec<catalog::manifest::File>>, DataFusionError> {
+    let glob_storage = CONFIG.storage().get_object_store();
+    let object_store_format = glob_storage
+        .get_object_store_format(stream)
+        .await
+        .map_err(|err| DataFusionError::Plan(err.to_string()))?;
+    let time_filters = extract_primary_filter(filters, object_store_format.time_partition.clone());
+    let snapshot = merged_snapshot(stream, glob_storage.as_ref(), object_store_format).await;
+    if time_filters.is_empty() || is_overlapping_query(&snapshot.manifest_list, &time_filters) {
+        return Ok(None);
+    }
+
+    let object_store = state
+        .runtime_env()
+        .object_store_registry
+        .get_store(&glob_storage.store_url())?;
+    collect_from_snapshot(&snapshot, &time_filters, object_store, filters, None)
+        .await
+        .map(Some)
+}
+
+// on a querier the snapshot is merged from the stream.json of every ingestor
+async fn merged_snapshot(
+    stream: &str,
+    glob_storage: &(dyn ObjectStorage + Send),
+    object_store_format: ObjectStoreFormat,
+) -> Snapshot {
+    if CONFIG.parseable.mode != Mode::Query {
+        return object_store_format.snapshot;
+    }
+    let mut merged_snapshot = Snapshot::default();
+    let path = RelativePathBuf::from_iter([stream, STREAM_ROOT_DIRECTORY]);
+    let obs = glob_storage
+        .get_objects(
+            Some(&path),
+            Box::new(|file_name| file_name.ends_with("stream.json")),
+        )
+        .await;
+    if let Ok(obs) = obs {
+        for ob in obs {
+            if let Ok(object_store_format) = serde_json::from_slice::<ObjectStoreFormat>(&ob) {
+                let snapshot = object_store_format.snapshot;
+                for manifest in snapshot.manifest_list {
+                    merged_snapshot.manifest_list.push(manifest);
+                }
+            }
+        }
+    }
+    merged_snapshot
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -236,7 +324,7 @@ fn partitioned_files(
                 .entry(col.name)
                 .and_modify(|x| {
                     if let Some((stats, col_stats)) = x.as_ref().cloned().zip(col.stats.clone()) {
-                        *x = Some(stats.update(col_stats));
+                        *x = stats.try_update(col_stats);
                     }
                 })
                 .or_insert_with(|| col.stats.as_ref().cloned());
@@ -304,7 +392,9 @@ impl TableProvider for StandardTableProvider {
             .get_object_store_format(&self.stream)
             .await
             .map_err(|err| DataFusionError::Plan(err.to_string()))?;
-        let time_partition = object_store_format.time_partition;
+        let time_partition = object_store_format.time_partition.clone();
+        let partition_filter =
+            PartitionFilter::new(filters, object_store_format.custom_partition.as_deref());
         let time_filters = extract_primary_filter(filters, time_partition.clone());
         if time_filters.is_empty() {
             return Err(DataFusionError::Plan("potentially unbounded query on time range. Table scanning requires atleast one time bound".to_string()));
@@ -322,30 +412,8 @@ impl TableProvider for StandardTableProvider {
                 );
             }
         };
-        let mut merged_snapshot: snapshot::Snapshot = Snapshot::default();
-        if CONFIG.parseable.mode == Mode::Query {
-            let path = RelativePathBuf::from_iter([&self.stream, STREAM_ROOT_DIRECTORY]);
-            let obs = glob_storage
-                .get_objects(
-                    Some(&path),
-                    Box::new(|file_name| file_name.ends_with("stream.json")),
-                )
-                .await;
-            if let Ok(obs) = obs {
-                for ob in obs {
-                    if let Ok(object_store_format) =
-                        serde_json::from_slice::<ObjectStoreFormat>(&ob)
-                    {
-                        let snapshot = object_store_format.snapshot;
-                        for manifest in snapshot.manifest_list {
-                            merged_snapshot.manifest_list.push(manifest);
-                        }
-                    }
-                }
-            }
-        } else {
-            merged_snapshot = object_store_format.snapshot;
-        }
+        let merged_snapshot =
+            merged_snapshot(&self.stream, glob_storage.as_ref(), object_store_format).await;
 
         // Is query timerange is overlapping with older data.
         if is_overlapping_query(&merged_snapshot.manifest_list, &time_filters) {
@@ -361,6 +429,7 @@ impl TableProvider for StandardTableProvider {
                 filters,
                 limit,
                 time_partition.clone(),
+                &partition_filter,
             )
             .await;
         }
@@ -373,6 +442,10 @@ impl TableProvider for StandardTableProvider {
             limit,
         )
         .await?;
+        manifest_files.retain(|file| !self.skipped_files.contains(&file.file_path));
+        if !partition_filter.is_empty() {
+            manifest_files.retain(|file| partition_filter.matches(&file.file_path));
+        }
 
         if manifest_files.is_empty() {
             return final_plan(vec![memory_exec], projection, self.schema.clone());
@@ -474,11 +547,12 @@ async fn legacy_listing_table(
     filters: &[Expr],
     limit: Option<usize>,
     time_partition: Option<String>,
+    partition_filter: &PartitionFilter,
 ) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
     let remote_table = ListingTableBuilder::new(stream)
         .populate_via_listing(glob_storage.clone(), object_store, time_filters)
         .and_then(|builder| async {
-            let table = builder.build(
+            let table = builder.prune(partition_filter).build(
                 schema.clone(),
                 |x| glob_storage.query_prefixes(x),
                 time_partition,
@@ -534,7 +608,7 @@ pub enum PartialTimeFilter {
 }
 
 impl PartialTimeFilter {
-    fn try_from_expr(expr: &Expr, time_partition: Option<String>) -> Option<Self> {
+    pub fn try_from_expr(expr: &Expr, time_partition: Option<String>) -> Option<Self> {
         let Expr::BinaryExpr(binexpr) = expr else {
             return None;
         };
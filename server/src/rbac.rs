@@ -100,6 +100,10 @@ impl Users {
         sessions().get(session).cloned().unwrap_or_default()
     }
 
+    pub fn get_username(&self, session: &SessionKey) -> Option<String> {
+        sessions().get_user(session).cloned()
+    }
+
     pub fn session_exists(&self, session: &SessionKey) -> bool {
         sessions().get(session).is_some()
     }
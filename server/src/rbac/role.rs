@@ -32,6 +32,21 @@ pub enum Action {
     PutRetention,
     GetCacheEnabled,
     PutCacheEnabled,
+    GetProtobuf,
+    PutProtobuf,
+    GetTimestampExtraction,
+    PutTimestampExtraction,
+    GetFlattening,
+    PutFlattening,
+    GetTypeWidening,
+    PutTypeWidening,
+    GetDeadLetterStream,
+    PutDeadLetterStream,
+    ReplayDeadLetters,
+    GetTransforms,
+    PutTransforms,
+    GetGrok,
+    PutGrok,
     PutAlert,
     GetAlert,
     PutUser,
@@ -46,6 +61,7 @@ pub enum Action {
     GetAbout,
     QueryLLM,
     ListCluster,
+    GetConnectors,
     ListClusterMetrics,
     Deleteingestor,
     All,
@@ -58,8 +74,23 @@ pub enum Action {
     GetFilter,
     CreateFilter,
     DeleteFilter,
+    ListCorrelation,
+    GetCorrelation,
+    CreateCorrelation,
+    DeleteCorrelation,
+    ListSavedQuery,
+    GetSavedQuery,
+    CreateSavedQuery,
+    DeleteSavedQuery,
+    ListScheduledQuery,
+    GetScheduledQuery,
+    CreateScheduledQuery,
+    DeleteScheduledQuery,
     ListCache,
     RemoveCache,
+    GetQueryConcurrency,
+    PutQueryConcurrency,
+    ManageQueries,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -116,6 +147,7 @@ impl RoleBuilder {
                 | Action::ListStream
                 | Action::ListCluster
                 | Action::ListClusterMetrics
+                | Action::GetConnectors
                 | Action::Deleteingestor
                 | Action::ListDashboard
                 | Action::GetDashboard
@@ -125,8 +157,23 @@ impl RoleBuilder {
                 | Action::ListFilter
                 | Action::CreateFilter
                 | Action::DeleteFilter
+                | Action::ListCorrelation
+                | Action::GetCorrelation
+                | Action::CreateCorrelation
+                | Action::DeleteCorrelation
+                | Action::ListSavedQuery
+                | Action::GetSavedQuery
+                | Action::CreateSavedQuery
+                | Action::DeleteSavedQuery
+                | Action::ListScheduledQuery
+                | Action::GetScheduledQuery
+                | Action::CreateScheduledQuery
+                | Action::DeleteScheduledQuery
                 | Action::ListCache
                 | Action::RemoveCache
+                | Action::GetQueryConcurrency
+                | Action::PutQueryConcurrency
+                | Action::ManageQueries
                 | Action::GetAnalytics => Permission::Unit(action),
                 Action::Ingest
                 | Action::GetSchema
@@ -135,6 +182,21 @@ impl RoleBuilder {
                 | Action::PutRetention
                 | Action::GetCacheEnabled
                 | Action::PutCacheEnabled
+                | Action::GetProtobuf
+                | Action::PutProtobuf
+                | Action::GetTimestampExtraction
+                | Action::PutTimestampExtraction
+                | Action::GetFlattening
+                | Action::PutFlattening
+                | Action::GetTypeWidening
+                | Action::PutTypeWidening
+                | Action::GetDeadLetterStream
+                | Action::PutDeadLetterStream
+                | Action::ReplayDeadLetters
+                | Action::GetTransforms
+                | Action::PutTransforms
+                | Action::GetGrok
+                | Action::PutGrok
                 | Action::PutAlert
                 | Action::GetAlert
                 | Action::All => Permission::Stream(action, self.stream.clone().unwrap()),
@@ -206,9 +268,24 @@ pub mod model {
                 Action::PutRetention,
                 Action::PutCacheEnabled,
                 Action::GetCacheEnabled,
+                Action::PutProtobuf,
+                Action::GetProtobuf,
+                Action::PutTimestampExtraction,
+                Action::GetTimestampExtraction,
+                Action::PutFlattening,
+                Action::GetFlattening,
+                Action::PutTypeWidening,
+                Action::GetTypeWidening,
+                Action::PutDeadLetterStream,
+                Action::GetDeadLetterStream,
+                Action::PutTransforms,
+                Action::GetTransforms,
+                Action::PutGrok,
+                Action::GetGrok,
                 Action::PutAlert,
                 Action::GetAlert,
                 Action::GetAbout,
+                Action::GetConnectors,
                 Action::QueryLLM,
             ],
             stream: Some("*".to_string()),
@@ -226,6 +303,13 @@ pub mod model {
                 Action::GetSchema,
                 Action::GetStats,
                 Action::GetRetention,
+                Action::GetProtobuf,
+                Action::GetTimestampExtraction,
+                Action::GetFlattening,
+                Action::GetTypeWidening,
+                Action::GetDeadLetterStream,
+                Action::GetTransforms,
+                Action::GetGrok,
                 Action::PutAlert,
                 Action::GetAlert,
                 Action::GetAbout,
@@ -245,6 +329,13 @@ pub mod model {
                 Action::GetSchema,
                 Action::GetStats,
                 Action::GetRetention,
+                Action::GetProtobuf,
+                Action::GetTimestampExtraction,
+                Action::GetFlattening,
+                Action::GetTypeWidening,
+                Action::GetDeadLetterStream,
+                Action::GetTransforms,
+                Action::GetGrok,
                 Action::GetAlert,
                 Action::GetAbout,
                 Action::QueryLLM,
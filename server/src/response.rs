@@ -18,15 +18,26 @@
 
 use crate::{
     handlers::http::query::QueryError,
+    option::Compression,
     utils::arrow::{
         flight::{into_flight_data, DoGetStream},
         record_batches_to_json,
     },
 };
 use actix_web::{web, Responder};
-use datafusion::arrow::record_batch::RecordBatch;
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::SchemaRef;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use datafusion::{
+    arrow::{csv, record_batch::RecordBatch},
+    error::DataFusionError,
+    execution::SendableRecordBatchStream,
+};
+use futures::{stream, Stream, StreamExt};
 use itertools::Itertools;
-use serde_json::{json, Value};
+use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
+use serde_json::{json, Map, Value};
 use tonic::{Response, Status};
 
 pub struct QueryResponse {
@@ -69,3 +80,603 @@ impl QueryResponse {
         into_flight_data(self.records)
     }
 }
+
+/// Field of the line a failed NDJSON stream ends with, holding the error message
+pub const STREAM_ERROR_KEY: &str = "p_error";
+
+/// Encoding of a query response that is written out batch by batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFormat {
+    /// One JSON object per line
+    NdJson,
+    /// Arrow IPC streaming format
+    ArrowIpc,
+    /// RFC 4180 CSV with a header row, downloaded as a file
+    Csv { delimiter: u8 },
+    /// A single Parquet file, downloaded as a file
+    Parquet,
+}
+
+impl StreamFormat {
+    pub const NDJSON_CONTENT_TYPE: &'static str = "application/x-ndjson";
+    pub const ARROW_IPC_CONTENT_TYPE: &'static str = "application/vnd.apache.arrow.stream";
+    pub const CSV_CONTENT_TYPE: &'static str = "text/csv";
+    pub const PARQUET_CONTENT_TYPE: &'static str = "application/vnd.apache.parquet";
+
+    /// Streaming format asked for by the `format` query parameter, the Accept
+    /// header or the `stream` query parameter, which defaults to NDJSON. None
+    /// keeps the buffered JSON response.
+    ///
+    /// `format` is either `csv` or `parquet`, CSV fields are separated by
+    /// `delimiter` which defaults to a comma.
+    pub fn requested(
+        accept: Option<&str>,
+        format: Option<&str>,
+        delimiter: Option<&str>,
+        stream_param: bool,
+    ) -> Result<Option<Self>, QueryError> {
+        let delimiter =
+            match delimiter.map(str::as_bytes) {
+                None => b',',
+                Some(&[byte]) if byte.is_ascii() && !matches!(byte, b'"' | b'\r' | b'\n') => byte,
+                Some(_) => return Err(QueryError::MalformedQuery(
+                    "delimiter must be a single ASCII character other than a quote or line break",
+                )),
+            };
+        match format {
+            Some("csv") => return Ok(Some(Self::Csv { delimiter })),
+            Some("parquet") => return Ok(Some(Self::Parquet)),
+            Some(_) => return Err(QueryError::MalformedQuery("format must be csv or parquet")),
+            None => {}
+        }
+
+        let accepts = |content_type| {
+            accept.is_some_and(|accept| {
+                accept
+                    .split(',')
+                    .any(|media| media.split(';').next().unwrap_or_default().trim() == content_type)
+            })
+        };
+        if accepts(Self::ARROW_IPC_CONTENT_TYPE) {
+            Ok(Some(Self::ArrowIpc))
+        } else if accepts(Self::CSV_CONTENT_TYPE) {
+            Ok(Some(Self::Csv { delimiter }))
+        } else if accepts(Self::PARQUET_CONTENT_TYPE) {
+            Ok(Some(Self::Parquet))
+        } else if accepts(Self::NDJSON_CONTENT_TYPE) || stream_param {
+            Ok(Some(Self::NdJson))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::NdJson => Self::NDJSON_CONTENT_TYPE,
+            Self::ArrowIpc => Self::ARROW_IPC_CONTENT_TYPE,
+            Self::Csv { .. } => Self::CSV_CONTENT_TYPE,
+            Self::Parquet => Self::PARQUET_CONTENT_TYPE,
+        }
+    }
+
+    /// Name suggested for saving an export of `stream` between `start` and
+    /// `end`, None for formats that aren't downloaded as a file
+    pub fn file_name(
+        &self,
+        stream: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Option<String> {
+        let extension = match self {
+            Self::NdJson | Self::ArrowIpc => return None,
+            Self::Csv { .. } => "csv",
+            Self::Parquet => "parquet",
+        };
+        // colons aren't allowed in file names on every platform
+        let time_format = "%Y%m%dT%H%M%SZ";
+        Some(format!(
+            "{stream}_{}_{}.{extension}",
+            start.format(time_format),
+            end.format(time_format)
+        ))
+    }
+}
+
+/// Encodes record batches into response chunks as the query produces them.
+///
+/// Only one batch is held in memory at a time, except for Parquet which
+/// buffers up to a row group before writing it out compressed with
+/// `parquet_compression`. When the query fails midway an NDJSON stream ends with
+/// a `{"p_error": "<message>"}` line, while the other formats are cut off so
+/// that readers fail: Arrow IPC before its end of stream marker and Parquet
+/// before its footer.
+pub fn stream_records(
+    records: SendableRecordBatchStream,
+    format: StreamFormat,
+    fill_null_fields: Option<Vec<String>>,
+    parquet_compression: Compression,
+) -> impl Stream<Item = Result<Bytes, QueryError>> {
+    let schema = records.schema();
+    let encoder = match format {
+        StreamFormat::NdJson => Ok(Encoder::NdJson(fill_null_fields)),
+        StreamFormat::ArrowIpc => StreamWriter::try_new(Vec::new(), &schema)
+            .map(|writer| Encoder::ArrowIpc(Box::new(writer)))
+            .map_err(|err| QueryError::Datafusion(err.into())),
+        StreamFormat::Csv { delimiter } => Ok(Encoder::Csv {
+            schema,
+            delimiter,
+            header_written: false,
+        }),
+        StreamFormat::Parquet => {
+            let props = WriterProperties::builder()
+                .set_compression(parquet_compression.into())
+                .build();
+            ArrowWriter::try_new(Vec::new(), schema, Some(props))
+                .map(|writer| Encoder::Parquet(Box::new(writer)))
+                .map_err(|err| QueryError::Datafusion(err.into()))
+        }
+    };
+    stream::unfold(Some((records, encoder)), |state| async move {
+        let (mut records, encoder) = state?;
+        let mut encoder = match encoder {
+            Ok(encoder) => encoder,
+            Err(err) => return Some((Err(err), None)),
+        };
+        match records.next().await {
+            Some(Ok(batch)) => {
+                let chunk = encoder.write(&batch);
+                Some((chunk, Some((records, Ok(encoder)))))
+            }
+            Some(Err(err)) => Some((encoder.fail(err), None)),
+            None => Some((encoder.finish(), None)),
+        }
+    })
+    // an empty chunk would read as the end of the chunked body
+    .filter(|chunk| std::future::ready(!matches!(chunk, Ok(bytes) if bytes.is_empty())))
+}
+
+enum Encoder {
+    NdJson(Option<Vec<String>>),
+    ArrowIpc(Box<StreamWriter<Vec<u8>>>),
+    Csv {
+        schema: SchemaRef,
+        delimiter: u8,
+        header_written: bool,
+    },
+    Parquet(Box<ArrowWriter<Vec<u8>>>),
+}
+
+impl Encoder {
+    fn write(&mut self, batch: &RecordBatch) -> Result<Bytes, QueryError> {
+        match self {
+            Encoder::NdJson(fill_null_fields) => {
+                let mut chunk = Vec::new();
+                for mut row in record_batches_to_json(&[batch])? {
+                    for field in fill_null_fields.iter().flatten() {
+                        if !row.contains_key(field) {
+                            row.insert(field.clone(), Value::Null);
+                        }
+                    }
+                    serde_json::to_writer(&mut chunk, &row).map_err(anyhow::Error::from)?;
+                    chunk.push(b'\n');
+                }
+                Ok(chunk.into())
+            }
+            Encoder::ArrowIpc(writer) => {
+                writer
+                    .write(batch)
+                    .map_err(|err| QueryError::Datafusion(err.into()))?;
+                Ok(std::mem::take(writer.get_mut()).into())
+            }
+            Encoder::Csv {
+                delimiter,
+                header_written,
+                ..
+            } => {
+                let mut writer = csv::WriterBuilder::new()
+                    .with_header(!*header_written)
+                    .with_delimiter(*delimiter)
+                    .build(Vec::new());
+                writer
+                    .write(batch)
+                    .map_err(|err| QueryError::Datafusion(err.into()))?;
+                *header_written = true;
+                Ok(writer.into_inner().into())
+            }
+            Encoder::Parquet(writer) => {
+                writer
+                    .write(batch)
+                    .map_err(|err| QueryError::Datafusion(err.into()))?;
+                // bytes of the row groups written out so far, the writer
+                // keeps track of their offsets for the footer
+                Ok(std::mem::take(writer.inner_mut()).into())
+            }
+        }
+    }
+
+    fn finish(mut self) -> Result<Bytes, QueryError> {
+        match self {
+            Encoder::NdJson(_) => Ok(Bytes::new()),
+            Encoder::ArrowIpc(mut writer) => {
+                writer
+                    .finish()
+                    .map_err(|err| QueryError::Datafusion(err.into()))?;
+                Ok(std::mem::take(writer.get_mut()).into())
+            }
+            // an empty result still gets its header row
+            Encoder::Csv {
+                header_written: false,
+                ref schema,
+                ..
+            } => {
+                let empty = RecordBatch::new_empty(schema.clone());
+                self.write(&empty)
+            }
+            Encoder::Csv { .. } => Ok(Bytes::new()),
+            Encoder::Parquet(writer) => writer
+                .into_inner()
+                .map(Bytes::from)
+                .map_err(|err| QueryError::Datafusion(err.into())),
+        }
+    }
+
+    fn fail(self, err: DataFusionError) -> Result<Bytes, QueryError> {
+        log::error!("Query failed while streaming its response: {err}");
+        match self {
+            Encoder::NdJson(_) => {
+                let mut line = Map::new();
+                line.insert(STREAM_ERROR_KEY.to_string(), err.to_string().into());
+                let mut chunk = Value::Object(line).to_string().into_bytes();
+                chunk.push(b'\n');
+                Ok(chunk.into())
+            }
+            Encoder::ArrowIpc(_) | Encoder::Csv { .. } | Encoder::Parquet(_) => {
+                Err(QueryError::Datafusion(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Int64Array, RecordBatch, StringArray};
+    use arrow_ipc::reader::StreamReader;
+    use arrow_schema::{DataType, Field, Schema, SchemaRef};
+    use bytes::Bytes;
+    use datafusion::{
+        datasource::MemTable, error::DataFusionError,
+        physical_plan::stream::RecordBatchStreamAdapter, prelude::SessionContext,
+    };
+    use futures::{channel::mpsc, StreamExt};
+    use parquet::{
+        arrow::arrow_reader::ParquetRecordBatchReaderBuilder, basic::Compression as Codec,
+    };
+    use serde_json::Value;
+
+    use super::{stream_records, StreamFormat, STREAM_ERROR_KEY};
+    use crate::option::Compression;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("message", DataType::Utf8, true),
+        ]))
+    }
+
+    fn batch(offset: i64, rows: i64) -> RecordBatch {
+        RecordBatch::try_new(
+            schema(),
+            vec![
+                Arc::new(Int64Array::from_iter_values(offset..offset + rows)),
+                Arc::new(StringArray::from_iter(
+                    (offset..offset + rows).map(|i| (i % 2 == 0).then(|| format!("event {i}"))),
+                )),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn lines(chunk: &Bytes) -> Vec<Value> {
+        std::str::from_utf8(chunk)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn format_is_picked_from_accept_header_or_parameter() {
+        let requested = |accept, format, delimiter, stream| {
+            StreamFormat::requested(accept, format, delimiter, stream).unwrap()
+        };
+        assert_eq!(requested(None, None, None, false), None);
+        assert_eq!(requested(Some("application/json"), None, None, false), None);
+        assert_eq!(
+            requested(None, None, None, true),
+            Some(StreamFormat::NdJson)
+        );
+        assert_eq!(
+            requested(Some("application/x-ndjson"), None, None, false),
+            Some(StreamFormat::NdJson)
+        );
+        assert_eq!(
+            requested(
+                Some("text/html, application/vnd.apache.arrow.stream;q=0.9"),
+                None,
+                None,
+                true
+            ),
+            Some(StreamFormat::ArrowIpc)
+        );
+        assert_eq!(
+            requested(Some("text/csv"), None, Some(";"), false),
+            Some(StreamFormat::Csv { delimiter: b';' })
+        );
+        // the parameter wins over the header
+        assert_eq!(
+            requested(Some("text/csv"), Some("parquet"), None, true),
+            Some(StreamFormat::Parquet)
+        );
+        assert_eq!(
+            requested(None, Some("csv"), Some("\t"), false),
+            Some(StreamFormat::Csv { delimiter: b'\t' })
+        );
+
+        for (format, delimiter) in [
+            (Some("xlsx"), None),
+            (Some("csv"), Some("\"")),
+            (Some("csv"), Some("\n")),
+            (Some("csv"), Some(";;")),
+            (Some("csv"), Some("é")),
+        ] {
+            assert!(StreamFormat::requested(None, format, delimiter, false).is_err());
+        }
+    }
+
+    #[test]
+    fn exports_suggest_a_file_name() {
+        let start = "2024-05-01T10:00:00Z".parse().unwrap();
+        let end = "2024-05-01T11:30:00Z".parse().unwrap();
+        assert_eq!(
+            StreamFormat::Parquet
+                .file_name("app", start, end)
+                .as_deref(),
+            Some("app_20240501T100000Z_20240501T113000Z.parquet")
+        );
+        assert_eq!(
+            StreamFormat::Csv { delimiter: b',' }
+                .file_name("app", start, end)
+                .as_deref(),
+            Some("app_20240501T100000Z_20240501T113000Z.csv")
+        );
+        assert_eq!(StreamFormat::NdJson.file_name("app", start, end), None);
+    }
+
+    #[actix_web::test]
+    async fn chunks_are_sent_while_the_query_is_running() {
+        let (sender, receiver) = mpsc::unbounded();
+        let records = Box::pin(RecordBatchStreamAdapter::new(schema(), receiver));
+        let mut body = Box::pin(stream_records(
+            records,
+            StreamFormat::NdJson,
+            Some(vec!["id".to_string(), "message".to_string()]),
+            Compression::default(),
+        ));
+
+        let mut chunk_sizes = vec![];
+        for i in 0..20 {
+            sender.unbounded_send(Ok(batch(i * 500, 500))).unwrap();
+            // the batch is readable before the next one exists
+            let chunk = body.next().await.unwrap().unwrap();
+            let rows = lines(&chunk);
+            assert_eq!(rows.len(), 500);
+            assert_eq!(rows[0]["id"], i * 500);
+            assert_eq!(rows[1]["message"], Value::Null);
+            chunk_sizes.push(chunk.len());
+        }
+        drop(sender);
+        assert!(body.next().await.is_none());
+
+        // every chunk holds a single batch, nothing accumulates across batches
+        let (min, max) = (
+            chunk_sizes.iter().min().unwrap(),
+            chunk_sizes.iter().max().unwrap(),
+        );
+        assert!(max - min < min / 10, "{chunk_sizes:?}");
+    }
+
+    #[actix_web::test]
+    async fn arrow_ipc_stream_of_a_multi_partition_query() {
+        let partitions = (0..4).map(|i| vec![batch(i * 100, 100), batch(i * 100 + 50, 50)]);
+        let table = MemTable::try_new(schema(), partitions.collect()).unwrap();
+        let ctx = SessionContext::new();
+        ctx.register_table("events", Arc::new(table)).unwrap();
+        let records = ctx
+            .sql("SELECT id, message FROM events WHERE id % 3 = 0")
+            .await
+            .unwrap()
+            .execute_stream()
+            .await
+            .unwrap();
+
+        let chunks: Vec<_> = stream_records(
+            records,
+            StreamFormat::ArrowIpc,
+            None,
+            Compression::default(),
+        )
+        .map(|chunk| chunk.unwrap())
+        .collect()
+        .await;
+        assert!(chunks.len() > 2);
+
+        let body = chunks.concat();
+        let reader = StreamReader::try_new(body.as_slice(), None).unwrap();
+        assert_eq!(reader.schema(), schema());
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        let expected = (0..4)
+            .flat_map(|i| (i * 100..i * 100 + 100).chain(i * 100 + 50..i * 100 + 100))
+            .filter(|id| id % 3 == 0)
+            .count();
+        assert_eq!(rows, expected);
+    }
+
+    #[actix_web::test]
+    async fn csv_export_quotes_embedded_separators() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("message", DataType::Utf8, true),
+        ]));
+        let messages = [
+            Some("plain"),
+            Some("one, two"),
+            Some("first line\nsecond line"),
+            Some("say \"hi\""),
+            None,
+        ];
+        let batches = messages.chunks(2).enumerate().map(|(chunk, messages)| {
+            let ids = (0..messages.len() as i64).map(|i| chunk as i64 * 2 + i);
+            Ok(RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(Int64Array::from_iter_values(ids)),
+                    Arc::new(StringArray::from(messages.to_vec())),
+                ],
+            )
+            .unwrap())
+        });
+        let records = Box::pin(RecordBatchStreamAdapter::new(
+            schema.clone(),
+            futures::stream::iter(batches.collect::<Vec<_>>()),
+        ));
+
+        let format = StreamFormat::Csv { delimiter: b',' };
+        let chunks: Vec<_> = stream_records(records, format, None, Compression::default())
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        // a chunk per batch, the header only in the first one
+        assert_eq!(chunks.len(), 3);
+        let body = String::from_utf8(chunks.concat()).unwrap();
+        assert_eq!(
+            body,
+            "id,message\n\
+             0,plain\n\
+             1,\"one, two\"\n\
+             2,\"first line\nsecond line\"\n\
+             3,\"say \"\"hi\"\"\"\n\
+             4,\n"
+        );
+
+        // semicolons only need quoting once they separate fields
+        let records = Box::pin(RecordBatchStreamAdapter::new(
+            schema.clone(),
+            futures::stream::iter(vec![Ok(RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(Int64Array::from(vec![0])),
+                    Arc::new(StringArray::from(vec!["a;b, c"])),
+                ],
+            )
+            .unwrap())]),
+        ));
+        let format = StreamFormat::Csv { delimiter: b';' };
+        let chunks: Vec<_> = stream_records(records, format, None, Compression::default())
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        assert_eq!(chunks.concat(), b"id;message\n0;\"a;b, c\"\n");
+
+        // an empty result is just the header
+        let records = Box::pin(RecordBatchStreamAdapter::new(
+            schema,
+            futures::stream::iter(vec![]),
+        ));
+        let chunks: Vec<_> = stream_records(records, format, None, Compression::default())
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        assert_eq!(chunks.concat(), b"id;message\n");
+    }
+
+    #[actix_web::test]
+    async fn parquet_export_round_trips() {
+        let (sender, receiver) = mpsc::unbounded();
+        let records = Box::pin(RecordBatchStreamAdapter::new(schema(), receiver));
+        let mut body = Box::pin(stream_records(
+            records,
+            StreamFormat::Parquet,
+            None,
+            Compression::ZSTD,
+        ));
+
+        let batches = 20;
+        for i in 0..batches {
+            sender.unbounded_send(Ok(batch(i * 500, 500))).unwrap();
+        }
+        drop(sender);
+        let mut chunks = vec![];
+        while let Some(chunk) = body.next().await {
+            chunks.push(chunk.unwrap());
+        }
+
+        let file = Bytes::from(chunks.concat());
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        let metadata = reader.metadata().clone();
+        assert!(metadata
+            .row_groups()
+            .iter()
+            .flat_map(|row_group| row_group.columns())
+            .all(|column| matches!(column.compression(), Codec::ZSTD(_))));
+
+        let read: Vec<_> = reader.build().unwrap().map(|b| b.unwrap()).collect();
+        assert_eq!(read[0].schema(), schema());
+        let expected: Vec<_> = (0..batches).map(|i| batch(i * 500, 500)).collect();
+        let expected = datafusion::arrow::compute::concat_batches(&schema(), &expected).unwrap();
+        let read = datafusion::arrow::compute::concat_batches(&schema(), &read).unwrap();
+        assert_eq!(read, expected);
+    }
+
+    #[actix_web::test]
+    async fn failure_midway_terminates_the_stream() {
+        let failing = || {
+            futures::stream::iter(vec![
+                Ok(batch(0, 10)),
+                Err(DataFusionError::Execution("disk on fire".to_string())),
+                Ok(batch(10, 10)),
+            ])
+        };
+
+        let records = Box::pin(RecordBatchStreamAdapter::new(schema(), failing()));
+        let chunks: Vec<_> =
+            stream_records(records, StreamFormat::NdJson, None, Compression::default())
+                .collect()
+                .await;
+        assert_eq!(chunks.len(), 2);
+        let trailer = lines(chunks[1].as_ref().unwrap());
+        assert!(trailer[0][STREAM_ERROR_KEY]
+            .as_str()
+            .unwrap()
+            .contains("disk on fire"));
+
+        let records = Box::pin(RecordBatchStreamAdapter::new(schema(), failing()));
+        let chunks: Vec<_> = stream_records(
+            records,
+            StreamFormat::ArrowIpc,
+            None,
+            Compression::default(),
+        )
+        .collect()
+        .await;
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[1].is_err());
+        // without the end of stream marker the reader can't finish cleanly
+        let body = chunks[0].as_ref().unwrap().clone();
+        let reader = StreamReader::try_new(body.as_ref(), None).unwrap();
+        let batches: Vec<_> = reader.collect();
+        assert_eq!(batches.len(), 1);
+        assert!(batches[0].is_ok());
+    }
+}
@@ -0,0 +1,692 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Queries run on a schedule, their results are ingested into a destination stream
+
+use std::{collections::HashMap, sync::Arc, sync::RwLock, time::Duration};
+
+use anyhow::anyhow;
+use arrow_array::{RecordBatch, TimestampMillisecondArray};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use datafusion::common::tree_node::TreeNode;
+use once_cell::sync::Lazy;
+use relative_path::RelativePathBuf;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    event::{self, DEFAULT_TIMESTAMP_KEY},
+    handlers::http::{ingest::create_stream_if_not_exists, query::update_schema_when_distributed},
+    metadata::LOCK_EXPECT,
+    metrics::{SCHEDULED_QUERY_LAG, SCHEDULED_QUERY_RUNS},
+    option::CONFIG,
+    query::{
+        concurrency::{QueryClass, QUERY_LIMITER},
+        registry::CancelFlag,
+        Query, TableScanVisitor, QUERY_SESSION,
+    },
+    storage::PARSEABLE_ROOT_DIRECTORY,
+};
+
+pub static SCHEDULED_QUERIES: Lazy<ScheduledQueries> = Lazy::new(ScheduledQueries::default);
+
+const SCHEDULED_QUERIES_DIR: &str = "scheduled_queries";
+// how often the scheduler looks for windows that are due
+const SCHEDULER_TICK: Duration = Duration::from_secs(30);
+// windows run per query and tick, catching up after a long downtime
+// happens over several ticks so that other queries get their turn
+const MAX_WINDOWS_PER_TICK: usize = 100;
+const RETRY_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// Start and end of the time range a run covers, the end is excluded
+pub type Window = (DateTime<Utc>, DateTime<Utc>);
+
+/// A query materialized into `destination` every `interval`.
+///
+/// Windows are aligned to multiples of the interval since the unix epoch, the
+/// same boundaries a cron schedule like `*/5 * * * *` fires on. Each run
+/// queries the window that just closed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledQuery {
+    version: String,
+    id: String,
+    name: String,
+    query: String,
+    #[serde(with = "humantime_serde")]
+    interval: Duration,
+    /// how long a window is waited on after it closes, for late events
+    #[serde(default, with = "humantime_serde")]
+    delay: Duration,
+    destination: String,
+}
+
+impl ScheduledQuery {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Why the query can't be scheduled, if it can't
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.interval < Duration::from_secs(1) {
+            return Err("Interval has to be at least one second");
+        }
+        if self.destination.is_empty() {
+            return Err("Destination stream is not set");
+        }
+        Ok(())
+    }
+
+    fn interval(&self) -> chrono::Duration {
+        chrono::Duration::from_std(self.interval).unwrap_or(chrono::Duration::max_value())
+    }
+
+    /// Start of the window `time` falls in
+    pub fn window_start(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let interval = self.interval().num_milliseconds();
+        let millis = time.timestamp_millis();
+        DateTime::from_timestamp_millis(millis - millis.rem_euclid(interval)).unwrap_or(time)
+    }
+
+    /// Windows after `watermark` that closed at least `delay` before `now`
+    fn due_windows(&self, watermark: DateTime<Utc>, now: DateTime<Utc>) -> Vec<Window> {
+        let interval = self.interval();
+        let delay = chrono::Duration::from_std(self.delay).unwrap_or(chrono::Duration::zero());
+        let mut windows = vec![];
+        let mut start = watermark;
+        while windows.len() < MAX_WINDOWS_PER_TICK {
+            let Some(end) = start.checked_add_signed(interval) else {
+                break;
+            };
+            if end + delay > now {
+                break;
+            }
+            windows.push((start, end));
+            start = end;
+        }
+        windows
+    }
+}
+
+/// Progress of a scheduled query, persisted after every window
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RunState {
+    /// end of the last materialized window, earlier windows never run again
+    watermark: DateTime<Utc>,
+    /// schema of the results, recorded on the first run
+    schema: Option<Schema>,
+    consecutive_failures: u32,
+    last_error: Option<String>,
+    retry_at: Option<DateTime<Utc>>,
+    last_run: Option<DateTime<Utc>>,
+}
+
+impl RunState {
+    /// State of a query scheduled at `now`, its first window is the current one
+    pub fn new(query: &ScheduledQuery, now: DateTime<Utc>) -> Self {
+        Self {
+            watermark: query.window_start(now),
+            schema: None,
+            consecutive_failures: 0,
+            last_error: None,
+            retry_at: None,
+            last_run: None,
+        }
+    }
+
+    fn fail(&mut self, err: String, now: DateTime<Utc>) {
+        self.consecutive_failures += 1;
+        self.last_error = Some(err);
+        let backoff = RETRY_BACKOFF
+            .saturating_mul(2u32.saturating_pow(self.consecutive_failures - 1))
+            .min(MAX_RETRY_BACKOFF);
+        self.retry_at = Some(now + chrono::Duration::from_std(backoff).unwrap_or_default());
+    }
+
+    fn succeed(&mut self, window: Window) {
+        self.watermark = window.1;
+        self.consecutive_failures = 0;
+        self.last_error = None;
+        self.retry_at = None;
+    }
+
+    // the first run records the schema, later runs have to match it
+    fn check_schema(&mut self, schema: &Schema) -> anyhow::Result<()> {
+        if schema.field_with_name(DEFAULT_TIMESTAMP_KEY).is_ok() {
+            return Err(anyhow!(
+                "results can't have a {DEFAULT_TIMESTAMP_KEY} column, alias it in the query"
+            ));
+        }
+        let Some(recorded) = &self.schema else {
+            self.schema = Some(schema.clone());
+            return Ok(());
+        };
+        let columns = |schema: &Schema| {
+            schema
+                .fields()
+                .iter()
+                .map(|field| (field.name().clone(), field.data_type().clone()))
+                .collect::<Vec<_>>()
+        };
+        if columns(recorded) != columns(schema) {
+            return Err(anyhow!(
+                "results no longer match the schema of the first run, expected {:?} got {:?}",
+                columns(recorded),
+                columns(schema)
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Runs the windows of scheduled queries and stores what they produce
+#[async_trait]
+pub trait Materializer {
+    async fn execute(
+        &self,
+        query: &ScheduledQuery,
+        window: Window,
+    ) -> anyhow::Result<Vec<RecordBatch>>;
+
+    async fn ingest(
+        &self,
+        destination: &str,
+        window: Window,
+        batches: Vec<RecordBatch>,
+    ) -> anyhow::Result<()>;
+
+    async fn save(&self, query: &ScheduledQuery, state: &RunState) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct ScheduledQueries(RwLock<Vec<(ScheduledQuery, RunState)>>);
+
+impl ScheduledQueries {
+    pub async fn load(&self) -> anyhow::Result<()> {
+        let mut this = vec![];
+        let store = CONFIG.storage().get_object_store();
+        let objs = store
+            .get_objects(
+                Some(&scheduled_query_path("")),
+                Box::new(|path| path.ends_with(".json") && !path.ends_with(".state.json")),
+            )
+            .await
+            .unwrap_or_default();
+
+        for obj in objs {
+            let Ok(query) = serde_json::from_slice::<ScheduledQuery>(&obj) else {
+                continue;
+            };
+            let state = store
+                .get_object(&state_path(query.id()))
+                .await
+                .ok()
+                .and_then(|state| serde_json::from_slice(&state).ok())
+                .unwrap_or_else(|| RunState::new(&query, Utc::now()));
+            this.push((query, state));
+        }
+
+        let mut s = self.0.write().expect(LOCK_EXPECT);
+        s.append(&mut this);
+
+        Ok(())
+    }
+
+    /// Adds or replaces a query, a replaced query keeps its progress
+    pub fn update(&self, query: ScheduledQuery, now: DateTime<Utc>) -> RunState {
+        let mut s = self.0.write().expect(LOCK_EXPECT);
+        let state = match s.iter().position(|(q, _)| q.id() == query.id()) {
+            Some(index) => {
+                let (previous, mut state) = s.remove(index);
+                if previous.query != query.query {
+                    state.schema = None;
+                }
+                state
+            }
+            None => RunState::new(&query, now),
+        };
+        s.push((query, state.clone()));
+        state
+    }
+
+    pub fn delete(&self, id: &str) {
+        let mut s = self.0.write().expect(LOCK_EXPECT);
+        s.retain(|(query, _)| query.id() != id);
+    }
+
+    pub fn list(&self) -> Vec<ScheduledQuery> {
+        let s = self.0.read().expect(LOCK_EXPECT);
+        s.iter().map(|(query, _)| query.clone()).collect()
+    }
+
+    pub fn find(&self, id: &str) -> Option<(ScheduledQuery, RunState)> {
+        let s = self.0.read().expect(LOCK_EXPECT);
+        s.iter().find(|(query, _)| query.id() == id).cloned()
+    }
+
+    fn set_state(&self, id: &str, state: RunState) {
+        let mut s = self.0.write().expect(LOCK_EXPECT);
+        if let Some((_, current)) = s.iter_mut().find(|(query, _)| query.id() == id) {
+            *current = state;
+        }
+    }
+
+    /// Runs the windows of every query that are due at `now`, in order.
+    ///
+    /// A failed window stops its query until the retry backoff passed, the
+    /// watermark only moves past windows that were ingested.
+    pub async fn run_due(&self, materializer: &impl Materializer, now: DateTime<Utc>) {
+        for query in self.list() {
+            let Some((_, mut state)) = self.find(query.id()) else {
+                continue;
+            };
+            if state.retry_at.is_some_and(|retry_at| now < retry_at) {
+                continue;
+            }
+            for window in query.due_windows(state.watermark, now) {
+                state.last_run = Some(now);
+                let result = materialize(materializer, &query, &mut state, window).await;
+                let ok = result.is_ok();
+                match result {
+                    Ok(()) => state.succeed(window),
+                    Err(err) => {
+                        log::error!("scheduled query {} failed: {err:#}", query.id());
+                        state.fail(format!("{err:#}"), now);
+                    }
+                }
+                SCHEDULED_QUERY_RUNS
+                    .with_label_values(&[query.id(), if ok { "success" } else { "failure" }])
+                    .inc();
+                if let Err(err) = materializer.save(&query, &state).await {
+                    log::warn!(
+                        "failed to save state of scheduled query {}: {err:#}",
+                        query.id()
+                    );
+                }
+                self.set_state(query.id(), state.clone());
+                if !ok {
+                    break;
+                }
+            }
+            SCHEDULED_QUERY_LAG
+                .with_label_values(&[query.id()])
+                .set((now - state.watermark).num_seconds());
+        }
+    }
+}
+
+async fn materialize(
+    materializer: &impl Materializer,
+    query: &ScheduledQuery,
+    state: &mut RunState,
+    window: Window,
+) -> anyhow::Result<()> {
+    let batches = materializer.execute(query, window).await?;
+    if let Some(batch) = batches.first() {
+        state.check_schema(&batch.schema())?;
+    }
+    materializer
+        .ingest(&query.destination, window, batches)
+        .await
+}
+
+/// path will be ".parseable/scheduled_queries/{file_name}"
+pub fn scheduled_query_path(file_name: &str) -> RelativePathBuf {
+    RelativePathBuf::from_iter([PARSEABLE_ROOT_DIRECTORY, SCHEDULED_QUERIES_DIR, file_name])
+}
+
+pub fn state_path(id: &str) -> RelativePathBuf {
+    scheduled_query_path(&format!("{id}.state.json"))
+}
+
+/// Runs scheduled queries with the query engine and ingests into streams
+pub struct StreamMaterializer;
+
+#[async_trait]
+impl Materializer for StreamMaterializer {
+    async fn execute(
+        &self,
+        query: &ScheduledQuery,
+        window: Window,
+    ) -> anyhow::Result<Vec<RecordBatch>> {
+        let session_state = QUERY_SESSION.state();
+        let raw_logical_plan = session_state.create_logical_plan(&query.query).await?;
+        let mut visitor = TableScanVisitor::default();
+        let _ = raw_logical_plan.visit(&mut visitor);
+        let stream = visitor
+            .top()
+            .ok_or_else(|| anyhow!("no stream found in the query"))?
+            .to_owned();
+        let streams = visitor.into_inner();
+        if streams.contains(&query.destination) {
+            return Err(anyhow!("the query can't read its destination stream"));
+        }
+        update_schema_when_distributed(streams)
+            .await
+            .map_err(|err| anyhow!("{err}"))?;
+
+        let _permit = QUERY_LIMITER.acquire(QueryClass::Internal).await?;
+        let logical_query = Query {
+            raw_logical_plan,
+            start: window.0,
+            end: window.1,
+            filter_tag: None,
+        };
+        let (batches, _) = logical_query
+            .execute(stream, &CancelFlag::unregistered())
+            .await?;
+        Ok(batches)
+    }
+
+    async fn ingest(
+        &self,
+        destination: &str,
+        window: Window,
+        batches: Vec<RecordBatch>,
+    ) -> anyhow::Result<()> {
+        create_stream_if_not_exists(destination, false).await?;
+        for batch in batches.into_iter().filter(|batch| batch.num_rows() > 0) {
+            event::Event {
+                rb: with_timestamp(&batch)?,
+                stream_name: destination.to_owned(),
+                origin_format: "json",
+                origin_size: batch.get_array_memory_size() as u64,
+                is_first_event: true,
+                parsed_timestamp: window.0.naive_utc(),
+                time_partition: None,
+                custom_partition_values: HashMap::new(),
+            }
+            .process()
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn save(&self, query: &ScheduledQuery, state: &RunState) -> anyhow::Result<()> {
+        let store = CONFIG.storage().get_object_store();
+        store
+            .put_object(
+                &state_path(query.id()),
+                Bytes::from(serde_json::to_vec(state)?),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+// the batch with the p_timestamp column events are stored with in front,
+// the writer fills it in
+fn with_timestamp(batch: &RecordBatch) -> anyhow::Result<RecordBatch> {
+    let mut fields = vec![Arc::new(Field::new(
+        DEFAULT_TIMESTAMP_KEY,
+        DataType::Timestamp(TimeUnit::Millisecond, None),
+        true,
+    ))];
+    fields.extend(batch.schema().fields().iter().cloned());
+    let mut columns = vec![Arc::new(TimestampMillisecondArray::from_value(
+        Utc::now().timestamp_millis(),
+        batch.num_rows(),
+    )) as _];
+    columns.extend(batch.columns().iter().cloned());
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+/// Runs due windows of scheduled queries in the background
+pub fn init_scheduler() {
+    log::info!("Setting up scheduled queries");
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(SCHEDULER_TICK);
+        loop {
+            interval.tick().await;
+            SCHEDULED_QUERIES
+                .run_due(&StreamMaterializer, Utc::now())
+                .await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use arrow_array::{Int64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+    use async_trait::async_trait;
+    use chrono::{DateTime, Duration, Utc};
+    use serde_json::json;
+
+    use super::{Materializer, RunState, ScheduledQueries, ScheduledQuery, Window};
+
+    #[derive(Default)]
+    struct Recorder {
+        executed: Mutex<Vec<Window>>,
+        ingested: Mutex<Vec<(String, Window, usize)>>,
+        saved: Mutex<Vec<DateTime<Utc>>>,
+        // windows starting at these minutes fail once
+        fail_once: Mutex<Vec<i64>>,
+        // windows from this minute on return a different schema
+        schema_change_at: Option<i64>,
+    }
+
+    fn minute(time: DateTime<Utc>) -> i64 {
+        time.timestamp() / 60
+    }
+
+    #[async_trait]
+    impl Materializer for Recorder {
+        async fn execute(
+            &self,
+            _query: &ScheduledQuery,
+            window: Window,
+        ) -> anyhow::Result<Vec<RecordBatch>> {
+            self.executed.lock().unwrap().push(window);
+            let mut fail_once = self.fail_once.lock().unwrap();
+            if let Some(index) = fail_once.iter().position(|m| *m == minute(window.0)) {
+                fail_once.remove(index);
+                anyhow::bail!("query failed");
+            }
+            let batch = if self.schema_change_at.is_some_and(|m| minute(window.0) >= m) {
+                let schema = Schema::new(vec![Field::new("count", DataType::Utf8, true)]);
+                RecordBatch::try_new(
+                    Arc::new(schema),
+                    vec![Arc::new(StringArray::from(vec!["1"]))],
+                )?
+            } else {
+                let schema = Schema::new(vec![Field::new("count", DataType::Int64, true)]);
+                RecordBatch::try_new(Arc::new(schema), vec![Arc::new(Int64Array::from(vec![1]))])?
+            };
+            Ok(vec![batch])
+        }
+
+        async fn ingest(
+            &self,
+            destination: &str,
+            window: Window,
+            batches: Vec<RecordBatch>,
+        ) -> anyhow::Result<()> {
+            let rows = batches.iter().map(|batch| batch.num_rows()).sum();
+            self.ingested
+                .lock()
+                .unwrap()
+                .push((destination.to_owned(), window, rows));
+            Ok(())
+        }
+
+        async fn save(&self, _query: &ScheduledQuery, state: &RunState) -> anyhow::Result<()> {
+            self.saved.lock().unwrap().push(state.watermark);
+            Ok(())
+        }
+    }
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_100, 0).unwrap() + Duration::minutes(minutes)
+    }
+
+    fn scheduled(queries: &ScheduledQueries, created: DateTime<Utc>) -> ScheduledQuery {
+        let query: ScheduledQuery = serde_json::from_value(json!({
+            "version": "v1",
+            "id": "requests_per_service",
+            "name": "requests per service",
+            "query": "SELECT service, count(*) AS count FROM app GROUP BY service",
+            "interval": "5m",
+            "destination": "app_rollup",
+        }))
+        .unwrap();
+        queries.update(query.clone(), created);
+        query
+    }
+
+    fn ingested_starts(recorder: &Recorder) -> Vec<DateTime<Utc>> {
+        recorder
+            .ingested
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, window, _)| window.0)
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn catches_up_after_downtime_exactly_once() {
+        let queries = ScheduledQueries::default();
+        let query = scheduled(&queries, at(0));
+        let first = query.window_start(at(0));
+        let recorder = Recorder::default();
+
+        // the current window is still open
+        queries.run_due(&recorder, at(1)).await;
+        assert!(recorder.executed.lock().unwrap().is_empty());
+
+        // the server was down for several windows
+        let down_until = first + Duration::minutes(17);
+        queries.run_due(&recorder, down_until).await;
+        let starts = ingested_starts(&recorder);
+        assert_eq!(
+            starts,
+            [0, 5, 10].map(|m| first + Duration::minutes(m)).to_vec()
+        );
+        let (_, state) = queries.find(query.id()).unwrap();
+        assert_eq!(state.watermark, first + Duration::minutes(15));
+        assert_eq!(recorder.saved.lock().unwrap().len(), 3);
+
+        // running again does not repeat windows
+        queries.run_due(&recorder, down_until).await;
+        queries
+            .run_due(&recorder, first + Duration::minutes(19))
+            .await;
+        assert_eq!(ingested_starts(&recorder).len(), 3);
+
+        queries
+            .run_due(&recorder, first + Duration::minutes(20))
+            .await;
+        assert_eq!(
+            ingested_starts(&recorder).last(),
+            Some(&(first + Duration::minutes(15)))
+        );
+        assert!(recorder
+            .ingested
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|(destination, _, rows)| destination == "app_rollup" && *rows == 1));
+    }
+
+    #[actix_web::test]
+    async fn failed_windows_retry_with_backoff() {
+        let queries = ScheduledQueries::default();
+        let query = scheduled(&queries, at(0));
+        let first = query.window_start(at(0));
+        let recorder = Recorder::default();
+        recorder
+            .fail_once
+            .lock()
+            .unwrap()
+            .push(minute(first + Duration::minutes(5)));
+
+        let now = first + Duration::minutes(16);
+        queries.run_due(&recorder, now).await;
+        assert_eq!(ingested_starts(&recorder), [first]);
+        let (_, state) = queries.find(query.id()).unwrap();
+        assert_eq!(state.consecutive_failures, 1);
+        assert_eq!(state.last_error.as_deref(), Some("query failed"));
+        assert_eq!(state.retry_at, Some(now + Duration::seconds(30)));
+
+        // within the backoff nothing runs
+        queries
+            .run_due(&recorder, now + Duration::seconds(10))
+            .await;
+        assert_eq!(recorder.executed.lock().unwrap().len(), 2);
+
+        queries
+            .run_due(&recorder, now + Duration::seconds(31))
+            .await;
+        let starts = ingested_starts(&recorder);
+        assert_eq!(
+            starts,
+            [0, 5, 10].map(|m| first + Duration::minutes(m)).to_vec()
+        );
+        let (_, state) = queries.find(query.id()).unwrap();
+        assert_eq!(state.consecutive_failures, 0);
+        assert!(state.last_error.is_none());
+    }
+
+    #[actix_web::test]
+    async fn results_are_validated_against_the_first_schema() {
+        let queries = ScheduledQueries::default();
+        let query = scheduled(&queries, at(0));
+        let first = query.window_start(at(0));
+        let recorder = Recorder {
+            schema_change_at: Some(minute(first + Duration::minutes(5))),
+            ..Default::default()
+        };
+
+        queries
+            .run_due(&recorder, first + Duration::minutes(10))
+            .await;
+        assert_eq!(ingested_starts(&recorder), [first]);
+        let (_, state) = queries.find(query.id()).unwrap();
+        assert!(state
+            .last_error
+            .as_deref()
+            .is_some_and(|err| err.contains("schema")));
+        assert_eq!(state.watermark, first + Duration::minutes(5));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_an_hour() {
+        let queries = ScheduledQueries::default();
+        let query = scheduled(&queries, at(0));
+        let mut state = RunState::new(&query, at(0));
+        let mut backoffs = vec![];
+        for _ in 0..10 {
+            state.fail("failed".to_owned(), at(0));
+            backoffs.push((state.retry_at.unwrap() - at(0)).num_seconds());
+        }
+        assert_eq!(
+            backoffs,
+            [30, 60, 120, 240, 480, 960, 1920, 3600, 3600, 3600]
+        );
+    }
+}
@@ -17,14 +17,21 @@
  */
 
 use crate::{
-    catalog::snapshot::Snapshot, metadata::error::stream_info::MetadataError, stats::FullStats,
+    catalog::snapshot::Snapshot, event::format::grok::GrokSettings,
+    event::format::protobuf::ProtobufSchema, event::timestamp::TimestampExtraction,
+    event::transform::TransformRules, event::widening::SchemaChange,
+    metadata::error::stream_info::MetadataError, option::CONFIG, stats::FullStats,
+    utils::json::flatten::FlattenOptions,
 };
 
 use chrono::Local;
+use once_cell::sync::Lazy;
+use tokio::sync::{Semaphore, SemaphorePermit};
 
-use std::fmt::Debug;
+use std::{fmt::Debug, sync::Arc};
 
-mod localfs;
+pub mod disk_usage;
+pub(crate) mod localfs;
 mod metrics_layer;
 pub(crate) mod object_storage;
 pub mod retention;
@@ -58,8 +65,36 @@ pub const LOCAL_SYNC_INTERVAL: u64 = 60;
 /// used for storage. Defaults to 1 min.
 pub const OBJECT_STORE_DATA_GRANULARITY: u32 = (LOCAL_SYNC_INTERVAL as u32) / 60;
 
-// max concurrent request allowed for datafusion object store
-const MAX_OBJECT_STORE_REQUESTS: usize = 1000;
+// permits shared by every object store handle, sized by P_STORE_CONCURRENCY
+static STORE_PERMITS: Lazy<StorePermits> =
+    Lazy::new(|| StorePermits::new(CONFIG.parseable.store_concurrency));
+
+/// Bounds the number of concurrent requests made through `ObjectStorage`
+#[derive(Debug, Clone)]
+pub struct StorePermits(Arc<Semaphore>);
+
+impl StorePermits {
+    pub fn new(permits: usize) -> Self {
+        Self(Arc::new(Semaphore::new(permits)))
+    }
+
+    /// Permits shared by all object stores of this process
+    pub fn global() -> Self {
+        STORE_PERMITS.clone()
+    }
+
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        self.0
+            .acquire()
+            .await
+            .expect("store semaphore is never closed")
+    }
+
+    #[cfg(test)]
+    pub fn available(&self) -> usize {
+        self.0.available_permits()
+    }
+}
 
 // all the supported permissions
 // const PERMISSIONS_READ: &str = "readonly";
@@ -100,6 +135,26 @@ pub struct ObjectStoreFormat {
     pub custom_partition: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub static_schema_flag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protobuf: Option<ProtobufSchema>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grok: Option<GrokSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp_extraction: Option<TimestampExtraction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flattening: Option<FlattenOptions>,
+    #[serde(default)]
+    pub allow_type_widening: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dead_letter_stream: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transforms: Option<TransformRules>,
+    /// transform rules replaced since the stream was created, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transform_history: Vec<TransformRules>,
+    /// columns widened while ingesting, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schema_changes: Vec<SchemaChange>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -167,6 +222,15 @@ impl Default for ObjectStoreFormat {
             time_partition_limit: None,
             custom_partition: None,
             static_schema_flag: None,
+            protobuf: None,
+            grok: None,
+            timestamp_extraction: None,
+            flattening: None,
+            allow_type_widening: false,
+            dead_letter_stream: None,
+            transforms: None,
+            transform_history: Vec::new(),
+            schema_changes: Vec::new(),
         }
     }
 }
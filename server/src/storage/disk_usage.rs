@@ -0,0 +1,354 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+//! Enforces `P_MAX_DISK_USAGE` on the disk holding the staging directory
+
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use futures::future::{BoxFuture, FutureExt, Shared};
+use once_cell::sync::Lazy;
+use sysinfo::{Disk, Disks};
+
+use crate::localcache::LocalCacheManager;
+use crate::metadata::STREAM_INFO;
+use crate::option::{DiskUsageAction, CONFIG};
+
+use super::retention;
+
+pub static DISK_GUARD: Lazy<DiskGuard> = Lazy::new(DiskGuard::default);
+
+// listing disks is a syscall per mount, ingestion reuses a recent reading
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Outcome for an event given the current disk usage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Accept,
+    /// Refuse the event, the stream accepts events again once usage drops
+    Reject,
+    /// Free the stream's local data until usage drops below the limit,
+    /// accepting the event if it does
+    Evict,
+    /// Refuse events for the stream until usage drops below the limit
+    ReadOnly,
+}
+
+/// Decides what happens to an event when `usage` percent of the disk is used
+pub fn decide(usage: f64, max_usage: Option<f64>, action: DiskUsageAction) -> Decision {
+    match max_usage {
+        Some(max_usage) if usage >= max_usage => match action {
+            DiskUsageAction::Reject => Decision::Reject,
+            DiskUsageAction::Evict => Decision::Evict,
+            DiskUsageAction::ReadOnly => Decision::ReadOnly,
+        },
+        _ => Decision::Accept,
+    }
+}
+
+/// Percentage of the disk `path` is stored on that is in use
+pub fn disk_usage(path: &Path) -> Option<f64> {
+    let disks = Disks::new_with_refreshed_list();
+    find_disk(&disks, path)
+        .filter(|disk| disk.total_space() > 0)
+        .map(|disk| 100. * (1. - disk.available_space() as f64 / disk.total_space() as f64))
+}
+
+/// Mount point of the disk `path` is stored on
+pub fn mount_point(path: &Path) -> Option<PathBuf> {
+    let disks = Disks::new_with_refreshed_list();
+    find_disk(&disks, path).map(|disk| disk.mount_point().to_path_buf())
+}
+
+fn find_disk<'a>(disks: &'a Disks, path: &Path) -> Option<&'a Disk> {
+    disks
+        .list()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DiskUsageError {
+    #[error("Disk usage of {usage:.1}% exceeds the limit of {max_usage}%, rejecting events for stream {stream}")]
+    Full {
+        stream: String,
+        usage: f64,
+        max_usage: f64,
+    },
+    #[error("Stream {0} was made read-only because disk usage exceeded its limit")]
+    ReadOnly(String),
+}
+
+#[derive(Debug, Default)]
+pub struct DiskGuard {
+    sample: Mutex<Option<(Instant, f64)>>,
+    read_only: Mutex<HashSet<String>>,
+    evicting: Mutex<HashMap<String, Shared<BoxFuture<'static, bool>>>>,
+}
+
+impl DiskGuard {
+    /// Checks whether an event for `stream` may be ingested, applying the configured action
+    pub async fn check(&'static self, stream: &str) -> Result<(), DiskUsageError> {
+        let Some(max_usage) = CONFIG.parseable.max_disk_usage else {
+            return Ok(());
+        };
+        let Some(usage) = self.usage() else {
+            return Ok(());
+        };
+        let full = || DiskUsageError::Full {
+            stream: stream.to_string(),
+            usage,
+            max_usage,
+        };
+
+        match self.decide_for(stream, usage, max_usage, CONFIG.parseable.disk_usage_action) {
+            Decision::Accept => Ok(()),
+            Decision::Reject => Err(full()),
+            Decision::Evict if self.evict(stream, max_usage).await => Ok(()),
+            Decision::Evict => Err(full()),
+            Decision::ReadOnly => Err(DiskUsageError::ReadOnly(stream.to_string())),
+        }
+    }
+
+    // the decision for an event of `stream`, a stream made read-only stays so
+    // until usage drops below the limit whatever the action
+    fn decide_for(
+        &self,
+        stream: &str,
+        usage: f64,
+        max_usage: f64,
+        action: DiskUsageAction,
+    ) -> Decision {
+        let mut read_only = self.read_only.lock().unwrap();
+        match decide(usage, Some(max_usage), action) {
+            Decision::Accept => {
+                if read_only.remove(stream) {
+                    log::info!(
+                        "Disk usage is down to {usage:.1}%, stream {stream} accepts events again"
+                    );
+                }
+                Decision::Accept
+            }
+            _ if read_only.contains(stream) => Decision::ReadOnly,
+            Decision::ReadOnly => {
+                log::warn!("Disk usage of {usage:.1}% exceeds {max_usage}%, stream {stream} is read-only until it drops");
+                read_only.insert(stream.to_string());
+                Decision::ReadOnly
+            }
+            decision => decision,
+        }
+    }
+
+    fn usage(&self) -> Option<f64> {
+        let mut sample = self.sample.lock().unwrap();
+        match *sample {
+            Some((at, usage)) if at.elapsed() < SAMPLE_INTERVAL => Some(usage),
+            _ => {
+                let usage = disk_usage(CONFIG.staging_dir())?;
+                *sample = Some((Instant::now(), usage));
+                Some(usage)
+            }
+        }
+    }
+
+    async fn evict(&'static self, stream: &str, max_usage: f64) -> bool {
+        self.evict_with(stream, move |stream| async move {
+            evict_local_data(&stream, max_usage).await
+        })
+        .await
+    }
+
+    // runs `evict` for the stream and returns what it returns, events arriving
+    // while an eviction of the stream runs wait for that one instead. The
+    // eviction runs as its own task so that it completes even if the requests
+    // waiting on it are dropped
+    async fn evict_with<F>(&'static self, stream: &str, evict: impl FnOnce(String) -> F) -> bool
+    where
+        F: Future<Output = bool> + Send + 'static,
+    {
+        let eviction = self
+            .evicting
+            .lock()
+            .unwrap()
+            .entry(stream.to_string())
+            .or_insert_with(|| {
+                let stream = stream.to_string();
+                let evicted = evict(stream.clone());
+                tokio::spawn(async move {
+                    let below_limit = evicted.await;
+                    self.evicting.lock().unwrap().remove(&stream);
+                    // the next event measures the usage after the eviction
+                    *self.sample.lock().unwrap() = None;
+                    below_limit
+                })
+                .map(|below_limit| below_limit.unwrap_or(false))
+                .boxed()
+                .shared()
+            })
+            .clone();
+        eviction.await
+    }
+}
+
+// frees what the stream keeps on the staging disk until its usage drops below
+// `max_usage`, without losing any of its events: first its data past retention
+// when the store is on that disk, then the copies of its uploaded files in the
+// local cache, least recently used first. Staged events, not uploaded yet, are
+// never evicted. Returns whether usage is below the limit afterwards
+async fn evict_local_data(stream: &str, max_usage: f64) -> bool {
+    let staging_dir = CONFIG.staging_dir();
+    let Some(staging) = mount_point(staging_dir) else {
+        return false;
+    };
+    let on_staging_disk = |path: &Path| mount_point(path).as_ref() == Some(&staging);
+    let below_limit = || disk_usage(staging_dir).is_some_and(|usage| usage < max_usage);
+
+    if !matches!(STREAM_INFO.get_retention(stream), Ok(Some(_))) {
+        log::warn!("Disk usage limit exceeded but stream {stream} has no retention set, none of its stored data can be evicted");
+    } else if CONFIG.storage_name == "drive"
+        && on_staging_disk(Path::new(&CONFIG.storage().get_endpoint()))
+    {
+        log::warn!("Disk usage limit exceeded, applying retention of stream {stream} now");
+        if retention::enforce(stream).await && below_limit() {
+            return true;
+        }
+    }
+
+    let cache = LocalCacheManager::global()
+        .filter(|_| CONFIG.cache_dir().as_deref().is_some_and(on_staging_disk));
+    if let Some(cache) = cache {
+        match cache.evict_stream(stream, below_limit).await {
+            Ok(0) => {}
+            Ok(bytes) => {
+                log::warn!("Disk usage limit exceeded, evicted {bytes} bytes of stream {stream} from the local cache");
+            }
+            Err(err) => log::error!("Could not evict stream {stream} from the local cache: {err}"),
+        }
+    }
+    below_limit()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use tokio::sync::Notify;
+
+    use super::{decide, Decision, DiskGuard};
+    use crate::option::DiskUsageAction;
+
+    #[test]
+    fn usage_below_limit_is_accepted() {
+        for action in [
+            DiskUsageAction::Reject,
+            DiskUsageAction::Evict,
+            DiskUsageAction::ReadOnly,
+        ] {
+            assert_eq!(decide(79.9, Some(80.), action), Decision::Accept);
+            assert_eq!(decide(99.9, None, action), Decision::Accept);
+        }
+    }
+
+    #[test]
+    fn reject_above_limit() {
+        assert_eq!(
+            decide(92.5, Some(80.), DiskUsageAction::Reject),
+            Decision::Reject
+        );
+    }
+
+    #[test]
+    fn evict_above_limit() {
+        assert_eq!(
+            decide(92.5, Some(80.), DiskUsageAction::Evict),
+            Decision::Evict
+        );
+    }
+
+    #[test]
+    fn read_only_above_limit() {
+        assert_eq!(
+            decide(80., Some(80.), DiskUsageAction::ReadOnly),
+            Decision::ReadOnly
+        );
+    }
+
+    #[test]
+    fn read_only_stream_is_released_below_limit() {
+        let guard = DiskGuard::default();
+        let decide = |stream, usage, action| guard.decide_for(stream, usage, 80., action);
+
+        assert_eq!(
+            decide("app", 85., DiskUsageAction::ReadOnly),
+            Decision::ReadOnly
+        );
+        // other streams are only refused once they receive events above the limit
+        assert_eq!(
+            decide("web", 70., DiskUsageAction::ReadOnly),
+            Decision::Accept
+        );
+        // a read-only stream stays so while usage is above the limit
+        assert_eq!(
+            decide("app", 82., DiskUsageAction::Reject),
+            Decision::ReadOnly
+        );
+        assert_eq!(
+            decide("app", 79., DiskUsageAction::ReadOnly),
+            Decision::Accept
+        );
+        assert_eq!(
+            decide("app", 82., DiskUsageAction::Reject),
+            Decision::Reject
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_events_wait_for_the_running_eviction() {
+        let guard: &'static DiskGuard = Box::leak(Box::new(DiskGuard::default()));
+        let evictions = Arc::new(AtomicUsize::new(0));
+        let done = Arc::new(Notify::new());
+        let evict = |stream: &'static str| {
+            let (evictions, done) = (evictions.clone(), done.clone());
+            guard.evict_with(stream, move |_| async move {
+                evictions.fetch_add(1, Ordering::SeqCst);
+                done.notified().await;
+                true
+            })
+        };
+
+        // the second event finds the first one's eviction running
+        let (first, second, ()) =
+            futures::join!(evict("app"), evict("app"), async { done.notify_one() });
+        assert!(first && second);
+        assert_eq!(evictions.load(Ordering::SeqCst), 1);
+
+        // events after it has completed start another one
+        done.notify_one();
+        assert!(evict("app").await);
+        assert_eq!(evictions.load(Ordering::SeqCst), 2);
+    }
+}
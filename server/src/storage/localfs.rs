@@ -28,6 +28,7 @@ use datafusion::{datasource::listing::ListingTableUrl, execution::runtime_env::R
 use fs_extra::file::CopyOptions;
 use futures::{stream::FuturesUnordered, TryStreamExt};
 use relative_path::{RelativePath, RelativePathBuf};
+use serde::Serialize;
 use tokio::fs::{self, DirEntry};
 use tokio_stream::wrappers::ReadDirStream;
 
@@ -35,11 +36,11 @@ use crate::metrics::storage::{localfs::REQUEST_RESPONSE_TIME, StorageMetrics};
 use crate::option::validation;
 
 use super::{
-    LogStream, ObjectStorage, ObjectStorageError, ObjectStorageProvider, PARSEABLE_ROOT_DIRECTORY,
-    SCHEMA_FILE_NAME, STREAM_METADATA_FILE_NAME, STREAM_ROOT_DIRECTORY,
+    LogStream, ObjectStorage, ObjectStorageError, ObjectStorageProvider, StorePermits,
+    PARSEABLE_ROOT_DIRECTORY, SCHEMA_FILE_NAME, STREAM_METADATA_FILE_NAME, STREAM_ROOT_DIRECTORY,
 };
 
-#[derive(Debug, Clone, clap::Args)]
+#[derive(Debug, Clone, clap::Args, Serialize)]
 #[command(
     name = "Local filesystem config",
     about = "Start Parseable with a drive as storage",
@@ -74,16 +75,25 @@ impl ObjectStorageProvider for FSConfig {
     fn register_store_metrics(&self, handler: &actix_web_prometheus::PrometheusMetrics) {
         self.register_metrics(handler);
     }
+
+    fn effective_config(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("storage config is serializable")
+    }
 }
 
 pub struct LocalFS {
     // absolute path of the data directory
     root: PathBuf,
+    permits: StorePermits,
 }
 
 impl LocalFS {
     pub fn new(root: PathBuf) -> Self {
-        Self { root }
+        Self::with_permits(root, StorePermits::global())
+    }
+
+    pub fn with_permits(root: PathBuf, permits: StorePermits) -> Self {
+        Self { root, permits }
     }
 
     pub fn path_in_root(&self, path: &RelativePath) -> PathBuf {
@@ -94,6 +104,7 @@ impl LocalFS {
 #[async_trait]
 impl ObjectStorage for LocalFS {
     async fn get_object(&self, path: &RelativePath) -> Result<Bytes, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let time = Instant::now();
         let file_path = self.path_in_root(path);
         let res: Result<Bytes, ObjectStorageError> = match fs::read(file_path).await {
@@ -117,6 +128,7 @@ impl ObjectStorage for LocalFS {
     async fn get_ingestor_meta_file_paths(
         &self,
     ) -> Result<Vec<RelativePathBuf>, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let time = Instant::now();
 
         let mut path_arr = vec![];
@@ -151,6 +163,7 @@ impl ObjectStorage for LocalFS {
         &self,
         stream_name: &str,
     ) -> Result<Vec<RelativePathBuf>, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let time = Instant::now();
         let mut path_arr = vec![];
 
@@ -197,6 +210,7 @@ impl ObjectStorage for LocalFS {
         base_path: Option<&RelativePath>,
         filter_func: Box<(dyn Fn(String) -> bool + std::marker::Send + 'static)>,
     ) -> Result<Vec<Bytes>, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let time = Instant::now();
 
         let prefix = if let Some(path) = base_path {
@@ -242,6 +256,7 @@ impl ObjectStorage for LocalFS {
         path: &RelativePath,
         resource: Bytes,
     ) -> Result<(), ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let time = Instant::now();
 
         let path = self.path_in_root(path);
@@ -260,24 +275,28 @@ impl ObjectStorage for LocalFS {
     }
 
     async fn delete_prefix(&self, path: &RelativePath) -> Result<(), ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let path = self.path_in_root(path);
         tokio::fs::remove_dir_all(path).await?;
         Ok(())
     }
 
     async fn delete_object(&self, path: &RelativePath) -> Result<(), ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let path = self.path_in_root(path);
         tokio::fs::remove_file(path).await?;
         Ok(())
     }
 
     async fn check(&self) -> Result<(), ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         fs::create_dir_all(&self.root)
             .await
             .map_err(|e| ObjectStorageError::UnhandledError(e.into()))
     }
 
     async fn delete_stream(&self, stream_name: &str) -> Result<(), ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let path = self.root.join(stream_name);
         Ok(fs::remove_dir_all(path).await?)
     }
@@ -286,11 +305,13 @@ impl ObjectStorage for LocalFS {
         &self,
         ingestor_filename: String,
     ) -> Result<(), ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let path = self.root.join(ingestor_filename);
         Ok(fs::remove_file(path).await?)
     }
 
     async fn list_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let ignore_dir = &["lost+found", PARSEABLE_ROOT_DIRECTORY];
         let directories = ReadDirStream::new(fs::read_dir(&self.root).await?);
         let entries: Vec<DirEntry> = directories.try_collect().await?;
@@ -311,6 +332,7 @@ impl ObjectStorage for LocalFS {
     }
 
     async fn list_old_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let ignore_dir = &["lost+found", PARSEABLE_ROOT_DIRECTORY];
         let directories = ReadDirStream::new(fs::read_dir(&self.root).await?);
         let entries: Vec<DirEntry> = directories.try_collect().await?;
@@ -331,6 +353,7 @@ impl ObjectStorage for LocalFS {
     }
 
     async fn list_dirs(&self) -> Result<Vec<String>, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let dirs = ReadDirStream::new(fs::read_dir(&self.root).await?)
             .try_collect::<Vec<DirEntry>>()
             .await?
@@ -348,6 +371,7 @@ impl ObjectStorage for LocalFS {
     }
 
     async fn list_dates(&self, stream_name: &str) -> Result<Vec<String>, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let path = self.root.join(stream_name);
         let directories = ReadDirStream::new(fs::read_dir(&path).await?);
         let entries: Vec<DirEntry> = directories.try_collect().await?;
@@ -358,6 +382,7 @@ impl ObjectStorage for LocalFS {
     }
 
     async fn upload_file(&self, key: &str, path: &Path) -> Result<(), ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let op = CopyOptions {
             overwrite: true,
             skip_exist: true,
@@ -491,3 +516,46 @@ impl From<fs_extra::error::Error> for ObjectStorageError {
         ObjectStorageError::UnhandledError(Box::new(e))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::rt::time::timeout;
+    use futures::future::join_all;
+    use relative_path::RelativePath;
+
+    use super::{LocalFS, ObjectStorage, StorePermits};
+
+    #[actix_web::test]
+    async fn get_object_respects_store_permits() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("object"), b"data").unwrap();
+        let permits = StorePermits::new(2);
+        let store = LocalFS::with_permits(dir.path().to_path_buf(), permits.clone());
+        let path = RelativePath::new("object");
+
+        // with every permit taken requests wait
+        let first = permits.acquire().await;
+        let second = permits.acquire().await;
+        assert!(timeout(Duration::from_millis(100), store.get_object(path))
+            .await
+            .is_err());
+
+        drop(first);
+        let object = timeout(Duration::from_secs(5), store.get_object(path))
+            .await
+            .expect("request proceeds once a permit is free")
+            .unwrap();
+        assert_eq!(&object[..], b"data");
+        drop(second);
+
+        // a burst larger than the limit completes and hands every permit back
+        join_all((0..64).map(|_| async {
+            let object = store.get_object(path).await.unwrap();
+            assert_eq!(&object[..], b"data");
+        }))
+        .await;
+        assert_eq!(permits.available(), 2);
+    }
+}
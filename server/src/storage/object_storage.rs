@@ -26,12 +26,15 @@ use super::{
 };
 
 use crate::handlers::http::modal::ingest_server::INGESTOR_META;
-use crate::handlers::http::users::{DASHBOARDS_DIR, FILTER_DIR, USERS_ROOT_DIR};
+use crate::handlers::http::users::{
+    CORRELATIONS_DIR, DASHBOARDS_DIR, FILTER_DIR, SAVED_QUERIES_DIR, USERS_ROOT_DIR,
+};
 use crate::metrics::{EVENTS_STORAGE_SIZE_DATE, LIFETIME_EVENTS_STORAGE_SIZE};
 use crate::option::Mode;
 use crate::{
     alerts::Alerts,
     catalog::{self, manifest::Manifest, snapshot::Snapshot},
+    event::widening::{self, merge_schemas, SchemaChange},
     localcache::LocalCacheManager,
     metadata::STREAM_INFO,
     metrics::{storage::StorageMetrics, STORAGE_SIZE},
@@ -46,6 +49,7 @@ use bytes::Bytes;
 use chrono::Local;
 use datafusion::{datasource::listing::ListingTableUrl, execution::runtime_env::RuntimeConfig};
 use itertools::Itertools;
+use object_store::UpdateVersion;
 use relative_path::RelativePath;
 use relative_path::RelativePathBuf;
 
@@ -57,11 +61,26 @@ use std::{
     time::{Duration, Instant},
 };
 
+// times a widened schema is put again when the stored one changed in between
+const SCHEMA_UPDATE_ATTEMPTS: usize = 5;
+
+/// Outcome of putting an object conditioned on the version it was read at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConditionalPut {
+    Put,
+    /// The object changed since that version, it was left as it is
+    Changed,
+    /// The store can't put conditionally
+    Unsupported,
+}
+
 pub trait ObjectStorageProvider: StorageMetrics + std::fmt::Debug {
     fn get_datafusion_runtime(&self) -> RuntimeConfig;
     fn get_object_store(&self) -> Arc<dyn ObjectStorage + Send>;
     fn get_endpoint(&self) -> String;
     fn register_store_metrics(&self, handler: &PrometheusMetrics);
+    /// Storage configuration with secrets redacted
+    fn effective_config(&self) -> serde_json::Value;
 }
 
 #[async_trait]
@@ -78,6 +97,23 @@ pub trait ObjectStorage: Sync + 'static {
         path: &RelativePath,
         resource: Bytes,
     ) -> Result<(), ObjectStorageError>;
+    /// Gets an object along with the version a conditional put of it checks,
+    /// stores without conditional writes return no version
+    async fn get_object_versioned(
+        &self,
+        path: &RelativePath,
+    ) -> Result<(Bytes, Option<UpdateVersion>), ObjectStorageError> {
+        Ok((self.get_object(path).await?, None))
+    }
+    /// Puts an object only if the stored one is still at `version`
+    async fn put_object_if_version(
+        &self,
+        _path: &RelativePath,
+        _resource: Bytes,
+        _version: UpdateVersion,
+    ) -> Result<ConditionalPut, ObjectStorageError> {
+        Ok(ConditionalPut::Unsupported)
+    }
     async fn delete_prefix(&self, path: &RelativePath) -> Result<(), ObjectStorageError>;
     async fn check(&self) -> Result<(), ObjectStorageError>;
     async fn delete_stream(&self, stream_name: &str) -> Result<(), ObjectStorageError>;
@@ -530,10 +566,89 @@ pub async fn commit_schema_to_storage(
 ) -> Result<(), ObjectStorageError> {
     let storage = CONFIG.storage().get_object_store();
     let stream_schema = storage.get_schema(stream_name).await?;
-    let new_schema = Schema::try_merge(vec![schema, stream_schema]).unwrap();
+    let new_schema = merge_schemas(vec![schema, stream_schema]).unwrap();
     storage.put_schema(stream_name, &new_schema).await
 }
 
+/// Widens columns of the schema in storage, retrying when another writer
+/// changed it in between, and records the changes in the stream metadata
+pub async fn commit_widened_schema_to_storage(
+    stream_name: &str,
+    changes: &[SchemaChange],
+) -> Result<(), ObjectStorageError> {
+    let storage = CONFIG.storage().get_object_store();
+    put_widened_schema(&*storage, stream_name, &schema_path(stream_name), changes).await?;
+
+    let mut stream_metadata = storage.get_object_store_format(stream_name).await?;
+    stream_metadata
+        .schema_changes
+        .extend(changes.iter().cloned());
+    storage
+        .put_stream_manifest(stream_name, &stream_metadata)
+        .await?;
+    for change in changes {
+        log::info!(
+            "Widened column {} of stream {stream_name} from {} to {}",
+            change.field,
+            change.from,
+            change.to
+        );
+    }
+    Ok(())
+}
+
+// widens columns of the schema of the stream stored at `path`, reading it
+// again when another writer changed it since it was read
+async fn put_widened_schema(
+    storage: &(impl ObjectStorage + ?Sized),
+    stream_name: &str,
+    path: &RelativePath,
+    changes: &[SchemaChange],
+) -> Result<(), ObjectStorageError> {
+    for _ in 0..SCHEMA_UPDATE_ATTEMPTS {
+        let (stored, version) = storage.get_object_versioned(path).await?;
+        let stream_schema: Schema = serde_json::from_slice(&stored)?;
+        let new_schema = widening::apply(&stream_schema, changes);
+        if put_schema_if_unchanged(storage, path, &stream_schema, version, &new_schema).await? {
+            return Ok(());
+        }
+    }
+
+    Err(ObjectStorageError::Custom(format!(
+        "Schema of stream {stream_name} kept changing while widening it"
+    )))
+}
+
+// puts the schema at `path` only if the stored one still is `expected`, read
+// at `version`, returns whether it was put. The store checks the version when
+// it has conditional writes. Others check by reading the schema right before
+// the write, which narrows the window for a concurrent change to be
+// overwritten without closing it.
+async fn put_schema_if_unchanged(
+    storage: &(impl ObjectStorage + ?Sized),
+    path: &RelativePath,
+    expected: &Schema,
+    version: Option<UpdateVersion>,
+    schema: &Schema,
+) -> Result<bool, ObjectStorageError> {
+    if let Some(version) = version {
+        match storage
+            .put_object_if_version(path, to_bytes(schema), version)
+            .await?
+        {
+            ConditionalPut::Put => return Ok(true),
+            ConditionalPut::Changed => return Ok(false),
+            ConditionalPut::Unsupported => {}
+        }
+    }
+    let stored: Schema = serde_json::from_slice(&storage.get_object(path).await?)?;
+    if stored != *expected {
+        return Ok(false);
+    }
+    storage.put_object(path, to_bytes(schema)).await?;
+    Ok(true)
+}
+
 #[inline(always)]
 pub fn to_bytes(any: &(impl ?Sized + serde::Serialize)) -> Bytes {
     serde_json::to_vec(any)
@@ -584,6 +699,23 @@ pub fn dashboard_path(user_id: &str, dashboard_file_name: &str) -> RelativePathB
     RelativePathBuf::from_iter([USERS_ROOT_DIR, user_id, DASHBOARDS_DIR, dashboard_file_name])
 }
 
+/// if correlation_id is an empty str it should not append it to the rel path
+#[inline(always)]
+pub fn correlation_path(user_id: &str, correlation_file_name: &str) -> RelativePathBuf {
+    RelativePathBuf::from_iter([
+        USERS_ROOT_DIR,
+        user_id,
+        CORRELATIONS_DIR,
+        correlation_file_name,
+    ])
+}
+
+/// if query_id is an empty str it should not append it to the rel path
+#[inline(always)]
+pub fn saved_query_path(user_id: &str, query_file_name: &str) -> RelativePathBuf {
+    RelativePathBuf::from_iter([USERS_ROOT_DIR, user_id, SAVED_QUERIES_DIR, query_file_name])
+}
+
 /// if filter_id is an empty str it should not append it to the rel path
 #[inline(always)]
 pub fn filter_path(user_id: &str, stream_name: &str, filter_file_name: &str) -> RelativePathBuf {
@@ -636,3 +768,260 @@ pub fn ingestor_metadata_path(id: Option<&str>) -> RelativePathBuf {
         &format!("ingestor.{}.json", INGESTOR_META.get_ingestor_id()),
     ])
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::Path,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use arrow_schema::{DataType, Field, Schema};
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use chrono::Utc;
+    use datafusion::datasource::listing::ListingTableUrl;
+    use object_store::UpdateVersion;
+    use relative_path::{RelativePath, RelativePathBuf};
+
+    use super::{
+        put_widened_schema, to_bytes, ConditionalPut, ObjectStorage, SCHEMA_UPDATE_ATTEMPTS,
+    };
+    use crate::{
+        event::widening::SchemaChange,
+        storage::{localfs::LocalFS, LogStream, ObjectStorageError, StorePermits},
+    };
+
+    // another writer adds a column to the stored schema right after each of
+    // the first `races` reads of it
+    struct RacingStore {
+        inner: LocalFS,
+        conditional: bool,
+        races: usize,
+        reads: AtomicUsize,
+    }
+
+    impl RacingStore {
+        fn new(root: &Path, conditional: bool, races: usize) -> Self {
+            Self {
+                inner: LocalFS::with_permits(root.to_path_buf(), StorePermits::new(4)),
+                conditional,
+                races,
+                reads: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    // the contents of an object stand in for its e-tag
+    fn e_tag(bytes: &[u8]) -> Option<String> {
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    #[async_trait]
+    impl ObjectStorage for RacingStore {
+        async fn get_object_versioned(
+            &self,
+            path: &RelativePath,
+        ) -> Result<(Bytes, Option<UpdateVersion>), ObjectStorageError> {
+            let stored = self.inner.get_object(path).await?;
+            let read = self.reads.fetch_add(1, Ordering::SeqCst);
+            if read < self.races {
+                let schema: Schema = serde_json::from_slice(&stored)?;
+                let mut fields = schema.fields().to_vec();
+                fields.push(Arc::new(Field::new(
+                    format!("added_{read}"),
+                    DataType::Utf8,
+                    true,
+                )));
+                self.inner
+                    .put_object(path, to_bytes(&Schema::new(fields)))
+                    .await?;
+            }
+            let version = self.conditional.then(|| UpdateVersion {
+                e_tag: e_tag(&stored),
+                version: None,
+            });
+            Ok((stored, version))
+        }
+
+        async fn put_object_if_version(
+            &self,
+            path: &RelativePath,
+            resource: Bytes,
+            version: UpdateVersion,
+        ) -> Result<ConditionalPut, ObjectStorageError> {
+            if !self.conditional {
+                return Ok(ConditionalPut::Unsupported);
+            }
+            if e_tag(&self.inner.get_object(path).await?) != version.e_tag {
+                return Ok(ConditionalPut::Changed);
+            }
+            self.inner.put_object(path, resource).await?;
+            Ok(ConditionalPut::Put)
+        }
+
+        async fn get_object(&self, path: &RelativePath) -> Result<Bytes, ObjectStorageError> {
+            self.inner.get_object(path).await
+        }
+
+        async fn get_objects(
+            &self,
+            base_path: Option<&RelativePath>,
+            filter_fun: Box<dyn Fn(String) -> bool + Send>,
+        ) -> Result<Vec<Bytes>, ObjectStorageError> {
+            self.inner.get_objects(base_path, filter_fun).await
+        }
+
+        async fn put_object(
+            &self,
+            path: &RelativePath,
+            resource: Bytes,
+        ) -> Result<(), ObjectStorageError> {
+            self.inner.put_object(path, resource).await
+        }
+
+        async fn delete_prefix(&self, path: &RelativePath) -> Result<(), ObjectStorageError> {
+            self.inner.delete_prefix(path).await
+        }
+
+        async fn check(&self) -> Result<(), ObjectStorageError> {
+            self.inner.check().await
+        }
+
+        async fn delete_stream(&self, stream_name: &str) -> Result<(), ObjectStorageError> {
+            self.inner.delete_stream(stream_name).await
+        }
+
+        async fn list_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError> {
+            self.inner.list_streams().await
+        }
+
+        async fn list_old_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError> {
+            self.inner.list_old_streams().await
+        }
+
+        async fn list_dirs(&self) -> Result<Vec<String>, ObjectStorageError> {
+            self.inner.list_dirs().await
+        }
+
+        async fn list_dates(&self, stream_name: &str) -> Result<Vec<String>, ObjectStorageError> {
+            self.inner.list_dates(stream_name).await
+        }
+
+        async fn upload_file(&self, key: &str, path: &Path) -> Result<(), ObjectStorageError> {
+            self.inner.upload_file(key, path).await
+        }
+
+        async fn delete_object(&self, path: &RelativePath) -> Result<(), ObjectStorageError> {
+            self.inner.delete_object(path).await
+        }
+
+        async fn get_ingestor_meta_file_paths(
+            &self,
+        ) -> Result<Vec<RelativePathBuf>, ObjectStorageError> {
+            self.inner.get_ingestor_meta_file_paths().await
+        }
+
+        async fn get_stream_file_paths(
+            &self,
+            stream_name: &str,
+        ) -> Result<Vec<RelativePathBuf>, ObjectStorageError> {
+            self.inner.get_stream_file_paths(stream_name).await
+        }
+
+        async fn try_delete_ingestor_meta(
+            &self,
+            ingestor_filename: String,
+        ) -> Result<(), ObjectStorageError> {
+            self.inner.try_delete_ingestor_meta(ingestor_filename).await
+        }
+
+        fn query_prefixes(&self, prefixes: Vec<String>) -> Vec<ListingTableUrl> {
+            self.inner.query_prefixes(prefixes)
+        }
+
+        fn absolute_url(&self, prefix: &RelativePath) -> object_store::path::Path {
+            self.inner.absolute_url(prefix)
+        }
+
+        fn store_url(&self) -> url::Url {
+            self.inner.store_url()
+        }
+
+        fn get_bucket_name(&self) -> String {
+            self.inner.get_bucket_name()
+        }
+    }
+
+    fn widen_latency() -> [SchemaChange; 1] {
+        [SchemaChange {
+            field: "latency".to_string(),
+            from: DataType::Int64,
+            to: DataType::Float64,
+            changed_at: Utc::now(),
+        }]
+    }
+
+    async fn stored_schema(store: &RacingStore, path: &RelativePath) -> Schema {
+        serde_json::from_slice(&store.inner.get_object(path).await.unwrap()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn widening_retries_when_the_schema_changed_since_it_was_read() {
+        // with conditional writes and with the read-then-write fallback
+        for conditional in [true, false] {
+            let dir = tempfile::tempdir().unwrap();
+            let store = RacingStore::new(dir.path(), conditional, 2);
+            let path = RelativePath::new("app/.stream/.schema");
+            let schema = Schema::new(vec![Field::new("latency", DataType::Int64, true)]);
+            store
+                .inner
+                .put_object(path, to_bytes(&schema))
+                .await
+                .unwrap();
+
+            put_widened_schema(&store, "app", path, &widen_latency())
+                .await
+                .unwrap();
+
+            // two writes lost the race, the third read went through
+            assert_eq!(store.reads.load(Ordering::SeqCst), 3, "{conditional}");
+            let stored = stored_schema(&store, path).await;
+            assert_eq!(
+                stored.field_with_name("latency").unwrap().data_type(),
+                &DataType::Float64
+            );
+            // neither concurrent change was overwritten
+            assert!(stored.field_with_name("added_0").is_ok(), "{conditional}");
+            assert!(stored.field_with_name("added_1").is_ok(), "{conditional}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn widening_gives_up_on_a_schema_that_keeps_changing() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = RacingStore::new(dir.path(), true, usize::MAX);
+        let path = RelativePath::new("app/.stream/.schema");
+        let schema = Schema::new(vec![Field::new("latency", DataType::Int64, true)]);
+        store
+            .inner
+            .put_object(path, to_bytes(&schema))
+            .await
+            .unwrap();
+
+        let err = put_widened_schema(&store, "app", path, &widen_latency())
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("kept changing"), "{err}");
+        assert_eq!(store.reads.load(Ordering::SeqCst), SCHEMA_UPDATE_ATTEMPTS);
+        let stored = stored_schema(&store, path).await;
+        assert_eq!(
+            stored.field_with_name("latency").unwrap().data_type(),
+            &DataType::Int64
+        );
+    }
+}
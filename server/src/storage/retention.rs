@@ -104,6 +104,23 @@ pub fn init_scheduler() {
     log::info!("Scheduler is initialized")
 }
 
+/// Runs the retention tasks of a stream right away instead of at the daily run,
+/// returning whether any data was deleted
+pub async fn enforce(stream: &str) -> bool {
+    let Ok(Some(retention)) = STREAM_INFO.get_retention(stream) else {
+        return false;
+    };
+    let mut deleted = false;
+    for Task { action, days, .. } in retention.tasks {
+        match action {
+            Action::Delete => {
+                deleted |= action::delete(stream.to_string(), u32::from(days)).await;
+            }
+        }
+    }
+    deleted
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
 #[serde(try_from = "Vec<TaskView>")]
 #[serde(into = "Vec<TaskView>")]
@@ -188,20 +205,22 @@ impl From<Retention> for Vec<TaskView> {
 
 mod action {
     use crate::catalog::remove_manifest_from_snapshot;
+    use crate::query::result_cache::RESULT_CACHE;
     use crate::{metadata, option::CONFIG};
     use chrono::{Days, NaiveDate, Utc};
     use futures::{stream::FuturesUnordered, StreamExt};
     use itertools::Itertools;
     use relative_path::RelativePathBuf;
 
-    pub(super) async fn delete(stream_name: String, days: u32) {
+    // whether the data of any date was deleted
+    pub(super) async fn delete(stream_name: String, days: u32) -> bool {
         log::info!("running retention task - delete for stream={stream_name}");
         let store = CONFIG.storage().get_object_store();
 
         let retain_until = get_retain_until(Utc::now().date_naive(), days as u64);
 
         let Ok(mut dates) = store.list_dates(&stream_name).await else {
-            return;
+            return false;
         };
         dates.retain(|date| date.starts_with("date"));
         let dates_to_delete = dates
@@ -230,9 +249,12 @@ mod action {
             for res in res {
                 if let Err(err) = res {
                     log::error!("Failed to run delete task {err:?}");
-                    return;
+                    return false;
                 }
             }
+            if let Some(result_cache) = RESULT_CACHE.as_ref() {
+                result_cache.invalidate_stream(&stream_name);
+            }
             if let Ok(first_event_at) = res_remove_manifest {
                 if let Err(err) =
                     metadata::STREAM_INFO.set_first_event_at(&stream_name, first_event_at)
@@ -244,6 +266,7 @@ mod action {
                 }
             }
         }
+        !dates.is_empty()
     }
 
     fn get_retain_until(current_date: NaiveDate, days: u64) -> NaiveDate {
@@ -25,11 +25,12 @@ use datafusion::datasource::object_store::{
 use datafusion::execution::runtime_env::RuntimeConfig;
 use futures::stream::FuturesUnordered;
 use futures::{StreamExt, TryStreamExt};
-use object_store::aws::{AmazonS3, AmazonS3Builder, AmazonS3ConfigKey, Checksum};
+use object_store::aws::{AmazonS3, AmazonS3Builder, AmazonS3ConfigKey, Checksum, S3ConditionalPut};
 use object_store::limit::LimitStore;
 use object_store::path::Path as StorePath;
-use object_store::{ClientOptions, ObjectStore};
+use object_store::{ClientOptions, ObjectStore, PutMode, PutOptions, UpdateVersion};
 use relative_path::{RelativePath, RelativePathBuf};
+use serde::Serialize;
 use tokio::fs::OpenOptions;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
@@ -40,12 +41,14 @@ use std::time::{Duration, Instant};
 
 use crate::handlers::http::users::USERS_ROOT_DIR;
 use crate::metrics::storage::{s3::REQUEST_RESPONSE_TIME, StorageMetrics};
+use crate::option::{redacted, CONFIG};
 use crate::storage::{LogStream, ObjectStorage, ObjectStorageError, PARSEABLE_ROOT_DIRECTORY};
 
 use super::metrics_layer::MetricLayer;
-use super::object_storage::parseable_json_path;
+use super::object_storage::{parseable_json_path, ConditionalPut};
 use super::{
-    ObjectStorageProvider, SCHEMA_FILE_NAME, STREAM_METADATA_FILE_NAME, STREAM_ROOT_DIRECTORY,
+    ObjectStorageProvider, StorePermits, SCHEMA_FILE_NAME, STREAM_METADATA_FILE_NAME,
+    STREAM_ROOT_DIRECTORY,
 };
 
 // in bytes
@@ -53,7 +56,7 @@ const MULTIPART_UPLOAD_SIZE: usize = 1024 * 1024 * 100;
 const CONNECT_TIMEOUT_SECS: u64 = 5;
 const AWS_CONTAINER_CREDENTIALS_RELATIVE_URI: &str = "AWS_CONTAINER_CREDENTIALS_RELATIVE_URI";
 
-#[derive(Debug, Clone, clap::Args)]
+#[derive(Debug, Clone, clap::Args, Serialize)]
 #[command(
     name = "S3 config",
     about = "Start Parseable with S3 or compatible as storage",
@@ -73,6 +76,7 @@ pub struct S3Config {
 
     /// The secret key for AWS S3 or compatible object storage platform
     #[arg(long, env = "P_S3_SECRET_KEY", value_name = "secret-key")]
+    #[serde(serialize_with = "redacted::option")]
     pub secret_key: Option<String>,
 
     /// The region for AWS S3 or compatible object storage platform
@@ -127,6 +131,15 @@ pub struct S3Config {
         required = false
     )]
     pub metadata_endpoint: Option<String>,
+
+    /// Set client to put the schema conditionally with If-Match, for stores supporting it
+    #[arg(
+        long,
+        env = "P_S3_CONDITIONAL_PUT",
+        value_name = "bool",
+        default_value = "false"
+    )]
+    pub conditional_put: bool,
 }
 
 impl S3Config {
@@ -173,6 +186,10 @@ impl S3Config {
             builder = builder.with_metadata_endpoint(metadata_endpoint)
         }
 
+        if self.conditional_put {
+            builder = builder.with_conditional_put(S3ConditionalPut::ETagMatch)
+        }
+
         builder.with_client_options(client_options)
     }
 }
@@ -182,7 +199,7 @@ impl ObjectStorageProvider for S3Config {
         let s3 = self.get_default_builder().build().unwrap();
 
         // limit objectstore to a concurrent request limit
-        let s3 = LimitStore::new(s3, super::MAX_OBJECT_STORE_REQUESTS);
+        let s3 = LimitStore::new(s3, CONFIG.parseable.store_concurrency);
         let s3 = MetricLayer::new(s3);
 
         let object_store_registry: DefaultObjectStoreRegistry = DefaultObjectStoreRegistry::new();
@@ -195,11 +212,9 @@ impl ObjectStorageProvider for S3Config {
     fn get_object_store(&self) -> Arc<dyn ObjectStorage + Send> {
         let s3 = self.get_default_builder().build().unwrap();
 
-        // limit objectstore to a concurrent request limit
-        let s3 = LimitStore::new(s3, super::MAX_OBJECT_STORE_REQUESTS);
-
         Arc::new(S3 {
             client: s3,
+            permits: StorePermits::global(),
             bucket: self.bucket_name.clone(),
             root: StorePath::from(""),
         })
@@ -212,6 +227,10 @@ impl ObjectStorageProvider for S3Config {
     fn register_store_metrics(&self, handler: &actix_web_prometheus::PrometheusMetrics) {
         self.register_metrics(handler)
     }
+
+    fn effective_config(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("storage config is serializable")
+    }
 }
 
 fn to_object_store_path(path: &RelativePath) -> StorePath {
@@ -219,7 +238,9 @@ fn to_object_store_path(path: &RelativePath) -> StorePath {
 }
 
 pub struct S3 {
-    client: LimitStore<AmazonS3>,
+    client: AmazonS3,
+    // concurrent requests are bounded by permits shared with every other store handle
+    permits: StorePermits,
     bucket: String,
     root: StorePath,
 }
@@ -410,6 +431,7 @@ impl S3 {
 #[async_trait]
 impl ObjectStorage for S3 {
     async fn get_object(&self, path: &RelativePath) -> Result<Bytes, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         Ok(self._get_object(path).await?)
     }
 
@@ -418,6 +440,7 @@ impl ObjectStorage for S3 {
         base_path: Option<&RelativePath>,
         filter_func: Box<dyn Fn(String) -> bool + Send>,
     ) -> Result<Vec<Bytes>, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let instant = Instant::now();
 
         let prefix = if let Some(base_path) = base_path {
@@ -438,7 +461,7 @@ impl ObjectStorage for S3 {
             }
 
             let byts = self
-                .get_object(
+                ._get_object(
                     RelativePath::from_path(meta.location.as_ref())
                         .map_err(ObjectStorageError::PathError)?,
                 )
@@ -458,6 +481,7 @@ impl ObjectStorage for S3 {
     async fn get_ingestor_meta_file_paths(
         &self,
     ) -> Result<Vec<RelativePathBuf>, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let time = Instant::now();
         let mut path_arr = vec![];
         let mut object_stream = self.client.list(Some(&self.root));
@@ -482,6 +506,7 @@ impl ObjectStorage for S3 {
         &self,
         stream_name: &str,
     ) -> Result<Vec<RelativePathBuf>, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let time = Instant::now();
         let mut path_arr = vec![];
         let path = to_object_store_path(&RelativePathBuf::from(stream_name));
@@ -514,6 +539,7 @@ impl ObjectStorage for S3 {
         path: &RelativePath,
         resource: Bytes,
     ) -> Result<(), ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         self._put_object(path, resource)
             .await
             .map_err(|err| ObjectStorageError::ConnectionError(Box::new(err)))?;
@@ -521,17 +547,76 @@ impl ObjectStorage for S3 {
         Ok(())
     }
 
+    async fn get_object_versioned(
+        &self,
+        path: &RelativePath,
+    ) -> Result<(Bytes, Option<UpdateVersion>), ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
+        let time = Instant::now();
+        let resp = self.client.get(&to_object_store_path(path)).await;
+        let status = if resp.is_ok() { "200" } else { "400" };
+        REQUEST_RESPONSE_TIME
+            .with_label_values(&["GET", status])
+            .observe(time.elapsed().as_secs_f64());
+
+        let resp = resp?;
+        let version = UpdateVersion {
+            e_tag: resp.meta.e_tag.clone(),
+            version: resp.meta.version.clone(),
+        };
+        let versioned = version.e_tag.is_some() || version.version.is_some();
+        let body = resp.bytes().await?;
+        Ok((body, versioned.then_some(version)))
+    }
+
+    async fn put_object_if_version(
+        &self,
+        path: &RelativePath,
+        resource: Bytes,
+        version: UpdateVersion,
+    ) -> Result<ConditionalPut, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
+        let time = Instant::now();
+        let opts = PutOptions {
+            mode: PutMode::Update(version),
+            ..Default::default()
+        };
+        let resp = self
+            .client
+            .put_opts(&to_object_store_path(path), resource, opts)
+            .await;
+        let status = match &resp {
+            Ok(_) => "200",
+            Err(object_store::Error::Precondition { .. }) => "412",
+            Err(_) => "400",
+        };
+        REQUEST_RESPONSE_TIME
+            .with_label_values(&["PUT", status])
+            .observe(time.elapsed().as_secs_f64());
+
+        match resp {
+            Ok(_) => Ok(ConditionalPut::Put),
+            Err(object_store::Error::Precondition { .. }) => Ok(ConditionalPut::Changed),
+            // the client only puts conditionally when configured to
+            Err(object_store::Error::NotImplemented) => Ok(ConditionalPut::Unsupported),
+            Err(err) => Err(err.into()),
+        }
+    }
+
     async fn delete_prefix(&self, path: &RelativePath) -> Result<(), ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         self._delete_prefix(path.as_ref()).await?;
 
         Ok(())
     }
 
     async fn delete_object(&self, path: &RelativePath) -> Result<(), ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         Ok(self.client.delete(&to_object_store_path(path)).await?)
     }
 
     async fn check(&self) -> Result<(), ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         Ok(self
             .client
             .head(&to_object_store_path(&parseable_json_path()))
@@ -540,6 +625,7 @@ impl ObjectStorage for S3 {
     }
 
     async fn delete_stream(&self, stream_name: &str) -> Result<(), ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         self._delete_prefix(stream_name).await?;
 
         Ok(())
@@ -549,6 +635,7 @@ impl ObjectStorage for S3 {
         &self,
         ingestor_filename: String,
     ) -> Result<(), ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let file = RelativePathBuf::from(&ingestor_filename);
         match self.client.delete(&to_object_store_path(&file)).await {
             Ok(_) => Ok(()),
@@ -567,12 +654,14 @@ impl ObjectStorage for S3 {
     }
 
     async fn list_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let streams = self._list_streams().await?;
 
         Ok(streams)
     }
 
     async fn list_old_streams(&self) -> Result<Vec<LogStream>, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let resp = self.client.list_with_delimiter(None).await?;
 
         let common_prefixes = resp.common_prefixes; // get all dirs
@@ -599,12 +688,14 @@ impl ObjectStorage for S3 {
     }
 
     async fn list_dates(&self, stream_name: &str) -> Result<Vec<String>, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let streams = self._list_dates(stream_name).await?;
 
         Ok(streams)
     }
 
     async fn upload_file(&self, key: &str, path: &StdPath) -> Result<(), ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         self._upload_file(key, path).await?;
 
         Ok(())
@@ -629,6 +720,7 @@ impl ObjectStorage for S3 {
     }
 
     async fn list_dirs(&self) -> Result<Vec<String>, ObjectStorageError> {
+        let _permit = self.permits.acquire().await;
         let pre = object_store::path::Path::from("/");
         let resp = self.client.list_with_delimiter(Some(&pre)).await?;
 
@@ -18,7 +18,7 @@
  */
 
 use crate::{
-    event::DEFAULT_TIMESTAMP_KEY,
+    event::{widening::merge_schemas, DEFAULT_TIMESTAMP_KEY},
     handlers::http::modal::{ingest_server::INGESTOR_META, IngestorMetadata, DEFAULT_VERSION},
     metrics,
     option::{Mode, CONFIG},
@@ -287,7 +287,7 @@ pub fn convert_disk_files_to_parquet(
     }
 
     if !schemas.is_empty() {
-        Ok(Some(Schema::try_merge(schemas).unwrap()))
+        Ok(Some(merge_schemas(schemas).unwrap()))
     } else {
         Ok(None)
     }
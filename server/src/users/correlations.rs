@@ -0,0 +1,173 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use once_cell::sync::Lazy;
+use relative_path::RelativePathBuf;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+use crate::{handlers::http::users::USERS_ROOT_DIR, metadata::LOCK_EXPECT, option::CONFIG};
+
+pub static CORRELATIONS: Lazy<Correlations> = Lazy::new(Correlations::default);
+
+/// Events of several streams that belong together, matched on a field of
+/// each stream and on how close in time they were ingested
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Correlation {
+    version: String,
+    correlation_id: String,
+    correlation_name: String,
+    /// streams in join order, every stream is matched against the first one
+    table_configs: Vec<TableConfig>,
+    /// most milliseconds between the p_timestamp of correlated events
+    time_tolerance_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TableConfig {
+    pub stream_name: String,
+    pub join_field: String,
+    /// fields returned for the stream, all of them when unset
+    pub selected_fields: Option<Vec<String>>,
+}
+
+impl Correlation {
+    pub fn correlation_id(&self) -> &str {
+        &self.correlation_id
+    }
+
+    pub fn table_configs(&self) -> &[TableConfig] {
+        &self.table_configs
+    }
+
+    pub fn time_tolerance_ms(&self) -> u64 {
+        self.time_tolerance_ms
+    }
+
+    /// Why the correlation can't be executed, if it can't
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.table_configs.len() < 2 {
+            return Err("A correlation needs at least two streams");
+        }
+        let mut streams: Vec<_> = self
+            .table_configs
+            .iter()
+            .map(|table| &table.stream_name)
+            .collect();
+        streams.sort();
+        streams.dedup();
+        if streams.len() != self.table_configs.len() {
+            return Err("A stream can appear only once in a correlation");
+        }
+        Ok(())
+    }
+
+    /// First stream `can_query` refuses, the correlation reads all of them
+    pub fn unauthorized_stream(&self, can_query: impl Fn(&str) -> bool) -> Option<&str> {
+        self.table_configs
+            .iter()
+            .map(|table| table.stream_name.as_str())
+            .find(|stream| !can_query(stream))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Correlations(RwLock<Vec<Correlation>>);
+
+impl Correlations {
+    pub async fn load(&self) -> anyhow::Result<()> {
+        let mut this = vec![];
+        let path = RelativePathBuf::from(USERS_ROOT_DIR);
+        let store = CONFIG.storage().get_object_store();
+
+        let objs = store
+            .get_objects(Some(&path), Box::new(|path| path.ends_with(".json")))
+            .await
+            .unwrap_or_default();
+
+        for obj in objs {
+            if let Ok(correlation) = serde_json::from_slice::<Correlation>(&obj) {
+                this.push(correlation);
+            }
+        }
+
+        let mut s = self.0.write().expect(LOCK_EXPECT);
+        s.append(&mut this);
+
+        Ok(())
+    }
+
+    pub fn update(&self, correlation: Correlation) {
+        let mut s = self.0.write().expect(LOCK_EXPECT);
+        s.retain(|c| c.correlation_id() != correlation.correlation_id());
+        s.push(correlation);
+    }
+
+    pub fn delete(&self, correlation_id: &str) {
+        let mut s = self.0.write().expect(LOCK_EXPECT);
+        s.retain(|c| c.correlation_id() != correlation_id);
+    }
+
+    pub fn find(&self, correlation_id: &str) -> Option<Correlation> {
+        self.0
+            .read()
+            .expect(LOCK_EXPECT)
+            .iter()
+            .find(|correlation| correlation.correlation_id() == correlation_id)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Correlation;
+
+    fn correlation(streams: &[&str]) -> Correlation {
+        let table_configs: Vec<_> = streams
+            .iter()
+            .map(|stream| json!({"stream_name": stream, "join_field": "trace_id"}))
+            .collect();
+        serde_json::from_value(json!({
+            "version": "v1",
+            "correlation_id": "c1",
+            "correlation_name": "requests",
+            "table_configs": table_configs,
+            "time_tolerance_ms": 1000,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn every_stream_needs_query_rights() {
+        let correlation = correlation(&["frontend", "backend"]);
+        assert_eq!(correlation.unauthorized_stream(|_| true), None);
+        assert_eq!(
+            correlation.unauthorized_stream(|stream| stream == "frontend"),
+            Some("backend")
+        );
+    }
+
+    #[test]
+    fn streams_are_validated() {
+        assert!(correlation(&["frontend", "backend"]).validate().is_ok());
+        assert!(correlation(&["frontend"]).validate().is_err());
+        assert!(correlation(&["frontend", "frontend"]).validate().is_err());
+    }
+}
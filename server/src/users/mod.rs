@@ -16,8 +16,10 @@
  *
  */
 
+pub mod correlations;
 pub mod dashboards;
 pub mod filters;
+pub mod saved_queries;
 
 use serde::{Deserialize, Serialize};
 
@@ -0,0 +1,402 @@
+/*
+ * Parseable Server (C) 2022 - 2024 Parseable, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ */
+
+use chrono::DateTime;
+use datafusion::sql::sqlparser::{
+    dialect::GenericDialect,
+    tokenizer::{Token, Tokenizer},
+};
+use once_cell::sync::Lazy;
+use relative_path::RelativePathBuf;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{collections::HashSet, fmt, sync::RwLock};
+
+use crate::{handlers::http::users::USERS_ROOT_DIR, metadata::LOCK_EXPECT, option::CONFIG};
+
+pub static SAVED_QUERIES: Lazy<SavedQueries> = Lazy::new(SavedQueries::default);
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum ParameterError {
+    #[error("Placeholder {{{{{0}}}}} has no parameter declared for it")]
+    Undeclared(String),
+    #[error("Parameter {0} is declared more than once")]
+    Duplicate(String),
+    #[error("No value given for parameter {0}")]
+    Missing(String),
+    #[error("Saved query has no parameter {0}")]
+    Unknown(String),
+    #[error("Parameter {0} expects a {1}")]
+    Type(String, ParameterType),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ParameterType {
+    String,
+    Number,
+    /// RFC 3339 timestamp such as 2024-05-01T10:00:00Z
+    Timestamp,
+}
+
+impl fmt::Display for ParameterType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::String => f.write_str("string"),
+            Self::Number => f.write_str("number"),
+            Self::Timestamp => f.write_str("timestamp"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Parameter {
+    name: String,
+    #[serde(rename = "type")]
+    kind: ParameterType,
+}
+
+impl Parameter {
+    // the value as a SQL literal that can't end up as anything but that literal
+    fn to_sql(&self, value: &Value) -> Result<String, ParameterError> {
+        let invalid = || ParameterError::Type(self.name.clone(), self.kind);
+        match (self.kind, value) {
+            (ParameterType::String, Value::String(value)) => quote(value).ok_or_else(invalid),
+            // parenthesised so that `x-{{n}}` can't turn into a `--` comment
+            (ParameterType::Number, Value::Number(value)) => Ok(format!("({value})")),
+            (ParameterType::Timestamp, Value::String(value)) => {
+                let timestamp = DateTime::parse_from_rfc3339(value).map_err(|_| invalid())?;
+                let timestamp = timestamp.naive_utc().format("%Y-%m-%dT%H:%M:%S%.f");
+                Ok(format!("CAST('{timestamp}' AS TIMESTAMP)"))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// a single quoted literal, None unless the tokenizer reads it back as exactly `value`
+fn quote(value: &str) -> Option<String> {
+    let literal = format!("'{}'", value.replace('\'', "''"));
+    let tokens = Tokenizer::new(&GenericDialect {}, &literal)
+        .tokenize()
+        .ok()?;
+    match tokens.as_slice() {
+        [Token::SingleQuotedString(parsed)] if parsed == value => Some(literal),
+        _ => None,
+    }
+}
+
+/// Placeholder names in `sql` with their byte ranges, `{{name}}` included
+fn placeholders(sql: &str) -> Vec<(std::ops::Range<usize>, &str)> {
+    let mut found = vec![];
+    let mut from = 0;
+    while let Some(start) = sql[from..].find("{{").map(|start| from + start) {
+        let Some(end) = sql[start..].find("}}").map(|end| start + end) else {
+            break;
+        };
+        let name = sql[start + 2..end].trim();
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            found.push((start..end + 2, name));
+            from = end + 2;
+        } else {
+            from = start + 2;
+        }
+    }
+    found
+}
+
+/// SQL saved with named `{{placeholders}}` filled in from typed parameters
+/// when it is executed
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SavedQuery {
+    version: String,
+    query_id: String,
+    query_name: String,
+    query: String,
+    #[serde(default)]
+    parameters: Vec<Parameter>,
+    /// other users can execute the query
+    #[serde(default)]
+    shared: bool,
+    /// bumped on every update, an update names the revision it replaces
+    #[serde(default)]
+    revision: u64,
+    /// set from the path the query is saved under
+    #[serde(default)]
+    user_id: String,
+}
+
+impl SavedQuery {
+    pub fn query_id(&self) -> &str {
+        &self.query_id
+    }
+
+    pub fn user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Stores the query as the owner's `revision`
+    pub fn save_as(&mut self, user_id: &str, revision: u64) {
+        self.user_id = user_id.to_owned();
+        self.revision = revision;
+    }
+
+    /// Checks that every placeholder is declared once
+    pub fn validate(&self) -> Result<(), ParameterError> {
+        let mut declared = HashSet::new();
+        for parameter in &self.parameters {
+            if !declared.insert(parameter.name.as_str()) {
+                return Err(ParameterError::Duplicate(parameter.name.clone()));
+            }
+        }
+        match placeholders(&self.query)
+            .into_iter()
+            .find(|(_, name)| !declared.contains(name))
+        {
+            Some((_, name)) => Err(ParameterError::Undeclared(name.to_owned())),
+            None => Ok(()),
+        }
+    }
+
+    /// The SQL with every placeholder replaced by its value from `values`
+    pub fn render(&self, values: &Map<String, Value>) -> Result<String, ParameterError> {
+        self.validate()?;
+        if let Some(unknown) = values
+            .keys()
+            .find(|name| !self.parameters.iter().any(|p| &p.name == *name))
+        {
+            return Err(ParameterError::Unknown(unknown.clone()));
+        }
+        let mut literals = Map::new();
+        for parameter in &self.parameters {
+            let value = values
+                .get(&parameter.name)
+                .ok_or_else(|| ParameterError::Missing(parameter.name.clone()))?;
+            literals.insert(parameter.name.clone(), parameter.to_sql(value)?.into());
+        }
+
+        let mut sql = String::with_capacity(self.query.len());
+        let mut copied = 0;
+        for (range, name) in placeholders(&self.query) {
+            sql.push_str(&self.query[copied..range.start]);
+            sql.push_str(literals[name].as_str().expect("literals are strings"));
+            copied = range.end;
+        }
+        sql.push_str(&self.query[copied..]);
+        Ok(sql)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SavedQueries(RwLock<Vec<SavedQuery>>);
+
+impl SavedQueries {
+    pub async fn load(&self) -> anyhow::Result<()> {
+        let mut this = vec![];
+        let path = RelativePathBuf::from(USERS_ROOT_DIR);
+        let store = CONFIG.storage().get_object_store();
+
+        let objs = store
+            .get_objects(Some(&path), Box::new(|path| path.ends_with(".json")))
+            .await
+            .unwrap_or_default();
+
+        for obj in objs {
+            if let Ok(query) = serde_json::from_slice::<SavedQuery>(&obj) {
+                this.push(query);
+            }
+        }
+
+        let mut s = self.0.write().expect(LOCK_EXPECT);
+        s.append(&mut this);
+
+        Ok(())
+    }
+
+    pub fn update(&self, query: SavedQuery) {
+        let mut s = self.0.write().expect(LOCK_EXPECT);
+        s.retain(|q| q.user_id() != query.user_id() || q.query_id() != query.query_id());
+        s.push(query);
+    }
+
+    pub fn delete(&self, user_id: &str, query_id: &str) {
+        let mut s = self.0.write().expect(LOCK_EXPECT);
+        s.retain(|q| q.user_id() != user_id || q.query_id() != query_id);
+    }
+
+    pub fn find(&self, user_id: &str, query_id: &str) -> Option<SavedQuery> {
+        self.0
+            .read()
+            .expect(LOCK_EXPECT)
+            .iter()
+            .find(|query| query.user_id() == user_id && query.query_id() == query_id)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::sql::sqlparser::{
+        ast::{Expr as SqlExpr, SetExpr, Statement, Value as SqlValue},
+        dialect::GenericDialect,
+        parser::Parser,
+        tokenizer::Token,
+    };
+    use serde_json::{json, Map, Value};
+
+    use super::{ParameterError, ParameterType, SavedQuery};
+
+    fn saved_query(query: &str) -> SavedQuery {
+        serde_json::from_value(json!({
+            "version": "v1",
+            "query_id": "q1",
+            "query_name": "errors by service",
+            "query": query,
+            "parameters": [
+                {"name": "service", "type": "string"},
+                {"name": "status", "type": "number"},
+                {"name": "since", "type": "timestamp"},
+            ],
+        }))
+        .unwrap()
+    }
+
+    fn values(values: Value) -> Map<String, Value> {
+        values.as_object().unwrap().clone()
+    }
+
+    const SQL: &str = "SELECT * FROM app WHERE service = {{service}} AND status >= {{ status }} \
+                       AND p_timestamp > {{since}}";
+
+    #[test]
+    fn renders_typed_literals() {
+        let sql = saved_query(SQL)
+            .render(&values(json!({
+                "service": "checkout",
+                "status": 500,
+                "since": "2024-05-01T12:00:00+02:00",
+            })))
+            .unwrap();
+        assert_eq!(
+            sql,
+            "SELECT * FROM app WHERE service = 'checkout' AND status >= (500) \
+             AND p_timestamp > CAST('2024-05-01T10:00:00' AS TIMESTAMP)"
+        );
+    }
+
+    #[test]
+    fn parameters_are_type_checked() {
+        let query = saved_query(SQL);
+        for (name, value, kind) in [
+            ("service", json!(5), ParameterType::String),
+            ("status", json!("500"), ParameterType::Number),
+            ("since", json!("yesterday"), ParameterType::Timestamp),
+            ("since", json!(1714557600000u64), ParameterType::Timestamp),
+        ] {
+            let mut given = values(json!({
+                "service": "checkout",
+                "status": 500,
+                "since": "2024-05-01T10:00:00Z",
+            }));
+            given.insert(name.to_owned(), value);
+            assert_eq!(
+                query.render(&given),
+                Err(ParameterError::Type(name.to_owned(), kind))
+            );
+        }
+    }
+
+    #[test]
+    fn parameters_must_match_declarations() {
+        let query = saved_query(SQL);
+        assert_eq!(
+            query.render(&values(json!({"service": "checkout", "status": 500}))),
+            Err(ParameterError::Missing("since".to_owned()))
+        );
+        assert_eq!(
+            query.render(&values(json!({
+                "service": "checkout",
+                "status": 500,
+                "since": "2024-05-01T10:00:00Z",
+                "region": "eu",
+            }))),
+            Err(ParameterError::Unknown("region".to_owned()))
+        );
+        assert_eq!(
+            saved_query("SELECT * FROM app WHERE host = {{host}}").validate(),
+            Err(ParameterError::Undeclared("host".to_owned()))
+        );
+    }
+
+    #[test]
+    fn string_parameters_cannot_inject_sql() {
+        let query = saved_query("SELECT * FROM app WHERE service = {{service}}");
+        for attempt in [
+            "x' OR '1'='1",
+            "x'; DROP TABLE app; --",
+            "x\\' OR 1=1 --",
+            "{{status}}",
+        ] {
+            let sql = query
+                .render(&values(json!({
+                    "service": attempt,
+                    "status": 1,
+                    "since": "2024-05-01T10:00:00Z",
+                })))
+                .unwrap();
+            // still one statement comparing service with the whole attempt
+            let mut parser = Parser::new(&GenericDialect {}).try_with_sql(&sql).unwrap();
+            let statement = parser.parse_statement().unwrap();
+            assert_eq!(parser.peek_token().token, Token::EOF, "{sql}");
+            let Statement::Query(query) = statement else {
+                panic!("{sql}")
+            };
+            let SetExpr::Select(select) = *query.body else {
+                panic!("{sql}")
+            };
+            let Some(SqlExpr::BinaryOp { right, .. }) = select.selection else {
+                panic!("{sql}")
+            };
+            assert_eq!(
+                *right,
+                SqlExpr::Value(SqlValue::SingleQuotedString(attempt.to_owned()))
+            );
+        }
+    }
+
+    #[test]
+    fn negative_numbers_cannot_start_a_comment() {
+        let query = saved_query("SELECT 1-{{status}} FROM app");
+        let sql = query
+            .render(&values(json!({
+                "service": "",
+                "status": -1,
+                "since": "2024-05-01T10:00:00Z",
+            })))
+            .unwrap();
+        assert_eq!(sql, "SELECT 1-(-1) FROM app");
+    }
+}
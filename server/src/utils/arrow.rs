@@ -19,8 +19,8 @@
 
 use std::sync::Arc;
 
-use arrow_array::{Array, RecordBatch};
-use arrow_schema::Schema;
+use arrow_array::{make_array, Array, ArrayRef, RecordBatch};
+use arrow_schema::{ArrowError, DataType, Schema};
 use itertools::Itertools;
 
 pub mod batch_adapter;
@@ -126,14 +126,58 @@ pub fn get_field<'a>(
         .find(|field| field.name() == name)
 }
 
+/// Labels a timestamp array with `time_zone`, or removes its label when None.
+///
+/// Only the label changes, the values stay the same instants. Other arrays are
+/// returned as they are.
+pub fn with_time_zone(
+    array: &ArrayRef,
+    time_zone: Option<Arc<str>>,
+) -> std::result::Result<ArrayRef, ArrowError> {
+    let DataType::Timestamp(unit, _) = array.data_type() else {
+        return Ok(array.clone());
+    };
+    let data = array
+        .to_data()
+        .into_builder()
+        .data_type(DataType::Timestamp(unit.clone(), time_zone))
+        .build()?;
+    Ok(make_array(data))
+}
+
+/// `batch` with the timestamp columns that have no time zone displayed in
+/// `time_zone`. Those columns hold UTC, which stays the instant they refer to.
+pub fn timestamps_in_time_zone(
+    batch: &RecordBatch,
+    time_zone: &str,
+) -> std::result::Result<RecordBatch, ArrowError> {
+    let time_zone: Arc<str> = time_zone.into();
+    let mut fields = vec![];
+    let mut columns = vec![];
+    for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+        match field.data_type() {
+            DataType::Timestamp(unit, None) => {
+                let data_type = DataType::Timestamp(unit.clone(), Some(time_zone.clone()));
+                fields.push(Arc::new(field.as_ref().clone().with_data_type(data_type)));
+                columns.push(with_time_zone(column, Some(time_zone.clone()))?);
+            }
+            _ => {
+                fields.push(field.clone());
+                columns.push(column.clone());
+            }
+        }
+    }
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
 
-    use arrow_array::{Array, Int32Array, RecordBatch};
-    use arrow_schema::{DataType, Field, Schema};
+    use arrow_array::{Array, Int32Array, RecordBatch, TimestampMillisecondArray};
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
 
-    use super::{record_batches_to_json, replace_columns};
+    use super::{record_batches_to_json, replace_columns, timestamps_in_time_zone};
 
     #[test]
     fn check_replace() {
@@ -171,4 +215,30 @@ mod tests {
         let batches = record_batches_to_json(&rb).unwrap();
         assert_eq!(batches, vec![]);
     }
+
+    #[test]
+    fn timestamps_are_formatted_in_time_zone() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "p_timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("status", DataType::Int32, false),
+        ]));
+        // 2024-03-10T12:00:00Z, after New York moved to daylight saving time
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(TimestampMillisecondArray::from(vec![1_710_072_000_000])),
+                Arc::new(Int32Array::from(vec![200])),
+            ],
+        )
+        .unwrap();
+
+        let local = timestamps_in_time_zone(&batch, "America/New_York").unwrap();
+        let rows = record_batches_to_json(&[&local]).unwrap();
+        assert_eq!(rows[0]["p_timestamp"], "2024-03-10T08:00:00-04:00");
+        assert_eq!(rows[0]["status"], 200);
+    }
 }
@@ -18,6 +18,7 @@
 
 use datafusion::arrow::array::new_null_array;
 use datafusion::arrow::array::ArrayRef;
+use datafusion::arrow::compute::cast;
 use datafusion::arrow::datatypes::Schema;
 use datafusion::arrow::record_batch::RecordBatch;
 
@@ -29,7 +30,8 @@ use std::sync::Arc;
 // in the record batch (i.e. the event) but are present in the
 // log stream schema.
 // This is necessary because all the record batches in a log
-// stream need to have all the fields.
+// stream need to have all the fields. Columns of a type the
+// stream schema has widened since are cast to the wider type.
 pub fn adapt_batch(table_schema: &Schema, batch: &RecordBatch) -> RecordBatch {
     let batch_schema = &*batch.schema();
     let batch_cols = batch.columns().to_vec();
@@ -37,7 +39,12 @@ pub fn adapt_batch(table_schema: &Schema, batch: &RecordBatch) -> RecordBatch {
     let mut cols: Vec<ArrayRef> = Vec::with_capacity(table_schema.fields().len());
     for table_field in table_schema.fields() {
         if let Some((batch_idx, _)) = batch_schema.column_with_name(table_field.name().as_str()) {
-            cols.push(Arc::clone(&batch_cols[batch_idx]));
+            let col = &batch_cols[batch_idx];
+            if col.data_type() == table_field.data_type() {
+                cols.push(Arc::clone(col));
+            } else {
+                cols.push(cast(col, table_field.data_type()).expect("column type is widenable"));
+            }
         } else {
             cols.push(new_null_array(table_field.data_type(), batch.num_rows()))
         }
@@ -27,7 +27,10 @@ use super::{
     adapt_batch,
     reverse_reader::{reverse, OffsetReader},
 };
-use crate::{event::DEFAULT_TIMESTAMP_KEY, utils};
+use crate::{
+    event::{widening::merge_schemas, DEFAULT_TIMESTAMP_KEY},
+    utils,
+};
 
 #[derive(Debug)]
 pub struct MergedRecordReader {
@@ -47,7 +50,7 @@ impl MergedRecordReader {
     }
 
     pub fn merged_schema(&self) -> Schema {
-        Schema::try_merge(
+        merge_schemas(
             self.readers
                 .iter()
                 .map(|reader| reader.schema().as_ref().clone()),
@@ -91,7 +94,7 @@ impl MergedReverseRecordReader {
     }
 
     pub fn merged_schema(&self) -> Schema {
-        Schema::try_merge(
+        merge_schemas(
             self.readers
                 .iter()
                 .map(|reader| reader.schema().as_ref().clone()),
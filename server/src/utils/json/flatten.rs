@@ -19,9 +19,224 @@
 use anyhow::anyhow;
 use chrono::{DateTime, Duration, Utc};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use serde_json::map::Map;
 use serde_json::value::Value;
 
+/// How arrays of objects are flattened
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArrayStrategy {
+    /// One list column per field of the objects
+    #[default]
+    Lists,
+    /// One column per element and field, the element index in the column name
+    Index,
+    /// The array as a JSON string
+    Json,
+    /// One row per element, its fields in place of the array
+    Explode,
+}
+
+fn default_separator() -> char {
+    '_'
+}
+
+/// How the events of a stream are flattened, as persisted in stream.json
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlattenOptions {
+    /// Levels of nesting flattened into columns, deeper objects and arrays of
+    /// objects are kept as JSON strings. Unlimited when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    #[serde(default = "default_separator")]
+    pub separator: char,
+    #[serde(default)]
+    pub arrays: ArrayStrategy,
+}
+
+impl Default for FlattenOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            separator: default_separator(),
+            arrays: ArrayStrategy::default(),
+        }
+    }
+}
+
+impl FlattenOptions {
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.max_depth == Some(0) {
+            return Err(anyhow!("max_depth must be at least 1"));
+        }
+        if self.separator.is_alphanumeric() || self.separator.is_whitespace() {
+            return Err(anyhow!(
+                "separator {:?} can't be a letter, digit or whitespace",
+                self.separator
+            ));
+        }
+        Ok(())
+    }
+
+    /// Flattens an event or an array of events, exploded arrays turn one
+    /// event into several
+    pub fn flatten(&self, value: Value) -> Result<Value, anyhow::Error> {
+        match value {
+            Value::Object(event) => {
+                let mut rows = self.flatten_event(event)?;
+                if rows.len() == 1 {
+                    Ok(Value::Object(rows.remove(0)))
+                } else {
+                    Ok(Value::Array(rows.into_iter().map(Value::Object).collect()))
+                }
+            }
+            Value::Array(events) => {
+                let mut rows = Vec::with_capacity(events.len());
+                for event in events {
+                    let Value::Object(event) = event else {
+                        return Err(anyhow!("Expected object in array of objects"));
+                    };
+                    rows.extend(self.flatten_event(event)?.into_iter().map(Value::Object));
+                }
+                Ok(Value::Array(rows))
+            }
+            _ => Err(anyhow!("Cannot flatten this JSON")),
+        }
+    }
+
+    fn flatten_event(
+        &self,
+        mut event: Map<String, Value>,
+    ) -> Result<Vec<Map<String, Value>>, anyhow::Error> {
+        if let Some(max_depth) = self.max_depth {
+            limit_depth(&mut event, 1, max_depth);
+        }
+        let events = match self.arrays {
+            ArrayStrategy::Lists => vec![event],
+            ArrayStrategy::Index | ArrayStrategy::Json => {
+                rewrite_arrays(&mut event, self.arrays);
+                vec![event]
+            }
+            ArrayStrategy::Explode => explode(event)?,
+        };
+
+        let separator = self.separator.to_string();
+        events
+            .into_iter()
+            .map(|event| {
+                let mut map = Map::new();
+                flatten_object(&mut map, None, event, &separator)?;
+                Ok(map)
+            })
+            .collect()
+    }
+}
+
+fn has_objects(array: &[Value]) -> bool {
+    array.iter().any(Value::is_object)
+}
+
+// objects and arrays of objects whose fields would land deeper than
+// `max_depth` are replaced by their JSON string
+fn limit_depth(object: &mut Map<String, Value>, depth: usize, max_depth: usize) {
+    for value in object.values_mut() {
+        let nested = match value {
+            Value::Object(_) => true,
+            Value::Array(array) => has_objects(array),
+            _ => false,
+        };
+        if !nested {
+            continue;
+        }
+        if depth >= max_depth {
+            *value = Value::String(value.to_string());
+            continue;
+        }
+        match value {
+            Value::Object(object) => limit_depth(object, depth + 1, max_depth),
+            Value::Array(array) => array
+                .iter_mut()
+                .filter_map(Value::as_object_mut)
+                .for_each(|element| limit_depth(element, depth + 1, max_depth)),
+            _ => unreachable!("only nested values are limited"),
+        }
+    }
+}
+
+// arrays of objects become objects keyed by element index, or JSON strings
+fn rewrite_arrays(object: &mut Map<String, Value>, arrays: ArrayStrategy) {
+    for value in object.values_mut() {
+        match value {
+            Value::Object(object) => rewrite_arrays(object, arrays),
+            Value::Array(array) if has_objects(array) => {
+                *value = match arrays {
+                    ArrayStrategy::Json => Value::String(value.to_string()),
+                    _ => Value::Object(
+                        std::mem::take(array)
+                            .into_iter()
+                            .enumerate()
+                            .filter(|(_, element)| !element.is_null())
+                            .map(|(index, mut element)| {
+                                if let Value::Object(element) = &mut element {
+                                    rewrite_arrays(element, arrays);
+                                }
+                                (index.to_string(), element)
+                            })
+                            .collect(),
+                    ),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// one object per combination of the elements of its arrays of objects, the
+// way an UNNEST of each array would
+fn explode(object: Map<String, Value>) -> Result<Vec<Map<String, Value>>, anyhow::Error> {
+    let mut rows = vec![Map::new()];
+    for (key, value) in object {
+        let variants: Vec<Value> = match value {
+            Value::Object(object) => explode(object)?.into_iter().map(Value::Object).collect(),
+            Value::Array(array) if has_objects(&array) => {
+                let mut variants = vec![];
+                for element in array {
+                    match element {
+                        Value::Object(element) => {
+                            variants.extend(explode(element)?.into_iter().map(Value::Object))
+                        }
+                        Value::Null => {}
+                        _ => {
+                            return Err(anyhow!(
+                                "Found non object element while flattening array of object(s)",
+                            ))
+                        }
+                    }
+                }
+                variants
+            }
+            value => vec![value],
+        };
+        // an array of nothing but nulls leaves the field out
+        if variants.is_empty() {
+            continue;
+        }
+        let (key, variants) = (&key, &variants);
+        rows = rows
+            .into_iter()
+            .flat_map(|row| {
+                variants.iter().map(move |variant| {
+                    let mut row = row.clone();
+                    row.insert(key.clone(), variant.clone());
+                    row
+                })
+            })
+            .collect();
+    }
+    Ok(rows)
+}
+
 pub fn flatten(
     nested_value: Value,
     separator: &str,
@@ -498,4 +713,179 @@ mod tests {
         assert_eq!(map.get("key.q.x").unwrap(), &json!([[1, 2], [1], null]));
         assert_eq!(map.get("key.r").unwrap(), &json!([null, 2, 3]));
     }
+
+    #[test]
+    fn flatten_options_shape_the_schema() {
+        use std::collections::HashMap;
+
+        use super::{ArrayStrategy, FlattenOptions};
+        use crate::event::format::{json, EventFormat};
+
+        // a trimmed down kubernetes audit event
+        let fixture = json!({
+            "verb": "create",
+            "user": {"username": "admin", "groups": ["system:masters"]},
+            "objectRef": {"resource": "pods", "namespace": "default"},
+            "responseStatus": {"metadata": {"code": 201}},
+            "containers": [{"name": "app", "ports": {"http": 80}}, {"name": "sidecar"}]
+        });
+        let base = [
+            "verb",
+            "user_username",
+            "user_groups",
+            "objectRef_resource",
+            "objectRef_namespace",
+        ];
+        let shallow = vec!["verb", "user", "objectRef", "responseStatus", "containers"];
+        let with = |rest: &[&'static str]| base.iter().chain(rest).copied().collect::<Vec<&str>>();
+        let cases = [
+            (
+                None,
+                ArrayStrategy::Lists,
+                with(&[
+                    "responseStatus_metadata_code",
+                    "containers_name",
+                    "containers_ports_http",
+                ]),
+                1,
+            ),
+            (
+                None,
+                ArrayStrategy::Index,
+                with(&[
+                    "responseStatus_metadata_code",
+                    "containers_0_name",
+                    "containers_0_ports_http",
+                    "containers_1_name",
+                ]),
+                1,
+            ),
+            (
+                None,
+                ArrayStrategy::Json,
+                with(&["responseStatus_metadata_code", "containers"]),
+                1,
+            ),
+            (
+                None,
+                ArrayStrategy::Explode,
+                with(&[
+                    "responseStatus_metadata_code",
+                    "containers_name",
+                    "containers_ports_http",
+                ]),
+                2,
+            ),
+            (Some(1), ArrayStrategy::Lists, shallow.clone(), 1),
+            (Some(1), ArrayStrategy::Index, shallow.clone(), 1),
+            (Some(1), ArrayStrategy::Json, shallow.clone(), 1),
+            (Some(1), ArrayStrategy::Explode, shallow.clone(), 1),
+            (
+                Some(2),
+                ArrayStrategy::Lists,
+                with(&[
+                    "responseStatus_metadata",
+                    "containers_name",
+                    "containers_ports",
+                ]),
+                1,
+            ),
+            (
+                Some(2),
+                ArrayStrategy::Index,
+                with(&[
+                    "responseStatus_metadata",
+                    "containers_0_name",
+                    "containers_0_ports",
+                    "containers_1_name",
+                ]),
+                1,
+            ),
+            (
+                Some(2),
+                ArrayStrategy::Json,
+                with(&["responseStatus_metadata", "containers"]),
+                1,
+            ),
+            (
+                Some(2),
+                ArrayStrategy::Explode,
+                with(&[
+                    "responseStatus_metadata",
+                    "containers_name",
+                    "containers_ports",
+                ]),
+                2,
+            ),
+        ];
+
+        for separator in ['_', '.'] {
+            for (max_depth, arrays, columns, rows) in &cases {
+                let options = FlattenOptions {
+                    max_depth: *max_depth,
+                    separator,
+                    arrays: *arrays,
+                };
+                let event = json::Event {
+                    data: options.flatten(fixture.clone()).unwrap(),
+                    tags: String::default(),
+                    metadata: String::default(),
+                };
+                let (rb, _) = event.into_recordbatch(HashMap::new(), None, None).unwrap();
+
+                let mut expected = columns
+                    .iter()
+                    .map(|column| column.replace('_', &separator.to_string()))
+                    .collect::<Vec<_>>();
+                expected.sort();
+                let mut actual = rb
+                    .schema()
+                    .fields()
+                    .iter()
+                    .map(|field| field.name().clone())
+                    .filter(|name| !name.starts_with("p_"))
+                    .collect::<Vec<_>>();
+                actual.sort();
+                assert_eq!(actual, expected, "{options:?}");
+                assert_eq!(rb.num_rows(), *rows, "{options:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn subtrees_past_max_depth_stay_json() {
+        use super::FlattenOptions;
+
+        let options = FlattenOptions {
+            max_depth: Some(1),
+            ..FlattenOptions::default()
+        };
+        let flattened = options
+            .flatten(json!({"user": {"name": "admin", "groups": ["a", "b"]}, "tags": ["x"]}))
+            .unwrap();
+        let user: Value = serde_json::from_str(flattened["user"].as_str().unwrap()).unwrap();
+        assert_eq!(user, json!({"name": "admin", "groups": ["a", "b"]}));
+        // arrays of scalars are columns at any depth
+        assert_eq!(flattened["tags"], json!(["x"]));
+    }
+
+    #[test]
+    fn flatten_options_are_validated() {
+        use super::FlattenOptions;
+
+        let options: FlattenOptions = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(options, FlattenOptions::default());
+        assert!(options.validate().is_ok());
+
+        for invalid in [
+            json!({"max_depth": 0}),
+            json!({"separator": "a"}),
+            json!({"separator": " "}),
+        ] {
+            let options: FlattenOptions = serde_json::from_value(invalid).unwrap();
+            assert!(options.validate().is_err(), "{options:?}");
+        }
+        assert!(serde_json::from_value::<FlattenOptions>(json!({"separator": "::"})).is_err());
+        assert!(serde_json::from_value::<FlattenOptions>(json!({"arrays": "zip"})).is_err());
+    }
 }